@@ -1,3 +1,31 @@
 fn main() {
+    emit_build_info();
     tauri_build::build()
 }
+
+/// Embed the git commit and build time, read by `updates::app_info`
+fn emit_build_info() {
+    let git_hash = std::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=KHI_GIT_HASH={}", git_hash);
+
+    // SOURCE_DATE_EPOCH keeps reproducible builds reproducible
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+    println!("cargo:rustc-env=KHI_BUILD_TIMESTAMP={}", timestamp);
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+}
@@ -0,0 +1,234 @@
+//! IPC contract tests
+//!
+//! `fixtures/frontend_to_rust.json` holds command arguments as the frontend
+//! sends them, `fixtures/rust_to_frontend.json` the responses and events as
+//! the backend emits them. The frontend can test against the same files.
+//!
+//! No IPC type denies unknown fields:
+//! - `Book` and `Highlight`: the frontend adds UI state such as
+//!   `isSelected` to the books it sends
+//! - `ExportConfig`, `MetadataConfig`, `AppSettings` and `UiPreferences`:
+//!   settings files and profiles written by a newer version must still load
+//! - `KoboDevice`: the device is validated again on import anyway
+//! - events only travel to the frontend
+//!
+//! Instead these tests fail when a field the frontend sends is dropped on
+//! the way through, so a casing typo shows up here rather than as silently
+//! lost data. Fields added over time default when missing.
+
+use crate::db::schema::SchemaCompatibility;
+use crate::device::monitor::{DeviceDetectedEvent, DeviceDisconnectedEvent};
+use crate::export::watch::{ExportFilesChangedEvent, EXPORT_FILES_CHANGED};
+use crate::export::{ExportBookStatus, ExportProgressEvent, ExportReport, ExportStartedEvent};
+use crate::models::{
+    Book, ExportConfig, Highlight, ImportResult, KoboDevice, ProgressScope, TocEntry,
+};
+use crate::settings::{AppSettings, LastImportRecord, ThemePreference};
+use crate::window::{ShowTrigger, WindowShownEvent, WINDOW_SHOWN};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+const FRONTEND_TO_RUST: &str = include_str!("fixtures/frontend_to_rust.json");
+const RUST_TO_FRONTEND: &str = include_str!("fixtures/rust_to_frontend.json");
+
+/// Fields the frontend sends that the backend doesn't keep on purpose
+const UI_ONLY_FIELDS: &[&str] = &["isSelected"];
+
+/// Deserialize `sent` as `T`, failing on any field lost in the round trip
+fn receive<T: DeserializeOwned + Serialize>(sent: &Value) -> T {
+    let value: T = serde_json::from_value(sent.clone())
+        .unwrap_or_else(|e| panic!("{} rejected: {}", std::any::type_name::<T>(), e));
+    let mut dropped = Vec::new();
+    dropped_fields(
+        sent,
+        &serde_json::to_value(&value).unwrap(),
+        "",
+        &mut dropped,
+    );
+    assert!(
+        dropped.is_empty(),
+        "{} dropped {:?}",
+        std::any::type_name::<T>(),
+        dropped
+    );
+    value
+}
+
+/// Paths of object keys in `sent` that `kept` doesn't have
+fn dropped_fields(sent: &Value, kept: &Value, path: &str, dropped: &mut Vec<String>) {
+    match (sent, kept) {
+        (Value::Object(sent), Value::Object(kept)) => {
+            for (key, value) in sent {
+                let field = format!("{}.{}", path, key);
+                match kept.get(key) {
+                    Some(kept) => dropped_fields(value, kept, &field, dropped),
+                    None if UI_ONLY_FIELDS.contains(&key.as_str()) => {}
+                    None => dropped.push(field),
+                }
+            }
+        }
+        (Value::Array(sent), Value::Array(kept)) => {
+            for (index, (sent, kept)) in sent.iter().zip(kept).enumerate() {
+                dropped_fields(sent, kept, &format!("{}[{}]", path, index), dropped);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn sample_book() -> Book {
+    let mut book = Book::new(
+        "file:///mnt/onboard/Walden.epub".to_string(),
+        "Walden".to_string(),
+        "Henry David Thoreau".to_string(),
+    );
+    book.language = Some("en".to_string());
+    book.date_last_read = Some("2025-02-02T10:00:00".to_string());
+    book.toc = vec![TocEntry {
+        title: "Where I Lived".to_string(),
+        depth: 1,
+        order: 0,
+    }];
+    book.slug = "walden-3f2a9c".to_string();
+    book.progress_scope = Some(ProgressScope::Book);
+
+    let mut highlight = Highlight::new(
+        "hl1".to_string(),
+        "Simplify, simplify.".to_string(),
+        "2025-02-01T21:00:00".to_string(),
+    );
+    highlight.chapter_title = Some("Where I Lived".to_string());
+    highlight.chapter_progress = Some(0.4);
+    highlight.stable_id = "8d1f0c2b7a6e5d4c".to_string();
+    highlight.page = Some(120);
+    book.highlights.push(highlight);
+    book
+}
+
+fn sample_device() -> KoboDevice {
+    KoboDevice {
+        name: "KOBOeReader".to_string(),
+        path: "/Volumes/KOBOeReader".to_string(),
+        is_valid: true,
+        serial_number: Some("N418123456789".to_string()),
+        invalid_reason: None,
+        is_stale: false,
+        model: Some("Kobo Clara 2E".to_string()),
+        friendly_name: Some("Kobo Clara 2E 6789".to_string()),
+        suggested_profile: None,
+    }
+}
+
+/// Every response and event payload, keyed by command or event name
+fn rust_to_frontend() -> Value {
+    let mut settings = AppSettings::default();
+    settings.export_config.export_path = "/Users/reader/Notes".to_string();
+    for profile in &mut settings.export_profiles {
+        profile.config.export_path = settings.export_config.export_path.clone();
+    }
+    settings.version = "0.0.0".to_string();
+
+    let report = ExportReport {
+        destination: "/Users/reader/Notes".to_string(),
+        total_books: 1,
+        exported_files: vec!["/Users/reader/Notes/Walden - Henry David Thoreau.md".to_string()],
+        failures: Vec::new(),
+        bytes_written: None,
+        aborted: None,
+        excluded_by_chapter: 0,
+        redacted: 0,
+        metrics: Default::default(),
+        git_commit: None,
+        warnings: Vec::new(),
+        encoding: Default::default(),
+        cloud_provider: None,
+        cancelled: None,
+        rolled_back: false,
+    };
+
+    let mut payloads = json!({
+        "scan_for_device": sample_device(),
+        "import_highlights": ImportResult {
+            books: vec![sample_book()],
+            schema: Some(SchemaCompatibility::Known),
+            schema_warning: None,
+            metrics: Default::default(),
+        },
+        "get_default_settings": settings,
+        "export-started": ExportStartedEvent {
+            total_books: 1,
+            destination: "/Users/reader/Notes".to_string(),
+        },
+        "export-progress": ExportProgressEvent {
+            index: 0,
+            total_books: 1,
+            title: "Walden".to_string(),
+            status: ExportBookStatus::Exported,
+            path: report.exported_files.first().cloned(),
+            error: None,
+        },
+        "export-finished": report,
+        "device-detected": DeviceDetectedEvent { device: sample_device() },
+        "device-disconnected": DeviceDisconnectedEvent,
+    });
+    payloads[EXPORT_FILES_CHANGED] = json!(ExportFilesChangedEvent {
+        slugs: vec!["walden-3f2a9c".to_string()],
+    });
+    payloads[WINDOW_SHOWN] = json!(WindowShownEvent {
+        trigger: ShowTrigger::FrontendReady,
+    });
+    payloads
+}
+
+#[test]
+fn test_rust_to_frontend_payloads_are_stable() {
+    let rendered = format!(
+        "{}\n",
+        serde_json::to_string_pretty(&rust_to_frontend()).unwrap()
+    );
+    assert_eq!(rendered, RUST_TO_FRONTEND);
+
+    // Payloads the frontend echoes back deserialize to the same values
+    let fixture: Value = serde_json::from_str(RUST_TO_FRONTEND).unwrap();
+    let imported: ImportResult = receive(&fixture["import_highlights"]);
+    assert_eq!(imported.books, vec![sample_book()]);
+    let device: KoboDevice = receive(&fixture["scan_for_device"]);
+    assert_eq!(device, sample_device());
+    let settings: AppSettings = receive(&fixture["get_default_settings"]);
+    assert_eq!(settings.version, "0.0.0");
+    let _: ExportReport = receive(&fixture["export-finished"]);
+}
+
+#[test]
+fn test_frontend_payloads_are_accepted() {
+    let fixture: Value = serde_json::from_str(FRONTEND_TO_RUST).unwrap();
+
+    let args = &fixture["import_highlights"];
+    let device: KoboDevice = receive(&args["device"]);
+    assert_eq!(device, sample_device());
+    assert_eq!(args["mergeSplits"], true);
+
+    let args = &fixture["export_books"];
+    let books: Vec<Book> = receive(&args["books"]);
+    assert_eq!(books[0].highlights[0].chapter_progress, Some(0.4));
+    assert_eq!(books[0].progress_scope, Some(ProgressScope::Book));
+    // Fields the frontend never sends take their defaults
+    assert!(books[0].authors.is_empty());
+    assert_eq!(books[0].highlights[0].stable_id, "");
+    let config: ExportConfig = receive(&args["config"]);
+    assert!(config.show_location);
+    assert_eq!(config.snapshot_keep, crate::models::DEFAULT_SNAPSHOT_KEEP);
+
+    let settings: AppSettings = receive(&fixture["save_settings"]["settings"]);
+    assert_eq!(settings.ui_preferences.theme, ThemePreference::Dark);
+    assert!(settings.ui_preferences.auto_import_on_connect);
+
+    let record: LastImportRecord = receive(&fixture["update_last_import"]["record"]);
+    assert_eq!(record.books_count, 3);
+
+    // Settings files from before the camelCase switch
+    let legacy: AppSettings =
+        serde_json::from_value(fixture["save_settings_snake_case"]["settings"].clone()).unwrap();
+    assert_eq!(legacy, settings);
+}
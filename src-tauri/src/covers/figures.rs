@@ -0,0 +1,234 @@
+//! Images of figure highlights
+//!
+//! A highlight made on an illustration has no text, only the position of
+//! the page holding it (`StartContainerPath`, e.g.
+//! `OEBPS/Text/ch03.xhtml#point(/1/4/2:0)`). The first image of that page is
+//! taken as the figure and cached next to the covers, under `figures/`.
+//! Kepub positions (`span#kobo.3.1`) don't name the page, so their figures
+//! keep no image.
+
+use super::{xml_attribute, CoverError, CoverExtractor};
+use crate::utils::slug::content_hash;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// Folder of the cover cache holding figure images
+const FIGURES_DIR: &str = "figures";
+
+impl CoverExtractor {
+    /// Cached image of the figure at `container_path` in `epub_path`
+    ///
+    /// `None` when the position doesn't name a page of the EPUB or the page
+    /// has no image.
+    pub fn extract_figure(
+        &self,
+        epub_path: &Path,
+        container_path: &str,
+    ) -> Result<Option<PathBuf>, CoverError> {
+        let Some(page) = page_file(container_path) else {
+            return Ok(None);
+        };
+        let file = fs::File::open(epub_path)?;
+        let mut archive = ZipArchive::new(file)?;
+        let Some(page_entry) = find_entry(&archive, page) else {
+            log::debug!("[Figures] Page {} not found in {:?}", page, epub_path);
+            return Ok(None);
+        };
+
+        let mut content = String::new();
+        archive.by_name(&page_entry)?.read_to_string(&mut content)?;
+        let Some(src) = first_image_src(&content) else {
+            return Ok(None);
+        };
+        let image_entry = resolve_href(&page_entry, &src);
+
+        let extension = Path::new(&image_entry)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_else(|| "jpg".to_string());
+        let cached = self.cache_dir.join(FIGURES_DIR).join(format!(
+            "{}_{}.{}",
+            self.compute_cache_key(epub_path)?,
+            content_hash(&image_entry),
+            extension
+        ));
+        if cached.exists() {
+            return Ok(Some(cached));
+        }
+
+        let mut data = Vec::new();
+        match archive.by_name(&image_entry) {
+            Ok(mut image) => image.read_to_end(&mut data)?,
+            Err(zip::result::ZipError::FileNotFound) => {
+                log::debug!(
+                    "[Figures] Image {} not found in {:?}",
+                    image_entry,
+                    epub_path
+                );
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        fs::create_dir_all(self.cache_dir.join(FIGURES_DIR))?;
+        fs::write(&cached, data)?;
+        Ok(Some(cached))
+    }
+}
+
+/// Page file named by a highlight position, without its fragment
+fn page_file(container_path: &str) -> Option<&str> {
+    let page = container_path.split('#').next()?.trim();
+    let lower = page.to_lowercase();
+    (lower.ends_with(".xhtml") || lower.ends_with(".html") || lower.ends_with(".htm"))
+        .then_some(page)
+}
+
+/// Archive entry of `page`: the same path, or one ending in it (positions
+/// may be relative to the OPF folder)
+fn find_entry<R: Read + std::io::Seek>(archive: &ZipArchive<R>, page: &str) -> Option<String> {
+    let page = page.trim_start_matches('/');
+    let suffix = format!("/{}", page);
+    archive
+        .file_names()
+        .find(|name| *name == page)
+        .or_else(|| archive.file_names().find(|name| name.ends_with(&suffix)))
+        .map(str::to_string)
+}
+
+/// Source of the first `<img>` (or SVG `<image>`) of a page
+fn first_image_src(content: &str) -> Option<String> {
+    let mut rest = content;
+    loop {
+        let start = rest.find('<')?;
+        rest = &rest[start + 1..];
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_lowercase();
+        if name == "img" || name == "image" || name.ends_with(":image") {
+            let tag = &rest[..rest.find('>').unwrap_or(rest.len())];
+            let src = xml_attribute(tag, "src")
+                .or_else(|| xml_attribute(tag, "xlink:href"))
+                .or_else(|| xml_attribute(tag, "href"));
+            if let Some(src) = src.filter(|src| !src.trim().is_empty()) {
+                return Some(src);
+            }
+        }
+    }
+}
+
+/// Archive entry of `href`, relative to the page at `page_entry`
+fn resolve_href(page_entry: &str, href: &str) -> String {
+    let href = href
+        .split(['#', '?'])
+        .next()
+        .unwrap_or(href)
+        .replace("%20", " ");
+    let mut parts: Vec<&str> = match page_entry.rsplit_once('/') {
+        Some((dir, _)) if !href.starts_with('/') => dir.split('/').collect(),
+        _ => Vec::new(),
+    };
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    /// EPUB with an illustrated chapter (image one folder up) and a text one
+    fn create_illustrated_epub(dir: &Path) -> PathBuf {
+        let epub_path = dir.join("illustrated.epub");
+        let mut zip = zip::ZipWriter::new(fs::File::create(&epub_path).unwrap());
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+        zip.start_file("mimetype", options).unwrap();
+        zip.write_all(b"application/epub+zip").unwrap();
+        zip.start_file("OEBPS/Text/ch02.xhtml", options).unwrap();
+        zip.write_all(
+            br#"<html xmlns="http://www.w3.org/1999/xhtml"><body>
+            <p>Figure 2 shows the map.</p>
+            <div class="figure"><img alt="Map" src="../Images/map%201.png"/></div>
+            <img src="../Images/second.png"/>
+            </body></html>"#,
+        )
+        .unwrap();
+        zip.start_file("OEBPS/Text/ch03.xhtml", options).unwrap();
+        zip.write_all(b"<html><body><p>No pictures here.</p></body></html>")
+            .unwrap();
+        zip.start_file("OEBPS/Images/map 1.png", options).unwrap();
+        zip.write_all(b"\x89PNG map").unwrap();
+        zip.start_file("OEBPS/Images/second.png", options).unwrap();
+        zip.write_all(b"\x89PNG second").unwrap();
+        zip.finish().unwrap();
+        epub_path
+    }
+
+    #[test]
+    fn test_figure_is_the_first_image_of_the_highlighted_page() {
+        let temp = TempDir::new().unwrap();
+        let epub_path = create_illustrated_epub(temp.path());
+        let extractor = CoverExtractor::new(temp.path().join("covers"));
+
+        let figure = extractor
+            .extract_figure(&epub_path, "OEBPS/Text/ch02.xhtml#point(/1/4/4/1:0)")
+            .unwrap()
+            .unwrap();
+        assert!(figure.starts_with(temp.path().join("covers").join(FIGURES_DIR)));
+        assert_eq!(figure.extension().unwrap(), "png");
+        assert_eq!(fs::read(&figure).unwrap(), b"\x89PNG map");
+
+        // Positions relative to the OPF folder find the same page (cached)
+        let again = extractor
+            .extract_figure(&epub_path, "Text/ch02.xhtml")
+            .unwrap();
+        assert_eq!(again, Some(figure));
+
+        // No image, no page, or a kepub position: nothing to extract
+        assert_eq!(
+            extractor
+                .extract_figure(&epub_path, "OEBPS/Text/ch03.xhtml#point(/1/2)")
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            extractor
+                .extract_figure(&epub_path, "OEBPS/Text/ch09.xhtml")
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            extractor
+                .extract_figure(&epub_path, "span#kobo\\.3\\.1")
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_image_sources_resolve_against_the_page() {
+        assert_eq!(
+            first_image_src(r#"<svg><image width="600" xlink:href="../Images/plate.jpg"/></svg>"#),
+            Some("../Images/plate.jpg".to_string())
+        );
+        assert_eq!(first_image_src("<p>imagery</p><imgx/>"), None);
+        assert_eq!(
+            resolve_href("OEBPS/Text/ch02.xhtml", "../Images/a.png#x"),
+            "OEBPS/Images/a.png"
+        );
+        assert_eq!(resolve_href("ch02.xhtml", "./a.png"), "a.png");
+    }
+}
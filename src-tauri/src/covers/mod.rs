@@ -1,9 +1,87 @@
+pub mod figures;
+
+use crate::device::device_fs::DeviceFs;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageOutputFormat};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::fs;
-use std::io::{Read, Seek, Write};
+use std::io::{Cursor, Read, Seek, Write};
 use std::path::{Path, PathBuf};
 use zip::ZipArchive;
 
+/// Content ID prefix of books stored on the device's onboard storage
+pub const ONBOARD_PREFIX: &str = "file:///mnt/onboard/";
+
+/// Width of cover thumbnails, for the library grid
+pub const THUMBNAIL_WIDTH: u32 = 120;
+
+/// Thumbnails of unusually tall covers are cut down to this height
+const THUMBNAIL_MAX_HEIGHT: u32 = 240;
+
+/// JPEG quality of thumbnails
+const THUMBNAIL_QUALITY: u8 = 85;
+
+/// Cached size of a cover
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rendition {
+    /// `THUMBNAIL_WIDTH` wide, generated during imports
+    Thumbnail,
+    /// The image as stored in the EPUB, generated on demand
+    Full,
+}
+
+impl Rendition {
+    /// Cache filename suffix of the rendition
+    fn suffix(self) -> &'static str {
+        match self {
+            Rendition::Thumbnail => "_thumb",
+            Rendition::Full => "",
+        }
+    }
+}
+
+/// Cached cover files of a book
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverFiles {
+    pub thumbnail: PathBuf,
+    /// `None` until the full-size cover is generated
+    pub full: Option<PathBuf>,
+    pub source: CoverSource,
+}
+
+/// New cover of a book whose cached cover file had disappeared
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverRepair {
+    pub content_id: String,
+    pub cover_path: String,
+}
+
+/// Where an extracted cover came from
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CoverSource {
+    /// Already in the cache
+    Cache,
+    /// The image the OPF manifest declares as cover
+    Opf,
+    /// An image named like a cover, when the OPF declares none
+    Fallback,
+    /// No image found
+    Placeholder,
+}
+
+/// Cover of a book extracted again by `refresh_book_cover`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverRefresh {
+    pub content_id: String,
+    pub cover_path: String,
+    pub thumbnail_path: String,
+    pub source: CoverSource,
+}
+
 pub struct CoverExtractor {
     cache_dir: PathBuf,
 }
@@ -17,46 +95,144 @@ impl CoverExtractor {
         Self { cache_dir }
     }
 
-    /// Extract cover from EPUB file
+    /// Extract cover from EPUB file (full size, with its thumbnail)
     pub fn extract_cover(&self, epub_path: &Path) -> Result<Option<PathBuf>, CoverError> {
-        // Check cache first
+        self.extract(epub_path).map(|(path, _)| Some(path))
+    }
+
+    /// Thumbnail of the cover of `epub_path`, without the full-size cover
+    pub fn extract_thumbnail(&self, epub_path: &Path) -> Result<PathBuf, CoverError> {
+        self.extract_renditions(epub_path, false)
+            .map(|files| files.thumbnail)
+    }
+
+    /// Cached full-size cover of `epub_path`, or the cover extracted now with
+    /// where it came from
+    fn extract(&self, epub_path: &Path) -> Result<(PathBuf, CoverSource), CoverError> {
+        let files = self.extract_renditions(epub_path, true)?;
+        let full = files.full.expect("full rendition requested");
+        Ok((full, files.source))
+    }
+
+    /// Cover thumbnail of `epub_path` and, with `with_full`, its full-size
+    /// cover
+    ///
+    /// The image is read and decoded once for both. Without a cover image
+    /// both renditions are placeholders.
+    pub fn extract_renditions(
+        &self,
+        epub_path: &Path,
+        with_full: bool,
+    ) -> Result<CoverFiles, CoverError> {
         let cache_key = self.compute_cache_key(epub_path)?;
-        let cached_path = self.cache_dir.join(format!("{}.jpg", cache_key));
+        let thumbnail = self.cover_file(&cache_key, Rendition::Thumbnail);
+        let full = self.cover_file(&cache_key, Rendition::Full);
+
+        if thumbnail.exists() && (!with_full || full.exists()) {
+            let full = full.exists().then_some(full);
+            return Ok(CoverFiles {
+                thumbnail,
+                full,
+                source: CoverSource::Cache,
+            });
+        }
 
-        if cached_path.exists() {
-            return Ok(Some(cached_path));
+        // A book already known to have no cover keeps its placeholders
+        let placeholder = self.placeholder_file(&cache_key, Rendition::Thumbnail);
+        let full_placeholder = self.placeholder_file(&cache_key, Rendition::Full);
+        if placeholder.exists() && !full.exists() && (!with_full || full_placeholder.exists()) {
+            let full = full_placeholder.exists().then_some(full_placeholder);
+            return Ok(CoverFiles {
+                thumbnail: placeholder,
+                full,
+                source: CoverSource::Placeholder,
+            });
         }
 
-        // Open EPUB as ZIP
+        // A full-size cover cached before thumbnails existed saves opening
+        // the EPUB
+        let (data, source) = if full.exists() {
+            (fs::read(&full)?, CoverSource::Cache)
+        } else {
+            match self.read_cover_image(epub_path)? {
+                Some(found) => found,
+                None => return self.generate_placeholders(&cache_key),
+            }
+        };
+
+        self.ensure_cache_dir()?;
+        if !thumbnail.exists() {
+            write_thumbnail(&data, &thumbnail)?;
+        }
+        if with_full && !full.exists() {
+            let mut output = fs::File::create(&full)?;
+            output.write_all(&data)?;
+        }
+        let full = full.exists().then_some(full);
+        Ok(CoverFiles {
+            thumbnail,
+            full,
+            source,
+        })
+    }
+
+    /// Bytes of the cover image in `epub_path` and how it was found
+    fn read_cover_image(
+        &self,
+        epub_path: &Path,
+    ) -> Result<Option<(Vec<u8>, CoverSource)>, CoverError> {
         let file = fs::File::open(epub_path)?;
         let mut archive = ZipArchive::new(file)?;
 
-        // Try to find cover image
-        let cover_path = self.find_cover_path(&mut archive)?;
+        let Some((path_in_epub, source)) = self.find_cover_path(&mut archive)? else {
+            return Ok(None);
+        };
+        let mut cover_file = archive.by_name(&path_in_epub)?;
+        let mut cover_data = Vec::new();
+        cover_file.read_to_end(&mut cover_data)?;
+        Ok(Some((cover_data, source)))
+    }
 
-        match cover_path {
-            Some(path_in_epub) => {
-                // Extract cover image
-                let mut cover_file = archive.by_name(&path_in_epub)?;
-                let mut cover_data = Vec::new();
-                cover_file.read_to_end(&mut cover_data)?;
+    /// Cache file of a cover rendition
+    fn cover_file(&self, cache_key: &str, rendition: Rendition) -> PathBuf {
+        self.cache_dir
+            .join(format!("{}{}.jpg", cache_key, rendition.suffix()))
+    }
 
-                // Save to cache
-                let mut output = fs::File::create(&cached_path)?;
-                output.write_all(&cover_data)?;
+    /// Cache file of a placeholder rendition
+    fn placeholder_file(&self, cache_key: &str, rendition: Rendition) -> PathBuf {
+        self.cache_dir.join(format!(
+            "{}_placeholder{}.svg",
+            cache_key,
+            rendition.suffix()
+        ))
+    }
 
-                Ok(Some(cached_path))
-            }
-            None => {
-                // Generate placeholder
-                let placeholder_path = self.generate_placeholder(&cache_key)?;
-                Ok(Some(placeholder_path))
-            }
-        }
+    /// Read Calibre series metadata from the EPUB's OPF, if present
+    pub fn read_series(&self, epub_path: &Path) -> Result<Option<SeriesInfo>, CoverError> {
+        Ok(self.read_metadata(epub_path)?.series)
+    }
+
+    /// Read series and publication year from the EPUB's OPF
+    pub fn read_metadata(&self, epub_path: &Path) -> Result<OpfMetadata, CoverError> {
+        let file = fs::File::open(epub_path)?;
+        let mut archive = ZipArchive::new(file)?;
+
+        let opf_path = match self.get_opf_path(&mut archive)? {
+            Some(path) => path,
+            None => return Ok(OpfMetadata::default()),
+        };
+        let mut content = String::new();
+        archive.by_name(&opf_path)?.read_to_string(&mut content)?;
+
+        Ok(OpfMetadata {
+            series: parse_calibre_series(&content),
+            publication_year: parse_publication_year(&content),
+        })
     }
 
     /// Compute cache key from file path and modification time
-    fn compute_cache_key(&self, epub_path: &Path) -> Result<String, CoverError> {
+    pub fn compute_cache_key(&self, epub_path: &Path) -> Result<String, CoverError> {
         let metadata = fs::metadata(epub_path)?;
         let modified = metadata.modified()?;
         let modified_secs = modified
@@ -79,19 +255,21 @@ impl CoverExtractor {
     fn find_cover_path<R: Read + Seek>(
         &self,
         archive: &mut ZipArchive<R>,
-    ) -> Result<Option<String>, CoverError> {
+    ) -> Result<Option<(String, CoverSource)>, CoverError> {
         // 1. Find the OPF file path from container.xml
         let opf_path = self.get_opf_path(archive)?;
         
         if let Some(path) = opf_path {
             // 2. Parse OPF to find cover image
             if let Ok(cover_href) = self.parse_opf_for_cover(archive, &path) {
-                return Ok(Some(cover_href));
+                return Ok(Some((cover_href, CoverSource::Opf)));
             }
         }
 
         // Fallback to filename-based search if OPF parsing fails
-        self.fallback_find_cover_path(archive)
+        Ok(self
+            .fallback_find_cover_path(archive)?
+            .map(|path| (path, CoverSource::Fallback)))
     }
 
     fn get_opf_path<R: Read + Seek>(&self, archive: &mut ZipArchive<R>) -> Result<Option<String>, CoverError> {
@@ -211,24 +389,147 @@ impl CoverExtractor {
 
     /// Generate a placeholder SVG when no cover is found
     fn generate_placeholder(&self, cache_key: &str) -> Result<PathBuf, CoverError> {
-        let placeholder_path = self
-            .cache_dir
-            .join(format!("{}_placeholder.svg", cache_key));
+        self.write_placeholder(cache_key, Rendition::Full)
+    }
+
+    /// Placeholders for both renditions
+    fn generate_placeholders(&self, cache_key: &str) -> Result<CoverFiles, CoverError> {
+        Ok(CoverFiles {
+            thumbnail: self.write_placeholder(cache_key, Rendition::Thumbnail)?,
+            full: Some(self.write_placeholder(cache_key, Rendition::Full)?),
+            source: CoverSource::Placeholder,
+        })
+    }
 
-        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="300" viewBox="0 0 200 300">
+    fn write_placeholder(
+        &self,
+        cache_key: &str,
+        rendition: Rendition,
+    ) -> Result<PathBuf, CoverError> {
+        let placeholder_path = self.placeholder_file(cache_key, rendition);
+        let (width, height) = match rendition {
+            Rendition::Thumbnail => (THUMBNAIL_WIDTH, THUMBNAIL_WIDTH * 3 / 2),
+            Rendition::Full => (200, 300),
+        };
+
+        let svg = format!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 200 300">
             <rect width="200" height="300" fill="#f0f0f0"/>
             <rect x="20" y="40" width="160" height="200" fill="#e0e0e0" stroke="#ccc" stroke-width="2"/>
             <text x="100" y="150" text-anchor="middle" font-family="sans-serif" font-size="14" fill="#999">
                 Sem Capa
             </text>
-        </svg>"##;
+        </svg>"##,
+            width, height
+        );
 
+        self.ensure_cache_dir()?;
         let mut file = fs::File::create(&placeholder_path)?;
         file.write_all(svg.as_bytes())?;
 
         Ok(placeholder_path)
     }
 
+    /// Re-create the cache directory if it was deleted (e.g. by the OS
+    /// purging caches) since the extractor was created
+    fn ensure_cache_dir(&self) -> Result<(), CoverError> {
+        if !self.cache_dir.is_dir() {
+            log::warn!(
+                "Cover cache {:?} is missing, re-creating it",
+                self.cache_dir
+            );
+            fs::create_dir_all(&self.cache_dir)?;
+        }
+        Ok(())
+    }
+
+    /// Replacement for a cover path that no longer exists
+    ///
+    /// Returns `None` while `cover_path` is still there. Otherwise the cover is
+    /// extracted again from the book's EPUB on `device` when it can be found
+    /// there, or replaced by a placeholder.
+    pub fn repair_cover(
+        &self,
+        content_id: &str,
+        cover_path: &str,
+        device: Option<&DeviceFs>,
+    ) -> Result<Option<PathBuf>, CoverError> {
+        if Path::new(cover_path).exists() {
+            return Ok(None);
+        }
+
+        let epub_path =
+            device.and_then(|device| device.locate(content_id.strip_prefix(ONBOARD_PREFIX)?));
+        if let Some(epub_path) = epub_path {
+            match self.extract_cover(&epub_path) {
+                Ok(Some(path)) => return Ok(Some(path)),
+                Ok(None) => {}
+                Err(e) => log::warn!("Failed to re-extract cover of {}: {}", content_id, e),
+            }
+        }
+
+        self.generate_placeholder(&placeholder_key(content_id))
+            .map(Some)
+    }
+
+    /// Cache files of `epub_path`'s current version: the extracted cover,
+    /// its placeholder and their thumbnails
+    pub fn cache_entries(&self, epub_path: &Path) -> Result<[PathBuf; 4], CoverError> {
+        let cache_key = self.compute_cache_key(epub_path)?;
+        Ok([
+            self.cover_file(&cache_key, Rendition::Full),
+            self.placeholder_file(&cache_key, Rendition::Full),
+            self.cover_file(&cache_key, Rendition::Thumbnail),
+            self.placeholder_file(&cache_key, Rendition::Thumbnail),
+        ])
+    }
+
+    /// Extract the cover of `content_id` again from `epub_path`, ignoring
+    /// what the cache holds for it
+    ///
+    /// `previous` is the cover path the book had; it is removed once the new
+    /// cover is written, if it lives in the cache. When `epub_path` can't be
+    /// reached nothing is touched, so a good cover is never replaced by a
+    /// placeholder.
+    pub fn refresh_cover(
+        &self,
+        content_id: &str,
+        previous: Option<&str>,
+        epub_path: Option<&Path>,
+    ) -> Result<CoverRefresh, CoverError> {
+        let epub_path = match epub_path {
+            Some(path) if path.is_file() => path,
+            _ => return Err(CoverError::SourceUnavailable(content_id.to_string())),
+        };
+
+        for entry in self.cache_entries(epub_path)? {
+            remove_if_exists(&entry)?;
+        }
+        let files = self.extract_renditions(epub_path, true)?;
+        let cover_path = files.full.expect("full rendition requested");
+
+        let stale = previous
+            .map(PathBuf::from)
+            .into_iter()
+            .chain(std::iter::once(self.cache_dir.join(format!(
+                "{}_placeholder.svg",
+                placeholder_key(content_id)
+            ))))
+            .flat_map(|path| thumbnail_of(&path).into_iter().chain(Some(path)));
+        for path in stale {
+            if path != cover_path && path != files.thumbnail && path.starts_with(&self.cache_dir) {
+                remove_if_exists(&path)?;
+            }
+        }
+
+        Ok(CoverRefresh {
+            content_id: content_id.to_string(),
+            cover_path: cover_path.to_string_lossy().to_string(),
+            thumbnail_path: files.thumbnail.to_string_lossy().to_string(),
+            source: files.source,
+        })
+    }
+
     /// Clear the cache directory
     pub fn clear_cache(&self) -> Result<(), CoverError> {
         if self.cache_dir.exists() {
@@ -243,17 +544,174 @@ impl CoverExtractor {
         Ok(())
     }
 
+    /// Remove cached covers not rewritten within `max_age`
+    ///
+    /// Cache keys include the EPUB's modification time, so entries for
+    /// updated or deleted books are never reused. Returns how many were removed.
+    pub fn evict_older_than(&self, max_age: std::time::Duration) -> Result<usize, CoverError> {
+        let mut removed = 0;
+        if !self.cache_dir.exists() {
+            return Ok(removed);
+        }
+
+        for entry in fs::read_dir(&self.cache_dir)? {
+            let path = entry?.path();
+            let age = fs::metadata(&path)?
+                .modified()?
+                .elapsed()
+                .unwrap_or_default();
+            if path.is_file() && age > max_age {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
     /// Get cache directory path
     pub fn cache_dir(&self) -> &Path {
         &self.cache_dir
     }
 }
 
+/// Write a `THUMBNAIL_WIDTH` wide JPEG of the image in `data`
+///
+/// Images the decoder doesn't support are written as they are, so the
+/// webview still gets to try them.
+fn write_thumbnail(data: &[u8], path: &Path) -> Result<(), CoverError> {
+    let image = match image::load_from_memory(data) {
+        Ok(image) => image,
+        Err(e) => {
+            log::warn!("Cover not decodable ({}), keeping it as its thumbnail", e);
+            fs::write(path, data)?;
+            return Ok(());
+        }
+    };
+
+    let thumbnail = if image.width() > THUMBNAIL_WIDTH || image.height() > THUMBNAIL_MAX_HEIGHT {
+        image.resize(THUMBNAIL_WIDTH, THUMBNAIL_MAX_HEIGHT, FilterType::Triangle)
+    } else {
+        image
+    };
+    // JPEG has no alpha channel
+    let mut encoded = Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(thumbnail.to_rgb8())
+        .write_to(&mut encoded, ImageOutputFormat::Jpeg(THUMBNAIL_QUALITY))?;
+    fs::write(path, encoded.into_inner())?;
+    Ok(())
+}
+
+/// Cache key of the placeholder of a book with no EPUB at hand: the first
+/// 16 hex characters of its content ID's SHA-256, as in earlier versions, so
+/// the placeholders they wrote stay in use
+fn placeholder_key(content_id: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(content_id.as_bytes());
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+/// Thumbnail cached next to a full-size cover or placeholder
+fn thumbnail_of(path: &Path) -> Option<PathBuf> {
+    let stem = path.file_stem()?.to_str()?;
+    let extension = path.extension()?.to_str()?;
+    Some(path.with_file_name(format!(
+        "{}{}.{}",
+        stem,
+        Rendition::Thumbnail.suffix(),
+        extension
+    )))
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), CoverError> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Series name and position from Calibre's OPF metadata
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeriesInfo {
+    pub name: String,
+    pub index: Option<f32>,
+}
+
+/// Book metadata read from an EPUB's OPF
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpfMetadata {
+    pub series: Option<SeriesInfo>,
+    pub publication_year: Option<i32>,
+}
+
+/// Year of the first `<dc:date>` ("2019", "2019-05-01", "2019-05-01T00:00:00Z")
+pub fn parse_publication_year(opf: &str) -> Option<i32> {
+    let start = opf.find("<dc:date")?;
+    let value = &opf[start..];
+    let value = &value[value.find('>')? + 1..];
+    let value = value[..value.find('<')?].trim();
+    let year: String = value.chars().take_while(|c| c.is_ascii_digit()).collect();
+    (year.len() == 4).then(|| year.parse().ok()).flatten()
+}
+
+/// Parse `<meta name="calibre:series" content="..."/>` (and `series_index`)
+pub fn parse_calibre_series(opf: &str) -> Option<SeriesInfo> {
+    let name = opf_meta_content(opf, "calibre:series")?;
+    let index = opf_meta_content(opf, "calibre:series_index")
+        .and_then(|value| value.trim().parse::<f32>().ok())
+        .filter(|index| index.is_finite() && *index >= 0.0);
+
+    Some(SeriesInfo { name, index })
+}
+
+/// `content` of the first `<meta>` tag whose `name` matches
+fn opf_meta_content(opf: &str, name: &str) -> Option<String> {
+    let mut rest = opf;
+    while let Some(start) = rest.find("<meta") {
+        let tag_end = rest[start..].find('>')? + start;
+        let tag = &rest[start..tag_end];
+        rest = &rest[tag_end..];
+
+        if xml_attribute(tag, "name").as_deref() == Some(name) {
+            return xml_attribute(tag, "content").filter(|value| !value.trim().is_empty());
+        }
+    }
+    None
+}
+
+/// Value of `attr="..."` or `attr='...'` in a tag, with entities decoded
+fn xml_attribute(tag: &str, attr: &str) -> Option<String> {
+    let mut search = tag;
+    loop {
+        let pos = search.find(attr)?;
+        let preceded_by_space = search[..pos].ends_with(char::is_whitespace);
+        let after = search[pos + attr.len()..].trim_start();
+        search = &search[pos + attr.len()..];
+
+        if let (true, Some(after)) = (preceded_by_space, after.strip_prefix('=')) {
+            let after = after.trim_start();
+            let quote = after.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            let value = &after[1..];
+            let end = value.find(quote)?;
+            return Some(
+                value[..end]
+                    .replace("&quot;", "\"")
+                    .replace("&apos;", "'")
+                    .replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&amp;", "&"),
+            );
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum CoverError {
     Io(std::io::Error),
     Zip(zip::result::ZipError),
+    Image(image::ImageError),
     NoCoverFound,
+    /// The book's EPUB can't be reached (device disconnected or file gone)
+    SourceUnavailable(String),
 }
 
 impl std::fmt::Display for CoverError {
@@ -261,7 +719,11 @@ impl std::fmt::Display for CoverError {
         match self {
             CoverError::Io(e) => write!(f, "IO error: {}", e),
             CoverError::Zip(e) => write!(f, "ZIP error: {}", e),
+            CoverError::Image(e) => write!(f, "Image error: {}", e),
             CoverError::NoCoverFound => write!(f, "No cover found in EPUB"),
+            CoverError::SourceUnavailable(id) => {
+                write!(f, "EPUB of {} is unavailable; connect the device", id)
+            }
         }
     }
 }
@@ -271,7 +733,8 @@ impl std::error::Error for CoverError {
         match self {
             CoverError::Io(e) => Some(e),
             CoverError::Zip(e) => Some(e),
-            CoverError::NoCoverFound => None,
+            CoverError::Image(e) => Some(e),
+            CoverError::NoCoverFound | CoverError::SourceUnavailable(_) => None,
         }
     }
 }
@@ -282,6 +745,12 @@ impl From<std::io::Error> for CoverError {
     }
 }
 
+impl From<image::ImageError> for CoverError {
+    fn from(err: image::ImageError) -> Self {
+        CoverError::Image(err)
+    }
+}
+
 impl From<zip::result::ZipError> for CoverError {
     fn from(err: zip::result::ZipError) -> Self {
         CoverError::Zip(err)
@@ -355,6 +824,71 @@ mod tests {
         epub_path
     }
 
+    fn create_mock_epub_with_opf(temp_dir: &Path, name: &str, opf: &str) -> PathBuf {
+        let epub_path = temp_dir.join(name);
+        let file = fs::File::create(&epub_path).unwrap();
+        let mut zip = zip::ZipWriter::new(file);
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+        zip.start_file("META-INF/container.xml", options).unwrap();
+        zip.write_all(br#"<container><rootfiles><rootfile full-path="OEBPS/content.opf"/></rootfiles></container>"#)
+            .unwrap();
+        zip.start_file("OEBPS/content.opf", options).unwrap();
+        zip.write_all(opf.as_bytes()).unwrap();
+
+        zip.finish().unwrap();
+        epub_path
+    }
+
+    const SERIES_OPF: &str = r#"<package><metadata>
+        <dc:title>Dune Messiah</dc:title>
+        <meta name="cover" content="cover-img"/>
+        <meta content="2" name="calibre:series_index"/>
+        <meta name="calibre:series" content="Dune &amp; Sequels"/>
+    </metadata></package>"#;
+
+    #[test]
+    fn test_read_series_from_opf() {
+        let temp = TempDir::new().unwrap();
+        let epub_path = create_mock_epub_with_opf(temp.path(), "dune.epub", SERIES_OPF);
+
+        let extractor = CoverExtractor::new(temp.path().join("cache"));
+        let series = extractor.read_series(&epub_path).unwrap();
+
+        assert_eq!(
+            series,
+            Some(SeriesInfo {
+                name: "Dune & Sequels".to_string(),
+                index: Some(2.0),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_series_fractional_and_missing() {
+        let novella = r#"<meta name='calibre:series' content='Discworld'/>
+            <meta name='calibre:series_index' content='2.5'/>"#;
+        assert_eq!(parse_calibre_series(novella).unwrap().index, Some(2.5));
+
+        assert_eq!(
+            parse_publication_year(r#"<dc:date opf:event="publication">2019-05-01</dc:date>"#),
+            Some(2019)
+        );
+        assert_eq!(parse_publication_year("<dc:date>unknown</dc:date>"), None);
+
+        let no_index = r#"<meta name="calibre:series" content="Discworld"/>"#;
+        assert_eq!(parse_calibre_series(no_index).unwrap().index, None);
+
+        let standalone = r#"<metadata><meta name="calibre:title_sort" content="Dune"/></metadata>"#;
+        assert_eq!(parse_calibre_series(standalone), None);
+
+        let temp = TempDir::new().unwrap();
+        let epub_path = create_mock_epub_without_cover(temp.path());
+        let extractor = CoverExtractor::new(temp.path().join("cache"));
+        assert_eq!(extractor.read_series(&epub_path).unwrap(), None);
+    }
+
     #[test]
     fn test_extract_nested_cover_preference() {
         let temp = TempDir::new().unwrap();
@@ -454,6 +988,275 @@ mod tests {
         assert!(cache_files.is_empty());
     }
 
+    #[test]
+    fn test_evict_older_than() {
+        let temp = TempDir::new().unwrap();
+        let cache_dir = temp.path().join("cache");
+        let epub_path = create_mock_epub_with_cover(temp.path());
+        let extractor = CoverExtractor::new(cache_dir.clone());
+        extractor.extract_cover(&epub_path).unwrap();
+
+        let hour = std::time::Duration::from_secs(3600);
+        assert_eq!(extractor.evict_older_than(hour).unwrap(), 0);
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert_eq!(
+            extractor
+                .evict_older_than(std::time::Duration::from_millis(1))
+                .unwrap(),
+            2
+        );
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_extract_after_cache_dir_deleted() {
+        let temp = TempDir::new().unwrap();
+        let cache_dir = temp.path().join("cache");
+        let with_cover = create_mock_epub_with_cover(temp.path());
+        let without_cover = create_mock_epub_without_cover(temp.path());
+        let extractor = CoverExtractor::new(cache_dir.clone());
+
+        extractor.extract_cover(&with_cover).unwrap();
+        fs::remove_dir_all(&cache_dir).unwrap();
+
+        let cover = extractor.extract_cover(&with_cover).unwrap().unwrap();
+        assert!(cover.exists());
+        let placeholder = extractor.extract_cover(&without_cover).unwrap().unwrap();
+        assert!(placeholder.exists());
+    }
+
+    #[test]
+    fn test_repair_cover_after_cache_purge() {
+        let temp = TempDir::new().unwrap();
+        let device_root = temp.path().join("device");
+        fs::create_dir_all(&device_root).unwrap();
+        let epub_path = create_mock_epub_with_cover(&device_root);
+        let content_id = format!("{}test_with_cover.epub", ONBOARD_PREFIX);
+
+        let cache_dir = temp.path().join("cache");
+        let extractor = CoverExtractor::new(cache_dir.clone());
+        let cover = extractor.extract_cover(&epub_path).unwrap().unwrap();
+        let cover = cover.to_string_lossy().into_owned();
+        let device = DeviceFs::new(device_root);
+
+        // Still there: nothing to repair
+        assert!(extractor
+            .repair_cover(&content_id, &cover, Some(&device))
+            .unwrap()
+            .is_none());
+
+        fs::remove_dir_all(&cache_dir).unwrap();
+        let repaired = extractor
+            .repair_cover(&content_id, &cover, Some(&device))
+            .unwrap()
+            .unwrap();
+        assert_eq!(repaired.to_string_lossy(), cover);
+        assert!(repaired.exists());
+
+        // Without the device the book gets a placeholder
+        fs::remove_dir_all(&cache_dir).unwrap();
+        let placeholder = extractor
+            .repair_cover(&content_id, &cover, None)
+            .unwrap()
+            .unwrap();
+        assert!(placeholder.exists());
+        assert!(fs::read_to_string(placeholder)
+            .unwrap()
+            .contains("Sem Capa"));
+    }
+
+    /// An EPUB with a wrong `cover.jpg` and the real cover in `front.jpg`
+    fn write_epub_with_two_covers(epub_path: &Path, opf: &str) {
+        let file = fs::File::create(epub_path).unwrap();
+        let mut zip = zip::ZipWriter::new(file);
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+        zip.start_file("META-INF/container.xml", options).unwrap();
+        zip.write_all(br#"<container><rootfiles><rootfile full-path="OEBPS/content.opf"/></rootfiles></container>"#)
+            .unwrap();
+        zip.start_file("OEBPS/content.opf", options).unwrap();
+        zip.write_all(opf.as_bytes()).unwrap();
+        zip.start_file("OEBPS/cover.jpg", options).unwrap();
+        zip.write_all(b"wrong").unwrap();
+        zip.start_file("OEBPS/images/front.jpg", options).unwrap();
+        zip.write_all(b"front").unwrap();
+
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_refresh_cover_after_fixing_opf() {
+        let temp = TempDir::new().unwrap();
+        let device_root = temp.path().join("device");
+        fs::create_dir_all(&device_root).unwrap();
+        let epub_path = device_root.join("book.epub");
+        let content_id = format!("{}book.epub", ONBOARD_PREFIX);
+        write_epub_with_two_covers(&epub_path, "<package><manifest/></package>");
+
+        let cache_dir = temp.path().join("cache");
+        let extractor = CoverExtractor::new(cache_dir.clone());
+        let (wrong, source) = extractor.extract(&epub_path).unwrap();
+        assert_eq!(source, CoverSource::Fallback);
+        assert_eq!(fs::read(&wrong).unwrap(), b"wrong");
+
+        let other_epub = create_mock_epub_with_cover(temp.path());
+        let other = extractor.extract_cover(&other_epub).unwrap().unwrap();
+        let other_placeholder = extractor
+            .generate_placeholder(&placeholder_key("other-book"))
+            .unwrap();
+
+        write_epub_with_two_covers(
+            &epub_path,
+            r#"<package><manifest>
+                <item id="front" href="images/front.jpg" properties="cover-image"/>
+            </manifest></package>"#,
+        );
+        let previous = wrong.to_string_lossy().into_owned();
+
+        // Unreachable EPUB: the previous cover stays
+        assert!(matches!(
+            extractor.refresh_cover(&content_id, Some(&previous), None),
+            Err(CoverError::SourceUnavailable(_))
+        ));
+        assert!(wrong.exists());
+
+        let refreshed = extractor
+            .refresh_cover(&content_id, Some(&previous), Some(&epub_path))
+            .unwrap();
+        assert_eq!(refreshed.source, CoverSource::Opf);
+        let cover = PathBuf::from(&refreshed.cover_path);
+        assert_eq!(fs::read(&cover).unwrap(), b"front");
+        assert_eq!(extractor.cache_entries(&epub_path).unwrap()[0], cover);
+
+        // Only the refreshed book's entry changed
+        let mut cached: Vec<PathBuf> = fs::read_dir(&cache_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        cached.sort();
+        let mut expected = vec![
+            PathBuf::from(&refreshed.thumbnail_path),
+            cover,
+            thumbnail_of(&other).unwrap(),
+            other,
+            other_placeholder,
+        ];
+        expected.sort();
+        assert_eq!(cached, expected);
+    }
+
+    /// Size of the image at `path`, whatever its extension says
+    fn dimensions(path: &Path) -> (u32, u32) {
+        image::io::Reader::open(path)
+            .unwrap()
+            .with_guessed_format()
+            .unwrap()
+            .into_dimensions()
+            .unwrap()
+    }
+
+    /// An EPUB whose `cover.png` is a real `width` x `height` image
+    fn create_mock_epub_with_image(temp_dir: &Path, width: u32, height: u32) -> PathBuf {
+        let epub_path = temp_dir.join("test_with_image.epub");
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::new_rgba8(width, height)
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .unwrap();
+
+        let file = fs::File::create(&epub_path).unwrap();
+        let mut zip = zip::ZipWriter::new(file);
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("OEBPS/cover.png", options).unwrap();
+        zip.write_all(png.get_ref()).unwrap();
+        zip.finish().unwrap();
+
+        epub_path
+    }
+
+    #[test]
+    fn test_thumbnail_and_full_renditions() {
+        let temp = TempDir::new().unwrap();
+        let epub_path = create_mock_epub_with_image(temp.path(), 600, 900);
+        let extractor = CoverExtractor::new(temp.path().join("cache"));
+
+        let files = extractor.extract_renditions(&epub_path, true).unwrap();
+        assert_eq!(files.source, CoverSource::Fallback);
+        assert!(files.thumbnail.to_string_lossy().ends_with("_thumb.jpg"));
+        assert_eq!(dimensions(&files.thumbnail), (120, 180));
+        let full = files.full.unwrap();
+        assert_eq!(dimensions(&full), (600, 900));
+
+        // Cached: nothing is decoded or written again
+        let cached = extractor.extract_renditions(&epub_path, true).unwrap();
+        assert_eq!(cached.source, CoverSource::Cache);
+        assert_eq!(cached.thumbnail, files.thumbnail);
+        assert_eq!(cached.full, Some(full));
+
+        // Small covers aren't enlarged
+        let small = TempDir::new().unwrap();
+        let epub_path = create_mock_epub_with_image(small.path(), 80, 100);
+        let thumbnail = CoverExtractor::new(small.path().join("cache"))
+            .extract_thumbnail(&epub_path)
+            .unwrap();
+        assert_eq!(dimensions(&thumbnail), (80, 100));
+    }
+
+    #[test]
+    fn test_full_cover_on_demand_after_thumbnail() {
+        let temp = TempDir::new().unwrap();
+        let epub_path = create_mock_epub_with_image(temp.path(), 300, 450);
+        let extractor = CoverExtractor::new(temp.path().join("cache"));
+
+        let files = extractor.extract_renditions(&epub_path, false).unwrap();
+        assert_eq!(files.full, None);
+        let [full, _, thumbnail, _] = extractor.cache_entries(&epub_path).unwrap();
+        assert_eq!(files.thumbnail, thumbnail);
+        assert!(!full.exists());
+        let thumbnail_written = fs::metadata(&thumbnail).unwrap().modified().unwrap();
+
+        let cover = extractor.extract_cover(&epub_path).unwrap().unwrap();
+        assert_eq!(cover, full);
+        assert_eq!(dimensions(&cover), (300, 450));
+        assert_eq!(
+            fs::metadata(&thumbnail).unwrap().modified().unwrap(),
+            thumbnail_written
+        );
+    }
+
+    #[test]
+    fn test_placeholder_renditions() {
+        let temp = TempDir::new().unwrap();
+        let epub_path = create_mock_epub_without_cover(temp.path());
+        let extractor = CoverExtractor::new(temp.path().join("cache"));
+
+        let files = extractor.extract_renditions(&epub_path, false).unwrap();
+        assert_eq!(files.source, CoverSource::Placeholder);
+        assert!(files
+            .thumbnail
+            .to_string_lossy()
+            .ends_with("_placeholder_thumb.svg"));
+        let thumbnail = fs::read_to_string(&files.thumbnail).unwrap();
+        assert!(thumbnail.contains(r#"width="120" height="180""#));
+        let full = fs::read_to_string(files.full.clone().unwrap()).unwrap();
+        assert!(full.contains(r#"width="200" height="300""#));
+
+        // Cached placeholders are reused without opening the EPUB again
+        let modified = fs::metadata(&epub_path).unwrap().modified().unwrap();
+        let file = fs::File::create(&epub_path).unwrap();
+        file.set_modified(modified).unwrap();
+        drop(file);
+        let again = extractor.extract_renditions(&epub_path, true).unwrap();
+        assert_eq!(again, files);
+
+        // Undecodable covers become their own thumbnail
+        let epub_path = create_mock_epub_with_cover(temp.path());
+        let thumbnail = extractor.extract_thumbnail(&epub_path).unwrap();
+        assert_eq!(fs::read(thumbnail).unwrap()[..2], [0xFF, 0xD8]);
+    }
+
     #[test]
     fn test_cache_dir_created() {
         let temp = TempDir::new().unwrap();
@@ -0,0 +1,192 @@
+//! Import-time highlight filters
+//!
+//! Applied to extracted books before covers, the library store and exports
+//! see them, so junk highlights (accidental one-word selections, dictionary
+//! lookups) never leave the import pipeline.
+
+use crate::models::{Book, Highlight, HighlightKind, ImportFilters};
+use serde::{Deserialize, Serialize};
+
+/// What the filters dropped from one book
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FilteredBook {
+    pub content_id: String,
+    pub title: String,
+    pub highlights_removed: usize,
+    /// The whole book matched an exclusion pattern
+    pub excluded: bool,
+}
+
+/// Summary of an import filter pass
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FilterReport {
+    pub books_removed: usize,
+    pub highlights_removed: usize,
+    pub highlights_kept: usize,
+    /// Only books that lost at least one highlight
+    pub books: Vec<FilteredBook>,
+}
+
+impl ImportFilters {
+    pub fn is_active(&self) -> bool {
+        self.min_highlight_length > 0
+            || self.drop_punctuation_only
+            || self.excluded_books.iter().any(|p| !p.trim().is_empty())
+    }
+
+    fn excludes_book(&self, book: &Book) -> bool {
+        let title = book.title.to_lowercase();
+        let content_id = book.content_id.to_lowercase();
+        self.excluded_books
+            .iter()
+            .map(|pattern| pattern.trim().to_lowercase())
+            .filter(|pattern| !pattern.is_empty())
+            .any(|pattern| title.contains(&pattern) || content_id.contains(&pattern))
+    }
+
+    fn keeps_highlight(&self, highlight: &Highlight) -> bool {
+        // Figures have no text to judge
+        if highlight.kind == HighlightKind::Figure {
+            return true;
+        }
+        let text = highlight.text.trim();
+        if self.min_highlight_length > 0 && text.chars().count() < self.min_highlight_length {
+            return false;
+        }
+        if self.drop_punctuation_only
+            && text.chars().all(|c| {
+                c.is_whitespace() || c.is_ascii_punctuation() || EXTRA_PUNCTUATION.contains(c)
+            })
+        {
+            return false;
+        }
+        true
+    }
+}
+
+/// Common non-ASCII punctuation found in ebook text
+const EXTRA_PUNCTUATION: &str = "«»“”‘’„—–…¡¿·•。、「」";
+
+/// Drop filtered highlights, and books left without any, in place
+pub fn apply_import_filters(books: &mut Vec<Book>, filters: &ImportFilters) -> FilterReport {
+    let mut report = FilterReport::default();
+
+    books.retain_mut(|book| {
+        let before = book.highlights.len();
+        let excluded = filters.excludes_book(book);
+        if excluded {
+            book.highlights.clear();
+        } else {
+            book.highlights.retain(|h| filters.keeps_highlight(h));
+        }
+
+        let removed = before - book.highlights.len();
+        report.highlights_removed += removed;
+        report.highlights_kept += book.highlights.len();
+        if removed > 0 || excluded {
+            report.books.push(FilteredBook {
+                content_id: book.content_id.clone(),
+                title: book.title.clone(),
+                highlights_removed: removed,
+                excluded,
+            });
+        }
+
+        let keep = !book.highlights.is_empty();
+        if !keep {
+            report.books_removed += 1;
+        }
+        keep
+    });
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_book(content_id: &str, title: &str, texts: &[&str]) -> Book {
+        let mut book = Book::new(
+            content_id.to_string(),
+            title.to_string(),
+            "Author".to_string(),
+        );
+        for (i, text) in texts.iter().enumerate() {
+            book.highlights.push(Highlight::new(
+                format!("{}-{}", content_id, i),
+                text.to_string(),
+                "2025-01-24".to_string(),
+            ));
+        }
+        book
+    }
+
+    #[test]
+    fn test_default_filters_keep_everything() {
+        let mut books = vec![create_book(
+            "vol1",
+            "Book",
+            &["a", "...", "A full sentence."],
+        )];
+        let report = apply_import_filters(&mut books, &ImportFilters::default());
+
+        assert!(!ImportFilters::default().is_active());
+        assert_eq!(books[0].highlights.len(), 3);
+        assert_eq!(report.highlights_removed, 0);
+        assert!(report.books.is_empty());
+    }
+
+    #[test]
+    fn test_min_length_and_punctuation() {
+        let mut books = vec![
+            create_book("vol1", "Novel", &["a", " “…” ", "ação", "A full sentence."]),
+            create_book("vol2", "Short", &["x"]),
+        ];
+        let filters = ImportFilters {
+            min_highlight_length: 4,
+            drop_punctuation_only: true,
+            ..Default::default()
+        };
+        let report = apply_import_filters(&mut books, &filters);
+
+        // "ação" is four characters even though it is six bytes
+        assert_eq!(books.len(), 1);
+        let texts: Vec<&str> = books[0]
+            .highlights
+            .iter()
+            .map(|h| h.text.as_str())
+            .collect();
+        assert_eq!(texts, vec!["ação", "A full sentence."]);
+        assert_eq!(report.highlights_removed, 3);
+        assert_eq!(report.highlights_kept, 2);
+        assert_eq!(report.books_removed, 1);
+    }
+
+    #[test]
+    fn test_excluded_books_match_title_or_content_id() {
+        let mut books = vec![
+            create_book("vol1", "Oxford Dictionary of English", &["word", "other"]),
+            create_book("file:///mnt/onboard/manuals/guide.epub", "Guide", &["tip"]),
+            create_book("vol3", "Novel", &["kept"]),
+        ];
+        let filters = ImportFilters {
+            excluded_books: vec![
+                "dictionary".to_string(),
+                "/manuals/".to_string(),
+                " ".to_string(),
+            ],
+            ..Default::default()
+        };
+        let report = apply_import_filters(&mut books, &filters);
+
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].title, "Novel");
+        assert_eq!(report.books_removed, 2);
+        assert_eq!(report.books.len(), 2);
+        assert!(report.books.iter().all(|b| b.excluded));
+        assert_eq!(report.books[0].highlights_removed, 2);
+    }
+}
@@ -1,42 +1,244 @@
-use crate::models::{Book, Highlight};
-use rusqlite::{Connection, Result};
-use std::collections::HashMap;
+use super::schema::{SchemaCompatibility, SchemaFingerprint};
+use crate::models::{
+    Book, BookKind, Highlight, HighlightKind, ProgressScope, TocEntry, VocabEntry,
+};
+use crate::utils::date::{parse_timestamp, utc_offset};
+use crate::utils::disambiguation::sort_books;
+use crate::utils::format::{reading_percent, reading_secs};
+use crate::utils::slug::{assign_slugs, book_slug};
+use chrono::NaiveDateTime;
+use rusqlite::{Connection, OptionalExtension, Result};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::ops::Bound;
+use std::path::Path;
+
+/// Maximum seconds between two bookmarks for them to count as one split highlight
+pub const SPLIT_MERGE_MAX_SECONDS: i64 = 10;
+
+/// Maximum chapter progress gap (0.0-1.0) between the two halves of a split highlight
+pub const SPLIT_MERGE_MAX_PROGRESS_GAP: f64 = 0.05;
+
+/// Content ID of the book collecting lookups made in books without highlights
+pub const UNKNOWN_VOCABULARY_BOOK_ID: &str = "khi-vocabulary:unknown";
+
+/// Title of books the database doesn't name
+pub const UNKNOWN_TITLE: &str = "Unknown Title";
+
+/// Author of books without an attribution
+pub const UNKNOWN_AUTHOR: &str = "Unknown Author";
+
+/// Largest KoboReader.sqlite we are willing to open (real ones are well under 1 GB)
+pub const MAX_DATABASE_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+/// `content.MimeType` of kepubs
+const KEPUB_MIME_TYPE: &str = "application/x-kobo-epub+zip";
+
+/// First 16 bytes of every unencrypted SQLite database
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// Bookmark rows of figure highlights: blank text, a position in the book
+const FIGURE_ROW: &str =
+    "(trim(COALESCE(b.Text, '')) = '' AND COALESCE(b.StartContainerPath, '') != '')";
+
+/// Tables and columns the highlight query relies on
+pub(super) const REQUIRED_SCHEMA: &[(&str, &[&str])] = &[
+    (
+        "Bookmark",
+        &[
+            "BookmarkID",
+            "ContentID",
+            "VolumeID",
+            "Text",
+            "Annotation",
+            "StartContainerPath",
+            "ChapterProgress",
+            "DateCreated",
+        ],
+    ),
+    (
+        "content",
+        &[
+            "ContentID",
+            "BookTitle",
+            "Title",
+            "Attribution",
+            "ISBN",
+            "Publisher",
+            "Language",
+            "DateLastRead",
+            "ContentType",
+        ],
+    ),
+];
 
 pub struct KoboDatabase {
     conn: Connection,
+    schema: SchemaCompatibility,
+    /// Extract figure highlights (no text, a position) too
+    figures: bool,
 }
 
 impl KoboDatabase {
-    pub fn new(path: &std::path::Path) -> Result<Self> {
+    /// Open a Kobo database, refusing oversized, corrupt or non-Kobo files
+    pub fn new(path: &std::path::Path) -> std::result::Result<Self, KoboDbError> {
+        let size = std::fs::metadata(path)?.len();
+        if size > MAX_DATABASE_BYTES {
+            return Err(KoboDbError::TooLarge(size));
+        }
+
         let conn = Connection::open(path)?;
-        Ok(Self { conn })
+        let integrity: String = conn
+            .query_row("PRAGMA quick_check", [], |row| row.get(0))
+            .map_err(|e| KoboDbError::from_open_error(e).check_header(path))?;
+        if integrity != "ok" {
+            return Err(KoboDbError::CorruptDatabase(integrity));
+        }
+
+        check_kobo_schema(&conn).map_err(|e| e.check_header(path))?;
+
+        // Unfamiliar schemas only warn: the required columns are present
+        let schema = match SchemaFingerprint::read(&conn) {
+            Ok(fingerprint) => {
+                log::info!(
+                    "[Kobo] Schema fingerprint: DbVersion {:?}, {} column(s)",
+                    fingerprint.db_version,
+                    fingerprint.columns.len()
+                );
+                fingerprint.compatibility()
+            }
+            Err(e) => {
+                log::warn!("[Kobo] Schema fingerprint unavailable: {}", e);
+                SchemaCompatibility::Known
+            }
+        };
+        if let Some(message) = schema.message() {
+            log::warn!("[Kobo] {}: {:?}", message, schema);
+        }
+        Ok(Self {
+            conn,
+            schema,
+            figures: false,
+        })
+    }
+
+    /// Also extract highlights made on figures, as `HighlightKind::Figure`
+    /// with empty text
+    pub fn with_figures(mut self, figures: bool) -> Self {
+        self.figures = figures;
+        self
     }
 
+    /// How the database schema compares with the ones khi was tested with
+    pub fn schema_compatibility(&self) -> &SchemaCompatibility {
+        &self.schema
+    }
+
+    /// Books with their visible highlights (hidden ones are skipped)
     pub fn extract_books_with_highlights(&self) -> Result<Vec<Book>> {
+        self.extract_books(false)
+    }
+
+    /// Books with highlights, optionally including the ones deleted on the
+    /// device, which are kept as hidden rows and marked `is_excluded`
+    ///
+    /// Firmware without a `Bookmark.Hidden` column treats every row as visible.
+    pub fn extract_books(&self, include_hidden: bool) -> Result<Vec<Book>> {
         log::info!("Starting extract_books_with_highlights");
 
-        // First, check if tables exist and have data
-        let count_result: Result<i64, _> = self.conn.query_row(
-            "SELECT COUNT(*) FROM Bookmark WHERE Text IS NOT NULL AND Text != ''",
+        // First, check if tables exist and have data; the volume count sizes
+        // the book map
+        let count_result: Result<(i64, i64), _> = self.conn.query_row(
+            "SELECT COUNT(*), COUNT(DISTINCT VolumeID) FROM Bookmark
+             WHERE Text IS NOT NULL AND Text != ''",
             [],
-            |row| row.get(0),
+            |row| Ok((row.get(0)?, row.get(1)?)),
         );
 
-        match count_result {
-            Ok(count) => log::info!("Found {} bookmarks with text", count),
+        let volumes = match count_result {
+            Ok((count, volumes)) => {
+                log::info!("Found {} bookmarks with text in {} volumes", count, volumes);
+                volumes.max(0) as usize
+            }
             Err(e) => {
                 log::error!("Error counting bookmarks: {}", e);
                 // Continue anyway to try the main query and get detailed error
+                0
             }
-        }
+        };
+
+        // Chapter titles and tables of contents come from the ContentType 899
+        // (TOC entry) rows, read once up front: joining them per bookmark by
+        // ContentID prefix (LIKE) goes quadratic on large databases. Chapter
+        // titles are a nicety: an unreadable TOC leaves them to the page titles
+        let mut toc = self.toc_index().unwrap_or_else(|e| {
+            log::warn!("Failed to read TOC entries, importing without them: {}", e);
+            TocIndex::default()
+        });
 
         // Query to get all bookmarks (highlights) with their content info
-        // We need three JOINs:
+        // We need two JOINs:
         // 1. c_book: joined by VolumeID to get book metadata (author, ISBN, etc.)
-        // 2. c_chapter: joined by ContentID to get chapter title (ContentType 9 — XHTML page)
-        // 3. c_toc: joined by ContentID prefix to get real chapter title (ContentType 899 — TOC entry)
-        //    TOC entries have ContentID = page ContentID + suffix "-N" (e.g., "-1", "-2")
-        let query = "SELECT
+        // 2. c_chapter: joined by ContentID to get the page title (ContentType 9 — XHTML page),
+        //    used when no TOC entry names the chapter
+        let hidden = if self.has_column("Bookmark", "Hidden")? {
+            "lower(CAST(b.Hidden AS TEXT)) IN ('1', 'true')"
+        } else {
+            "0"
+        };
+        // Page count of the rendered book, on firmware that records it
+        let num_pages = if self.has_column("content", "___NumPages")? {
+            "c_book.___NumPages"
+        } else {
+            "NULL"
+        };
+        let mime_type = if self.has_column("content", "MimeType")? {
+            "c_book.MimeType"
+        } else {
+            "NULL"
+        };
+        // Web address of Pocket articles
+        let content_url = if self.has_column("content", "ContentURL")? {
+            "c_book.ContentURL"
+        } else {
+            "NULL"
+        };
+        // Library loans: a non-zero expiration status, and on firmware that
+        // records it, the due date
+        let expiration_status = if self.has_column("content", "___ExpirationStatus")? {
+            "CAST(c_book.___ExpirationStatus AS INTEGER)"
+        } else {
+            "NULL"
+        };
+        let expiration_date = if self.has_column("content", "ExpirationDate")? {
+            "c_book.ExpirationDate"
+        } else {
+            "NULL"
+        };
+        // Rows without text that still point at a page are figure
+        // highlights, unless they are bookmarks ("dogear")
+        let figures = if !self.figures {
+            String::new()
+        } else if self.has_column("Bookmark", "Type")? {
+            format!("OR ({} AND COALESCE(b.Type, '') != 'dogear')", FIGURE_ROW)
+        } else {
+            format!("OR {}", FIGURE_ROW)
+        };
+        // Reading statistics, on firmware that records them
+        let percent_read = if self.has_column("content", "___PercentRead")? {
+            "CAST(c_book.___PercentRead AS REAL)"
+        } else {
+            "NULL"
+        };
+        let time_spent = if self.has_column("content", "TimeSpentReading")? {
+            "CAST(c_book.TimeSpentReading AS INTEGER)"
+        } else {
+            "NULL"
+        };
+        let query = format!(
+            "SELECT
                 b.BookmarkID,
                 b.ContentID,
                 b.VolumeID,
@@ -45,127 +247,125 @@ impl KoboDatabase {
                 b.StartContainerPath,
                 b.ChapterProgress,
                 b.DateCreated,
-                COALESCE(c_book.Title, c_book.BookTitle, c_chapter.BookTitle, c_chapter.Title, 'Unknown Title') as BookTitle,
-                COALESCE(
-                    c_toc.Title,
-                    CASE WHEN c_chapter.Title IS NOT NULL
-                              AND c_chapter.Title NOT LIKE '%.xhtml%'
-                              AND c_chapter.Title NOT LIKE '%.html%'
-                              AND c_chapter.Title NOT LIKE '%.htm%'
-                              AND c_chapter.Title NOT LIKE '%/%'
-                         THEN c_chapter.Title
-                         ELSE NULL
-                    END
-                ) as ChapterTitle,
+                COALESCE(c_book.Title, c_book.BookTitle, c_chapter.BookTitle, c_chapter.Title) as BookTitle,
+                c_book.ContentID IS NULL as Orphaned,
+                CASE WHEN c_chapter.Title IS NOT NULL
+                          AND c_chapter.Title NOT LIKE '%.xhtml%'
+                          AND c_chapter.Title NOT LIKE '%.html%'
+                          AND c_chapter.Title NOT LIKE '%.htm%'
+                          AND c_chapter.Title NOT LIKE '%/%'
+                     THEN c_chapter.Title
+                     ELSE NULL
+                END as PageTitle,
                 c_book.Attribution,
                 c_book.ISBN,
                 c_book.Publisher,
                 c_book.Language,
-                c_book.DateLastRead
+                c_book.DateLastRead,
+                {} as Hidden,
+                {} as NumPages,
+                {} as MimeType,
+                {} as ContentURL,
+                {} as ExpirationStatus,
+                {} as ExpirationDate,
+                {} as PercentRead,
+                {} as TimeSpentReading
              FROM Bookmark b
              LEFT JOIN content c_book ON b.VolumeID = c_book.ContentID
              LEFT JOIN content c_chapter ON b.ContentID = c_chapter.ContentID
-             LEFT JOIN content c_toc ON c_toc.ContentType = 899
-                AND c_toc.ContentID LIKE b.ContentID || '%'
-             WHERE b.Text IS NOT NULL AND b.Text != ''
-             ORDER BY BookTitle, b.DateCreated";
+             WHERE (b.Text IS NOT NULL AND b.Text != '') {}
+             ORDER BY BookTitle, b.DateCreated",
+            hidden,
+            num_pages,
+            mime_type,
+            content_url,
+            expiration_status,
+            expiration_date,
+            percent_read,
+            time_spent,
+            figures
+        );
 
-        let mut stmt = self.conn.prepare(query).map_err(|e| {
+        let mut stmt = self.conn.prepare(&query).map_err(|e| {
             log::error!("Failed to prepare query: {}", e);
             e
         })?;
 
-        let rows = stmt.query_map([], |row| {
-            Ok((
-                row.get::<_, String>("BookmarkID")?,
-                // Use VolumeID as the grouping key for the book, as ContentID is specific to the chapter/fragment
-                row.get::<_, String>("VolumeID")?,
-                row.get::<_, Option<String>>("BookTitle")?,
-                row.get::<_, Option<String>>("ChapterTitle")?,
-                row.get::<_, Option<String>>("Attribution")?,
-                row.get::<_, Option<String>>("Text")?,
-                row.get::<_, Option<String>>("Annotation")?,
-                row.get::<_, Option<String>>("StartContainerPath")?,
-                row.get::<_, Option<f64>>("ChapterProgress")?,
-                row.get::<_, Option<String>>("DateCreated")?,
-                row.get::<_, Option<String>>("ISBN")?,
-                row.get::<_, Option<String>>("Publisher")?,
-                row.get::<_, Option<String>>("Language")?,
-                row.get::<_, Option<String>>("DateLastRead")?,
-            ))
-        })?;
-
-        // Group highlights by book
-        let mut books_map: HashMap<String, Book> = HashMap::new();
+        // Group highlights by book, reading rows as they come
+        let mut books_map: HashMap<String, Book> = HashMap::with_capacity(volumes);
+        let mut rows = stmt.query([])?;
 
-        for row in rows {
-            let (
-                bookmark_id,
-                volume_id, // This is our book ID
-                book_title,
-                chapter_title,
-                attribution,
-                text,
-                annotation,
-                container_path,
-                chapter_progress,
-                date_created,
-                isbn,
-                publisher,
-                language,
-                date_last_read,
-            ) = row?;
+        while let Some(row) = rows.next()? {
+            let hidden: bool = row.get("Hidden")?;
+            if hidden && !include_hidden {
+                continue;
+            }
 
+            let text = row.get::<_, Option<String>>("Text")?.unwrap_or_default();
+            let container_path: Option<String> = row.get("StartContainerPath")?;
+            let kind = if self.figures {
+                HighlightKind::detect(&text, container_path.as_deref())
+            } else {
+                HighlightKind::Text
+            };
             // Skip if no text
-            let text = match text {
-                Some(t) if !t.is_empty() => t,
-                _ => continue,
+            if text.is_empty() && kind == HighlightKind::Text {
+                continue;
+            }
+            let text = match kind {
+                HighlightKind::Figure => String::new(),
+                HighlightKind::Text => text,
             };
 
-            // Get or create book using volume_id as key
-            let book = books_map.entry(volume_id.clone()).or_insert_with(|| {
-                let mut b = Book::new(
-                    volume_id.clone(),
-                    book_title
-                        .clone()
-                        .unwrap_or_else(|| "Unknown Title".to_string()),
-                    attribution
-                        .clone()
-                        .unwrap_or_else(|| "Unknown Author".to_string()),
-                );
+            // Use VolumeID as the grouping key for the book, as ContentID is specific to the chapter/fragment
+            let volume_id: String = row.get("VolumeID")?;
 
-                // Set file path if it looks like a local file
-                if volume_id.starts_with("file:///mnt/onboard/") {
-                    b.file_path = Some(volume_id.replace("file:///mnt/onboard/", ""));
+            // Get or create book using volume_id as key; book columns are
+            // only read again while some metadata is still missing
+            let book = match books_map.entry(volume_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let book = new_book(entry.key(), row)?;
+                    entry.insert(book)
                 }
-                
-                b
-            });
+            };
 
             // Update book metadata if available
-            if book.isbn.is_none() && isbn.is_some() {
-                book.isbn = isbn;
-            }
-            if book.publisher.is_none() && publisher.is_some() {
-                book.publisher = publisher;
-            }
-            if book.language.is_none() && language.is_some() {
-                book.language = language;
-            }
-            if book.date_last_read.is_none() && date_last_read.is_some() {
-                book.date_last_read = date_last_read;
-            }
+            fill_missing(&mut book.isbn, row, "ISBN")?;
+            fill_missing(&mut book.publisher, row, "Publisher")?;
+            fill_missing(&mut book.language, row, "Language")?;
+            fill_missing(&mut book.date_last_read, row, "DateLastRead")?;
+
+            // The page's first TOC entry names the chapter, else the page title
+            let content_id: String = row.get("ContentID")?;
+            let chapter_title = match toc.chapter_title(&content_id) {
+                Some(title) => Some(title.to_string()),
+                None => row.get("PageTitle")?,
+            };
+            let chapter_progress: Option<f64> = row.get("ChapterProgress")?;
+
+            let date_created = row
+                .get::<_, Option<String>>("DateCreated")?
+                .unwrap_or_else(|| "Unknown".to_string());
 
             // Create highlight
             let highlight = Highlight {
-                id: bookmark_id,
+                id: row.get("BookmarkID")?,
                 text,
-                annotation,
+                annotation: row.get("Annotation")?,
                 chapter_title,
                 chapter_progress,
                 container_path,
-                date_created: date_created.unwrap_or_else(|| "Unknown".to_string()),
+                date_created_utc: parse_timestamp(&date_created, utc_offset()),
+                date_created,
                 color: None, // Color não disponível neste modelo
+                merged_from: Vec::new(),
+                stable_id: String::new(),
+                is_excluded: hidden,
+                kind,
+                figure_path: None,
+                tags: Vec::new(),
+                page: estimate_page(chapter_progress, book.progress_scope, row.get("NumPages")?),
             };
 
             book.highlights.push(highlight);
@@ -173,6 +373,10 @@ impl KoboDatabase {
 
         // Convert HashMap to Vec
         let mut books: Vec<Book> = books_map.into_values().collect();
+        for book in &mut books {
+            assign_stable_ids(book);
+            book.toc = toc.books.remove(&book.content_id).unwrap_or_default();
+        }
 
         log::info!("Total distinct books collected in HashMap: {}", books.len());
         for b in &books {
@@ -184,17 +388,814 @@ impl KoboDatabase {
             );
         }
 
-        // Sort books by title
-        books.sort_by(|a, b| a.title.cmp(&b.title));
+        let mut books = merge_cloud_duplicates(books);
+        assign_slugs(&mut books);
+
+        // Sort books by title (same-title books in a stable order)
+        sort_books(&mut books);
 
         Ok(books)
     }
+
+    /// Every ContentType 899 row, grouped for `extract_books`
+    ///
+    /// Same rules as `extract_toc`, in one pass over the table instead of a
+    /// query per book.
+    fn toc_index(&self) -> Result<TocIndex> {
+        let columns = self.table_columns("content")?;
+        let has = |name: &str| columns.iter().any(|c| c.eq_ignore_ascii_case(name));
+        let (depth, order) = toc_columns(&has);
+        let book_id = if has("BookID") { "BookID" } else { "NULL" };
+
+        let query = format!(
+            "SELECT ContentID, Title, {}, {} FROM content
+             WHERE ContentType = 899 AND Title IS NOT NULL AND trim(Title) != ''
+             ORDER BY {}",
+            depth, book_id, order
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let mut rows = stmt.query([])?;
+
+        let mut index = TocIndex::default();
+        while let Some(row) = rows.next()? {
+            let content_id: String = row.get(0)?;
+            let title = row.get::<_, String>(1)?.trim().to_string();
+            let depth = row.get::<_, Option<i64>>(2)?.unwrap_or(1).max(1) as u32;
+            let book_id: Option<String> = row.get(3)?;
+
+            let prefix = content_id.split_once('!').map(|(volume, _)| volume);
+            let mut owners: Vec<&str> = book_id.as_deref().into_iter().chain(prefix).collect();
+            owners.dedup();
+            for owner in owners {
+                let toc = index.books.entry(owner.to_string()).or_default();
+                toc.push(TocEntry {
+                    title: title.clone(),
+                    depth,
+                    order: toc.len() as u32,
+                });
+            }
+            let position = index.entries.len();
+            index.entries.entry(content_id).or_insert((position, title));
+        }
+        Ok(index)
+    }
+
+    /// Table of contents of a book, from its ContentType 899 rows
+    ///
+    /// Rows belong to the book by `BookID` or by a `ContentID` prefixed with
+    /// the volume ID. Firmware without `VolumeIndex`/`Depth` columns falls
+    /// back to row order and a flat list; books without TOC rows yield an
+    /// empty list.
+    pub fn extract_toc(&self, volume_id: &str) -> Result<Vec<TocEntry>> {
+        let columns = self.table_columns("content")?;
+        let has = |name: &str| columns.iter().any(|c| c.eq_ignore_ascii_case(name));
+        let (depth, order) = toc_columns(&has);
+        // substr() rather than LIKE: volume IDs often contain '_' and '%'
+        let book_match = if has("BookID") {
+            "(BookID = ?1 OR substr(ContentID, 1, length(?1) + 1) = ?1 || '!')"
+        } else {
+            "substr(ContentID, 1, length(?1) + 1) = ?1 || '!'"
+        };
+
+        let query = format!(
+            "SELECT Title, {} FROM content
+             WHERE ContentType = 899 AND {} AND Title IS NOT NULL AND trim(Title) != ''
+             ORDER BY {}",
+            depth, book_match, order
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let rows = stmt.query_map([volume_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<i64>>(1)?))
+        })?;
+
+        let mut toc = Vec::new();
+        for row in rows {
+            let (title, depth) = row?;
+            toc.push(TocEntry {
+                title: title.trim().to_string(),
+                depth: depth.unwrap_or(1).max(1) as u32,
+                order: toc.len() as u32,
+            });
+        }
+        Ok(toc)
+    }
+
+    /// Dictionary lookups from the `WordList` table, in lookup order
+    ///
+    /// Firmware without the table yields an empty list, and without a
+    /// `DateCreated` column entries have no date.
+    pub fn extract_word_list(&self) -> Result<Vec<VocabEntry>> {
+        let columns = self.table_columns("WordList")?;
+        if columns.is_empty() {
+            log::info!("No WordList table; skipping vocabulary");
+            return Ok(Vec::new());
+        }
+        let date = if columns
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case("DateCreated"))
+        {
+            "DateCreated"
+        } else {
+            "NULL"
+        };
+
+        let query = format!(
+            "SELECT Text, VolumeId, {} FROM WordList
+             WHERE Text IS NOT NULL AND trim(Text) != ''
+             ORDER BY rowid",
+            date
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let entries = stmt
+            .query_map([], |row| {
+                Ok(VocabEntry {
+                    word: row.get::<_, String>(0)?.trim().to_string(),
+                    volume_id: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                    date_created: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+
+    /// The book of the newest bookmark, holding only that bookmark
+    ///
+    /// Cheaper than `extract_books` for checks that only need the newest
+    /// date (see `device::freshness`); hidden bookmarks count too.
+    pub fn newest_bookmark(&self) -> Result<Option<Book>> {
+        self.conn
+            .query_row(
+                "SELECT b.BookmarkID, b.VolumeID, b.DateCreated,
+                    COALESCE(c_book.Title, c_book.BookTitle, c_chapter.BookTitle, c_chapter.Title),
+                    COALESCE(c_book.Attribution, c_chapter.Attribution)
+                 FROM Bookmark b
+                 LEFT JOIN content c_book ON c_book.ContentID = b.VolumeID
+                 LEFT JOIN content c_chapter ON c_chapter.ContentID = b.ContentID
+                 WHERE b.DateCreated = (SELECT max(DateCreated) FROM Bookmark)
+                 LIMIT 1",
+                [],
+                |row| {
+                    let mut book = Book::new(
+                        row.get(1)?,
+                        row.get::<_, Option<String>>(3)?
+                            .unwrap_or_else(|| UNKNOWN_TITLE.to_string()),
+                        row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                    );
+                    book.highlights
+                        .push(Highlight::new(row.get(0)?, String::new(), row.get(2)?));
+                    Ok(book)
+                },
+            )
+            .optional()
+    }
+
+    fn table_columns(&self, table: &str) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("PRAGMA table_info({})", table))?;
+        let columns = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(columns)
+    }
+
+    fn has_column(&self, table: &str, column: &str) -> Result<bool> {
+        Ok(self
+            .table_columns(table)?
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case(column)))
+    }
+}
+
+/// ContentType 899 rows of a database, read once per import
+#[derive(Debug, Default)]
+struct TocIndex {
+    /// Reading position and title of each entry, by ContentID
+    entries: BTreeMap<String, (usize, String)>,
+    /// TOC entries in reading order, by volume ID
+    books: HashMap<String, Vec<TocEntry>>,
+}
+
+impl TocIndex {
+    /// Title of the first entry (in reading order) whose ContentID starts
+    /// with the page's: entries are named after their page plus "-N"
+    fn chapter_title(&self, page_id: &str) -> Option<&str> {
+        self.entries
+            .range::<str, _>((Bound::Included(page_id), Bound::Unbounded))
+            .take_while(|(content_id, _)| content_id.starts_with(page_id))
+            .min_by_key(|(_, (position, _))| *position)
+            .map(|(_, (_, title))| title.as_str())
+    }
+}
+
+/// Depth and ordering columns of TOC rows on this firmware
+fn toc_columns(has: &dyn Fn(&str) -> bool) -> (&'static str, &'static str) {
+    let depth = if has("Depth") { "Depth" } else { "1" };
+    let order = if has("VolumeIndex") {
+        "VolumeIndex, rowid"
+    } else {
+        "rowid"
+    };
+    (depth, order)
+}
+
+/// Book for the first highlight row of `volume_id`
+fn new_book(volume_id: &str, row: &rusqlite::Row) -> Result<Book> {
+    let book_title: Option<String> = row.get("BookTitle")?;
+    let attribution: Option<String> = row.get("Attribution")?;
+    let mime_type: Option<String> = row.get("MimeType")?;
+    let content_url: Option<String> = row.get("ContentURL")?;
+    let orphaned: bool = row.get("Orphaned")?;
+
+    let pocket = is_pocket_item(volume_id, mime_type.as_deref());
+    let source_url = content_url.filter(|url| pocket && is_web_url(url));
+    let mut b = Book::new(
+        volume_id.to_string(),
+        book_title
+            .or_else(|| source_url.clone())
+            .or_else(|| orphaned.then(|| orphan_title(volume_id)))
+            .unwrap_or_else(|| UNKNOWN_TITLE.to_string()),
+        attribution
+            .or_else(|| source_url.as_deref().and_then(url_host))
+            .unwrap_or_else(|| UNKNOWN_AUTHOR.to_string()),
+    );
+    b.is_orphaned = orphaned;
+
+    // Set file path if it looks like a local file
+    if volume_id.starts_with("file:///mnt/onboard/") {
+        b.file_path = Some(volume_id.replace("file:///mnt/onboard/", ""));
+    }
+    b.progress_scope = Some(progress_scope(volume_id, mime_type.as_deref()));
+    if pocket {
+        b.kind = BookKind::PocketArticle;
+        b.source_url = source_url;
+    }
+    let expiration_status: Option<i64> = row.get("ExpirationStatus")?;
+    b.loan_expiry = row
+        .get::<_, Option<String>>("ExpirationDate")?
+        .filter(|date| !date.trim().is_empty());
+    b.is_loan = expiration_status.is_some_and(|status| status != 0) || b.loan_expiry.is_some();
+    b.percent_read = reading_percent(row.get("PercentRead")?);
+    b.time_spent_reading_secs = reading_secs(row.get("TimeSpentReading")?);
+    Ok(b)
+}
+
+/// Title of a book whose content row is gone: its file name, or for store
+/// books (a UUID) "Unknown Title" with the start of the ID, so two of them
+/// can be told apart
+fn orphan_title(volume_id: &str) -> String {
+    title_from_volume_id(volume_id).unwrap_or_else(|| {
+        let id: String = volume_id.chars().take(8).collect();
+        format!("{} ({})", UNKNOWN_TITLE, id)
+    })
+}
+
+/// Best-effort title of a book known only by its volume ID: the file name,
+/// URL-decoded, without its path and extensions
+///
+/// `file:///mnt/onboard/Books/O%20Alquimista.kepub.epub` gives "O Alquimista".
+/// `None` for IDs without a usable file name (e.g. bare UUIDs of store books).
+fn title_from_volume_id(volume_id: &str) -> Option<String> {
+    let name = volume_id.rsplit(['/', '\\']).next()?;
+    let name = percent_decode(name);
+    let (stem, extension) = name.rsplit_once('.')?;
+    if extension.is_empty() || extension.len() > 5 {
+        return None;
+    }
+    let stem = stem
+        .strip_suffix(".kepub")
+        .unwrap_or(stem)
+        .replace('_', " ");
+    let title = stem.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!title.is_empty()).then_some(title)
+}
+
+/// Decode `%XX` escapes; malformed ones are kept as they are
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Set `field` from `column` of `row` unless it already has a value
+fn fill_missing(field: &mut Option<String>, row: &rusqlite::Row, column: &str) -> Result<()> {
+    if field.is_none() {
+        *field = row.get(column)?;
+    }
+    Ok(())
+}
+
+/// Best-effort page from reading progress and the book's page count
+///
+/// Unknown, zero or negative page counts (and out-of-range progress) give
+/// `None` so exports fall back to the percentage. So does chapter-relative
+/// progress, which says nothing about the page.
+fn estimate_page(
+    progress: Option<f64>,
+    scope: Option<ProgressScope>,
+    num_pages: Option<i64>,
+) -> Option<u32> {
+    if scope == Some(ProgressScope::Chapter) {
+        return None;
+    }
+    let progress = progress.filter(|p| (0.0..=1.0).contains(p))?;
+    let num_pages = u32::try_from(num_pages?).ok().filter(|&n| n > 0)?;
+    Some(((progress * num_pages as f64).round() as u32).clamp(1, num_pages))
+}
+
+/// Verify that `conn` has the Kobo tables and columns we query
+pub fn check_kobo_schema(conn: &Connection) -> std::result::Result<(), KoboDbError> {
+    for (table, columns) in REQUIRED_SCHEMA {
+        let mut stmt = conn
+            .prepare(&format!("PRAGMA table_info({})", table))
+            .map_err(KoboDbError::from_open_error)?;
+        let present: Vec<String> = stmt
+            .query_map([], |row| row.get::<_, String>("name"))
+            .map_err(KoboDbError::from_open_error)?
+            .collect::<Result<_>>()?;
+
+        if present.is_empty() {
+            return Err(KoboDbError::NotAKoboDatabase(format!(
+                "missing table {}",
+                table
+            )));
+        }
+        if let Some(missing) = columns
+            .iter()
+            .find(|c| !present.iter().any(|p| p.eq_ignore_ascii_case(c)))
+        {
+            return Err(KoboDbError::NotAKoboDatabase(format!(
+                "missing column {}.{}",
+                table, missing
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Kobo database errors
+#[derive(Debug)]
+pub enum KoboDbError {
+    /// SQLite error
+    Sqlite(rusqlite::Error),
+    /// IO error
+    Io(std::io::Error),
+    /// The file exceeds `MAX_DATABASE_BYTES`
+    TooLarge(u64),
+    /// A valid SQLite file without the Kobo schema
+    NotAKoboDatabase(String),
+    /// A SQLite file that failed the integrity check
+    CorruptDatabase(String),
+    /// No SQLite header: encrypted (SQLCipher) or not a database at all
+    EncryptedOrInvalidDatabase,
+}
+
+impl KoboDbError {
+    /// Classify errors from the first statements run on a freshly opened file
+    fn from_open_error(err: rusqlite::Error) -> Self {
+        match err.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::NotADatabase)
+            | Some(rusqlite::ErrorCode::DatabaseCorrupt) => {
+                KoboDbError::CorruptDatabase(err.to_string())
+            }
+            _ => KoboDbError::Sqlite(err),
+        }
+    }
+
+    /// Tell an unreadable file without the SQLite magic (encrypted, or not a
+    /// database) from a damaged SQLite file
+    pub fn check_header(self, path: &Path) -> Self {
+        match self {
+            KoboDbError::CorruptDatabase(_) if !has_sqlite_header(path) => {
+                KoboDbError::EncryptedOrInvalidDatabase
+            }
+            other => other,
+        }
+    }
+}
+
+/// Whether the file starts with the 16-byte SQLite magic
+fn has_sqlite_header(path: &Path) -> bool {
+    let mut header = [0u8; 16];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok()
+        && &header == SQLITE_HEADER
+}
+
+impl std::fmt::Display for KoboDbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KoboDbError::Sqlite(e) => write!(f, "Database error: {}", e),
+            KoboDbError::Io(e) => write!(f, "IO error: {}", e),
+            KoboDbError::TooLarge(size) => write!(
+                f,
+                "Database is too large ({} bytes, limit {})",
+                size, MAX_DATABASE_BYTES
+            ),
+            KoboDbError::NotAKoboDatabase(reason) => {
+                write!(f, "Not a Kobo database ({})", reason)
+            }
+            KoboDbError::CorruptDatabase(reason) => write!(f, "Corrupt database: {}", reason),
+            KoboDbError::EncryptedOrInvalidDatabase => write!(
+                f,
+                "Database not readable (encrypted?): the file has no SQLite header. \
+                 Encrypted copies made by backup tools or managed readers aren't supported; \
+                 import from the reader itself or an unencrypted copy"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for KoboDbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KoboDbError::Sqlite(e) => Some(e),
+            KoboDbError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for KoboDbError {
+    fn from(err: rusqlite::Error) -> Self {
+        KoboDbError::Sqlite(err)
+    }
+}
+
+impl From<std::io::Error> for KoboDbError {
+    fn from(err: std::io::Error) -> Self {
+        KoboDbError::Io(err)
+    }
+}
+
+/// Short hash over the parts that identify a highlight independently of its BookmarkID
+fn stable_hash(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0u8]);
+    }
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+/// Assign content-derived `stable_id`s to a book's highlights
+///
+/// The ID hashes the book identity (ISBN, else the normalized volume path),
+/// the chapter and the whitespace-normalized text. Identical quotes in the
+/// same chapter are told apart by folding in their position, and as a last
+/// resort their order of appearance.
+pub fn assign_stable_ids(book: &mut Book) {
+    let book_key = match book.isbn.as_deref().map(str::trim) {
+        Some(isbn) if !isbn.is_empty() => isbn.to_string(),
+        _ => book
+            .content_id
+            .trim_start_matches("file:///mnt/onboard/")
+            .to_lowercase(),
+    };
+
+    let base: Vec<String> = book
+        .highlights
+        .iter()
+        .map(|h| {
+            let chapter = h
+                .chapter_title
+                .as_deref()
+                .or(h.container_path.as_deref())
+                .unwrap_or("");
+            let text = h.text.split_whitespace().collect::<Vec<_>>().join(" ");
+            stable_hash(&[&book_key, chapter, &text])
+        })
+        .collect();
+
+    let mut occurrences: HashMap<&str, usize> = HashMap::with_capacity(base.len());
+    for hash in &base {
+        *occurrences.entry(hash.as_str()).or_insert(0) += 1;
+    }
+
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (i, highlight) in book.highlights.iter_mut().enumerate() {
+        let duplicated = occurrences[base[i].as_str()] > 1;
+        let mut id = if duplicated {
+            let position = format!(
+                "{}|{}",
+                highlight.container_path.as_deref().unwrap_or(""),
+                highlight
+                    .chapter_progress
+                    .map(|p| format!("{:.4}", p))
+                    .unwrap_or_default()
+            );
+            stable_hash(&[&base[i], &position])
+        } else {
+            base[i].clone()
+        };
+
+        let count = seen.entry(id.clone()).or_insert(0);
+        *count += 1;
+        if *count > 1 {
+            id = format!("{}-{}", id, count);
+        }
+        highlight.stable_id = id;
+    }
+}
+
+/// Parse a Kobo `DateCreated` value into a naive (UTC) timestamp
+///
+/// Kobo writes `2025-01-24T10:15:30.000`, sometimes with a `Z` or offset;
+/// see `parse_timestamp` for the other shapes. Zone-less values are taken
+/// as UTC.
+pub fn parse_kobo_datetime(value: &str) -> Option<NaiveDateTime> {
+    parse_timestamp(value, utc_offset()).map(|dt| dt.naive_utc())
+}
+
+/// Whether text ends a sentence (ignoring trailing quotes and brackets)
+fn ends_sentence(text: &str) -> bool {
+    text.trim_end()
+        .trim_end_matches(['"', '\'', '”', '’', ')', ']', '»'])
+        .ends_with(['.', '!', '?', '…'])
+}
+
+/// Whether `next` looks like the continuation of `prev` split across a page boundary
+fn is_split_continuation(prev: &Highlight, next: &Highlight) -> bool {
+    if prev.kind != HighlightKind::Text
+        || next.kind != HighlightKind::Text
+        || prev.chapter_title != next.chapter_title
+        || prev.is_excluded != next.is_excluded
+        || ends_sentence(&prev.text)
+    {
+        return false;
+    }
+
+    let gap = match (prev.chapter_progress, next.chapter_progress) {
+        (Some(a), Some(b)) => b - a,
+        _ => return false,
+    };
+    if !(0.0..=SPLIT_MERGE_MAX_PROGRESS_GAP).contains(&gap) {
+        return false;
+    }
+
+    match (
+        parse_kobo_datetime(&prev.date_created),
+        parse_kobo_datetime(&next.date_created),
+    ) {
+        (Some(a), Some(b)) => (0..=SPLIT_MERGE_MAX_SECONDS).contains(&(b - a).num_seconds()),
+        _ => false,
+    }
+}
+
+/// Attach dictionary lookups to the books they were made in (by volume ID)
+///
+/// Lookups from volumes not in `books` are collected in an extra
+/// "Unknown book" with no highlights, added only when there are any.
+pub fn attach_vocabulary(books: &mut Vec<Book>, entries: Vec<VocabEntry>) {
+    let mut orphans = Vec::new();
+    for entry in entries {
+        match books.iter_mut().find(|b| b.content_id == entry.volume_id) {
+            Some(book) => book.vocabulary.push(entry),
+            None => orphans.push(entry),
+        }
+    }
+
+    if !orphans.is_empty() {
+        let mut unknown = Book::new(
+            UNKNOWN_VOCABULARY_BOOK_ID.to_string(),
+            "Unknown book".to_string(),
+            String::new(),
+        );
+        unknown.slug = book_slug(&unknown.content_id, &unknown.title);
+        unknown.vocabulary = orphans;
+        books.push(unknown);
+    }
+}
+
+/// Merge highlights that Kobo recorded as two bookmarks across a page boundary
+///
+/// Consecutive highlights in the same chapter, created within
+/// `SPLIT_MERGE_MAX_SECONDS` of each other, whose progress is adjacent and
+/// where the first doesn't end a sentence, are joined into one. The merged
+/// highlight keeps the first ID and date and lists every source ID in
+/// `merged_from`.
+pub fn merge_split_highlights(book: &mut Book) {
+    let mut merged: Vec<Highlight> = Vec::with_capacity(book.highlights.len());
+
+    for highlight in book.highlights.drain(..) {
+        match merged.last_mut() {
+            Some(prev) if is_split_continuation(prev, &highlight) => {
+                log::info!(
+                    "Merging split highlight {} into {} ('{}')",
+                    highlight.id,
+                    prev.id,
+                    book.title
+                );
+                if prev.merged_from.is_empty() {
+                    prev.merged_from.push(prev.id.clone());
+                }
+                prev.merged_from.push(highlight.id);
+                prev.text = format!("{} {}", prev.text.trim_end(), highlight.text.trim_start());
+                prev.annotation = match (prev.annotation.take(), highlight.annotation) {
+                    (Some(a), Some(b)) => Some(format!("{}\n{}", a, b)),
+                    (a, b) => a.or(b),
+                };
+                // Progress of the merged highlight extends to the continuation
+                prev.chapter_progress = highlight.chapter_progress;
+            }
+            _ => merged.push(highlight),
+        }
+    }
+
+    book.highlights = merged;
+}
+
+/// Whether a ContentID is a bare Kobo store UUID (`8-4-4-4-12` hex)
+fn is_store_uuid(content_id: &str) -> bool {
+    let groups: Vec<&str> = content_id.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(g, len)| g.len() == len && g.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Whether a ContentID is a kepub file on the device
+fn is_kepub_path(content_id: &str) -> bool {
+    content_id.starts_with("file://") && content_id.to_lowercase().ends_with(".kepub.epub")
+}
+
+/// Whether a volume is an article saved through Kobo's Pocket integration:
+/// by its MIME type, or by the numeric Pocket item ID Kobo uses as its
+/// VolumeID
+fn is_pocket_item(volume_id: &str, mime_type: Option<&str>) -> bool {
+    mime_type.is_some_and(|mime| mime.to_lowercase().contains("pocket"))
+        || (!volume_id.is_empty() && volume_id.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn is_web_url(url: &str) -> bool {
+    let url = url.trim().to_lowercase();
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+/// Host of a web address, without `www.`
+fn url_host(url: &str) -> Option<String> {
+    let rest = url.trim().split_once("://")?.1;
+    let host = rest.split(['/', '?', '#']).next()?;
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    (!host.is_empty()).then(|| host.to_string())
+}
+
+/// What a book's `ChapterProgress` is relative to
+///
+/// Kepubs (by MIME type or file name, and store books, which are always
+/// kepubs) report progress within the chapter; other formats within the book.
+fn progress_scope(volume_id: &str, mime_type: Option<&str>) -> ProgressScope {
+    if mime_type == Some(KEPUB_MIME_TYPE) || is_kepub_path(volume_id) || is_store_uuid(volume_id) {
+        ProgressScope::Chapter
+    } else {
+        ProgressScope::Book
+    }
+}
+
+/// Title, author and ISBN, trimmed, identifying a Kobo Cloud duplicate pair
+fn cloud_pair_key(book: &Book) -> (String, String, String) {
+    (
+        book.title.trim().to_string(),
+        book.author.trim().to_string(),
+        book.isbn.as_deref().unwrap_or("").trim().to_string(),
+    )
+}
+
+/// Lowercased file stem of a kepub path or a store UUID: a kepub Kobo Cloud
+/// downloaded is named after its store UUID
+fn volume_family(content_id: &str) -> String {
+    let name = content_id
+        .rsplit('/')
+        .next()
+        .unwrap_or(content_id)
+        .to_lowercase();
+    name.strip_suffix(".kepub.epub")
+        .unwrap_or(&name)
+        .to_string()
+}
+
+/// Merge books that Kobo Cloud sync stored twice: once under the kepub file
+/// path and once under the store UUID
+///
+/// Only a single kepub/UUID pair with identical title, author and ISBN is
+/// merged; without an ISBN the kepub must also be named after the UUID, so
+/// two different books that merely share a title and author stay apart. The entry with `date_last_read` (the latest, if both have one)
+/// supplies the metadata; highlights are the union of both, deduplicated by
+/// stable ID.
+pub fn merge_cloud_duplicates(books: Vec<Book>) -> Vec<Book> {
+    let mut kepubs: HashMap<(String, String, String), Vec<usize>> = HashMap::new();
+    let mut uuids: HashMap<(String, String, String), Vec<usize>> = HashMap::new();
+    for (i, book) in books.iter().enumerate() {
+        if is_kepub_path(&book.content_id) {
+            kepubs.entry(cloud_pair_key(book)).or_default().push(i);
+        } else if is_store_uuid(&book.content_id) {
+            uuids.entry(cloud_pair_key(book)).or_default().push(i);
+        }
+    }
+
+    let pairs: HashMap<usize, usize> = kepubs
+        .iter()
+        .filter_map(
+            |(key, kepub)| match (kepub.as_slice(), uuids.get(key)?.as_slice()) {
+                ([kepub], [uuid])
+                    if !key.2.is_empty()
+                        || volume_family(&books[*kepub].content_id)
+                            == volume_family(&books[*uuid].content_id) =>
+                {
+                    Some((*uuid, *kepub))
+                }
+                _ => None,
+            },
+        )
+        .collect();
+    if pairs.is_empty() {
+        return books;
+    }
+
+    let mut slots: Vec<Option<Book>> = books.into_iter().map(Some).collect();
+    for (&uuid, &kepub) in &pairs {
+        let (Some(uuid_book), Some(kepub_book)) = (slots[uuid].take(), slots[kepub].take()) else {
+            continue;
+        };
+        log::info!(
+            "Merging Kobo Cloud duplicate '{}' ({} + {})",
+            uuid_book.title,
+            kepub_book.content_id,
+            uuid_book.content_id
+        );
+        slots[uuid] = Some(merge_book_pair(uuid_book, kepub_book));
+    }
+
+    slots.into_iter().flatten().collect()
+}
+
+fn merge_book_pair(a: Book, b: Book) -> Book {
+    let a_is_primary = match (&a.date_last_read, &b.date_last_read) {
+        (Some(a_read), Some(b_read)) => a_read >= b_read,
+        (a_read, b_read) => a_read.is_some() || b_read.is_none(),
+    };
+    let (mut primary, other) = if a_is_primary { (a, b) } else { (b, a) };
+
+    primary.isbn = primary.isbn.or(other.isbn);
+    primary.publisher = primary.publisher.or(other.publisher);
+    primary.language = primary.language.or(other.language);
+    primary.date_last_read = primary.date_last_read.or(other.date_last_read);
+    primary.description = primary.description.or(other.description);
+    primary.file_path = primary.file_path.or(other.file_path);
+    primary.cover_path = primary.cover_path.or(other.cover_path);
+    primary.series = primary.series.or(other.series);
+    primary.series_index = primary.series_index.or(other.series_index);
+    if primary.is_orphaned && !other.is_orphaned {
+        primary.title = other.title;
+        primary.author = other.author;
+        primary.authors = other.authors;
+        primary.is_orphaned = false;
+    }
+    if primary.toc.is_empty() {
+        primary.toc = other.toc;
+    }
+
+    for highlight in other.highlights {
+        if !primary
+            .highlights
+            .iter()
+            .any(|h| h.stable_id == highlight.stable_id)
+        {
+            primary.highlights.push(highlight);
+        }
+    }
+    primary.highlights.sort_by_cached_key(|h| {
+        let created = h.created_at();
+        (created.is_none(), created, h.date_created.clone())
+    });
+    primary
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::LoanState;
     use rusqlite::Connection;
+    use std::path::PathBuf;
     use tempfile::NamedTempFile;
 
     fn create_mock_db() -> NamedTempFile {
@@ -270,6 +1271,75 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_rejects_sqlite_without_kobo_schema() {
+        let temp = NamedTempFile::new().unwrap();
+        let conn = Connection::open(temp.path()).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE Bookmark (BookmarkID TEXT, Text TEXT);
+             CREATE TABLE content (ContentID TEXT);",
+        )
+        .unwrap();
+        drop(conn);
+
+        match KoboDatabase::new(temp.path()) {
+            Err(KoboDbError::NotAKoboDatabase(reason)) => {
+                assert!(reason.contains("Bookmark.ContentID"), "{}", reason)
+            }
+            other => panic!("expected NotAKoboDatabase, got {:?}", other.err()),
+        }
+
+        let empty = NamedTempFile::new().unwrap();
+        assert!(matches!(
+            KoboDatabase::new(empty.path()),
+            Err(KoboDbError::NotAKoboDatabase(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_corrupted_database() {
+        let mock_db = create_mock_db();
+        let mut bytes = std::fs::read(mock_db.path()).unwrap();
+
+        // Garbage in place of the SQLite header
+        let original = bytes.clone();
+        bytes[..16].copy_from_slice(b"definitely-not!!");
+        std::fs::write(mock_db.path(), &bytes).unwrap();
+        assert!(matches!(
+            KoboDatabase::new(mock_db.path()),
+            Err(KoboDbError::EncryptedOrInvalidDatabase)
+        ));
+
+        // Valid header, but a table's b-tree page is overwritten
+        let mut bytes = original;
+        let page_size = u16::from_be_bytes([bytes[16], bytes[17]]) as usize;
+        bytes[page_size..page_size * 2].fill(0xFF);
+        std::fs::write(mock_db.path(), &bytes).unwrap();
+        assert!(matches!(
+            KoboDatabase::new(mock_db.path()),
+            Err(KoboDbError::CorruptDatabase(_))
+        ));
+    }
+
+    #[test]
+    fn test_random_bytes_reported_as_encrypted() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("KoboReader.sqlite");
+        // Deterministic noise standing in for an SQLCipher file
+        let bytes: Vec<u8> = (0..8192u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        std::fs::write(&path, bytes).unwrap();
+
+        let err = KoboDatabase::new(&path).err().unwrap();
+        assert!(matches!(err, KoboDbError::EncryptedOrInvalidDatabase));
+        assert!(err.to_string().contains("encrypted?"));
+
+        let mock_db = create_mock_db();
+        assert!(has_sqlite_header(mock_db.path()));
+        assert!(KoboDatabase::new(mock_db.path()).is_ok());
+    }
+
     #[test]
     fn test_extract_highlights() {
         let mock_db = create_mock_db();
@@ -284,6 +1354,122 @@ mod tests {
         assert_eq!(books[0].highlights[0].text, "Test highlight text");
     }
 
+    #[test]
+    fn test_hidden_highlights_skipped_or_excluded() {
+        let mock_db = create_mock_db();
+        let conn = Connection::open(mock_db.path()).unwrap();
+        conn.execute_batch(
+            "ALTER TABLE Bookmark ADD COLUMN Hidden BOOL DEFAULT 'false';
+             INSERT INTO Bookmark VALUES ('hl2', 'vol1!section1', 'vol1', 'Deleted quote',
+                NULL, 'OEBPS/ch01.xhtml', 0.5, '2025-01-25', NULL, 'true');
+             INSERT INTO Bookmark VALUES ('hl3', 'vol1!section1', 'vol1', 'Also deleted',
+                NULL, 'OEBPS/ch01.xhtml', 0.6, '2025-01-26', NULL, 1);
+             INSERT INTO Bookmark VALUES ('hl4', 'vol1!section1', 'vol1', 'Still here',
+                NULL, 'OEBPS/ch01.xhtml', 0.7, '2025-01-27', NULL, 'false');",
+        )
+        .unwrap();
+        let db = KoboDatabase::new(mock_db.path()).unwrap();
+
+        let visible = db.extract_books_with_highlights().unwrap();
+        let ids: Vec<&str> = visible[0]
+            .highlights
+            .iter()
+            .map(|h| h.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["hl1", "hl4"]);
+
+        let all = db.extract_books(true).unwrap();
+        let excluded: Vec<(&str, bool)> = all[0]
+            .highlights
+            .iter()
+            .map(|h| (h.id.as_str(), h.is_excluded))
+            .collect();
+        assert_eq!(
+            excluded,
+            vec![("hl1", false), ("hl2", true), ("hl3", true), ("hl4", false)]
+        );
+    }
+
+    #[test]
+    fn test_newest_bookmark_names_its_book() {
+        let mock_db = create_mock_db();
+        let db = KoboDatabase::new(mock_db.path()).unwrap();
+        db.conn
+            .execute(
+                "INSERT INTO Bookmark VALUES ('hl2', 'vol1!section1', 'vol1', 'Later',
+                    NULL, 'OEBPS/ch01.xhtml', 0.5, '2025-02-03T08:00:00', NULL)",
+                [],
+            )
+            .unwrap();
+
+        let newest = db.newest_bookmark().unwrap().unwrap();
+        assert_eq!(newest.title, "Test Book");
+        assert_eq!(newest.highlights.len(), 1);
+        assert_eq!(newest.highlights[0].id, "hl2");
+        assert_eq!(newest.highlights[0].date_created, "2025-02-03T08:00:00");
+
+        db.conn.execute("DELETE FROM Bookmark", []).unwrap();
+        assert_eq!(db.newest_bookmark().unwrap(), None);
+    }
+
+    #[test]
+    fn test_schema_without_hidden_column_is_all_visible() {
+        let mock_db = create_mock_db();
+        let db = KoboDatabase::new(mock_db.path()).unwrap();
+
+        let books = db.extract_books(true).unwrap();
+
+        assert_eq!(books[0].highlights.len(), 1);
+        assert!(!books[0].highlights[0].is_excluded);
+    }
+
+    #[test]
+    fn test_page_estimated_from_page_count() {
+        let mock_db = create_mock_db();
+        let conn = Connection::open(mock_db.path()).unwrap();
+        conn.execute_batch(
+            "ALTER TABLE Content ADD COLUMN ___NumPages INTEGER;
+             UPDATE Content SET ___NumPages = 320 WHERE ContentID = 'vol1';",
+        )
+        .unwrap();
+        let db = KoboDatabase::new(mock_db.path()).unwrap();
+
+        let books = db.extract_books_with_highlights().unwrap();
+        // ChapterProgress 0.25 of 320 pages
+        assert_eq!(books[0].highlights[0].page, Some(80));
+
+        // A zero page count (unrendered book) must not produce a page
+        conn.execute("UPDATE Content SET ___NumPages = 0", [])
+            .unwrap();
+        let books = db.extract_books_with_highlights().unwrap();
+        assert_eq!(books[0].highlights[0].page, None);
+    }
+
+    #[test]
+    fn test_page_absent_without_page_count_column() {
+        let mock_db = create_mock_db();
+        let db = KoboDatabase::new(mock_db.path()).unwrap();
+
+        let books = db.extract_books_with_highlights().unwrap();
+        assert_eq!(books[0].highlights[0].page, None);
+        assert_eq!(books[0].highlights[0].chapter_progress, Some(0.25));
+    }
+
+    #[test]
+    fn test_estimate_page_bounds() {
+        let book = Some(ProgressScope::Book);
+        assert_eq!(estimate_page(Some(0.0), book, Some(100)), Some(1));
+        assert_eq!(estimate_page(Some(1.0), book, Some(100)), Some(100));
+        assert_eq!(estimate_page(Some(0.5), None, Some(100)), Some(50));
+        assert_eq!(estimate_page(Some(0.5), book, Some(-1)), None);
+        assert_eq!(estimate_page(Some(1.5), book, Some(100)), None);
+        assert_eq!(estimate_page(None, book, Some(100)), None);
+        assert_eq!(
+            estimate_page(Some(0.5), Some(ProgressScope::Chapter), Some(100)),
+            None
+        );
+    }
+
     #[test]
     fn test_file_path_normalization() {
         let temp = tempfile::NamedTempFile::new().unwrap();
@@ -321,67 +1507,537 @@ mod tests {
         let db = KoboDatabase::new(temp.path()).unwrap();
         let books = db.extract_books_with_highlights().unwrap();
 
-        assert_eq!(books.len(), 1);
-        assert_eq!(books[0].file_path, Some("Books/MyBook.epub".to_string()));
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].file_path, Some("Books/MyBook.epub".to_string()));
+    }
+
+    #[test]
+    fn test_handle_null_annotation() {
+        let mock_db = create_mock_db();
+        let conn = Connection::open(mock_db.path()).unwrap();
+
+        // Insert highlight without annotation
+        conn.execute(
+            "INSERT INTO Bookmark VALUES ('hl2', 'vol1!section1', 'vol1', 
+             'Second highlight', NULL, 'OEBPS/ch01.xhtml', 0.50, 
+             '2025-01-25', 'blue')",
+            [],
+        )
+        .unwrap();
+
+        let db = KoboDatabase::new(mock_db.path()).unwrap();
+        let books = db.extract_books_with_highlights().unwrap();
+
+        let second_highlight = books[0].highlights.iter().find(|h| h.id == "hl2").unwrap();
+
+        assert!(second_highlight.annotation.is_none());
+    }
+
+    #[test]
+    fn test_empty_highlights_filtered() {
+        let mock_db = create_mock_db();
+        let conn = Connection::open(mock_db.path()).unwrap();
+
+        // Insert bookmark with empty text (should be ignored)
+        conn.execute(
+            "INSERT INTO Bookmark VALUES ('hl3', 'vol1!section1', 'vol1',
+             '', NULL, 'OEBPS/ch01.xhtml', 0.75, '2025-01-26', 'red')",
+            [],
+        )
+        .unwrap();
+
+        // Insert bookmark with NULL text (should be ignored)
+        conn.execute(
+            "INSERT INTO Bookmark VALUES ('hl4', 'vol1!section1', 'vol1',
+             NULL, NULL, 'OEBPS/ch01.xhtml', 0.80, '2025-01-27', 'green')",
+            [],
+        )
+        .unwrap();
+
+        let db = KoboDatabase::new(mock_db.path()).unwrap();
+        let books = db.extract_books_with_highlights().unwrap();
+
+        // Should only have hl1, not hl3 or hl4
+        assert_eq!(books[0].highlights.len(), 1);
+        assert_eq!(books[0].highlights[0].id, "hl1");
+    }
+
+    #[test]
+    fn test_figure_highlights_extracted_on_request() {
+        let mock_db = create_mock_db();
+        let conn = Connection::open(mock_db.path()).unwrap();
+        conn.execute_batch(
+            "ALTER TABLE Bookmark ADD COLUMN Type TEXT;
+             INSERT INTO Bookmark VALUES ('fig1', 'vol1!section1', 'vol1', NULL, NULL,
+                 'OEBPS/ch02.xhtml#point(/1/4/2:0)', 0.40, '2025-01-25', NULL, 'highlight');
+             INSERT INTO Bookmark VALUES ('fig2', 'vol1!section1', 'vol1', '  ', NULL,
+                 'OEBPS/ch03.xhtml', 0.50, '2025-01-26', NULL, 'highlight');
+             INSERT INTO Bookmark VALUES ('dog1', 'vol1!section1', 'vol1', NULL, NULL,
+                 'OEBPS/ch03.xhtml', 0.60, '2025-01-27', NULL, 'dogear');
+             INSERT INTO Bookmark VALUES ('none', 'vol1!section1', 'vol1', '', NULL,
+                 NULL, 0.70, '2025-01-28', NULL, 'highlight');",
+        )
+        .unwrap();
+
+        // Off by default: rows without text are skipped as before
+        let db = KoboDatabase::new(mock_db.path()).unwrap();
+        let books = db.extract_books_with_highlights().unwrap();
+        let ids: Vec<&str> = books[0].highlights.iter().map(|h| h.id.as_str()).collect();
+        assert_eq!(ids, vec!["hl1", "fig2"]);
+        assert!(books[0]
+            .highlights
+            .iter()
+            .all(|h| h.kind == HighlightKind::Text));
+
+        let db = KoboDatabase::new(mock_db.path())
+            .unwrap()
+            .with_figures(true);
+        let books = db.extract_books_with_highlights().unwrap();
+        let kinds: Vec<(&str, HighlightKind)> = books[0]
+            .highlights
+            .iter()
+            .map(|h| (h.id.as_str(), h.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("hl1", HighlightKind::Text),
+                ("fig1", HighlightKind::Figure),
+                ("fig2", HighlightKind::Figure),
+            ]
+        );
+        let figure = &books[0].highlights[1];
+        assert_eq!(figure.text, "");
+        assert_eq!(figure.figure_path, None);
+        assert_eq!(
+            figure.container_path.as_deref(),
+            Some("OEBPS/ch02.xhtml#point(/1/4/2:0)")
+        );
+        // Same chapter and no text, yet each figure keeps its own id
+        assert_ne!(
+            books[0].highlights[1].stable_id,
+            books[0].highlights[2].stable_id
+        );
+    }
+
+    /// Helper to create a mock DB with ContentType 9 (pages) and 899 (TOC entries)
+    fn create_mock_db_with_toc() -> NamedTempFile {
+        let temp = NamedTempFile::new().unwrap();
+        let conn = Connection::open(temp.path()).unwrap();
+
+        conn.execute(
+            "CREATE TABLE Bookmark (
+                BookmarkID TEXT PRIMARY KEY,
+                ContentID TEXT,
+                VolumeID TEXT,
+                Text TEXT,
+                Annotation TEXT,
+                StartContainerPath TEXT,
+                ChapterProgress REAL,
+                DateCreated TEXT,
+                Color TEXT
+            )",
+            [],
+        )
+        .unwrap();
+
+        conn.execute(
+            "CREATE TABLE Content (
+                ContentID TEXT,
+                BookTitle TEXT,
+                Title TEXT,
+                Attribution TEXT,
+                ISBN TEXT,
+                Publisher TEXT,
+                Language TEXT,
+                DateLastRead TEXT,
+                ContentType INTEGER
+            )",
+            [],
+        )
+        .unwrap();
+
+        // Book-level entry (ContentType 6)
+        conn.execute(
+            "INSERT INTO Content VALUES ('file:///mnt/onboard/book.epub', 'My Book', 'My Book',
+             'Author Name', '978-0000000000', 'Publisher', 'en', '2025-01-24', 6)",
+            [],
+        )
+        .unwrap();
+
+        // Chapter page entry (ContentType 9) — Title is a filename
+        conn.execute(
+            "INSERT INTO Content VALUES ('file:///mnt/onboard/book.epub!xhtml/chapter3.xhtml',
+             'My Book', 'xhtml/chapter3.xhtml', 'Author Name', NULL, NULL, NULL, NULL, 9)",
+            [],
+        )
+        .unwrap();
+
+        // TOC entry (ContentType 899) — Title is the real chapter title
+        conn.execute(
+            "INSERT INTO Content VALUES ('file:///mnt/onboard/book.epub!xhtml/chapter3.xhtml-1',
+             'My Book', 'Chapter 3: Connect Your Notes', NULL, NULL, NULL, NULL, NULL, 899)",
+            [],
+        )
+        .unwrap();
+
+        // Highlight pointing to the chapter page
+        conn.execute(
+            "INSERT INTO Bookmark VALUES ('hl-toc', 'file:///mnt/onboard/book.epub!xhtml/chapter3.xhtml',
+             'file:///mnt/onboard/book.epub', 'A highlight text', NULL, NULL, 0.30,
+             '2025-01-24', NULL)",
+            [],
+        )
+        .unwrap();
+
+        temp
+    }
+
+    #[test]
+    fn test_chapter_title_from_toc() {
+        // TOC entry (ContentType 899) should be preferred over page title (ContentType 9)
+        let mock_db = create_mock_db_with_toc();
+        let db = KoboDatabase::new(mock_db.path()).unwrap();
+
+        let books = db.extract_books_with_highlights().unwrap();
+
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].highlights.len(), 1);
+        assert_eq!(
+            books[0].highlights[0].chapter_title,
+            Some("Chapter 3: Connect Your Notes".to_string())
+        );
+    }
+
+    /// Kobo database of `books` books with `pages` pages each, every page
+    /// with a TOC entry (but the last, whose filename title is dropped) and
+    /// `highlights` highlights
+    fn create_large_db(path: &Path, books: usize, pages: usize, highlights: usize) {
+        let mut conn = Connection::open(path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE Bookmark (
+                BookmarkID TEXT PRIMARY KEY, ContentID TEXT, VolumeID TEXT, Text TEXT,
+                Annotation TEXT, StartContainerPath TEXT, ChapterProgress REAL,
+                DateCreated TEXT, Color TEXT
+            );
+            CREATE TABLE content (
+                ContentID TEXT, BookTitle TEXT, Title TEXT, Attribution TEXT, ISBN TEXT,
+                Publisher TEXT, Language TEXT, DateLastRead TEXT, ContentType INTEGER
+            );",
+        )
+        .unwrap();
+        let tx = conn.transaction().unwrap();
+        {
+            let mut content = tx
+                .prepare("INSERT INTO content VALUES (?1, ?2, ?3, ?4, NULL, NULL, 'en', NULL, ?5)")
+                .unwrap();
+            let mut bookmark = tx
+                .prepare("INSERT INTO Bookmark VALUES (?1, ?2, ?3, ?4, NULL, NULL, ?5, ?6, NULL)")
+                .unwrap();
+            for b in 0..books {
+                let volume = format!("file:///mnt/onboard/book_{:04}.epub", b);
+                let title = format!("Book {:04}", b);
+                content
+                    .execute(rusqlite::params![volume, title, title, "Author", 6])
+                    .unwrap();
+                for p in 0..pages {
+                    let page = format!("{}!OEBPS/ch{:03}.xhtml", volume, p);
+                    let page_title = format!("OEBPS/ch{:03}.xhtml", p);
+                    content
+                        .execute(rusqlite::params![page, title, page_title, "Author", 9])
+                        .unwrap();
+                    if p + 1 < pages {
+                        let chapter = format!("Chapter {}", p + 1);
+                        content
+                            .execute(rusqlite::params![
+                                format!("{}-1", page),
+                                title,
+                                chapter,
+                                Option::<String>::None,
+                                899
+                            ])
+                            .unwrap();
+                    }
+                    for h in 0..highlights {
+                        bookmark
+                            .execute(rusqlite::params![
+                                format!("bm-{}-{}-{}", b, p, h),
+                                page,
+                                volume,
+                                format!("Highlight {} of page {} in book {}", h, p, b),
+                                (h as f64 + 0.5) / highlights as f64,
+                                format!("2025-01-{:02}T10:{:02}:00", p % 28 + 1, h % 60)
+                            ])
+                            .unwrap();
+                    }
+                }
+            }
+        }
+        tx.commit().unwrap();
+    }
+
+    #[test]
+    fn test_chapter_titles_match_previous_query() {
+        let temp = NamedTempFile::new().unwrap();
+        create_large_db(temp.path(), 12, 6, 3);
+
+        // The per-bookmark LIKE join import used before the TOC pre-pass
+        let conn = Connection::open(temp.path()).unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT b.BookmarkID, COALESCE(
+                    c_toc.Title,
+                    CASE WHEN c_chapter.Title IS NOT NULL
+                              AND c_chapter.Title NOT LIKE '%.xhtml%'
+                              AND c_chapter.Title NOT LIKE '%.html%'
+                              AND c_chapter.Title NOT LIKE '%.htm%'
+                              AND c_chapter.Title NOT LIKE '%/%'
+                         THEN c_chapter.Title
+                         ELSE NULL
+                    END
+                 ) as ChapterTitle
+                 FROM Bookmark b
+                 LEFT JOIN content c_book ON b.VolumeID = c_book.ContentID
+                 LEFT JOIN content c_chapter ON b.ContentID = c_chapter.ContentID
+                 LEFT JOIN content c_toc ON c_toc.ContentType = 899
+                    AND c_toc.ContentID LIKE b.ContentID || '%'
+                 WHERE b.Text IS NOT NULL AND b.Text != ''",
+            )
+            .unwrap();
+        let mut expected: Vec<(String, Option<String>)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        expected.sort();
+
+        let db = KoboDatabase::new(temp.path()).unwrap();
+        let books = db.extract_books_with_highlights().unwrap();
+        let mut actual: Vec<(String, Option<String>)> = books
+            .iter()
+            .flat_map(|b| &b.highlights)
+            .map(|h| (h.id.clone(), h.chapter_title.clone()))
+            .collect();
+        actual.sort();
+
+        assert_eq!(books.len(), 12);
+        assert_eq!(actual.len(), 12 * 6 * 3);
+        assert_eq!(actual, expected);
+        assert_eq!(books[0].toc.len(), 5);
+        assert_eq!(books[0].toc[4].title, "Chapter 5");
+    }
+
+    #[test]
+    fn test_large_database_imports_every_highlight() {
+        let temp = NamedTempFile::new().unwrap();
+        // 50,000 highlights over 500 books, 20,500 content rows
+        create_large_db(temp.path(), 500, 20, 5);
+
+        let db = KoboDatabase::new(temp.path()).unwrap();
+        let books = db.extract_books_with_highlights().unwrap();
+
+        assert_eq!(books.len(), 500);
+        assert_eq!(
+            books.iter().map(|b| b.highlights.len()).sum::<usize>(),
+            50_000
+        );
+        assert_eq!(
+            books[0].highlights[0].chapter_title.as_deref(),
+            Some("Chapter 1")
+        );
+    }
+
+    #[test]
+    fn test_extract_two_level_toc() {
+        let temp = NamedTempFile::new().unwrap();
+        let conn = Connection::open(temp.path()).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE Bookmark (
+                BookmarkID TEXT PRIMARY KEY,
+                ContentID TEXT,
+                VolumeID TEXT,
+                Text TEXT,
+                Annotation TEXT,
+                StartContainerPath TEXT,
+                ChapterProgress REAL,
+                DateCreated TEXT,
+                Color TEXT
+            );
+            CREATE TABLE content (
+                ContentID TEXT,
+                BookID TEXT,
+                BookTitle TEXT,
+                Title TEXT,
+                Attribution TEXT,
+                ISBN TEXT,
+                Publisher TEXT,
+                Language TEXT,
+                DateLastRead TEXT,
+                ContentType INTEGER,
+                VolumeIndex INTEGER,
+                Depth INTEGER
+            );
+            INSERT INTO content (ContentID, Title, Attribution, ContentType)
+                VALUES ('file:///mnt/onboard/a_b.epub', 'Two Levels', 'Author', 6),
+                       ('file:///mnt/onboard/plain.epub', 'No Toc', 'Author', 6);
+            -- Inserted out of reading order; VolumeIndex decides
+            INSERT INTO content (ContentID, BookID, Title, ContentType, VolumeIndex, Depth)
+                VALUES ('file:///mnt/onboard/a_b.epub!ch2.xhtml-1', 'file:///mnt/onboard/a_b.epub',
+                        'Part II', 899, 3, 1),
+                       ('file:///mnt/onboard/a_b.epub!ch1.xhtml-1', 'file:///mnt/onboard/a_b.epub',
+                        'Part I', 899, 0, 1),
+                       ('file:///mnt/onboard/a_b.epub!ch1.xhtml-2', 'file:///mnt/onboard/a_b.epub',
+                        'Chapter 1', 899, 1, 2),
+                       ('file:///mnt/onboard/a_b.epub!ch1.xhtml-3', 'file:///mnt/onboard/a_b.epub',
+                        'Chapter 2', 899, 2, 2),
+                       ('file:///mnt/onboard/a_b.epub!ch3.xhtml-1', 'file:///mnt/onboard/a_b.epub',
+                        '  ', 899, 4, 1),
+                       ('file:///mnt/onboard/aXb.epub!ch1.xhtml-1', 'file:///mnt/onboard/aXb.epub',
+                        'Other Book', 899, 0, 1);
+            INSERT INTO Bookmark (BookmarkID, ContentID, VolumeID, Text, DateCreated)
+                VALUES ('hl1', 'file:///mnt/onboard/a_b.epub!ch1.xhtml', 'file:///mnt/onboard/a_b.epub',
+                        'Text', '2025-01-24'),
+                       ('hl2', 'file:///mnt/onboard/plain.epub!ch1.xhtml',
+                        'file:///mnt/onboard/plain.epub', 'Text', '2025-01-24');",
+        )
+        .unwrap();
+
+        let db = KoboDatabase::new(temp.path()).unwrap();
+        let books = db.extract_books_with_highlights().unwrap();
+
+        let toc: Vec<(&str, u32, u32)> = books[1]
+            .toc
+            .iter()
+            .map(|e| (e.title.as_str(), e.depth, e.order))
+            .collect();
+        assert_eq!(books[1].title, "Two Levels");
+        assert_eq!(
+            toc,
+            vec![
+                ("Part I", 1, 0),
+                ("Chapter 1", 2, 1),
+                ("Chapter 2", 2, 2),
+                ("Part II", 1, 3)
+            ]
+        );
+        assert_eq!(books[0].title, "No Toc");
+        assert!(books[0].toc.is_empty());
     }
 
     #[test]
-    fn test_handle_null_annotation() {
-        let mock_db = create_mock_db();
-        let conn = Connection::open(mock_db.path()).unwrap();
-
-        // Insert highlight without annotation
-        conn.execute(
-            "INSERT INTO Bookmark VALUES ('hl2', 'vol1!section1', 'vol1', 
-             'Second highlight', NULL, 'OEBPS/ch01.xhtml', 0.50, 
-             '2025-01-25', 'blue')",
-            [],
+    fn test_unreadable_toc_imports_without_chapters() {
+        let temp = NamedTempFile::new().unwrap();
+        let conn = Connection::open(temp.path()).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE Bookmark (
+                BookmarkID TEXT PRIMARY KEY,
+                ContentID TEXT,
+                VolumeID TEXT,
+                Text TEXT,
+                Annotation TEXT,
+                StartContainerPath TEXT,
+                ChapterProgress REAL,
+                DateCreated TEXT,
+                Color TEXT
+            );
+            CREATE TABLE content (
+                ContentID TEXT,
+                BookTitle TEXT,
+                Title TEXT,
+                Attribution TEXT,
+                ISBN TEXT,
+                Publisher TEXT,
+                Language TEXT,
+                DateLastRead TEXT,
+                ContentType INTEGER
+            );
+            INSERT INTO content (ContentID, Title, Attribution, ContentType)
+                VALUES ('file:///mnt/onboard/a.epub', 'Book', 'Author', 6),
+                       -- A title that isn't text can't be read as one
+                       ('file:///mnt/onboard/a.epub!ch1.xhtml-1', X'FF00', NULL, 899);
+            INSERT INTO Bookmark (BookmarkID, ContentID, VolumeID, Text, DateCreated)
+                VALUES ('hl1', 'file:///mnt/onboard/a.epub!ch1.xhtml', 'file:///mnt/onboard/a.epub',
+                        'Text', '2025-01-24');",
         )
         .unwrap();
 
-        let db = KoboDatabase::new(mock_db.path()).unwrap();
+        let db = KoboDatabase::new(temp.path()).unwrap();
+        assert!(db.toc_index().is_err());
         let books = db.extract_books_with_highlights().unwrap();
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].highlights.len(), 1);
+        assert!(books[0].toc.is_empty());
+    }
 
-        let second_highlight = books[0].highlights.iter().find(|h| h.id == "hl2").unwrap();
+    #[test]
+    fn test_toc_without_depth_columns_is_flat() {
+        let mock_db = create_mock_db_with_toc();
+        let db = KoboDatabase::new(mock_db.path()).unwrap();
 
-        assert!(second_highlight.annotation.is_none());
+        let toc = db.extract_toc("file:///mnt/onboard/book.epub").unwrap();
+        assert_eq!(toc.len(), 1);
+        assert_eq!(toc[0].title, "Chapter 3: Connect Your Notes");
+        assert_eq!(toc[0].depth, 1);
     }
 
     #[test]
-    fn test_empty_highlights_filtered() {
+    fn test_word_list_attached_by_volume() {
         let mock_db = create_mock_db();
         let conn = Connection::open(mock_db.path()).unwrap();
-
-        // Insert bookmark with empty text (should be ignored)
-        conn.execute(
-            "INSERT INTO Bookmark VALUES ('hl3', 'vol1!section1', 'vol1',
-             '', NULL, 'OEBPS/ch01.xhtml', 0.75, '2025-01-26', 'red')",
-            [],
+        conn.execute_batch(
+            "INSERT INTO Content VALUES ('vol2', 'Second Book', 'Second Book', 'Other Author',
+             NULL, NULL, 'pt', '2025-02-01', 6);
+             INSERT INTO Bookmark VALUES ('hl2', 'vol2!section1', 'vol2', 'Outro destaque',
+             NULL, 'OEBPS/ch01.xhtml', 0.5, '2025-02-01', NULL);
+             CREATE TABLE WordList (Text TEXT, VolumeId TEXT, DictSuffix TEXT, DateCreated TEXT);
+             INSERT INTO WordList VALUES ('saudade', 'vol2', '-pt', '2025-02-01T10:00:00.000');
+             INSERT INTO WordList VALUES ('serendipity', 'vol1', '-en', '2025-01-24T09:00:00.000');
+             INSERT INTO WordList VALUES ('ephemeral', 'vol1', '-en', NULL);
+             INSERT INTO WordList VALUES ('hygge', 'vol-gone', '-en', '2025-03-01T08:00:00.000');
+             INSERT INTO WordList VALUES ('  ', 'vol1', '-en', NULL);",
         )
         .unwrap();
+        drop(conn);
 
-        // Insert bookmark with NULL text (should be ignored)
-        conn.execute(
-            "INSERT INTO Bookmark VALUES ('hl4', 'vol1!section1', 'vol1',
-             NULL, NULL, 'OEBPS/ch01.xhtml', 0.80, '2025-01-27', 'green')",
-            [],
-        )
-        .unwrap();
+        let db = KoboDatabase::new(mock_db.path()).unwrap();
+        let entries = db.extract_word_list().unwrap();
+        assert_eq!(entries.len(), 4);
+
+        let mut books = db.extract_books_with_highlights().unwrap();
+        assert_eq!(books.len(), 2);
+        attach_vocabulary(&mut books, entries);
+        assert_eq!(books.len(), 3);
+
+        let words = |id: &str| -> Vec<String> {
+            let book = books.iter().find(|b| b.content_id == id).unwrap();
+            book.vocabulary.iter().map(|v| v.word.clone()).collect()
+        };
+        assert_eq!(words("vol1"), vec!["serendipity", "ephemeral"]);
+        assert_eq!(words("vol2"), vec!["saudade"]);
+        assert_eq!(words(UNKNOWN_VOCABULARY_BOOK_ID), vec!["hygge"]);
+
+        let unknown = books.last().unwrap();
+        assert!(unknown.highlights.is_empty());
+        assert_eq!(unknown.vocabulary[0].volume_id, "vol-gone");
+    }
 
+    #[test]
+    fn test_word_list_absent() {
+        let mock_db = create_mock_db();
         let db = KoboDatabase::new(mock_db.path()).unwrap();
-        let books = db.extract_books_with_highlights().unwrap();
+        assert!(db.extract_word_list().unwrap().is_empty());
 
-        // Should only have hl1, not hl3 or hl4
-        assert_eq!(books[0].highlights.len(), 1);
-        assert_eq!(books[0].highlights[0].id, "hl1");
+        let mut books = db.extract_books_with_highlights().unwrap();
+        attach_vocabulary(&mut books, Vec::new());
+        assert_eq!(books.len(), 1);
+        assert!(books[0].vocabulary.is_empty());
     }
 
-    /// Helper to create a mock DB with ContentType 9 (pages) and 899 (TOC entries)
-    fn create_mock_db_with_toc() -> NamedTempFile {
+    #[test]
+    fn test_kobo_cloud_duplicates_are_merged() {
         let temp = NamedTempFile::new().unwrap();
         let conn = Connection::open(temp.path()).unwrap();
-
-        conn.execute(
+        conn.execute_batch(
             "CREATE TABLE Bookmark (
                 BookmarkID TEXT PRIMARY KEY,
                 ContentID TEXT,
@@ -392,13 +2048,8 @@ mod tests {
                 ChapterProgress REAL,
                 DateCreated TEXT,
                 Color TEXT
-            )",
-            [],
-        )
-        .unwrap();
-
-        conn.execute(
-            "CREATE TABLE Content (
+            );
+            CREATE TABLE content (
                 ContentID TEXT,
                 BookTitle TEXT,
                 Title TEXT,
@@ -408,60 +2059,384 @@ mod tests {
                 Language TEXT,
                 DateLastRead TEXT,
                 ContentType INTEGER
-            )",
-            [],
+            );
+            INSERT INTO content VALUES
+                ('file:///mnt/onboard/kepub/Dune.kepub.epub', NULL, 'Dune', 'Frank Herbert',
+                 '9780441013593', NULL, 'en', NULL, 6),
+                ('6f1c2a9e-0b7d-4c55-9a1e-3d2f8b6c4e10', NULL, 'Dune', 'Frank Herbert',
+                 '9780441013593', 'Ace', NULL, '2025-02-01T20:00:00', 6),
+                ('file:///mnt/onboard/Dune.epub', NULL, 'Dune', 'Someone Else',
+                 '9780441013593', NULL, NULL, NULL, 6);
+            INSERT INTO Bookmark (BookmarkID, ContentID, VolumeID, Text, DateCreated) VALUES
+                ('k1', 'file:///mnt/onboard/kepub/Dune.kepub.epub!ch1', 'file:///mnt/onboard/kepub/Dune.kepub.epub',
+                 'Fear is the mind-killer.', '2025-01-10T10:00:00'),
+                ('k2', 'file:///mnt/onboard/kepub/Dune.kepub.epub!ch1', 'file:///mnt/onboard/kepub/Dune.kepub.epub',
+                 'The spice must flow.', '2025-01-11T10:00:00'),
+                ('u1', '6f1c2a9e-0b7d-4c55-9a1e-3d2f8b6c4e10!ch1', '6f1c2a9e-0b7d-4c55-9a1e-3d2f8b6c4e10',
+                 'Fear is the mind-killer.', '2025-01-10T10:00:05'),
+                ('u2', '6f1c2a9e-0b7d-4c55-9a1e-3d2f8b6c4e10!ch1', '6f1c2a9e-0b7d-4c55-9a1e-3d2f8b6c4e10',
+                 'I must not fear.', '2025-01-09T10:00:00'),
+                ('e1', 'file:///mnt/onboard/Dune.epub!ch1', 'file:///mnt/onboard/Dune.epub',
+                 'Not a cloud copy.', '2025-01-09T10:00:00');",
         )
         .unwrap();
 
-        // Book-level entry (ContentType 6)
-        conn.execute(
-            "INSERT INTO Content VALUES ('file:///mnt/onboard/book.epub', 'My Book', 'My Book',
-             'Author Name', '978-0000000000', 'Publisher', 'en', '2025-01-24', 6)",
-            [],
+        let db = KoboDatabase::new(temp.path()).unwrap();
+        let books = db.extract_books_with_highlights().unwrap();
+
+        // The epub by a different author is not part of the pair
+        assert_eq!(books.len(), 2);
+        let dune = books.iter().find(|b| b.author == "Frank Herbert").unwrap();
+        assert_eq!(dune.content_id, "6f1c2a9e-0b7d-4c55-9a1e-3d2f8b6c4e10");
+        assert_eq!(dune.publisher.as_deref(), Some("Ace"));
+        assert_eq!(dune.language.as_deref(), Some("en"));
+        assert_eq!(dune.file_path.as_deref(), Some("kepub/Dune.kepub.epub"));
+        let texts: Vec<&str> = dune.highlights.iter().map(|h| h.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "I must not fear.",
+                "Fear is the mind-killer.",
+                "The spice must flow."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_same_title_author_books_without_isbn_are_not_merged() {
+        let temp = NamedTempFile::new().unwrap();
+        let conn = Connection::open(temp.path()).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE Bookmark (
+                BookmarkID TEXT PRIMARY KEY, ContentID TEXT, VolumeID TEXT, Text TEXT,
+                Annotation TEXT, StartContainerPath TEXT, ChapterProgress REAL,
+                DateCreated TEXT, Color TEXT
+            );
+            CREATE TABLE content (
+                ContentID TEXT, BookTitle TEXT, Title TEXT, Attribution TEXT, ISBN TEXT,
+                Publisher TEXT, Language TEXT, DateLastRead TEXT, ContentType INTEGER
+            );
+            INSERT INTO content VALUES
+                ('file:///mnt/onboard/kepub/Essays.kepub.epub', NULL, 'Essays', 'Montaigne',
+                 NULL, NULL, 'en', '2025-01-01T10:00:00', 6),
+                ('6f1c2a9e-0b7d-4c55-9a1e-3d2f8b6c4e10', NULL, 'Essays', 'Montaigne',
+                 NULL, NULL, 'en', '2025-01-01T10:00:00', 6),
+                ('file:///mnt/onboard/.kobo/kepub/0a5d7c1e-2b3f-4e6a-8c9d-1e2f3a4b5c6d.kepub.epub',
+                 NULL, 'Essays', 'Emerson', NULL, NULL, 'en', NULL, 6),
+                ('0A5D7C1E-2B3F-4E6A-8C9D-1E2F3A4B5C6D', NULL, 'Essays', 'Emerson',
+                 NULL, NULL, 'en', NULL, 6);
+            INSERT INTO Bookmark (BookmarkID, ContentID, VolumeID, Text, DateCreated) VALUES
+                ('k1', 'file:///mnt/onboard/kepub/Essays.kepub.epub!ch1',
+                 'file:///mnt/onboard/kepub/Essays.kepub.epub', 'Of idleness.', '2025-01-01T10:00:00'),
+                ('u1', '6f1c2a9e-0b7d-4c55-9a1e-3d2f8b6c4e10!ch1',
+                 '6f1c2a9e-0b7d-4c55-9a1e-3d2f8b6c4e10', 'Of cannibals.', '2025-01-02T10:00:00'),
+                ('e1', 'file:///mnt/onboard/.kobo/kepub/0a5d7c1e-2b3f-4e6a-8c9d-1e2f3a4b5c6d.kepub.epub!ch1',
+                 'file:///mnt/onboard/.kobo/kepub/0a5d7c1e-2b3f-4e6a-8c9d-1e2f3a4b5c6d.kepub.epub',
+                 'Trust thyself.', '2025-01-03T10:00:00'),
+                ('e2', '0A5D7C1E-2B3F-4E6A-8C9D-1E2F3A4B5C6D!ch1',
+                 '0A5D7C1E-2B3F-4E6A-8C9D-1E2F3A4B5C6D', 'Trust thyself.', '2025-01-03T10:00:00');",
         )
         .unwrap();
 
-        // Chapter page entry (ContentType 9) — Title is a filename
-        conn.execute(
-            "INSERT INTO Content VALUES ('file:///mnt/onboard/book.epub!xhtml/chapter3.xhtml',
-             'My Book', 'xhtml/chapter3.xhtml', 'Author Name', NULL, NULL, NULL, NULL, 9)",
-            [],
+        let db = KoboDatabase::new(temp.path()).unwrap();
+        let books = db.extract_books_with_highlights().unwrap();
+
+        // Emerson's kepub is named after its UUID: the same book. Montaigne's
+        // two books only share a title and author, and keep a stable order.
+        let ids: Vec<&str> = books.iter().map(|b| b.content_id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "0A5D7C1E-2B3F-4E6A-8C9D-1E2F3A4B5C6D",
+                "6f1c2a9e-0b7d-4c55-9a1e-3d2f8b6c4e10",
+                "file:///mnt/onboard/kepub/Essays.kepub.epub",
+            ]
+        );
+        assert_ne!(books[1].slug, books[2].slug);
+    }
+
+    #[test]
+    fn test_pocket_articles_keep_title_and_url() {
+        let temp = NamedTempFile::new().unwrap();
+        let conn = Connection::open(temp.path()).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE Bookmark (
+                BookmarkID TEXT PRIMARY KEY, ContentID TEXT, VolumeID TEXT, Text TEXT,
+                Annotation TEXT, StartContainerPath TEXT, ChapterProgress REAL,
+                DateCreated TEXT, Color TEXT
+            );
+            CREATE TABLE content (
+                ContentID TEXT, BookTitle TEXT, Title TEXT, Attribution TEXT, ISBN TEXT,
+                Publisher TEXT, Language TEXT, DateLastRead TEXT, ContentType INTEGER,
+                MimeType TEXT, ContentURL TEXT
+            );
+            INSERT INTO content VALUES
+                ('3456789012', NULL, 'The Slow Web', NULL, NULL, NULL, 'en', NULL, 6,
+                 'application/x-kobo-html+pocket', 'https://www.example.com/slow-web?utm=kobo'),
+                ('4567890123', NULL, NULL, NULL, NULL, NULL, NULL, NULL, 6, NULL,
+                 'https://blog.example.org/untitled'),
+                ('file:///mnt/onboard/Walden.epub', NULL, 'Walden', 'Henry David Thoreau',
+                 NULL, NULL, 'en', NULL, 6, 'application/epub+zip', NULL);
+            INSERT INTO Bookmark (BookmarkID, ContentID, VolumeID, Text, DateCreated) VALUES
+                ('p1', '3456789012', '3456789012', 'Slow down.', '2025-03-01T10:00:00'),
+                ('p2', '4567890123', '4567890123', 'No title here.', '2025-03-02T10:00:00'),
+                ('e1', 'file:///mnt/onboard/Walden.epub!ch.xhtml', 'file:///mnt/onboard/Walden.epub',
+                 'Simplify, simplify.', '2025-01-01T10:00:00');",
         )
         .unwrap();
 
-        // TOC entry (ContentType 899) — Title is the real chapter title
-        conn.execute(
-            "INSERT INTO Content VALUES ('file:///mnt/onboard/book.epub!xhtml/chapter3.xhtml-1',
-             'My Book', 'Chapter 3: Connect Your Notes', NULL, NULL, NULL, NULL, NULL, 899)",
-            [],
-        )
+        let db = KoboDatabase::new(temp.path()).unwrap();
+        let books = db.extract_books_with_highlights().unwrap();
+        let find = |id: &str| books.iter().find(|b| b.content_id == id).unwrap();
+
+        let article = find("3456789012");
+        assert_eq!(article.title, "The Slow Web");
+        assert_eq!(article.kind, BookKind::PocketArticle);
+        assert_eq!(
+            article.source_url.as_deref(),
+            Some("https://www.example.com/slow-web?utm=kobo")
+        );
+        assert_eq!(article.author, "example.com");
+
+        // Without a title, the article goes by its URL
+        let untitled = find("4567890123");
+        assert_eq!(untitled.kind, BookKind::PocketArticle);
+        assert_eq!(untitled.title, "https://blog.example.org/untitled");
+
+        let walden = find("file:///mnt/onboard/Walden.epub");
+        assert_eq!(walden.kind, BookKind::Book);
+        assert_eq!(walden.source_url, None);
+    }
+
+    #[test]
+    fn test_orphaned_books_get_titles_from_file_names() {
+        let temp = NamedTempFile::new().unwrap();
+        let conn = Connection::open(temp.path()).unwrap();
+        conn.execute_batch(&format!(
+            "{}
+            INSERT INTO content VALUES ('file:///mnt/onboard/Walden.epub', NULL, 'Walden',
+                'Henry David Thoreau', NULL, NULL, 'en', NULL, 6);
+            INSERT INTO Bookmark (BookmarkID, ContentID, VolumeID, Text, DateCreated) VALUES
+                ('w1', 'file:///mnt/onboard/Walden.epub', 'file:///mnt/onboard/Walden.epub',
+                 'Simplify, simplify.', '2025-01-01T10:00:00'),
+                ('a1', 'file:///mnt/onboard/Books/O%20Alquimista.kepub.epub!!ch1.xhtml',
+                 'file:///mnt/onboard/Books/O%20Alquimista.kepub.epub',
+                 'Quando queres alguma coisa', '2025-01-02T10:00:00'),
+                ('a2', 'file:///mnt/onboard/Books/O%20Alquimista.kepub.epub!!ch2.xhtml',
+                 'file:///mnt/onboard/Books/O%20Alquimista.kepub.epub',
+                 'todo o Universo conspira', '2025-01-03T10:00:00'),
+                ('s1', 'file:///mnt/onboard/the_old_man_and_the_sea.pdf',
+                 'file:///mnt/onboard/the_old_man_and_the_sea.pdf',
+                 'A man can be destroyed', '2025-01-04T10:00:00'),
+                ('u1', '0b1f6c2e-5a4d-4e8b-9a61-3f0f1f2c9d7e',
+                 '0b1f6c2e-5a4d-4e8b-9a61-3f0f1f2c9d7e', 'Store book', '2025-01-05T10:00:00');",
+            crate::fixtures::KOBO_SCHEMA
+        ))
         .unwrap();
 
-        // Highlight pointing to the chapter page
-        conn.execute(
-            "INSERT INTO Bookmark VALUES ('hl-toc', 'file:///mnt/onboard/book.epub!xhtml/chapter3.xhtml',
-             'file:///mnt/onboard/book.epub', 'A highlight text', NULL, NULL, 0.30,
-             '2025-01-24', NULL)",
-            [],
+        let db = KoboDatabase::new(temp.path()).unwrap();
+        let books = db.extract_books_with_highlights().unwrap();
+        let find = |id: &str| books.iter().find(|b| b.content_id == id).unwrap();
+
+        let walden = find("file:///mnt/onboard/Walden.epub");
+        assert!(!walden.is_orphaned);
+        assert_eq!(walden.title, "Walden");
+
+        let alchemist = find("file:///mnt/onboard/Books/O%20Alquimista.kepub.epub");
+        assert!(alchemist.is_orphaned);
+        assert_eq!(alchemist.title, "O Alquimista");
+        assert_eq!(alchemist.author, UNKNOWN_AUTHOR);
+        assert_eq!(alchemist.highlights.len(), 2);
+
+        let sea = find("file:///mnt/onboard/the_old_man_and_the_sea.pdf");
+        assert!(sea.is_orphaned);
+        assert_eq!(sea.title, "the old man and the sea");
+
+        let store = find("0b1f6c2e-5a4d-4e8b-9a61-3f0f1f2c9d7e");
+        assert!(store.is_orphaned);
+        assert_eq!(store.title, "Unknown Title (0b1f6c2e)");
+
+        assert_eq!(
+            title_from_volume_id("file:///mnt/onboard/%E2%82%AC%zz.epub").as_deref(),
+            Some("€%zz")
+        );
+        assert_eq!(
+            title_from_volume_id(r"C:\Books\Dune.epub").as_deref(),
+            Some("Dune")
+        );
+        assert_eq!(title_from_volume_id("file:///mnt/onboard/.epub"), None);
+
+        let exporter = crate::export::MarkdownExporter::new(PathBuf::from("/tmp"));
+        let config = crate::settings::AppSettings::default().export_config;
+        let markdown = exporter.generate_markdown(alchemist, &config);
+        assert!(markdown.starts_with(
+            "# O Alquimista\n\n**Note**: This book is no longer on the device; its title \
+             comes from the file name and the rest of its metadata is unavailable\n"
+        ));
+        assert!(!exporter
+            .generate_markdown(walden, &config)
+            .contains("no longer on the device"));
+    }
+
+    #[test]
+    fn test_library_loans_are_classified_and_noted() {
+        let temp = NamedTempFile::new().unwrap();
+        let conn = Connection::open(temp.path()).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE Bookmark (
+                BookmarkID TEXT PRIMARY KEY, ContentID TEXT, VolumeID TEXT, Text TEXT,
+                Annotation TEXT, StartContainerPath TEXT, ChapterProgress REAL,
+                DateCreated TEXT, Color TEXT
+            );
+            CREATE TABLE content (
+                ContentID TEXT, BookTitle TEXT, Title TEXT, Attribution TEXT, ISBN TEXT,
+                Publisher TEXT, Language TEXT, DateLastRead TEXT, ContentType INTEGER,
+                ___ExpirationStatus INTEGER, ExpirationDate TEXT
+            );
+            INSERT INTO content VALUES
+                ('loan-expired', NULL, 'Middlemarch', 'George Eliot', NULL, NULL, 'en',
+                 NULL, 6, 3, '2024-03-01T00:00:00.000'),
+                ('loan-open', NULL, 'Persuasion', 'Jane Austen', NULL, NULL, 'en',
+                 NULL, 6, 1, NULL),
+                ('purchase', NULL, 'Walden', 'Henry David Thoreau', NULL, NULL, 'en',
+                 NULL, 6, 0, NULL);
+            INSERT INTO Bookmark (BookmarkID, ContentID, VolumeID, Text, DateCreated) VALUES
+                ('m1', 'loan-expired', 'loan-expired', 'It is a narrow mind.', '2024-02-01T10:00:00'),
+                ('p1', 'loan-open', 'loan-open', 'Time will explain.', '2025-01-01T10:00:00'),
+                ('w1', 'purchase', 'purchase', 'Simplify, simplify.', '2025-01-01T10:00:00');",
         )
         .unwrap();
 
-        temp
+        let db = KoboDatabase::new(temp.path()).unwrap();
+        let books = db.extract_books_with_highlights().unwrap();
+        let find = |id: &str| books.iter().find(|b| b.content_id == id).unwrap();
+        let now = chrono::Utc::now().naive_utc();
+
+        let expired = find("loan-expired");
+        assert!(expired.is_loan);
+        assert_eq!(
+            expired.loan_expiry.as_deref(),
+            Some("2024-03-01T00:00:00.000")
+        );
+        assert_eq!(expired.loan_state(now), Some(LoanState::Expired));
+        let open = find("loan-open");
+        assert!(open.is_loan);
+        assert_eq!(open.loan_expiry, None);
+        assert_eq!(open.loan_state(now), Some(LoanState::Active));
+        let purchase = find("purchase");
+        assert!(!purchase.is_loan);
+        assert_eq!(purchase.loan_state(now), None);
+
+        let exporter = crate::export::MarkdownExporter::new(PathBuf::from("/tmp"));
+        let mut config = crate::settings::AppSettings::default().export_config;
+        config.date_format = crate::models::DateFormat::Iso8601;
+        config.metadata.loan = true;
+        let markdown = exporter.generate_markdown(expired, &config);
+        assert!(markdown.contains("**Loan**: Expired library loan, due 2024-03-01\n"));
+        assert!(exporter
+            .generate_markdown(open, &config)
+            .contains("**Loan**: Library loan\n"));
+        assert!(!exporter
+            .generate_markdown(purchase, &config)
+            .contains("**Loan**"));
+
+        // Loans are judged at the exporter's clock
+        struct Before;
+        impl crate::scheduler::Clock for Before {
+            fn now(&self) -> chrono::DateTime<chrono::Utc> {
+                "2024-02-15T00:00:00Z".parse().unwrap()
+            }
+        }
+        let before = crate::export::MarkdownExporter::new(PathBuf::from("/tmp"))
+            .with_clock(Box::new(Before));
+        assert!(before
+            .generate_markdown(expired, &config)
+            .contains("**Loan**: Library loan, due 2024-03-01\n"));
+        config.metadata.loan = false;
+        assert!(!exporter
+            .generate_markdown(expired, &config)
+            .contains("**Loan**"));
     }
 
     #[test]
-    fn test_chapter_title_from_toc() {
-        // TOC entry (ContentType 899) should be preferred over page title (ContentType 9)
-        let mock_db = create_mock_db_with_toc();
-        let db = KoboDatabase::new(mock_db.path()).unwrap();
+    fn test_store_uuid_and_kepub_detection() {
+        assert!(is_store_uuid("6f1c2a9e-0b7d-4c55-9a1e-3d2f8b6c4e10"));
+        assert!(!is_store_uuid("6f1c2a9e-0b7d-4c55-9a1e"));
+        assert!(!is_store_uuid("file:///mnt/onboard/book.epub"));
+        assert!(is_kepub_path("file:///mnt/onboard/kepub/Book.KEPUB.EPUB"));
+        assert!(!is_kepub_path("file:///mnt/onboard/book.epub"));
+    }
 
-        let books = db.extract_books_with_highlights().unwrap();
+    #[test]
+    fn test_progress_scope_by_format() {
+        let temp = NamedTempFile::new().unwrap();
+        let conn = Connection::open(temp.path()).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE Bookmark (
+                BookmarkID TEXT PRIMARY KEY, ContentID TEXT, VolumeID TEXT, Text TEXT,
+                Annotation TEXT, StartContainerPath TEXT, ChapterProgress REAL,
+                DateCreated TEXT, Color TEXT
+            );
+            CREATE TABLE content (
+                ContentID TEXT, BookTitle TEXT, Title TEXT, Attribution TEXT, ISBN TEXT,
+                Publisher TEXT, Language TEXT, DateLastRead TEXT, ContentType INTEGER,
+                MimeType TEXT
+            );
+            INSERT INTO content VALUES
+                ('file:///mnt/onboard/Dune.kepub.epub', NULL, 'Dune', 'Frank Herbert',
+                 NULL, NULL, 'en', NULL, 6, 'application/x-kobo-epub+zip'),
+                ('file:///mnt/onboard/Dune.kepub.epub!ch1.xhtml-1', 'Dune', 'Book One',
+                 NULL, NULL, NULL, NULL, NULL, 899, NULL),
+                ('file:///mnt/onboard/Dune.kepub.epub!ch2.xhtml-1', 'Dune', 'Book Two',
+                 NULL, NULL, NULL, NULL, NULL, 899, NULL),
+                ('file:///mnt/onboard/Walden.epub', NULL, 'Walden', 'Henry David Thoreau',
+                 NULL, NULL, 'en', NULL, 6, 'application/epub+zip');
+            INSERT INTO Bookmark (BookmarkID, ContentID, VolumeID, Text, ChapterProgress, DateCreated) VALUES
+                ('k1', 'file:///mnt/onboard/Dune.kepub.epub!ch2.xhtml', 'file:///mnt/onboard/Dune.kepub.epub',
+                 'The spice must flow.', 0.1, '2025-01-01T10:00:00'),
+                ('k2', 'file:///mnt/onboard/Dune.kepub.epub!ch1.xhtml', 'file:///mnt/onboard/Dune.kepub.epub',
+                 'Fear is the mind-killer.', 0.9, '2025-01-02T10:00:00'),
+                ('k3', 'file:///mnt/onboard/Dune.kepub.epub!ch1.xhtml', 'file:///mnt/onboard/Dune.kepub.epub',
+                 'I must not fear.', 0.2, '2025-01-03T10:00:00'),
+                ('e1', 'file:///mnt/onboard/Walden.epub!ch.xhtml', 'file:///mnt/onboard/Walden.epub',
+                 'Simplify, simplify.', 0.6, '2025-01-01T10:00:00'),
+                ('e2', 'file:///mnt/onboard/Walden.epub!ch.xhtml', 'file:///mnt/onboard/Walden.epub',
+                 'I went to the woods.', 0.3, '2025-01-02T10:00:00');",
+        )
+        .unwrap();
 
-        assert_eq!(books.len(), 1);
-        assert_eq!(books[0].highlights.len(), 1);
+        let books = KoboDatabase::new(temp.path())
+            .unwrap()
+            .extract_books_with_highlights()
+            .unwrap();
+        let (dune, walden) = (&books[0], &books[1]);
+        assert_eq!(dune.progress_scope, Some(ProgressScope::Chapter));
+        assert_eq!(walden.progress_scope, Some(ProgressScope::Book));
+
+        // Chapter order first for the kepub; the raw float would put k1 first
+        let ids = |book: &Book| -> Vec<String> {
+            book.highlights_by_position()
+                .iter()
+                .map(|h| h.id.clone())
+                .collect()
+        };
+        assert_eq!(ids(dune), vec!["k3", "k2", "k1"]);
+        assert_eq!(ids(walden), vec!["e2", "e1"]);
+
+        let config = crate::settings::AppSettings::default().export_config;
+        let exporter = crate::export::MarkdownExporter::new(temp.path().to_path_buf());
+        let markdown = exporter.generate_markdown(dune, &config);
+        assert!(markdown.contains("Ch. 1 · Book One · 20% of chapter"));
+        assert!(markdown.contains("Ch. 2 · Book Two · 10% of chapter"));
+        assert!(markdown.find("I must not fear.") < markdown.find("The spice must flow."));
+        let markdown = exporter.generate_markdown(walden, &config);
+        assert!(markdown.contains("30% of book"));
+        assert!(markdown.find("I went to the woods.") < markdown.find("Simplify, simplify."));
+
+        // Store books are kepubs whatever their MIME type says
         assert_eq!(
-            books[0].highlights[0].chapter_title,
-            Some("Chapter 3: Connect Your Notes".to_string())
+            progress_scope("6f1c2a9e-0b7d-4c55-9a1e-3d2f8b6c4e10", None),
+            ProgressScope::Chapter
         );
     }
 
@@ -582,4 +2557,134 @@ mod tests {
             Some("Introduction".to_string())
         );
     }
+
+    fn split_test_highlight(id: &str, text: &str, progress: f64, date: &str) -> Highlight {
+        let mut h = Highlight::new(id.to_string(), text.to_string(), date.to_string());
+        h.chapter_title = Some("Chapter 1".to_string());
+        h.chapter_progress = Some(progress);
+        h
+    }
+
+    #[test]
+    fn test_parse_kobo_datetime_formats() {
+        assert!(parse_kobo_datetime("2025-01-24T10:15:30.000").is_some());
+        assert!(parse_kobo_datetime("2025-01-24T10:15:30Z").is_some());
+        assert!(parse_kobo_datetime("2025-01-24T10:15:30+01:00").is_some());
+        assert!(parse_kobo_datetime("Unknown").is_none());
+    }
+
+    #[test]
+    fn test_merge_genuine_split_pair() {
+        let mut book = Book::new("vol".to_string(), "Book".to_string(), "Author".to_string());
+        book.highlights = vec![
+            split_test_highlight(
+                "a",
+                "The quick brown fox jumps",
+                0.40,
+                "2025-01-24T10:15:30.000",
+            ),
+            split_test_highlight("b", "over the lazy dog.", 0.41, "2025-01-24T10:15:33.000"),
+        ];
+
+        merge_split_highlights(&mut book);
+
+        assert_eq!(book.highlights.len(), 1);
+        let merged = &book.highlights[0];
+        assert_eq!(merged.id, "a");
+        assert_eq!(merged.text, "The quick brown fox jumps over the lazy dog.");
+        assert_eq!(merged.date_created, "2025-01-24T10:15:30.000");
+        assert_eq!(merged.merged_from, vec!["a".to_string(), "b".to_string()]);
+    }
+
+    #[test]
+    fn test_adjacent_independent_highlights_not_merged() {
+        let mut book = Book::new("vol".to_string(), "Book".to_string(), "Author".to_string());
+        book.highlights = vec![
+            // Ends a sentence, so the next highlight is independent
+            split_test_highlight(
+                "a",
+                "First complete thought.",
+                0.40,
+                "2025-01-24T10:15:30.000",
+            ),
+            split_test_highlight("b", "Second thought", 0.41, "2025-01-24T10:15:33.000"),
+            // Unfinished, but created long after
+            split_test_highlight("c", "A later highlight", 0.42, "2025-01-24T11:00:00.000"),
+        ];
+
+        merge_split_highlights(&mut book);
+
+        assert_eq!(book.highlights.len(), 3);
+        assert!(book.highlights.iter().all(|h| h.merged_from.is_empty()));
+    }
+
+    #[test]
+    fn test_stable_ids_survive_bookmark_id_changes() {
+        let mock_db = create_mock_db();
+        let conn = Connection::open(mock_db.path()).unwrap();
+        conn.execute(
+            "INSERT INTO Bookmark VALUES ('hl2', 'vol1!section1', 'vol1',
+             'Second highlight', NULL, 'OEBPS/ch01.xhtml', 0.5, '2025-01-25', NULL)",
+            [],
+        )
+        .unwrap();
+        let db = KoboDatabase::new(mock_db.path()).unwrap();
+        let before = db.extract_books_with_highlights().unwrap().remove(0);
+
+        // Favorites and export tracking keyed by the old device IDs
+        let favorites = ["hl2".to_string()];
+        let exported = ["hl1".to_string(), "hl2".to_string()];
+
+        // A factory reset reassigns every BookmarkID
+        conn.execute(
+            "UPDATE Bookmark SET BookmarkID = 'reset-' || BookmarkID",
+            [],
+        )
+        .unwrap();
+        let after = db.extract_books_with_highlights().unwrap().remove(0);
+        assert!(after.highlights.iter().all(|h| h.id.starts_with("reset-")));
+
+        let remapped = crate::models::remap_highlight_ids(&before.highlights, &after.highlights);
+        let favorites: Vec<&String> = favorites.iter().map(|id| &remapped[id]).collect();
+        let exported: Vec<&String> = exported.iter().map(|id| &remapped[id]).collect();
+        assert_eq!(favorites, vec!["reset-hl2"]);
+        assert_eq!(exported, vec!["reset-hl1", "reset-hl2"]);
+
+        for (old, new) in before.highlights.iter().zip(&after.highlights) {
+            assert_eq!(old.stable_id, new.stable_id);
+            assert!(old.same_highlight(new));
+        }
+    }
+
+    #[test]
+    fn test_stable_ids_disambiguate_identical_quotes() {
+        let mut book = Book::new(
+            "vol1".to_string(),
+            "Title".to_string(),
+            "Author".to_string(),
+        );
+        book.highlights = vec![
+            split_test_highlight("a", "Same quote", 0.1, "2025-01-24"),
+            split_test_highlight("b", "Same  quote ", 0.6, "2025-01-24"),
+            split_test_highlight("c", "Same quote", 0.6, "2025-01-24"),
+            split_test_highlight("d", "Other quote", 0.6, "2025-01-24"),
+        ];
+
+        assign_stable_ids(&mut book);
+        let ids: Vec<&str> = book
+            .highlights
+            .iter()
+            .map(|h| h.stable_id.as_str())
+            .collect();
+        assert_eq!(ids[2], format!("{}-2", ids[1]));
+        let mut unique = ids.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), 4);
+
+        // Re-running is deterministic
+        let mut again = book.clone();
+        assign_stable_ids(&mut again);
+        assert_eq!(again, book);
+    }
 }
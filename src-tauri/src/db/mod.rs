@@ -1 +1,4 @@
+pub mod filters;
 pub mod kobo;
+pub mod query;
+pub mod schema;
@@ -0,0 +1,326 @@
+//! Read-only SQL queries against the Kobo database
+//!
+//! An escape hatch for data khi doesn't surface yet (e.g. the `WordList` of
+//! dictionary lookups). The database is opened read-only, an authorizer only
+//! lets a single SELECT through (no pragmas, ATTACH or writes), and a row cap
+//! and time limit bound what a query can cost.
+
+use rusqlite::hooks::{AuthAction, AuthContext, Authorization};
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{params_from_iter, Batch, Connection, ErrorCode, OpenFlags};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Rows returned before the result is truncated
+pub const DEFAULT_MAX_ROWS: usize = 1000;
+
+/// Time a query may run before it is interrupted
+pub const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// SQL functions refused even inside a SELECT
+const BLOCKED_FUNCTIONS: &[&str] = &["load_extension", "readfile", "writefile", "fts3_tokenizer"];
+
+/// Row and time limits of a query
+#[derive(Debug, Clone, Copy)]
+pub struct QueryLimits {
+    pub max_rows: usize,
+    pub timeout: Duration,
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        Self {
+            max_rows: DEFAULT_MAX_ROWS,
+            timeout: DEFAULT_QUERY_TIMEOUT,
+        }
+    }
+}
+
+/// Result rows as JSON arrays, in column order
+///
+/// Blobs are returned as lowercase hex strings.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<serde_json::Value>>,
+    /// More rows matched than `max_rows`
+    pub truncated: bool,
+}
+
+/// Run one SELECT with positional `params` against the database at `path`
+pub fn run_readonly_query(
+    path: &Path,
+    sql: &str,
+    params: &[serde_json::Value],
+    limits: &QueryLimits,
+) -> Result<QueryResult, QueryError> {
+    let conn = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    conn.execute_batch("PRAGMA query_only = ON;")?;
+
+    let denied = Arc::new(Mutex::new(None::<String>));
+    let denied_by_hook = denied.clone();
+    conn.authorizer(Some(move |ctx: AuthContext<'_>| {
+        let allowed = match ctx.action {
+            AuthAction::Select | AuthAction::Read { .. } | AuthAction::Recursive => true,
+            AuthAction::Function { function_name } => !BLOCKED_FUNCTIONS
+                .iter()
+                .any(|blocked| blocked.eq_ignore_ascii_case(function_name)),
+            _ => false,
+        };
+        if allowed {
+            Authorization::Allow
+        } else {
+            let mut denied = denied_by_hook.lock().unwrap_or_else(|e| e.into_inner());
+            denied.get_or_insert_with(|| format!("{:?}", ctx.action));
+            Authorization::Deny
+        }
+    }));
+    // Denied functions fail with a generic error code, so the hook's record
+    // decides whether the failure was a rejection
+    let rejected =
+        |err: rusqlite::Error| match denied.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            Some(action) => {
+                QueryError::Rejected(format!("only a single SELECT is allowed ({})", action))
+            }
+            None => QueryError::Sqlite(err),
+        };
+
+    let mut batch = Batch::new(&conn, sql);
+    let mut stmt = batch
+        .next()
+        .map_err(&rejected)?
+        .ok_or_else(|| QueryError::Rejected("empty query".to_string()))?;
+    if batch.next().map_err(&rejected)?.is_some() {
+        return Err(QueryError::Rejected(
+            "multiple statements are not allowed".to_string(),
+        ));
+    }
+    if !stmt.readonly() {
+        return Err(QueryError::Rejected(
+            "only read-only statements are allowed".to_string(),
+        ));
+    }
+
+    let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+    let deadline = Instant::now() + limits.timeout;
+    conn.progress_handler(1000, Some(move || Instant::now() > deadline));
+
+    let mut rows = stmt.query(params_from_iter(params.iter().map(json_to_sql)))?;
+    let mut result = QueryResult {
+        columns,
+        rows: Vec::new(),
+        truncated: false,
+    };
+    loop {
+        let row = match rows.next() {
+            Ok(Some(row)) => row,
+            Ok(None) => break,
+            Err(e) if e.sqlite_error_code() == Some(ErrorCode::OperationInterrupted) => {
+                return Err(QueryError::Timeout(limits.timeout));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if result.rows.len() == limits.max_rows {
+            result.truncated = true;
+            break;
+        }
+        let values = (0..result.columns.len())
+            .map(|index| row.get_ref(index).map(sql_to_json))
+            .collect::<Result<Vec<_>, _>>()?;
+        result.rows.push(values);
+    }
+    Ok(result)
+}
+
+fn json_to_sql(value: &serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Integer(*b as i64),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Real(n.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(s) => Value::Text(s.clone()),
+        other => Value::Text(other.to_string()),
+    }
+}
+
+fn sql_to_json(value: ValueRef<'_>) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(i) => i.into(),
+        ValueRef::Real(f) => serde_json::Number::from_f64(f)
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        ValueRef::Text(text) => String::from_utf8_lossy(text).into_owned().into(),
+        ValueRef::Blob(blob) => blob
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+            .into(),
+    }
+}
+
+/// Read-only query errors
+#[derive(Debug)]
+pub enum QueryError {
+    /// SQLite error
+    Sqlite(rusqlite::Error),
+    /// Not a single read-only SELECT
+    Rejected(String),
+    /// Interrupted after the time limit
+    Timeout(Duration),
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryError::Sqlite(e) => write!(f, "Database error: {}", e),
+            QueryError::Rejected(reason) => write!(f, "Query rejected: {}", reason),
+            QueryError::Timeout(limit) => {
+                write!(f, "Query took longer than {} seconds", limit.as_secs_f32())
+            }
+        }
+    }
+}
+
+impl std::error::Error for QueryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QueryError::Sqlite(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for QueryError {
+    fn from(err: rusqlite::Error) -> Self {
+        QueryError::Sqlite(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn mock_db() -> (TempDir, std::path::PathBuf) {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("KoboReader.sqlite");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE WordList (Text TEXT, VolumeId TEXT, DictSuffix TEXT);
+             INSERT INTO WordList VALUES ('saudade', 'book1', '-pt');
+             INSERT INTO WordList VALUES ('hygge', 'book1', '-en');
+             INSERT INTO WordList VALUES ('ubuntu', 'book2', '-en');",
+        )
+        .unwrap();
+        (temp, path)
+    }
+
+    fn query(path: &Path, sql: &str) -> Result<QueryResult, QueryError> {
+        run_readonly_query(path, sql, &[], &QueryLimits::default())
+    }
+
+    #[test]
+    fn test_select_returns_rows_with_columns() {
+        let (_temp, path) = mock_db();
+        let result = run_readonly_query(
+            &path,
+            "SELECT Text, DictSuffix FROM WordList WHERE VolumeId = ?1 ORDER BY Text",
+            &[json!("book1")],
+            &QueryLimits::default(),
+        )
+        .unwrap();
+
+        assert_eq!(result.columns, vec!["Text", "DictSuffix"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![json!("hygge"), json!("-en")],
+                vec![json!("saudade"), json!("-pt")]
+            ]
+        );
+        assert!(!result.truncated);
+    }
+
+    #[test]
+    fn test_row_cap_truncates_with_flag() {
+        let (_temp, path) = mock_db();
+        let limits = QueryLimits {
+            max_rows: 2,
+            ..QueryLimits::default()
+        };
+        let result = run_readonly_query(&path, "SELECT * FROM WordList", &[], &limits).unwrap();
+
+        assert_eq!(result.rows.len(), 2);
+        assert!(result.truncated);
+    }
+
+    #[test]
+    fn test_update_is_rejected() {
+        let (_temp, path) = mock_db();
+        let err = query(&path, "UPDATE WordList SET Text = 'x'").unwrap_err();
+        assert!(matches!(err, QueryError::Rejected(_)), "{}", err);
+
+        let count = query(&path, "SELECT COUNT(*) FROM WordList WHERE Text = 'x'").unwrap();
+        assert_eq!(count.rows, vec![vec![json!(0)]]);
+    }
+
+    #[test]
+    fn test_multiple_statements_are_rejected() {
+        let (_temp, path) = mock_db();
+        let err = query(&path, "SELECT 1; SELECT 2").unwrap_err();
+        assert!(matches!(err, QueryError::Rejected(_)), "{}", err);
+
+        let err = query(&path, "SELECT 1; DELETE FROM WordList").unwrap_err();
+        assert!(matches!(err, QueryError::Rejected(_)), "{}", err);
+
+        // A trailing comment is not a second statement
+        assert!(query(&path, "SELECT 1; -- done").is_ok());
+    }
+
+    #[test]
+    fn test_attach_and_pragmas_are_rejected() {
+        let (temp, path) = mock_db();
+        let other = temp.path().join("other.sqlite");
+        let attach = format!("ATTACH DATABASE '{}' AS other", other.display());
+
+        for sql in [
+            attach.as_str(),
+            "PRAGMA writable_schema = ON",
+            "PRAGMA table_info(WordList)",
+            "SELECT load_extension('evil')",
+        ] {
+            let err = query(&path, sql).unwrap_err();
+            assert!(matches!(err, QueryError::Rejected(_)), "{}: {}", sql, err);
+        }
+        assert!(!other.exists());
+    }
+
+    #[test]
+    fn test_slow_query_times_out() {
+        let (_temp, path) = mock_db();
+        let limits = QueryLimits {
+            timeout: Duration::from_millis(50),
+            ..QueryLimits::default()
+        };
+        let err = run_readonly_query(
+            &path,
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n)
+             SELECT COUNT(*) FROM n",
+            &[],
+            &limits,
+        )
+        .unwrap_err();
+
+        assert!(matches!(err, QueryError::Timeout(_)), "{}", err);
+    }
+}
@@ -0,0 +1,442 @@
+//! Kobo database schema fingerprints
+//!
+//! Firmware updates now and then add or drop columns. Opening a database
+//! fingerprints it (the `DbVersion` and the columns of `Bookmark` and
+//! `content`) and compares it with the schemas khi has been tested with, so
+//! an import from unfamiliar firmware can say so instead of quietly missing
+//! data. Only the required columns (`REQUIRED_SCHEMA`) ever fail an import,
+//! and only the columns imports read can make a schema unknown: others that
+//! come or go are reported, not held against it.
+//!
+//! Imports log the fingerprint; adding a verified firmware means adding its
+//! entry to `KNOWN_SCHEMAS`.
+
+use super::kobo::REQUIRED_SCHEMA;
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// A schema khi has been tested with
+pub struct KnownSchema {
+    pub db_version: i64,
+    pub bookmark: &'static [&'static str],
+    pub content: &'static [&'static str],
+}
+
+const BOOKMARK_COLUMNS: &[&str] = &[
+    "BookmarkID",
+    "VolumeID",
+    "ContentID",
+    "StartContainerPath",
+    "StartContainerChildIndex",
+    "StartOffset",
+    "EndContainerPath",
+    "EndContainerChildIndex",
+    "EndOffset",
+    "Text",
+    "Annotation",
+    "ExtraAnnotationData",
+    "DateCreated",
+    "ChapterProgress",
+    "Hidden",
+    "Version",
+    "DateModified",
+    "Creator",
+    "UUID",
+    "UserID",
+    "SyncTime",
+    "Published",
+    "ContextString",
+    "Type",
+    "Color",
+];
+
+const CONTENT_COLUMNS: &[&str] = &[
+    "ContentID",
+    "ContentType",
+    "MimeType",
+    "BookID",
+    "BookTitle",
+    "ImageId",
+    "Title",
+    "Attribution",
+    "Description",
+    "DateCreated",
+    "ShortCoverKey",
+    "adobe_location",
+    "Publisher",
+    "IsEncrypted",
+    "DateLastRead",
+    "FirstTimeReading",
+    "ChapterIDBookmarked",
+    "ParagraphBookmarked",
+    "BookmarkWordOffset",
+    "NumShortcovers",
+    "VolumeIndex",
+    "___NumPages",
+    "ReadStatus",
+    "___SyncTime",
+    "___UserID",
+    "PublicationId",
+    "___FileOffset",
+    "___FileSize",
+    "___PercentRead",
+    "___ExpirationStatus",
+    "FavouritesIndex",
+    "Accessibility",
+    "ContentURL",
+    "Language",
+    "BookshelfTags",
+    "IsDownloaded",
+    "FeedbackType",
+    "AverageRating",
+    "Depth",
+    "PageProgressDirection",
+    "InWishlist",
+    "ISBN",
+    "WishlistedDate",
+    "FeedbackTypeSynced",
+    "IsSocialEnabled",
+    "EpubType",
+    "Monetization",
+    "ExternalId",
+    "Series",
+    "SeriesNumber",
+    "Subtitle",
+    "WordCount",
+    "Fallback",
+    "RestOfBookEstimate",
+    "CurrentChapterEstimate",
+    "CurrentChapterProgress",
+    "PocketStatus",
+    "UnsyncedPocketChanges",
+    "ImageUrl",
+    "DateAdded",
+    "WorkId",
+    "Properties",
+    "RenditionSpread",
+    "RatingCount",
+    "ReviewsSyncDate",
+    "MediaOverlay",
+    "MediaOverlayType",
+    "RedirectPreviewUrl",
+    "PreviewFileSize",
+    "EntitlementId",
+    "CrossRevisionId",
+    "DownloadUrl",
+    "ReadStateSynced",
+    "TimesStartedReading",
+    "TimeSpentReading",
+    "LastTimeStartedReading",
+    "LastTimeFinishedReading",
+    "ApplicableSubscriptions",
+    "ExternalIds",
+    "PurchaseRate",
+    "SeriesID",
+    "SeriesNumberFloat",
+];
+
+/// Columns imports read when the database has them, besides the required
+/// ones (see `KoboDatabase::extract_books`)
+const OPTIONAL_COLUMNS: &[&str] = &[
+    "Bookmark.Hidden",
+    "Bookmark.Type",
+    "content.___NumPages",
+    "content.MimeType",
+    "content.ContentURL",
+    "content.___ExpirationStatus",
+    "content.ExpirationDate",
+    "content.___PercentRead",
+    "content.TimeSpentReading",
+];
+
+/// Schemas khi has been tested with, oldest first
+pub const KNOWN_SCHEMAS: &[KnownSchema] = &[KnownSchema {
+    db_version: 174,
+    bookmark: BOOKMARK_COLUMNS,
+    content: CONTENT_COLUMNS,
+}];
+
+/// `DbVersion` and tracked columns of a Kobo database
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaFingerprint {
+    /// From the `DbVersion` table, else `PRAGMA user_version` (if set)
+    pub db_version: Option<i64>,
+    /// `Table.Column` names of `Bookmark` and `content`
+    pub columns: BTreeSet<String>,
+}
+
+impl SchemaFingerprint {
+    pub fn read(conn: &Connection) -> Result<Self> {
+        let has_version_table: bool = conn
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'DbVersion'",
+                [],
+                |_| Ok(true),
+            )
+            .optional()?
+            .unwrap_or(false);
+        let db_version = if has_version_table {
+            // A DbVersion table of another shape only loses the version
+            conn.query_row("SELECT MAX(version) FROM DbVersion", [], |row| row.get(0))
+                .unwrap_or_else(|e| {
+                    log::warn!("[Kobo] Unreadable DbVersion: {}", e);
+                    None
+                })
+        } else {
+            let user_version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+            Some(user_version).filter(|&v| v > 0)
+        };
+
+        let mut columns = BTreeSet::new();
+        for table in ["Bookmark", "content"] {
+            let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+            let names = stmt.query_map([], |row| row.get::<_, String>("name"))?;
+            for name in names {
+                columns.insert(format!("{}.{}", table, name?));
+            }
+        }
+        Ok(Self {
+            db_version,
+            columns,
+        })
+    }
+
+    /// How this schema compares with the known ones
+    pub fn compatibility(&self) -> SchemaCompatibility {
+        self.compare(KNOWN_SCHEMAS)
+    }
+
+    fn compare(&self, known: &[KnownSchema]) -> SchemaCompatibility {
+        let Some(closest) = self.closest(known) else {
+            return SchemaCompatibility::Known;
+        };
+        let expected = known_columns(closest);
+        let read = read_columns();
+        let missing_read = expected
+            .iter()
+            .any(|column| read.contains(column) && !self.columns.contains(column));
+        if self.db_version.is_none_or(|v| v == closest.db_version) && !missing_read {
+            return SchemaCompatibility::Known;
+        }
+
+        let differences = SchemaDifferences {
+            db_version: self.db_version,
+            known_version: closest.db_version,
+            new_columns: self.columns.difference(&expected).cloned().collect(),
+            missing_columns: expected.difference(&self.columns).cloned().collect(),
+        };
+        let newer = match self.db_version {
+            Some(version) => version > closest.db_version,
+            // Without a version, firmware usually adds columns rather than
+            // dropping them
+            None => differences.new_columns.len() >= differences.missing_columns.len(),
+        };
+        if newer {
+            SchemaCompatibility::NewerUnknown { differences }
+        } else {
+            SchemaCompatibility::OlderUnknown { differences }
+        }
+    }
+
+    /// The known schema with the nearest version (or, without a version,
+    /// the fewest differing columns)
+    fn closest<'a>(&self, known: &'a [KnownSchema]) -> Option<&'a KnownSchema> {
+        match self.db_version {
+            Some(version) => known
+                .iter()
+                .min_by_key(|schema| (schema.db_version - version).abs()),
+            None => known.iter().min_by_key(|schema| {
+                known_columns(schema)
+                    .symmetric_difference(&self.columns)
+                    .count()
+            }),
+        }
+    }
+}
+
+fn known_columns(schema: &KnownSchema) -> BTreeSet<String> {
+    let bookmark = schema.bookmark.iter().map(|c| format!("Bookmark.{}", c));
+    let content = schema.content.iter().map(|c| format!("content.{}", c));
+    bookmark.chain(content).collect()
+}
+
+/// `Table.Column` names imports read, required or not
+fn read_columns() -> BTreeSet<String> {
+    let required = REQUIRED_SCHEMA.iter().flat_map(|(table, columns)| {
+        columns
+            .iter()
+            .map(move |column| format!("{}.{}", table, column))
+    });
+    required
+        .chain(OPTIONAL_COLUMNS.iter().map(|column| column.to_string()))
+        .collect()
+}
+
+/// Columns that differ from the closest known schema
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaDifferences {
+    /// The database's version, when it records one
+    pub db_version: Option<i64>,
+    /// Version of the known schema compared against
+    pub known_version: i64,
+    /// `Table.Column` names khi doesn't know (and ignores)
+    pub new_columns: Vec<String>,
+    /// Known `Table.Column` names the database lacks
+    pub missing_columns: Vec<String>,
+}
+
+/// Whether the device database matches a schema khi has been tested with
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum SchemaCompatibility {
+    Known,
+    NewerUnknown { differences: SchemaDifferences },
+    OlderUnknown { differences: SchemaDifferences },
+}
+
+impl SchemaCompatibility {
+    pub fn is_known(&self) -> bool {
+        matches!(self, SchemaCompatibility::Known)
+    }
+
+    /// One line for the UI and the log; `None` for known schemas
+    pub fn message(&self) -> Option<String> {
+        let (age, differences) = match self {
+            SchemaCompatibility::Known => return None,
+            SchemaCompatibility::NewerUnknown { differences } => ("newer", differences),
+            SchemaCompatibility::OlderUnknown { differences } => ("older", differences),
+        };
+        let mut details = Vec::new();
+        if !differences.new_columns.is_empty() {
+            details.push(format!(
+                "{} new column(s) ignored",
+                differences.new_columns.len()
+            ));
+        }
+        if !differences.missing_columns.is_empty() {
+            details.push(format!(
+                "{} known column(s) missing",
+                differences.missing_columns.len()
+            ));
+        }
+        let mut message = format!(
+            "Your firmware is {} than khi has been tested with — import proceeded",
+            age
+        );
+        if !details.is_empty() {
+            message = format!("{}, {}", message, details.join(", "));
+        }
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database with the given `DbVersion` and columns
+    fn database(version: Option<i64>, bookmark: &[&str], content: &[&str]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        let table = |name: &str, columns: &[&str]| {
+            let columns: Vec<String> = columns.iter().map(|c| format!("\"{}\"", c)).collect();
+            format!("CREATE TABLE {} ({});", name, columns.join(", "))
+        };
+        conn.execute_batch(&table("Bookmark", bookmark)).unwrap();
+        conn.execute_batch(&table("content", content)).unwrap();
+        if let Some(version) = version {
+            conn.execute_batch(&format!(
+                "CREATE TABLE DbVersion (version INTEGER); INSERT INTO DbVersion VALUES ({});",
+                version
+            ))
+            .unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn test_registered_schema_is_known() {
+        let known = &KNOWN_SCHEMAS[0];
+        let conn = database(Some(known.db_version), known.bookmark, known.content);
+        let fingerprint = SchemaFingerprint::read(&conn).unwrap();
+        assert_eq!(fingerprint.db_version, Some(known.db_version));
+        assert_eq!(fingerprint.compatibility(), SchemaCompatibility::Known);
+        assert_eq!(fingerprint.compatibility().message(), None);
+    }
+
+    #[test]
+    fn test_extra_columns_are_newer_unknown() {
+        let known = &KNOWN_SCHEMAS[0];
+        let mut bookmark = known.bookmark.to_vec();
+        bookmark.push("HighlightStyle");
+        let mut content = known.content.to_vec();
+        content.push("ReadingGoal");
+        let conn = database(Some(known.db_version + 5), &bookmark, &content);
+
+        let compatibility = SchemaFingerprint::read(&conn).unwrap().compatibility();
+        let SchemaCompatibility::NewerUnknown { differences } = &compatibility else {
+            panic!("{:?}", compatibility);
+        };
+        assert_eq!(
+            differences.new_columns,
+            vec!["Bookmark.HighlightStyle", "content.ReadingGoal"]
+        );
+        assert!(differences.missing_columns.is_empty());
+        assert_eq!(
+            compatibility.message().unwrap(),
+            "Your firmware is newer than khi has been tested with — import proceeded, \
+             2 new column(s) ignored"
+        );
+    }
+
+    #[test]
+    fn test_columns_imports_dont_read_dont_matter() {
+        let known = &KNOWN_SCHEMAS[0];
+        let mut bookmark = known.bookmark.to_vec();
+        bookmark.push("HighlightStyle");
+        let content: Vec<&str> = known
+            .content
+            .iter()
+            .copied()
+            .filter(|c| *c != "WishlistedDate")
+            .collect();
+        let conn = database(Some(known.db_version), &bookmark, &content);
+        assert_eq!(
+            SchemaFingerprint::read(&conn).unwrap().compatibility(),
+            SchemaCompatibility::Known
+        );
+
+        // Nor does a DbVersion table khi can't read
+        let conn = database(None, known.bookmark, known.content);
+        conn.execute_batch("CREATE TABLE DbVersion (release TEXT)")
+            .unwrap();
+        let fingerprint = SchemaFingerprint::read(&conn).unwrap();
+        assert_eq!(fingerprint.db_version, None);
+        assert!(fingerprint.compatibility().is_known());
+    }
+
+    #[test]
+    fn test_missing_optional_column_is_older_unknown() {
+        let known = &KNOWN_SCHEMAS[0];
+        let content: Vec<&str> = known
+            .content
+            .iter()
+            .copied()
+            .filter(|c| *c != "MimeType")
+            .collect();
+        // No DbVersion table: classified by the columns alone
+        let conn = database(None, known.bookmark, &content);
+
+        let compatibility = SchemaFingerprint::read(&conn).unwrap().compatibility();
+        let SchemaCompatibility::OlderUnknown { differences } = &compatibility else {
+            panic!("{:?}", compatibility);
+        };
+        assert_eq!(differences.db_version, None);
+        assert_eq!(differences.missing_columns, vec!["content.MimeType"]);
+        assert!(compatibility
+            .message()
+            .unwrap()
+            .ends_with("1 known column(s) missing"));
+    }
+}
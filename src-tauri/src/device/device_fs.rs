@@ -0,0 +1,283 @@
+//! Walking the files of a mounted device
+//!
+//! Device volumes collect OS junk (`.Trashes`, `.Spotlight-V100`,
+//! `System Volume Information`, AppleDouble `._*` files) and Kobo's own
+//! `.kobo-images` cover cache. `DeviceFs` skips hidden entries, the
+//! `DEFAULT_IGNORED` names and any names the user adds, plus every folder
+//! holding an ignore marker (`.nomedia`, `.khiignore`). Unreadable folders
+//! are skipped with a debug log: volumes are full of them and none matter.
+
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, ReadDir};
+use std::path::{Component, Path, PathBuf};
+
+/// Folders and files never walked, besides hidden ones
+pub const DEFAULT_IGNORED: &[&str] = &[
+    ".Trashes",
+    ".Spotlight-V100",
+    ".fseventsd",
+    ".TemporaryItems",
+    ".DocumentRevisions-V100",
+    "System Volume Information",
+    "$RECYCLE.BIN",
+    ".kobo-images",
+];
+
+/// Files that exclude the folder holding them (and its subfolders)
+pub const IGNORE_MARKERS: &[&str] = &[".nomedia", ".khiignore"];
+
+/// Files of a device volume, without the junk
+pub struct DeviceFs {
+    root: PathBuf,
+    /// Lowercase names of ignored folders and files
+    ignored: Vec<String>,
+    /// Book files by file name, built on the first `locate` miss
+    index: OnceCell<HashMap<OsString, PathBuf>>,
+}
+
+impl DeviceFs {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            ignored: DEFAULT_IGNORED.iter().map(|n| n.to_lowercase()).collect(),
+            index: OnceCell::new(),
+        }
+    }
+
+    /// Also ignore the user's `names` (matched case-insensitively)
+    pub fn with_ignored(mut self, names: &[String]) -> Self {
+        self.ignored.extend(
+            names
+                .iter()
+                .map(|n| n.trim().to_lowercase())
+                .filter(|n| !n.is_empty()),
+        );
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Whether a folder or file called `name` is skipped
+    pub fn is_ignored_name(&self, name: &OsStr) -> bool {
+        let name = name.to_string_lossy();
+        name.starts_with('.') || self.ignored.contains(&name.to_lowercase())
+    }
+
+    /// Whether `relative` (to the root) lies in a skipped folder or is a
+    /// skipped file
+    pub fn is_ignored(&self, relative: &Path) -> bool {
+        let mut dir = self.root.clone();
+        for component in relative.components() {
+            let Component::Normal(name) = component else {
+                return true;
+            };
+            if self.is_ignored_name(name) || has_marker(&dir) {
+                return true;
+            }
+            dir.push(name);
+        }
+        false
+    }
+
+    /// Every file under the root that isn't skipped, in no particular order
+    pub fn files(&self) -> DeviceWalk<'_> {
+        DeviceWalk {
+            device: self,
+            pending: vec![self.root.clone()],
+            current: None,
+        }
+    }
+
+    /// The book file stored at `relative`, or else a file with the same name
+    /// elsewhere on the device (books moved into folders since the import)
+    pub fn locate(&self, relative: &str) -> Option<PathBuf> {
+        let relative = Path::new(relative);
+        let direct = self.root.join(relative);
+        if direct.is_file() && !self.is_ignored(relative) {
+            return Some(direct);
+        }
+        let name = relative.file_name()?;
+        self.index
+            .get_or_init(|| {
+                self.files()
+                    .filter_map(|path| Some((path.file_name()?.to_os_string(), path)))
+                    .collect()
+            })
+            .get(name)
+            .cloned()
+    }
+}
+
+/// Whether `dir` holds an ignore marker
+fn has_marker(dir: &Path) -> bool {
+    IGNORE_MARKERS
+        .iter()
+        .any(|marker| dir.join(marker).exists())
+}
+
+/// Iterator over the files of a `DeviceFs` (see `DeviceFs::files`)
+pub struct DeviceWalk<'a> {
+    device: &'a DeviceFs,
+    /// Folders still to read
+    pending: Vec<PathBuf>,
+    current: Option<ReadDir>,
+}
+
+impl DeviceWalk<'_> {
+    /// Start reading the next pending folder; `false` once none are left
+    fn open_next(&mut self) -> bool {
+        while let Some(dir) = self.pending.pop() {
+            if has_marker(&dir) {
+                log::debug!("[DEVICE] {:?} ignorado (marcador)", dir);
+                continue;
+            }
+            match fs::read_dir(&dir) {
+                Ok(entries) => {
+                    self.current = Some(entries);
+                    return true;
+                }
+                Err(e) => log::debug!("[DEVICE] {:?} ilegível: {}", dir, e),
+            }
+        }
+        false
+    }
+}
+
+impl Iterator for DeviceWalk<'_> {
+    type Item = PathBuf;
+
+    fn next(&mut self) -> Option<PathBuf> {
+        loop {
+            let Some(entries) = self.current.as_mut() else {
+                if self.open_next() {
+                    continue;
+                }
+                return None;
+            };
+            let entry = match entries.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(e)) => {
+                    log::debug!("[DEVICE] Entrada ilegível: {}", e);
+                    continue;
+                }
+                None => {
+                    self.current = None;
+                    continue;
+                }
+            };
+            if self.device.is_ignored_name(&entry.file_name()) {
+                continue;
+            }
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => self.pending.push(entry.path()),
+                Ok(kind) if kind.is_file() => return Some(entry.path()),
+                Ok(_) => {}
+                Err(e) => log::debug!("[DEVICE] {:?} ilegível: {}", entry.path(), e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use tempfile::TempDir;
+
+    fn touch(root: &Path, relative: &str) {
+        let path = root.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "x").unwrap();
+    }
+
+    /// A device with two real books among the usual junk
+    fn junk_device() -> TempDir {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        touch(root, "Walden.epub");
+        touch(root, "Books/Saramago/Memorial.kepub.epub");
+        touch(root, "._Walden.epub");
+        touch(root, ".kobo/KoboReader.sqlite");
+        touch(root, ".kobo-images/123/cover.parsed");
+        touch(root, ".Trashes/501/Old.epub");
+        touch(root, ".Spotlight-V100/Store-V2/store.db");
+        touch(root, "System Volume Information/IndexerVolumeGuid");
+        touch(root, "Comics/.nomedia");
+        touch(root, "Comics/Issue1.cbz");
+        touch(root, "Backups/Walden.epub");
+        temp
+    }
+
+    fn relative_files(device: &DeviceFs) -> BTreeSet<String> {
+        device
+            .files()
+            .map(|path| {
+                path.strip_prefix(device.root())
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_walk_skips_junk_and_unreadable_folders() {
+        let temp = junk_device();
+        #[cfg(unix)]
+        for junk in [".Trashes", "System Volume Information"] {
+            use std::os::unix::fs::PermissionsExt;
+            let path = temp.path().join(junk);
+            fs::set_permissions(path, fs::Permissions::from_mode(0o000)).unwrap();
+        }
+
+        let device = DeviceFs::new(temp.path().to_path_buf()).with_ignored(&["backups".into()]);
+        assert_eq!(
+            relative_files(&device),
+            BTreeSet::from([
+                "Books/Saramago/Memorial.kepub.epub".to_string(),
+                "Walden.epub".to_string(),
+            ])
+        );
+
+        #[cfg(unix)]
+        for junk in [".Trashes", "System Volume Information"] {
+            use std::os::unix::fs::PermissionsExt;
+            let path = temp.path().join(junk);
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_unreadable_root_yields_nothing() {
+        let temp = TempDir::new().unwrap();
+        let device = DeviceFs::new(temp.path().join("unmounted"));
+        assert_eq!(device.files().count(), 0);
+        assert_eq!(device.locate("Walden.epub"), None);
+    }
+
+    #[test]
+    fn test_locate_prefers_the_recorded_path_and_skips_junk() {
+        let temp = junk_device();
+        let device = DeviceFs::new(temp.path().to_path_buf());
+
+        assert_eq!(
+            device.locate("Walden.epub"),
+            Some(temp.path().join("Walden.epub"))
+        );
+        // Moved on the device since the import
+        assert_eq!(
+            device.locate("Memorial.kepub.epub"),
+            Some(temp.path().join("Books/Saramago/Memorial.kepub.epub"))
+        );
+        // Only ever found in the trash or a marked folder
+        assert_eq!(device.locate(".Trashes/501/Old.epub"), None);
+        assert_eq!(device.locate("Old.epub"), None);
+        assert_eq!(device.locate("Comics/Issue1.cbz"), None);
+        assert!(device.is_ignored(Path::new("system volume information/x")));
+        assert!(!device.is_ignored(Path::new("Books/Saramago/Memorial.kepub.epub")));
+    }
+}
@@ -0,0 +1,148 @@
+//! Highlights the device may not have saved yet
+//!
+//! A Kobo put to sleep in the middle of a book (rather than from the home
+//! screen before connecting) can keep its latest annotations in memory, so
+//! an import right away misses them. When the device records its last sleep
+//! (`POWER_STATE_FILES`), a highlight newer than that belongs to a reading
+//! session that was never closed, and highlights made after it may be
+//! missing. This is a heuristic: it only produces an `ImportWarning`, never
+//! fails an import, and says nothing when the sleep time isn't recorded.
+
+use crate::models::Book;
+use crate::settings::ImportWarning;
+use crate::utils::date::parse_timestamp;
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Power state files (relative to the volume) and the key holding the last
+/// suspend time, in order of preference
+const POWER_STATE_FILES: &[(&str, &str)] = &[(".kobo/Kobo/Kobo eReader.conf", "LastSuspendTime")];
+
+/// Whether an import of a device may be missing recent highlights
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceFreshness {
+    /// Newest highlight in the device database
+    pub newest_highlight: Option<DateTime<Utc>>,
+    /// When the device last went to sleep; `None` when it isn't recorded
+    pub last_suspend: Option<DateTime<Utc>>,
+    /// Set when the data looks stale, with what to do about it
+    pub warning: Option<ImportWarning>,
+}
+
+impl DeviceFreshness {
+    pub fn possibly_stale(&self) -> bool {
+        self.warning.is_some()
+    }
+}
+
+/// Last suspend time recorded on the volume at `root`; zone-less values are
+/// at `offset`, like highlight dates
+pub fn last_suspend(root: &Path, offset: FixedOffset) -> Option<DateTime<Utc>> {
+    POWER_STATE_FILES.iter().find_map(|(file, key)| {
+        let content = fs::read_to_string(root.join(file)).ok()?;
+        content.lines().find_map(|line| {
+            let (name, value) = line.split_once('=')?;
+            if !name.trim().eq_ignore_ascii_case(key) {
+                return None;
+            }
+            parse_timestamp(value.trim().trim_matches('"'), offset)
+        })
+    })
+}
+
+/// Compare the newest highlight of `books` (read from the device at `root`)
+/// with the device's last suspend
+pub fn check_freshness(root: &Path, books: &[Book], offset: FixedOffset) -> DeviceFreshness {
+    let newest = books
+        .iter()
+        .flat_map(|book| book.highlights.iter().map(move |h| (book, h)))
+        .filter_map(|(book, h)| Some((h.created_at()?, book)))
+        .max_by_key(|(created, _)| *created);
+    let last_suspend = last_suspend(root, offset);
+
+    let warning = match (newest, last_suspend) {
+        (Some((created, book)), Some(suspend)) if created > suspend => {
+            log::warn!(
+                "[Freshness] Newest highlight ({}) is after the last suspend ({}): '{}' may have unsaved highlights",
+                created,
+                suspend,
+                book.title
+            );
+            Some(ImportWarning::possibly_stale_data(book, suspend))
+        }
+        _ => None,
+    };
+    DeviceFreshness {
+        newest_highlight: newest.map(|(created, _)| created),
+        last_suspend,
+        warning,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Highlight;
+    use crate::settings::ImportWarningKind;
+    use crate::utils::date::utc_offset;
+    use tempfile::TempDir;
+
+    fn write_suspend(root: &Path, value: &str) {
+        let conf = root.join(".kobo/Kobo/Kobo eReader.conf");
+        fs::create_dir_all(conf.parent().unwrap()).unwrap();
+        fs::write(
+            conf,
+            format!(
+                "[PowerOptions]\nAutoOffMinutes=60\nLastSuspendTime={}\n",
+                value
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_highlight_after_last_suspend_is_flagged_until_it_sleeps_again() {
+        let temp = TempDir::new().unwrap();
+        let mut walden = Book::new("vol2".into(), "Walden".into(), "Thoreau".into());
+        walden.add_highlight(Highlight::new(
+            "hl1".into(),
+            "Simplify, simplify.".into(),
+            "2025-02-01T20:00:00.000".into(),
+        ));
+        walden.add_highlight(Highlight::new(
+            "hl2".into(),
+            "Rather than love, than money.".into(),
+            "2025-02-02T21:30:00.000".into(),
+        ));
+        let books = vec![walden];
+
+        // Nothing recorded: no warning, the import goes on
+        let freshness = check_freshness(temp.path(), &books, utc_offset());
+        assert_eq!(freshness.last_suspend, None);
+        assert!(!freshness.possibly_stale());
+
+        // Put to sleep before the newest highlight
+        write_suspend(temp.path(), "2025-02-02T21:00:00Z");
+        let freshness = check_freshness(temp.path(), &books, utc_offset());
+        let warning = freshness.warning.as_ref().unwrap();
+        assert_eq!(warning.kind, ImportWarningKind::PossiblyStaleData);
+        assert_eq!(warning.content_id, "vol2");
+        assert!(warning.message.contains("sleep"));
+        assert_eq!(
+            freshness.newest_highlight.unwrap().to_rfc3339(),
+            "2025-02-02T21:30:00+00:00"
+        );
+
+        // Woken and put to sleep again (epoch seconds also accepted)
+        let later = DateTime::parse_from_rfc3339("2025-02-02T22:00:00Z")
+            .unwrap()
+            .timestamp();
+        write_suspend(temp.path(), &later.to_string());
+        let freshness = check_freshness(temp.path(), &books, utc_offset());
+        assert!(!freshness.possibly_stale());
+        assert!(freshness.last_suspend.is_some());
+    }
+}
@@ -1,8 +1,14 @@
+pub mod device_fs;
+pub mod freshness;
 pub mod monitor;
+pub mod registry;
 
+use crate::db::kobo::{check_kobo_schema, KoboDbError, MAX_DATABASE_BYTES};
 use crate::models::KoboDevice;
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 pub struct DeviceDetector {
     volumes_path: PathBuf,
@@ -14,13 +20,33 @@ impl DeviceDetector {
     }
 
     /// Scan for connected Kobo devices
+    ///
+    /// A volume whose database passes schema validation wins over volumes
+    /// that merely contain a `.kobo` folder. Stale mounts are never returned.
     pub fn scan_for_kobo(&self) -> Result<Option<KoboDevice>, DeviceError> {
+        let mut first_invalid = None;
+        for device in self.scan_for_devices()? {
+            match device {
+                device if device.is_stale => {}
+                device if device.is_valid => return Ok(Some(device)),
+                device => {
+                    first_invalid.get_or_insert(device);
+                }
+            }
+        }
+        Ok(first_invalid)
+    }
+
+    /// Every mounted Kobo volume, with outdated duplicate mounts marked
+    /// `is_stale` (see `mark_stale_duplicates`)
+    pub fn scan_for_devices(&self) -> Result<Vec<KoboDevice>, DeviceError> {
         // Check if volumes directory exists
         if !self.volumes_path.exists() {
-            return Ok(None);
+            return Ok(Vec::new());
         }
 
         // Iterate through mounted volumes
+        let mut candidates = Vec::new();
         for entry in fs::read_dir(&self.volumes_path)? {
             let entry = entry?;
             let path = entry.path();
@@ -28,16 +54,23 @@ impl DeviceDetector {
             if path.is_dir() {
                 // Check if this is a Kobo device
                 if let Some(device) = self.check_kobo_device(&path)? {
-                    return Ok(Some(device));
+                    let database_modified = self
+                        .get_database_path(&device)
+                        .and_then(|db| fs::metadata(db).and_then(|m| m.modified()).ok());
+                    candidates.push(DeviceCandidate {
+                        device,
+                        database_modified,
+                        readable: fs::read_dir(&path).is_ok(),
+                    });
                 }
             }
         }
 
-        Ok(None)
+        Ok(mark_stale_duplicates(candidates))
     }
 
-    /// Check if a volume is a Kobo device
-    fn check_kobo_device(&self, volume_path: &Path) -> Result<Option<KoboDevice>, DeviceError> {
+    /// Check if a volume is a Kobo device (`None` without a `.kobo` folder)
+    pub fn check_kobo_device(&self, volume_path: &Path) -> Result<Option<KoboDevice>, DeviceError> {
         let name = volume_path
             .file_name()
             .and_then(|n| n.to_str())
@@ -55,48 +88,67 @@ impl DeviceDetector {
         let has_sqlite = sqlite_path.exists() && sqlite_path.is_file();
 
         // Try to validate SQLite accessibility
-        let is_valid = if has_sqlite {
-            self.validate_sqlite(&sqlite_path)
+        let invalid_reason = if has_sqlite {
+            self.validate_sqlite(&sqlite_path).err().map(|e| {
+                log::debug!("Rejected {:?}: {}", sqlite_path, e);
+                e.to_string()
+            })
         } else {
-            false
+            Some("KoboReader.sqlite not found".to_string())
         };
 
-        // Try to get serial number from version file
-        let serial_number = self.read_serial_number(&kobo_dir);
+        // Try to get serial number and model from version file
+        let (serial_number, model) = self.read_version(&kobo_dir);
 
         Ok(Some(KoboDevice {
             name,
             path: volume_path.to_string_lossy().to_string(),
-            is_valid,
+            is_valid: invalid_reason.is_none(),
             serial_number,
+            invalid_reason,
+            is_stale: false,
+            model,
+            friendly_name: None,
+            suggested_profile: None,
         }))
     }
 
-    /// Validate that the SQLite database is accessible
-    fn validate_sqlite(&self, sqlite_path: &Path) -> bool {
-        match rusqlite::Connection::open(sqlite_path) {
-            Ok(conn) => {
-                // Try a simple query to verify the database is valid
-                // Use query_row instead of execute for SELECT statements
-                conn.query_row("SELECT 1", [], |_| Ok(())).is_ok()
-            }
-            Err(_) => false,
+    /// Validate that the database is a plausibly sized Kobo database
+    ///
+    /// Opened read-only and only the schema is inspected; the full integrity
+    /// check runs when an import opens it.
+    fn validate_sqlite(&self, sqlite_path: &Path) -> Result<(), KoboDbError> {
+        let size = fs::metadata(sqlite_path)?.len();
+        if size > MAX_DATABASE_BYTES {
+            return Err(KoboDbError::TooLarge(size));
         }
+
+        let conn = rusqlite::Connection::open_with_flags(
+            sqlite_path,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        check_kobo_schema(&conn).map_err(|e| e.check_header(sqlite_path))
     }
 
-    /// Read serial number from .kobo/version
-    fn read_serial_number(&self, kobo_dir: &Path) -> Option<String> {
-        let version_path = kobo_dir.join("version");
-        if version_path.exists() {
-            if let Ok(content) = fs::read_to_string(&version_path) {
-                // The version file typically contains the serial number
-                let trimmed = content.trim();
-                if !trimmed.is_empty() {
-                    return Some(trimmed.to_string());
-                }
-            }
-        }
-        None
+    /// Read serial number and model from .kobo/version
+    ///
+    /// The file is one comma-separated line: serial number first, product
+    /// ID last (`N418…,4.1.15,4.38.21908,…,00000000-0000-0000-0000-000000000386`).
+    /// Older fixtures hold just the serial number.
+    fn read_version(&self, kobo_dir: &Path) -> (Option<String>, Option<String>) {
+        let Ok(content) = fs::read_to_string(kobo_dir.join("version")) else {
+            return (None, None);
+        };
+        let fields: Vec<&str> = content.trim().split(',').map(str::trim).collect();
+        let serial = fields
+            .first()
+            .filter(|serial| !serial.is_empty())
+            .map(|serial| serial.to_string());
+        let model = match fields.as_slice() {
+            [_, .., product] => registry::model_name(product).map(str::to_string),
+            _ => None,
+        };
+        (serial, model)
     }
 
     /// Get the path to the Kobo SQLite database
@@ -111,6 +163,69 @@ impl DeviceDetector {
     }
 }
 
+/// A Kobo volume found by a scan, before duplicates are resolved
+#[derive(Debug, Clone)]
+pub struct DeviceCandidate {
+    pub device: KoboDevice,
+    /// Modification time of `.kobo/KoboReader.sqlite`
+    pub database_modified: Option<SystemTime>,
+    /// Whether the volume's root can be listed
+    pub readable: bool,
+}
+
+/// Mark all but one of the volumes sharing a serial number as stale
+///
+/// macOS can leave a ghost mount (`/Volumes/KOBOeReader 1`) behind after an
+/// unclean eject. Of the volumes with the same serial, the readable one
+/// with the newest database is kept (then a valid one, then the first
+/// found). Volumes without a serial are never compared. Order is kept.
+pub fn mark_stale_duplicates(candidates: Vec<DeviceCandidate>) -> Vec<KoboDevice> {
+    let preference = |c: &DeviceCandidate| (c.readable, c.database_modified, c.device.is_valid);
+    let mut kept: HashMap<&str, usize> = HashMap::new();
+    for (index, candidate) in candidates.iter().enumerate() {
+        let Some(serial) = candidate.device.serial_number.as_deref() else {
+            continue;
+        };
+        kept.entry(serial)
+            .and_modify(|best| {
+                if preference(candidate) > preference(&candidates[*best]) {
+                    *best = index;
+                }
+            })
+            .or_insert(index);
+    }
+
+    let stale: Vec<bool> = candidates
+        .iter()
+        .enumerate()
+        .map(|(index, candidate)| match &candidate.device.serial_number {
+            Some(serial) => kept[serial.as_str()] != index,
+            None => false,
+        })
+        .collect();
+    candidates
+        .into_iter()
+        .zip(stale)
+        .map(|(candidate, is_stale)| {
+            if is_stale {
+                log::warn!(
+                    "[Device] {} is a stale mount of {}",
+                    candidate.device.path,
+                    candidate
+                        .device
+                        .serial_number
+                        .as_deref()
+                        .unwrap_or_default()
+                );
+            }
+            KoboDevice {
+                is_stale,
+                ..candidate.device
+            }
+        })
+        .collect()
+}
+
 #[derive(Debug)]
 pub enum DeviceError {
     Io(std::io::Error),
@@ -150,30 +265,13 @@ impl From<rusqlite::Error> for DeviceError {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixtures;
     use rusqlite::Connection;
     use tempfile::TempDir;
 
     fn create_mock_kobo_device(temp_dir: &Path, name: &str) -> PathBuf {
         let device_path = temp_dir.join(name);
-        let kobo_dir = device_path.join(".kobo");
-        fs::create_dir_all(&kobo_dir).unwrap();
-
-        // Create a valid SQLite database
-        let sqlite_path = kobo_dir.join("KoboReader.sqlite");
-        let conn = Connection::open(&sqlite_path).unwrap();
-        // Create a proper table that allows querying
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS test (id INTEGER PRIMARY KEY)",
-            [],
-        )
-        .unwrap();
-        conn.execute("INSERT INTO test (id) VALUES (1)", [])
-            .unwrap();
-        drop(conn);
-
-        // Create version file with serial number
-        fs::write(kobo_dir.join("version"), "SN12345678").unwrap();
-
+        fixtures::create_kobo_volume(&device_path, "SN12345678", "").unwrap();
         device_path
     }
 
@@ -208,24 +306,82 @@ mod tests {
         assert!(device.is_none());
     }
 
-    #[test]
-    fn test_validate_sqlite_accessibility() {
-        let temp = TempDir::new().unwrap();
-        let device_path = temp.path().join("KOBOeReader");
+    fn create_decoy_device(temp_dir: &Path, name: &str, contents: Option<&[u8]>) -> PathBuf {
+        let device_path = temp_dir.join(name);
         let kobo_dir = device_path.join(".kobo");
         fs::create_dir_all(&kobo_dir).unwrap();
 
-        // Create a valid SQLite database
         let sqlite_path = kobo_dir.join("KoboReader.sqlite");
-        let conn = Connection::open(&sqlite_path).unwrap();
-        conn.execute("CREATE TABLE test (id INTEGER)", []).unwrap();
-        drop(conn);
+        match contents {
+            Some(bytes) => fs::write(&sqlite_path, bytes).unwrap(),
+            None => {
+                // A valid SQLite file without the Kobo tables
+                let conn = Connection::open(&sqlite_path).unwrap();
+                conn.execute("CREATE TABLE test (id INTEGER)", []).unwrap();
+            }
+        }
+        device_path
+    }
+
+    #[test]
+    fn test_validate_sqlite_requires_kobo_schema() {
+        let temp = TempDir::new().unwrap();
+        create_decoy_device(temp.path(), "Backup", None);
 
         let detector = DeviceDetector::new(temp.path().to_path_buf());
-        let device = detector.scan_for_kobo().unwrap();
+        let device = detector.scan_for_kobo().unwrap().unwrap();
+        assert!(!device.is_valid);
+    }
 
-        assert!(device.is_some());
-        assert!(device.unwrap().is_valid);
+    #[test]
+    fn test_validate_sqlite_rejects_garbage_file() {
+        let temp = TempDir::new().unwrap();
+        create_decoy_device(
+            temp.path(),
+            "Stick",
+            Some(b"SQLite format 3\0 but not really"),
+        );
+
+        let detector = DeviceDetector::new(temp.path().to_path_buf());
+        let device = detector.scan_for_kobo().unwrap().unwrap();
+        assert!(!device.is_valid);
+        assert!(device
+            .invalid_reason
+            .unwrap()
+            .starts_with("Corrupt database"));
+    }
+
+    #[test]
+    fn test_encrypted_database_reason() {
+        let temp = TempDir::new().unwrap();
+        let noise: Vec<u8> = (0..4096u32).map(|i| (i * 7919 % 251) as u8).collect();
+        create_decoy_device(temp.path(), "Managed", Some(&noise));
+
+        let detector = DeviceDetector::new(temp.path().to_path_buf());
+        let device = detector.scan_for_kobo().unwrap().unwrap();
+        assert!(!device.is_valid);
+        assert!(device
+            .invalid_reason
+            .unwrap()
+            .starts_with("Database not readable (encrypted?)"));
+
+        let valid = create_mock_kobo_device(temp.path(), "KOBOeReader");
+        let device = detector.check_kobo_device(&valid).unwrap().unwrap();
+        assert!(device.is_valid);
+        assert_eq!(device.invalid_reason, None);
+    }
+
+    #[test]
+    fn test_valid_device_preferred_over_decoy() {
+        let temp = TempDir::new().unwrap();
+        create_decoy_device(temp.path(), "AAA-Decoy", None);
+        create_mock_kobo_device(temp.path(), "KOBOeReader");
+        create_decoy_device(temp.path(), "ZZZ-Decoy", None);
+
+        let detector = DeviceDetector::new(temp.path().to_path_buf());
+        let device = detector.scan_for_kobo().unwrap().unwrap();
+        assert_eq!(device.name, "KOBOeReader");
+        assert!(device.is_valid);
     }
 
     #[test]
@@ -291,4 +447,87 @@ mod tests {
         assert!(device.is_some());
         assert_eq!(device.unwrap().name, "KOBOeReader");
     }
+
+    fn candidate(path: &str, serial: &str, modified_secs: u64, readable: bool) -> DeviceCandidate {
+        DeviceCandidate {
+            device: KoboDevice {
+                name: path.rsplit('/').next().unwrap().to_string(),
+                path: path.to_string(),
+                is_valid: true,
+                serial_number: Some(serial.to_string()),
+                invalid_reason: None,
+                is_stale: false,
+                model: None,
+                friendly_name: None,
+                suggested_profile: None,
+            },
+            database_modified: Some(
+                SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(modified_secs),
+            ),
+            readable,
+        }
+    }
+
+    fn stale_paths(devices: &[KoboDevice]) -> Vec<&str> {
+        devices
+            .iter()
+            .filter(|d| d.is_stale)
+            .map(|d| d.path.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_ghost_mount_is_stale() {
+        let devices = mark_stale_duplicates(vec![
+            candidate("/Volumes/KOBOeReader 1", "SN1", 100, true),
+            candidate("/Volumes/KOBOeReader", "SN1", 200, true),
+        ]);
+        assert_eq!(stale_paths(&devices), vec!["/Volumes/KOBOeReader 1"]);
+        assert_eq!(devices[0].path, "/Volumes/KOBOeReader 1");
+
+        // An unreadable volume loses even with a newer database
+        let devices = mark_stale_duplicates(vec![
+            candidate("/Volumes/KOBOeReader", "SN1", 100, true),
+            candidate("/Volumes/KOBOeReader 1", "SN1", 200, false),
+        ]);
+        assert_eq!(stale_paths(&devices), vec!["/Volumes/KOBOeReader 1"]);
+    }
+
+    #[test]
+    fn test_different_devices_are_both_kept() {
+        let mut unknown = candidate("/Volumes/KOBO", "SN3", 100, true);
+        unknown.device.serial_number = None;
+        let devices = mark_stale_duplicates(vec![
+            candidate("/Volumes/KOBOeReader", "SN1", 100, true),
+            candidate("/Volumes/KOBOeReader 1", "SN2", 200, true),
+            unknown.clone(),
+            unknown,
+        ]);
+        assert_eq!(devices.len(), 4);
+        assert!(stale_paths(&devices).is_empty());
+    }
+
+    #[test]
+    fn test_scan_skips_stale_mount() {
+        let temp = TempDir::new().unwrap();
+        let ghost = create_mock_kobo_device(temp.path(), "KOBOeReader 1");
+        create_mock_kobo_device(temp.path(), "KOBOeReader");
+        let outdated = SystemTime::now() - std::time::Duration::from_secs(3600);
+        fs::File::options()
+            .write(true)
+            .open(ghost.join(".kobo/KoboReader.sqlite"))
+            .unwrap()
+            .set_modified(outdated)
+            .unwrap();
+
+        let detector = DeviceDetector::new(temp.path().to_path_buf());
+        let devices = detector.scan_for_devices().unwrap();
+        assert_eq!(devices.len(), 2);
+        let ghost_path = ghost.to_string_lossy().to_string();
+        assert_eq!(stale_paths(&devices), vec![ghost_path.as_str()]);
+        assert_eq!(
+            detector.scan_for_kobo().unwrap().unwrap().name,
+            "KOBOeReader"
+        );
+    }
 }
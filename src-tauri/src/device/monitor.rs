@@ -1,11 +1,13 @@
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
-use tauri::{AppHandle, Emitter};
+use tauri::{AppHandle, Emitter, Manager};
 
+use crate::device::registry::join_registry;
 use crate::device::DeviceDetector;
 use crate::models::KoboDevice;
+use crate::profiles::{suggest_profile, ProfileState};
+use crate::settings::{SettingsState, DEFAULT_DEVICE_SETTLE_SCANS};
 
 /// Event emitted when a device is detected
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
@@ -31,12 +33,11 @@ impl DeviceMonitor {
     /// Start monitoring for device changes (polling every 2 seconds)
     /// Uses std::thread instead of tokio to avoid runtime dependency issues
     pub fn start_monitoring(self) {
-        // Use Arc<Mutex<>> for thread-safe shared state
-        let last_device = Arc::new(Mutex::new(None::<KoboDevice>));
         let app_handle = self.app_handle.clone();
 
         thread::spawn(move || {
             log::info!("[DeviceMonitor] Starting device monitoring thread (2s interval)");
+            let mut state = MonitorState::new(device_settle_scans(&app_handle), DEPARTURE_SCANS);
 
             loop {
                 // Sleep at the start of each iteration
@@ -45,61 +46,44 @@ impl DeviceMonitor {
                 let volumes_path = PathBuf::from("/Volumes");
                 let detector = DeviceDetector::new(volumes_path);
 
-                match detector.scan_for_kobo() {
-                    Ok(current_device) => {
-                        let mut last = last_device.lock().unwrap();
+                // Volumes failing schema validation are hidden unless opted in
+                let show_invalid = show_invalid_devices(&app_handle);
+                let scan = detector
+                    .scan_for_kobo()
+                    .map(|device| device.filter(|d| d.is_valid || show_invalid));
 
-                        match (&*last, &current_device) {
-                            // Device connected (first detection)
-                            (None, Some(device)) => {
+                match scan {
+                    Ok(current_device) => {
+                        state.set_settle_scans(device_settle_scans(&app_handle));
+                        match state.observe(current_device) {
+                            Some(MonitorEvent::Detected(mut device)) => {
+                                if let Some(settings) = app_handle.try_state::<SettingsState>() {
+                                    join_registry(&settings, &mut device);
+                                }
+                                if let Some(profiles) = app_handle.try_state::<ProfileState>() {
+                                    suggest_profile(&profiles, &mut device);
+                                }
                                 log::info!(
                                     "[DeviceMonitor] Device connected: {} at {}",
                                     device.name,
                                     device.path
                                 );
-                                let event = DeviceDetectedEvent {
-                                    device: device.clone(),
-                                };
+                                let event = DeviceDetectedEvent { device };
                                 if let Err(e) = app_handle.emit("device-detected", event) {
                                     log::error!(
                                         "[DeviceMonitor] Failed to emit device-detected event: {}",
                                         e
                                     );
                                 }
-                                *last = Some(device.clone());
                             }
-                            // Device disconnected
-                            (Some(_), None) => {
+                            Some(MonitorEvent::Disconnected) => {
                                 log::info!("[DeviceMonitor] Device disconnected");
                                 let event = DeviceDisconnectedEvent;
                                 if let Err(e) = app_handle.emit("device-disconnected", event) {
                                     log::error!("[DeviceMonitor] Failed to emit device-disconnected event: {}", e);
                                 }
-                                *last = None;
-                            }
-                            // Same device still connected - no event needed
-                            (Some(last_dev), Some(current_dev)) => {
-                                if last_dev.path != current_dev.path
-                                    || last_dev.serial_number != current_dev.serial_number
-                                {
-                                    // Different device connected
-                                    log::info!(
-                                        "[DeviceMonitor] Device changed: {} at {}",
-                                        current_dev.name,
-                                        current_dev.path
-                                    );
-                                    let event = DeviceDetectedEvent {
-                                        device: current_dev.clone(),
-                                    };
-                                    if let Err(e) = app_handle.emit("device-detected", event) {
-                                        log::error!("[DeviceMonitor] Failed to emit device-detected event: {}", e);
-                                    }
-                                    *last = Some(current_dev.clone());
-                                }
-                                // Same device, do nothing
                             }
-                            // No device connected, no change
-                            (None, None) => {}
+                            None => {}
                         }
                     }
                     Err(e) => {
@@ -113,33 +97,160 @@ impl DeviceMonitor {
     }
 }
 
+/// Consecutive empty scans before a present device counts as disconnected
+pub const DEPARTURE_SCANS: u32 = 2;
+
+/// Device presence as tracked between scans
+#[derive(Debug, Clone, PartialEq)]
+pub enum Presence {
+    /// No device
+    Absent,
+    /// Seen for `scans` consecutive scans, not announced yet
+    Settling { device: KoboDevice, scans: u32 },
+    /// Announced with "device-detected"
+    Present(KoboDevice),
+    /// Announced device missing for `misses` consecutive scans
+    Departing { device: KoboDevice, misses: u32 },
+}
+
+/// Event the monitor should emit after a scan
+#[derive(Debug, Clone, PartialEq)]
+pub enum MonitorEvent {
+    Detected(KoboDevice),
+    Disconnected,
+}
+
+/// Hysteresis over raw scan results
+///
+/// macOS may mount and unmount the volume several times right after it is
+/// plugged in; a device is only announced once it stayed present for
+/// `settle_scans` scans, and only reported gone after `departure_scans`
+/// empty scans, so a flap never produces an event pair.
+#[derive(Debug, Clone)]
+pub struct MonitorState {
+    presence: Presence,
+    settle_scans: u32,
+    departure_scans: u32,
+}
+
+impl MonitorState {
+    pub fn new(settle_scans: u32, departure_scans: u32) -> Self {
+        Self {
+            presence: Presence::Absent,
+            settle_scans,
+            departure_scans,
+        }
+    }
+
+    pub fn presence(&self) -> &Presence {
+        &self.presence
+    }
+
+    /// Scans required before announcing a new device (0 and 1 are immediate)
+    pub fn set_settle_scans(&mut self, settle_scans: u32) {
+        self.settle_scans = settle_scans;
+    }
+
+    /// Feed one scan result; returns the event to emit, if any
+    pub fn observe(&mut self, scan: Option<KoboDevice>) -> Option<MonitorEvent> {
+        let presence = std::mem::replace(&mut self.presence, Presence::Absent);
+        let (presence, event) = match (presence, scan) {
+            (Presence::Absent, None) => (Presence::Absent, None),
+            (Presence::Absent, Some(device)) => self.settle(device, 1),
+            (Presence::Settling { device, scans }, Some(current)) => {
+                if same_device(&device, &current) {
+                    self.settle(current, scans + 1)
+                } else {
+                    self.settle(current, 1)
+                }
+            }
+            // Gone before settling: the detection is dropped silently
+            (Presence::Settling { .. }, None) => (Presence::Absent, None),
+            (Presence::Present(device), Some(current)) => {
+                if same_device(&device, &current) {
+                    (Presence::Present(device), None)
+                } else {
+                    // A different device replaced it
+                    (
+                        Presence::Present(current.clone()),
+                        Some(MonitorEvent::Detected(current)),
+                    )
+                }
+            }
+            (Presence::Present(device), None) => self.depart(device, 1),
+            (Presence::Departing { device, .. }, Some(current)) => {
+                if same_device(&device, &current) {
+                    (Presence::Present(device), None)
+                } else {
+                    (
+                        Presence::Present(current.clone()),
+                        Some(MonitorEvent::Detected(current)),
+                    )
+                }
+            }
+            (Presence::Departing { device, misses }, None) => self.depart(device, misses + 1),
+        };
+        self.presence = presence;
+        event
+    }
+
+    fn settle(&self, device: KoboDevice, scans: u32) -> (Presence, Option<MonitorEvent>) {
+        if scans >= self.settle_scans {
+            (
+                Presence::Present(device.clone()),
+                Some(MonitorEvent::Detected(device)),
+            )
+        } else {
+            (Presence::Settling { device, scans }, None)
+        }
+    }
+
+    fn depart(&self, device: KoboDevice, misses: u32) -> (Presence, Option<MonitorEvent>) {
+        if misses >= self.departure_scans {
+            (Presence::Absent, Some(MonitorEvent::Disconnected))
+        } else {
+            (Presence::Departing { device, misses }, None)
+        }
+    }
+}
+
+fn same_device(a: &KoboDevice, b: &KoboDevice) -> bool {
+    a.path == b.path && a.serial_number == b.serial_number
+}
+
+/// Scans a new device must stay present before it is announced
+fn device_settle_scans(app_handle: &AppHandle) -> u32 {
+    app_handle
+        .try_state::<SettingsState>()
+        .and_then(|state| {
+            state
+                .with_manager(|manager| Ok(manager.get().ui_preferences.device_settle_scans))
+                .ok()
+        })
+        .unwrap_or(DEFAULT_DEVICE_SETTLE_SCANS)
+}
+
+/// Whether devices failing schema validation should still be reported
+fn show_invalid_devices(app_handle: &AppHandle) -> bool {
+    app_handle
+        .try_state::<SettingsState>()
+        .and_then(|state| {
+            state
+                .with_manager(|manager| Ok(manager.get().ui_preferences.show_invalid_devices))
+                .ok()
+        })
+        .unwrap_or(false)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rusqlite::Connection;
     use std::fs;
     use tempfile::TempDir;
 
     fn create_mock_kobo_device(temp_dir: &std::path::Path, name: &str) -> PathBuf {
         let device_path = temp_dir.join(name);
-        let kobo_dir = device_path.join(".kobo");
-        fs::create_dir_all(&kobo_dir).unwrap();
-
-        // Create a valid SQLite database
-        let sqlite_path = kobo_dir.join("KoboReader.sqlite");
-        let conn = Connection::open(&sqlite_path).unwrap();
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS test (id INTEGER PRIMARY KEY)",
-            [],
-        )
-        .unwrap();
-        conn.execute("INSERT INTO test (id) VALUES (1)", [])
-            .unwrap();
-        drop(conn);
-
-        // Create version file with serial number
-        fs::write(kobo_dir.join("version"), "SN12345678").unwrap();
-
+        crate::fixtures::create_kobo_volume(&device_path, "SN12345678", "").unwrap();
         device_path
     }
 
@@ -151,6 +262,11 @@ mod tests {
             path: "/Volumes/KOBOeReader".to_string(),
             is_valid: true,
             serial_number: Some("SN12345678".to_string()),
+            invalid_reason: None,
+            is_stale: false,
+            model: None,
+            friendly_name: None,
+            suggested_profile: None,
         };
 
         let event = DeviceDetectedEvent { device };
@@ -174,6 +290,96 @@ mod tests {
         assert!(json == "{}" || json == "null" || json.is_empty());
     }
 
+    fn device(serial: &str) -> KoboDevice {
+        KoboDevice {
+            name: "KOBOeReader".to_string(),
+            path: "/Volumes/KOBOeReader".to_string(),
+            is_valid: true,
+            serial_number: Some(serial.to_string()),
+            invalid_reason: None,
+            is_stale: false,
+            model: None,
+            friendly_name: None,
+            suggested_profile: None,
+        }
+    }
+
+    fn run(state: &mut MonitorState, scans: &[Option<KoboDevice>]) -> Vec<Option<MonitorEvent>> {
+        scans
+            .iter()
+            .map(|scan| state.observe(scan.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_monitor_clean_connect() {
+        let mut state = MonitorState::new(2, DEPARTURE_SCANS);
+        let kobo = device("SN1");
+
+        let events = run(&mut state, &[None, Some(kobo.clone()), Some(kobo.clone())]);
+
+        assert_eq!(
+            events,
+            vec![None, None, Some(MonitorEvent::Detected(kobo.clone()))]
+        );
+        assert_eq!(state.presence(), &Presence::Present(kobo));
+    }
+
+    #[test]
+    fn test_monitor_flapping_mount_fires_once() {
+        let mut state = MonitorState::new(2, DEPARTURE_SCANS);
+        let kobo = Some(device("SN1"));
+
+        let events = run(
+            &mut state,
+            &[
+                kobo.clone(),
+                None,
+                kobo.clone(),
+                None,
+                kobo.clone(),
+                kobo.clone(),
+            ],
+        );
+
+        // Each flap cancels the pending detection without any event
+        assert_eq!(&events[..5], &[None, None, None, None, None]);
+        assert_eq!(events[5], Some(MonitorEvent::Detected(device("SN1"))));
+
+        // A single missed scan while present is not a disconnect either
+        let events = run(&mut state, &[None, kobo.clone(), kobo.clone()]);
+        assert_eq!(events, vec![None, None, None]);
+        assert_eq!(state.presence(), &Presence::Present(device("SN1")));
+    }
+
+    #[test]
+    fn test_monitor_clean_disconnect() {
+        let mut state = MonitorState::new(1, DEPARTURE_SCANS);
+        let kobo = device("SN1");
+
+        let events = run(&mut state, &[Some(kobo.clone()), None, None, None]);
+
+        assert_eq!(
+            events,
+            vec![
+                Some(MonitorEvent::Detected(kobo.clone())),
+                None,
+                Some(MonitorEvent::Disconnected),
+                None
+            ]
+        );
+        assert_eq!(state.presence(), &Presence::Absent);
+    }
+
+    #[test]
+    fn test_monitor_device_swap_is_announced() {
+        let mut state = MonitorState::new(2, DEPARTURE_SCANS);
+        run(&mut state, &[Some(device("SN1")), Some(device("SN1"))]);
+
+        let events = run(&mut state, &[Some(device("SN2"))]);
+        assert_eq!(events, vec![Some(MonitorEvent::Detected(device("SN2")))]);
+    }
+
     #[test]
     fn test_device_detector_finds_mock_device() {
         let temp = TempDir::new().unwrap();
@@ -0,0 +1,197 @@
+//! Devices seen before, keyed by serial number
+//!
+//! Every Kobo mounts as "KOBOeReader", so two identical readers can't be
+//! told apart by their volume name. The first scan of a serial records it
+//! in the settings with its model and a friendly name the user can change;
+//! scans then attach that name to the `KoboDevice` they report.
+
+use crate::models::KoboDevice;
+use crate::settings::SettingsState;
+use serde::{Deserialize, Serialize};
+
+/// Kobo product IDs (last field of `.kobo/version`) and their names
+const MODELS: &[(u32, &str)] = &[
+    (371, "Kobo Glo HD"),
+    (372, "Kobo Touch 2.0"),
+    (373, "Kobo Aura ONE"),
+    (374, "Kobo Aura H2O Edition 2"),
+    (375, "Kobo Aura Edition 2"),
+    (376, "Kobo Clara HD"),
+    (377, "Kobo Forma"),
+    (380, "Kobo Forma"),
+    (382, "Kobo Nia"),
+    (383, "Kobo Sage"),
+    (384, "Kobo Libra H2O"),
+    (386, "Kobo Clara 2E"),
+    (387, "Kobo Elipsa"),
+    (388, "Kobo Libra 2"),
+    (389, "Kobo Elipsa 2E"),
+    (390, "Kobo Libra Colour"),
+];
+
+/// Model of unrecognized devices in friendly names
+const UNKNOWN_MODEL: &str = "Kobo";
+
+/// A device in the registry
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct KnownDevice {
+    pub serial: String,
+    /// Model name, if it was recognized
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Friendly name shown instead of the volume name
+    pub name: String,
+    /// RFC 3339 timestamp of the first scan
+    #[serde(alias = "first_seen")]
+    pub first_seen: String,
+}
+
+impl KnownDevice {
+    /// Registry entry for a device seen for the first time
+    pub fn first_seen(serial: &str, model: Option<String>) -> Self {
+        Self {
+            serial: serial.to_string(),
+            name: default_device_name(model.as_deref(), serial),
+            model,
+            first_seen: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+/// Model name of a Kobo product ID (`00000000-0000-0000-0000-000000000386`)
+pub fn model_name(product_id: &str) -> Option<&'static str> {
+    let number: u32 = product_id.rsplit('-').next()?.parse().ok()?;
+    MODELS
+        .iter()
+        .find(|(id, _)| *id == number)
+        .map(|(_, name)| *name)
+}
+
+/// Model followed by the last 4 characters of the serial ("Kobo Clara 2E 6789")
+pub fn default_device_name(model: Option<&str>, serial: &str) -> String {
+    let chars: Vec<char> = serial.chars().collect();
+    let suffix: String = chars[chars.len().saturating_sub(4)..].iter().collect();
+    format!("{} {}", model.unwrap_or(UNKNOWN_MODEL), suffix)
+}
+
+/// Record `device` in the registry on its first scan and attach its
+/// friendly name; devices without a serial are left as they are
+pub fn join_registry(state: &SettingsState, device: &mut KoboDevice) {
+    if device.serial_number.is_none() {
+        return;
+    }
+    match state.with_manager(|manager| manager.register_device(device)) {
+        Ok(Some(known)) => device.friendly_name = Some(known.name),
+        Ok(None) => {}
+        Err(e) => log::warn!("[Device] Registo de dispositivos indisponível: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::DeviceDetector;
+    use crate::settings::{LastImportRecord, SettingsManager};
+    use tempfile::TempDir;
+
+    const CLARA_VERSION: &str =
+        "N418000006789,4.1.15,4.38.21908,4.1.15,4.1.15,00000000-0000-0000-0000-000000000386";
+
+    fn scan(volumes: &TempDir, state: &SettingsState) -> KoboDevice {
+        let mut device = DeviceDetector::new(volumes.path().to_path_buf())
+            .scan_for_kobo()
+            .unwrap()
+            .unwrap();
+        join_registry(state, &mut device);
+        device
+    }
+
+    #[test]
+    fn test_model_names() {
+        assert_eq!(
+            model_name("00000000-0000-0000-0000-000000000386"),
+            Some("Kobo Clara 2E")
+        );
+        assert_eq!(model_name("00000000-0000-0000-0000-000000000999"), None);
+        assert_eq!(model_name("garbage"), None);
+        assert_eq!(default_device_name(None, "N41"), "Kobo N41");
+    }
+
+    #[test]
+    fn test_detect_rename_and_forget() {
+        let volumes = TempDir::new().unwrap();
+        crate::fixtures::create_kobo_volume(&volumes.path().join("KOBOeReader"), CLARA_VERSION, "")
+            .unwrap();
+        let config = TempDir::new().unwrap();
+        let settings_path = config.path().join("settings.json");
+        let state =
+            SettingsState::from_manager(SettingsManager::with_path(settings_path.clone()).unwrap());
+
+        // First scan registers the device, the second finds it again
+        let first = scan(&volumes, &state);
+        assert_eq!(first.serial_number.as_deref(), Some("N418000006789"));
+        assert_eq!(first.model.as_deref(), Some("Kobo Clara 2E"));
+        assert_eq!(first.friendly_name.as_deref(), Some("Kobo Clara 2E 6789"));
+        let registered = state
+            .with_manager(|m| Ok(m.get().known_devices["N418000006789"].clone()))
+            .unwrap();
+        let second = scan(&volumes, &state);
+        assert_eq!(second.friendly_name, first.friendly_name);
+        let known = state
+            .with_manager(|m| Ok(m.get().known_devices.clone()))
+            .unwrap();
+        assert_eq!(known.len(), 1);
+        assert_eq!(known["N418000006789"], registered);
+
+        // Renamed, across a restart
+        state
+            .with_manager(|m| m.rename_device("N418000006789", "  Clara da Ana "))
+            .unwrap();
+        let restarted =
+            SettingsState::from_manager(SettingsManager::with_path(settings_path).unwrap());
+        assert_eq!(
+            scan(&volumes, &restarted).friendly_name.as_deref(),
+            Some("Clara da Ana")
+        );
+        assert!(restarted
+            .with_manager(|m| m.rename_device("unknown", "x"))
+            .is_err());
+        // A blank name goes back to the default
+        restarted
+            .with_manager(|m| m.rename_device("N418000006789", " "))
+            .unwrap();
+        assert_eq!(
+            scan(&volumes, &restarted).friendly_name.as_deref(),
+            Some("Kobo Clara 2E 6789")
+        );
+
+        // Forgetting clears its import history too
+        restarted
+            .with_manager(|m| {
+                m.set_last_import(LastImportRecord {
+                    timestamp: "2025-03-01T10:00:00Z".to_string(),
+                    device_id: Some("N418000006789".to_string()),
+                    books_count: 3,
+                    highlights_count: 10,
+                    duration_ms: 0,
+                    warnings_count: 0,
+                    warnings: Vec::new(),
+                    filtered_count: 0,
+                    metrics: Default::default(),
+                    schema: None,
+                })
+            })
+            .unwrap();
+        restarted
+            .with_manager(|m| m.forget_device("N418000006789"))
+            .unwrap();
+        let settings = restarted.with_manager(|m| Ok(m.get().clone())).unwrap();
+        assert!(settings.known_devices.is_empty());
+        assert!(settings.device_imports.is_empty());
+        assert!(settings.last_import.is_none());
+        assert!(restarted
+            .with_manager(|m| m.forget_device("N418000006789"))
+            .is_err());
+    }
+}
@@ -0,0 +1,788 @@
+//! Adopting a folder of exports written by another tool
+//!
+//! People coming from October, Kobo export scripts or Calibre's annotation
+//! plugin already have a folder of markdown or JSON files. `adopt_exports`
+//! matches each file to a library book, by the title (and author) it
+//! declares in its frontmatter or JSON, by a "Title - Author" filename, or
+//! else by how many of the book's highlights its text contains, and returns
+//! an `AdoptionReport` with the confidence of each match.
+//!
+//! Only with `AdoptionOptions::confirm` are the matches recorded: in the
+//! append-mode manifest (the highlights found in the file, its hash, slug
+//! and, as `adopted`, its path) and in the library's export tracking. No
+//! adopted file is written by adoption; later append-mode exports add new
+//! highlights to it, whatever its name. Ambiguous and unmatched files are
+//! listed and left alone.
+
+use super::append::{self, highlight_key, manifest_key};
+use super::sidecar::SIDECAR_EXTENSION;
+use super::verify::{files_with_extensions, hash_file};
+use crate::library::{LibraryError, LibraryStore};
+use crate::models::Book;
+use crate::utils::slug::book_slug;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Extensions of the files considered for adoption
+const ADOPTED_EXTENSIONS: &[&str] = &["md", "markdown", "txt", "json"];
+
+/// Share of a book's highlights a file must contain to match on content
+const MIN_FINGERPRINT_SHARE: f64 = 0.5;
+
+/// Share of a book's highlights that makes a content match likely
+const STRONG_FINGERPRINT_SHARE: f64 = 0.8;
+
+/// How a file was matched to its book
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMethod {
+    /// Title in the YAML frontmatter or the JSON
+    Frontmatter,
+    /// "Title - Author" (or just the title) as the filename
+    Filename,
+    /// The book's highlights appear in the file
+    Fingerprint,
+}
+
+/// How sure a match is, from least to most
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchConfidence {
+    #[default]
+    Low,
+    Medium,
+    High,
+}
+
+/// What adoption records
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AdoptionOptions {
+    /// Matches less sure than this are reported but not adopted
+    #[serde(default, alias = "min_confidence")]
+    pub min_confidence: MatchConfidence,
+    /// Record the matches; without it only the report is returned
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+/// A file matched to a library book
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AdoptionMatch {
+    /// Path relative to the export folder
+    pub path: String,
+    pub content_id: String,
+    pub title: String,
+    pub slug: String,
+    pub method: MatchMethod,
+    pub confidence: MatchConfidence,
+    /// Highlights of the book found in the file, out of `highlights`
+    pub highlights_found: usize,
+    pub highlights: usize,
+    /// Whether the match is (or, in a dry run, would be) recorded
+    pub adopted: bool,
+}
+
+/// A book a file could belong to
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AdoptionCandidate {
+    pub content_id: String,
+    pub title: String,
+    pub author: String,
+}
+
+/// A file that matches several books equally well
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AmbiguousFile {
+    pub path: String,
+    pub candidates: Vec<AdoptionCandidate>,
+}
+
+/// Every file of the folder and what adoption makes of it, sorted by path
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AdoptionReport {
+    /// Whether the matches were recorded
+    pub confirmed: bool,
+    pub matches: Vec<AdoptionMatch>,
+    pub ambiguous: Vec<AmbiguousFile>,
+    /// Files that match no book
+    pub unmatched: Vec<String>,
+    /// Files the manifest already lists, skipped
+    pub tracked: Vec<String>,
+    /// Highlights marked as exported
+    pub highlights: usize,
+}
+
+/// Match the files of `export_dir` to the books of `store` and, with
+/// `options.confirm`, record the matches as khi exports
+pub fn adopt_exports(
+    store: &mut LibraryStore,
+    export_dir: &Path,
+    options: &AdoptionOptions,
+) -> Result<AdoptionReport, LibraryError> {
+    let books = store.books()?;
+    let passages: Vec<Vec<Vec<String>>> = books
+        .iter()
+        .map(|book| book.highlights.iter().map(|h| words(&h.text)).collect())
+        .collect();
+    let mut manifest = append::load_manifest(export_dir);
+    let mut report = AdoptionReport::default();
+    let mut found_in: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut files = files_with_extensions(export_dir, ADOPTED_EXTENSIONS)?;
+    files.sort();
+
+    for path in files {
+        let key = manifest_key(export_dir, &path);
+        if key.ends_with(&format!(".{}", SIDECAR_EXTENSION)) {
+            continue;
+        }
+        if manifest.files.contains_key(&key) {
+            report.tracked.push(key);
+            continue;
+        }
+        match match_file(&path, &books, &passages)? {
+            FileMatch::Book(found) => {
+                let book = &books[found.book];
+                found_in.insert(key.clone(), found.highlights.clone());
+                report.matches.push(AdoptionMatch {
+                    path: key,
+                    content_id: book.content_id.clone(),
+                    title: book.title.clone(),
+                    slug: if book.slug.is_empty() {
+                        book_slug(&book.content_id, &book.title)
+                    } else {
+                        book.slug.clone()
+                    },
+                    method: found.method,
+                    confidence: found.confidence,
+                    highlights_found: found.highlights.len(),
+                    highlights: book.highlights.len(),
+                    adopted: found.confidence >= options.min_confidence,
+                });
+            }
+            FileMatch::Ambiguous(candidates) => report.ambiguous.push(AmbiguousFile {
+                path: key,
+                candidates: candidates
+                    .into_iter()
+                    .map(|i| AdoptionCandidate {
+                        content_id: books[i].content_id.clone(),
+                        title: books[i].title.clone(),
+                        author: books[i].author.clone(),
+                    })
+                    .collect(),
+            }),
+            FileMatch::None => report.unmatched.push(key),
+        }
+    }
+
+    let adopted: Vec<&AdoptionMatch> = report.matches.iter().filter(|m| m.adopted).collect();
+    report.highlights = adopted.iter().map(|m| m.highlights_found).sum();
+    if !options.confirm {
+        return Ok(report);
+    }
+
+    let mut marks: Vec<(String, String)> = Vec::new();
+    for adoption in &adopted {
+        let book = books
+            .iter()
+            .find(|b| b.content_id == adoption.content_id)
+            .expect("matched books come from the library");
+        let path = export_dir.join(&adoption.path);
+        // Only the highlights the file holds count as exported
+        let found = &found_in[&adoption.path];
+        let mut keys: Vec<String> = found
+            .iter()
+            .map(|&i| highlight_key(&book.highlights[i]).to_string())
+            .collect();
+        keys.sort();
+        let key = &adoption.path;
+        manifest.stale.remove(key);
+        manifest.hashes.insert(key.clone(), hash_file(&path)?);
+        manifest.books.insert(key.clone(), adoption.slug.clone());
+        manifest.adopted.insert(adoption.slug.clone(), key.clone());
+        manifest.files.insert(key.clone(), keys);
+        marks.extend(
+            found
+                .iter()
+                .map(|&i| (book.highlights[i].stable_id.clone(), key.clone())),
+        );
+    }
+    store.mark_exported_many(&marks)?;
+    append::save_manifest(export_dir, &manifest)?;
+    log::info!(
+        "[EXPORTER] {} ficheiro(s) adotado(s) em {:?}",
+        adopted.len(),
+        export_dir
+    );
+    report.confirmed = true;
+    Ok(report)
+}
+
+/// The book a file was matched to, by index
+struct FoundBook {
+    book: usize,
+    method: MatchMethod,
+    confidence: MatchConfidence,
+    /// Indices of the book's highlights found in the file
+    highlights: Vec<usize>,
+}
+
+enum FileMatch {
+    Book(FoundBook),
+    /// Indices of the books that match equally well
+    Ambiguous(Vec<usize>),
+    None,
+}
+
+/// Match one file against `books`, whose highlight words are `passages`
+fn match_file(
+    path: &Path,
+    books: &[Book],
+    passages: &[Vec<Vec<String>>],
+) -> std::io::Result<FileMatch> {
+    let bytes = fs::read(path)?;
+    let raw = String::from_utf8_lossy(&bytes);
+    let raw = raw.trim_start_matches('\u{feff}');
+    let is_json = path.extension().is_some_and(|ext| ext == "json");
+    let json = if is_json {
+        serde_json::from_str::<serde_json::Value>(raw).ok()
+    } else {
+        None
+    };
+    let text = match &json {
+        Some(value) => json_strings(value).join("\n"),
+        None => raw.to_string(),
+    };
+    let declared = match &json {
+        Some(value) => json_title(value),
+        None => frontmatter_title(raw),
+    };
+
+    let file_words = words(&text);
+    let fingerprint = Fingerprint::new(&file_words);
+    let found = |i: usize| -> Vec<usize> {
+        passages[i]
+            .iter()
+            .enumerate()
+            .filter(|(_, passage)| fingerprint.contains(passage))
+            .map(|(index, _)| index)
+            .collect()
+    };
+    let share = |i: usize, found: usize| match books[i].highlights.len() {
+        0 => 0.0,
+        total => found as f64 / total as f64,
+    };
+
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (method, named) = match declared {
+        Some((title, author)) => (
+            MatchMethod::Frontmatter,
+            books_by_title(books, &title, author.as_deref()),
+        ),
+        None => (MatchMethod::Filename, books_by_filename(books, &stem)),
+    };
+
+    if !named.is_empty() {
+        // Books whose author matched too win over title-only matches
+        let with_author: Vec<usize> = named.iter().filter(|(_, a)| *a).map(|(i, _)| *i).collect();
+        let (candidates, author_matched) = if with_author.is_empty() {
+            (named.iter().map(|(i, _)| *i).collect(), false)
+        } else {
+            (with_author, true)
+        };
+        let mut scored: Vec<(usize, Vec<usize>)> =
+            candidates.iter().map(|&i| (i, found(i))).collect();
+        let best = scored.iter().map(|(_, f)| f.len()).max().unwrap_or(0);
+        scored.retain(|(_, f)| f.len() == best);
+        if scored.len() > 1 {
+            return Ok(FileMatch::Ambiguous(
+                scored.into_iter().map(|(i, _)| i).collect(),
+            ));
+        }
+        let (book, highlights) = scored.remove(0);
+        // A name alone is never enough to be sure
+        let confidence =
+            if best > 0 && (author_matched || share(book, best) >= MIN_FINGERPRINT_SHARE) {
+                MatchConfidence::High
+            } else {
+                MatchConfidence::Medium
+            };
+        return Ok(FileMatch::Book(FoundBook {
+            book,
+            method,
+            confidence,
+            highlights,
+        }));
+    }
+
+    let mut scored: Vec<(usize, Vec<usize>, f64)> = (0..books.len())
+        .map(|i| {
+            let f = found(i);
+            let s = share(i, f.len());
+            (i, f, s)
+        })
+        .filter(|(_, _, share)| *share >= MIN_FINGERPRINT_SHARE)
+        .collect();
+    let Some(best) = scored.iter().map(|(_, _, s)| *s).reduce(f64::max) else {
+        return Ok(FileMatch::None);
+    };
+    scored.retain(|(_, _, s)| *s == best);
+    if scored.len() > 1 {
+        return Ok(FileMatch::Ambiguous(
+            scored.into_iter().map(|(i, _, _)| i).collect(),
+        ));
+    }
+    let (book, highlights, share) = scored.remove(0);
+    Ok(FileMatch::Book(FoundBook {
+        book,
+        method: MatchMethod::Fingerprint,
+        confidence: if share >= STRONG_FINGERPRINT_SHARE {
+            MatchConfidence::Medium
+        } else {
+            MatchConfidence::Low
+        },
+        highlights,
+    }))
+}
+
+/// Books titled `title`, each with whether `author` matched it too
+fn books_by_title(books: &[Book], title: &str, author: Option<&str>) -> Vec<(usize, bool)> {
+    let title = match_key(title);
+    if title.is_empty() {
+        return Vec::new();
+    }
+    let author = author.map(match_key).filter(|a| !a.is_empty());
+    books
+        .iter()
+        .enumerate()
+        .filter(|(_, book)| match_key(&book.title) == title)
+        .map(|(i, book)| {
+            let matched = author.as_ref().is_some_and(|author| {
+                match_key(&book.author) == *author
+                    || book.authors.iter().any(|a| match_key(a) == *author)
+            });
+            (i, matched)
+        })
+        .collect()
+}
+
+/// Books named by a filename stem: "Title - Author", "Author - Title" (both
+/// matching the author) or just the title
+///
+/// Punctuation is ignored, so titles khi or another tool sanitized (":"
+/// written as " -") still match. A trailing collision suffix like " (2)" is
+/// dropped.
+fn books_by_filename(books: &[Book], stem: &str) -> Vec<(usize, bool)> {
+    let stem = match stem.rsplit_once(" (") {
+        Some((base, suffix))
+            if suffix
+                .strip_suffix(')')
+                .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())) =>
+        {
+            base
+        }
+        _ => stem,
+    };
+    let key = match_key(stem);
+    if key.is_empty() {
+        return Vec::new();
+    }
+    books
+        .iter()
+        .enumerate()
+        .filter_map(|(i, book)| {
+            let title = match_key(&book.title);
+            let author = match_key(&book.author);
+            if key == format!("{} {}", title, author) || key == format!("{} {}", author, title) {
+                Some((i, true))
+            } else if key == title {
+                Some((i, false))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// `title:` and `author:` of a markdown file's YAML frontmatter
+fn frontmatter_title(text: &str) -> Option<(String, Option<String>)> {
+    let mut lines = text.lines();
+    if lines.next()?.trim_end() != "---" {
+        return None;
+    }
+    let mut title = None;
+    let mut author = None;
+    for line in lines {
+        if line.trim_end() == "---" {
+            break;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim().trim_matches(|c| c == '"' || c == '\'').trim();
+        if value.is_empty() {
+            continue;
+        }
+        match key.trim().to_lowercase().as_str() {
+            "title" => title = Some(value.to_string()),
+            "author" | "authors" => author = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    title.map(|title| (title, author))
+}
+
+/// `title` and `author` (or the first of `authors`) of a JSON object
+fn json_title(value: &serde_json::Value) -> Option<(String, Option<String>)> {
+    let title = value.get("title")?.as_str()?.to_string();
+    let author = match value.get("author").or_else(|| value.get("authors")) {
+        Some(serde_json::Value::String(author)) => Some(author.clone()),
+        Some(serde_json::Value::Array(authors)) => {
+            authors.first().and_then(|a| a.as_str()).map(str::to_string)
+        }
+        _ => None,
+    };
+    Some((title, author))
+}
+
+/// Every string of a JSON document, in order
+fn json_strings(value: &serde_json::Value) -> Vec<&str> {
+    match value {
+        serde_json::Value::String(s) => vec![s.as_str()],
+        serde_json::Value::Array(items) => items.iter().flat_map(json_strings).collect(),
+        serde_json::Value::Object(fields) => fields.values().flat_map(json_strings).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Lowercase words of `text`, without punctuation or markup
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// `text` as lowercase words joined by single spaces, to compare titles and
+/// authors however they were punctuated
+fn match_key(text: &str) -> String {
+    words(text).join(" ")
+}
+
+/// Word trigrams of a file, to find highlights in without a substring
+/// search per highlight
+struct Fingerprint<'a> {
+    words: &'a [String],
+    trigrams: HashSet<[&'a str; 3]>,
+}
+
+impl<'a> Fingerprint<'a> {
+    fn new(words: &'a [String]) -> Self {
+        let trigrams = words
+            .windows(3)
+            .map(|w| [w[0].as_str(), w[1].as_str(), w[2].as_str()])
+            .collect();
+        Self { words, trigrams }
+    }
+
+    /// Whether `passage` appears in the file (for passages of three words
+    /// or more, whether each of its trigrams does)
+    fn contains(&self, passage: &[String]) -> bool {
+        match passage.len() {
+            0 => false,
+            1 | 2 => self.words.windows(passage.len()).any(|w| w == passage),
+            _ => passage.windows(3).all(|w| {
+                self.trigrams
+                    .contains(&[w[0].as_str(), w[1].as_str(), w[2].as_str()])
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::MarkdownExporter;
+    use crate::models::{ExportWriteMode, Highlight};
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    fn book(content_id: &str, title: &str, author: &str, highlights: &[&str]) -> Book {
+        let mut book = Book::new(
+            content_id.to_string(),
+            title.to_string(),
+            author.to_string(),
+        );
+        for (i, text) in highlights.iter().enumerate() {
+            book.highlights.push(Highlight::new(
+                format!("{}-{}", content_id, i),
+                text.to_string(),
+                "2025-01-24T10:00:00".to_string(),
+            ));
+        }
+        book
+    }
+
+    fn library() -> LibraryStore {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        store
+            .merge_books(&[
+                book(
+                    "walden",
+                    "Walden",
+                    "Henry David Thoreau",
+                    &[
+                        "Simplify, simplify.",
+                        "Our life is frittered away by detail.",
+                    ],
+                ),
+                book(
+                    "odyssey",
+                    "The Odyssey",
+                    "Homer",
+                    &["Tell me, O Muse, of that ingenious hero."],
+                ),
+                book(
+                    "meditations",
+                    "Meditations",
+                    "Marcus Aurelius",
+                    &[
+                        "You have power over your mind, not outside events.",
+                        "The happiness of your life depends upon the quality of your thoughts.",
+                    ],
+                ),
+                book(
+                    "poems-a",
+                    "Poems",
+                    "Emily Dickinson",
+                    &["Hope is the thing with feathers"],
+                ),
+                book(
+                    "poems-b",
+                    "Poems",
+                    "Walt Whitman",
+                    &["I contain multitudes"],
+                ),
+            ])
+            .unwrap();
+        store
+    }
+
+    /// What another tool left behind: one file per naming scheme it used
+    fn other_tool_folder() -> TempDir {
+        let temp = TempDir::new().unwrap();
+        let write = |path: &str, content: &str| {
+            let path = temp.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write(
+            "Walden - Henry David Thoreau.md",
+            "# Walden\n\n> Simplify, simplify.\n\n> Our life is frittered\n> away by detail.\n",
+        );
+        write(
+            "Kobo/odyssey-notes.md",
+            "---\ntitle: \"The Odyssey\"\nauthor: Homer\n---\n\nSome notes of mine.\n",
+        );
+        write(
+            "export-2024.json",
+            r#"{"highlights": [
+                {"text": "You have power over your mind, not outside events."},
+                {"text": "The happiness of your life depends upon the quality of your thoughts."}
+            ]}"#,
+        );
+        write("Poems.md", "# Poems\n\nNothing copied yet.\n");
+        write("Shopping list.md", "- bread\n- milk\n");
+        write(".obsidian/workspace.json", "{}");
+        temp
+    }
+
+    fn snapshot(dir: &Path) -> BTreeMap<String, Vec<u8>> {
+        files_with_extensions(dir, ADOPTED_EXTENSIONS)
+            .unwrap()
+            .into_iter()
+            .map(|path| (manifest_key(dir, &path), fs::read(&path).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_adoption_matches_files_and_seeds_manifest() {
+        let mut store = library();
+        let temp = other_tool_folder();
+        let before = snapshot(temp.path());
+
+        let plan = adopt_exports(&mut store, temp.path(), &AdoptionOptions::default()).unwrap();
+        assert!(!plan.confirmed);
+        assert!(!temp.path().join(append::MANIFEST_FILENAME).exists());
+        let matched: Vec<(&str, &str, MatchMethod, MatchConfidence)> = plan
+            .matches
+            .iter()
+            .map(|m| {
+                (
+                    m.path.as_str(),
+                    m.content_id.as_str(),
+                    m.method,
+                    m.confidence,
+                )
+            })
+            .collect();
+        assert_eq!(
+            matched,
+            vec![
+                (
+                    "Kobo/odyssey-notes.md",
+                    "odyssey",
+                    MatchMethod::Frontmatter,
+                    // Title and author match, but none of its highlights
+                    MatchConfidence::Medium
+                ),
+                (
+                    "Walden - Henry David Thoreau.md",
+                    "walden",
+                    MatchMethod::Filename,
+                    MatchConfidence::High
+                ),
+                (
+                    "export-2024.json",
+                    "meditations",
+                    MatchMethod::Fingerprint,
+                    MatchConfidence::Medium
+                ),
+            ]
+        );
+        assert_eq!(plan.matches[1].highlights_found, 2);
+        assert_eq!(plan.ambiguous.len(), 1);
+        assert_eq!(plan.ambiguous[0].path, "Poems.md");
+        let authors: Vec<&str> = plan.ambiguous[0]
+            .candidates
+            .iter()
+            .map(|c| c.author.as_str())
+            .collect();
+        assert_eq!(authors, vec!["Emily Dickinson", "Walt Whitman"]);
+        assert_eq!(plan.unmatched, vec!["Shopping list.md"]);
+
+        let options = AdoptionOptions {
+            confirm: true,
+            ..Default::default()
+        };
+        let report = adopt_exports(&mut store, temp.path(), &options).unwrap();
+        assert!(report.confirmed);
+        // Only the highlights found in the files count as exported
+        assert_eq!(report.highlights, 4);
+        assert_eq!(store.stats().unwrap().exported, 4);
+        let manifest = append::load_manifest(temp.path());
+        assert_eq!(manifest.files.len(), 3);
+        assert_eq!(manifest.files["Walden - Henry David Thoreau.md"].len(), 2);
+        assert!(manifest.files["Kobo/odyssey-notes.md"].is_empty());
+        assert_eq!(
+            manifest.adopted[&book_slug("odyssey", "The Odyssey")],
+            "Kobo/odyssey-notes.md"
+        );
+        assert_eq!(
+            manifest.hashes["export-2024.json"],
+            hash_file(&temp.path().join("export-2024.json")).unwrap()
+        );
+        assert_eq!(
+            manifest.books["Kobo/odyssey-notes.md"],
+            book_slug("odyssey", "The Odyssey")
+        );
+
+        assert_eq!(snapshot(temp.path()), before);
+
+        let again = adopt_exports(&mut store, temp.path(), &AdoptionOptions::default()).unwrap();
+        assert!(again.matches.is_empty());
+        assert_eq!(again.tracked.len(), 3);
+    }
+
+    #[test]
+    fn test_adopted_file_gets_only_new_highlights_appended() {
+        let mut store = library();
+        let temp = other_tool_folder();
+        let path = temp.path().join("Walden - Henry David Thoreau.md");
+        let original = fs::read_to_string(&path).unwrap();
+        let options = AdoptionOptions {
+            min_confidence: MatchConfidence::High,
+            confirm: true,
+        };
+        let report = adopt_exports(&mut store, temp.path(), &options).unwrap();
+        let meditations = report
+            .matches
+            .iter()
+            .find(|m| m.content_id == "meditations")
+            .unwrap();
+        assert!(!meditations.adopted);
+        assert!(!append::load_manifest(temp.path())
+            .files
+            .contains_key("export-2024.json"));
+
+        let mut walden = book(
+            "walden",
+            "Walden",
+            "Henry David Thoreau",
+            &[
+                "Simplify, simplify.",
+                "Our life is frittered away by detail.",
+            ],
+        );
+        walden.highlights.push(Highlight::new(
+            "walden-2".into(),
+            "Shams and delusions".into(),
+            "2025-02-01T10:00:00".into(),
+        ));
+        store.merge_books(std::slice::from_ref(&walden)).unwrap();
+        let walden = store
+            .books()
+            .unwrap()
+            .into_iter()
+            .find(|b| b.content_id == "walden")
+            .unwrap();
+
+        let mut config = crate::settings::AppSettings::default().export_config;
+        config.export_path = temp.path().to_string_lossy().to_string();
+        config.write_mode = ExportWriteMode::Append;
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        assert_eq!(exporter.export_book(&walden, &config).unwrap(), path);
+
+        let content = fs::read_to_string(&path).unwrap();
+        let added = content.strip_prefix(original.as_str()).unwrap();
+        assert!(added.contains("Shams and delusions"));
+        assert!(!added.contains("Simplify"));
+    }
+
+    #[test]
+    fn test_adopted_file_under_another_name_keeps_receiving_the_book() {
+        let mut store = library();
+        let temp = other_tool_folder();
+        let path = temp.path().join("Kobo/odyssey-notes.md");
+        let options = AdoptionOptions {
+            confirm: true,
+            ..Default::default()
+        };
+        adopt_exports(&mut store, temp.path(), &options).unwrap();
+        let odyssey = store
+            .books()
+            .unwrap()
+            .into_iter()
+            .find(|b| b.content_id == "odyssey")
+            .unwrap();
+
+        let mut config = crate::settings::AppSettings::default().export_config;
+        config.export_path = temp.path().to_string_lossy().to_string();
+        config.write_mode = ExportWriteMode::Append;
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        assert_eq!(exporter.export_book(&odyssey, &config).unwrap(), path);
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("---\ntitle: \"The Odyssey\""));
+        assert!(content.contains("Some notes of mine."));
+        assert!(content.contains("Tell me, O Muse"));
+    }
+}
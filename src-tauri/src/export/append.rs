@@ -0,0 +1,161 @@
+//! Append-mode exports
+//!
+//! With `ExportWriteMode::Append` every highlight carries an invisible
+//! `<!-- khi:<id> -->` anchor, and `.khi-manifest.json` in the export folder
+//! records which highlights went into which file. Re-exports then append only
+//! new highlights under a dated heading, leaving everything above (including
+//! hand-written commentary) untouched.
+
+use crate::models::Highlight;
+use crate::utils::fs::{atomic_write_with, FileOps, SystemFileOps};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Manifest of append-mode exports, in the export folder
+pub const MANIFEST_FILENAME: &str = ".khi-manifest.json";
+
+const ANCHOR_PREFIX: &str = "<!-- khi:";
+const ANCHOR_SUFFIX: &str = " -->";
+
+/// Highlight keys written to each file, by path relative to the export folder
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportManifest {
+    #[serde(default)]
+    pub files: BTreeMap<String, Vec<String>>,
+    /// SHA-256 of each file as khi last wrote it (see `verify`)
+    #[serde(default)]
+    pub hashes: BTreeMap<String, String>,
+    /// Files the next export rewrites in full instead of appending to
+    #[serde(default)]
+    pub stale: BTreeSet<String>,
+    /// Slug of the book written to each file
+    #[serde(default)]
+    pub books: BTreeMap<String, String>,
+    /// File adopted from another tool for each book slug; append-mode
+    /// exports of the book go there instead of its own filename
+    #[serde(default)]
+    pub adopted: BTreeMap<String, String>,
+}
+
+/// The manifest of `export_dir`; empty when missing or unreadable
+pub fn load_manifest(export_dir: &Path) -> ExportManifest {
+    let path = export_dir.join(MANIFEST_FILENAME);
+    match fs::read_to_string(&path) {
+        Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            log::warn!("[EXPORTER] Manifesto inválido {:?}: {}", path, e);
+            ExportManifest::default()
+        }),
+        Err(_) => ExportManifest::default(),
+    }
+}
+
+pub fn save_manifest(export_dir: &Path, manifest: &ExportManifest) -> io::Result<()> {
+    save_manifest_with(&SystemFileOps, export_dir, manifest)
+}
+
+/// `save_manifest` through `ops` (an exporter's, so cloud-synced folders
+/// retry blocked renames)
+pub fn save_manifest_with(
+    ops: &dyn FileOps,
+    export_dir: &Path,
+    manifest: &ExportManifest,
+) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(manifest).map_err(io::Error::other)?;
+    atomic_write_with(ops, &export_dir.join(MANIFEST_FILENAME), |writer| {
+        writer.write_all(json.as_bytes())
+    })
+}
+
+/// Manifest key of an exported file: its path relative to `export_dir`
+pub fn manifest_key(export_dir: &Path, path: &Path) -> String {
+    path.strip_prefix(export_dir)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// Identity of a highlight across exports (the stable ID, else the device ID)
+pub fn highlight_key(highlight: &Highlight) -> &str {
+    if highlight.stable_id.is_empty() {
+        &highlight.id
+    } else {
+        &highlight.stable_id
+    }
+}
+
+/// HTML comment marking a highlight in the markdown
+pub fn highlight_anchor(highlight: &Highlight) -> String {
+    format!(
+        "{}{}{}",
+        ANCHOR_PREFIX,
+        highlight_key(highlight),
+        ANCHOR_SUFFIX
+    )
+}
+
+/// Keys of every highlight anchor in `text`
+pub fn anchors_in(text: &str) -> HashSet<String> {
+    text.match_indices(ANCHOR_PREFIX)
+        .filter_map(|(start, _)| {
+            let rest = &text[start + ANCHOR_PREFIX.len()..];
+            rest.find(ANCHOR_SUFFIX).map(|end| rest[..end].to_string())
+        })
+        .filter(|key| !key.is_empty() && !key.contains(char::is_whitespace))
+        .collect()
+}
+
+/// `existing` followed by a `## heading` section holding `body`
+///
+/// The existing bytes are kept as they are; only a line break is added when
+/// the file doesn't end with one.
+pub fn append_section(existing: &str, heading: &str, body: &str) -> String {
+    let mut content = existing.to_string();
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&format!("\n## {}\n\n{}\n", heading, body.trim_end()));
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_anchors_round_trip() {
+        let mut highlight = Highlight::new(
+            "bm1".to_string(),
+            "Texto".to_string(),
+            "2025-01-24".to_string(),
+        );
+        assert_eq!(highlight_anchor(&highlight), "<!-- khi:bm1 -->");
+        highlight.stable_id = "a1b2c3".to_string();
+
+        let text = format!(
+            "> Texto {}\n<!-- a normal comment -->\n<!-- khi: -->",
+            highlight_anchor(&highlight)
+        );
+        assert_eq!(anchors_in(&text), HashSet::from(["a1b2c3".to_string()]));
+    }
+
+    #[test]
+    fn test_manifest_missing_or_corrupt_is_empty() {
+        let temp = TempDir::new().unwrap();
+        assert_eq!(load_manifest(temp.path()), ExportManifest::default());
+
+        let mut manifest = ExportManifest::default();
+        manifest
+            .files
+            .insert("Livro.md".to_string(), vec!["hl1".to_string()]);
+        save_manifest(temp.path(), &manifest).unwrap();
+        assert_eq!(load_manifest(temp.path()), manifest);
+
+        fs::write(temp.path().join(MANIFEST_FILENAME), "{not json").unwrap();
+        assert_eq!(load_manifest(temp.path()), ExportManifest::default());
+    }
+}
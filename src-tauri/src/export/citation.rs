@@ -0,0 +1,491 @@
+//! Citation export (CSL-JSON and BibTeX) for reference managers like Zotero
+//!
+//! Both formats write one records file for all selected books. Authors come
+//! from the normalized author list; highlights can be attached as the
+//! record's note.
+
+use super::plain_text;
+use super::tabular::render_tabular;
+use crate::models::{Book, BookKind, ExportConfig, ExportFormat, TitleForm};
+use crate::utils::text::ellipsize;
+use chrono::FixedOffset;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+pub const CSL_JSON_FILENAME: &str = "references.json";
+pub const BIBTEX_FILENAME: &str = "references.bib";
+
+/// Longest note attached to a record, in characters
+pub const NOTE_MAX_CHARS: usize = 10_000;
+
+/// Generational suffixes kept apart from the family name
+const NAME_SUFFIXES: &[&str] = &["jr", "jr.", "sr", "sr.", "ii", "iii", "iv"];
+
+/// Records filename and content for a citation or tabular format (`None`
+/// for the per-book formats), with titles in `title`; tabular dates are
+/// shown at `offset`
+pub fn render_records(
+    books: &[&Book],
+    config: &ExportConfig,
+    offset: FixedOffset,
+    title: TitleForm,
+) -> Option<(&'static str, String)> {
+    match config.format {
+        ExportFormat::Csv | ExportFormat::Tsv | ExportFormat::Readwise => {
+            render_tabular(books, config, offset, title)
+        }
+        // NDJSON is streamed by the exporter instead of rendered in memory
+        ExportFormat::Markdown
+        | ExportFormat::Logseq
+        | ExportFormat::Ndjson
+        | ExportFormat::Journal => None,
+        ExportFormat::CslJson => Some((
+            CSL_JSON_FILENAME,
+            generate_csl_json(books, config.citation_notes, title),
+        )),
+        ExportFormat::Bibtex => Some((
+            BIBTEX_FILENAME,
+            generate_bibtex(books, config.citation_notes, title),
+        )),
+    }
+}
+
+/// A personal name split for citation formats
+#[derive(Debug, Clone, PartialEq)]
+enum CitationName {
+    Person {
+        family: String,
+        given: String,
+        suffix: Option<String>,
+    },
+    /// Single-word and corporate names are never split
+    Literal(String),
+}
+
+impl CitationName {
+    fn parse(name: &str) -> Self {
+        let (base, suffix) = match name.split_once(", ") {
+            Some((base, suffix)) if NAME_SUFFIXES.contains(&suffix.to_lowercase().as_str()) => {
+                (base, Some(suffix.to_string()))
+            }
+            Some(_) => return CitationName::Literal(name.to_string()),
+            None => (name, None),
+        };
+
+        match base.rsplit_once(' ') {
+            Some((given, family)) => CitationName::Person {
+                family: family.to_string(),
+                given: given.to_string(),
+                suffix,
+            },
+            None => CitationName::Literal(name.to_string()),
+        }
+    }
+
+    fn family(&self) -> &str {
+        match self {
+            CitationName::Person { family, .. } => family,
+            CitationName::Literal(name) => name,
+        }
+    }
+}
+
+fn citation_names(book: &Book) -> Vec<CitationName> {
+    let authors = if book.authors.is_empty() {
+        crate::utils::author::parse_authors(&book.author)
+    } else {
+        book.authors.clone()
+    };
+    authors.iter().map(|a| CitationName::parse(a)).collect()
+}
+
+/// Year the book was read, from `date_last_read`
+fn book_year(book: &Book) -> Option<i32> {
+    let date = book.date_last_read.as_deref()?.trim();
+    date.get(..4)
+        .filter(|year| year.chars().all(|c| c.is_ascii_digit()))
+        .and_then(|year| year.parse().ok())
+}
+
+/// Highlights (and their notes) as plain text, capped at `NOTE_MAX_CHARS`
+fn highlights_note(book: &Book) -> Option<String> {
+    if book.highlights.is_empty() {
+        return None;
+    }
+
+    let entries: Vec<String> = book
+        .highlights
+        .iter()
+        .map(|h| {
+            let mut entry = strip_markdown(&plain_text(book, h));
+            if let Some(page) = h.page {
+                entry.push_str(&format!(" (p. {})", page));
+            }
+            if let Some(note) = h.annotation.as_deref().filter(|n| !n.trim().is_empty()) {
+                entry.push_str(&format!(" (Nota: {})", strip_markdown(note)));
+            }
+            entry
+        })
+        .collect();
+    Some(ellipsize(&entries.join("\n\n"), NOTE_MAX_CHARS))
+}
+
+/// Drop markdown emphasis, code, heading and quote markers
+fn strip_markdown(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            line.trim_start_matches(['#', '>'])
+                .replace("**", "")
+                .replace("__", "")
+                .replace('`', "")
+                .trim()
+                .to_string()
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Fold common Latin accents to ASCII for citation keys
+fn ascii_fold(c: char) -> Option<char> {
+    const FOLDS: &[(&str, char)] = &[
+        ("áàâãäå", 'a'),
+        ("ç", 'c'),
+        ("éèêë", 'e'),
+        ("íìîï", 'i'),
+        ("ñ", 'n'),
+        ("óòôõöø", 'o'),
+        ("úùûü", 'u'),
+        ("ýÿ", 'y'),
+    ];
+    let lower = c.to_lowercase().next().unwrap_or(c);
+    if lower.is_ascii_alphanumeric() {
+        return Some(lower);
+    }
+    FOLDS
+        .iter()
+        .find(|(accented, _)| accented.contains(lower))
+        .map(|(_, plain)| *plain)
+}
+
+fn key_part(text: &str) -> String {
+    text.chars().filter_map(ascii_fold).collect()
+}
+
+/// Citation keys like `saramago2024ensaio`, made unique with a/b/c suffixes
+fn citation_keys(books: &[&Book]) -> Vec<String> {
+    let bases: Vec<String> = books
+        .iter()
+        .map(|book| {
+            let family = citation_names(book)
+                .first()
+                .map(|name| key_part(name.family()))
+                .unwrap_or_default();
+            let year = book_year(book).map(|y| y.to_string()).unwrap_or_default();
+            let word = book
+                .title
+                .split_whitespace()
+                .map(key_part)
+                .find(|w| w.len() > 3)
+                .unwrap_or_default();
+
+            let key = format!("{}{}{}", family, year, word);
+            if key.is_empty() {
+                "book".to_string()
+            } else {
+                key
+            }
+        })
+        .collect();
+
+    let mut totals: HashMap<&str, usize> = HashMap::new();
+    for base in &bases {
+        *totals.entry(base.as_str()).or_default() += 1;
+    }
+
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    bases
+        .iter()
+        .map(|base| {
+            if totals[base.as_str()] == 1 {
+                return base.clone();
+            }
+            let n = seen.entry(base.as_str()).or_default();
+            *n += 1;
+            let letter = (b'a' + ((*n - 1) % 26) as u8) as char;
+            format!("{}{}", base, letter)
+        })
+        .collect()
+}
+
+/// CSL-JSON array of `book` items (`webpage` for Pocket articles), titled
+/// in `title`
+pub fn generate_csl_json(books: &[&Book], include_notes: bool, title: TitleForm) -> String {
+    let items: Vec<Value> = books
+        .iter()
+        .zip(citation_keys(books))
+        .map(|(book, key)| {
+            let mut item = Map::new();
+            item.insert("id".to_string(), json!(key));
+            let kind = match book.kind {
+                BookKind::Book | BookKind::KindleImport => "book",
+                BookKind::PocketArticle => "webpage",
+            };
+            item.insert("type".to_string(), json!(kind));
+            item.insert("title".to_string(), json!(book.title_in(title)));
+
+            let authors: Vec<Value> = citation_names(book)
+                .into_iter()
+                .map(|name| match name {
+                    CitationName::Person {
+                        family,
+                        given,
+                        suffix,
+                    } => {
+                        let mut author = json!({ "family": family, "given": given });
+                        if let Some(suffix) = suffix {
+                            author["suffix"] = json!(suffix);
+                        }
+                        author
+                    }
+                    CitationName::Literal(name) => json!({ "literal": name }),
+                })
+                .collect();
+            if !authors.is_empty() {
+                item.insert("author".to_string(), Value::Array(authors));
+            }
+
+            let optional = [
+                ("ISBN", &book.isbn),
+                ("publisher", &book.publisher),
+                ("language", &book.language),
+                ("URL", &book.source_url),
+            ];
+            for (field, value) in optional {
+                if let Some(value) = value.as_deref().filter(|v| !v.trim().is_empty()) {
+                    item.insert(field.to_string(), json!(value.trim()));
+                }
+            }
+            if let Some(year) = book_year(book) {
+                item.insert("issued".to_string(), json!({ "date-parts": [[year]] }));
+            }
+            if include_notes {
+                if let Some(note) = highlights_note(book) {
+                    item.insert("note".to_string(), json!(note));
+                }
+            }
+            Value::Object(item)
+        })
+        .collect();
+
+    let mut output = serde_json::to_string_pretty(&items).unwrap_or_else(|_| "[]".to_string());
+    output.push('\n');
+    output
+}
+
+/// Escape BibTeX/LaTeX special characters in a field value
+fn escape_bibtex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '{' | '}' | '&' | '%' | '$' | '#' | '_' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '\n' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `Family, Given` name for a BibTeX author list, already escaped
+fn bibtex_name(name: &CitationName) -> String {
+    match name {
+        CitationName::Person {
+            family,
+            given,
+            suffix: Some(suffix),
+        } => escape_bibtex(&format!("{}, {}, {}", family, suffix, given)),
+        CitationName::Person {
+            family,
+            given,
+            suffix: None,
+        } => escape_bibtex(&format!("{}, {}", family, given)),
+        // Extra braces keep BibTeX from splitting corporate names
+        CitationName::Literal(name) => format!("{{{}}}", escape_bibtex(name)),
+    }
+}
+
+/// BibTeX `@book` entries (`@online` for Pocket articles), titled in `title`
+pub fn generate_bibtex(books: &[&Book], include_notes: bool, title: TitleForm) -> String {
+    let mut output = String::new();
+
+    for (book, key) in books.iter().zip(citation_keys(books)) {
+        let mut fields: Vec<(&str, String)> = vec![("title", escape_bibtex(&book.title_in(title)))];
+
+        let names = citation_names(book);
+        if !names.is_empty() {
+            let authors: Vec<String> = names.iter().map(bibtex_name).collect();
+            fields.push(("author", authors.join(" and ")));
+        }
+        let optional = [
+            ("isbn", &book.isbn),
+            ("publisher", &book.publisher),
+            ("language", &book.language),
+        ];
+        for (field, value) in optional {
+            if let Some(value) = value.as_deref().filter(|v| !v.trim().is_empty()) {
+                fields.push((field, escape_bibtex(value.trim())));
+            }
+        }
+        // `url` is read verbatim, so it isn't escaped
+        if let Some(url) = book.source_url.as_deref().filter(|v| !v.trim().is_empty()) {
+            fields.push(("url", url.trim().to_string()));
+        }
+        if let Some(year) = book_year(book) {
+            fields.push(("year", year.to_string()));
+        }
+        if include_notes {
+            if let Some(note) = highlights_note(book) {
+                fields.push(("note", escape_bibtex(&note)));
+            }
+        }
+
+        let entry_type = match book.kind {
+            BookKind::Book | BookKind::KindleImport => "book",
+            BookKind::PocketArticle => "online",
+        };
+        output.push_str(&format!("@{}{{{},\n", entry_type, key));
+        let body: Vec<String> = fields
+            .iter()
+            .map(|(field, value)| format!("  {} = {{{}}}", field, value))
+            .collect();
+        output.push_str(&body.join(",\n"));
+        output.push_str("\n}\n\n");
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Highlight;
+    use crate::utils::date::utc_offset;
+
+    fn create_citation_books() -> Vec<Book> {
+        let mut ensaio = Book::new(
+            "vol1".to_string(),
+            "Ensaio sobre a Cegueira".to_string(),
+            "Saramago, José".to_string(),
+        );
+        ensaio.isbn = Some("978-972-21-1129-2".to_string());
+        ensaio.publisher = Some("Caminho".to_string());
+        ensaio.language = Some("pt".to_string());
+        ensaio.date_last_read = Some("2024-03-10T21:15:00.000".to_string());
+        let mut highlight = Highlight::new(
+            "hl1".to_string(),
+            "O **pior** cego é o que não quer ver.".to_string(),
+            "2024-03-01".to_string(),
+        );
+        highlight.annotation = Some("Tema central".to_string());
+        ensaio.highlights.push(highlight);
+
+        let mut tex = Book::new(
+            "vol2".to_string(),
+            "Sets {and} Functions & 100% of $x_1$ #1".to_string(),
+            "Martin Luther King, Jr.; Acme, Inc.".to_string(),
+        );
+        tex.date_last_read = Some("2023-11-02".to_string());
+
+        // Same key base as the first book
+        let mut ensaio2 = Book::new(
+            "vol3".to_string(),
+            "Ensaio sobre a Lucidez".to_string(),
+            "José Saramago".to_string(),
+        );
+        ensaio2.date_last_read = Some("2024-05-01".to_string());
+
+        vec![ensaio, tex, ensaio2]
+    }
+
+    #[test]
+    fn test_csl_json_golden() {
+        let books = create_citation_books();
+        let refs: Vec<&Book> = books.iter().collect();
+        let output = generate_csl_json(&refs, true, TitleForm::Processed);
+
+        assert_eq!(output, include_str!("testdata/references.json"));
+        let parsed: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(parsed[0]["author"][0]["family"], "Saramago");
+        assert_eq!(parsed[0]["author"][0]["given"], "José");
+    }
+
+    #[test]
+    fn test_bibtex_golden() {
+        let books = create_citation_books();
+        let refs: Vec<&Book> = books.iter().collect();
+
+        assert_eq!(
+            generate_bibtex(&refs, true, TitleForm::Processed),
+            include_str!("testdata/references.bib")
+        );
+    }
+
+    #[test]
+    fn test_bibtex_escaping() {
+        assert_eq!(
+            escape_bibtex("Sets {and} Functions & 100% of $x_1$ #1"),
+            "Sets \\{and\\} Functions \\& 100\\% of \\$x\\_1\\$ \\#1"
+        );
+        assert_eq!(
+            escape_bibtex("a\\b~c^d"),
+            "a\\textbackslash{}b\\textasciitilde{}c\\textasciicircum{}d"
+        );
+        assert_eq!(escape_bibtex("Gonçalo Tavares"), "Gonçalo Tavares");
+    }
+
+    #[test]
+    fn test_note_cites_page_when_known() {
+        let mut book = create_citation_books().remove(0);
+        book.highlights[0].page = Some(42);
+        assert_eq!(
+            highlights_note(&book).unwrap(),
+            "O pior cego é o que não quer ver. (p. 42) (Nota: Tema central)"
+        );
+    }
+
+    #[test]
+    fn test_note_is_capped_and_optional() {
+        let mut book = create_citation_books().remove(0);
+        book.highlights[0].text = "palavra ".repeat(NOTE_MAX_CHARS);
+
+        let note = highlights_note(&book).unwrap();
+        assert!(note.chars().count() <= NOTE_MAX_CHARS + 1);
+        assert!(note.ends_with('…'));
+
+        let output = generate_csl_json(&[&book], false, TitleForm::Processed);
+        assert!(!output.contains("\"note\""));
+    }
+
+    #[test]
+    fn test_records_only_for_citation_formats() {
+        let books = create_citation_books();
+        let refs: Vec<&Book> = books.iter().collect();
+        let mut config = crate::settings::AppSettings::default().export_config;
+
+        assert_eq!(
+            render_records(&refs, &config, utc_offset(), TitleForm::Processed),
+            None
+        );
+        config.format = ExportFormat::Bibtex;
+        let (filename, content) =
+            render_records(&refs, &config, utc_offset(), TitleForm::Processed).unwrap();
+        assert_eq!(filename, BIBTEX_FILENAME);
+        assert!(content.starts_with("@book{saramago2024ensaioa,"));
+    }
+}
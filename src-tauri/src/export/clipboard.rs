@@ -0,0 +1,319 @@
+//! Markdown for a selection of highlights copied to the clipboard
+//!
+//! Highlights are rendered with the exporter's own highlight markdown, so a
+//! copied quote looks exactly like the exported one. Highlights of the same
+//! book are grouped (in the order their books were first selected) under a
+//! small heading when the selection spans several books.
+
+use super::{location_parts, MarkdownExporter};
+use crate::library::{HighlightRef, MissingReason, ResolvedHighlight};
+use crate::models::{Book, ExportConfig, ExportWriteMode, Highlight};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Largest clipboard payload by default, in bytes
+pub const CLIPBOARD_MAX_BYTES: usize = 256 * 1024;
+
+/// How copied highlights are rendered
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipboardStyle {
+    /// Blockquote and location line, as in markdown exports
+    #[default]
+    Quote,
+    /// One list item per highlight
+    Compact,
+    /// Blockquote attributed to its author and book (no book headings)
+    Citation,
+}
+
+/// A selected highlight that is no longer in the library
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MissingHighlight {
+    pub content_id: String,
+    pub highlight_id: String,
+    pub reason: MissingReason,
+}
+
+/// Rendered selection
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipboardSelection {
+    pub markdown: String,
+    /// Highlights in `markdown` (the last one possibly cut short)
+    pub highlights: usize,
+    /// The size cap cut the selection short
+    pub truncated: bool,
+    pub missing: Vec<MissingHighlight>,
+}
+
+/// Render resolved `selection` (see `LibraryStore::resolve_highlights`) in
+/// at most `max_bytes`
+///
+/// Whole highlights are dropped once the cap is reached; only a first
+/// highlight that doesn't fit on its own is cut mid-text.
+pub fn render_selection(
+    selection: &[(HighlightRef, ResolvedHighlight)],
+    style: ClipboardStyle,
+    config: &ExportConfig,
+    max_bytes: usize,
+) -> ClipboardSelection {
+    let mut config = config.clone();
+    config.compact = style == ClipboardStyle::Compact;
+    // Append-mode anchors mean nothing outside the exported file
+    config.write_mode = ExportWriteMode::Overwrite;
+
+    let mut missing = Vec::new();
+    let mut groups: Vec<(&Book, Vec<&Highlight>)> = Vec::new();
+    for (selected, resolved) in selection {
+        match resolved {
+            Ok((book, highlight)) => {
+                match groups
+                    .iter_mut()
+                    .find(|(b, _)| b.content_id == book.content_id)
+                {
+                    Some((_, highlights)) => highlights.push(highlight),
+                    None => groups.push((book, vec![highlight])),
+                }
+            }
+            Err(reason) => missing.push(MissingHighlight {
+                content_id: selected.content_id.clone(),
+                highlight_id: selected.highlight_id.clone(),
+                reason: *reason,
+            }),
+        }
+    }
+
+    let exporter = MarkdownExporter::new(PathBuf::new());
+    let headings = groups.len() > 1 && style != ClipboardStyle::Citation;
+    let item_separator = if config.compact { "\n" } else { "\n\n" };
+    // One block per highlight, with what goes before it
+    let mut blocks: Vec<(&str, String)> = Vec::new();
+    for (book, highlights) in &groups {
+        for (index, highlight) in highlights.iter().enumerate() {
+            let mut block = String::new();
+            if headings && index == 0 {
+                block = format!("### {} — {}\n\n", book.title, book.display_author());
+            }
+            match style {
+                ClipboardStyle::Citation => {
+                    block.push_str(&citation_block(&exporter, book, highlight, &config))
+                }
+                _ => block.push_str(
+                    exporter
+                        .generate_highlight_markdown(book, highlight, &config)
+                        .trim_end(),
+                ),
+            }
+            let separator = if index == 0 { "\n\n" } else { item_separator };
+            blocks.push((separator, block));
+        }
+    }
+
+    let mut rendered = ClipboardSelection {
+        missing,
+        ..Default::default()
+    };
+    for (separator, block) in blocks {
+        let separator = if rendered.markdown.is_empty() {
+            ""
+        } else {
+            separator
+        };
+        if rendered.markdown.len() + separator.len() + block.len() > max_bytes {
+            rendered.truncated = true;
+            if rendered.markdown.is_empty() {
+                let mut end = max_bytes;
+                while !block.is_char_boundary(end) {
+                    end -= 1;
+                }
+                rendered.markdown = block[..end].to_string();
+                rendered.highlights = 1;
+            }
+            break;
+        }
+        rendered.markdown.push_str(separator);
+        rendered.markdown.push_str(&block);
+        rendered.highlights += 1;
+    }
+    rendered
+}
+
+/// Blockquote followed by "— Author, *Title*" and the location
+fn citation_block(
+    exporter: &MarkdownExporter,
+    book: &Book,
+    highlight: &Highlight,
+    config: &ExportConfig,
+) -> String {
+    let quote_config = ExportConfig {
+        show_location: false,
+        ..config.clone()
+    };
+    let quote = exporter.generate_highlight_markdown(book, highlight, &quote_config);
+    let mut attribution = vec![format!("*{}*", book.title)];
+    if !book.author.is_empty() {
+        attribution.insert(0, book.display_author());
+    }
+    if config.show_location {
+        attribution.extend(location_parts(book, highlight));
+    }
+    format!("{}\n>\n> — {}", quote.trim_end(), attribution.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::LibraryStore;
+
+    fn library() -> LibraryStore {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let mut books = crate::sample::sample_books();
+        books.truncate(2);
+        store.merge_books(&books).unwrap();
+        store
+    }
+
+    fn select(
+        store: &LibraryStore,
+        refs: Vec<HighlightRef>,
+    ) -> Vec<(HighlightRef, ResolvedHighlight)> {
+        let resolved = store.resolve_highlights(&refs).unwrap();
+        refs.into_iter().zip(resolved).collect()
+    }
+
+    fn selected(book: &Book, index: usize) -> HighlightRef {
+        HighlightRef {
+            content_id: book.content_id.clone(),
+            highlight_id: book.highlights[index].stable_id.clone(),
+        }
+    }
+
+    fn config() -> ExportConfig {
+        crate::settings::AppSettings::default().export_config
+    }
+
+    #[test]
+    fn test_cross_book_selection_groups_by_book() {
+        let store = library();
+        let books = crate::sample::sample_books();
+        let refs = vec![
+            selected(&books[0], 1),
+            selected(&books[1], 0),
+            selected(&books[0], 0),
+        ];
+        let selection = select(&store, refs);
+
+        let rendered = render_selection(
+            &selection,
+            ClipboardStyle::Quote,
+            &config(),
+            CLIPBOARD_MAX_BYTES,
+        );
+        assert!(!rendered.truncated);
+        assert!(rendered.missing.is_empty());
+        assert_eq!(rendered.highlights, 3);
+        let first_heading = format!("### {} — {}", books[0].title, books[0].display_author());
+        let second_heading = format!("### {} — {}", books[1].title, books[1].display_author());
+        assert!(
+            rendered.markdown.starts_with(&first_heading),
+            "{}",
+            rendered.markdown
+        );
+        let second = rendered.markdown.find(&second_heading).unwrap();
+        // Both highlights of the first book come before the second book
+        let quote = |book: &Book, index: usize| {
+            rendered
+                .markdown
+                .find(&format!("> {}", book.highlights[index].text))
+                .unwrap()
+        };
+        assert!(quote(&books[0], 1) < quote(&books[0], 0));
+        assert!(quote(&books[0], 0) < second);
+        assert!(second < quote(&books[1], 0));
+
+        // One book: no heading
+        let single = select(&store, vec![selected(&books[1], 0)]);
+        let rendered = render_selection(
+            &single,
+            ClipboardStyle::Citation,
+            &config(),
+            CLIPBOARD_MAX_BYTES,
+        );
+        assert!(!rendered.markdown.contains("###"));
+        assert!(rendered.markdown.contains(&format!(
+            "> — {}, *{}*",
+            books[1].display_author(),
+            books[1].title
+        )));
+    }
+
+    #[test]
+    fn test_missing_refs_are_reported_per_item() {
+        let store = library();
+        let books = crate::sample::sample_books();
+        let refs = vec![
+            selected(&books[0], 0),
+            HighlightRef {
+                content_id: books[0].content_id.clone(),
+                highlight_id: "deleted".to_string(),
+            },
+            HighlightRef {
+                content_id: "gone".to_string(),
+                highlight_id: "any".to_string(),
+            },
+        ];
+        let rendered = render_selection(
+            &select(&store, refs),
+            ClipboardStyle::Compact,
+            &config(),
+            CLIPBOARD_MAX_BYTES,
+        );
+
+        assert_eq!(rendered.highlights, 1);
+        assert!(rendered.markdown.starts_with("- \""));
+        assert_eq!(
+            rendered
+                .missing
+                .iter()
+                .map(|m| (m.highlight_id.as_str(), m.reason))
+                .collect::<Vec<_>>(),
+            vec![
+                ("deleted", MissingReason::Highlight),
+                ("any", MissingReason::Book)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_size_cap_truncates_at_highlight_boundaries() {
+        let store = library();
+        let books = crate::sample::sample_books();
+        let refs: Vec<HighlightRef> = (0..books[0].highlights.len().min(4))
+            .map(|index| selected(&books[0], index))
+            .collect();
+        let selection = select(&store, refs);
+        let full = render_selection(
+            &selection,
+            ClipboardStyle::Quote,
+            &config(),
+            CLIPBOARD_MAX_BYTES,
+        );
+        assert!(!full.truncated);
+
+        let cap = full.markdown.len() - 1;
+        let capped = render_selection(&selection, ClipboardStyle::Quote, &config(), cap);
+        assert!(capped.truncated);
+        assert!(capped.markdown.len() <= cap);
+        assert_eq!(capped.highlights, full.highlights - 1);
+        assert!(full.markdown.starts_with(&capped.markdown));
+
+        // A lone highlight larger than the cap is cut on a char boundary
+        let tiny = render_selection(&selection[..1], ClipboardStyle::Quote, &config(), 7);
+        assert!(tiny.truncated);
+        assert_eq!(tiny.highlights, 1);
+        assert!(tiny.markdown.len() <= 7);
+        assert!(tiny.markdown.starts_with("> "));
+    }
+}
@@ -0,0 +1,216 @@
+//! Line diff between a freshly rendered export and the file on disk
+
+use serde::{Deserialize, Serialize};
+
+/// Upper bound on the LCS table (lines × lines) before the input is truncated
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Lines of unchanged context around each hunk
+const CONTEXT_LINES: usize = 3;
+
+/// What re-exporting a book would change in its existing file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportDiff {
+    pub path: String,
+    /// No file exists yet at the planned path
+    pub is_new_file: bool,
+    pub unified_diff: String,
+    pub lines_added: usize,
+    pub lines_removed: usize,
+    /// Highlight blockquotes (`> ...`) added or removed
+    pub highlights_added: usize,
+    pub highlights_removed: usize,
+    /// The inputs were too large and only their start was compared
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Diff `old` (existing file, if any) against `new` (rendered markdown)
+pub fn diff_export(path: &str, old: Option<&str>, new: &str) -> ExportDiff {
+    let old_lines: Vec<&str> = old.map(|o| o.lines().collect()).unwrap_or_default();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let (ops, truncated) = diff_lines(&old_lines, &new_lines);
+
+    let mut diff = ExportDiff {
+        path: path.to_string(),
+        is_new_file: old.is_none(),
+        unified_diff: String::new(),
+        lines_added: 0,
+        lines_removed: 0,
+        highlights_added: 0,
+        highlights_removed: 0,
+        truncated,
+    };
+
+    for (op, line) in &ops {
+        let is_highlight = line.starts_with("> ");
+        match op {
+            Op::Insert => {
+                diff.lines_added += 1;
+                diff.highlights_added += usize::from(is_highlight);
+            }
+            Op::Delete => {
+                diff.lines_removed += 1;
+                diff.highlights_removed += usize::from(is_highlight);
+            }
+            Op::Equal => {}
+        }
+    }
+
+    if diff.lines_added + diff.lines_removed > 0 {
+        diff.unified_diff = unified(path, old.is_none(), &ops);
+    }
+    diff
+}
+
+/// Edit script between two line lists (LCS), with common ends trimmed first
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> (Vec<(Op, &'a str)>, bool) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut old_mid = &old[prefix..old.len() - suffix];
+    let mut new_mid = &new[prefix..new.len() - suffix];
+
+    // Cap the quadratic table for pathological inputs
+    let mut truncated = false;
+    while (old_mid.len() + 1) * (new_mid.len() + 1) > MAX_DIFF_CELLS {
+        truncated = true;
+        if old_mid.len() > new_mid.len() {
+            old_mid = &old_mid[..old_mid.len() / 2];
+        } else {
+            new_mid = &new_mid[..new_mid.len() / 2];
+        }
+    }
+
+    let mut ops: Vec<(Op, &str)> = old[..prefix].iter().map(|l| (Op::Equal, *l)).collect();
+
+    let (n, m) = (old_mid.len(), new_mid.len());
+    let mut table = vec![0u32; (n + 1) * (m + 1)];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            table[i * (m + 1) + j] = if old_mid[i] == new_mid[j] {
+                table[(i + 1) * (m + 1) + j + 1] + 1
+            } else {
+                table[(i + 1) * (m + 1) + j].max(table[i * (m + 1) + j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old_mid[i] == new_mid[j] {
+            ops.push((Op::Equal, old_mid[i]));
+            i += 1;
+            j += 1;
+        } else if table[(i + 1) * (m + 1) + j] >= table[i * (m + 1) + j + 1] {
+            ops.push((Op::Delete, old_mid[i]));
+            i += 1;
+        } else {
+            ops.push((Op::Insert, new_mid[j]));
+            j += 1;
+        }
+    }
+    ops.extend(old_mid[i..].iter().map(|l| (Op::Delete, *l)));
+    ops.extend(new_mid[j..].iter().map(|l| (Op::Insert, *l)));
+
+    if !truncated {
+        ops.extend(old[old.len() - suffix..].iter().map(|l| (Op::Equal, *l)));
+    }
+    (ops, truncated)
+}
+
+/// Render an edit script as unified diff text
+fn unified(path: &str, is_new_file: bool, ops: &[(Op, &str)]) -> String {
+    let old_name = if is_new_file {
+        "/dev/null".to_string()
+    } else {
+        format!("a/{}", path)
+    };
+    let mut out = format!("--- {}\n+++ b/{}\n", old_name, path);
+
+    let changed: Vec<usize> = (0..ops.len()).filter(|&k| ops[k].0 != Op::Equal).collect();
+    let mut k = 0;
+    while k < changed.len() {
+        // Extend the hunk while the next change is within the context window
+        let start = changed[k].saturating_sub(CONTEXT_LINES);
+        let mut end = changed[k];
+        while k + 1 < changed.len() && changed[k + 1] <= end + 2 * CONTEXT_LINES + 1 {
+            k += 1;
+            end = changed[k];
+        }
+        let end = (end + CONTEXT_LINES + 1).min(ops.len());
+        k += 1;
+
+        let old_start = ops[..start]
+            .iter()
+            .filter(|(op, _)| *op != Op::Insert)
+            .count();
+        let new_start = ops[..start]
+            .iter()
+            .filter(|(op, _)| *op != Op::Delete)
+            .count();
+        let hunk = &ops[start..end];
+        let old_len = hunk.iter().filter(|(op, _)| *op != Op::Insert).count();
+        let new_len = hunk.iter().filter(|(op, _)| *op != Op::Delete).count();
+
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start + usize::from(old_len > 0),
+            old_len,
+            new_start + usize::from(new_len > 0),
+            new_len
+        ));
+        for (op, line) in hunk {
+            let marker = match op {
+                Op::Equal => ' ',
+                Op::Delete => '-',
+                Op::Insert => '+',
+            };
+            out.push(marker);
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hunk_header_and_context() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\n";
+        let new = "a\nb\nc\nd\nX\nf\ng\nh\n";
+        let diff = diff_export("book.md", Some(old), new);
+
+        assert_eq!(
+            diff.unified_diff,
+            "--- a/book.md\n+++ b/book.md\n@@ -2,7 +2,7 @@\n b\n c\n d\n-e\n+X\n f\n g\n h\n"
+        );
+        assert_eq!((diff.lines_added, diff.lines_removed), (1, 1));
+        assert!(!diff.truncated);
+    }
+
+    #[test]
+    fn test_pathological_input_is_truncated() {
+        let old: String = (0..3000).map(|i| format!("old {}\n", i)).collect();
+        let new: String = (0..3000).map(|i| format!("new {}\n", i)).collect();
+
+        let diff = diff_export("book.md", Some(&old), &new);
+        assert!(diff.truncated);
+        assert!(diff.lines_added > 0);
+    }
+}
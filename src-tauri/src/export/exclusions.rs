@@ -0,0 +1,215 @@
+//! Chapter exclusion rules
+//!
+//! Highlights in front matter or acknowledgements can be left out of exports,
+//! either per book (chapter titles or spine files saved in the library) or
+//! for every book through `ExportConfig::excluded_chapter_patterns`.
+
+use crate::models::{Book, Highlight};
+use regex::Regex;
+use std::collections::HashMap;
+
+/// Prefix marking a global pattern as a glob (`glob:*acknowledg*`) instead
+/// of a regex
+pub const GLOB_PREFIX: &str = "glob:";
+
+/// Excluded chapter titles or spine files, by book content ID
+pub type BookChapterExclusions = HashMap<String, Vec<String>>;
+
+/// Compiled exclusion rules for one export
+pub struct ChapterRules<'a> {
+    per_book: &'a BookChapterExclusions,
+    patterns: Vec<Regex>,
+}
+
+impl<'a> ChapterRules<'a> {
+    /// Compile the global patterns; invalid ones are logged and ignored
+    pub fn new(per_book: &'a BookChapterExclusions, patterns: &[String]) -> Self {
+        let patterns = patterns
+            .iter()
+            .filter(|pattern| !pattern.trim().is_empty())
+            .filter_map(|pattern| match compile_pattern(pattern) {
+                Ok(regex) => Some(regex),
+                Err(e) => {
+                    log::warn!("[EXPORTER] Ignoring chapter pattern {:?}: {}", pattern, e);
+                    None
+                }
+            })
+            .collect();
+        Self { per_book, patterns }
+    }
+
+    /// Whether no rule could exclude anything
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty() && self.per_book.values().all(Vec::is_empty)
+    }
+
+    /// Whether a highlight of `book` falls in an excluded chapter
+    ///
+    /// Highlights without a chapter title or spine file are never excluded.
+    pub fn excludes(&self, book: &Book, highlight: &Highlight) -> bool {
+        let title = highlight.chapter_title.as_deref().map(str::trim);
+        let spine_file = highlight
+            .container_path
+            .as_deref()
+            .and_then(|path| path.split('#').next())
+            .filter(|path| !path.is_empty());
+
+        if let Some(entries) = self.per_book.get(&book.content_id) {
+            for entry in entries.iter().map(|e| e.trim()).filter(|e| !e.is_empty()) {
+                if title.is_some_and(|t| t.eq_ignore_ascii_case(entry)) {
+                    return true;
+                }
+                if spine_file.is_some_and(|path| {
+                    path == entry || path.ends_with(&format!("/{}", entry.trim_start_matches('/')))
+                }) {
+                    return true;
+                }
+            }
+        }
+
+        title.is_some_and(|t| self.patterns.iter().any(|regex| regex.is_match(t)))
+    }
+
+    /// The book without its excluded highlights, and how many were dropped
+    pub fn apply(&self, book: &Book) -> (Book, usize) {
+        let mut filtered = book.clone();
+        filtered.highlights.retain(|h| !self.excludes(book, h));
+        let excluded = book.highlights.len() - filtered.highlights.len();
+        (filtered, excluded)
+    }
+}
+
+/// Compile a global pattern: a regex, or a case-insensitive whole-title glob
+/// with the `glob:` prefix
+fn compile_pattern(pattern: &str) -> Result<Regex, regex::Error> {
+    let Some(glob) = pattern.strip_prefix(GLOB_PREFIX) else {
+        return Regex::new(pattern);
+    };
+
+    let mut regex = String::from("(?i)^");
+    for c in glob.trim().chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            _ => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{BookKind, HighlightKind};
+
+    fn highlight(chapter: Option<&str>, container: Option<&str>) -> Highlight {
+        Highlight {
+            id: "h".to_string(),
+            text: "text".to_string(),
+            annotation: None,
+            chapter_title: chapter.map(str::to_string),
+            chapter_progress: None,
+            container_path: container.map(str::to_string),
+            date_created: "2025-01-01".to_string(),
+            date_created_utc: None,
+            color: None,
+            merged_from: Vec::new(),
+            stable_id: String::new(),
+            is_excluded: false,
+            kind: HighlightKind::Text,
+            figure_path: None,
+            tags: Vec::new(),
+            page: None,
+        }
+    }
+
+    fn book(highlights: Vec<Highlight>) -> Book {
+        Book {
+            content_id: "book1".to_string(),
+            title: "Livro".to_string(),
+            author: "Autor".to_string(),
+            authors: Vec::new(),
+            isbn: None,
+            publisher: None,
+            language: None,
+            date_last_read: None,
+            description: None,
+            file_path: None,
+            cover_path: None,
+            thumbnail_path: None,
+            series: None,
+            series_index: None,
+            toc: Vec::new(),
+            highlights,
+            highlight_total: None,
+            vocabulary: Vec::new(),
+            slug: String::new(),
+            progress_scope: None,
+            publication_year: None,
+            disambiguator: None,
+            kind: BookKind::Book,
+            source_url: None,
+            is_loan: false,
+            is_orphaned: false,
+            loan_expiry: None,
+            percent_read: None,
+            time_spent_reading_secs: None,
+            notes: None,
+            subtitle: None,
+            raw_title: None,
+        }
+    }
+
+    #[test]
+    fn test_per_book_titles_and_spine_files() {
+        let per_book = BookChapterExclusions::from([(
+            "book1".to_string(),
+            vec!["Copyright".to_string(), "Text/dedication.xhtml".to_string()],
+        )]);
+        let rules = ChapterRules::new(&per_book, &[]);
+        let book = book(vec![
+            highlight(Some(" copyright "), None),
+            highlight(Some("Dedication"), Some("OEBPS/Text/dedication.xhtml#p1")),
+            highlight(Some("Chapter 1"), Some("OEBPS/Text/ch01.xhtml")),
+        ]);
+
+        let (filtered, excluded) = rules.apply(&book);
+        assert_eq!(excluded, 2);
+        assert_eq!(filtered.highlights.len(), 1);
+        assert_eq!(
+            filtered.highlights[0].chapter_title.as_deref(),
+            Some("Chapter 1")
+        );
+
+        let mut other = book.clone();
+        other.content_id = "book2".to_string();
+        assert_eq!(rules.apply(&other).1, 0);
+    }
+
+    #[test]
+    fn test_global_regex_and_glob_patterns() {
+        let per_book = BookChapterExclusions::new();
+        let patterns = vec![
+            "(?i)acknowledg".to_string(),
+            "glob:about the *".to_string(),
+            "(unclosed".to_string(),
+        ];
+        let rules = ChapterRules::new(&per_book, &patterns);
+
+        let book = book(vec![]);
+        assert!(rules.excludes(&book, &highlight(Some("ACKNOWLEDGEMENTS"), None)));
+        assert!(rules.excludes(&book, &highlight(Some("About the Author"), None)));
+        assert!(!rules.excludes(&book, &highlight(Some("Thinking about the past"), None)));
+        assert!(!rules.excludes(&book, &highlight(Some("Chapter 1"), None)));
+    }
+
+    #[test]
+    fn test_chapterless_highlight_is_never_excluded() {
+        let per_book =
+            BookChapterExclusions::from([("book1".to_string(), vec!["Copyright".to_string()])]);
+        let rules = ChapterRules::new(&per_book, &[".*".to_string()]);
+
+        assert!(!rules.excludes(&book(vec![]), &highlight(None, None)));
+    }
+}
@@ -0,0 +1,196 @@
+//! Atom feed of exported highlights
+
+use crate::models::{Book, Highlight};
+
+/// File name of the feed written to the export root
+pub const FEED_FILENAME: &str = "highlights.atom";
+
+/// Timestamp used for highlights without a parseable date
+const FALLBACK_UPDATED: &str = "1970-01-01T00:00:00Z";
+
+struct FeedEntry<'a> {
+    book: &'a Book,
+    highlight: &'a Highlight,
+    updated: String,
+}
+
+/// Render an Atom feed with one entry per highlight, newest first
+///
+/// Output only depends on the books, so re-exporting unchanged highlights
+/// produces an identical file. `limit` keeps the N most recent entries
+/// (0 keeps all).
+pub fn generate_atom_feed(books: &[&Book], limit: usize) -> String {
+    let mut entries: Vec<FeedEntry> = books
+        .iter()
+        .flat_map(|book| {
+            book.highlights.iter().map(move |highlight| FeedEntry {
+                book,
+                highlight,
+                updated: rfc3339(highlight),
+            })
+        })
+        .collect();
+
+    entries.sort_by(|a, b| {
+        b.updated
+            .cmp(&a.updated)
+            .then_with(|| entry_id(a.highlight).cmp(&entry_id(b.highlight)))
+    });
+    if limit > 0 {
+        entries.truncate(limit);
+    }
+
+    let feed_updated = entries
+        .first()
+        .map(|e| e.updated.as_str())
+        .unwrap_or(FALLBACK_UPDATED);
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str("  <id>urn:khi:highlights</id>\n");
+    xml.push_str("  <title>Kobo Highlights</title>\n");
+    xml.push_str(&format!("  <updated>{}</updated>\n", feed_updated));
+    xml.push_str("  <generator>Khi</generator>\n");
+
+    for entry in &entries {
+        let author = entry.book.display_author();
+        let content = format!(
+            "{}\n\n— {}, {}",
+            entry.highlight.text, entry.book.title, author
+        );
+
+        xml.push_str("  <entry>\n");
+        xml.push_str(&format!(
+            "    <id>urn:khi:highlight:{}</id>\n",
+            escape_xml(&entry_id(entry.highlight))
+        ));
+        xml.push_str(&format!(
+            "    <title>{}</title>\n",
+            escape_xml(&entry.book.title)
+        ));
+        xml.push_str(&format!("    <updated>{}</updated>\n", entry.updated));
+        xml.push_str(&format!(
+            "    <author><name>{}</name></author>\n",
+            escape_xml(&author)
+        ));
+        xml.push_str(&format!(
+            "    <content type=\"text\">{}</content>\n",
+            escape_xml(&content)
+        ));
+        xml.push_str("  </entry>\n");
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
+
+/// Stable ID when available, the device's BookmarkID otherwise
+fn entry_id(highlight: &Highlight) -> String {
+    if highlight.stable_id.is_empty() {
+        highlight.id.clone()
+    } else {
+        highlight.stable_id.clone()
+    }
+}
+
+/// Creation time of a highlight in RFC 3339 (UTC), falling back to the epoch
+fn rfc3339(highlight: &Highlight) -> String {
+    highlight
+        .created_at()
+        .map(|dt| dt.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .unwrap_or_else(|| FALLBACK_UPDATED.to_string())
+}
+
+/// Escape text for XML, dropping characters XML 1.0 does not allow
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if (c as u32) < 0x20 || c == '\u{FFFE}' || c == '\u{FFFF}' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_feed_book() -> Book {
+        let mut book = Book::new(
+            "vol1".to_string(),
+            "Tom & Jerry <Collected>".to_string(),
+            "Hanna, William; Barbera, Joseph".to_string(),
+        );
+        let mut first = Highlight::new(
+            "hl1".to_string(),
+            "Ends a CDATA ]]> & keeps going".to_string(),
+            "2025-01-24T10:15:30.000".to_string(),
+        );
+        first.stable_id = "abc123".to_string();
+        let second = Highlight::new(
+            "hl2".to_string(),
+            "Older \u{1} quote".to_string(),
+            "2024-12-01".to_string(),
+        );
+        book.highlights = vec![second, first];
+        book
+    }
+
+    #[test]
+    fn test_feed_is_valid_xml() {
+        let book = create_feed_book();
+        let xml = generate_atom_feed(&[&book], 0);
+
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+        let entries: Vec<_> = doc
+            .root_element()
+            .children()
+            .filter(|n| n.has_tag_name("entry"))
+            .collect();
+        assert_eq!(entries.len(), 2);
+
+        let text_of = |node: roxmltree::Node, tag: &str| {
+            node.children()
+                .find(|n| n.has_tag_name(tag))
+                .and_then(|n| n.text())
+                .unwrap()
+                .to_string()
+        };
+        // Newest first, stable id used when present
+        assert_eq!(text_of(entries[0], "id"), "urn:khi:highlight:abc123");
+        assert_eq!(text_of(entries[0], "updated"), "2025-01-24T10:15:30Z");
+        assert_eq!(text_of(entries[0], "title"), "Tom & Jerry <Collected>");
+        assert_eq!(
+            text_of(entries[0], "content"),
+            "Ends a CDATA ]]> & keeps going\n\n— Tom & Jerry <Collected>, William Hanna, Joseph Barbera"
+        );
+        assert_eq!(text_of(entries[1], "id"), "urn:khi:highlight:hl2");
+        assert_eq!(text_of(entries[1], "updated"), "2024-12-01T00:00:00Z");
+        assert_eq!(
+            text_of(doc.root_element(), "updated"),
+            "2025-01-24T10:15:30Z"
+        );
+    }
+
+    #[test]
+    fn test_feed_limit_and_determinism() {
+        let book = create_feed_book();
+        let limited = generate_atom_feed(&[&book], 1);
+        assert_eq!(limited.matches("<entry>").count(), 1);
+        assert!(limited.contains("abc123"));
+
+        assert_eq!(
+            generate_atom_feed(&[&book], 0),
+            generate_atom_feed(&[&book], 0)
+        );
+    }
+}
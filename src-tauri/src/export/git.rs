@@ -0,0 +1,223 @@
+//! Commits of exported files
+//!
+//! With `git_commit_after_export` enabled and the export path inside a git
+//! work tree, every successful export run is committed. The commit holds
+//! exactly the files the run wrote, on top of `HEAD`: anything else staged
+//! in the repository stays staged and out of the commit.
+
+use git2::build::TreeUpdateBuilder;
+use git2::{ErrorCode, FileMode, Repository};
+use std::path::{Component, Path, PathBuf};
+
+/// Commit message used when the template is empty
+pub const DEFAULT_COMMIT_MESSAGE: &str =
+    "khi export {date}: {book_count} book(s), {highlight_count} highlight(s)";
+
+/// Root of the git work tree holding `path`, found by walking up to `.git`
+pub fn find_work_tree(path: &Path) -> Option<PathBuf> {
+    let path = path.canonicalize().ok()?;
+    path.ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .map(Path::to_path_buf)
+}
+
+/// Commit message from `template` (`{date}`, `{book_count}`,
+/// `{highlight_count}`)
+pub fn render_commit_message(
+    template: &str,
+    date: &str,
+    book_count: usize,
+    highlight_count: usize,
+) -> String {
+    let template = if template.trim().is_empty() {
+        DEFAULT_COMMIT_MESSAGE
+    } else {
+        template
+    };
+    template
+        .replace("{date}", date)
+        .replace("{book_count}", &book_count.to_string())
+        .replace("{highlight_count}", &highlight_count.to_string())
+}
+
+/// Commit `files` to the repository holding `export_dir`
+///
+/// Returns the commit ID, or `None` when the export path is not in a git
+/// work tree or the files are unchanged since `HEAD`. Ignored files and
+/// files outside the work tree are left out.
+pub fn commit_files(
+    export_dir: &Path,
+    files: &[PathBuf],
+    message: &str,
+) -> Result<Option<String>, GitExportError> {
+    let Some(root) = find_work_tree(export_dir) else {
+        return Ok(None);
+    };
+    let repo = Repository::open(&root)?;
+    if repo.head_detached()? {
+        return Err(GitExportError::DetachedHead);
+    }
+    let signature = repo.signature().map_err(GitExportError::MissingIdentity)?;
+
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(e) if e.code() == ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(e.into()),
+    };
+    let baseline = match &parent {
+        Some(commit) => commit.tree()?,
+        None => repo.find_tree(repo.treebuilder(None)?.write()?)?,
+    };
+
+    let mut update = TreeUpdateBuilder::new();
+    let mut staged = Vec::new();
+    for file in files {
+        let Some(relative) = relative_to(&root, file) else {
+            log::warn!("[EXPORTER] {:?} fora do repositório git", file);
+            continue;
+        };
+        if repo.is_path_ignored(&relative)? {
+            continue;
+        }
+        let blob = repo.blob_path(file)?;
+        update.upsert(tree_path(&relative), blob, FileMode::Blob);
+        staged.push(relative);
+    }
+
+    let tree_id = update.create_updated(&repo, &baseline)?;
+    if staged.is_empty() || tree_id == baseline.id() {
+        return Ok(None);
+    }
+    let tree = repo.find_tree(tree_id)?;
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    let commit = repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )?;
+
+    // Stage the committed files so they don't show up as changed, without
+    // touching any other index entry
+    let mut index = repo.index()?;
+    for relative in &staged {
+        index.add_path(relative)?;
+    }
+    index.write()?;
+
+    Ok(Some(commit.to_string()))
+}
+
+/// `file` relative to the work tree `root`
+fn relative_to(root: &Path, file: &Path) -> Option<PathBuf> {
+    let file = file.canonicalize().ok()?;
+    let relative = file.strip_prefix(root).ok()?;
+    relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+        .then(|| relative.to_path_buf())
+}
+
+/// Tree entry path (always `/`-separated)
+fn tree_path(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Why an export could not be committed
+#[derive(Debug)]
+pub enum GitExportError {
+    /// Git error
+    Git(git2::Error),
+    /// HEAD points to a commit instead of a branch
+    DetachedHead,
+    /// `user.name` / `user.email` are not configured
+    MissingIdentity(git2::Error),
+}
+
+impl std::fmt::Display for GitExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GitExportError::Git(e) => write!(f, "Git error: {}", e.message()),
+            GitExportError::DetachedHead => {
+                write!(f, "HEAD is detached; check out a branch to commit exports")
+            }
+            GitExportError::MissingIdentity(_) => write!(
+                f,
+                "No git identity; set user.name and user.email to commit exports"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GitExportError {}
+
+impl From<git2::Error> for GitExportError {
+    fn from(err: git2::Error) -> Self {
+        GitExportError::Git(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn repository(dir: &Path) -> Repository {
+        let repo = Repository::init(dir).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Leitor").unwrap();
+        config.set_str("user.email", "leitor@example.com").unwrap();
+        repo
+    }
+
+    #[test]
+    fn test_message_placeholders() {
+        assert_eq!(
+            render_commit_message(
+                "{date} – {book_count}/{highlight_count}",
+                "2025-01-24",
+                2,
+                7
+            ),
+            "2025-01-24 – 2/7"
+        );
+        assert_eq!(
+            render_commit_message(" ", "2025-01-24", 1, 3),
+            "khi export 2025-01-24: 1 book(s), 3 highlight(s)"
+        );
+    }
+
+    #[test]
+    fn test_detached_head_is_reported() {
+        let temp = TempDir::new().unwrap();
+        let repo = repository(temp.path());
+        let file = temp.path().join("Livro.md");
+        fs::write(&file, "# Livro").unwrap();
+        let first = commit_files(temp.path(), std::slice::from_ref(&file), "primeiro")
+            .unwrap()
+            .unwrap();
+        repo.set_head_detached(git2::Oid::from_str(&first).unwrap())
+            .unwrap();
+
+        fs::write(&file, "# Livro\n\n> novo").unwrap();
+        let result = commit_files(temp.path(), &[file], "segundo");
+        assert!(matches!(result, Err(GitExportError::DetachedHead)));
+    }
+
+    #[test]
+    fn test_outside_a_repository_commits_nothing() {
+        let temp = TempDir::new().unwrap();
+        let file = temp.path().join("Livro.md");
+        fs::write(&file, "# Livro").unwrap();
+        assert!(commit_files(temp.path(), &[file], "export")
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,425 @@
+//! Reading journal export
+//!
+//! Every selected highlight, across books, grouped by the calendar month it
+//! was made in and ordered by date. Each entry is the quote followed by a
+//! short citation line. Highlights without a parseable date go to a final
+//! "Undated" section. With `group_by_color` each month's entries are split
+//! under one heading per color label. Quotes and citations are escaped, so
+//! no highlight or title can add formatting or headings to the journal.
+
+use super::append::highlight_key;
+use super::{color_groups, format_date, is_structural_line, plain_text};
+use crate::models::{Book, ExportConfig, Highlight, JournalLayout};
+use chrono::{Datelike, FixedOffset, NaiveDateTime};
+
+/// Folder of the monthly journal files, in the export folder
+pub const JOURNAL_FOLDER: &str = "Journal";
+
+/// File name of the combined journal
+pub const JOURNAL_FILENAME: &str = "Reading Journal.md";
+
+/// Heading and file stem of the section of highlights without a date
+const UNDATED: &str = "Undated";
+
+const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+struct JournalEntry<'a> {
+    book: &'a Book,
+    highlight: &'a Highlight,
+    created: Option<NaiveDateTime>,
+}
+
+/// Highlights of one calendar month (`None` for the undated ones)
+struct JournalMonth<'a> {
+    month: Option<(i32, u32)>,
+    entries: Vec<JournalEntry<'a>>,
+}
+
+impl JournalMonth<'_> {
+    fn heading(&self) -> String {
+        match self.month {
+            Some((year, month)) => format!("{} {}", MONTH_NAMES[month as usize - 1], year),
+            None => UNDATED.to_string(),
+        }
+    }
+
+    fn file_stem(&self) -> String {
+        match self.month {
+            Some((year, month)) => format!("{:04}-{:02}", year, month),
+            None => UNDATED.to_string(),
+        }
+    }
+}
+
+/// Whether `name` is the name of a monthly journal file (`2025-03.md`,
+/// `Undated.md`)
+pub fn is_journal_file_name(name: &str) -> bool {
+    let Some(stem) = name.strip_suffix(".md") else {
+        return false;
+    };
+    if stem == UNDATED {
+        return true;
+    }
+    let bytes = stem.as_bytes();
+    bytes.len() == 7
+        && bytes[4] == b'-'
+        && bytes
+            .iter()
+            .enumerate()
+            .all(|(i, b)| i == 4 || b.is_ascii_digit())
+}
+
+/// Journal files as (path relative to the export folder, content)
+///
+/// Output only depends on the books: entries on the same date and time are
+/// ordered by highlight ID, so unchanged highlights export identically.
+/// Months and dates are those of the calendar at `offset`.
+pub fn render_journal(
+    books: &[&Book],
+    config: &ExportConfig,
+    offset: FixedOffset,
+) -> Vec<(String, String)> {
+    let months = journal_months(books, offset);
+    match config.journal_layout {
+        JournalLayout::Monthly => months
+            .iter()
+            .map(|month| {
+                (
+                    format!("{}/{}.md", JOURNAL_FOLDER, month.file_stem()),
+                    render_month(month, config).join("\n"),
+                )
+            })
+            .collect(),
+        JournalLayout::Combined if months.is_empty() => Vec::new(),
+        JournalLayout::Combined => {
+            let lines: Vec<String> = months
+                .iter()
+                .flat_map(|month| render_month(month, config))
+                .collect();
+            vec![(JOURNAL_FILENAME.to_string(), lines.join("\n"))]
+        }
+    }
+}
+
+/// Highlights grouped by month, oldest first, undated last
+fn journal_months<'a>(books: &[&'a Book], offset: FixedOffset) -> Vec<JournalMonth<'a>> {
+    let mut entries: Vec<JournalEntry> = books
+        .iter()
+        .flat_map(|book| {
+            book.highlights.iter().map(move |highlight| JournalEntry {
+                book,
+                highlight,
+                created: highlight.created_local(offset),
+            })
+        })
+        .collect();
+
+    // `None` sorts first, so undated entries lead until moved to the end
+    entries.sort_by(|a, b| {
+        a.created
+            .cmp(&b.created)
+            .then_with(|| a.book.title.cmp(&b.book.title))
+            .then_with(|| highlight_key(a.highlight).cmp(highlight_key(b.highlight)))
+    });
+    let undated = entries.iter().take_while(|e| e.created.is_none()).count();
+    entries.rotate_left(undated);
+
+    let mut months: Vec<JournalMonth> = Vec::new();
+    for entry in entries {
+        let month = entry.created.map(|dt| (dt.year(), dt.month()));
+        match months.last_mut() {
+            Some(last) if last.month == month => last.entries.push(entry),
+            _ => months.push(JournalMonth {
+                month,
+                entries: vec![entry],
+            }),
+        }
+    }
+    months
+}
+
+fn render_month(month: &JournalMonth, config: &ExportConfig) -> Vec<String> {
+    let mut lines = vec![format!("# {}", month.heading()), String::new()];
+    if config.group_by_color {
+        let groups = color_groups(&month.entries, |e| e.highlight.color.as_deref(), config);
+        for (label, entries) in groups {
+            lines.push(format!("## {}", label));
+            lines.push(String::new());
+            for entry in entries {
+                push_entry(&mut lines, entry, config);
+            }
+        }
+    } else {
+        for entry in &month.entries {
+            push_entry(&mut lines, entry, config);
+        }
+    }
+    lines
+}
+
+/// Quote of an entry followed by its citation
+fn push_entry(lines: &mut Vec<String>, entry: &JournalEntry, config: &ExportConfig) {
+    for line in plain_text(entry.book, entry.highlight).trim().lines() {
+        let line = escape_inline(line.trim_end());
+        if is_structural_line(&line) {
+            lines.push(format!("> \\{}", line.trim_start()));
+        } else {
+            lines.push(format!("> {}", line).trim_end().to_string());
+        }
+    }
+    lines.push(String::new());
+    lines.push(citation(entry, config));
+    lines.push(String::new());
+}
+
+/// "— *Title*, Author · Chapter · date"
+fn citation(entry: &JournalEntry, config: &ExportConfig) -> String {
+    let mut parts = vec![format!("*{}*", escape_inline(&entry.book.title))];
+    let author = entry.book.display_author();
+    if !author.trim().is_empty() {
+        parts[0] = format!("{}, {}", parts[0], escape_inline(&author));
+    }
+    if let Some(chapter) = &entry.highlight.chapter_title {
+        parts.push(escape_inline(chapter));
+    }
+    if let Some(created) = entry.created {
+        let date = created.format("%Y-%m-%d").to_string();
+        parts.push(format_date(&date, &config.date_format));
+    }
+    format!("— {}", parts.join(" · "))
+}
+
+/// `text` with the characters markdown reads as inline formatting escaped
+pub(super) fn escape_inline(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DateFormat;
+    use crate::settings::AppSettings;
+    use crate::utils::date::{assumed_offset, normalize_highlight_dates, utc_offset};
+
+    fn highlight(id: &str, text: &str, date: &str) -> Highlight {
+        let mut highlight = Highlight::new(id.to_string(), text.to_string(), date.to_string());
+        highlight.stable_id = format!("s-{}", id);
+        highlight
+    }
+
+    fn books() -> Vec<Book> {
+        let mut walden = Book::new(
+            "walden".to_string(),
+            "Walden".to_string(),
+            "Henry David Thoreau".to_string(),
+        );
+        walden.highlights = vec![
+            highlight("w2", "Simplify, simplify.", "2025-03-14T21:00:00"),
+            highlight("w1", "I went to the woods.", "2025-01-02T08:30:00"),
+            highlight("w3", "Undated thought.", "Unknown"),
+        ];
+        walden.highlights[0].chapter_title = Some("Where I Lived".to_string());
+
+        let mut memorial = Book::new(
+            "memorial".to_string(),
+            "Memorial do Convento".to_string(),
+            "José Saramago".to_string(),
+        );
+        memorial.highlights = vec![
+            // Same day without a time: ordered by stable ID
+            highlight("m2", "Segundo", "2025-03-14"),
+            highlight("m1", "Primeiro", "2025-03-14"),
+            highlight("m3", "Linha um\nLinha dois", "2025-02-28T23:59:59Z"),
+            highlight("m4", "Sem data", ""),
+        ];
+        vec![walden, memorial]
+    }
+
+    fn config(layout: JournalLayout) -> ExportConfig {
+        let mut config = AppSettings::default().export_config;
+        config.journal_layout = layout;
+        config.date_format = DateFormat::Iso8601;
+        config
+    }
+
+    #[test]
+    fn test_monthly_files_in_order() {
+        let books = books();
+        let refs: Vec<&Book> = books.iter().collect();
+        let files = render_journal(&refs, &config(JournalLayout::Monthly), utc_offset());
+
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "Journal/2025-01.md",
+                "Journal/2025-02.md",
+                "Journal/2025-03.md",
+                "Journal/Undated.md"
+            ]
+        );
+
+        assert_eq!(
+            files[1].1,
+            "# February 2025\n\n> Linha um\n> Linha dois\n\n\
+             — *Memorial do Convento*, José Saramago · 2025-02-28\n"
+        );
+
+        let march = &files[2].1;
+        assert!(march.starts_with("# March 2025\n"));
+        let primeiro = march.find("Primeiro").unwrap();
+        let segundo = march.find("Segundo").unwrap();
+        let simplify = march.find("Simplify").unwrap();
+        assert!(primeiro < segundo && segundo < simplify);
+        assert!(march.contains("— *Walden*, Henry David Thoreau · Where I Lived · 2025-03-14"));
+
+        // Undated entries have no date in their citation
+        let undated = &files[3].1;
+        assert!(undated.starts_with("# Undated\n"));
+        assert!(undated.contains("— *Memorial do Convento*, José Saramago\n"));
+        assert!(undated.find("Sem data") < undated.find("Undated thought"));
+    }
+
+    #[test]
+    fn test_mixed_firmware_dates_interleave() {
+        let mut book = Book::new("b".to_string(), "Book".to_string(), "Author".to_string());
+        book.highlights = vec![
+            // Epoch milliseconds: 2025-03-01T00:00:00Z
+            highlight("b", "Beta", "1740787200000"),
+            // Local time at +02:00: 2025-02-28T23:30:00Z
+            highlight("a", "Older", "2025-03-01T01:30:00"),
+            highlight("c", "Newer", "2025-02-28T23:45:00.000Z"),
+        ];
+        let mut books = vec![book];
+        normalize_highlight_dates(&mut books, assumed_offset(120));
+        let refs: Vec<&Book> = books.iter().collect();
+        let files = render_journal(&refs, &config(JournalLayout::Monthly), utc_offset());
+
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["Journal/2025-02.md", "Journal/2025-03.md"]);
+        assert!(files[0].1.find("Older") < files[0].1.find("Newer"));
+        assert!(files[1].1.contains("Beta"));
+        assert!(files[1].1.contains("· 2025-03-01"));
+
+        // On the reader's calendar all three were made on March 1st
+        let files = render_journal(&refs, &config(JournalLayout::Monthly), assumed_offset(120));
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].0, "Journal/2025-03.md");
+        let march = &files[0].1;
+        assert!(march.find("Older") < march.find("Newer"));
+        assert!(march.find("Newer") < march.find("Beta"));
+        assert_eq!(march.matches("· 2025-03-01").count(), 3);
+    }
+
+    #[test]
+    fn test_bare_dates_keep_their_day_east_of_utc() {
+        let mut book = Book::new("b".to_string(), "Book".to_string(), "Author".to_string());
+        book.highlights = vec![highlight("a", "Alpha", "2025-03-01")];
+        let mut books = vec![book];
+        normalize_highlight_dates(&mut books, assumed_offset(120));
+        let refs: Vec<&Book> = books.iter().collect();
+
+        let files = render_journal(&refs, &config(JournalLayout::Monthly), assumed_offset(120));
+        assert_eq!(files[0].0, "Journal/2025-03.md");
+        assert!(files[0].1.contains("· 2025-03-01"));
+    }
+
+    #[test]
+    fn test_combined_file_is_reproducible() {
+        let books = books();
+        let refs: Vec<&Book> = books.iter().collect();
+        let files = render_journal(&refs, &config(JournalLayout::Combined), utc_offset());
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].0, JOURNAL_FILENAME);
+
+        let headings: Vec<&str> = files[0]
+            .1
+            .lines()
+            .filter(|line| line.starts_with("# "))
+            .collect();
+        assert_eq!(
+            headings,
+            vec![
+                "# January 2025",
+                "# February 2025",
+                "# March 2025",
+                "# Undated"
+            ]
+        );
+
+        // Input order doesn't matter
+        let reversed: Vec<&Book> = books.iter().rev().collect();
+        assert_eq!(
+            render_journal(&reversed, &config(JournalLayout::Combined), utc_offset()),
+            files
+        );
+        assert!(render_journal(&[], &config(JournalLayout::Combined), utc_offset()).is_empty());
+    }
+
+    #[test]
+    fn test_group_by_color_splits_each_month() {
+        let mut books = books();
+        books[0].highlights[0].color = Some("blue".to_string());
+        let refs: Vec<&Book> = books.iter().collect();
+        let mut config = config(JournalLayout::Monthly);
+        config.group_by_color = true;
+        config.color_labels = [("blue".to_string(), "Ideias".to_string())].into();
+
+        let files = render_journal(&refs, &config, utc_offset());
+        let march = &files[2].1;
+        let headings: Vec<&str> = march.lines().filter(|l| l.starts_with("## ")).collect();
+        assert_eq!(headings, vec!["## Ideias", "## Sem cor"]);
+        assert!(march.find("Simplify") < march.find("## Sem cor"));
+        assert!(march.find("## Sem cor") < march.find("Primeiro"));
+    }
+
+    #[test]
+    fn test_quotes_and_citations_are_escaped() {
+        let mut book = Book::new(
+            "b".to_string(),
+            "The *Real* Book_Title".to_string(),
+            "A [Author]".to_string(),
+        );
+        let mut entry = highlight("a", "# Not a heading\n---\nSee *this* <b>", "2025-03-01");
+        entry.chapter_title = Some("Chapter `1`".to_string());
+        book.highlights = vec![entry];
+        let refs = vec![&book];
+
+        let files = render_journal(&refs, &config(JournalLayout::Monthly), utc_offset());
+        assert_eq!(
+            files[0].1,
+            "# March 2025\n\n\
+             > \\# Not a heading\n> \\---\n> See \\*this\\* \\<b\\>\n\n\
+             — *The \\*Real\\* Book\\_Title*, A \\[Author\\] · Chapter \\`1\\` · 2025-03-01\n"
+        );
+    }
+
+    #[test]
+    fn test_journal_file_names() {
+        assert!(is_journal_file_name("2025-03.md"));
+        assert!(is_journal_file_name("Undated.md"));
+        assert!(!is_journal_file_name("2025-03.txt"));
+        assert!(!is_journal_file_name("2025-3.md"));
+        assert!(!is_journal_file_name("My notes.md"));
+    }
+}
@@ -0,0 +1,247 @@
+//! Logseq outliner pages
+//!
+//! Logseq expects bullet-structured pages instead of free-form markdown: page
+//! properties (`title::`, `author::`) replace the heading, every highlight is
+//! a top-level `- > quote` bullet with `location::` and `date::` properties,
+//! and its note is a nested bullet. With `group_by_color` the highlights are
+//! nested under one bullet per color label.
+
+use super::{color_groups, format_date, ExportBookData, ExportHighlightData};
+use crate::models::ExportConfig;
+
+/// Render a book as a Logseq page
+pub fn render_logseq(data: &ExportBookData, config: &ExportConfig) -> String {
+    let indent = config.bullet_indentation.as_str();
+    let mut lines: Vec<String> = Vec::new();
+
+    // Page properties are single-line values
+    let mut property = |name: &str, value: &str| {
+        let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !value.is_empty() {
+            lines.push(format!("{}:: {}", name, value));
+        }
+    };
+    property("title", &data.title);
+    if config.metadata.author {
+        property("author", &data.author);
+    }
+    if config.metadata.isbn {
+        property("isbn", data.isbn.as_deref().unwrap_or_default());
+    }
+    if config.metadata.publisher {
+        property("publisher", data.publisher.as_deref().unwrap_or_default());
+    }
+    if config.metadata.language {
+        property("language", data.language.as_deref().unwrap_or_default());
+    }
+    if config.metadata.date_last_read {
+        property("read-date", data.read_date.as_deref().unwrap_or_default());
+    }
+    if config.metadata.description {
+        property(
+            "description",
+            data.description.as_deref().unwrap_or_default(),
+        );
+    }
+    lines.push(String::new());
+
+    if config.group_by_color {
+        let groups = color_groups(&data.highlights, |h| h.color.as_deref(), config);
+        for (label, highlights) in groups {
+            push_block(&mut lines, "", [label]);
+            for highlight in highlights {
+                push_highlight(&mut lines, highlight, config, indent);
+            }
+        }
+    } else {
+        for highlight in &data.highlights {
+            push_highlight(&mut lines, highlight, config, "");
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// Quote bullet of a highlight at `level`, with its note nested below
+fn push_highlight(
+    lines: &mut Vec<String>,
+    highlight: &ExportHighlightData,
+    config: &ExportConfig,
+    level: &str,
+) {
+    let quote: Vec<String> = highlight
+        .text
+        .trim()
+        .lines()
+        .map(|line| match line.trim_end() {
+            "" => ">".to_string(),
+            line => format!("> {}", line),
+        })
+        .collect();
+    let mut properties = Vec::new();
+    if config.show_location && !highlight.location.is_empty() {
+        properties.push(format!("location:: {}", highlight.location));
+    }
+    let date = highlight.date.get(..10).unwrap_or(&highlight.date);
+    if !date.is_empty() {
+        properties.push(format!("date:: {}", format_date(date, &config.date_format)));
+    }
+    push_block(lines, level, quote.into_iter().chain(properties));
+
+    if let Some(note) = highlight.note.as_deref().map(str::trim) {
+        if !note.is_empty() {
+            let nested = format!("{}{}", level, config.bullet_indentation.as_str());
+            push_block(lines, &nested, note.lines().map(str::to_string));
+        }
+    }
+}
+
+/// One bullet at `indent`: the first line after `- `, the rest as
+/// continuation lines aligned with it
+fn push_block(lines: &mut Vec<String>, indent: &str, block: impl IntoIterator<Item = String>) {
+    for (index, line) in block.into_iter().enumerate() {
+        if index == 0 {
+            lines.push(format!("{}- {}", indent, line));
+        } else {
+            lines.push(format!("{}  {}", indent, line));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::MarkdownExporter;
+    use crate::models::{
+        Book, BulletIndentation, DateFormat, ExportFormat, Highlight, MetadataConfig,
+    };
+    use crate::settings::AppSettings;
+    use tempfile::TempDir;
+
+    fn fixture_book() -> Book {
+        let mut book = Book::new(
+            "vol1".to_string(),
+            "Walden".to_string(),
+            "Henry David Thoreau".to_string(),
+        );
+        book.publisher = Some("Ticknor and Fields".to_string());
+        book.language = Some("en".to_string());
+
+        let mut first = Highlight::new(
+            "hl1".to_string(),
+            "The mass of men lead lives of quiet desperation.".to_string(),
+            "2024-11-02T19:22:10.000".to_string(),
+        );
+        first.chapter_title = Some("Economy".to_string());
+        first.chapter_progress = Some(0.04);
+        first.annotation = Some("Still true.".to_string());
+        let mut second = Highlight::new(
+            "hl2".to_string(),
+            "Rather than love, than money, than fame, give me truth.".to_string(),
+            "2024-11-30T20:58:51.000".to_string(),
+        );
+        second.chapter_title = Some("Conclusion".to_string());
+        second.page = Some(312);
+        book.highlights = vec![first, second];
+        book
+    }
+
+    fn logseq_config() -> ExportConfig {
+        ExportConfig {
+            format: ExportFormat::Logseq,
+            date_format: DateFormat::Iso8601,
+            metadata: MetadataConfig {
+                author: true,
+                isbn: true,
+                publisher: true,
+                date_last_read: true,
+                language: true,
+                description: true,
+                stats: false,
+                series: false,
+                vocabulary: false,
+                loan: false,
+                book_notes: false,
+                book_notes_raw: false,
+                order: Vec::new(),
+            },
+            ..AppSettings::default().export_config
+        }
+    }
+
+    fn render(book: &Book, config: &ExportConfig) -> String {
+        let exporter = MarkdownExporter::new(TempDir::new().unwrap().path().to_path_buf());
+        render_logseq(&exporter.export_book_data(book, config), config)
+    }
+
+    #[test]
+    fn test_logseq_golden_page() {
+        let expected = "\
+title:: Walden
+author:: Henry David Thoreau
+publisher:: Ticknor and Fields
+language:: en
+
+- > The mass of men lead lives of quiet desperation.
+  location:: Economy · 4%
+  date:: 2024-11-02
+\t- Still true.
+- > Rather than love, than money, than fame, give me truth.
+  location:: Conclusion · p. 312
+  date:: 2024-11-30";
+
+        assert_eq!(render(&fixture_book(), &logseq_config()), expected);
+    }
+
+    #[test]
+    fn test_multi_paragraph_highlight_stays_in_one_bullet() {
+        let mut book = fixture_book();
+        book.highlights.truncate(1);
+        book.highlights[0].text = "First paragraph.\n\nSecond paragraph.".to_string();
+        book.highlights[0].annotation = Some("Line one\nline two".to_string());
+        let mut config = logseq_config();
+        config.bullet_indentation = BulletIndentation::TwoSpaces;
+
+        let page = render(&book, &config);
+        let body: Vec<&str> = page.lines().skip_while(|l| !l.starts_with("- ")).collect();
+        assert_eq!(
+            body,
+            vec![
+                "- > First paragraph.",
+                "  >",
+                "  > Second paragraph.",
+                "  location:: Economy · 4%",
+                "  date:: 2024-11-02",
+                "  - Line one",
+                "    line two",
+            ]
+        );
+        assert_eq!(page.lines().filter(|l| l.starts_with("- ")).count(), 1);
+    }
+
+    #[test]
+    fn test_group_by_color_nests_highlights_under_labels() {
+        let mut book = fixture_book();
+        book.highlights[1].color = Some("yellow".to_string());
+        let mut config = logseq_config();
+        config.group_by_color = true;
+        config.color_labels = [("yellow".to_string(), "Key ideas".to_string())].into();
+
+        let page = render(&book, &config);
+        let body: Vec<&str> = page.lines().skip_while(|l| !l.starts_with("- ")).collect();
+        assert_eq!(
+            body,
+            vec![
+                "- Key ideas",
+                "\t- > Rather than love, than money, than fame, give me truth.",
+                "\t  location:: Conclusion · p. 312",
+                "\t  date:: 2024-11-30",
+                "- Sem cor",
+                "\t- > The mass of men lead lives of quiet desperation.",
+                "\t  location:: Economy · 4%",
+                "\t  date:: 2024-11-02",
+                "\t\t- Still true.",
+            ]
+        );
+    }
+}
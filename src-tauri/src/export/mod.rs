@@ -1,9 +1,76 @@
-use crate::models::{Book, DateFormat, ExportConfig, Highlight};
-use chrono::Datelike;
-use serde::Serialize;
-use std::fs;
-use std::io::Write;
+pub mod adopt;
+pub mod append;
+pub mod citation;
+pub mod clipboard;
+pub mod diff;
+pub mod exclusions;
+pub mod feed;
+pub mod git;
+pub mod journal;
+pub mod logseq;
+pub mod ndjson;
+pub mod parts;
+pub mod preview;
+pub mod redaction;
+pub mod sidecar;
+pub mod sink;
+pub mod snapshot;
+pub mod staging;
+pub mod style;
+pub mod tabular;
+pub mod template;
+pub mod throttle;
+pub mod verify;
+pub mod watch;
+
+use crate::models::{
+    color_label, parse_highlight_date, Book, BookKind, BookStats, DateFormat, ExportConfig,
+    ExportFormat, ExportWriteMode, Highlight, HighlightKind, HighlightSeparator, JournalLayout,
+    LoanState, MetadataField, ProgressScope, TitleForm, TocEntry,
+};
+use crate::scheduler::{Clock, SystemClock};
+use crate::settings::SortPreference;
+use crate::utils::author::{author_sort_key, parse_authors};
+use crate::utils::cancel::{CancellationToken, Cancelled};
+use crate::utils::cloud::{detect_cloud_provider, CloudProvider};
+use crate::utils::date::utc_offset;
+use crate::utils::disambiguation::{book_order, disambiguators};
+use crate::utils::fs::{
+    atomic_write_text, atomic_write_with, is_disk_full, CloudSyncOps, FileOps, SystemFileOps,
+    TextEncoder, TextEncoding, RENAME_RETRY_DELAYS,
+};
+use crate::utils::language::{is_language_folder_name, language_folder_name};
+use crate::utils::metrics::{Metrics, MetricsSummary};
+use crate::utils::path::file_url;
+use crate::utils::slug::{book_slug, content_hash};
+use crate::utils::text::{ascii_filename, sanitize_filename};
+use crate::utils::titles::TitleOptions;
+use append::{anchors_in, append_section, highlight_anchor, highlight_key, manifest_key};
+use chrono::{Datelike, FixedOffset, NaiveDateTime};
+use citation::render_records;
+use diff::{diff_export, ExportDiff};
+use exclusions::{BookChapterExclusions, ChapterRules};
+use feed::{generate_atom_feed, FEED_FILENAME};
+use journal::render_journal;
+use logseq::render_logseq;
+use ndjson::{write_ndjson, NDJSON_FILENAME};
+use parts::LARGE_BOOK_HIGHLIGHTS;
+use preview::PreviewCache;
+use redaction::RedactionRules;
+use serde::{Deserialize, Serialize};
+use sink::{BlockSpan, IoSink, MarkdownSink};
+use staging::{BookFinish, Staging};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
+use style::HighlightParts;
 
 /// Structured data for a single highlight (for frontend export)
 #[derive(Serialize)]
@@ -14,6 +81,7 @@ pub struct ExportHighlightData {
     pub location: String,
     pub date: String,
     pub note: Option<String>,
+    pub color: Option<String>,
     pub is_edited: bool,
 }
 
@@ -27,509 +95,5817 @@ pub struct ExportBookData {
     pub language: Option<String>,
     pub read_date: Option<String>,
     pub description: Option<String>,
+    pub toc: Vec<TocEntry>,
     pub highlights: Vec<ExportHighlightData>,
 }
 
+/// Receiver for export lifecycle events
+///
+/// Keeps the exporter independent of the Tauri `AppHandle`; the command layer
+/// forwards these to the frontend, tests can record them.
+pub trait EventSink {
+    fn send(&self, event: &str, payload: serde_json::Value);
+}
+
+/// Sink that drops every event (used for silent/programmatic exports)
+pub struct NoopSink;
+
+impl EventSink for NoopSink {
+    fn send(&self, _event: &str, _payload: serde_json::Value) {}
+}
+
+/// Event emitted when an export starts ("export-started")
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportStartedEvent {
+    pub total_books: usize,
+    pub destination: String,
+}
+
+/// Outcome of a single book in an export
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportBookStatus {
+    Exported,
+    Failed,
+}
+
+/// Event sent after each book is processed
+pub const EXPORT_PROGRESS: &str = "export-progress";
+
+/// Event emitted after each book is processed ("export-progress")
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportProgressEvent {
+    pub index: usize,
+    pub total_books: usize,
+    pub title: String,
+    pub status: ExportBookStatus,
+    pub path: Option<String>,
+    pub error: Option<String>,
+}
+
+/// A book that failed to export
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportFailure {
+    pub title: String,
+    pub error: String,
+}
+
+/// Summary of an export run, payload of "export-finished"
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportReport {
+    pub destination: String,
+    pub total_books: usize,
+    pub exported_files: Vec<String>,
+    pub failures: Vec<ExportFailure>,
+    /// Size of the single file written by records and NDJSON formats
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes_written: Option<u64>,
+    /// Why the run stopped before exporting every book (disk full)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aborted: Option<String>,
+    /// Highlights left out because their chapter is excluded
+    #[serde(default)]
+    pub excluded_by_chapter: usize,
+    /// Highlights left out or with their note redacted as private
+    #[serde(default)]
+    pub redacted: usize,
+    /// Where the run's time went (render, write, …)
+    #[serde(default, skip_serializing_if = "MetricsSummary::is_empty")]
+    pub metrics: MetricsSummary,
+    /// Commit of the exported files (`git_commit_after_export`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_commit: Option<String>,
+    /// Problems that didn't fail the export (git commits)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// BOM and line endings the text files were written with
+    #[serde(default)]
+    pub encoding: TextEncoding,
+    /// Sync service of the export folder, when it is cloud-synced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cloud_provider: Option<CloudProvider>,
+    /// Set when the run was cancelled or timed out before every book was
+    /// written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cancelled: Option<Cancelled>,
+    /// A transactional run discarded everything it wrote; the export folder
+    /// is as it was before the run
+    #[serde(
+        default,
+        alias = "rolled_back",
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub rolled_back: bool,
+}
+
+/// BOM and line endings of the files `config` exports; JSON formats are
+/// always plain UTF-8 with `\n`, as JSON parsers expect
+pub fn output_encoding(config: &ExportConfig) -> TextEncoding {
+    match config.format {
+        ExportFormat::CslJson | ExportFormat::Ndjson => TextEncoding::default(),
+        _ => TextEncoding {
+            bom: config.bom,
+            line_endings: config.line_endings,
+        },
+    }
+}
+
+fn send_event<T: Serialize>(sink: &dyn EventSink, event: &str, payload: &T) {
+    match serde_json::to_value(payload) {
+        Ok(value) => sink.send(event, value),
+        Err(e) => log::error!("[EXPORTER] Failed to serialize {} event: {}", event, e),
+    }
+}
+
+/// Check an export config before exporting with it
+pub fn validate_export_config(config: &ExportConfig) -> Result<(), String> {
+    if matches!(config.format, ExportFormat::Csv | ExportFormat::Tsv) {
+        tabular::validate_tabular_options(&config.tabular)?;
+    }
+    redaction::validate_redaction_markers(&config.redaction_markers)?;
+    if config.group_by_color && config.include_toc {
+        return Err(
+            "Grouping highlights by color can't be combined with chapter headings (table of contents)"
+                .to_string(),
+        );
+    }
+    if template::is_custom(config) {
+        template::check_template(&config.markdown_template)?;
+        if config.write_mode == ExportWriteMode::Append {
+            return Err("Markdown templates can't be used with append mode".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Record the run's git commit, or why there is none, in `report`
+fn report_commit(result: Result<Option<String>, git::GitExportError>, report: &mut ExportReport) {
+    match result {
+        Ok(commit) => report.git_commit = commit,
+        Err(e) => report
+            .warnings
+            .push(format!("Export not committed to git: {}", e)),
+    }
+}
+
+/// Highlights touched by `MarkdownExporter::apply_export_rules`
+#[derive(Debug, Default)]
+struct RuleCounts {
+    excluded_by_chapter: usize,
+    redacted: usize,
+    /// Excluded in the library (deleted on the device)
+    hidden: usize,
+}
+
+impl RuleCounts {
+    fn log(&self) {
+        if self.excluded_by_chapter > 0 {
+            log::info!(
+                "[EXPORTER] {} destaque(s) em capítulos excluídos",
+                self.excluded_by_chapter
+            );
+        }
+        if self.redacted > 0 {
+            log::info!(
+                "[EXPORTER] {} destaque(s) privado(s) redigido(s)",
+                self.redacted
+            );
+        }
+        if self.hidden > 0 {
+            log::info!(
+                "[EXPORTER] {} destaque(s) excluído(s) omitido(s)",
+                self.hidden
+            );
+        }
+    }
+}
+
+/// Manifest entry of one written file, saved with the rest of its run's
+#[derive(Debug)]
+struct ManifestRecord {
+    /// See `manifest_key`
+    key: String,
+    slug: String,
+    hash: String,
+    /// Highlight keys, when the append-mode manifest tracks the file
+    highlights: Option<Vec<String>>,
+}
+
+/// Name of the library index written to the export root
+pub const BOOKSHELF_FILENAME: &str = "_Bookshelf.md";
+
 pub struct MarkdownExporter {
     export_dir: PathBuf,
+    index_sort: SortPreference,
+    file_ops: Box<dyn FileOps>,
+    chapter_exclusions: BookChapterExclusions,
+    /// Serializes read-modify-write cycles of the append-mode manifest
+    manifest_lock: Mutex<()>,
+    /// Spans of the current run, drained into its `ExportReport`
+    metrics: Metrics,
+    /// Renders shared with previews and diffs, and the library revision
+    /// the exported books are at
+    render_cache: Option<(PreviewCache, u64)>,
+    /// Sync service of the export folder; its renames are retried
+    cloud_provider: Option<CloudProvider>,
+    /// Pause after each file written to a cloud-synced folder
+    cloud_settle: Duration,
+    /// Checked before each book is written
+    cancellation: CancellationToken,
+    /// Title form of headings, filenames and the index order
+    title_options: TitleOptions,
+    /// Offset dates are shown at (the reader's assumed offset)
+    assumed_offset: FixedOffset,
+    /// Disambiguators of the library's books; others are told apart
+    /// within the batch
+    library_disambiguators: HashMap<String, Option<String>>,
+    /// Time loans are judged against
+    clock: Box<dyn Clock + Sync>,
 }
 
 impl MarkdownExporter {
     pub fn new(export_dir: PathBuf) -> Self {
+        match Self::try_new(export_dir) {
+            Ok(exporter) => exporter,
+            Err(e) => panic!("Failed to create export directory: {}", e),
+        }
+    }
+
+    /// Like `new`, but an export directory that can't be created is an error
+    pub fn try_new(export_dir: PathBuf) -> Result<Self, ExportError> {
         log::info!("[EXPORTER] Criando MarkdownExporter");
         log::info!("[EXPORTER] Export directory: {:?}", export_dir);
 
         // Ensure export directory exists
         if !export_dir.exists() {
             log::info!("[EXPORTER] Diretório não existe, a criar...");
-            match fs::create_dir_all(&export_dir) {
-                Ok(_) => log::info!("[EXPORTER] ✅ Diretório criado com sucesso"),
-                Err(e) => {
-                    log::error!("[EXPORTER] ❌ Erro fatal ao criar diretório: {}", e);
-                    panic!("Failed to create export directory: {}", e);
-                }
+            if let Err(e) = fs::create_dir_all(&export_dir) {
+                log::error!("[EXPORTER] ❌ Erro fatal ao criar diretório: {}", e);
+                return Err(e.into());
             }
+            log::info!("[EXPORTER] ✅ Diretório criado com sucesso");
         } else {
             log::info!("[EXPORTER] ✅ Diretório já existe");
         }
-        Self { export_dir }
+        Ok(Self::detached(export_dir))
     }
 
-    /// Export a single book to markdown
-    pub fn export_book(&self, book: &Book, config: &ExportConfig) -> Result<PathBuf, ExportError> {
-        log::info!("[EXPORTER] A exportar livro: '{}'", book.title);
+    /// An exporter that only plans or renders: the export directory is
+    /// left alone, even when missing
+    pub fn detached(export_dir: PathBuf) -> Self {
+        let cloud_provider = detect_cloud_provider(&export_dir.to_string_lossy());
+        if let Some(provider) = cloud_provider {
+            log::info!("[EXPORTER] Pasta sincronizada com {}", provider.name());
+        }
+        Self {
+            export_dir,
+            index_sort: SortPreference::default(),
+            file_ops: Box::new(SystemFileOps),
+            chapter_exclusions: BookChapterExclusions::new(),
+            manifest_lock: Mutex::new(()),
+            metrics: Metrics::new(),
+            render_cache: None,
+            cloud_provider,
+            cloud_settle: Duration::ZERO,
+            cancellation: CancellationToken::new(),
+            title_options: TitleOptions::default(),
+            assumed_offset: utc_offset(),
+            library_disambiguators: HashMap::new(),
+            clock: Box::new(SystemClock),
+        }
+    }
 
-        log::info!("[EXPORTER] A gerar filename...");
-        let filename = generate_filename(book);
-        log::info!("[EXPORTER] Filename gerado: {}", filename);
+    /// Order used for the rows of the bookshelf index
+    pub fn with_index_sort(mut self, sort: SortPreference) -> Self {
+        self.index_sort = sort;
+        self
+    }
 
-        let file_path = self.export_dir.join(&filename);
-        log::info!("[EXPORTER] Path completo: {:?}", file_path);
+    /// File operations used for writing, replaceable to simulate failures
+    pub fn with_file_ops(mut self, file_ops: Box<dyn FileOps>) -> Self {
+        self.file_ops = file_ops;
+        self
+    }
 
-        log::info!("[EXPORTER] A gerar markdown...");
-        let markdown = self.generate_markdown(book, config);
-        log::info!("[EXPORTER] Markdown gerado ({} bytes)", markdown.len());
+    /// Pause after each file written, when the export folder is cloud-synced
+    pub fn with_cloud_settle(mut self, settle: Duration) -> Self {
+        self.cloud_settle = settle;
+        self
+    }
 
-        log::info!("[EXPORTER] A criar ficheiro...");
-        let mut file = fs::File::create(&file_path)?;
-        log::info!("[EXPORTER] A escrever conteúdo...");
-        file.write_all(markdown.as_bytes())?;
-        log::info!(
-            "[EXPORTER] ✅ Ficheiro escrito com sucesso: {:?}",
-            file_path
-        );
+    /// Stop writing books once `token` is cancelled or past its deadline
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = token;
+        self
+    }
 
-        Ok(file_path)
+    /// Which title form (raw or processed) headings, filenames and the
+    /// bookshelf order use
+    pub fn with_title_options(mut self, options: TitleOptions) -> Self {
+        self.title_options = options;
+        self
     }
 
-    /// Export multiple books to markdown files
-    pub fn export_books(
-        &self,
-        books: &[Book],
-        config: &ExportConfig,
-    ) -> Vec<Result<PathBuf, ExportError>> {
-        log::info!("[EXPORTER] ==========================================");
-        log::info!(
-            "[EXPORTER] Iniciando exportação de {} livro(s)",
-            books.len()
-        );
-        log::info!("[EXPORTER] Diretório de exportação: {:?}", self.export_dir);
+    /// Show highlight dates on the calendar at `offset` instead of UTC
+    pub fn with_assumed_offset(mut self, offset: FixedOffset) -> Self {
+        self.assumed_offset = offset;
+        self
+    }
 
-        // Verificar se diretório existe
-        if !self.export_dir.exists() {
-            log::info!("[EXPORTER] Diretório não existe, a criar...");
-            match fs::create_dir_all(&self.export_dir) {
-                Ok(_) => log::info!("[EXPORTER] ✅ Diretório criado com sucesso"),
-                Err(e) => {
-                    log::error!("[EXPORTER] ❌ Falha ao criar diretório: {}", e);
-                    return vec![Err(ExportError::Io(e))];
-                }
-            }
-        } else {
-            log::info!("[EXPORTER] ✅ Diretório já existe");
+    /// Disambiguators from the whole library (`LibraryStore::disambiguators`),
+    /// so a book's filename doesn't depend on which siblings are exported
+    /// with it
+    pub fn with_disambiguators(mut self, disambiguators: HashMap<String, Option<String>>) -> Self {
+        self.library_disambiguators = disambiguators;
+        self
+    }
+
+    /// Judge loans (expired or not) at the time of `clock`
+    pub fn with_clock(mut self, clock: Box<dyn Clock + Sync>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Disambiguator of each of `books`: the library's, or from the batch
+    /// for books the library doesn't hold
+    fn disambiguators_of(&self, books: &[Book]) -> Vec<Option<String>> {
+        books
+            .iter()
+            .zip(disambiguators(books))
+            .map(
+                |(book, in_batch)| match self.library_disambiguators.get(&book.content_id) {
+                    Some(in_library) => in_library.clone(),
+                    None => in_batch,
+                },
+            )
+            .collect()
+    }
+
+    /// File operations of one write: on cloud-synced folders, renames
+    /// blocked by the sync daemon are retried
+    fn write_ops(&self) -> CloudSyncOps<'_> {
+        match self.cloud_provider {
+            Some(_) => CloudSyncOps::new(
+                self.file_ops.as_ref(),
+                RENAME_RETRY_DELAYS,
+                self.cloud_settle,
+            ),
+            None => CloudSyncOps::new(self.file_ops.as_ref(), &[], Duration::ZERO),
         }
+    }
 
-        let mut results = Vec::new();
+    /// Per-book excluded chapters (titles or spine files), by content ID
+    pub fn with_chapter_exclusions(mut self, exclusions: BookChapterExclusions) -> Self {
+        self.chapter_exclusions = exclusions;
+        self
+    }
 
-        for (i, book) in books.iter().enumerate() {
-            log::info!(
-                "[EXPORTER] --- A processar livro {}/{} ---",
-                i + 1,
-                books.len()
-            );
-            let result = self.export_book(book, config);
-            results.push(result);
+    /// Reuse the renders of `cache` for books at library `revision`, so a
+    /// book previewed or diffed with the same config isn't rendered again
+    /// (no revision, no cache)
+    pub fn with_render_cache(mut self, cache: PreviewCache, revision: Option<u64>) -> Self {
+        self.render_cache = revision.map(|revision| (cache, revision));
+        self
+    }
+
+    /// The render cache, unless titles are shown raw: previews always show
+    /// the processed title, so raw-title renders aren't shared with them
+    fn render_cache(&self) -> Option<(&PreviewCache, u64)> {
+        match &self.render_cache {
+            Some((cache, revision)) if self.title_options.display == TitleForm::Processed => {
+                Some((cache, *revision))
+            }
+            _ => None,
         }
+    }
 
-        let success_count = results.iter().filter(|r| r.is_ok()).count();
-        let error_count = results.len() - success_count;
-        log::info!("[EXPORTER] ==========================================");
-        log::info!(
-            "[EXPORTER] Exportação concluída: {} sucesso, {} erro(s)",
-            success_count,
-            error_count
+    fn count_render_cache(&self, hit: bool) {
+        self.metrics.add(
+            if hit {
+                "render_cache_hits"
+            } else {
+                "render_cache_misses"
+            },
+            1,
         );
+    }
 
-        results
+    /// `render_book`, through (and into) the render cache when the exporter
+    /// has one
+    fn cached_render(&self, book: &Book, config: &ExportConfig) -> String {
+        let Some((cache, revision)) = self.render_cache() else {
+            return self.render_book(book, config);
+        };
+        let (rendered, hit) = cache.get_or_render(
+            book,
+            Some(revision),
+            config,
+            &self.chapter_exclusions,
+            self.assumed_offset,
+            || self.render_book(book, config),
+        );
+        self.count_render_cache(hit);
+        rendered
     }
 
-    /// Export book as structured data for frontend processing
-    pub fn export_book_data(&self, book: &Book, config: &ExportConfig) -> ExportBookData {
-        // Use all highlights (editing features removed)
-        let highlights: Vec<&Highlight> = book.highlights.iter().collect();
+    /// The cached render of `book`, if any; a miss renders and caches
+    /// nothing, so a batch export doesn't fill the cache with every book
+    fn cache_hit(&self, book: &Book, config: &ExportConfig) -> Option<String> {
+        let (cache, revision) = self.render_cache()?;
+        let cached = cache.cached(
+            &book.content_id,
+            revision,
+            config,
+            &self.chapter_exclusions,
+            self.assumed_offset,
+        );
+        self.count_render_cache(cached.is_some());
+        cached
+    }
 
-        // Convert highlights to export data
-        let highlights_data: Vec<ExportHighlightData> = highlights
+    /// Books as exported: without excluded highlights (`is_excluded`) or
+    /// the highlights of excluded chapters, and with private notes redacted
+    /// (see `redaction`), plus how many highlights each rule touched
+    ///
+    /// Every export path goes through here, so nothing else filters
+    /// `is_excluded`.
+    fn apply_export_rules<'b>(
+        &self,
+        books: &'b [Book],
+        config: &ExportConfig,
+    ) -> (Cow<'b, [Book]>, RuleCounts) {
+        let rules = ChapterRules::new(&self.chapter_exclusions, &config.excluded_chapter_patterns);
+        let redaction = RedactionRules::new(config);
+        let disambiguators = self.disambiguators_of(books);
+        let mut counts = RuleCounts::default();
+        let relabel = books
             .iter()
-            .map(|h| {
-                // Build location string
-                let mut location_parts: Vec<String> = Vec::new();
-                if let Some(chapter_title) = &h.chapter_title {
-                    location_parts.push(chapter_title.clone());
-                }
-                if let Some(progress) = h.chapter_progress {
-                    location_parts.push(format!("{}%", (progress * 100.0) as i32));
-                }
-                let location = location_parts.join(" · ");
+            .zip(&disambiguators)
+            .any(|(book, disambiguator)| book.disambiguator != *disambiguator);
+        let has_hidden = |book: &Book| book.highlights.iter().any(|h| h.is_excluded);
+        if rules.is_empty()
+            && !relabel
+            && !books
+                .iter()
+                .any(|book| redaction.touches(book) || has_hidden(book))
+        {
+            return (Cow::Borrowed(books), counts);
+        }
 
-                ExportHighlightData {
-                    id: h.id.clone(),
-                    text: h.text.clone(),
-                    chapter: h.chapter_title.clone(),
-                    location,
-                    date: h.date_created.clone(),
-                    note: None,
-                    is_edited: false,
-                }
+        let filtered = books
+            .iter()
+            .zip(disambiguators)
+            .map(|(book, disambiguator)| {
+                let mut book = if rules.is_empty() {
+                    book.clone()
+                } else {
+                    let (book, excluded) = rules.apply(book);
+                    counts.excluded_by_chapter += excluded;
+                    book
+                };
+                let before = book.highlights.len();
+                book.highlights.retain(|h| !h.is_excluded);
+                counts.hidden += before - book.highlights.len();
+                counts.redacted += redaction.apply(&mut book);
+                book.disambiguator = disambiguator;
+                book
             })
             .collect();
+        (Cow::Owned(filtered), counts)
+    }
 
-        // Format read date if present
-        let read_date = book
-            .date_last_read
-            .as_ref()
-            .map(|d| format_date(d, &config.date_format));
+    /// Replace `path` atomically through a synced temporary file
+    fn write_file(&self, path: &Path, bytes: &[u8]) -> Result<(), ExportError> {
+        atomic_write_with(&self.write_ops(), path, |writer| writer.write_all(bytes))?;
+        Ok(())
+    }
 
-        ExportBookData {
-            title: book.title.clone(),
-            author: book.author.clone(),
-            isbn: book.isbn.clone(),
-            publisher: book.publisher.clone(),
-            language: book.language.clone(),
-            read_date,
-            description: book.description.clone(),
-            highlights: highlights_data,
-        }
+    /// `write_file` for exported text, with the BOM and line endings of
+    /// `config`; returns the bytes written
+    fn write_text(
+        &self,
+        path: &Path,
+        content: &str,
+        config: &ExportConfig,
+    ) -> Result<u64, ExportError> {
+        let bytes =
+            atomic_write_text(&self.write_ops(), path, output_encoding(config), |writer| {
+                writer.write_all(content.as_bytes())?;
+                Ok(writer.bytes())
+            })?;
+        Ok(bytes)
     }
 
-    /// Generate markdown content for a book
-    fn generate_markdown(&self, book: &Book, config: &ExportConfig) -> String {
-        let mut lines: Vec<String> = Vec::new();
+    /// Export a single book to markdown
+    pub fn export_book(&self, book: &Book, config: &ExportConfig) -> Result<PathBuf, ExportError> {
+        self.export_book_unique(book, config, &mut HashSet::new())
+    }
 
-        // Title
-        lines.push(format!("# {}", book.title));
-        lines.push(String::new());
+    /// Export a single book, renaming it if another book in the same run
+    /// already wrote to the same path
+    fn export_book_unique(
+        &self,
+        book: &Book,
+        config: &ExportConfig,
+        written: &mut HashSet<PathBuf>,
+    ) -> Result<PathBuf, ExportError> {
+        let file_path = self.reserve_book_path(book, config, written)?;
+        let (file_path, records) = self.write_book(book, config, file_path, None)?;
+        self.record_in_manifest(records)?;
+        Ok(file_path)
+    }
 
-        // Metadata
-        let mut metadata: Vec<String> = Vec::new();
+    /// Path a book will be written to in this run
+    ///
+    /// A path already taken by another book of the run gets a ` (2)` suffix,
+    /// as does, in append mode, a file khi didn't write.
+    fn reserve_book_path(
+        &self,
+        book: &Book,
+        config: &ExportConfig,
+        written: &mut HashSet<PathBuf>,
+    ) -> Result<PathBuf, ExportError> {
+        log::info!("[EXPORTER] A exportar livro: '{}'", book.title);
+
+        let title = self.title_options.filename_title(book);
+        let target_dir = self.export_dir.join(export_folder(config, book, &title));
 
-        if config.metadata.author && !book.author.is_empty() {
-            metadata.push(format!("**Autor**: {}", book.author));
+        // A file adopted from another tool keeps receiving the book
+        if let Some(adopted) = self.adopted_path(book, config) {
+            if written.insert(adopted.clone()) {
+                log::info!("[EXPORTER] Ficheiro adotado: {:?}", adopted);
+                return Ok(adopted);
+            }
         }
-        if config.metadata.isbn && book.isbn.is_some() {
-            metadata.push(format!("**ISBN**: {}", book.isbn.as_ref().unwrap()));
+
+        log::info!("[EXPORTER] A gerar filename...");
+        let mut filename = export_filename(config, book, &title);
+        let stem = filename.trim_end_matches(".md").to_string();
+        let mut suffix = 2;
+        while written.contains(&target_dir.join(&filename))
+            || self.is_foreign_append_target(config, &target_dir.join(&filename))
+        {
+            filename = format!("{} ({}).md", stem, suffix);
+            suffix += 1;
         }
-        if config.metadata.publisher && book.publisher.is_some() {
-            metadata.push(format!(
-                "**Publisher**: {}",
-                book.publisher.as_ref().unwrap()
-            ));
+        log::info!("[EXPORTER] Filename gerado: {}", filename);
+
+        let file_path = target_dir.join(&filename);
+        written.insert(file_path.clone());
+        log::info!("[EXPORTER] Path completo: {:?}", file_path);
+        Ok(file_path)
+    }
+
+    /// Render a book and write it to its reserved path
+    ///
+    /// Returns the book's manifest records, for the caller to save with the
+    /// rest of the run's. With `staging`, the files go to the staging folder
+    /// and the manifest and cleanup of older files wait until they are moved
+    /// into place.
+    fn write_book(
+        &self,
+        book: &Book,
+        config: &ExportConfig,
+        file_path: PathBuf,
+        staging: Option<&Staging>,
+    ) -> Result<(PathBuf, Vec<ManifestRecord>), ExportError> {
+        let target = match staging {
+            Some(staging) => staging.stage(&file_path)?,
+            None => {
+                if let Some(parent) = file_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                file_path.clone()
+            }
+        };
+        if config.format == ExportFormat::Markdown {
+            self.copy_figures(book, &file_path, staging);
+        }
+        let append =
+            config.write_mode == ExportWriteMode::Append && config.format == ExportFormat::Markdown;
+        let appended = if append {
+            self.append_new_highlights(book, config, &file_path, &target)?
+        } else {
+            None
+        };
+        let finish = match appended {
+            Some(finish) => finish,
+            None => {
+                log::info!("[EXPORTER] A gerar e escrever ficheiro...");
+                let (bytes, parts) = {
+                    let _span = self.metrics.span("write");
+                    self.write_rendered(book, config, &target)?
+                };
+                self.metrics.add("bytes_written", bytes);
+                log::info!(
+                    "[EXPORTER] ✅ Ficheiro escrito com sucesso ({} bytes): {:?}",
+                    bytes,
+                    target
+                );
+                BookFinish {
+                    rendered_parts: Some(parts),
+                    manifest: append.then(HashSet::new),
+                    appended: false,
+                }
+            }
+        };
+
+        let records = match staging {
+            Some(staging) => {
+                staging.defer(file_path.clone(), finish);
+                Vec::new()
+            }
+            None => self.finish_book(book, config, &file_path, finish)?,
+        };
+        Ok((file_path, records))
+    }
+
+    /// Copy the images of the book's figure highlights into the
+    /// `ATTACHMENTS_DIR` folder next to `file_path`, where its markdown
+    /// links them (through `staging` when the run has one); a missing image
+    /// only costs the picture
+    fn copy_figures(&self, book: &Book, file_path: &Path, staging: Option<&Staging>) {
+        for (source, live) in figure_files(book, file_path) {
+            if live.exists() {
+                continue;
+            }
+            let copied = match staging {
+                Some(staging) => staging.stage(&live),
+                None => live
+                    .parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .map(|_| live.clone()),
+            }
+            .and_then(|target| fs::copy(source, target));
+            if let Err(e) = copied {
+                log::warn!("[EXPORTER] Falha ao copiar figura {:?}: {}", source, e);
+            }
+        }
+    }
+
+    /// Bring what surrounds a book's freshly written file up to date:
+    /// older part files, sidecar and copies in other language folders
+    ///
+    /// Returns the manifest records of the book's files, which the caller
+    /// saves along with the rest of the run's.
+    fn finish_book(
+        &self,
+        book: &Book,
+        config: &ExportConfig,
+        file_path: &Path,
+        finish: BookFinish,
+    ) -> Result<Vec<ManifestRecord>, ExportError> {
+        if let Some(parts) = finish.rendered_parts {
+            self.remove_stale_parts(config, file_path, parts);
         }
-        if config.metadata.date_last_read && book.date_last_read.is_some() {
-            let formatted = format_date(book.date_last_read.as_ref().unwrap(), &config.date_format);
-            metadata.push(format!("**Data de Leitura**: {}", formatted));
+        if finish.appended {
+            // Byte ranges of an earlier full render no longer hold
+            sidecar::remove_sidecar(file_path);
         }
-        if config.metadata.language && book.language.is_some() {
-            metadata.push(format!("**Idioma**: {}", book.language.as_ref().unwrap()));
+        let mut records = vec![match finish.manifest {
+            Some(present) => self.appended_file_record(book, config, file_path, present)?,
+            None => self.book_file_record(book, file_path)?,
+        }];
+        for number in 1..=finish.rendered_parts.unwrap_or(0) {
+            records.push(self.book_file_record(book, &parts::part_path(file_path, number))?);
         }
-        if config.metadata.description && book.description.is_some() {
-            metadata.push(String::new());
-            metadata.push(book.description.as_ref().unwrap().clone());
+        if config.format == ExportFormat::Markdown {
+            for (_, figure) in figure_files(book, file_path) {
+                if figure.is_file() {
+                    records.push(self.book_file_record(book, &figure)?);
+                }
+            }
         }
 
-        if !metadata.is_empty() {
-            lines.extend(metadata);
-            lines.push(String::new());
+        // An append-mode file carries notes of its own; it never moves
+        if let (Some(target_dir), Some(filename), false) = (
+            file_path.parent(),
+            file_path.file_name(),
+            config.write_mode == ExportWriteMode::Append,
+        ) {
+            self.remove_stale_language_copies(
+                book,
+                &config.folder_pattern,
+                target_dir,
+                &filename.to_string_lossy(),
+            );
         }
+        Ok(records)
+    }
 
-        if book.highlights.is_empty() {
-            return lines.join("\n");
+    /// Remove part files from an earlier, larger export of the markdown
+    /// book at `path`, now written with `parts` parts
+    fn remove_stale_parts(&self, config: &ExportConfig, path: &Path, parts: usize) {
+        if config.format != ExportFormat::Markdown {
+            return;
         }
+        for stale in parts::stale_part_paths(path, parts + 1) {
+            match fs::remove_file(&stale) {
+                Ok(()) => log::info!("[EXPORTER] Parte antiga removida: {:?}", stale),
+                Err(e) => log::warn!("[EXPORTER] Falha ao remover {:?}: {}", stale, e),
+            }
+            sidecar::remove_sidecar(&stale);
+        }
+    }
 
-        lines.push("---".to_string());
-        lines.push(String::new());
-
-        // Render highlights sequentially (no chapter grouping)
-        for highlight in &book.highlights {
-            lines.push(self.generate_highlight_markdown(highlight, config));
+    /// Render `book` into `path`, returning the bytes written and the
+    /// number of part files
+    ///
+    /// Markdown streams straight into the file; a large book writes its part
+    /// files first, then the overview at `path`. Each file is followed by its
+    /// sidecar when `write_sidecars` is on.
+    fn write_rendered(
+        &self,
+        book: &Book,
+        config: &ExportConfig,
+        path: &Path,
+    ) -> Result<(u64, usize), ExportError> {
+        if config.format != ExportFormat::Markdown {
+            let content = {
+                let _span = self.metrics.span("render");
+                self.cache_hit(book, config)
+                    .unwrap_or_else(|| self.render_book(book, config))
+            };
+            return Ok((self.write_text(path, &content, config)?, 0));
         }
 
-        lines.join("\n")
+        let original = book;
+        let (books, _) = self.apply_export_rules(std::slice::from_ref(book), config);
+        let book = &books[0];
+        let part_paths = parts::part_paths(book, config, path);
+        let highlights = book.highlights_by_position();
+        let exported_at = chrono::Utc::now().to_rfc3339();
+        let mut bytes = 0;
+        for (index, (chunk, part_path)) in highlights
+            .chunks(LARGE_BOOK_HIGHLIGHTS)
+            .zip(&part_paths)
+            .enumerate()
+        {
+            let (written, blocks) = self.stream_markdown(part_path, config, |out| {
+                self.write_split_part(book, config, chunk, index + 1, part_paths.len(), path, out)
+            })?;
+            bytes += written;
+            self.write_sidecar(book, config, part_path, &blocks, &exported_at)?;
+        }
+        // Sidecars need the block spans of a streamed render
+        let cached = (part_paths.is_empty() && !sidecar::is_enabled(config))
+            .then(|| self.cache_hit(original, config))
+            .flatten();
+        let (written, blocks) = if let Some(content) = cached {
+            (self.write_text(path, &content, config)?, Vec::new())
+        } else if part_paths.is_empty() {
+            self.stream_markdown(path, config, |out| self.write_markdown(book, config, out))?
+        } else {
+            log::info!(
+                "[EXPORTER] Livro dividido em {} partes ({} destaques)",
+                part_paths.len(),
+                highlights.len()
+            );
+            self.stream_markdown(path, config, |out| {
+                self.write_split_overview(book, config, &part_paths, out)
+            })?
+        };
+        bytes += written;
+        self.write_sidecar(book, config, path, &blocks, &exported_at)?;
+        Ok((bytes, part_paths.len()))
     }
 
-    /// Generate markdown for a single highlight
-    fn generate_highlight_markdown(&self, highlight: &Highlight, _config: &ExportConfig) -> String {
-        let mut lines: Vec<String> = Vec::new();
-
-        // Highlight text as blockquote
-        lines.push(format!("> {}", highlight.text));
+    /// Atomically replace `path` with the markdown `render` streams,
+    /// returning the bytes written and (with sidecars on) the highlight
+    /// blocks
+    fn stream_markdown(
+        &self,
+        path: &Path,
+        config: &ExportConfig,
+        render: impl FnOnce(
+            &mut MarkdownSink<IoSink<&mut TextEncoder<&mut BufWriter<File>>>>,
+        ) -> fmt::Result,
+    ) -> Result<(u64, Vec<BlockSpan>), ExportError> {
+        let track = sidecar::is_enabled(config);
+        let encoding = output_encoding(config);
+        let written = atomic_write_text(&self.write_ops(), path, encoding, |writer| {
+            let _span = self.metrics.span("render");
+            let mut out = MarkdownSink::new(IoSink::new(&mut *writer));
+            if track {
+                out = out.tracking_blocks();
+            }
+            let result = render(&mut out);
+            let mut blocks = out.take_blocks();
+            let mut sink = out.into_inner();
+            result.map_err(|_| sink.take_error())?;
+            // Spans count the rendered text; the sidecar wants file offsets
+            for block in &mut blocks {
+                block.range = writer.encoded_offset(block.range.start)
+                    ..writer.encoded_offset(block.range.end);
+            }
+            Ok((writer.bytes(), blocks))
+        })?;
+        Ok(written)
+    }
 
-        // Location info (no label, just the value)
-        let mut location_parts: Vec<String> = Vec::new();
-        if let Some(chapter_title) = &highlight.chapter_title {
-            location_parts.push(chapter_title.clone());
+    /// Write the sidecar of the markdown file just written to `path`
+    ///
+    /// A sidecar that can't be written is removed, so none ever describes an
+    /// older version of its file.
+    fn write_sidecar(
+        &self,
+        book: &Book,
+        config: &ExportConfig,
+        path: &Path,
+        blocks: &[BlockSpan],
+        exported_at: &str,
+    ) -> Result<(), ExportError> {
+        if !sidecar::is_enabled(config) {
+            return Ok(());
         }
-        if let Some(progress) = highlight.chapter_progress {
-            location_parts.push(format!("{}%", (progress * 100.0) as i32));
+        let sidecar = sidecar::build_sidecar(book, config, blocks, exported_at);
+        let json = serde_json::to_string_pretty(&sidecar).map_err(std::io::Error::other)?;
+        if let Err(e) = self.write_file(&sidecar::sidecar_path(path), json.as_bytes()) {
+            sidecar::remove_sidecar(path);
+            return Err(e);
         }
+        Ok(())
+    }
 
-        if !location_parts.is_empty() {
-            lines.push(String::new());
-            lines.push(location_parts.join(" · "));
-            lines.push(String::new());
+    /// Append the highlights `file_path` doesn't have yet under a dated
+    /// `## Imported` heading, writing the result to `target`
+    ///
+    /// Highlights count as present when the manifest lists them for the file
+    /// or their anchor is in it. Returns `None` when the file doesn't exist
+    /// or is marked stale, so the book is written whole. A file khi never
+    /// wrote (no manifest entry, no anchors) gets a suffixed path when the
+    /// path is reserved; one created after that is left alone and the book
+    /// fails with `AlreadyExists`.
+    fn append_new_highlights(
+        &self,
+        book: &Book,
+        config: &ExportConfig,
+        file_path: &Path,
+        target: &Path,
+    ) -> Result<Option<BookFinish>, ExportError> {
+        let existing = match fs::read_to_string(file_path) {
+            Ok(existing) => existing,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let manifest = append::load_manifest(&self.export_dir);
+        let key = manifest_key(&self.export_dir, file_path);
+        if manifest.stale.contains(&key) {
+            log::info!(
+                "[EXPORTER] {:?} marcado para nova exportação, a reescrever",
+                file_path
+            );
+            return Ok(None);
         }
+        let listed = manifest.files.get(&key);
+        let mut present = anchors_in(&existing);
+        if listed.is_none() && present.is_empty() {
+            // Created after its path was reserved: leave it alone
+            log::warn!(
+                "[EXPORTER] {:?} não foi escrito pelo khi, ignorado",
+                file_path
+            );
+            return Err(ExportError::Io(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} was not written by khi", file_path.display()),
+            )));
+        }
+        present.extend(listed.into_iter().flatten().cloned());
 
-        lines.join("\n")
+        let (books, _) = self.apply_export_rules(std::slice::from_ref(book), config);
+        let new: Vec<&Highlight> = books[0]
+            .highlights_by_position()
+            .into_iter()
+            .filter(|h| !present.contains(highlight_key(h)))
+            .collect();
+        if !new.is_empty() {
+            let mut body = MarkdownSink::new(String::new());
+            let _ = self.write_highlights(&mut body, &books[0], &new, config);
+            let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+            let heading = format!("Imported {}", format_date(&today, &config.date_format));
+            let content = append_section(&existing, &heading, &body.into_inner());
+            {
+                let _span = self.metrics.span("write");
+                self.write_text(target, &content, config)?;
+            }
+            self.metrics
+                .add("bytes_written", (content.len() - existing.len()) as u64);
+            log::info!(
+                "[EXPORTER] ✅ {} destaque(s) novo(s) acrescentado(s) a {:?}",
+                new.len(),
+                file_path
+            );
+        }
+        Ok(Some(BookFinish {
+            rendered_parts: None,
+            manifest: Some(present),
+            appended: !new.is_empty(),
+        }))
     }
 
-    /// Get the export directory path
-    pub fn export_dir(&self) -> &Path {
-        &self.export_dir
+    /// The file adopted for `book` (see `adopt`), when appending and it is
+    /// still there
+    fn adopted_path(&self, book: &Book, config: &ExportConfig) -> Option<PathBuf> {
+        if config.write_mode != ExportWriteMode::Append || config.format != ExportFormat::Markdown {
+            return None;
+        }
+        let manifest = {
+            let _guard = self.manifest_lock.lock().unwrap_or_else(|e| e.into_inner());
+            append::load_manifest(&self.export_dir)
+        };
+        let path = self
+            .export_dir
+            .join(manifest.adopted.get(&manifest_slug(book))?);
+        path.is_file().then_some(path)
+    }
+
+    /// Whether appending to `path` would touch a file khi didn't write (no
+    /// manifest entry, no highlight anchors), e.g. one with hand-written notes
+    fn is_foreign_append_target(&self, config: &ExportConfig, path: &Path) -> bool {
+        if config.write_mode != ExportWriteMode::Append || config.format != ExportFormat::Markdown {
+            return false;
+        }
+        let Ok(existing) = fs::read_to_string(path) else {
+            return false;
+        };
+        let manifest = {
+            let _guard = self.manifest_lock.lock().unwrap_or_else(|e| e.into_inner());
+            append::load_manifest(&self.export_dir)
+        };
+        let key = manifest_key(&self.export_dir, path);
+        !manifest.files.contains_key(&key)
+            && !manifest.stale.contains(&key)
+            && anchors_in(&existing).is_empty()
+    }
+
+    /// Manifest record of the book's highlights (plus `present`) as written
+    /// to `file_path`, with the hash of its contents
+    fn appended_file_record(
+        &self,
+        book: &Book,
+        config: &ExportConfig,
+        file_path: &Path,
+        present: HashSet<String>,
+    ) -> Result<ManifestRecord, ExportError> {
+        let (books, _) = self.apply_export_rules(std::slice::from_ref(book), config);
+        let mut keys: Vec<String> = present
+            .into_iter()
+            .chain(
+                books[0]
+                    .highlights
+                    .iter()
+                    .map(|h| highlight_key(h).to_string()),
+            )
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        keys.sort();
+
+        Ok(ManifestRecord {
+            highlights: Some(keys),
+            ..self.book_file_record(book, file_path)?
+        })
+    }
+
+    /// Manifest record of which book `file_path` holds, with the hash of its
+    /// contents, whatever the write mode, so later runs (and the watcher)
+    /// can tell the book's files apart from edits made outside khi
+    fn book_file_record(
+        &self,
+        book: &Book,
+        file_path: &Path,
+    ) -> Result<ManifestRecord, ExportError> {
+        Ok(ManifestRecord {
+            key: manifest_key(&self.export_dir, file_path),
+            slug: manifest_slug(book),
+            hash: verify::hash_file(file_path)?,
+            highlights: None,
+        })
+    }
+
+    /// Save `records` in the manifest, in one write; nothing is written when
+    /// the manifest already holds them
+    fn record_in_manifest(&self, records: Vec<ManifestRecord>) -> std::io::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let _guard = self.manifest_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut manifest = append::load_manifest(&self.export_dir);
+        let before = manifest.clone();
+        for record in records {
+            if let Some(highlights) = record.highlights {
+                manifest.stale.remove(&record.key);
+                manifest.files.insert(record.key.clone(), highlights);
+            }
+            manifest.hashes.insert(record.key.clone(), record.hash);
+            manifest.books.insert(record.key, record.slug);
+        }
+        if manifest == before {
+            return Ok(());
+        }
+        append::save_manifest_with(&self.write_ops(), &self.export_dir, &manifest)
+    }
+
+    /// Save the manifest records of the books at `indices` of a run, failing
+    /// those books' outcomes when the manifest can't be written
+    fn record_run_in_manifest(
+        &self,
+        records: Vec<(usize, Vec<ManifestRecord>)>,
+        outcomes: &mut [Option<Result<PathBuf, ExportError>>],
+    ) {
+        let indices: Vec<usize> = records.iter().map(|(index, _)| *index).collect();
+        let records = records
+            .into_iter()
+            .flat_map(|(_, records)| records)
+            .collect();
+        if let Err(e) = self.record_in_manifest(records) {
+            log::error!("[EXPORTER] ❌ Falha ao gravar o manifesto: {}", e);
+            for index in indices {
+                outcomes[index] = Some(Err(std::io::Error::new(e.kind(), e.to_string()).into()));
+            }
+        }
+    }
+
+    /// Write every book's markdown file, `max_concurrent_writes` at a time
+    ///
+    /// Paths are reserved up front in input order, so collision renames don't
+    /// depend on timing. `on_done` runs on the calling thread as books finish
+    /// (in completion order). After a disk full error (or with `staging`, any
+    /// error) no further book is started; books never started have no
+    /// result. The manifest is saved once, after every book is written.
+    fn write_books(
+        &self,
+        books: &[Book],
+        config: &ExportConfig,
+        staging: Option<&Staging>,
+        mut on_done: impl FnMut(usize, &Result<PathBuf, ExportError>),
+    ) -> Vec<Option<Result<PathBuf, ExportError>>> {
+        let mut written = HashSet::new();
+        let planned: Vec<Result<PathBuf, ExportError>> = books
+            .iter()
+            .map(|book| self.reserve_book_path(book, config, &mut written))
+            .collect();
+
+        let workers = config.max_concurrent_writes.clamp(1, books.len().max(1));
+        if workers > 1 {
+            log::info!("[EXPORTER] A escrever com {} tarefas em paralelo", workers);
+        }
+        let jobs = Mutex::new(planned.into_iter().enumerate());
+        let stop = AtomicBool::new(false);
+        let mut results: Vec<Option<Result<PathBuf, ExportError>>> =
+            books.iter().map(|_| None).collect();
+        let mut records = Vec::new();
+
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            for _ in 0..workers {
+                let sender = sender.clone();
+                let (jobs, stop) = (&jobs, &stop);
+                scope.spawn(move || loop {
+                    // Books already being written finish, so files are never torn
+                    if stop.load(Ordering::SeqCst) || self.cancellation.is_cancelled() {
+                        break;
+                    }
+                    let next = jobs.lock().unwrap_or_else(|e| e.into_inner()).next();
+                    let Some((index, planned)) = next else {
+                        break;
+                    };
+                    let result = planned
+                        .and_then(|path| self.write_book(&books[index], config, path, staging));
+                    // One failure dooms a transactional run
+                    if matches!(result, Err(ExportError::DiskFull(_)))
+                        || (staging.is_some() && result.is_err())
+                    {
+                        stop.store(true, Ordering::SeqCst);
+                    }
+                    if sender.send((index, result)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(sender);
+
+            for (index, result) in receiver {
+                let result = result.map(|(path, book_records)| {
+                    records.push((index, book_records));
+                    path
+                });
+                on_done(index, &result);
+                results[index] = Some(result);
+            }
+        });
+
+        self.record_run_in_manifest(records, &mut results);
+        results
+    }
+
+    /// Remove copies of a book left in other language folders
+    ///
+    /// When a book's language changes between imports, the next export writes
+    /// it into a new folder; the old copy is removed so the file effectively
+    /// moves. Only applies when `{language}` is a whole path segment, and only
+    /// to sibling folders named like language folders, holding a file that
+    /// `is_export_of` the book.
+    fn remove_stale_language_copies(
+        &self,
+        book: &Book,
+        pattern: &str,
+        target_dir: &Path,
+        filename: &str,
+    ) {
+        let segments: Vec<&str> = pattern.split(['/', '\\']).collect();
+        let Some(index) = segments.iter().position(|s| s.trim() == "{language}") else {
+            return;
+        };
+        let title = self.title_options.filename_title(book);
+
+        let parent =
+            self.export_dir
+                .join(resolve_folder_segments(&segments[..index], book, &title));
+        let suffix = resolve_folder_segments(&segments[index + 1..], book, &title);
+        let entries = match fs::read_dir(&parent) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for entry in entries.flatten() {
+            if !is_language_folder_name(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let candidate = entry.path().join(&suffix).join(filename);
+            if !entry.path().is_dir()
+                || !candidate.is_file()
+                || candidate.starts_with(target_dir)
+                || !self.is_export_of(book, &candidate)
+            {
+                continue;
+            }
+            match fs::remove_file(&candidate) {
+                Ok(_) => log::info!("[EXPORTER] Removed stale copy: {:?}", candidate),
+                Err(e) => log::warn!(
+                    "[EXPORTER] Failed to remove stale copy {:?}: {}",
+                    candidate,
+                    e
+                ),
+            }
+            sidecar::remove_sidecar(&candidate);
+        }
+    }
+
+    /// Whether the file at `path` was exported from `book`: its manifest
+    /// entry names the book's slug, or its sidecar or frontmatter the book's
+    /// content ID
+    fn is_export_of(&self, book: &Book, path: &Path) -> bool {
+        let manifest = {
+            let _guard = self.manifest_lock.lock().unwrap_or_else(|e| e.into_inner());
+            append::load_manifest(&self.export_dir)
+        };
+        if let Some(slug) = manifest.books.get(&manifest_key(&self.export_dir, path)) {
+            return *slug == manifest_slug(book);
+        }
+        if let Some(sidecar) = fs::read_to_string(sidecar::sidecar_path(path))
+            .ok()
+            .and_then(|json| serde_json::from_str::<sidecar::Sidecar>(&json).ok())
+        {
+            return sidecar.content_id == book.content_id;
+        }
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|text| frontmatter_value(&text, &["content_id", "contentId"]))
+            .is_some_and(|content_id| content_id == book.content_id)
+    }
+
+    /// Files an export run would write, without the optional summary files
+    /// (ignoring in-run collision renames)
+    pub fn planned_files(&self, books: &[Book], config: &ExportConfig) -> Vec<PathBuf> {
+        let single = |name: &str| vec![self.export_dir.join(name)];
+        match config.format {
+            ExportFormat::Markdown | ExportFormat::Logseq => books
+                .iter()
+                .flat_map(|book| {
+                    let path = self.planned_path(book, config);
+                    let parts = parts::part_paths(book, config, &path);
+                    std::iter::once(path).chain(parts)
+                })
+                .collect(),
+            ExportFormat::CslJson => single(citation::CSL_JSON_FILENAME),
+            ExportFormat::Bibtex => single(citation::BIBTEX_FILENAME),
+            ExportFormat::Ndjson => single(NDJSON_FILENAME),
+            ExportFormat::Csv | ExportFormat::Tsv | ExportFormat::Readwise => {
+                tabular::tabular_filename(config.format)
+                    .map(single)
+                    .unwrap_or_default()
+            }
+            ExportFormat::Journal => {
+                let refs: Vec<&Book> = books.iter().collect();
+                render_journal(&refs, config, self.assumed_offset)
+                    .into_iter()
+                    .map(|(name, _)| self.export_dir.join(name))
+                    .collect()
+            }
+        }
+    }
+
+    /// Path a book would be written to (ignoring in-run collision renames)
+    pub fn planned_path(&self, book: &Book, config: &ExportConfig) -> PathBuf {
+        let title = self.title_options.filename_title(book);
+        self.export_dir
+            .join(export_folder(config, book, &title))
+            .join(export_filename(config, book, &title))
+    }
+
+    /// Compare a book's rendered markdown with its currently exported file
+    pub fn export_diff(
+        &self,
+        book: &Book,
+        config: &ExportConfig,
+    ) -> Result<ExportDiff, ExportError> {
+        let path = self.planned_path(book, config);
+        let existing = match fs::read_to_string(&path) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(ExportError::Io(e)),
+        };
+
+        let relative = path.strip_prefix(&self.export_dir).unwrap_or(&path);
+        let rendered = if parts::is_split(book, config) {
+            let mut out = MarkdownSink::new(String::new());
+            let _ = self.write_split_overview(
+                book,
+                config,
+                &parts::part_paths(book, config, &path),
+                &mut out,
+            );
+            out.into_inner()
+        } else {
+            self.cached_render(book, config)
+        };
+        Ok(diff_export(
+            &relative.to_string_lossy(),
+            existing.as_deref(),
+            &rendered,
+        ))
+    }
+
+    /// Export multiple books to markdown files
+    ///
+    /// Citation formats write one records file instead, with a single result.
+    pub fn export_books(
+        &self,
+        books: &[Book],
+        config: &ExportConfig,
+    ) -> Vec<Result<PathBuf, ExportError>> {
+        log::info!("[EXPORTER] ==========================================");
+        log::info!(
+            "[EXPORTER] Iniciando exportação de {} livro(s)",
+            books.len()
+        );
+        log::info!("[EXPORTER] Diretório de exportação: {:?}", self.export_dir);
+
+        // Verificar se diretório existe
+        if !self.export_dir.exists() {
+            log::info!("[EXPORTER] Diretório não existe, a criar...");
+            match fs::create_dir_all(&self.export_dir) {
+                Ok(_) => log::info!("[EXPORTER] ✅ Diretório criado com sucesso"),
+                Err(e) => {
+                    log::error!("[EXPORTER] ❌ Falha ao criar diretório: {}", e);
+                    return vec![Err(e.into())];
+                }
+            }
+        } else {
+            log::info!("[EXPORTER] ✅ Diretório já existe");
+        }
+
+        let (books, counts) = self.apply_export_rules(books, config);
+        let books = books.as_ref();
+        counts.log();
+        if let Err(cancelled) = self.cancellation.check() {
+            return vec![Err(cancelled.with_progress(0, books.len()).into())];
+        }
+
+        if let Some(result) = self.export_records_file(books, config) {
+            return match result {
+                Ok((paths, _)) => {
+                    self.write_run_snapshot(&paths, config);
+                    let _ = self.commit_run(&paths, &books.iter().collect::<Vec<_>>(), config);
+                    paths.into_iter().map(Ok).collect()
+                }
+                Err(e) => vec![Err(e)],
+            };
+        }
+
+        let staging = match self.begin_staging(config) {
+            Ok(staging) => staging,
+            Err(e) => return vec![Err(e)],
+        };
+        let mut outcomes = self.write_books(books, config, staging.as_ref(), |index, _| {
+            log::info!(
+                "[EXPORTER] --- Livro {}/{} processado ---",
+                index + 1,
+                books.len()
+            );
+        });
+        let not_started = outcomes.iter().filter(|r| r.is_none()).count();
+        let rollback = staging.and_then(|staging| {
+            self.settle_staging(staging, books, config, &mut outcomes)
+                .err()
+        });
+        let mut results: Vec<Result<PathBuf, ExportError>> =
+            outcomes.into_iter().flatten().collect();
+        if results
+            .iter()
+            .any(|r| matches!(r, Err(ExportError::DiskFull(_))))
+        {
+            log::error!(
+                "[EXPORTER] ❌ Disco cheio, {} livro(s) por exportar",
+                not_started
+            );
+            return results;
+        }
+        if let Some(cancelled) = self.cancelled_run(books.len(), not_started) {
+            results.push(Err(cancelled.into()));
+            return results;
+        }
+        if let Some(e) = rollback {
+            if !matches!(e, ExportError::RolledBack) {
+                results.push(Err(e));
+            }
+            return results;
+        }
+
+        let entries: Vec<(&Book, &PathBuf)> = books
+            .iter()
+            .zip(&results)
+            .filter_map(|(book, result)| result.as_ref().ok().map(|path| (book, path)))
+            .collect();
+        let mut produced: Vec<PathBuf> = entries
+            .iter()
+            .flat_map(|(book, path)| book_files(book, config, path))
+            .collect();
+        produced.extend(self.write_summary_files(&entries, config));
+        self.write_run_snapshot(&produced, config);
+        let exported: Vec<&Book> = entries.iter().map(|(book, _)| *book).collect();
+        let _ = self.commit_run(&produced, &exported, config);
+
+        let success_count = results.iter().filter(|r| r.is_ok()).count();
+        let error_count = results.len() - success_count;
+        log::info!("[EXPORTER] ==========================================");
+        log::info!(
+            "[EXPORTER] Exportação concluída: {} sucesso, {} erro(s)",
+            success_count,
+            error_count
+        );
+
+        results
+    }
+
+    /// Export multiple books, reporting progress through an event sink
+    ///
+    /// Emits "export-started", one "export-progress" per book and
+    /// "export-finished" with the full report.
+    pub fn export_books_with_events(
+        &self,
+        books: &[Book],
+        config: &ExportConfig,
+        sink: &dyn EventSink,
+    ) -> ExportReport {
+        let destination = self.export_dir.to_string_lossy().to_string();
+        send_event(
+            sink,
+            "export-started",
+            &ExportStartedEvent {
+                total_books: books.len(),
+                destination: destination.clone(),
+            },
+        );
+
+        let mut report = ExportReport {
+            destination,
+            total_books: books.len(),
+            exported_files: Vec::new(),
+            failures: Vec::new(),
+            bytes_written: None,
+            aborted: None,
+            excluded_by_chapter: 0,
+            redacted: 0,
+            metrics: MetricsSummary::default(),
+            git_commit: None,
+            warnings: Vec::new(),
+            encoding: output_encoding(config),
+            cloud_provider: self.cloud_provider,
+            cancelled: None,
+            rolled_back: false,
+        };
+
+        let (books, counts) = self.apply_export_rules(books, config);
+        let books = books.as_ref();
+        counts.log();
+        report.excluded_by_chapter = counts.excluded_by_chapter;
+        report.redacted = counts.redacted;
+        if let Err(cancelled) = self.cancellation.check() {
+            report.cancelled = Some(cancelled.with_progress(0, books.len()));
+            return self.finish_report(report, sink);
+        }
+
+        if let Some(result) = self.export_records_file(books, config) {
+            if let Ok((paths, _)) = &result {
+                self.write_run_snapshot(paths, config);
+                let exported: Vec<&Book> = books.iter().collect();
+                report_commit(self.commit_run(paths, &exported, config), &mut report);
+            }
+            self.report_records_file(books, result, &mut report, sink);
+            return self.finish_report(report, sink);
+        }
+
+        let staging = match self.begin_staging(config) {
+            Ok(staging) => staging,
+            Err(e) => {
+                report.aborted = Some(format!("Failed to create the staging folder: {}", e));
+                return self.finish_report(report, sink);
+            }
+        };
+        // Progress follows completion order; the report keeps input order
+        let mut outcomes = self.write_books(books, config, staging.as_ref(), |index, result| {
+            let book = &books[index];
+            let event = match result {
+                Ok(path) => ExportProgressEvent {
+                    index,
+                    total_books: books.len(),
+                    title: book.title.clone(),
+                    status: ExportBookStatus::Exported,
+                    path: Some(path.to_string_lossy().to_string()),
+                    error: None,
+                },
+                Err(e) => {
+                    log::error!("[EXPORTER] ❌ Erro no livro '{}': {}", book.title, e);
+                    ExportProgressEvent {
+                        index,
+                        total_books: books.len(),
+                        title: book.title.clone(),
+                        status: ExportBookStatus::Failed,
+                        path: None,
+                        error: Some(e.to_string()),
+                    }
+                }
+            };
+            send_event(sink, EXPORT_PROGRESS, &event);
+        });
+
+        let not_started = outcomes.iter().filter(|r| r.is_none()).count();
+        let rollback = staging.and_then(|staging| {
+            self.settle_staging(staging, books, config, &mut outcomes)
+                .err()
+        });
+        let mut indexed: Vec<(&Book, PathBuf)> = Vec::new();
+        let mut disk_full = Vec::new();
+        for (book, outcome) in books.iter().zip(outcomes) {
+            match outcome {
+                Some(Ok(path)) => {
+                    report
+                        .exported_files
+                        .push(path.to_string_lossy().to_string());
+                    indexed.push((book, path));
+                }
+                // Only the books that failed are failures
+                Some(Err(ExportError::RolledBack)) => {}
+                Some(Err(e)) => {
+                    if let ExportError::DiskFull(_) = e {
+                        disk_full.push(book);
+                    }
+                    report.failures.push(ExportFailure {
+                        title: book.title.clone(),
+                        error: e.to_string(),
+                    });
+                }
+                None => {}
+            }
+        }
+        if let Some(e) = &rollback {
+            report.rolled_back = true;
+            if !matches!(e, ExportError::RolledBack) {
+                report.aborted = Some(format!(
+                    "Failed to move the exported files into place: {}",
+                    e
+                ));
+            }
+        }
+        if let Some(book) = disk_full.first() {
+            report.aborted = Some(format!(
+                "Disk full: export stopped at '{}', {} of {} book(s) not exported",
+                book.title,
+                not_started + disk_full.len(),
+                books.len()
+            ));
+            log::error!("[EXPORTER] ❌ Exportação interrompida: disco cheio");
+            return self.finish_report(report, sink);
+        }
+        // Like a full disk, a cancelled run skips the index, snapshot and commit
+        if let Some(cancelled) = self.cancelled_run(books.len(), not_started) {
+            report.cancelled = Some(cancelled);
+            return self.finish_report(report, sink);
+        }
+        if report.rolled_back {
+            return self.finish_report(report, sink);
+        }
+
+        let entries: Vec<(&Book, &PathBuf)> =
+            indexed.iter().map(|(book, path)| (*book, path)).collect();
+        let mut produced: Vec<PathBuf> = indexed
+            .iter()
+            .flat_map(|(book, path)| book_files(book, config, path))
+            .collect();
+        produced.extend(self.write_summary_files(&entries, config));
+        self.write_run_snapshot(&produced, config);
+        let exported: Vec<&Book> = entries.iter().map(|(book, _)| *book).collect();
+        report_commit(self.commit_run(&produced, &exported, config), &mut report);
+
+        self.finish_report(report, sink)
+    }
+
+    /// Staging folder of the run, with `transactional` on
+    fn begin_staging(&self, config: &ExportConfig) -> Result<Option<Staging>, ExportError> {
+        if !config.transactional {
+            return Ok(None);
+        }
+        Ok(Some(Staging::create(&self.export_dir)?))
+    }
+
+    /// Move the staged files of a transactional run into place and finish
+    /// its books, or discard them when a book failed or was never started
+    ///
+    /// When nothing is moved, the written books' outcomes become
+    /// `RolledBack` and so is the error; a failed move (undone) is returned
+    /// as is.
+    fn settle_staging(
+        &self,
+        staging: Staging,
+        books: &[Book],
+        config: &ExportConfig,
+        outcomes: &mut [Option<Result<PathBuf, ExportError>>],
+    ) -> Result<(), ExportError> {
+        let complete = outcomes.iter().all(|o| matches!(o, Some(Ok(_))));
+        let moved = if complete {
+            staging.commit(&self.write_ops()).map_err(ExportError::from)
+        } else {
+            Err(ExportError::RolledBack)
+        };
+        if let Err(e) = moved {
+            log::warn!("[EXPORTER] Exportação desfeita, nada foi alterado: {}", e);
+            for outcome in outcomes.iter_mut() {
+                if matches!(outcome, Some(Ok(_))) {
+                    *outcome = Some(Err(ExportError::RolledBack));
+                }
+            }
+            return Err(e);
+        }
+
+        let mut deferred: HashMap<PathBuf, BookFinish> =
+            staging.take_deferred().into_iter().collect();
+        let mut records = Vec::new();
+        for (index, (book, outcome)) in books.iter().zip(outcomes.iter_mut()).enumerate() {
+            let path = match outcome {
+                Some(Ok(path)) => path.clone(),
+                _ => continue,
+            };
+            if let Some(finish) = deferred.remove(&path) {
+                match self.finish_book(book, config, &path, finish) {
+                    Ok(book_records) => records.push((index, book_records)),
+                    Err(e) => *outcome = Some(Err(e)),
+                }
+            }
+        }
+        self.record_run_in_manifest(records, outcomes);
+        Ok(())
+    }
+
+    /// Why a run of `total` books left `not_started` of them unwritten, when
+    /// its token stopped it
+    fn cancelled_run(&self, total: usize, not_started: usize) -> Option<Cancelled> {
+        if not_started == 0 {
+            return None;
+        }
+        let cancelled = self
+            .cancellation
+            .check()
+            .err()?
+            .with_progress(total - not_started, total);
+        log::warn!("[EXPORTER] ❌ Exportação interrompida: {}", cancelled);
+        Some(cancelled)
+    }
+
+    /// Attach the run's metrics to `report`, log them and send "export-finished"
+    fn finish_report(&self, mut report: ExportReport, sink: &dyn EventSink) -> ExportReport {
+        report.metrics = self.metrics.take();
+        log::info!("[Metrics] {}", report.metrics.log_line("export"));
+        send_event(sink, "export-finished", &report);
+        report
+    }
+
+    /// Write all books into shared files: citation records (CSL-JSON,
+    /// BibTeX), NDJSON or the reading journal; returns the paths and bytes
+    /// written
+    ///
+    /// Returns `None` for the per-book formats.
+    fn export_records_file(
+        &self,
+        books: &[Book],
+        config: &ExportConfig,
+    ) -> Option<Result<(Vec<PathBuf>, u64), ExportError>> {
+        match config.format {
+            ExportFormat::Ndjson => {
+                let _span = self.metrics.span("write");
+                return Some(
+                    self.write_ndjson_file(books, config)
+                        .map(|(path, bytes)| (vec![path], bytes)),
+                );
+            }
+            ExportFormat::Journal => return Some(self.write_journal_files(books, config)),
+            _ => {}
+        }
+
+        let refs: Vec<&Book> = books.iter().collect();
+        let (filename, content) = render_records(
+            &refs,
+            config,
+            self.assumed_offset,
+            self.title_options.display,
+        )?;
+        let path = self.export_dir.join(filename);
+        log::info!("[EXPORTER] A escrever referências: {:?}", path);
+
+        let _span = self.metrics.span("write");
+        Some(
+            self.write_text(&path, &content, config)
+                .map(|bytes| (vec![path], bytes)),
+        )
+    }
+
+    /// Write the reading journal, one file per month or a combined file
+    fn write_journal_files(
+        &self,
+        books: &[Book],
+        config: &ExportConfig,
+    ) -> Result<(Vec<PathBuf>, u64), ExportError> {
+        let refs: Vec<&Book> = books.iter().collect();
+        let files = {
+            let _span = self.metrics.span("render");
+            render_journal(&refs, config, self.assumed_offset)
+        };
+        log::info!("[EXPORTER] A escrever diário: {} ficheiro(s)", files.len());
+
+        let _span = self.metrics.span("write");
+        let mut paths = Vec::with_capacity(files.len());
+        let mut bytes = 0;
+        for (name, content) in files {
+            let path = self.export_dir.join(name);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            bytes += self.write_text(&path, &content, config)?;
+            paths.push(path);
+        }
+        if config.journal_layout == JournalLayout::Monthly {
+            self.remove_stale_journal_months(&paths);
+        }
+        Ok((paths, bytes))
+    }
+
+    /// Remove monthly journal files of months that no longer have
+    /// highlights; other files in the journal folder are left alone
+    fn remove_stale_journal_months(&self, written: &[PathBuf]) {
+        let folder = self.export_dir.join(journal::JOURNAL_FOLDER);
+        let Ok(entries) = fs::read_dir(&folder) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !journal::is_journal_file_name(&entry.file_name().to_string_lossy())
+                || !path.is_file()
+                || written.contains(&path)
+            {
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => log::info!("[EXPORTER] Mês antigo do diário removido: {:?}", path),
+                Err(e) => log::warn!("[EXPORTER] Falha ao remover {:?}: {}", path, e),
+            }
+        }
+    }
+
+    /// Stream every highlight into `highlights.ndjson`, synced to disk
+    fn write_ndjson_file(
+        &self,
+        books: &[Book],
+        config: &ExportConfig,
+    ) -> Result<(PathBuf, u64), ExportError> {
+        let path = self.export_dir.join(NDJSON_FILENAME);
+        log::info!("[EXPORTER] A escrever NDJSON: {:?}", path);
+
+        let color_labels = config.group_by_color.then_some(&config.color_labels);
+        let stats = atomic_write_with(&self.write_ops(), &path, |writer| {
+            write_ndjson(books, color_labels, writer)
+        })?;
+
+        log::info!(
+            "[EXPORTER] ✅ NDJSON escrito: {} destaque(s), {} bytes",
+            stats.lines,
+            stats.bytes
+        );
+        Ok((path, stats.bytes))
+    }
+
+    /// Report every book as part of the shared records files
+    ///
+    /// Progress events point at the file, or at the folder holding them
+    /// when there are several (monthly journal files).
+    fn report_records_file(
+        &self,
+        books: &[Book],
+        result: Result<(Vec<PathBuf>, u64), ExportError>,
+        report: &mut ExportReport,
+        sink: &dyn EventSink,
+    ) {
+        let (path, error) = match result {
+            Ok((paths, bytes)) => {
+                report
+                    .exported_files
+                    .extend(paths.iter().map(|p| p.to_string_lossy().to_string()));
+                report.bytes_written = Some(bytes);
+                let location = match paths.as_slice() {
+                    [single] => Some(single.as_path()),
+                    _ => paths.first().and_then(|p| p.parent()),
+                };
+                (location.map(|p| p.to_string_lossy().to_string()), None)
+            }
+            Err(e) => {
+                log::error!("[EXPORTER] ❌ Falha ao escrever referências: {}", e);
+                (None, Some(e.to_string()))
+            }
+        };
+
+        for (index, book) in books.iter().enumerate() {
+            if let Some(error) = &error {
+                report.failures.push(ExportFailure {
+                    title: book.title.clone(),
+                    error: error.clone(),
+                });
+            }
+            let event = ExportProgressEvent {
+                index,
+                total_books: books.len(),
+                title: book.title.clone(),
+                status: if error.is_some() {
+                    ExportBookStatus::Failed
+                } else {
+                    ExportBookStatus::Exported
+                },
+                path: path.clone(),
+                error: error.clone(),
+            };
+            send_event(sink, EXPORT_PROGRESS, &event);
+        }
+    }
+
+    /// Write the optional whole-export files (bookshelf index, Atom feed)
+    ///
+    /// Returns the files written.
+    fn write_summary_files(
+        &self,
+        entries: &[(&Book, &PathBuf)],
+        config: &ExportConfig,
+    ) -> Vec<PathBuf> {
+        let mut written = Vec::new();
+        if config.write_index {
+            match self.write_bookshelf_index(entries, config) {
+                Ok(path) => {
+                    log::info!("[EXPORTER] ✅ Índice escrito: {:?}", path);
+                    written.push(path);
+                }
+                Err(e) => log::error!("[EXPORTER] ❌ Falha ao escrever índice: {}", e),
+            }
+        }
+        if config.atom_feed {
+            let books: Vec<&Book> = entries.iter().map(|(book, _)| *book).collect();
+            match self.write_atom_feed(&books, config) {
+                Ok(path) => {
+                    log::info!("[EXPORTER] ✅ Feed escrito: {:?}", path);
+                    written.push(path);
+                }
+                Err(e) => log::error!("[EXPORTER] ❌ Falha ao escrever feed: {}", e),
+            }
+        }
+        written
+    }
+
+    /// Archive the files of this run when `snapshot_exports` is on
+    ///
+    /// Failures are only logged: a snapshot never fails the export itself.
+    fn write_run_snapshot(&self, files: &[PathBuf], config: &ExportConfig) {
+        if !config.snapshot_exports || files.is_empty() {
+            return;
+        }
+
+        match snapshot::write_snapshot(
+            &self.write_ops(),
+            &self.export_dir,
+            files,
+            chrono::Utc::now(),
+        ) {
+            Ok(info) => log::info!(
+                "[EXPORTER] ✅ Snapshot {} escrito ({} ficheiros)",
+                info.id,
+                info.files.len()
+            ),
+            Err(e) => {
+                log::error!("[EXPORTER] ❌ Falha ao escrever snapshot: {}", e);
+                return;
+            }
+        }
+        let max_bytes = config.snapshot_max_mb.saturating_mul(1024 * 1024);
+        match snapshot::prune_snapshots(
+            &self.write_ops(),
+            &self.export_dir,
+            config.snapshot_keep,
+            max_bytes,
+        ) {
+            Ok(removed) if !removed.is_empty() => {
+                log::info!("[EXPORTER] Snapshots antigos removidos: {}", removed.len())
+            }
+            Ok(_) => {}
+            Err(e) => log::error!("[EXPORTER] ❌ Falha ao limpar snapshots: {}", e),
+        }
+    }
+
+    /// Commit the files of this run when `git_commit_after_export` is on
+    ///
+    /// Errors are only logged here; callers report them as warnings, never
+    /// as failed exports.
+    fn commit_run(
+        &self,
+        files: &[PathBuf],
+        books: &[&Book],
+        config: &ExportConfig,
+    ) -> Result<Option<String>, git::GitExportError> {
+        if !config.git_commit_after_export || files.is_empty() {
+            return Ok(None);
+        }
+
+        let highlights = books.iter().map(|book| book.highlights.len()).sum();
+        let message = git::render_commit_message(
+            &config.commit_message_template,
+            &chrono::Local::now().format("%Y-%m-%d").to_string(),
+            books.len(),
+            highlights,
+        );
+        let _span = self.metrics.span("git");
+        match git::commit_files(&self.export_dir, files, &message) {
+            Ok(Some(commit)) => {
+                log::info!("[EXPORTER] ✅ Commit git {} criado", commit);
+                Ok(Some(commit))
+            }
+            Ok(None) => {
+                log::info!("[EXPORTER] Sem alterações para commit git");
+                Ok(None)
+            }
+            Err(e) => {
+                log::warn!("[EXPORTER] ⚠️ Exportação sem commit git: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Write `highlights.atom`, leaving the file untouched if nothing changed
+    pub fn write_atom_feed(
+        &self,
+        books: &[&Book],
+        config: &ExportConfig,
+    ) -> Result<PathBuf, ExportError> {
+        let feed_path = self.export_dir.join(FEED_FILENAME);
+        let xml = generate_atom_feed(books, config.feed_limit);
+        if fs::read_to_string(&feed_path).ok().as_deref() != Some(xml.as_str()) {
+            self.write_file(&feed_path, xml.as_bytes())?;
+        }
+        Ok(feed_path)
+    }
+
+    /// Write the `_Bookshelf.md` index for the books written in this export
+    ///
+    /// The file is rewritten from scratch each time; links are relative to the
+    /// export root and point at the paths actually written.
+    pub fn write_bookshelf_index(
+        &self,
+        entries: &[(&Book, &PathBuf)],
+        config: &ExportConfig,
+    ) -> Result<PathBuf, ExportError> {
+        let mut rows: Vec<&(&Book, &PathBuf)> = entries.iter().collect();
+        // Ties of the sort below keep this total order
+        rows.sort_by(|(a, _), (b, _)| book_order(a, b));
+        match self.index_sort {
+            SortPreference::Title => {
+                rows.sort_by_cached_key(|(book, _)| {
+                    book.title_in(self.title_options.sorting).to_lowercase()
+                });
+            }
+            SortPreference::Author => {
+                rows.sort_by_cached_key(|(book, _)| {
+                    if book.authors.is_empty() {
+                        author_sort_key(&parse_authors(&book.author))
+                    } else {
+                        author_sort_key(&book.authors)
+                    }
+                });
+            }
+            SortPreference::DateLastRead => {
+                rows.sort_by(
+                    |(a, _), (b, _)| match (&a.date_last_read, &b.date_last_read) {
+                        (Some(a), Some(b)) => b.cmp(a),
+                        (Some(_), None) => std::cmp::Ordering::Less,
+                        (None, Some(_)) => std::cmp::Ordering::Greater,
+                        (None, None) => std::cmp::Ordering::Equal,
+                    },
+                );
+            }
+            SortPreference::HighlightCount => {
+                rows.sort_by_key(|(book, _)| std::cmp::Reverse(book.highlights.len()));
+            }
+            SortPreference::TimeSpentReading => {
+                // `None` sorts below every time, so unknown ones come last
+                rows.sort_by_key(|(book, _)| std::cmp::Reverse(book.time_spent_reading_secs));
+            }
+        }
+
+        let mut md = String::new();
+        md.push_str("# Bookshelf\n\n");
+        md.push_str("| Title | Author | Highlights | Last Read |\n");
+        md.push_str("|---|---|---|---|\n");
+
+        for (book, path) in rows {
+            let link = link_target(&self.export_dir, path);
+            let last_read = book
+                .date_last_read
+                .as_deref()
+                .map(|d| format_date(d.get(..10).unwrap_or(d), &config.date_format))
+                .unwrap_or_default();
+
+            md.push_str(&format!(
+                "| [{}]({}) | {} | {} | {} |\n",
+                escape_table_cell(&book.title_in(self.title_options.display)),
+                link,
+                escape_table_cell(&book.display_author()),
+                book.highlights.len(),
+                last_read
+            ));
+        }
+
+        let index_path = self.export_dir.join(BOOKSHELF_FILENAME);
+        self.write_text(&index_path, &md, config)?;
+        Ok(index_path)
+    }
+
+    /// Export book as structured data for frontend processing
+    pub fn export_book_data(&self, book: &Book, config: &ExportConfig) -> ExportBookData {
+        let (books, _) = self.apply_export_rules(std::slice::from_ref(book), config);
+        let book = &books[0];
+
+        // Use all highlights (editing features removed)
+        let highlights = book.highlights_by_position();
+
+        // Convert highlights to export data
+        let highlights_data: Vec<ExportHighlightData> = highlights
+            .iter()
+            .map(|h| {
+                let location = location_parts(book, h).join(" · ");
+
+                ExportHighlightData {
+                    id: h.id.clone(),
+                    text: plain_text(book, h).into_owned(),
+                    chapter: h.chapter_title.clone(),
+                    location,
+                    date: h
+                        .created_local(self.assumed_offset)
+                        .map(|dt| dt.format("%Y-%m-%dT%H:%M:%S").to_string())
+                        .unwrap_or_else(|| h.date_created.clone()),
+                    note: h.annotation.clone(),
+                    color: h.color.clone(),
+                    is_edited: false,
+                }
+            })
+            .collect();
+
+        // Format read date if present
+        let read_date = book
+            .date_last_read
+            .as_ref()
+            .map(|d| format_date(d, &config.date_format));
+
+        ExportBookData {
+            title: book.title_in(self.title_options.display),
+            author: book.display_author(),
+            isbn: book.isbn.clone(),
+            publisher: book.publisher.clone(),
+            language: book.language.clone(),
+            read_date,
+            description: book.description.clone(),
+            toc: book.toc.clone(),
+            highlights: highlights_data,
+        }
+    }
+
+    /// A book's file content in the configured per-book format
+    pub fn render_book(&self, book: &Book, config: &ExportConfig) -> String {
+        match config.format {
+            ExportFormat::Logseq => render_logseq(&self.export_book_data(book, config), config),
+            _ => self.generate_markdown(book, config),
+        }
+    }
+
+    /// Generate markdown content for a book
+    pub fn generate_markdown(&self, book: &Book, config: &ExportConfig) -> String {
+        let mut sink = MarkdownSink::new(String::new());
+        // Writing into a String cannot fail
+        let _ = self.write_markdown(book, config, &mut sink);
+        sink.into_inner()
+    }
+
+    /// Stream a book's markdown into `out`
+    pub fn write_markdown<W: fmt::Write>(
+        &self,
+        book: &Book,
+        config: &ExportConfig,
+        out: &mut MarkdownSink<W>,
+    ) -> fmt::Result {
+        let (books, _) = self.apply_export_rules(std::slice::from_ref(book), config);
+        let book = &books[0];
+        if template::is_custom(config) {
+            return out.line(&template::render_template(
+                book,
+                config,
+                self.assumed_offset,
+                self.title_options.display,
+            ));
+        }
+
+        self.write_header(book, config, out)?;
+        if book.highlights.is_empty() {
+            return write_vocabulary(out, book, config);
+        }
+
+        if config.include_toc && !book.toc.is_empty() {
+            return self.write_markdown_with_toc(book, config, out);
+        }
+
+        out.line("---")?;
+        out.blank()?;
+
+        // Render highlights sequentially (no chapter grouping)
+        let highlights = book.highlights_by_position();
+        if config.group_by_color {
+            self.write_color_groups(out, book, &highlights, config)?;
+        } else {
+            self.write_highlights(out, book, &highlights, config)?;
+        }
+        write_vocabulary(out, book, config)
+    }
+
+    /// Title, metadata and (for books with highlights) the stats block
+    fn write_header<W: fmt::Write>(
+        &self,
+        book: &Book,
+        config: &ExportConfig,
+        out: &mut MarkdownSink<W>,
+    ) -> fmt::Result {
+        out.line(&format!("# {}", book.title_in(self.title_options.display)))?;
+        out.blank()?;
+        if let Some(url) = &book.source_url {
+            out.line(&source_link(url))?;
+            out.blank()?;
+        }
+
+        let mut metadata: Vec<String> = Vec::new();
+        if book.is_orphaned {
+            metadata.push(ORPHANED_NOTE.to_string());
+        }
+        let now = self.clock.now().naive_utc();
+        for field in config.metadata.field_order() {
+            if config.metadata.is_enabled(field) {
+                push_metadata_field(&mut metadata, book, field, config, now);
+            }
+        }
+        if !metadata.is_empty() {
+            for line in &metadata {
+                out.line(line)?;
+            }
+            out.blank()?;
+        }
+
+        if let Some(notes) = book_notes(book, config) {
+            out.line(&format!("## {}", NOTES_HEADING))?;
+            out.blank()?;
+            for line in note_lines(notes, config.metadata.book_notes_raw) {
+                out.line(&line)?;
+            }
+            out.blank()?;
+        }
+
+        if config.metadata.stats && !book.highlights.is_empty() {
+            for line in self
+                .generate_stats_markdown(&BookStats::at_offset(book, self.assumed_offset), config)
+            {
+                out.line(&line)?;
+            }
+            out.blank()?;
+        }
+        Ok(())
+    }
+
+    /// Highlights grouped under chapter headings, preceded by the book's TOC
+    ///
+    /// TOC entries link to the heading of their chapter when it has
+    /// highlights; the rest are listed without a link.
+    fn write_markdown_with_toc<W: fmt::Write>(
+        &self,
+        book: &Book,
+        config: &ExportConfig,
+        out: &mut MarkdownSink<W>,
+    ) -> fmt::Result {
+        let highlights = book.highlights_by_position();
+        let groups = chapter_groups(&highlights);
+
+        let mut used = HashSet::from([heading_anchor(TOC_HEADING)]);
+        if book_notes(book, config).is_some() {
+            used.insert(heading_anchor(NOTES_HEADING));
+        }
+        let anchors: Vec<Option<String>> = groups
+            .iter()
+            .map(|(chapter, _)| chapter.map(|c| unique_anchor(heading_anchor(c), &mut used)))
+            .collect();
+
+        out.line(&format!("## {}", TOC_HEADING))?;
+        out.blank()?;
+        let min_depth = book.toc.iter().map(|e| e.depth).min().unwrap_or(1);
+        for entry in &book.toc {
+            let indent = "  ".repeat((entry.depth - min_depth) as usize);
+            let anchor = groups
+                .iter()
+                .zip(&anchors)
+                .find(|((chapter, _), _)| *chapter == Some(entry.title.as_str()))
+                .and_then(|(_, anchor)| anchor.as_ref());
+            match anchor {
+                Some(anchor) => out.line(&format!("{}- [{}](#{})", indent, entry.title, anchor))?,
+                None => out.line(&format!("{}- {}", indent, entry.title))?,
+            }
+        }
+        out.blank()?;
+
+        out.line("---")?;
+        out.blank()?;
+
+        self.write_chapter_groups(out, book, &groups, config)?;
+        write_vocabulary(out, book, config)
+    }
+
+    /// Highlights under one `## label` heading per color (see
+    /// `color_groups`)
+    fn write_color_groups<W: fmt::Write>(
+        &self,
+        out: &mut MarkdownSink<W>,
+        book: &Book,
+        highlights: &[&Highlight],
+        config: &ExportConfig,
+    ) -> fmt::Result {
+        let groups = color_groups(highlights.iter().copied(), |h| h.color.as_deref(), config);
+        let groups: Vec<(Option<&str>, Vec<&Highlight>)> = groups
+            .iter()
+            .map(|(label, highlights)| (Some(label.as_str()), highlights.clone()))
+            .collect();
+        self.write_chapter_groups(out, book, &groups, config)
+    }
+
+    /// Each group's highlights under its `## chapter` heading
+    fn write_chapter_groups<W: fmt::Write>(
+        &self,
+        out: &mut MarkdownSink<W>,
+        book: &Book,
+        groups: &[(Option<&str>, Vec<&Highlight>)],
+        config: &ExportConfig,
+    ) -> fmt::Result {
+        for (chapter, highlights) in groups {
+            if let Some(chapter) = chapter {
+                out.line(&format!("## {}", chapter))?;
+                out.blank()?;
+            }
+            self.write_highlights(out, book, highlights, config)?;
+        }
+        Ok(())
+    }
+
+    /// Overview file of a split book: the header, links to its parts and the
+    /// vocabulary
+    fn write_split_overview<W: fmt::Write>(
+        &self,
+        book: &Book,
+        config: &ExportConfig,
+        parts: &[PathBuf],
+        out: &mut MarkdownSink<W>,
+    ) -> fmt::Result {
+        self.write_header(book, config, out)?;
+        out.line(&format!("## {}", PARTS_HEADING))?;
+        out.blank()?;
+        let total = book.highlights.len();
+        for (index, part) in parts.iter().enumerate() {
+            let first = index * LARGE_BOOK_HIGHLIGHTS + 1;
+            let last = ((index + 1) * LARGE_BOOK_HIGHLIGHTS).min(total);
+            let name = part
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            out.line(&format!(
+                "- [Parte {}]({}) — destaques {}–{}",
+                index + 1,
+                encode_link_segment(&name),
+                first,
+                last
+            ))?;
+        }
+        write_vocabulary(out, book, config)
+    }
+
+    /// One part file of a split book, linking back to its overview
+    ///
+    /// With `include_toc` the part's highlights keep their chapter headings,
+    /// with `group_by_color` their color headings.
+    #[allow(clippy::too_many_arguments)]
+    fn write_split_part<W: fmt::Write>(
+        &self,
+        book: &Book,
+        config: &ExportConfig,
+        highlights: &[&Highlight],
+        number: usize,
+        count: usize,
+        overview: &Path,
+        out: &mut MarkdownSink<W>,
+    ) -> fmt::Result {
+        let title = book.title_in(self.title_options.display);
+        out.line(&format!("# {} (Parte {} de {})", title, number, count))?;
+        out.blank()?;
+        let overview_name = overview
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        out.line(&format!(
+            "[← {}]({})",
+            title,
+            encode_link_segment(&overview_name)
+        ))?;
+        out.blank()?;
+        out.line("---")?;
+        out.blank()?;
+
+        if config.include_toc && !book.toc.is_empty() {
+            self.write_chapter_groups(out, book, &chapter_groups(highlights), config)
+        } else if config.group_by_color {
+            self.write_color_groups(out, book, highlights, config)
+        } else {
+            self.write_highlights(out, book, highlights, config)
+        }
+    }
+
+    /// Generate the compact statistics list shown after the metadata
+    fn generate_stats_markdown(&self, stats: &BookStats, config: &ExportConfig) -> Vec<String> {
+        let mut lines = vec![
+            format!("- **Destaques**: {}", stats.highlights_count),
+            format!("- **Notas**: {}", stats.notes_count),
+        ];
+
+        if let (Some(first), Some(last)) = (&stats.first_highlight_date, &stats.last_highlight_date)
+        {
+            let first = format_date(first, &config.date_format);
+            let last = format_date(last, &config.date_format);
+            if first == last {
+                lines.push(format!("- **Período**: {}", first));
+            } else {
+                lines.push(format!("- **Período**: {} – {}", first, last));
+            }
+        }
+        if let Some(chapter) = &stats.top_chapter {
+            lines.push(format!("- **Capítulo mais destacado**: {}", chapter));
+        }
+        if let Some(percent) = &stats.percent_read {
+            lines.push(format!("- **Lido**: {}", percent));
+        }
+        if let Some(time) = &stats.time_spent_reading {
+            lines.push(format!("- **Tempo de leitura**: {}", time));
+        }
+
+        lines
+    }
+
+    /// Write highlights with the configured separator between them
+    fn write_highlights<W: fmt::Write>(
+        &self,
+        out: &mut MarkdownSink<W>,
+        book: &Book,
+        highlights: &[&Highlight],
+        config: &ExportConfig,
+    ) -> fmt::Result {
+        for (index, highlight) in highlights.iter().enumerate() {
+            if index > 0 {
+                match config.highlight_separator {
+                    HighlightSeparator::None => {}
+                    HighlightSeparator::Rule => {
+                        // A rule right under text would turn it into a heading
+                        if out.after_open_text() {
+                            out.blank()?;
+                        }
+                        out.line("---")?;
+                        out.blank()?;
+                    }
+                    HighlightSeparator::BlankLines(count) => {
+                        for _ in 0..count {
+                            out.blank()?;
+                        }
+                    }
+                }
+            }
+            out.highlight_line(
+                &highlight.id,
+                &self.generate_highlight_markdown(book, highlight, config),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Generate markdown for a single highlight, laid out by the renderer
+    /// of the configured `HighlightStyle` (see `style`)
+    fn generate_highlight_markdown(
+        &self,
+        book: &Book,
+        highlight: &Highlight,
+        config: &ExportConfig,
+    ) -> String {
+        let location = if config.show_location {
+            location_parts(book, highlight)
+        } else {
+            Vec::new()
+        };
+
+        // Invisible anchor so append-mode re-exports can spot the highlight
+        let anchor = if config.write_mode == ExportWriteMode::Append {
+            format!(" {}", highlight_anchor(highlight))
+        } else {
+            String::new()
+        };
+
+        let figure =
+            (highlight.kind == HighlightKind::Figure).then(|| figure_markdown(book, highlight));
+        style::renderer(config).render(&HighlightParts {
+            text: figure.as_deref().unwrap_or(&highlight.text),
+            annotation: highlight
+                .annotation
+                .as_deref()
+                .filter(|note| !note.trim().is_empty()),
+            location,
+            show_location: config.show_location,
+            anchor,
+        })
+    }
+
+    /// Spans and counters recorded since the last export run (or call)
+    pub fn take_metrics(&self) -> MetricsSummary {
+        self.metrics.take()
+    }
+
+    /// Get the export directory path
+    pub fn export_dir(&self) -> &Path {
+        &self.export_dir
+    }
+}
+
+/// Heading of the table of contents section
+const TOC_HEADING: &str = "Índice";
+
+/// Heading of the part links in the overview of a split book
+const PARTS_HEADING: &str = "Partes";
+
+/// Heading of the dictionary lookups section
+const VOCABULARY_HEADING: &str = "Vocabulário";
+
+/// Section of uncolored highlights with `group_by_color`
+const UNCOLORED_HEADING: &str = "Sem cor";
+
+/// Folder next to the exported books holding figure images
+pub const ATTACHMENTS_DIR: &str = "attachments";
+
+/// Caption of figure highlights, before their location
+const FIGURE_CAPTION: &str = "Figura";
+
+/// Heading of the book's reading notes
+const NOTES_HEADING: &str = "Notes";
+
+/// Metadata line of books whose content row is gone from the device
+const ORPHANED_NOTE: &str = "**Note**: This book is no longer on the device; its title \
+    comes from the file name and the rest of its metadata is unavailable";
+
+/// One header field of `book`, skipped when the book has no value for it;
+/// loans are judged at `now`
+fn push_metadata_field(
+    metadata: &mut Vec<String>,
+    book: &Book,
+    field: MetadataField,
+    config: &ExportConfig,
+    now: NaiveDateTime,
+) {
+    match field {
+        MetadataField::Author if !book.author.is_empty() => {
+            metadata.push(format!("**Autor**: {}", book.display_author()));
+        }
+        MetadataField::Series => match (&book.series, book.series_index) {
+            (Some(series), Some(index)) => {
+                metadata.push(format!("**Série**: {} #{}", series, index))
+            }
+            (Some(series), None) => metadata.push(format!("**Série**: {}", series)),
+            (None, _) => {}
+        },
+        MetadataField::Isbn => {
+            if let Some(isbn) = &book.isbn {
+                metadata.push(format!("**ISBN**: {}", isbn));
+            }
+        }
+        MetadataField::Publisher => {
+            if let Some(publisher) = &book.publisher {
+                metadata.push(format!("**Publisher**: {}", publisher));
+            }
+        }
+        MetadataField::DateLastRead => {
+            if let Some(date) = &book.date_last_read {
+                let formatted = format_date(date, &config.date_format);
+                metadata.push(format!("**Data de Leitura**: {}", formatted));
+            }
+        }
+        MetadataField::Language => {
+            if let Some(language) = &book.language {
+                metadata.push(format!("**Idioma**: {}", language));
+            }
+        }
+        MetadataField::Description => {
+            if let Some(description) = &book.description {
+                metadata.push(String::new());
+                metadata.push(description.clone());
+            }
+        }
+        MetadataField::Loan => {
+            let due = book
+                .loan_expiry
+                .as_deref()
+                .and_then(parse_highlight_date)
+                .map(|date| {
+                    let date = date.format("%Y-%m-%d").to_string();
+                    format!(", due {}", format_date(&date, &config.date_format))
+                })
+                .unwrap_or_default();
+            match book.loan_state(now) {
+                Some(LoanState::Expired) => {
+                    metadata.push(format!("**Loan**: Expired library loan{}", due))
+                }
+                Some(_) => metadata.push(format!("**Loan**: Library loan{}", due)),
+                None => {}
+            }
+        }
+        MetadataField::Author => {}
+    }
+}
+
+/// The book's reading notes, when enabled and not blank
+fn book_notes<'a>(book: &'a Book, config: &ExportConfig) -> Option<&'a str> {
+    book.notes
+        .as_deref()
+        .filter(|notes| config.metadata.book_notes && !notes.trim().is_empty())
+}
+
+/// Lines of a reading note: line endings unified, blank runs collapsed
+///
+/// Unless `raw`, lines markdown would read as headings, thematic breaks or
+/// setext underlines (`---` under a line turns it into a heading) are
+/// escaped, so the note can't reshape the document around it. Code blocks
+/// are kept as written, and one left open is closed at the end of the note.
+fn note_lines(notes: &str, raw: bool) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut open_fence: Option<&str> = None;
+    for line in notes.trim().lines() {
+        let line = line.trim_end();
+        match (open_fence, code_fence(line)) {
+            (None, Some(fence)) => open_fence = Some(fence),
+            (Some(open), Some(fence)) if fence.starts_with(open) && line.trim() == fence => {
+                open_fence = None
+            }
+            _ => {}
+        }
+        if open_fence.is_some() {
+            lines.push(line.to_string());
+            continue;
+        }
+        if line.is_empty() && lines.last().is_some_and(|l| l.is_empty()) {
+            continue;
+        }
+        if !raw && is_structural_line(line) {
+            let indent = line.len() - line.trim_start().len();
+            lines.push(format!("{}\\{}", &line[..indent], &line[indent..]));
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    if let Some(fence) = open_fence {
+        lines.push(fence.to_string());
+    }
+    lines
+}
+
+/// The backticks or tildes opening (or closing) a fenced code block
+fn code_fence(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let mark = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let fence = &trimmed[..trimmed.len() - trimmed.trim_start_matches(mark).len()];
+    (fence.len() >= 3).then_some(fence)
+}
+
+/// A heading (`# …`) or a line of only `-`, `*`, `_` or `=` (and spaces)
+fn is_structural_line(line: &str) -> bool {
+    let trimmed = line.trim();
+    if trimmed.starts_with('#') {
+        return true;
+    }
+    let marks: Vec<char> = trimmed.chars().filter(|c| !c.is_whitespace()).collect();
+    !marks.is_empty()
+        && matches!(marks[0], '-' | '*' | '_' | '=')
+        && marks.iter().all(|c| *c == marks[0])
+}
+
+/// Alphabetized dictionary lookups with their dates, when enabled
+fn write_vocabulary<W: fmt::Write>(
+    out: &mut MarkdownSink<W>,
+    book: &Book,
+    config: &ExportConfig,
+) -> fmt::Result {
+    if !config.metadata.vocabulary || book.vocabulary.is_empty() {
+        return Ok(());
+    }
+
+    let mut entries: Vec<_> = book.vocabulary.iter().collect();
+    entries.sort_by_cached_key(|e| (e.word.to_lowercase(), e.date_created.clone()));
+
+    if out.after_text() {
+        out.blank()?;
+    }
+    out.line(&format!("## {}", VOCABULARY_HEADING))?;
+    out.blank()?;
+    for entry in entries {
+        match entry.date_created.as_deref().and_then(|d| d.get(..10)) {
+            Some(date) => out.line(&format!(
+                "- **{}** — {}",
+                entry.word,
+                format_date(date, &config.date_format)
+            ))?,
+            None => out.line(&format!("- **{}**", entry.word))?,
+        }
+    }
+    out.blank()
+}
+
+/// Items grouped by the label of their `color`, in order of each label's
+/// first item, with uncolored items last under `UNCOLORED_HEADING`
+fn color_groups<'a, T>(
+    items: impl IntoIterator<Item = &'a T>,
+    color: fn(&T) -> Option<&str>,
+    config: &ExportConfig,
+) -> Vec<(String, Vec<&'a T>)> {
+    let mut groups: Vec<(String, Vec<&T>)> = Vec::new();
+    let mut uncolored = Vec::new();
+    for item in items {
+        let Some(color) = color(item).filter(|c| !c.trim().is_empty()) else {
+            uncolored.push(item);
+            continue;
+        };
+        let label = color_label(&config.color_labels, color);
+        match groups.iter_mut().find(|(l, _)| *l == label) {
+            Some((_, group)) => group.push(item),
+            None => groups.push((label, vec![item])),
+        }
+    }
+    if !uncolored.is_empty() {
+        groups.push((UNCOLORED_HEADING.to_string(), uncolored));
+    }
+    groups
+}
+
+/// Highlights grouped by chapter, in order of each chapter's first highlight
+fn chapter_groups<'a>(highlights: &[&'a Highlight]) -> Vec<(Option<&'a str>, Vec<&'a Highlight>)> {
+    let mut groups: Vec<(Option<&str>, Vec<&Highlight>)> = Vec::new();
+    for highlight in highlights {
+        let chapter = highlight.chapter_title.as_deref();
+        match groups.iter_mut().find(|(c, _)| *c == chapter) {
+            Some((_, group)) => group.push(highlight),
+            None => groups.push((chapter, vec![highlight])),
+        }
+    }
+    groups
+}
+
+/// Files a book was written to: its path and, when split, its parts, each
+/// with its sidecar when enabled
+fn book_files(book: &Book, config: &ExportConfig, path: &Path) -> Vec<PathBuf> {
+    let mut files = vec![path.to_path_buf()];
+    files.extend(parts::part_paths(book, config, path));
+    if sidecar::is_enabled(config) {
+        let sidecars: Vec<PathBuf> = files.iter().map(|f| sidecar::sidecar_path(f)).collect();
+        files.extend(sidecars);
+    }
+    if config.format == ExportFormat::Markdown {
+        files.extend(
+            figure_files(book, path)
+                .into_iter()
+                .map(|(_, figure)| figure)
+                .filter(|figure| figure.is_file()),
+        );
+    }
+    files
+}
+
+/// Cached image and exported copy (in `ATTACHMENTS_DIR` next to `path`) of
+/// each figure highlight of `book` with an image
+fn figure_files<'a>(book: &'a Book, path: &Path) -> Vec<(&'a Path, PathBuf)> {
+    let Some(target_dir) = path.parent() else {
+        return Vec::new();
+    };
+    let attachments = target_dir.join(ATTACHMENTS_DIR);
+    let mut files: Vec<(&Path, PathBuf)> = Vec::new();
+    for source in book
+        .highlights
+        .iter()
+        .filter_map(|h| h.figure_path.as_deref())
+        .map(Path::new)
+    {
+        if let Some(name) = source.file_name() {
+            let copy = attachments.join(name);
+            if !files.iter().any(|(_, known)| *known == copy) {
+                files.push((source, copy));
+            }
+        }
+    }
+    files
+}
+
+/// Slug recording `book` in the manifest
+fn manifest_slug(book: &Book) -> String {
+    if book.slug.is_empty() {
+        book_slug(&book.content_id, &book.title)
+    } else {
+        book.slug.clone()
+    }
+}
+
+/// First of `keys` in a markdown file's YAML frontmatter
+fn frontmatter_value(text: &str, keys: &[&str]) -> Option<String> {
+    let mut lines = text.lines();
+    if lines.next()?.trim_end() != "---" {
+        return None;
+    }
+    lines
+        .take_while(|line| line.trim_end() != "---")
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| keys.contains(&key.trim()))
+        .map(|(_, value)| {
+            value
+                .trim()
+                .trim_matches(|c| c == '"' || c == '\'')
+                .to_string()
+        })
+}
+
+/// Chapter and position of a highlight: "p. 123" when the page is known,
+/// otherwise the progress percentage
+///
+/// Kepub progress is within the chapter ("Ch. 3 · 45% of chapter"), other
+/// formats' within the book ("45% of book"); books of unknown format show a
+/// bare percentage.
+fn location_parts(book: &Book, highlight: &Highlight) -> Vec<String> {
+    let mut parts = Vec::new();
+    if book.progress_scope == Some(ProgressScope::Chapter) {
+        if let Some(number) = book.chapter_number(highlight) {
+            parts.push(format!("Ch. {}", number));
+        }
+    }
+    if let Some(chapter_title) = &highlight.chapter_title {
+        parts.push(chapter_title.clone());
+    }
+    let percent = |progress: f64| (progress.clamp(0.0, 1.0) * 100.0) as i32;
+    match (
+        highlight.page,
+        highlight.chapter_progress,
+        book.progress_scope,
+    ) {
+        (Some(page), _, _) => parts.push(format!("p. {}", page)),
+        (None, Some(progress), Some(ProgressScope::Chapter)) => {
+            parts.push(format!("{}% of chapter", percent(progress)))
+        }
+        (None, Some(progress), Some(ProgressScope::Book)) => {
+            parts.push(format!("{}% of book", percent(progress)))
+        }
+        (None, Some(progress), None) => parts.push(format!("{}%", (progress * 100.0) as i32)),
+        (None, None, _) => {}
+    }
+    parts
+}
+
+/// Stand-in text of a figure highlight: its image in `ATTACHMENTS_DIR`
+/// captioned with the location, or the caption alone without an image
+fn figure_markdown(book: &Book, highlight: &Highlight) -> String {
+    let caption = figure_caption(book, highlight);
+    match highlight
+        .figure_path
+        .as_deref()
+        .and_then(|path| Path::new(path).file_name())
+    {
+        Some(name) => format!(
+            "![{}]({}/{})",
+            caption,
+            ATTACHMENTS_DIR,
+            name.to_string_lossy()
+        ),
+        None => format!("*{}*", caption),
+    }
+}
+
+/// "Figura · <location>", naming a figure highlight
+fn figure_caption(book: &Book, highlight: &Highlight) -> String {
+    std::iter::once(FIGURE_CAPTION.to_string())
+        .chain(location_parts(book, highlight))
+        .collect::<Vec<_>>()
+        .join(" · ")
+}
+
+/// Text of a highlight in formats without images: figures are named by
+/// their caption
+fn plain_text<'a>(book: &Book, highlight: &'a Highlight) -> Cow<'a, str> {
+    match highlight.kind {
+        HighlightKind::Figure => Cow::Owned(figure_caption(book, highlight)),
+        _ => Cow::Borrowed(&highlight.text),
+    }
+}
+
+/// Anchor of a markdown heading, as GitHub and Obsidian generate it
+///
+/// Lowercased, punctuation removed, spaces turned into hyphens; letters
+/// outside ASCII are kept.
+pub fn heading_anchor(heading: &str) -> String {
+    heading
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Suffix repeated anchors with `-1`, `-2`, … like GitHub does
+fn unique_anchor(anchor: String, used: &mut HashSet<String>) -> String {
+    let mut candidate = anchor.clone();
+    let mut n = 1;
+    while !used.insert(candidate.clone()) {
+        candidate = format!("{}-{}", anchor, n);
+        n += 1;
+    }
+    candidate
+}
+
+/// Generate a filename for the book
+pub fn generate_filename(book: &Book) -> String {
+    titled_filename(book, &book.full_title())
+}
+
+/// `generate_filename` with `title` in place of the book's
+fn titled_filename(book: &Book, title: &str) -> String {
+    let sanitized_title = sanitize_filename(title);
+    let sanitized_author = sanitize_filename(&book.author);
+    format!(
+        "{} - {}{}.md",
+        sanitized_title,
+        sanitized_author,
+        disambiguation_suffix(book)
+    )
+}
+
+/// ` (2019)` for a book with a same-title-author sibling in its batch
+fn disambiguation_suffix(book: &Book) -> String {
+    book.disambiguator
+        .as_deref()
+        .map(|disambiguator| format!(" ({})", sanitize_filename(disambiguator)))
+        .unwrap_or_default()
+}
+
+/// Filename for a book from a filename pattern (empty = `generate_filename`)
+///
+/// Supports `{title}`, `{author}`, `{series}` and `{series_index}`. Parts
+/// separated by " - " that resolve to nothing are dropped, so
+/// `{series} {series_index} - {title}` degrades to `{title}` for standalone books.
+pub fn resolve_filename_pattern(pattern: &str, book: &Book) -> String {
+    resolve_titled_filename(pattern, book, &book.full_title())
+}
+
+/// `resolve_filename_pattern` with `title` as `{title}`
+fn resolve_titled_filename(pattern: &str, book: &Book, title: &str) -> String {
+    if pattern.trim().is_empty() {
+        return titled_filename(book, title);
+    }
+
+    let parts: Vec<String> = pattern
+        .split(" - ")
+        .map(|part| collapse_spaces(&substitute_variables(part, book, title)))
+        .filter(|part| !part.is_empty())
+        .collect();
+    format!(
+        "{}{}.md",
+        sanitize_filename(&parts.join(" - ")),
+        disambiguation_suffix(book)
+    )
+}
+
+/// Resolve the export subfolder for a book from a folder pattern
+///
+/// Supported variables: `{language}` (uppercase ISO 639-1 code, or `unknown`),
+/// `{author}` (first normalized author), `{title}`, `{series}` and
+/// `{series_index}`. Each path segment is sanitized; segments that resolve to
+/// nothing are skipped and an empty pattern exports to the root.
+pub fn resolve_folder_pattern(pattern: &str, book: &Book) -> PathBuf {
+    let segments: Vec<&str> = pattern.split(['/', '\\']).collect();
+    resolve_folder_segments(&segments, book, &book.full_title())
+}
+
+/// Folder of Pocket articles with `group_pocket_articles`
+pub const POCKET_FOLDER: &str = "Pocket Articles";
+
+/// Export subfolder of a book, with `title` as `{title}`; ASCII-only with
+/// `ascii_filenames`
+///
+/// A segment left without letters or digits (an all-CJK author, …) becomes
+/// a short hash of its original name, so distinct names stay distinct.
+pub fn export_folder(config: &ExportConfig, book: &Book, title: &str) -> PathBuf {
+    if config.group_pocket_articles && book.kind == BookKind::PocketArticle {
+        return PathBuf::from(POCKET_FOLDER);
+    }
+    let segments: Vec<&str> = config.folder_pattern.split(['/', '\\']).collect();
+    let folder = resolve_folder_segments(&segments, book, title);
+    if !config.ascii_filenames {
+        return folder;
+    }
+    folder
+        .iter()
+        .map(|segment| {
+            let segment = segment.to_string_lossy();
+            ascii_filename(&segment).unwrap_or_else(|| content_hash(&segment))
+        })
+        .filter(|segment| segment != "." && segment != "..")
+        .collect()
+}
+
+/// Export filename of a book titled `title`, ASCII-only with
+/// `ascii_filenames` (falling back to the book's slug when nothing readable
+/// is left)
+pub fn export_filename(config: &ExportConfig, book: &Book, title: &str) -> String {
+    let filename = resolve_titled_filename(&config.filename_pattern, book, title);
+    if !config.ascii_filenames {
+        return filename;
+    }
+    let stem = filename.strip_suffix(".md").unwrap_or(&filename);
+    let stem = ascii_filename(stem).unwrap_or_else(|| book_slug(&book.content_id, &book.title));
+    format!("{}.md", stem)
+}
+
+/// Resolve already-split pattern segments, joining them with `PathBuf`
+fn resolve_folder_segments(segments: &[&str], book: &Book, title: &str) -> PathBuf {
+    segments
+        .iter()
+        .map(|segment| collapse_spaces(&substitute_variables(segment, book, title)))
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| sanitize_filename(&segment))
+        .filter(|segment| segment != "." && segment != "..")
+        .collect()
+}
+
+fn substitute_variables(template: &str, book: &Book, title: &str) -> String {
+    let language = language_folder_name(book.language.as_deref());
+    let author = book
+        .authors
+        .first()
+        .cloned()
+        .unwrap_or_else(|| book.author.clone());
+
+    template
+        .replace("{language}", &language)
+        .replace("{author}", &author)
+        .replace("{title}", title)
+        .replace("{series}", book.series.as_deref().unwrap_or(""))
+        .replace(
+            "{series_index}",
+            &book
+                .series_index
+                .map(format_series_index)
+                .unwrap_or_default(),
+        )
+}
+
+fn collapse_spaces(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Zero-padded series position: 2 -> "02", 2.5 -> "02.5"
+fn format_series_index(index: f32) -> String {
+    let whole = index.trunc() as u32;
+    let formatted = index.to_string();
+    match formatted.find('.') {
+        Some(dot) if index.fract() != 0.0 => format!("{:02}{}", whole, &formatted[dot..]),
+        _ => format!("{:02}", whole),
+    }
+}
+
+/// Escape characters that would break a markdown table cell
+fn escape_table_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Markdown link target for an exported file: relative to the export root,
+/// or a `file://` URL when it lies elsewhere (another drive or share)
+fn link_target(export_dir: &Path, path: &Path) -> String {
+    match path.strip_prefix(export_dir) {
+        Ok(relative) => relative
+            .components()
+            .map(|c| encode_link_segment(&c.as_os_str().to_string_lossy()))
+            .collect::<Vec<_>>()
+            .join("/"),
+        Err(_) => file_url(path, encode_link_segment),
+    }
+}
+
+/// Percent-encode the characters that break a markdown link target
+fn encode_link_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for c in segment.chars() {
+        match c {
+            '%' => encoded.push_str("%25"),
+            ' ' => encoded.push_str("%20"),
+            '(' => encoded.push_str("%28"),
+            ')' => encoded.push_str("%29"),
+            '|' => encoded.push_str("%7C"),
+            '#' => encoded.push_str("%23"),
+            _ => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+/// Markdown link to an article's source URL, with the shown URL escaped and
+/// the target percent-encoded where it would end the link early
+fn source_link(url: &str) -> String {
+    let mut target = String::with_capacity(url.len());
+    for c in url.chars() {
+        match c {
+            ' ' => target.push_str("%20"),
+            '(' => target.push_str("%28"),
+            ')' => target.push_str("%29"),
+            '<' => target.push_str("%3C"),
+            '>' => target.push_str("%3E"),
+            '\\' => target.push_str("%5C"),
+            _ => target.push(c),
+        }
+    }
+    format!("[{}]({})", journal::escape_inline(url), target)
+}
+
+/// Format a date according to the specified format
+fn format_date(date_str: &str, format: &DateFormat) -> String {
+    // Try to parse the date
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(date_str, "%Y-%m-%d") {
+        match format {
+            DateFormat::DdMmYyyy => date.format("%d/%m/%Y").to_string(),
+            DateFormat::DdMonthYyyy => {
+                // Portuguese month names
+                let months = [
+                    "Janeiro",
+                    "Fevereiro",
+                    "Março",
+                    "Abril",
+                    "Maio",
+                    "Junho",
+                    "Julho",
+                    "Agosto",
+                    "Setembro",
+                    "Outubro",
+                    "Novembro",
+                    "Dezembro",
+                ];
+                let month_name = months[(date.month() - 1) as usize];
+                format!("{:02} {} {}", date.day(), month_name, date.year())
+            }
+            DateFormat::Iso8601 => date.format("%Y-%m-%d").to_string(),
+        }
+    } else {
+        date_str.to_string()
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(std::io::Error),
+    /// No space left on the export volume
+    DiskFull(std::io::Error),
+    /// The run was cancelled or timed out
+    Cancelled(Cancelled),
+    /// Written to the staging folder of a transactional run, then discarded
+    RolledBack,
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Io(e) => write!(f, "IO error: {}", e),
+            ExportError::DiskFull(e) => write!(f, "Disk full: {}", e),
+            ExportError::Cancelled(c) => write!(f, "Export stopped: {}", c),
+            ExportError::RolledBack => write!(f, "Rolled back, the export folder is unchanged"),
+        }
     }
 }
 
-/// Generate a filename for the book
-pub fn generate_filename(book: &Book) -> String {
-    let sanitized_title = sanitize_filename(&book.title);
-    let sanitized_author = sanitize_filename(&book.author);
-    format!("{} - {}.md", sanitized_title, sanitized_author)
-}
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExportError::Io(e) | ExportError::DiskFull(e) => Some(e),
+            ExportError::Cancelled(c) => Some(c),
+            ExportError::RolledBack => None,
+        }
+    }
+}
+
+impl From<Cancelled> for ExportError {
+    fn from(cancelled: Cancelled) -> Self {
+        ExportError::Cancelled(cancelled)
+    }
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(err: std::io::Error) -> Self {
+        if is_disk_full(&err) {
+            ExportError::DiskFull(err)
+        } else {
+            ExportError::Io(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        BookKind, BulletIndentation, ExportFormat, ExportWriteMode, HighlightStyle, JournalLayout,
+        RedactionPolicy, TabularColumn, TabularOptions, VocabEntry, DEFAULT_REDACTION_MARKER,
+        DEFAULT_SNAPSHOT_KEEP, DEFAULT_SNAPSHOT_MAX_MB,
+    };
+    use crate::utils::cancel::CancelReason;
+    use crate::utils::fs::{temp_path, LineEndings, MockFileOps, UTF8_BOM};
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    fn create_test_book() -> Book {
+        Book {
+            content_id: "book1".to_string(),
+            title: "Test Book".to_string(),
+            author: "Test Author".to_string(),
+            authors: Vec::new(),
+            isbn: Some("978-1234567890".to_string()),
+            publisher: Some("Test Publisher".to_string()),
+            language: Some("en".to_string()),
+            date_last_read: Some("2025-01-24".to_string()),
+            description: Some("A test book description".to_string()),
+            file_path: None,
+            cover_path: None,
+            thumbnail_path: None,
+            series: None,
+            series_index: None,
+            toc: Vec::new(),
+            highlights: vec![
+                Highlight {
+                    id: "hl1".to_string(),
+                    text: "First highlight".to_string(),
+                    annotation: None,
+                    chapter_title: Some("Chapter 1".to_string()),
+                    chapter_progress: Some(0.25),
+                    container_path: None,
+                    date_created: "2025-01-24".to_string(),
+                    date_created_utc: None,
+                    color: Some("yellow".to_string()),
+                    merged_from: Vec::new(),
+                    stable_id: String::new(),
+                    is_excluded: false,
+                    kind: HighlightKind::Text,
+                    figure_path: None,
+                    tags: Vec::new(),
+                    page: None,
+                },
+                Highlight {
+                    id: "hl2".to_string(),
+                    text: "Second highlight".to_string(),
+                    annotation: None,
+                    chapter_title: Some("Chapter 1".to_string()),
+                    chapter_progress: Some(0.50),
+                    container_path: None,
+                    date_created: "2025-01-25".to_string(),
+                    date_created_utc: None,
+                    color: None,
+                    merged_from: Vec::new(),
+                    stable_id: String::new(),
+                    is_excluded: false,
+                    kind: HighlightKind::Text,
+                    figure_path: None,
+                    tags: Vec::new(),
+                    page: None,
+                },
+            ],
+            highlight_total: None,
+            vocabulary: Vec::new(),
+            slug: String::new(),
+            progress_scope: None,
+            publication_year: None,
+            disambiguator: None,
+            kind: BookKind::Book,
+            source_url: None,
+            is_loan: false,
+            is_orphaned: false,
+            loan_expiry: None,
+            percent_read: None,
+            time_spent_reading_secs: None,
+            notes: None,
+            subtitle: None,
+            raw_title: None,
+        }
+    }
+
+    fn create_test_book_2() -> Book {
+        Book {
+            content_id: "book2".to_string(),
+            title: "Another Book".to_string(),
+            author: "Another Author".to_string(),
+            authors: Vec::new(),
+            isbn: None,
+            publisher: None,
+            language: None,
+            date_last_read: None,
+            description: None,
+            file_path: None,
+            cover_path: None,
+            thumbnail_path: None,
+            series: None,
+            series_index: None,
+            toc: Vec::new(),
+            highlights: vec![Highlight {
+                id: "hl3".to_string(),
+                text: "Another highlight".to_string(),
+                annotation: None,
+                chapter_title: None,
+                chapter_progress: None,
+                container_path: None,
+                date_created: "2025-01-26".to_string(),
+                date_created_utc: None,
+                color: None,
+                merged_from: Vec::new(),
+                stable_id: String::new(),
+                is_excluded: false,
+                kind: HighlightKind::Text,
+                figure_path: None,
+                tags: Vec::new(),
+                page: None,
+            }],
+            highlight_total: None,
+            vocabulary: Vec::new(),
+            slug: String::new(),
+            progress_scope: None,
+            publication_year: None,
+            disambiguator: None,
+            kind: BookKind::Book,
+            source_url: None,
+            is_loan: false,
+            is_orphaned: false,
+            loan_expiry: None,
+            percent_read: None,
+            time_spent_reading_secs: None,
+            notes: None,
+            subtitle: None,
+            raw_title: None,
+        }
+    }
+
+    fn create_test_config() -> ExportConfig {
+        ExportConfig {
+            export_path: "/tmp/export".to_string(),
+            metadata: crate::models::MetadataConfig {
+                author: true,
+                isbn: true,
+                publisher: true,
+                date_last_read: true,
+                language: true,
+                description: true,
+                stats: false,
+                series: false,
+                vocabulary: false,
+                loan: false,
+                book_notes: false,
+                book_notes_raw: false,
+                order: Vec::new(),
+            },
+            date_format: DateFormat::DdMonthYyyy,
+            folder_pattern: String::new(),
+            write_index: false,
+            atom_feed: false,
+            feed_limit: 0,
+            highlight_separator: HighlightSeparator::None,
+            compact: false,
+            show_location: true,
+            excluded_chapter_patterns: Vec::new(),
+            max_concurrent_writes: 1,
+            bullet_indentation: BulletIndentation::Tab,
+            write_mode: ExportWriteMode::Overwrite,
+            journal_layout: JournalLayout::Monthly,
+            git_commit_after_export: false,
+            commit_message_template: String::new(),
+            write_sidecars: false,
+            tabular: TabularOptions::default(),
+            markdown_template: String::new(),
+            ascii_filenames: false,
+            highlight_style: HighlightStyle::Blockquote,
+            redaction: RedactionPolicy::Exclude,
+            redaction_markers: vec![DEFAULT_REDACTION_MARKER.to_string()],
+            group_pocket_articles: false,
+            bom: false,
+            line_endings: LineEndings::Lf,
+            group_by_color: false,
+            color_labels: BTreeMap::new(),
+            cloud_settle_ms: 0,
+            transactional: false,
+            filename_pattern: String::new(),
+            format: ExportFormat::Markdown,
+            citation_notes: false,
+            include_toc: false,
+            snapshot_exports: false,
+            snapshot_keep: DEFAULT_SNAPSHOT_KEEP,
+            snapshot_max_mb: DEFAULT_SNAPSHOT_MAX_MB,
+        }
+    }
+
+    #[test]
+    fn test_export_single_book() {
+        let temp = TempDir::new().unwrap();
+        let book = create_test_book();
+        let config = create_test_config();
+
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let result = exporter.export_book(&book, &config);
+
+        assert!(result.is_ok());
+
+        let file_path = result.unwrap();
+        assert!(file_path.exists());
+
+        let content = fs::read_to_string(file_path).unwrap();
+        assert!(content.contains("# Test Book"));
+        assert!(content.contains("Test Author"));
+        assert!(content.contains("> First highlight"));
+    }
+
+    #[test]
+    fn test_figure_highlights_embed_their_images() {
+        let temp = TempDir::new().unwrap();
+        let image = temp.path().join("cache").join("0123_abcd.png");
+        fs::create_dir_all(image.parent().unwrap()).unwrap();
+        fs::write(&image, b"\x89PNG").unwrap();
+
+        let mut book = create_test_book();
+        book.highlights[0].text = String::new();
+        book.highlights[0].kind = HighlightKind::Figure;
+        book.highlights[0].figure_path = Some(image.to_string_lossy().to_string());
+        book.highlights[1].text = String::new();
+        book.highlights[1].kind = HighlightKind::Figure;
+        let mut config = create_test_config();
+        config.show_location = false;
+
+        let exporter = MarkdownExporter::new(temp.path().join("export"));
+        let file_path = exporter.export_book(&book, &config).unwrap();
+        let content = fs::read_to_string(&file_path).unwrap();
+
+        // Captioned with the location even when locations are hidden
+        assert!(content.contains("> ![Figura · Chapter 1 · 25%](attachments/0123_abcd.png)"));
+        // No image extracted: the caption alone
+        assert!(content.contains("> *Figura · Chapter 1 · 50%*"));
+        let copied = file_path
+            .parent()
+            .unwrap()
+            .join(ATTACHMENTS_DIR)
+            .join("0123_abcd.png");
+        assert_eq!(fs::read(copied).unwrap(), b"\x89PNG");
+    }
+
+    #[test]
+    fn test_figure_images_follow_their_book() {
+        let temp = TempDir::new().unwrap();
+        let image = temp.path().join("cache").join("0123_abcd.png");
+        fs::create_dir_all(image.parent().unwrap()).unwrap();
+        fs::write(&image, b"\x89PNG").unwrap();
+        let mut book = create_test_book();
+        book.highlights[0].text = String::new();
+        book.highlights[0].kind = HighlightKind::Figure;
+        book.highlights[0].figure_path = Some(image.to_string_lossy().to_string());
+
+        // Staged with the book, then recorded and archived like its file
+        let export = temp.path().join("export");
+        let mut config = create_test_config();
+        config.transactional = true;
+        config.snapshot_exports = true;
+        let report = MarkdownExporter::new(export.clone()).export_books_with_events(
+            &[book.clone()],
+            &config,
+            &NoopSink,
+        );
+        assert!(!report.rolled_back);
+        let file_path = PathBuf::from(&report.exported_files[0]);
+        let copied = file_path
+            .parent()
+            .unwrap()
+            .join(ATTACHMENTS_DIR)
+            .join("0123_abcd.png");
+        assert_eq!(fs::read(&copied).unwrap(), b"\x89PNG");
+        let key = manifest_key(&export, &copied);
+        let manifest = append::load_manifest(&export);
+        assert_eq!(manifest.books.get(&key), Some(&manifest_slug(&book)));
+        let snapshots = snapshot::list_snapshots(&export).unwrap();
+        assert!(snapshots[0].files.contains(&key));
+
+        // Formats without images name the figure by its caption
+        let exporter = MarkdownExporter::new(temp.path().join("other"));
+        for format in [ExportFormat::Csv, ExportFormat::Bibtex] {
+            config.format = format;
+            config.citation_notes = true;
+            let (_, records) =
+                render_records(&[&book], &config, utc_offset(), TitleForm::Processed).unwrap();
+            assert!(records.contains("Figura · Chapter 1"), "{}", records);
+        }
+        config.format = ExportFormat::Journal;
+        let journal = journal::render_journal(&[&book], &config, utc_offset());
+        assert!(journal
+            .iter()
+            .any(|(_, text)| text.contains("> Figura · Chapter 1")));
+        config.format = ExportFormat::Logseq;
+        assert!(exporter
+            .render_book(&book, &config)
+            .contains("> Figura · Chapter 1"));
+    }
+
+    #[test]
+    fn test_export_with_markdown_template() {
+        let temp = TempDir::new().unwrap();
+        let book = create_test_book();
+        let mut config = create_test_config();
+        config.markdown_template =
+            "## {{title}}\n{{#highlights}}* {{text}}\n{{/highlights}}".to_string();
+        config.write_sidecars = true;
+        assert!(validate_export_config(&config).is_ok());
+
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let path = exporter.export_book(&book, &config).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("## Test Book\n* First highlight\n"));
+        // No block spans to describe
+        assert!(!sidecar::sidecar_path(&path).exists());
+
+        config.write_mode = ExportWriteMode::Append;
+        assert!(validate_export_config(&config).is_err());
+        config.write_mode = ExportWriteMode::Overwrite;
+        config.markdown_template = "{{#highlights}}{{text}}".to_string();
+        assert!(validate_export_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_filename_sanitization() {
+        let book = Book {
+            content_id: "id1".to_string(),
+            title: "Book: With / Invalid? Characters".to_string(),
+            author: "Author".to_string(),
+            authors: Vec::new(),
+            isbn: None,
+            publisher: None,
+            language: None,
+            date_last_read: None,
+            description: None,
+            file_path: None,
+            cover_path: None,
+            thumbnail_path: None,
+            series: None,
+            series_index: None,
+            toc: Vec::new(),
+            highlights: vec![],
+            highlight_total: None,
+            vocabulary: Vec::new(),
+            slug: String::new(),
+            progress_scope: None,
+            publication_year: None,
+            disambiguator: None,
+            kind: BookKind::Book,
+            source_url: None,
+            is_loan: false,
+            is_orphaned: false,
+            loan_expiry: None,
+            percent_read: None,
+            time_spent_reading_secs: None,
+            notes: None,
+            subtitle: None,
+            raw_title: None,
+        };
+
+        let filename = generate_filename(&book);
+        assert!(!filename.contains(':'));
+        assert!(!filename.contains('/'));
+        assert!(!filename.contains('?'));
+        assert!(filename.ends_with(".md"));
+    }
+
+    #[test]
+    fn test_export_multiple_books() {
+        let temp = TempDir::new().unwrap();
+        let books = vec![create_test_book(), create_test_book_2()];
+        let config = create_test_config();
+
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let results = exporter.export_books(&books, &config);
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.is_ok()));
+
+        // Verify files exist, next to the manifest naming their books
+        let files: Vec<_> = fs::read_dir(temp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name != append::MANIFEST_FILENAME)
+            .collect();
+        assert_eq!(files.len(), 2);
+        assert_eq!(append::load_manifest(temp.path()).books.len(), 2);
+    }
+
+    #[test]
+    fn test_export_dir_created() {
+        let temp = TempDir::new().unwrap();
+        let export_dir = temp.path().join("new_export_dir");
+
+        assert!(!export_dir.exists());
+
+        let _exporter = MarkdownExporter::new(export_dir.clone());
+
+        assert!(export_dir.exists());
+    }
+
+    #[test]
+    fn test_sanitize_filename_empty() {
+        let result = sanitize_filename("");
+        assert_eq!(result, "Untitled");
+    }
+
+    #[test]
+    fn test_sanitize_filename_whitespace() {
+        let result = sanitize_filename("  Book Title  ");
+        assert_eq!(result, "Book Title");
+    }
+
+    #[test]
+    fn test_generate_filename_format() {
+        let book = Book {
+            content_id: "id1".to_string(),
+            title: "My Book".to_string(),
+            author: "John Doe".to_string(),
+            authors: Vec::new(),
+            isbn: None,
+            publisher: None,
+            language: None,
+            date_last_read: None,
+            description: None,
+            file_path: None,
+            cover_path: None,
+            thumbnail_path: None,
+            series: None,
+            series_index: None,
+            toc: Vec::new(),
+            highlights: vec![],
+            highlight_total: None,
+            vocabulary: Vec::new(),
+            slug: String::new(),
+            progress_scope: None,
+            publication_year: None,
+            disambiguator: None,
+            kind: BookKind::Book,
+            source_url: None,
+            is_loan: false,
+            is_orphaned: false,
+            loan_expiry: None,
+            percent_read: None,
+            time_spent_reading_secs: None,
+            notes: None,
+            subtitle: None,
+            raw_title: None,
+        };
+
+        let filename = generate_filename(&book);
+        assert_eq!(filename, "My Book - John Doe.md");
+    }
+
+    #[test]
+    fn test_same_title_author_books_get_distinct_filenames() {
+        let temp = TempDir::new().unwrap();
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let config = crate::settings::AppSettings::default().export_config;
+        let essays = |content_id: &str, year: i32| {
+            let mut book = create_test_book();
+            book.content_id = content_id.to_string();
+            book.title = "Essays".to_string();
+            book.publication_year = Some(year);
+            book
+        };
+        let books = vec![essays("vol-2021", 2021), essays("vol-2019", 2019)];
+
+        let report = exporter.export_books_with_events(&books, &config, &NoopSink);
+        assert!(report.failures.is_empty(), "{:?}", report.failures);
+        let mut names: Vec<String> = report
+            .exported_files
+            .iter()
+            .map(|path| {
+                Path::new(path)
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        names.sort();
+        let author = sanitize_filename(&books[0].author);
+        assert_eq!(
+            names,
+            vec![
+                format!("Essays - {} (2019).md", author),
+                format!("Essays - {} (2021).md", author),
+            ]
+        );
+
+        // Exported alone, a book keeps its plain name
+        let alone = exporter.export_book(&books[0], &config).unwrap();
+        assert!(alone.ends_with(format!("Essays - {}.md", author)));
+
+        // unless the library knows its sibling
+        let exporter = MarkdownExporter::new(temp.path().join("library")).with_disambiguators(
+            HashMap::from([("vol-2021".to_string(), Some("2021".to_string()))]),
+        );
+        let report = exporter.export_books_with_events(&books[..1], &config, &NoopSink);
+        assert!(report.exported_files[0].ends_with(&format!("Essays - {} (2021).md", author)));
+    }
+
+    #[test]
+    fn test_title_forms_in_heading_and_filename() {
+        let temp = TempDir::new().unwrap();
+        let mut config = create_test_config();
+        let mut books = vec![create_test_book()];
+        books[0].title = "THE PRAGMATIC PROGRAMMER: 20TH ANNIVERSARY EDITION".to_string();
+        let mut options = TitleOptions {
+            smart_case: true,
+            split_subtitle: true,
+            ..TitleOptions::default()
+        };
+        crate::utils::titles::process_titles(&mut books, &options);
+        let book = &books[0];
+        let author = sanitize_filename(&book.author);
+
+        let exporter =
+            MarkdownExporter::new(temp.path().to_path_buf()).with_title_options(options.clone());
+        assert!(exporter
+            .generate_markdown(book, &config)
+            .starts_with("# The Pragmatic Programmer: 20th Anniversary Edition\n"));
+        let path = exporter.export_book(book, &config).unwrap();
+        assert!(path.ends_with(format!(
+            "The Pragmatic Programmer - 20th Anniversary Edition - {}.md",
+            author
+        )));
+
+        // Shorter names without the subtitle; raw titles where asked for
+        options.filename_subtitle = false;
+        options.display = TitleForm::Raw;
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf()).with_title_options(options);
+        assert!(exporter
+            .generate_markdown(book, &config)
+            .starts_with("# THE PRAGMATIC PROGRAMMER: 20TH ANNIVERSARY EDITION\n"));
+        let path = exporter.export_book(book, &config).unwrap();
+        assert!(path.ends_with(format!("The Pragmatic Programmer - {}.md", author)));
+
+        // Every format shows the same title form
+        assert_eq!(
+            exporter.export_book_data(book, &config).title,
+            "THE PRAGMATIC PROGRAMMER: 20TH ANNIVERSARY EDITION"
+        );
+        for format in [ExportFormat::Csv, ExportFormat::Bibtex] {
+            config.format = format;
+            let (_, records) = render_records(
+                &[book],
+                &config,
+                utc_offset(),
+                exporter.title_options.display,
+            )
+            .unwrap();
+            assert!(records.contains("THE PRAGMATIC PROGRAMMER: 20TH ANNIVERSARY EDITION"));
+        }
+    }
+
+    #[test]
+    fn test_pocket_article_link_and_grouped_folder() {
+        let temp = TempDir::new().unwrap();
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let mut config = create_test_config();
+        config.folder_pattern = "{author}".to_string();
+        let mut article = create_test_book();
+        article.title = "The Slow Web".to_string();
+        article.kind = BookKind::PocketArticle;
+        article.source_url = Some("https://example.com/slow-web".to_string());
+
+        let markdown = exporter.generate_markdown(&article, &config);
+        assert!(markdown.starts_with(
+            "# The Slow Web\n\n[https://example.com/slow-web](https://example.com/slow-web)\n\n"
+        ));
+        assert_eq!(
+            source_link("https://example.com/a_(b) [c]"),
+            "[https://example.com/a\\_(b) \\[c\\]](https://example.com/a_%28b%29%20[c])"
+        );
+        let bibtex = citation::generate_bibtex(&[&article], false, TitleForm::Processed);
+        assert!(bibtex.starts_with("@online{"), "{}", bibtex);
+        assert!(bibtex.contains("url = {https://example.com/slow-web}"));
+
+        let path = exporter.export_book(&article, &config).unwrap();
+        assert!(path.starts_with(temp.path().join(sanitize_filename(&article.author))));
+        config.group_pocket_articles = true;
+        let path = exporter.export_book(&article, &config).unwrap();
+        assert_eq!(path.parent().unwrap(), temp.path().join(POCKET_FOLDER));
+        // Books stay where the pattern puts them
+        let book = exporter.export_book(&create_test_book(), &config).unwrap();
+        assert!(!book.starts_with(temp.path().join(POCKET_FOLDER)));
+    }
+
+    fn create_series_book(series: Option<&str>, index: Option<f32>) -> Book {
+        let mut book = Book::new(
+            "dune2".to_string(),
+            "Dune Messiah".to_string(),
+            "Frank Herbert".to_string(),
+        );
+        book.series = series.map(str::to_string);
+        book.series_index = index;
+        book
+    }
+
+    #[test]
+    fn test_filename_pattern_with_series() {
+        let pattern = "{series} {series_index} - {title} - {author}";
+
+        let book = create_series_book(Some("Dune"), Some(2.0));
+        assert_eq!(
+            resolve_filename_pattern(pattern, &book),
+            "Dune 02 - Dune Messiah - Frank Herbert.md"
+        );
+
+        let book = create_series_book(Some("Discworld"), Some(2.5));
+        assert_eq!(
+            resolve_filename_pattern(pattern, &book),
+            "Discworld 02.5 - Dune Messiah - Frank Herbert.md"
+        );
+
+        // Standalone books drop the empty series part
+        let book = create_series_book(None, None);
+        assert_eq!(
+            resolve_filename_pattern(pattern, &book),
+            "Dune Messiah - Frank Herbert.md"
+        );
+        assert_eq!(
+            resolve_filename_pattern("", &book),
+            generate_filename(&book)
+        );
+    }
+
+    #[test]
+    fn test_folder_pattern_skips_empty_series() {
+        let book = create_series_book(Some("Dune"), Some(12.0));
+        assert_eq!(
+            resolve_folder_pattern("{author}/{series}", &book),
+            PathBuf::from("Frank Herbert").join("Dune")
+        );
+        assert_eq!(format_series_index(12.0), "12");
+        assert_eq!(format_series_index(0.5), "00.5");
+
+        let book = create_series_book(None, None);
+        assert_eq!(
+            resolve_folder_pattern("{author}/{series}", &book),
+            PathBuf::from("Frank Herbert")
+        );
+    }
+
+    #[test]
+    fn test_markdown_series_metadata() {
+        let temp = TempDir::new().unwrap();
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let mut config = create_test_config();
+
+        let book = create_series_book(Some("Dune"), Some(2.0));
+        assert!(!exporter
+            .generate_markdown(&book, &config)
+            .contains("**Série**"));
+
+        config.metadata.series = true;
+        assert!(exporter
+            .generate_markdown(&book, &config)
+            .contains("**Série**: Dune #2"));
+        let book = create_series_book(Some("Discworld"), Some(2.5));
+        assert!(exporter
+            .generate_markdown(&book, &config)
+            .contains("**Série**: Discworld #2.5"));
+        let book = create_series_book(None, None);
+        assert!(!exporter
+            .generate_markdown(&book, &config)
+            .contains("**Série**"));
+    }
+
+    fn metadata_header(book: &Book, config: &ExportConfig) -> String {
+        let exporter = MarkdownExporter::new(PathBuf::from("/tmp/export"));
+        let markdown = exporter.generate_markdown(book, config);
+        let header = markdown.split("\n---\n").next().unwrap();
+        header.trim_start_matches("# Test Book\n\n").to_string()
+    }
+
+    fn new_highlight(id: &str, text: &str) -> Highlight {
+        let mut highlight =
+            Highlight::new(id.to_string(), text.to_string(), "2025-02-01".to_string());
+        highlight.chapter_title = Some("Chapter 2".to_string());
+        highlight
+    }
+
+    /// Export, add two highlights, optionally lose the manifest, export again
+    fn assert_append_keeps_original(delete_manifest: bool) {
+        let temp = TempDir::new().unwrap();
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let mut config = create_test_config();
+        config.write_mode = ExportWriteMode::Append;
+        let mut book = create_test_book();
+
+        let path = exporter.export_book(&book, &config).unwrap();
+        // Hand-written commentary between the highlights
+        let original = fs::read_to_string(&path).unwrap().replacen(
+            "\n> Second",
+            "\nMy own note.\n\n> Second",
+            1,
+        );
+        fs::write(&path, &original).unwrap();
+        if delete_manifest {
+            fs::remove_file(temp.path().join(append::MANIFEST_FILENAME)).unwrap();
+        }
+
+        book.highlights
+            .push(new_highlight("hl4", "Third highlight"));
+        book.highlights
+            .push(new_highlight("hl5", "Fourth highlight"));
+        assert_eq!(exporter.export_book(&book, &config).unwrap(), path);
+
+        let content = fs::read_to_string(&path).unwrap();
+        let added = content.strip_prefix(original.as_str()).unwrap();
+        assert!(added.starts_with("\n## Imported "), "{}", added);
+        assert_eq!(added.matches("<!-- khi:").count(), 2);
+        assert!(added.contains("> Third highlight <!-- khi:hl4 -->"));
+        assert!(added.contains("> Fourth highlight <!-- khi:hl5 -->"));
+
+        // Nothing new: the file is left alone
+        exporter.export_book(&book, &config).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+    }
+
+    #[test]
+    fn test_append_mode_adds_only_new_highlights() {
+        assert_append_keeps_original(false);
+    }
+
+    #[test]
+    fn test_append_mode_survives_lost_manifest() {
+        assert_append_keeps_original(true);
+    }
+
+    #[test]
+    fn test_append_mode_leaves_files_khi_did_not_write() {
+        let temp = TempDir::new().unwrap();
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let mut config = create_test_config();
+        config.write_mode = ExportWriteMode::Append;
+        let book = create_test_book();
+
+        let path = exporter
+            .reserve_book_path(&book, &config, &mut HashSet::new())
+            .unwrap();
+        fs::write(&path, "# My own notes\n").unwrap();
+        let written = exporter.export_book(&book, &config).unwrap();
+
+        // The hand-written file is untouched; the book goes next to it
+        assert_eq!(fs::read_to_string(&path).unwrap(), "# My own notes\n");
+        assert_ne!(written, path);
+        assert!(written.to_string_lossy().ends_with(" (2).md"));
+        let content = fs::read_to_string(&written).unwrap();
+        assert!(content.contains("> First highlight <!-- khi:hl1 -->"));
+        let manifest = append::load_manifest(temp.path());
+        assert_eq!(
+            manifest.files[&manifest_key(temp.path(), &written)],
+            vec!["hl1".to_string(), "hl2".to_string()]
+        );
+
+        // Later runs keep appending to the suffixed file
+        assert_eq!(exporter.export_book(&book, &config).unwrap(), written);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "# My own notes\n");
+    }
+
+    #[test]
+    fn test_append_mode_fails_on_file_created_after_reservation() {
+        let temp = TempDir::new().unwrap();
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let mut config = create_test_config();
+        config.write_mode = ExportWriteMode::Append;
+        let book = create_test_book();
+
+        // The file appears between reserving its path and writing the book
+        let path = exporter
+            .reserve_book_path(&book, &config, &mut HashSet::new())
+            .unwrap();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "# My own notes\n").unwrap();
+        let result = exporter.write_book(&book, &config, path.clone(), None);
+
+        assert!(matches!(
+            result,
+            Err(ExportError::Io(e)) if e.kind() == std::io::ErrorKind::AlreadyExists
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "# My own notes\n");
+        assert!(append::load_manifest(temp.path()).books.is_empty());
+    }
+
+    #[test]
+    fn test_legacy_metadata_keeps_default_order() {
+        let mut config = create_test_config();
+        config.metadata = serde_json::from_str(
+            r#"{"author":true,"isbn":true,"publisher":true,"dateLastRead":true,
+                "language":true,"description":true}"#,
+        )
+        .unwrap();
+        assert!(config.metadata.order.is_empty());
+
+        let expected = "\
+**Autor**: Test Author
+**ISBN**: 978-1234567890
+**Publisher**: Test Publisher
+**Data de Leitura**: 24 Janeiro 2025
+**Idioma**: en
+
+A test book description
+";
+        assert_eq!(metadata_header(&create_test_book(), &config), expected);
+    }
+
+    #[test]
+    fn test_custom_metadata_order() {
+        let mut config = create_test_config();
+        config.metadata.order = vec![
+            MetadataField::DateLastRead,
+            MetadataField::Language,
+            MetadataField::Author,
+        ];
+        let mut book = create_test_book();
+        book.publisher = None;
+
+        // Unlisted fields follow in the default order; fields without a value are skipped
+        let expected = "\
+**Data de Leitura**: 24 Janeiro 2025
+**Idioma**: en
+**Autor**: Test Author
+**ISBN**: 978-1234567890
+
+A test book description
+";
+        assert_eq!(metadata_header(&book, &config), expected);
+    }
+
+    #[test]
+    fn test_duplicate_metadata_fields_first_position_wins() {
+        let mut config = create_test_config();
+        config.metadata.description = false;
+        config.metadata.order =
+            serde_json::from_str(r#"["isbn", "author", "isbn", "date_last_read", "author"]"#)
+                .unwrap();
+        assert_eq!(
+            config.metadata.field_order()[..3],
+            [
+                MetadataField::Isbn,
+                MetadataField::Author,
+                MetadataField::DateLastRead
+            ]
+        );
+        assert_eq!(
+            config.metadata.field_order().len(),
+            MetadataField::DEFAULT_ORDER.len()
+        );
+
+        let expected = "\
+**ISBN**: 978-1234567890
+**Autor**: Test Author
+**Data de Leitura**: 24 Janeiro 2025
+**Publisher**: Test Publisher
+**Idioma**: en
+";
+        assert_eq!(metadata_header(&create_test_book(), &config), expected);
+    }
+
+    #[test]
+    fn test_markdown_renders_normalized_authors() {
+        let temp = TempDir::new().unwrap();
+        let mut book = create_test_book();
+        book.author = "Doe, John; Smith, Jane".to_string();
+        book.authors = crate::utils::author::parse_authors(&book.author);
+
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let markdown = exporter.generate_markdown(&book, &create_test_config());
+
+        assert!(markdown.contains("**Autor**: John Doe, Jane Smith"));
+    }
+
+    #[test]
+    fn test_markdown_toc_links_chapter_headings() {
+        let temp = TempDir::new().unwrap();
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let mut config = create_test_config();
+        let mut book = create_test_book();
+        book.toc = [("Parte I", 1), ("Capítulo 1: O Início", 2), ("Parte II", 1)]
+            .iter()
+            .enumerate()
+            .map(|(order, (title, depth))| TocEntry {
+                title: title.to_string(),
+                depth: *depth,
+                order: order as u32,
+            })
+            .collect();
+        let mut highlight = book.highlights[0].clone();
+        highlight.chapter_title = Some("Capítulo 1: O Início".to_string());
+        book.highlights = vec![highlight.clone(), highlight];
+
+        assert!(!exporter
+            .generate_markdown(&book, &config)
+            .contains("## Índice"));
+
+        config.include_toc = true;
+        let markdown = exporter.generate_markdown(&book, &config);
+        assert!(markdown.contains(
+            "## Índice\n\n- Parte I\n  - [Capítulo 1: O Início](#capítulo-1-o-início)\n- Parte II\n"
+        ));
+        assert_eq!(markdown.matches("## Capítulo 1: O Início").count(), 1);
+        assert_eq!(markdown.matches("> ").count(), 2);
+
+        // A book without TOC rows renders as before
+        book.toc.clear();
+        assert!(!exporter.generate_markdown(&book, &config).contains("## "));
+    }
+
+    #[test]
+    fn test_heading_anchor_and_duplicates() {
+        assert_eq!(
+            heading_anchor("Chapter 3: Connect Your Notes"),
+            "chapter-3-connect-your-notes"
+        );
+        assert_eq!(heading_anchor("Índice"), "índice");
+
+        let mut used = HashSet::new();
+        assert_eq!(unique_anchor("notes".to_string(), &mut used), "notes");
+        assert_eq!(unique_anchor("notes".to_string(), &mut used), "notes-1");
+        assert_eq!(unique_anchor("notes".to_string(), &mut used), "notes-2");
+    }
+
+    #[test]
+    fn test_resolve_folder_pattern() {
+        let mut book = create_test_book();
+
+        assert_eq!(resolve_folder_pattern("", &book), PathBuf::new());
+        assert_eq!(
+            resolve_folder_pattern("{language}", &book),
+            PathBuf::from("EN")
+        );
+        assert_eq!(
+            resolve_folder_pattern("Books/{language}/", &book),
+            PathBuf::from("Books").join("EN")
+        );
+
+        book.language = Some("pt_PT".to_string());
+        assert_eq!(
+            resolve_folder_pattern("{language}", &book),
+            PathBuf::from("PT")
+        );
+
+        book.language = None;
+        assert_eq!(
+            resolve_folder_pattern("{language}", &book),
+            PathBuf::from("unknown")
+        );
+
+        book.authors = vec!["Jane Smith".to_string(), "John Doe".to_string()];
+        assert_eq!(
+            resolve_folder_pattern("{author}/{language}", &book),
+            PathBuf::from("Jane Smith").join("unknown")
+        );
+        book.authors.clear();
+        assert_eq!(
+            resolve_folder_pattern("{author}", &book),
+            PathBuf::from("Test Author")
+        );
+
+        // Traversal segments are dropped
+        assert_eq!(
+            resolve_folder_pattern("../{language}", &book),
+            PathBuf::from("unknown")
+        );
+    }
+
+    #[test]
+    fn test_folder_pattern_never_leaves_export_root() {
+        let book = create_test_book();
+
+        // Mixed separators, drive letters and UNC prefixes stay relative
+        let mixed = resolve_folder_pattern("Books\\{language}/Notes", &book);
+        assert_eq!(mixed, PathBuf::from("Books").join("EN").join("Notes"));
+        for pattern in ["C:\\Books", "\\\\server\\share\\Books", "/Books"] {
+            let resolved = resolve_folder_pattern(pattern, &book);
+            assert!(resolved.is_relative(), "{:?}", resolved);
+            assert!(resolved.ends_with("Books"));
+        }
+    }
+
+    #[test]
+    fn test_ascii_filenames_collide_after_transliteration() {
+        let temp = TempDir::new().unwrap();
+        let mut config = create_test_config();
+        config.folder_pattern = "{author}".to_string();
+        config.ascii_filenames = true;
+
+        let mut accented = create_test_book();
+        accented.title = "Café".to_string();
+        accented.author = "Fiódor Dostoiévski".to_string();
+        accented.authors = vec![accented.author.clone()];
+        let mut plain = accented.clone();
+        plain.content_id = "book1-plain".to_string();
+        plain.title = "Cafe".to_string();
+        let mut cjk = create_test_book_2();
+        cjk.title = "ノルウェイの森".to_string();
+        cjk.author = "村上春樹".to_string();
+        cjk.authors = vec![cjk.author.clone()];
+
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let report =
+            exporter.export_books_with_events(&[accented, plain, cjk.clone()], &config, &NoopSink);
+        assert!(report.failures.is_empty());
+        let author_dir = temp.path().join("Fiodor Dostoievski");
+        assert!(author_dir.join("Cafe - Fiodor Dostoievski.md").exists());
+        assert!(author_dir.join("Cafe - Fiodor Dostoievski (2).md").exists());
+        assert_eq!(
+            PathBuf::from(&report.exported_files[2]),
+            temp.path()
+                .join(content_hash("村上春樹"))
+                .join(format!("{}.md", book_slug(&cjk.content_id, &cjk.title)))
+        );
+        for file in &report.exported_files {
+            let relative = Path::new(file).strip_prefix(temp.path()).unwrap();
+            assert!(relative.to_string_lossy().is_ascii(), "{:?}", relative);
+        }
+
+        // Off by default
+        config.ascii_filenames = false;
+        assert!(exporter
+            .planned_path(&cjk, &config)
+            .ends_with("村上春樹/ノルウェイの森 - 村上春樹.md"));
+    }
+
+    #[test]
+    fn test_link_target_falls_back_to_absolute_url() {
+        let root = std::env::temp_dir().join("khi export");
+        let inside = root.join("My Books").join("a (1).md");
+        assert_eq!(link_target(&root, &inside), "My%20Books/a%20%281%29.md");
+
+        let outside = std::env::temp_dir().join("elsewhere").join("b.md");
+        let link = link_target(&root, &outside);
+        assert!(link.starts_with("file://"), "{}", link);
+        assert!(link.ends_with("/elsewhere/b.md"), "{}", link);
+        assert!(!link.contains('\\'));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_link_target_across_drives() {
+        let link = link_target(Path::new(r"C:\Notes"), Path::new(r"D:\Other\My Book.md"));
+        assert_eq!(link, "file:///D:/Other/My%20Book.md");
+    }
+
+    #[test]
+    fn test_export_into_language_folder_moves_on_language_change() {
+        let temp = TempDir::new().unwrap();
+        let mut book = create_test_book();
+        let mut config = create_test_config();
+        config.folder_pattern = "{language}".to_string();
+
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let first = exporter.export_book(&book, &config).unwrap();
+        assert_eq!(first, temp.path().join("EN").join(generate_filename(&book)));
+
+        book.language = Some("deu".to_string());
+        let second = exporter.export_book(&book, &config).unwrap();
+
+        assert_eq!(
+            second,
+            temp.path().join("DE").join(generate_filename(&book))
+        );
+        assert!(second.exists());
+        assert!(!first.exists());
+    }
+
+    #[test]
+    fn test_language_move_keeps_files_of_other_books_and_user_folders() {
+        let temp = TempDir::new().unwrap();
+        let book = create_test_book();
+        let mut config = create_test_config();
+        config.folder_pattern = "{language}".to_string();
+        let filename = generate_filename(&book);
+
+        // Same file name in a folder the user made, and another edition of
+        // the same title in a language folder
+        let own = temp.path().join("Favourites").join(&filename);
+        fs::create_dir_all(own.parent().unwrap()).unwrap();
+        fs::write(&own, "# My notes").unwrap();
+        let edition = temp.path().join("FR").join(&filename);
+        fs::create_dir_all(edition.parent().unwrap()).unwrap();
+        fs::write(&edition, "---\ncontent_id: other-edition\n---\n# Test Book").unwrap();
+
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        exporter.export_book(&book, &config).unwrap();
+        assert!(own.exists());
+        assert!(edition.exists());
+
+        // A copy khi wrote for this book is still moved
+        let mut moved = book.clone();
+        moved.language = Some("fr".to_string());
+        fs::remove_file(&edition).unwrap();
+        let french = exporter.export_book(&moved, &config).unwrap();
+        assert_eq!(french, edition);
+        assert!(!temp.path().join("EN").join(&filename).exists());
+        assert!(own.exists());
+    }
+
+    /// Sink that records every event for assertions
+    #[derive(Default)]
+    struct RecordingSink {
+        events: std::cell::RefCell<Vec<(String, serde_json::Value)>>,
+    }
+
+    impl EventSink for RecordingSink {
+        fn send(&self, event: &str, payload: serde_json::Value) {
+            self.events.borrow_mut().push((event.to_string(), payload));
+        }
+    }
+
+    #[test]
+    fn test_export_event_payload_shapes() {
+        let started = serde_json::to_value(ExportStartedEvent {
+            total_books: 2,
+            destination: "/tmp/out".to_string(),
+        })
+        .unwrap();
+        assert_eq!(
+            started,
+            serde_json::json!({ "totalBooks": 2, "destination": "/tmp/out" })
+        );
+
+        let progress = serde_json::to_value(ExportProgressEvent {
+            index: 0,
+            total_books: 2,
+            title: "Book".to_string(),
+            status: ExportBookStatus::Failed,
+            path: None,
+            error: Some("IO error".to_string()),
+        })
+        .unwrap();
+        assert_eq!(
+            progress,
+            serde_json::json!({
+                "index": 0,
+                "totalBooks": 2,
+                "title": "Book",
+                "status": "failed",
+                "path": null,
+                "error": "IO error"
+            })
+        );
+
+        let report = serde_json::to_value(ExportReport {
+            destination: "/tmp/out".to_string(),
+            total_books: 1,
+            exported_files: vec!["/tmp/out/a.md".to_string()],
+            failures: vec![],
+            bytes_written: None,
+            aborted: None,
+            excluded_by_chapter: 0,
+            redacted: 0,
+            metrics: MetricsSummary::default(),
+            git_commit: None,
+            warnings: Vec::new(),
+            encoding: TextEncoding {
+                bom: true,
+                line_endings: LineEndings::CrLf,
+            },
+            cloud_provider: Some(CloudProvider::Dropbox),
+            cancelled: None,
+            rolled_back: false,
+        })
+        .unwrap();
+        assert_eq!(
+            report,
+            serde_json::json!({
+                "destination": "/tmp/out",
+                "totalBooks": 1,
+                "exportedFiles": ["/tmp/out/a.md"],
+                "failures": [],
+                "excludedByChapter": 0,
+                "redacted": 0,
+                "encoding": { "bom": true, "lineEndings": "crlf" },
+                "cloudProvider": "Dropbox"
+            })
+        );
+    }
+
+    #[test]
+    fn test_chapter_exclusions_apply_to_export_and_preview() {
+        let temp = TempDir::new().unwrap();
+        let mut book = create_test_book();
+        book.highlights[1].chapter_title = Some("Acknowledgements".to_string());
+        let mut chapterless = create_test_book_2();
+        chapterless.highlights[0].chapter_title = None;
+        let mut config = create_test_config();
+        config.excluded_chapter_patterns = vec!["(?i)acknowledg".to_string()];
+
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf()).with_chapter_exclusions(
+            BookChapterExclusions::from([("book1".to_string(), vec!["chapter 1".to_string()])]),
+        );
+        let report =
+            exporter.export_books_with_events(&[book.clone(), chapterless], &config, &NoopSink);
+
+        assert_eq!(report.excluded_by_chapter, 2);
+        assert!(report.failures.is_empty());
+        let written = fs::read_to_string(&report.exported_files[0]).unwrap();
+        assert!(!written.contains("First highlight"));
+        assert!(!written.contains("Second highlight"));
+        let other = fs::read_to_string(&report.exported_files[1]).unwrap();
+        assert!(other.contains("Another highlight"));
+
+        // Previews and structured data match the exported file
+        assert_eq!(exporter.generate_markdown(&book, &config), written);
+        assert!(exporter
+            .export_book_data(&book, &config)
+            .highlights
+            .is_empty());
+    }
+
+    #[test]
+    fn test_excluded_highlights_are_left_out_of_every_format() {
+        let temp = TempDir::new().unwrap();
+        let mut book = create_test_book();
+        book.highlights[1].is_excluded = true;
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+
+        for format in [
+            ExportFormat::Markdown,
+            ExportFormat::Csv,
+            ExportFormat::Ndjson,
+            ExportFormat::Logseq,
+        ] {
+            let mut config = create_test_config();
+            config.format = format;
+            let report =
+                exporter.export_books_with_events(std::slice::from_ref(&book), &config, &NoopSink);
+            assert!(report.failures.is_empty(), "{:?}", format);
+            let written: String = report
+                .exported_files
+                .iter()
+                .map(|path| fs::read_to_string(path).unwrap())
+                .collect();
+            assert!(written.contains("First highlight"), "{:?}", format);
+            assert!(!written.contains("Second highlight"), "{:?}", format);
+        }
+        let config = create_test_config();
+        assert!(!exporter
+            .generate_markdown(&book, &config)
+            .contains("Second highlight"));
+        assert_eq!(
+            exporter.export_book_data(&book, &config).highlights.len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_export_events_sequence_with_failure() {
+        let temp = TempDir::new().unwrap();
+        let books = vec![create_test_book(), create_test_book_2()];
+        let config = create_test_config();
+
+        // A directory in place of the second book's file makes its write fail
+        fs::create_dir_all(temp.path().join(generate_filename(&books[1]))).unwrap();
 
-/// Sanitize a filename by removing invalid characters
-fn sanitize_filename(filename: &str) -> String {
-    if filename.trim().is_empty() {
-        return "Untitled".to_string();
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let sink = RecordingSink::default();
+        let report = exporter.export_books_with_events(&books, &config, &sink);
+
+        assert_eq!(report.exported_files.len(), 1);
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].title, "Another Book");
+        assert_eq!(report.metrics.span("render").unwrap().count, 2);
+        assert_eq!(report.metrics.span("write").unwrap().count, 2);
+        assert!(report.metrics.counters["bytes_written"] > 0);
+
+        let events = sink.events.borrow();
+        let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "export-started",
+                "export-progress",
+                "export-progress",
+                "export-finished"
+            ]
+        );
+        assert_eq!(events[0].1["totalBooks"], 2);
+        assert_eq!(events[1].1["status"], "exported");
+        assert_eq!(events[2].1["status"], "failed");
+        assert_eq!(events[3].1, serde_json::to_value(&report).unwrap());
     }
 
-    filename
-        .trim()
-        .replace(':', " -")
-        .replace(['/', '\\', '?', '*', '|', '"', '<', '>'], "-")
-        .replace(|c: char| c.is_ascii_control(), "")
-}
+    #[test]
+    fn test_citation_format_writes_one_records_file() {
+        let temp = TempDir::new().unwrap();
+        let books = vec![create_test_book(), create_test_book_2()];
+        let mut config = create_test_config();
+        config.format = ExportFormat::CslJson;
+        config.write_index = true;
 
-/// Format a date according to the specified format
-fn format_date(date_str: &str, format: &DateFormat) -> String {
-    // Try to parse the date
-    if let Ok(date) = chrono::NaiveDate::parse_from_str(date_str, "%Y-%m-%d") {
-        match format {
-            DateFormat::DdMmYyyy => date.format("%d/%m/%Y").to_string(),
-            DateFormat::DdMonthYyyy => {
-                // Portuguese month names
-                let months = [
-                    "Janeiro",
-                    "Fevereiro",
-                    "Março",
-                    "Abril",
-                    "Maio",
-                    "Junho",
-                    "Julho",
-                    "Agosto",
-                    "Setembro",
-                    "Outubro",
-                    "Novembro",
-                    "Dezembro",
-                ];
-                let month_name = months[(date.month() - 1) as usize];
-                format!("{:02} {} {}", date.day(), month_name, date.year())
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let sink = RecordingSink::default();
+        let report = exporter.export_books_with_events(&books, &config, &sink);
+
+        let records = temp.path().join(citation::CSL_JSON_FILENAME);
+        assert_eq!(
+            report.exported_files,
+            vec![records.to_string_lossy().to_string()]
+        );
+        assert!(report.failures.is_empty());
+        let entries: Vec<_> = fs::read_dir(temp.path()).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+
+        let parsed: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&records).unwrap()).unwrap();
+        assert_eq!(parsed.as_array().unwrap().len(), 2);
+
+        let events = sink.events.borrow();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[2].1["title"], "Another Book");
+        assert_eq!(events[2].1["path"], report.exported_files[0].as_str());
+    }
+
+    #[test]
+    fn test_vocabulary_section_is_alphabetized_and_optional() {
+        let exporter = MarkdownExporter::new(TempDir::new().unwrap().path().to_path_buf());
+        let mut config = create_test_config();
+        let mut book = create_test_book();
+        book.vocabulary = vec![
+            VocabEntry {
+                word: "serendipity".to_string(),
+                volume_id: book.content_id.clone(),
+                date_created: Some("2025-01-24T09:00:00.000".to_string()),
+            },
+            VocabEntry {
+                word: "Ephemeral".to_string(),
+                volume_id: book.content_id.clone(),
+                date_created: None,
+            },
+        ];
+
+        let markdown = exporter.generate_markdown(&book, &config);
+        assert!(!markdown.contains(VOCABULARY_HEADING));
+
+        config.metadata.vocabulary = true;
+        let markdown = exporter.generate_markdown(&book, &config);
+        let section = markdown
+            .split(&format!("## {}\n\n", VOCABULARY_HEADING))
+            .nth(1)
+            .unwrap();
+        assert_eq!(
+            section,
+            "- **Ephemeral**\n- **serendipity** — 24 Janeiro 2025\n"
+        );
+
+        config.include_toc = true;
+        book.toc = vec![TocEntry {
+            title: "Chapter 1".to_string(),
+            depth: 1,
+            order: 0,
+        }];
+        let markdown = exporter.generate_markdown(&book, &config);
+        assert!(markdown.contains(VOCABULARY_HEADING));
+    }
+
+    #[test]
+    fn test_book_notes_section() {
+        let exporter = MarkdownExporter::new(TempDir::new().unwrap().path().to_path_buf());
+        let mut config = create_test_config();
+        let mut book = create_test_book();
+        book.notes = Some("Read for the book club.\r\n\r\n\r\nKey idea\n---\n# Not a heading\n  ***\n- a list item\n".to_string());
+
+        let without_notes = exporter.generate_markdown(&book, &config);
+        assert!(!without_notes.contains("book club"));
+        assert!(!without_notes.contains(NOTES_HEADING));
+
+        config.metadata.book_notes = true;
+        let markdown = exporter.generate_markdown(&book, &config);
+        let expected = "\
+## Notes
+
+Read for the book club.
+
+Key idea
+\\---
+\\# Not a heading
+  \\***
+- a list item
+
+";
+        assert!(markdown.contains(expected), "{}", markdown);
+        // After the metadata, before the highlights
+        let notes_at = markdown.find("## Notes").unwrap();
+        assert!(markdown.find("A test book description").unwrap() < notes_at);
+        assert!(notes_at < markdown.find(&book.highlights[0].text).unwrap());
+        // The note added no headings or rules of its own
+        assert_eq!(
+            markdown.matches("\n---\n").count(),
+            without_notes.matches("\n---\n").count()
+        );
+        assert_eq!(
+            markdown.matches("\n#").count(),
+            without_notes.matches("\n#").count() + 1
+        );
+
+        config.metadata.book_notes_raw = true;
+        let markdown = exporter.generate_markdown(&book, &config);
+        assert!(markdown.contains("Key idea\n---\n# Not a heading\n  ***\n"));
+
+        book.notes = Some(" \n ".to_string());
+        let markdown = exporter.generate_markdown(&book, &config);
+        assert!(!markdown.contains(NOTES_HEADING));
+    }
+
+    #[test]
+    fn test_code_blocks_in_book_notes_are_kept_and_closed() {
+        let exporter = MarkdownExporter::new(TempDir::new().unwrap().path().to_path_buf());
+        let mut config = create_test_config();
+        config.metadata.book_notes = true;
+        let mut book = create_test_book();
+        book.notes =
+            Some("Setup:\n```sh\n# install\n\n\n---\n```\nThen:\n~~~\n# left open".to_string());
+
+        let markdown = exporter.generate_markdown(&book, &config);
+        let expected = "\
+Setup:
+```sh
+# install
+
+
+---
+```
+Then:
+~~~
+# left open
+~~~
+";
+        assert!(markdown.contains(expected), "{}", markdown);
+        // The highlights after the note aren't swallowed by the code block
+        let closed_at = markdown.find(expected).unwrap() + expected.len();
+        assert!(markdown[closed_at..].contains(&book.highlights[0].text));
+
+        config.metadata.book_notes_raw = true;
+        let markdown = exporter.generate_markdown(&book, &config);
+        assert!(markdown.contains("# left open\n~~~\n"));
+    }
+
+    #[test]
+    fn test_location_prefers_page_over_percentage() {
+        let exporter = MarkdownExporter::new(TempDir::new().unwrap().path().to_path_buf());
+        let config = create_test_config();
+        let mut book = create_test_book();
+
+        let markdown = exporter.generate_markdown(&book, &config);
+        assert!(markdown.contains("Chapter 1 · 25%"));
+
+        book.highlights[0].page = Some(123);
+        let markdown = exporter.generate_markdown(&book, &config);
+        assert!(markdown.contains("Chapter 1 · p. 123"));
+        assert!(!markdown.contains("25%"));
+        let data = exporter.export_book_data(&book, &config);
+        assert_eq!(data.highlights[0].location, "Chapter 1 · p. 123");
+    }
+
+    #[test]
+    fn test_ndjson_format_streams_one_file() {
+        let temp = TempDir::new().unwrap();
+        let books = vec![create_test_book(), create_test_book_2()];
+        let mut config = create_test_config();
+        config.format = ExportFormat::Ndjson;
+
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let report = exporter.export_books_with_events(&books, &config, &NoopSink);
+
+        let path = temp.path().join(NDJSON_FILENAME);
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(report.bytes_written, Some(content.len() as u64));
+        assert_eq!(fs::read_dir(temp.path()).unwrap().count(), 1);
+
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let total: usize = books.iter().map(|b| b.highlights.len()).sum();
+        assert_eq!(lines.len(), total);
+        assert_eq!(lines[0]["bookTitle"], books[0].title.as_str());
+    }
+
+    #[test]
+    fn test_group_by_color_sections_and_labels() {
+        let mut book = Book::new(
+            "colors".to_string(),
+            "Colors".to_string(),
+            "Author".to_string(),
+        );
+        for (id, color) in [
+            ("q1", Some("yellow")),
+            ("v1", Some("blue")),
+            ("plain", None),
+            ("d1", Some("pink")),
+            ("q2", Some("yellow")),
+        ] {
+            let mut highlight = Highlight::new(
+                id.to_string(),
+                format!("Text {}", id),
+                "2025-01-24T10:00:00".to_string(),
+            );
+            highlight.color = color.map(str::to_string);
+            book.highlights.push(highlight);
+        }
+        let mut config = create_test_config();
+        config.show_location = false;
+        config.group_by_color = true;
+        config.color_labels = BTreeMap::from([
+            ("blue".to_string(), "Vocabulary".to_string()),
+            ("Pink".to_string(), "Disagreements".to_string()),
+        ]);
+        let temp = TempDir::new().unwrap();
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+
+        let markdown = exporter.generate_markdown(&book, &config);
+        let headings: Vec<&str> = markdown
+            .lines()
+            .filter_map(|line| line.strip_prefix("## "))
+            .collect();
+        assert_eq!(
+            headings,
+            vec!["Yellow", "Vocabulary", "Disagreements", UNCOLORED_HEADING]
+        );
+        let yellow = markdown
+            .split("## Yellow\n")
+            .nth(1)
+            .unwrap()
+            .split("## Vocabulary")
+            .next()
+            .unwrap();
+        assert!(yellow.find("Text q1").unwrap() < yellow.find("Text q2").unwrap());
+        assert!(markdown
+            .split(&format!("## {}\n", UNCOLORED_HEADING))
+            .nth(1)
+            .unwrap()
+            .contains("Text plain"));
+
+        config.format = ExportFormat::Csv;
+        config.tabular.columns = vec![TabularColumn::Text, TabularColumn::Color];
+        exporter.export_books_with_events(std::slice::from_ref(&book), &config, &NoopSink);
+        let csv = fs::read_to_string(temp.path().join(tabular::CSV_FILENAME)).unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            rows,
+            vec![
+                "Text,Color,Color Label",
+                "Text q1,yellow,Yellow",
+                "Text v1,blue,Vocabulary",
+                "Text plain,,",
+                "Text d1,pink,Disagreements",
+                "Text q2,yellow,Yellow"
+            ]
+        );
+
+        config.format = ExportFormat::Ndjson;
+        exporter.export_books_with_events(std::slice::from_ref(&book), &config, &NoopSink);
+        let labels: Vec<serde_json::Value> = fs::read_to_string(temp.path().join(NDJSON_FILENAME))
+            .unwrap()
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<serde_json::Value>(line).unwrap()["colorLabel"].clone()
+            })
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                serde_json::json!("Yellow"),
+                serde_json::json!("Vocabulary"),
+                serde_json::Value::Null,
+                serde_json::json!("Disagreements"),
+                serde_json::json!("Yellow")
+            ]
+        );
+
+        config.format = ExportFormat::Markdown;
+        assert!(validate_export_config(&config).is_ok());
+        config.include_toc = true;
+        assert!(validate_export_config(&config)
+            .unwrap_err()
+            .contains("by color"));
+    }
+
+    #[test]
+    fn test_journal_format_writes_month_files() {
+        let temp = TempDir::new().unwrap();
+        let mut books = vec![create_test_book(), create_test_book_2()];
+        books[1].highlights[0].date_created = "2025-03-02T09:00:00".to_string();
+        let mut config = create_test_config();
+        config.format = ExportFormat::Journal;
+
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let sink = RecordingSink::default();
+        let report = exporter.export_books_with_events(&books, &config, &sink);
+
+        let journal = temp.path().join(journal::JOURNAL_FOLDER);
+        let expected = vec![
+            journal.join("2025-01.md").to_string_lossy().to_string(),
+            journal.join("2025-03.md").to_string_lossy().to_string(),
+        ];
+        assert_eq!(report.exported_files, expected);
+        let planned: Vec<String> = exporter
+            .planned_files(&books, &config)
+            .iter()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+        assert_eq!(planned, expected);
+
+        let january = fs::read_to_string(journal.join("2025-01.md")).unwrap();
+        assert_eq!(january.matches("— *Test Book*").count(), 2);
+        let events = sink.events.borrow();
+        assert_eq!(events[1].1["path"], journal.to_string_lossy().as_ref());
+
+        // March loses its only highlight: its file goes, other files stay
+        fs::write(journal.join("Reading plan.md"), "mine").unwrap();
+        books[1].highlights[0].date_created = "2025-01-05T09:00:00".to_string();
+        let report = exporter.export_books_with_events(&books, &config, &NoopSink);
+        assert_eq!(report.exported_files, expected[..1]);
+        assert!(!journal.join("2025-03.md").exists());
+        assert!(journal.join("Reading plan.md").exists());
+
+        config.journal_layout = JournalLayout::Combined;
+        let report = exporter.export_books_with_events(&books, &config, &NoopSink);
+        assert_eq!(
+            report.exported_files,
+            vec![temp
+                .path()
+                .join(journal::JOURNAL_FILENAME)
+                .to_string_lossy()
+                .to_string()]
+        );
+    }
+
+    #[test]
+    fn test_failed_rename_keeps_previous_export() {
+        let temp = TempDir::new().unwrap();
+        let book = create_test_book();
+        let config = create_test_config();
+        let path = MarkdownExporter::new(temp.path().to_path_buf())
+            .export_book(&book, &config)
+            .unwrap();
+        let previous = fs::read_to_string(&path).unwrap();
+
+        let mut ops = MockFileOps::new();
+        ops.expect_create().returning(|path| fs::File::create(path));
+        ops.expect_rename()
+            .returning(|_, _| Err(std::io::Error::other("crash before rename")));
+        let exporter =
+            MarkdownExporter::new(temp.path().to_path_buf()).with_file_ops(Box::new(ops));
+
+        let mut changed = book.clone();
+        changed.highlights.truncate(1);
+        assert!(matches!(
+            exporter.export_book(&changed, &config),
+            Err(ExportError::Io(_))
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), previous);
+        assert!(!temp_path(&path).exists());
+    }
+
+    /// Whether `path` is (the temporary file of) the export manifest
+    fn is_manifest(path: &Path) -> bool {
+        path.file_name().is_some_and(|name| {
+            name.to_string_lossy()
+                .starts_with(append::MANIFEST_FILENAME)
+        })
+    }
+
+    #[test]
+    fn test_manifest_saved_once_per_run() {
+        let temp = TempDir::new().unwrap();
+        let books: Vec<Book> = (0..3)
+            .map(|i| {
+                let mut book = create_test_book();
+                book.content_id = format!("vol{}", i);
+                book.title = format!("Livro {}", i);
+                book
+            })
+            .collect();
+        let mut config = create_test_config();
+        config.max_concurrent_writes = 3;
+
+        let saves = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = saves.clone();
+        let mut ops = MockFileOps::new();
+        ops.expect_create().returning(move |path| {
+            if is_manifest(path) {
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
             }
-            DateFormat::Iso8601 => date.format("%Y-%m-%d").to_string(),
+            fs::File::create(path)
+        });
+        ops.expect_rename()
+            .returning(|from, to| fs::rename(from, to));
+        let exporter =
+            MarkdownExporter::new(temp.path().to_path_buf()).with_file_ops(Box::new(ops));
+
+        let report = exporter.export_books_with_events(&books, &config, &NoopSink);
+        assert!(report.failures.is_empty(), "{:?}", report.failures);
+        assert_eq!(saves.load(std::sync::atomic::Ordering::SeqCst), 1);
+        let manifest = append::load_manifest(temp.path());
+        for file in &report.exported_files {
+            let key = manifest_key(temp.path(), Path::new(file));
+            assert_eq!(
+                manifest.hashes.get(&key),
+                Some(&verify::hash_file(Path::new(file)).unwrap())
+            );
         }
-    } else {
-        date_str.to_string()
+
+        // An unchanged re-export leaves the manifest alone
+        exporter.export_books_with_events(&books, &config, &NoopSink);
+        assert_eq!(saves.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_disk_full_aborts_remaining_books() {
+        let temp = TempDir::new().unwrap();
+        let mut third = create_test_book();
+        third.title = "Terceiro".to_string();
+        let books = vec![create_test_book(), create_test_book_2(), third];
+        let mut config = create_test_config();
+        config.write_index = true;
+
+        let created = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = created.clone();
+        let mut ops = MockFileOps::new();
+        ops.expect_create().returning(move |path| {
+            // Only book files count; the manifest goes through the same ops
+            if is_manifest(path) {
+                return fs::File::create(path);
+            }
+            if counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                fs::File::create(path)
+            } else {
+                Err(std::io::Error::from(std::io::ErrorKind::StorageFull))
+            }
+        });
+        ops.expect_rename()
+            .returning(|from, to| fs::rename(from, to));
+        let exporter =
+            MarkdownExporter::new(temp.path().to_path_buf()).with_file_ops(Box::new(ops));
+
+        let report = exporter.export_books_with_events(&books, &config, &NoopSink);
+
+        assert_eq!(report.exported_files.len(), 1);
+        assert_eq!(report.failures.len(), 1);
+        assert!(report.failures[0].error.starts_with("Disk full"));
+        let aborted = report.aborted.unwrap();
+        assert!(aborted.contains("2 of 3"), "{}", aborted);
+        // Nothing else is attempted after the disk filled up
+        assert_eq!(created.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert!(!temp.path().join(BOOKSHELF_FILENAME).exists());
+    }
+
+    /// Every file under `dir`, by relative path
+    fn read_tree(dir: &Path) -> BTreeMap<String, Vec<u8>> {
+        fn walk(root: &Path, dir: &Path, files: &mut BTreeMap<String, Vec<u8>>) {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    walk(root, &path, files);
+                } else {
+                    let relative = path.strip_prefix(root).unwrap();
+                    files.insert(
+                        relative.to_string_lossy().to_string(),
+                        fs::read(&path).unwrap(),
+                    );
+                }
+            }
+        }
+        let mut files = BTreeMap::new();
+        walk(dir, dir, &mut files);
+        files
+    }
+
+    #[test]
+    fn test_transactional_export_rolls_back_on_failure() {
+        let temp = TempDir::new().unwrap();
+        let books: Vec<Book> = (0..4)
+            .map(|i| {
+                let mut book = create_test_book();
+                book.content_id = format!("vol{}", i);
+                book.title = format!("Livro {}", i);
+                book
+            })
+            .collect();
+        let mut config = create_test_config();
+        config.folder_pattern = "{title}".to_string();
+        config.write_mode = ExportWriteMode::Append;
+        config.write_index = true;
+        config.transactional = true;
+        MarkdownExporter::new(temp.path().to_path_buf())
+            .export_book(&books[0], &config)
+            .unwrap();
+        let before = read_tree(temp.path());
+
+        // The third book can't be written
+        let mut ops = MockFileOps::new();
+        ops.expect_create().returning(|path| {
+            if path.to_string_lossy().contains("Livro 2") {
+                Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
+            } else {
+                fs::File::create(path)
+            }
+        });
+        ops.expect_rename()
+            .returning(|from, to| fs::rename(from, to));
+        let exporter =
+            MarkdownExporter::new(temp.path().to_path_buf()).with_file_ops(Box::new(ops));
+
+        let report = exporter.export_books_with_events(&books, &config, &NoopSink);
+        assert!(report.rolled_back);
+        assert!(report.exported_files.is_empty());
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].title, "Livro 2");
+        assert_eq!(read_tree(temp.path()), before);
+
+        let results = exporter.export_books(&books, &config);
+        assert!(matches!(results[1], Err(ExportError::RolledBack)));
+        assert_eq!(read_tree(temp.path()), before);
+
+        // Without the failure, every book lands at once
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let report = exporter.export_books_with_events(&books, &config, &NoopSink);
+        assert!(!report.rolled_back);
+        assert!(report.failures.is_empty(), "{:?}", report.failures);
+        assert_eq!(report.exported_files.len(), 4);
+        for (book, file) in books.iter().zip(&report.exported_files) {
+            assert!(Path::new(file).starts_with(temp.path().join(&book.title)));
+            assert_eq!(
+                fs::read_to_string(file).unwrap(),
+                exporter.generate_markdown(book, &config)
+            );
+        }
+        let manifest = append::load_manifest(temp.path());
+        assert_eq!(manifest.files.len(), 4);
+        assert!(temp.path().join(BOOKSHELF_FILENAME).exists());
+        assert!(!read_tree(temp.path())
+            .keys()
+            .any(|path| path.starts_with(staging::STAGING_PREFIX)));
+    }
+
+    #[test]
+    fn test_cancelled_export_leaves_whole_files() {
+        let temp = TempDir::new().unwrap();
+        let books: Vec<Book> = (0..5)
+            .map(|i| {
+                let mut book = create_test_book();
+                book.content_id = format!("vol{}", i);
+                book.title = format!("Livro {}", i);
+                book
+            })
+            .collect();
+        let mut config = create_test_config();
+        config.write_index = true;
+
+        // A slow writer; the user cancels while the second book is written
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        let created = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = created.clone();
+        let mut ops = MockFileOps::new();
+        ops.expect_create().returning(move |path| {
+            if is_manifest(path) {
+                return fs::File::create(path);
+            }
+            std::thread::sleep(Duration::from_millis(20));
+            if counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 1 {
+                cancel.cancel();
+            }
+            fs::File::create(path)
+        });
+        ops.expect_rename()
+            .returning(|from, to| fs::rename(from, to));
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf())
+            .with_file_ops(Box::new(ops))
+            .with_cancellation(token);
+        let sink = RecordingSink {
+            events: Default::default(),
+        };
+
+        let report = exporter.export_books_with_events(&books, &config, &sink);
+
+        let cancelled = report.cancelled.clone().unwrap();
+        assert_eq!(cancelled.reason, CancelReason::Requested);
+        assert_eq!((cancelled.completed, cancelled.total), (Some(2), Some(5)));
+        assert!(report.failures.is_empty());
+        assert_eq!(report.exported_files.len(), 2);
+        assert_eq!(created.load(std::sync::atomic::Ordering::SeqCst), 2);
+        // Finished books are complete, nothing half-written is left behind
+        let mut names: Vec<String> = fs::read_dir(temp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name != append::MANIFEST_FILENAME)
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![generate_filename(&books[0]), generate_filename(&books[1])]
+        );
+        for (book, file) in books.iter().zip(&report.exported_files) {
+            assert_eq!(
+                fs::read_to_string(file).unwrap(),
+                exporter.generate_markdown(book, &config)
+            );
+        }
+        let events = sink.events.borrow();
+        let (last, payload) = events.last().unwrap();
+        assert_eq!(last, "export-finished");
+        assert_eq!(payload["cancelled"]["completed"], 2);
+
+        // The plain export reports the cancellation as its last result
+        let results = MarkdownExporter::new(temp.path().join("again"))
+            .with_cancellation(CancellationToken::with_timeout(Some(Duration::ZERO)))
+            .export_books(&books, &config);
+        assert!(matches!(
+            results.as_slice(),
+            [Err(ExportError::Cancelled(Cancelled {
+                reason: CancelReason::DeadlineExceeded,
+                ..
+            }))]
+        ));
+    }
+
+    #[test]
+    fn test_concurrent_export_keeps_input_order() {
+        let temp = TempDir::new().unwrap();
+        let books: Vec<Book> = (0..50)
+            .map(|i| {
+                let mut book = create_test_book();
+                book.content_id = format!("vol{}", i);
+                book.title = format!("Livro {:02}", i);
+                book.highlights[0].text = format!("Destaque do livro {}", i);
+                book
+            })
+            .collect();
+        let mut config = create_test_config();
+        config.max_concurrent_writes = 4;
+        config.write_index = true;
+
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let sink = RecordingSink {
+            events: Default::default(),
+        };
+        let report = exporter.export_books_with_events(&books, &config, &sink);
+
+        assert!(report.failures.is_empty());
+        assert_eq!(report.exported_files.len(), books.len());
+        for (book, file) in books.iter().zip(&report.exported_files) {
+            assert_eq!(
+                Path::new(file),
+                temp.path().join(generate_filename(book)).as_path()
+            );
+            assert_eq!(
+                fs::read_to_string(file).unwrap(),
+                exporter.generate_markdown(book, &config)
+            );
+        }
+        assert!(temp.path().join(BOOKSHELF_FILENAME).exists());
+
+        let mut indices: Vec<u64> = sink
+            .events
+            .borrow()
+            .iter()
+            .filter(|(event, _)| event == "export-progress")
+            .map(|(_, payload)| payload["index"].as_u64().unwrap())
+            .collect();
+        indices.sort();
+        assert_eq!(indices, (0..50).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_export_snapshots_restore_first_run() {
+        let temp = TempDir::new().unwrap();
+        let export = temp.path().join("export");
+        let mut config = create_test_config();
+        config.snapshot_exports = true;
+        config.write_index = true;
+        let exporter = MarkdownExporter::new(export.clone());
+
+        let first_run = vec![create_test_book(), create_test_book_2()];
+        exporter.export_books(&first_run, &config);
+        let first_files: Vec<(String, String)> = [
+            generate_filename(&first_run[0]),
+            generate_filename(&first_run[1]),
+            BOOKSHELF_FILENAME.to_string(),
+        ]
+        .into_iter()
+        .map(|name| {
+            let content = fs::read_to_string(export.join(&name)).unwrap();
+            (name, content)
+        })
+        .collect();
+
+        let mut changed = create_test_book();
+        changed.highlights[0].text = "Texto alterado".to_string();
+        exporter.export_books(&[changed], &config);
+
+        let snapshots = snapshot::list_snapshots(&export).unwrap();
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].files.len(), 3);
+        assert_eq!(snapshots[1].files.len(), 2);
+
+        let restore_dir = temp.path().join("restored");
+        let restored = snapshot::restore_snapshot(&export, &snapshots[0].id, &restore_dir).unwrap();
+        assert_eq!(restored.len(), 3);
+        for (name, content) in &first_files {
+            assert_eq!(
+                &fs::read_to_string(restore_dir.join(name)).unwrap(),
+                content
+            );
+        }
+        // The live export keeps the second run
+        assert!(
+            fs::read_to_string(export.join(generate_filename(&first_run[0])))
+                .unwrap()
+                .contains("Texto alterado")
+        );
+    }
+
+    #[test]
+    fn test_snapshot_failure_does_not_fail_export() {
+        let temp = TempDir::new().unwrap();
+        let mut config = create_test_config();
+        config.snapshot_exports = true;
+        // A file where the snapshot folder should be
+        fs::write(temp.path().join(snapshot::SNAPSHOT_DIR), "").unwrap();
+
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let results = exporter.export_books(&[create_test_book()], &config);
+        assert!(results.iter().all(|r| r.is_ok()));
+    }
+
+    #[test]
+    fn test_git_commits_contain_only_exported_files() {
+        let temp = TempDir::new().unwrap();
+        let repo = git2::Repository::init(temp.path()).unwrap();
+        let mut git_config = repo.config().unwrap();
+        git_config.set_str("user.name", "Leitor").unwrap();
+        git_config
+            .set_str("user.email", "leitor@example.com")
+            .unwrap();
+        // Staged but unrelated to khi
+        fs::write(temp.path().join("todo.txt"), "ler mais").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("todo.txt")).unwrap();
+        index.write().unwrap();
+
+        let mut config = create_test_config();
+        config.git_commit_after_export = true;
+        config.commit_message_template = "{book_count} livro(s)".to_string();
+        config.write_index = true;
+        let exporter = MarkdownExporter::new(temp.path().join("notes"));
+
+        let books = vec![create_test_book(), create_test_book_2()];
+        let first = exporter.export_books_with_events(&books, &config, &NoopSink);
+        let mut changed = create_test_book();
+        changed.highlights[0].text = "Texto alterado".to_string();
+        let second = exporter.export_books_with_events(&[changed], &config, &NoopSink);
+        assert!(first.warnings.is_empty() && second.warnings.is_empty());
+
+        let commit_paths = |id: &Option<String>| {
+            let commit = repo
+                .find_commit(git2::Oid::from_str(id.as_deref().unwrap()).unwrap())
+                .unwrap();
+            let parent = commit.parents().next().map(|p| p.tree().unwrap());
+            let diff = repo
+                .diff_tree_to_tree(parent.as_ref(), Some(&commit.tree().unwrap()), None)
+                .unwrap();
+            let mut paths: Vec<String> = diff
+                .deltas()
+                .map(|d| d.new_file().path().unwrap().to_string_lossy().to_string())
+                .collect();
+            paths.sort();
+            (commit.message().unwrap().to_string(), paths)
+        };
+        let book1 = format!("notes/{}", generate_filename(&books[0]));
+        let book2 = format!("notes/{}", generate_filename(&books[1]));
+        let bookshelf = format!("notes/{}", BOOKSHELF_FILENAME);
+        assert_eq!(
+            commit_paths(&first.git_commit),
+            (
+                "2 livro(s)".to_string(),
+                vec![book2, book1.clone(), bookshelf.clone()]
+            )
+        );
+        assert_eq!(
+            commit_paths(&second.git_commit),
+            ("1 livro(s)".to_string(), vec![book1.clone(), bookshelf])
+        );
+
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.id().to_string(), second.git_commit.unwrap());
+        assert_eq!(head.parent_count(), 1);
+        assert_eq!(head.parent(0).unwrap().parent_count(), 0);
+        // The unrelated file is still staged, and the exports are clean
+        assert!(head
+            .tree()
+            .unwrap()
+            .get_path(Path::new("todo.txt"))
+            .is_err());
+        assert_eq!(
+            repo.status_file(Path::new("todo.txt")).unwrap(),
+            git2::Status::INDEX_NEW
+        );
+        assert_eq!(
+            repo.status_file(Path::new(&book1)).unwrap(),
+            git2::Status::CURRENT
+        );
+    }
+
+    #[test]
+    fn test_bookshelf_index_links_with_folder_pattern_and_collision() {
+        let temp = TempDir::new().unwrap();
+        let mut config = create_test_config();
+        config.folder_pattern = "{language}".to_string();
+        config.filename_pattern = "{title}".to_string();
+        config.write_index = true;
+
+        // Same title, another author: only the filename pattern collides
+        let first = create_test_book();
+        let mut duplicate = create_test_book();
+        duplicate.content_id = "book1-copy".to_string();
+        duplicate.author = "Another Author".to_string();
+        let other = create_test_book_2();
+
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let report =
+            exporter.export_books_with_events(&[first, duplicate, other], &config, &NoopSink);
+        assert!(report.failures.is_empty());
+        assert!(temp.path().join("EN/Test Book (2).md").exists());
+
+        let index = fs::read_to_string(temp.path().join(BOOKSHELF_FILENAME)).unwrap();
+        assert!(index.contains("(EN/Test%20Book.md)"));
+        assert!(index.contains("(EN/Test%20Book%20%282%29.md)"));
+        for file in &report.exported_files {
+            let relative = Path::new(file).strip_prefix(temp.path()).unwrap();
+            let link = relative
+                .to_string_lossy()
+                .replace(' ', "%20")
+                .replace('(', "%28")
+                .replace(')', "%29");
+            assert!(index.contains(&link), "missing link {}", link);
+        }
+    }
+
+    #[test]
+    fn test_bookshelf_index_escapes_pipes_and_is_regenerated() {
+        let temp = TempDir::new().unwrap();
+        let mut config = create_test_config();
+        config.write_index = true;
+
+        let mut book = create_test_book();
+        book.title = "Either | Or".to_string();
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf())
+            .with_index_sort(SortPreference::HighlightCount);
+
+        let results = exporter.export_books(&[book, create_test_book_2()], &config);
+        assert!(results.iter().all(|r| r.is_ok()));
+        let index = fs::read_to_string(temp.path().join(BOOKSHELF_FILENAME)).unwrap();
+        assert!(index.contains(
+            "| [Either \\| Or](Either%20-%20Or%20-%20Test%20Author.md) | Test Author | 2 |"
+        ));
+
+        exporter.export_books(&[create_test_book_2()], &config);
+        let index = fs::read_to_string(temp.path().join(BOOKSHELF_FILENAME)).unwrap();
+        assert!(!index.contains("Either"));
+        assert_eq!(index.matches("Another Book").count(), 1);
     }
-}
 
-#[derive(Debug)]
-pub enum ExportError {
-    Io(std::io::Error),
-}
+    fn layout_config() -> ExportConfig {
+        let mut config = create_test_config();
+        config.metadata = crate::models::MetadataConfig {
+            author: false,
+            isbn: false,
+            publisher: false,
+            date_last_read: false,
+            language: false,
+            description: false,
+            stats: false,
+            series: false,
+            vocabulary: false,
+            loan: false,
+            book_notes: false,
+            book_notes_raw: false,
+            order: Vec::new(),
+        };
+        config
+    }
 
-impl std::fmt::Display for ExportError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ExportError::Io(e) => write!(f, "IO error: {}", e),
-        }
+    #[test]
+    fn test_default_layout_golden() {
+        let exporter = MarkdownExporter::new(TempDir::new().unwrap().path().to_path_buf());
+        let markdown = exporter.generate_markdown(&create_test_book(), &layout_config());
+
+        assert_eq!(
+            markdown,
+            "# Test Book\n\n---\n\n\
+             > First highlight\n\nChapter 1 · 25%\n\n\
+             > Second highlight\n\nChapter 1 · 50%\n"
+        );
     }
-}
 
-impl std::error::Error for ExportError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        match self {
-            ExportError::Io(e) => Some(e),
-        }
+    #[test]
+    fn test_blockquote_style_is_the_default_layout() {
+        let exporter = MarkdownExporter::new(TempDir::new().unwrap().path().to_path_buf());
+        let mut book = create_test_book();
+        book.highlights[0].annotation = Some("A note".to_string());
+        let mut config = layout_config();
+        assert_eq!(config.highlight_style, HighlightStyle::Blockquote);
+        let default = exporter.generate_markdown(&book, &config);
+
+        config.highlight_style = HighlightStyle::Blockquote;
+        assert_eq!(exporter.generate_markdown(&book, &config), default);
+        assert_eq!(
+            default,
+            "# Test Book\n\n---\n\n\
+             > First highlight\n\nChapter 1 · 25%\n\n\
+             > Second highlight\n\nChapter 1 · 50%\n"
+        );
     }
-}
 
-impl From<std::io::Error> for ExportError {
-    fn from(err: std::io::Error) -> Self {
-        ExportError::Io(err)
+    #[test]
+    fn test_callout_style_golden() {
+        let exporter = MarkdownExporter::new(TempDir::new().unwrap().path().to_path_buf());
+        let mut book = create_test_book();
+        book.highlights[0].text = "First paragraph\n\nSecond paragraph".to_string();
+        book.highlights[0].annotation = Some("My note\n\nstill mine".to_string());
+        let mut config = layout_config();
+        config.highlight_style = HighlightStyle::Callout;
+
+        assert_eq!(
+            exporter.generate_markdown(&book, &config),
+            "# Test Book\n\n---\n\n\
+             > [!quote] Chapter 1 · 25%\n\
+             > First paragraph\n\
+             >\n\
+             > Second paragraph\n\
+             >\n\
+             > > [!note]\n\
+             > > My note\n\
+             > >\n\
+             > > still mine\n\n\
+             > [!quote] Chapter 1 · 50%\n\
+             > Second highlight\n"
+        );
+
+        // Without a location the callout has no title
+        config.show_location = false;
+        assert!(exporter
+            .generate_markdown(&book, &config)
+            .ends_with("> [!quote]\n> Second highlight\n"));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::TempDir;
+    #[test]
+    fn test_plain_style_golden() {
+        let exporter = MarkdownExporter::new(TempDir::new().unwrap().path().to_path_buf());
+        let mut book = create_test_book();
+        book.highlights[0].text = "First paragraph\n\nSecond paragraph".to_string();
+        book.highlights[0].annotation = Some("My note".to_string());
+        let mut config = layout_config();
+        config.highlight_style = HighlightStyle::Plain;
 
-    fn create_test_book() -> Book {
-        Book {
-            content_id: "book1".to_string(),
-            title: "Test Book".to_string(),
-            author: "Test Author".to_string(),
-            isbn: Some("978-1234567890".to_string()),
-            publisher: Some("Test Publisher".to_string()),
-            language: Some("en".to_string()),
-            date_last_read: Some("2025-01-24".to_string()),
-            description: Some("A test book description".to_string()),
-            file_path: None,
-            cover_path: None,
-            highlights: vec![
-                Highlight {
-                    id: "hl1".to_string(),
-                    text: "First highlight".to_string(),
-                    annotation: None,
-                    chapter_title: Some("Chapter 1".to_string()),
-                    chapter_progress: Some(0.25),
-                    container_path: None,
-                    date_created: "2025-01-24".to_string(),
-                    color: Some("yellow".to_string()),
-                },
-                Highlight {
-                    id: "hl2".to_string(),
-                    text: "Second highlight".to_string(),
-                    annotation: None,
-                    chapter_title: Some("Chapter 1".to_string()),
-                    chapter_progress: Some(0.50),
-                    container_path: None,
-                    date_created: "2025-01-25".to_string(),
-                    color: None,
-                },
-            ],
-        }
+        assert_eq!(
+            exporter.generate_markdown(&book, &config),
+            "# Test Book\n\n---\n\n\
+             First paragraph\n\nSecond paragraph\n\n\
+             Chapter 1 · 25%\n\n\
+             Nota: My note\n\n\
+             Second highlight\n\n\
+             Chapter 1 · 50%\n"
+        );
     }
 
-    fn create_test_book_2() -> Book {
-        Book {
-            content_id: "book2".to_string(),
-            title: "Another Book".to_string(),
-            author: "Another Author".to_string(),
-            isbn: None,
-            publisher: None,
-            language: None,
-            date_last_read: None,
-            description: None,
-            file_path: None,
-            cover_path: None,
-            highlights: vec![Highlight {
-                id: "hl3".to_string(),
-                text: "Another highlight".to_string(),
-                annotation: None,
-                chapter_title: None,
-                chapter_progress: None,
-                container_path: None,
-                date_created: "2025-01-26".to_string(),
-                color: None,
-            }],
-        }
+    #[test]
+    fn test_compact_layout_golden() {
+        let exporter = MarkdownExporter::new(TempDir::new().unwrap().path().to_path_buf());
+        let mut book = create_test_book();
+        book.highlights[0].text = "First line\n  continues\nhere".to_string();
+        let mut config = layout_config();
+        config.compact = true;
+
+        assert_eq!(
+            exporter.generate_markdown(&book, &config),
+            "# Test Book\n\n---\n\n\
+             - \"First line continues here\" — Chapter 1 · 25%\n\
+             - \"Second highlight\" — Chapter 1 · 50%"
+        );
+
+        config.show_location = false;
+        assert!(exporter
+            .generate_markdown(&book, &config)
+            .ends_with("- \"First line continues here\"\n- \"Second highlight\""));
     }
 
-    fn create_test_config() -> ExportConfig {
-        ExportConfig {
-            export_path: "/tmp/export".to_string(),
-            metadata: crate::models::MetadataConfig {
-                author: true,
-                isbn: true,
-                publisher: true,
-                date_last_read: true,
-                language: true,
-                description: true,
-            },
-            date_format: DateFormat::DdMonthYyyy,
-        }
+    #[test]
+    fn test_separator_layout_golden() {
+        let exporter = MarkdownExporter::new(TempDir::new().unwrap().path().to_path_buf());
+        let book = create_test_book();
+        let mut config = layout_config();
+        config.highlight_separator = HighlightSeparator::Rule;
+
+        assert_eq!(
+            exporter.generate_markdown(&book, &config),
+            "# Test Book\n\n---\n\n\
+             > First highlight\n\nChapter 1 · 25%\n\n---\n\n\
+             > Second highlight\n\nChapter 1 · 50%\n"
+        );
+
+        config.compact = true;
+        assert!(exporter.generate_markdown(&book, &config).ends_with(
+            "- \"First highlight\" — Chapter 1 · 25%\n\n---\n\n\
+             - \"Second highlight\" — Chapter 1 · 50%"
+        ));
+
+        config.compact = false;
+        config.show_location = false;
+        config.highlight_separator = HighlightSeparator::BlankLines(2);
+        assert!(exporter
+            .generate_markdown(&book, &config)
+            .ends_with("> First highlight\n\n\n\n> Second highlight\n"));
     }
 
     #[test]
-    fn test_export_single_book() {
+    fn test_generate_markdown_stats_block() {
+        let exporter = MarkdownExporter::new(std::env::temp_dir());
+        let mut book = create_test_book();
+        book.highlights[1].annotation = Some("Worth revisiting".to_string());
+        let mut config = create_test_config();
+        config.metadata = crate::models::MetadataConfig {
+            author: true,
+            isbn: false,
+            publisher: false,
+            date_last_read: false,
+            language: false,
+            description: false,
+            stats: true,
+            series: false,
+            vocabulary: false,
+            loan: false,
+            book_notes: false,
+            book_notes_raw: false,
+            order: Vec::new(),
+        };
+
+        let markdown = exporter.generate_markdown(&book, &config);
+        let expected = "# Test Book\n\n\
+                        **Autor**: Test Author\n\n\
+                        - **Destaques**: 2\n\
+                        - **Notas**: 1\n\
+                        - **Período**: 24 Janeiro 2025 – 25 Janeiro 2025\n\
+                        - **Capítulo mais destacado**: Chapter 1\n\n\
+                        ---\n";
+        assert!(
+            markdown.starts_with(expected),
+            "unexpected stats block:\n{}",
+            markdown
+        );
+    }
+
+    #[test]
+    fn test_stats_block_renders_reading_stats_like_book_stats() {
+        let exporter = MarkdownExporter::new(std::env::temp_dir());
+        let mut book = create_test_book();
+        book.percent_read = Some(85.6);
+        book.time_spent_reading_secs = Some(4 * 3600 + 32 * 60 + 10);
+        let mut config = create_test_config();
+        config.metadata.stats = true;
+
+        let markdown = exporter.generate_markdown(&book, &config);
+        assert!(markdown.contains("- **Lido**: 86%\n"), "{}", markdown);
+        assert!(markdown.contains("- **Tempo de leitura**: 4 h 32 m\n"));
+
+        // The stats command reads the library copy and renders the same
+        let mut store = crate::library::LibraryStore::open_in_memory().unwrap();
+        store.merge_books(std::slice::from_ref(&book)).unwrap();
+        let stored = store.book(&book.content_id).unwrap().unwrap();
+        let stats = BookStats::from(&stored);
+        assert_eq!(stats.percent_read.as_deref(), Some("86%"));
+        assert_eq!(stats.time_spent_reading.as_deref(), Some("4 h 32 m"));
+        assert_eq!(
+            exporter.generate_stats_markdown(&stats, &config),
+            exporter.generate_stats_markdown(&BookStats::from(&book), &config)
+        );
+
+        // Unknown values leave their lines out
+        book.percent_read = None;
+        book.time_spent_reading_secs = None;
+        let markdown = exporter.generate_markdown(&book, &config);
+        assert!(!markdown.contains("**Lido**"));
+        assert!(!markdown.contains("**Tempo de leitura**"));
+    }
+
+    #[test]
+    fn test_export_diff_unchanged_book() {
         let temp = TempDir::new().unwrap();
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
         let book = create_test_book();
         let config = create_test_config();
+        exporter.export_book(&book, &config).unwrap();
 
-        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
-        let result = exporter.export_book(&book, &config);
+        let diff = exporter.export_diff(&book, &config).unwrap();
+        assert!(!diff.is_new_file);
+        assert!(diff.unified_diff.is_empty());
+        assert_eq!((diff.lines_added, diff.lines_removed), (0, 0));
+    }
 
-        assert!(result.is_ok());
+    #[test]
+    fn test_export_diff_new_highlight() {
+        let temp = TempDir::new().unwrap();
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let mut book = create_test_book();
+        let config = create_test_config();
+        exporter.export_book(&book, &config).unwrap();
 
-        let file_path = result.unwrap();
-        assert!(file_path.exists());
+        let mut added = book.highlights[1].clone();
+        added.id = "hl3".to_string();
+        added.text = "Third highlight".to_string();
+        book.highlights.push(added);
 
-        let content = fs::read_to_string(file_path).unwrap();
-        assert!(content.contains("# Test Book"));
-        assert!(content.contains("Test Author"));
-        assert!(content.contains("> First highlight"));
+        let diff = exporter.export_diff(&book, &config).unwrap();
+        assert_eq!(diff.path, "Test Book - Test Author.md");
+        assert_eq!(diff.highlights_added, 1);
+        assert_eq!(diff.highlights_removed, 0);
+        assert_eq!(diff.lines_removed, 0);
+        assert!(diff.unified_diff.starts_with(
+            "--- a/Test Book - Test Author.md\n+++ b/Test Book - Test Author.md\n@@ "
+        ));
+        assert!(diff.unified_diff.contains("\n+> Third highlight\n"));
     }
 
     #[test]
-    fn test_filename_sanitization() {
-        let book = Book {
-            content_id: "id1".to_string(),
-            title: "Book: With / Invalid? Characters".to_string(),
-            author: "Author".to_string(),
-            isbn: None,
-            publisher: None,
-            language: None,
-            date_last_read: None,
-            description: None,
-            file_path: None,
-            cover_path: None,
-            highlights: vec![],
-        };
+    fn test_export_diff_missing_file_is_new() {
+        let temp = TempDir::new().unwrap();
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let book = create_test_book();
 
-        let filename = generate_filename(&book);
-        assert!(!filename.contains(':'));
-        assert!(!filename.contains('/'));
-        assert!(!filename.contains('?'));
-        assert!(filename.ends_with(".md"));
+        let diff = exporter.export_diff(&book, &create_test_config()).unwrap();
+        assert!(diff.is_new_file);
+        assert_eq!(diff.highlights_added, 2);
+        assert_eq!(diff.lines_removed, 0);
+        assert!(diff.unified_diff.starts_with("--- /dev/null\n"));
     }
 
     #[test]
-    fn test_export_multiple_books() {
+    fn test_export_writes_atom_feed() {
         let temp = TempDir::new().unwrap();
-        let books = vec![create_test_book(), create_test_book_2()];
-        let config = create_test_config();
+        let mut config = create_test_config();
+        config.atom_feed = true;
+        config.feed_limit = 2;
 
         let exporter = MarkdownExporter::new(temp.path().to_path_buf());
-        let results = exporter.export_books(&books, &config);
+        let books = [create_test_book(), create_test_book_2()];
+        exporter.export_books(&books, &config);
 
-        assert_eq!(results.len(), 2);
-        assert!(results.iter().all(|r| r.is_ok()));
+        let feed_path = temp.path().join(feed::FEED_FILENAME);
+        let first = fs::read_to_string(&feed_path).unwrap();
+        assert_eq!(first.matches("<entry>").count(), 2);
 
-        // Verify files exist
-        let files: Vec<_> = fs::read_dir(temp.path()).unwrap().collect();
-        assert_eq!(files.len(), 2);
+        exporter.export_books(&books, &config);
+        assert_eq!(fs::read_to_string(&feed_path).unwrap(), first);
     }
 
     #[test]
-    fn test_export_dir_created() {
+    fn test_sample_library_exports_cleanly_in_every_format() {
+        let books = crate::sample::sample_books();
+        for format in [
+            ExportFormat::Markdown,
+            ExportFormat::CslJson,
+            ExportFormat::Bibtex,
+            ExportFormat::Ndjson,
+            ExportFormat::Journal,
+            ExportFormat::Csv,
+            ExportFormat::Tsv,
+            ExportFormat::Readwise,
+        ] {
+            let temp = TempDir::new().unwrap();
+            let mut config = create_test_config();
+            config.format = format;
+
+            let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+            let report = exporter.export_books_with_events(&books, &config, &NoopSink);
+
+            assert!(
+                report.failures.is_empty(),
+                "{:?}: {:?}",
+                format,
+                report.failures
+            );
+            assert!(report.aborted.is_none(), "{:?}", format);
+            assert!(!report.exported_files.is_empty(), "{:?}", format);
+            let content: String = report
+                .exported_files
+                .iter()
+                .map(|file| fs::read_to_string(file).unwrap())
+                .collect();
+            for book in &books {
+                assert!(
+                    content.contains(&book.title),
+                    "{:?}: {}",
+                    format,
+                    book.title
+                );
+            }
+        }
+    }
+
+    /// `fmt::Write` sink keeping only the total size and the largest write
+    #[derive(Default)]
+    struct CountingSink {
+        bytes: usize,
+        largest_write: usize,
+    }
+
+    impl fmt::Write for CountingSink {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.bytes += s.len();
+            self.largest_write = self.largest_write.max(s.len());
+            Ok(())
+        }
+    }
+
+    /// One book with `count` highlights, like a converted reference PDF
+    fn large_book(count: usize) -> Book {
+        let mut book = Book::new(
+            "file:///mnt/onboard/Reference.epub".to_string(),
+            "Reference".to_string(),
+            "Editors".to_string(),
+        );
+        book.highlights = (0..count)
+            .map(|n| {
+                let mut highlight = Highlight::new(
+                    format!("bm{}", n),
+                    format!(
+                        "Entry {} of the reference, with a definition worth keeping.",
+                        n
+                    ),
+                    "2025-01-24T10:00:00.000".to_string(),
+                );
+                highlight.stable_id = format!("s{:05}", n);
+                highlight.chapter_title = Some(format!("Section {}", n / 500));
+                highlight.chapter_progress = Some(n as f64 / count as f64);
+                highlight
+            })
+            .collect();
+        book
+    }
+
+    #[test]
+    fn test_large_book_streams_and_splits_into_parts() {
         let temp = TempDir::new().unwrap();
-        let export_dir = temp.path().join("new_export_dir");
+        let mut book = large_book(12_000);
+        let config = create_test_config();
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
 
-        assert!(!export_dir.exists());
+        // Streaming never holds more than one highlight's markdown at a time
+        let mut sink = MarkdownSink::new(CountingSink::default());
+        exporter.write_markdown(&book, &config, &mut sink).unwrap();
+        let sink = sink.into_inner();
+        assert!(sink.bytes > 1_000_000);
+        assert!(sink.largest_write < 1024, "{}", sink.largest_write);
 
-        let _exporter = MarkdownExporter::new(export_dir.clone());
+        let results = exporter.export_books(std::slice::from_ref(&book), &config);
+        let overview_path = results[0].as_ref().unwrap().clone();
 
-        assert!(export_dir.exists());
+        let part_paths = parts::part_paths(&book, &config, &overview_path);
+        assert_eq!(part_paths.len(), 6);
+        let mut quoted = Vec::new();
+        for (index, path) in part_paths.iter().enumerate() {
+            let part = fs::read_to_string(path).unwrap();
+            assert!(part.starts_with(&format!("# Reference (Parte {} de 6)", index + 1)));
+            assert!(part.contains("[← Reference](Reference%20-%20Editors.md)"));
+            let lines: Vec<&str> = part.lines().filter(|l| l.starts_with("> ")).collect();
+            assert_eq!(lines.len(), LARGE_BOOK_HIGHLIGHTS);
+            quoted.extend(lines.into_iter().map(str::to_string));
+        }
+        // Every highlight exactly once, in reading order
+        let expected: Vec<String> = book
+            .highlights
+            .iter()
+            .map(|h| format!("> {}", h.text))
+            .collect();
+        assert_eq!(quoted, expected);
+
+        let overview = fs::read_to_string(&overview_path).unwrap();
+        assert!(overview.len() < 4096);
+        assert!(overview.contains(
+            "- [Parte 6](Reference%20-%20Editors%20%28Parte%206%29.md) — destaques 10001–12000"
+        ));
+        assert!(!overview.contains("> Entry"));
+
+        // Back under the threshold: one file again, old parts removed
+        book.highlights.truncate(100);
+        exporter.export_books(std::slice::from_ref(&book), &config);
+        assert!(part_paths.iter().all(|path| !path.exists()));
+        let whole = fs::read_to_string(&overview_path).unwrap();
+        assert_eq!(whole.matches("> Entry").count(), 100);
+    }
+
+    fn read_sidecar(markdown: &Path) -> sidecar::Sidecar {
+        let json = fs::read_to_string(sidecar::sidecar_path(markdown)).unwrap();
+        serde_json::from_str(&json).unwrap()
     }
 
     #[test]
-    fn test_sanitize_filename_empty() {
-        let result = sanitize_filename("");
-        assert_eq!(result, "Untitled");
+    fn test_sidecar_byte_ranges_slice_highlight_blocks() {
+        let temp = TempDir::new().unwrap();
+        let book = create_test_book();
+        let mut config = create_test_config();
+        config.write_sidecars = true;
+        config.highlight_separator = HighlightSeparator::Rule;
+        config.metadata.stats = true;
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+
+        let path = exporter.export_book(&book, &config).unwrap();
+        let markdown = fs::read(&path).unwrap();
+        let sidecar = read_sidecar(&path);
+        assert_eq!(
+            sidecar::sidecar_path(&path),
+            temp.path().join("Test Book - Test Author.khi.json")
+        );
+        assert_eq!(sidecar.content_id, book.content_id);
+        assert_eq!(sidecar.config_hash, preview::hash_json(&config));
+
+        let order: Vec<&str> = book
+            .highlights_by_position()
+            .iter()
+            .map(|h| h.id.as_str())
+            .collect();
+        let listed: Vec<&str> = sidecar
+            .highlights
+            .iter()
+            .map(|e| e.highlight_id.as_str())
+            .collect();
+        assert_eq!(listed, order);
+        for entry in &sidecar.highlights {
+            let highlight = book
+                .highlights
+                .iter()
+                .find(|h| h.id == entry.highlight_id)
+                .unwrap();
+            let range = entry.byte_range.start as usize..entry.byte_range.end as usize;
+            assert_eq!(
+                &markdown[range],
+                exporter
+                    .generate_highlight_markdown(&book, highlight, &config)
+                    .as_bytes()
+            );
+            assert_eq!(entry.anchor, highlight_anchor(highlight));
+        }
     }
 
     #[test]
-    fn test_sanitize_filename_whitespace() {
-        let result = sanitize_filename("  Book Title  ");
-        assert_eq!(result, "Book Title");
+    fn test_bom_and_crlf_apply_to_written_bytes() {
+        let temp = TempDir::new().unwrap();
+        let book = create_test_book();
+        let lf_config = create_test_config();
+        let lf_exporter = MarkdownExporter::new(temp.path().join("lf"));
+        let lf = fs::read(lf_exporter.export_book(&book, &lf_config).unwrap()).unwrap();
+        assert!(!lf.starts_with(UTF8_BOM));
+        assert!(!lf.contains(&b'\r'));
+
+        let mut config = create_test_config();
+        config.bom = true;
+        config.line_endings = LineEndings::CrLf;
+        config.write_sidecars = true;
+        let exporter = MarkdownExporter::new(temp.path().join("crlf"));
+        let path = exporter.export_book(&book, &config).unwrap();
+        let crlf = fs::read(&path).unwrap();
+        let expected = [
+            UTF8_BOM,
+            String::from_utf8(lf)
+                .unwrap()
+                .replace('\n', "\r\n")
+                .as_bytes(),
+        ]
+        .concat();
+        assert_eq!(crlf, expected);
+
+        let sidecar = read_sidecar(&path);
+        for entry in &sidecar.highlights {
+            let highlight = book
+                .highlights
+                .iter()
+                .find(|h| h.id == entry.highlight_id)
+                .unwrap();
+            let range = entry.byte_range.start as usize..entry.byte_range.end as usize;
+            let block = exporter.generate_highlight_markdown(&book, highlight, &config);
+            assert_eq!(&crlf[range], block.replace('\n', "\r\n").as_bytes());
+        }
+
+        // Re-exporting over the CRLF file neither doubles the BOM nor the CRs
+        config.write_mode = ExportWriteMode::Append;
+        exporter.export_book(&book, &config).unwrap();
+        let again = fs::read(&path).unwrap();
+        assert!(again.starts_with(UTF8_BOM) && !again[3..].starts_with(UTF8_BOM));
+        assert!(!again.windows(3).any(|w| w == b"\r\r\n"));
+
+        config.format = ExportFormat::Csv;
+        config.write_mode = ExportWriteMode::Overwrite;
+        let csv = fs::read(exporter.export_book(&book, &config).unwrap()).unwrap();
+        assert!(csv.starts_with(UTF8_BOM));
+        let rows = String::from_utf8(csv[3..].to_vec()).unwrap();
+        assert_eq!(rows.matches('\n').count(), rows.matches("\r\n").count());
     }
 
     #[test]
-    fn test_generate_filename_format() {
-        let book = Book {
-            content_id: "id1".to_string(),
-            title: "My Book".to_string(),
-            author: "John Doe".to_string(),
-            isbn: None,
-            publisher: None,
-            language: None,
-            date_last_read: None,
-            description: None,
-            file_path: None,
-            cover_path: None,
-            highlights: vec![],
-        };
+    fn test_sidecars_follow_parts_and_are_removed_with_them() {
+        let temp = TempDir::new().unwrap();
+        let mut book = large_book(LARGE_BOOK_HIGHLIGHTS + 1);
+        let mut config = create_test_config();
+        config.write_sidecars = true;
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
 
-        let filename = generate_filename(&book);
-        assert_eq!(filename, "My Book - John Doe.md");
+        let overview = exporter.export_book(&book, &config).unwrap();
+        assert!(read_sidecar(&overview).highlights.is_empty());
+        let last_part = parts::part_path(&overview, 2);
+        let entries = read_sidecar(&last_part).highlights;
+        assert_eq!(entries.len(), 1);
+        let part = fs::read_to_string(&last_part).unwrap();
+        let range = entries[0].byte_range.start as usize..entries[0].byte_range.end as usize;
+        assert!(part[range].starts_with("> Entry 2000 of the reference"));
+
+        book.highlights.truncate(10);
+        exporter.export_book(&book, &config).unwrap();
+        for number in [1, 2] {
+            let part = parts::part_path(&overview, number);
+            assert!(!part.exists() && !sidecar::sidecar_path(&part).exists());
+        }
+        assert_eq!(read_sidecar(&overview).highlights.len(), 10);
     }
 }
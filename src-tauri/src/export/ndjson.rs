@@ -0,0 +1,215 @@
+//! Streamed NDJSON export
+//!
+//! One JSON object per line and highlight, with the book fields repeated on
+//! every line. Lines are serialized straight into the writer, so the output
+//! is never held in memory as a whole.
+
+use crate::models::{color_label, Book, Highlight};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// Name of the NDJSON file written to the export root
+pub const NDJSON_FILENAME: &str = "highlights.ndjson";
+
+/// One line of the NDJSON export
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NdjsonLine<'a> {
+    book_content_id: &'a str,
+    book_title: &'a str,
+    author: &'a str,
+    authors: &'a [String],
+    isbn: Option<&'a str>,
+    publisher: Option<&'a str>,
+    language: Option<&'a str>,
+    series: Option<&'a str>,
+    series_index: Option<f32>,
+    highlight_id: &'a str,
+    stable_id: &'a str,
+    text: &'a str,
+    annotation: Option<&'a str>,
+    chapter_title: Option<&'a str>,
+    chapter_progress: Option<f64>,
+    page: Option<u32>,
+    date_created: &'a str,
+    color: Option<&'a str>,
+    /// With `group_by_color`, the label of the color
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color_label: Option<String>,
+}
+
+impl<'a> NdjsonLine<'a> {
+    fn new(
+        book: &'a Book,
+        highlight: &'a Highlight,
+        color_labels: Option<&BTreeMap<String, String>>,
+    ) -> Self {
+        Self {
+            book_content_id: &book.content_id,
+            book_title: &book.title,
+            author: &book.author,
+            authors: &book.authors,
+            isbn: book.isbn.as_deref(),
+            publisher: book.publisher.as_deref(),
+            language: book.language.as_deref(),
+            series: book.series.as_deref(),
+            series_index: book.series_index,
+            highlight_id: &highlight.id,
+            stable_id: &highlight.stable_id,
+            text: &highlight.text,
+            annotation: highlight.annotation.as_deref(),
+            chapter_title: highlight.chapter_title.as_deref(),
+            chapter_progress: highlight.chapter_progress,
+            page: highlight.page,
+            date_created: &highlight.date_created,
+            color: highlight.color.as_deref(),
+            color_label: color_labels
+                .zip(highlight.color.as_deref())
+                .map(|(labels, color)| color_label(labels, color)),
+        }
+    }
+}
+
+/// Lines and bytes written by `write_ndjson`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NdjsonStats {
+    pub lines: usize,
+    pub bytes: u64,
+}
+
+/// Write one line per highlight of `books` into `writer`
+///
+/// With `color_labels`, colored highlights carry their label. The writer is
+/// not flushed; wrap files in a `BufWriter`.
+pub fn write_ndjson<'a, W: Write>(
+    books: impl IntoIterator<Item = &'a Book>,
+    color_labels: Option<&BTreeMap<String, String>>,
+    writer: W,
+) -> io::Result<NdjsonStats> {
+    let mut counter = CountingWriter {
+        inner: writer,
+        bytes: 0,
+    };
+    let mut lines = 0;
+
+    for book in books {
+        for highlight in &book.highlights {
+            // serde_json escapes control characters, so text newlines
+            // never break a line
+            serde_json::to_writer(
+                &mut counter,
+                &NdjsonLine::new(book, highlight, color_labels),
+            )?;
+            counter.write_all(b"\n")?;
+            lines += 1;
+        }
+    }
+
+    Ok(NdjsonStats {
+        lines,
+        bytes: counter.bytes,
+    })
+}
+
+/// Counts the bytes passed through to the inner writer
+struct CountingWriter<W> {
+    inner: W,
+    bytes: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sink that keeps only line totals and the largest single write
+    #[derive(Default)]
+    struct RecordingSink {
+        bytes: u64,
+        largest_write: usize,
+        pending: Vec<u8>,
+        lines: Vec<serde_json::Value>,
+    }
+
+    impl Write for RecordingSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.bytes += buf.len() as u64;
+            self.largest_write = self.largest_write.max(buf.len());
+            for &byte in buf {
+                if byte == b'\n' {
+                    self.lines
+                        .push(serde_json::from_slice(&self.pending).unwrap());
+                    self.pending.clear();
+                } else {
+                    self.pending.push(byte);
+                }
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn synthetic_library(books: usize, per_book: usize) -> Vec<Book> {
+        (0..books)
+            .map(|b| {
+                let mut book = Book::new(
+                    format!("book-{}", b),
+                    format!("Livro \"{}\"", b),
+                    "Ana Autora & Rui Outro".to_string(),
+                );
+                for h in 0..per_book {
+                    let mut highlight = Highlight::new(
+                        format!("{}-{}", b, h),
+                        format!("Linha um\nlinha dois\t{} \u{2014} {}", b, h),
+                        "2025-01-24T10:00:00.000".to_string(),
+                    );
+                    highlight.annotation = (h % 3 == 0).then(|| "nota\r\n".to_string());
+                    book.highlights.push(highlight);
+                }
+                book
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_streams_large_library_line_by_line() {
+        let books = synthetic_library(100, 100);
+        let mut sink = RecordingSink::default();
+
+        let stats = write_ndjson(&books, None, &mut sink).unwrap();
+
+        assert_eq!(stats.lines, 10_000);
+        assert_eq!(sink.lines.len(), 10_000);
+        assert!(sink.pending.is_empty());
+        assert_eq!(stats.bytes, sink.bytes);
+        // No write comes close to one line, let alone the whole output
+        assert!(sink.largest_write < 200, "{}", sink.largest_write);
+
+        let first = &sink.lines[0];
+        assert_eq!(first["bookTitle"], "Livro \"0\"");
+        assert_eq!(first["text"], "Linha um\nlinha dois\t0 \u{2014} 0");
+        assert_eq!(first["annotation"], "nota\r\n");
+        assert_eq!(
+            first["authors"],
+            serde_json::to_value(&books[0].authors).unwrap()
+        );
+        let last = &sink.lines[9_998];
+        assert_eq!(last["highlightId"], "99-98");
+        assert_eq!(last["annotation"], serde_json::Value::Null);
+    }
+}
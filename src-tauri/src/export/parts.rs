@@ -0,0 +1,102 @@
+//! Large books split across files
+//!
+//! A book with more than `LARGE_BOOK_HIGHLIGHTS` highlights is exported as an
+//! overview file at its usual path (metadata, stats and links) plus part
+//! files next to it, `LARGE_BOOK_HIGHLIGHTS` highlights each, so no single
+//! file gets too big for an editor to open comfortably.
+
+use crate::models::{Book, ExportConfig, ExportFormat, ExportWriteMode};
+use std::path::{Path, PathBuf};
+
+/// Highlights per file before a book is split into parts
+pub const LARGE_BOOK_HIGHLIGHTS: usize = 2000;
+
+/// Whether `book` is exported as an overview and part files
+///
+/// Only plain markdown overwrites are split: append mode keeps adding to
+/// the one file it already manages, and a custom template lays out the
+/// whole book itself.
+pub fn is_split(book: &Book, config: &ExportConfig) -> bool {
+    config.format == ExportFormat::Markdown
+        && config.write_mode == ExportWriteMode::Overwrite
+        && book.highlights.len() > LARGE_BOOK_HIGHLIGHTS
+        && !super::template::is_custom(config)
+}
+
+/// Number of part files for `highlights` highlights
+pub fn part_count(highlights: usize) -> usize {
+    highlights.div_ceil(LARGE_BOOK_HIGHLIGHTS)
+}
+
+/// Path of part `number` (from 1) of the book written to `overview`
+pub fn part_path(overview: &Path, number: usize) -> PathBuf {
+    let stem = overview
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    overview.with_file_name(format!("{} (Parte {}).md", stem, number))
+}
+
+/// Part files of `book` when it is split, else none
+pub fn part_paths(book: &Book, config: &ExportConfig, overview: &Path) -> Vec<PathBuf> {
+    if !is_split(book, config) {
+        return Vec::new();
+    }
+    (1..=part_count(book.highlights.len()))
+        .map(|number| part_path(overview, number))
+        .collect()
+}
+
+/// Part files from an earlier, larger export, numbered `first` and up
+pub fn stale_part_paths(overview: &Path, first: usize) -> Vec<PathBuf> {
+    (first..)
+        .map(|number| part_path(overview, number))
+        .take_while(|path| path.is_file())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Highlight;
+    use crate::settings::AppSettings;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_only_large_markdown_overwrites_split() {
+        let mut book = Book::new("id".to_string(), "Livro".to_string(), "Autor".to_string());
+        book.highlights = (0..=LARGE_BOOK_HIGHLIGHTS)
+            .map(|i| Highlight::new(i.to_string(), "x".to_string(), String::new()))
+            .collect();
+        let mut config = AppSettings::default().export_config;
+        let overview = Path::new("/notas/Livro - Autor.md");
+
+        assert_eq!(
+            part_paths(&book, &config, overview),
+            vec![
+                PathBuf::from("/notas/Livro - Autor (Parte 1).md"),
+                PathBuf::from("/notas/Livro - Autor (Parte 2).md"),
+            ]
+        );
+        config.write_mode = ExportWriteMode::Append;
+        assert!(part_paths(&book, &config, overview).is_empty());
+        config.write_mode = ExportWriteMode::Overwrite;
+        book.highlights.pop();
+        assert!(!is_split(&book, &config));
+    }
+
+    #[test]
+    fn test_stale_parts_stop_at_first_gap() {
+        let temp = TempDir::new().unwrap();
+        let overview = temp.path().join("Livro.md");
+        for number in [2, 3, 5] {
+            fs::write(part_path(&overview, number), "").unwrap();
+        }
+        assert_eq!(
+            stale_part_paths(&overview, 2),
+            vec![part_path(&overview, 2), part_path(&overview, 3)]
+        );
+        assert!(stale_part_paths(&overview, 4).is_empty());
+    }
+}
@@ -0,0 +1,538 @@
+//! Cached renders of books
+//!
+//! Rendering a large book takes long enough to notice when switching between
+//! books, so previews, diffs and exports share a small LRU cache keyed by the
+//! book's content ID, the library revision and a hash of the export config:
+//! a (book, config) pair is rendered once until either changes (the offset
+//! dates are shown at is part of the config). Every library edit (merged
+//! highlights, notes, favorites) bumps the revision, so renders of older
+//! revisions are dropped as soon as a newer one is cached. Books outside the
+//! library (no revision) are never cached. `prewarm` renders books into it
+//! on a background thread ahead of the user clicking them. Saving settings
+//! clears the cache.
+
+use super::exclusions::BookChapterExclusions;
+use super::MarkdownExporter;
+use crate::models::{Book, ExportConfig};
+use crate::utils::metrics::SessionMetrics;
+use chrono::FixedOffset;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// Renders kept before the least recently used one is evicted
+pub const PREVIEW_CACHE_CAPACITY: usize = 20;
+
+/// Total size of the kept renders before the least recently used is evicted
+pub const PREVIEW_CACHE_MAX_BYTES: usize = 32 * 1024 * 1024;
+
+struct CachedPreview {
+    content_id: String,
+    config_hash: String,
+    /// Library revision the preview was rendered at
+    revision: u64,
+    markdown: String,
+}
+
+struct PreviewCacheInner {
+    capacity: usize,
+    max_bytes: usize,
+    /// Least recently used first
+    entries: VecDeque<CachedPreview>,
+    /// Size of every entry's markdown
+    bytes: usize,
+    /// Books rendered into the cache so far
+    renders: u64,
+}
+
+/// Managed cache of rendered books (cheap to clone, shared)
+#[derive(Clone)]
+pub struct PreviewCache {
+    inner: Arc<Mutex<PreviewCacheInner>>,
+}
+
+impl Default for PreviewCache {
+    fn default() -> Self {
+        Self::new(PREVIEW_CACHE_CAPACITY)
+    }
+}
+
+impl PreviewCache {
+    pub fn new(capacity: usize) -> Self {
+        Self::with_limits(capacity, PREVIEW_CACHE_MAX_BYTES)
+    }
+
+    /// Cache of at most `capacity` renders totalling `max_bytes` (the most
+    /// recent render is always kept)
+    pub fn with_limits(capacity: usize, max_bytes: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(PreviewCacheInner {
+                capacity: capacity.max(1),
+                max_bytes,
+                entries: VecDeque::new(),
+                bytes: 0,
+                renders: 0,
+            })),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PreviewCacheInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Drop every cached render
+    pub fn invalidate(&self) {
+        let mut inner = self.lock();
+        inner.entries.clear();
+        inner.bytes = 0;
+    }
+
+    /// How many books were rendered into the cache (misses and prewarming)
+    pub fn render_count(&self) -> u64 {
+        self.lock().renders
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The preview of `book` at library `revision` with dates at `offset`,
+    /// rendered only on a cache miss
+    pub fn preview(
+        &self,
+        book: &Book,
+        revision: Option<u64>,
+        config: &ExportConfig,
+        exclusions: &BookChapterExclusions,
+        offset: FixedOffset,
+        session: &SessionMetrics,
+    ) -> String {
+        let (markdown, hit) =
+            self.get_or_render(book, revision, config, exclusions, offset, || {
+                preview_exporter(config, exclusions, offset).render_book(book, config)
+            });
+        session.record_preview(hit);
+        markdown
+    }
+
+    /// The cached render of `book`, or what `render` returns (then cached
+    /// when the book has a library `revision`); also tells whether it was a
+    /// hit
+    pub fn get_or_render(
+        &self,
+        book: &Book,
+        revision: Option<u64>,
+        config: &ExportConfig,
+        exclusions: &BookChapterExclusions,
+        offset: FixedOffset,
+        render: impl FnOnce() -> String,
+    ) -> (String, bool) {
+        let Some(revision) = revision else {
+            return (render(), false);
+        };
+        let config_hash = config_hash(&book.content_id, config, exclusions, offset);
+        if let Some(markdown) = self.get(&book.content_id, &config_hash, revision) {
+            return (markdown, true);
+        }
+
+        let markdown = render();
+        self.insert(CachedPreview {
+            content_id: book.content_id.clone(),
+            config_hash,
+            revision,
+            markdown: markdown.clone(),
+        });
+        (markdown, false)
+    }
+
+    /// The cached render of the book `content_id`, without rendering or
+    /// caching anything on a miss
+    pub fn cached(
+        &self,
+        content_id: &str,
+        revision: u64,
+        config: &ExportConfig,
+        exclusions: &BookChapterExclusions,
+        offset: FixedOffset,
+    ) -> Option<String> {
+        let config_hash = config_hash(content_id, config, exclusions, offset);
+        self.get(content_id, &config_hash, revision)
+    }
+
+    /// Render `books`, as of library `revision`, on a background thread
+    ///
+    /// Books already cached are skipped; the thread returns how many
+    /// previews it rendered.
+    pub fn prewarm(
+        &self,
+        books: Vec<Book>,
+        revision: u64,
+        config: ExportConfig,
+        exclusions: BookChapterExclusions,
+        offset: FixedOffset,
+    ) -> JoinHandle<usize> {
+        let cache = self.clone();
+        std::thread::spawn(move || {
+            let exporter = preview_exporter(&config, &exclusions, offset);
+            let mut rendered = 0;
+            for book in books {
+                let config_hash = config_hash(&book.content_id, &config, &exclusions, offset);
+                if cache.contains(&book.content_id, &config_hash, revision) {
+                    continue;
+                }
+                let markdown = exporter.render_book(&book, &config);
+                cache.insert(CachedPreview {
+                    content_id: book.content_id,
+                    config_hash,
+                    revision,
+                    markdown,
+                });
+                rendered += 1;
+            }
+            log::debug!("[Preview] Prewarmed {} preview(s)", rendered);
+            rendered
+        })
+    }
+
+    fn position(
+        inner: &PreviewCacheInner,
+        content_id: &str,
+        config_hash: &str,
+        revision: u64,
+    ) -> Option<usize> {
+        inner.entries.iter().position(|entry| {
+            entry.content_id == content_id
+                && entry.config_hash == config_hash
+                && entry.revision == revision
+        })
+    }
+
+    fn contains(&self, content_id: &str, config_hash: &str, revision: u64) -> bool {
+        Self::position(&self.lock(), content_id, config_hash, revision).is_some()
+    }
+
+    /// A cached preview, marked as most recently used
+    fn get(&self, content_id: &str, config_hash: &str, revision: u64) -> Option<String> {
+        let mut inner = self.lock();
+        let index = Self::position(&inner, content_id, config_hash, revision)?;
+        let entry = inner.entries.remove(index)?;
+        let markdown = entry.markdown.clone();
+        inner.entries.push_back(entry);
+        Some(markdown)
+    }
+
+    fn insert(&self, preview: CachedPreview) {
+        let mut inner = self.lock();
+        inner.renders += 1;
+        // A book has one render per config, and renders of an older
+        // revision can't be hit again
+        let mut removed = 0;
+        inner.entries.retain(|entry| {
+            let outdated = entry.revision < preview.revision
+                || (entry.content_id == preview.content_id
+                    && entry.config_hash == preview.config_hash);
+            if outdated {
+                removed += entry.markdown.len();
+            }
+            !outdated
+        });
+        inner.bytes = inner.bytes - removed + preview.markdown.len();
+        inner.entries.push_back(preview);
+        while inner.entries.len() > inner.capacity
+            || (inner.entries.len() > 1 && inner.bytes > inner.max_bytes)
+        {
+            if let Some(evicted) = inner.entries.pop_front() {
+                inner.bytes -= evicted.markdown.len();
+            }
+        }
+    }
+}
+
+/// Exporter that renders in memory; previews never touch exported files
+fn preview_exporter(
+    config: &ExportConfig,
+    exclusions: &BookChapterExclusions,
+    offset: FixedOffset,
+) -> MarkdownExporter {
+    MarkdownExporter::new(PathBuf::from(&config.export_path))
+        .with_chapter_exclusions(exclusions.clone())
+        .with_assumed_offset(offset)
+}
+
+/// Hash of everything besides the book that changes its preview: the whole
+/// config, the book's chapter exclusions and the offset of its dates
+fn config_hash(
+    content_id: &str,
+    config: &ExportConfig,
+    exclusions: &BookChapterExclusions,
+    offset: FixedOffset,
+) -> String {
+    hash_json(&(config, exclusions.get(content_id), offset.local_minus_utc()))
+}
+
+pub(super) fn hash_json<T: serde::Serialize>(value: &T) -> String {
+    let json = serde_json::to_vec(value).unwrap_or_default();
+    format!("{:x}", Sha256::digest(&json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Highlight;
+    use crate::settings::AppSettings;
+    use crate::utils::date::utc_offset;
+
+    /// Library revision of the books previewed
+    const REV: Option<u64> = Some(1);
+
+    fn book(content_id: &str) -> Book {
+        let mut book = Book::new(
+            content_id.to_string(),
+            format!("Livro {}", content_id),
+            "Autor".to_string(),
+        );
+        book.highlights.push(Highlight::new(
+            format!("{}-hl", content_id),
+            "Um destaque".to_string(),
+            "2025-01-24T10:00:00".to_string(),
+        ));
+        book
+    }
+
+    fn config() -> ExportConfig {
+        AppSettings::default().export_config
+    }
+
+    #[test]
+    fn test_hits_return_identical_previews() {
+        let cache = PreviewCache::default();
+        let session = SessionMetrics::default();
+        let exclusions = BookChapterExclusions::new();
+        let book = book("a");
+
+        let first = cache.preview(&book, REV, &config(), &exclusions, utc_offset(), &session);
+        let second = cache.preview(&book, REV, &config(), &exclusions, utc_offset(), &session);
+        assert_eq!(first, second);
+        assert_eq!(
+            first,
+            MarkdownExporter::new(PathBuf::new()).render_book(&book, &config())
+        );
+        let report = session.report();
+        assert_eq!(
+            (report.preview_cache_hits, report.preview_cache_misses),
+            (1, 1)
+        );
+
+        // A book edited in the library (a newer revision) is rendered again,
+        // and the older render dropped
+        let mut edited = book.clone();
+        edited.highlights[0].text = "Outro destaque".to_string();
+        assert!(cache
+            .preview(
+                &edited,
+                Some(2),
+                &config(),
+                &exclusions,
+                utc_offset(),
+                &session
+            )
+            .contains("Outro destaque"));
+        assert_eq!(session.report().preview_cache_misses, 2);
+        assert_eq!(cache.len(), 1);
+
+        // Books outside the library are never cached
+        cache.preview(&book, None, &config(), &exclusions, utc_offset(), &session);
+        cache.preview(&book, None, &config(), &exclusions, utc_offset(), &session);
+        assert_eq!(session.report().preview_cache_misses, 4);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_config_changes_and_invalidation_miss() {
+        let cache = PreviewCache::default();
+        let session = SessionMetrics::default();
+        let mut exclusions = BookChapterExclusions::new();
+        let book = book("a");
+        cache.preview(&book, REV, &config(), &exclusions, utc_offset(), &session);
+
+        let mut changed = config();
+        changed.show_location = !changed.show_location;
+        cache.preview(&book, REV, &changed, &exclusions, utc_offset(), &session);
+        exclusions.insert("a".to_string(), vec!["Prefácio".to_string()]);
+        cache.preview(&book, REV, &changed, &exclusions, utc_offset(), &session);
+        assert_eq!(session.report().preview_cache_misses, 3);
+
+        cache.invalidate();
+        assert!(cache.is_empty());
+        cache.preview(&book, REV, &changed, &exclusions, utc_offset(), &session);
+        let report = session.report();
+        assert_eq!(
+            (report.preview_cache_hits, report.preview_cache_misses),
+            (0, 4)
+        );
+    }
+
+    #[test]
+    fn test_least_recently_used_is_evicted() {
+        let cache = PreviewCache::new(2);
+        let session = SessionMetrics::default();
+        let exclusions = BookChapterExclusions::new();
+        let (a, b, c) = (book("a"), book("b"), book("c"));
+
+        cache.preview(&a, REV, &config(), &exclusions, utc_offset(), &session);
+        cache.preview(&b, REV, &config(), &exclusions, utc_offset(), &session);
+        // Touching `a` makes `b` the oldest
+        cache.preview(&a, REV, &config(), &exclusions, utc_offset(), &session);
+        cache.preview(&c, REV, &config(), &exclusions, utc_offset(), &session);
+        assert_eq!(cache.len(), 2);
+
+        cache.preview(&a, REV, &config(), &exclusions, utc_offset(), &session);
+        cache.preview(&b, REV, &config(), &exclusions, utc_offset(), &session);
+        let report = session.report();
+        assert_eq!(
+            (report.preview_cache_hits, report.preview_cache_misses),
+            (2, 4)
+        );
+    }
+
+    #[test]
+    fn test_preview_diff_and_export_render_once() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let mut config = config();
+        config.export_path = temp.path().to_string_lossy().into_owned();
+        let cache = PreviewCache::default();
+        let session = SessionMetrics::default();
+        let exclusions = BookChapterExclusions::new();
+        let mut big = book("big");
+        for i in 0..1500 {
+            big.highlights.push(Highlight::new(
+                format!("big-{}", i),
+                format!("Destaque número {} de um livro grande", i),
+                "2025-01-24T10:00:00".to_string(),
+            ));
+        }
+        let other = book("other");
+
+        let preview = cache.preview(&big, REV, &config, &exclusions, utc_offset(), &session);
+        cache.preview(&other, REV, &config, &exclusions, utc_offset(), &session);
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf())
+            .with_chapter_exclusions(exclusions.clone())
+            .with_render_cache(cache.clone(), REV);
+        let diff = exporter.export_diff(&big, &config).unwrap();
+        session.record_render_cache(&exporter.take_metrics());
+        assert!(diff.is_new_file);
+        let report = exporter.export_books_with_events(
+            std::slice::from_ref(&big),
+            &config,
+            &crate::export::NoopSink,
+        );
+        assert!(report.failures.is_empty(), "{:?}", report.failures);
+        session.record_render_cache(&report.metrics);
+
+        assert_eq!(cache.render_count(), 2);
+        assert_eq!(
+            std::fs::read_to_string(&report.exported_files[0]).unwrap(),
+            preview
+        );
+        let metrics = session.report();
+        assert_eq!(
+            (metrics.preview_cache_hits, metrics.preview_cache_misses),
+            (2, 2)
+        );
+
+        // A library edit renders previews again at the new revision
+        big.highlights[0].text = "Destaque editado".to_string();
+        let edited = cache.preview(&big, Some(2), &config, &exclusions, utc_offset(), &session);
+        assert!(edited.contains("Destaque editado"));
+        assert_eq!(cache.render_count(), 3);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_exports_only_read_the_cache() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let mut config = config();
+        config.export_path = temp.path().to_string_lossy().into_owned();
+        let cache = PreviewCache::default();
+        let books = vec![book("a"), book("b")];
+
+        let exporter =
+            MarkdownExporter::new(temp.path().to_path_buf()).with_render_cache(cache.clone(), REV);
+        let report = exporter.export_books_with_events(&books, &config, &crate::export::NoopSink);
+        assert!(report.failures.is_empty(), "{:?}", report.failures);
+        assert!(cache.is_empty());
+        assert_eq!(report.metrics.counters.get("render_cache_misses"), Some(&2));
+    }
+
+    #[test]
+    fn test_size_cap_evicts_oldest() {
+        let session = SessionMetrics::default();
+        let exclusions = BookChapterExclusions::new();
+        let size = PreviewCache::default()
+            .preview(
+                &book("a"),
+                REV,
+                &config(),
+                &exclusions,
+                utc_offset(),
+                &session,
+            )
+            .len();
+        let cache = PreviewCache::with_limits(10, size * 2);
+        for id in ["a", "b", "c"] {
+            cache.preview(
+                &book(id),
+                REV,
+                &config(),
+                &exclusions,
+                utc_offset(),
+                &session,
+            );
+        }
+        assert_eq!(cache.len(), 2);
+        cache.preview(
+            &book("a"),
+            REV,
+            &config(),
+            &exclusions,
+            utc_offset(),
+            &session,
+        );
+        assert_eq!(cache.render_count(), 4);
+    }
+
+    #[test]
+    fn test_prewarmed_book_hits() {
+        let cache = PreviewCache::default();
+        let session = SessionMetrics::default();
+        let exclusions = BookChapterExclusions::new();
+        let book = book("a");
+
+        let handle = cache.prewarm(
+            vec![book.clone()],
+            1,
+            config(),
+            exclusions.clone(),
+            utc_offset(),
+        );
+        assert_eq!(handle.join().unwrap(), 1);
+
+        let preview = cache.preview(&book, REV, &config(), &exclusions, utc_offset(), &session);
+        assert!(preview.contains("Um destaque"));
+        let report = session.report();
+        assert_eq!(
+            (report.preview_cache_hits, report.preview_cache_misses),
+            (1, 0)
+        );
+
+        // Already cached: nothing left to render
+        let handle = cache.prewarm(vec![book], 1, config(), exclusions, utc_offset());
+        assert_eq!(handle.join().unwrap(), 0);
+    }
+}
@@ -0,0 +1,281 @@
+//! Private notes kept out of exported files
+//!
+//! A note (or highlight) carrying one of `ExportConfig::redaction_markers`
+//! is handled per `ExportConfig::redaction` in the same step that applies
+//! chapter exclusions, so every format and the previews see the same books.
+//! The library keeps the content as it is: redaction is only an export
+//! concern.
+//!
+//! Markers are matched on a canonical form of the text (normalized with
+//! every `TextNormalization` step, ASCII punctuation included, and
+//! lowercased), so "#Private" or "don’t share" still match when the device
+//! or a smart-quote setting changed a character.
+
+use crate::models::{Book, ExportConfig, Highlight, RedactionPolicy};
+use crate::utils::text::TextNormalization;
+use regex::{Regex, RegexBuilder};
+
+/// What `RedactionPolicy::Mask` leaves of a private note
+pub const MASKED_NOTE: &str = "[private note]";
+
+/// The redaction markers of a config, ready to match
+pub struct RedactionRules {
+    policy: RedactionPolicy,
+    normalization: TextNormalization,
+    plain: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl RedactionRules {
+    /// Rules of `config`; invalid regexes are skipped (see
+    /// `validate_redaction_markers`)
+    pub fn new(config: &ExportConfig) -> Self {
+        let mut rules = Self {
+            policy: config.redaction,
+            normalization: TextNormalization {
+                normalize_punctuation: true,
+                ..TextNormalization::default()
+            },
+            plain: Vec::new(),
+            patterns: Vec::new(),
+        };
+        for marker in &config.redaction_markers {
+            match marker_regex(marker) {
+                Some(Ok(regex)) => rules.patterns.push(regex),
+                Some(Err(e)) => {
+                    log::warn!(
+                        "[EXPORTER] Marcador de redação inválido {:?}: {}",
+                        marker,
+                        e
+                    )
+                }
+                None => {
+                    let marker = rules.canonical(marker);
+                    if !marker.is_empty() {
+                        rules.plain.push(marker);
+                    }
+                }
+            }
+        }
+        rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plain.is_empty() && self.patterns.is_empty()
+    }
+
+    fn canonical(&self, text: &str) -> String {
+        self.normalization.normalize(text).to_lowercase()
+    }
+
+    /// Whether `text` carries a marker
+    pub fn is_private(&self, text: &str) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let text = self.canonical(text);
+        self.plain
+            .iter()
+            .any(|marker| text.contains(marker.as_str()))
+            || self.patterns.iter().any(|regex| regex.is_match(&text))
+    }
+
+    fn highlight_is_private(&self, highlight: &Highlight) -> bool {
+        self.is_private(&highlight.text)
+    }
+
+    fn note_is_private(&self, highlight: &Highlight) -> bool {
+        highlight
+            .annotation
+            .as_deref()
+            .is_some_and(|note| self.is_private(note))
+    }
+
+    /// Whether `book` has anything to redact
+    pub fn touches(&self, book: &Book) -> bool {
+        !self.is_empty()
+            && book
+                .highlights
+                .iter()
+                .any(|h| self.highlight_is_private(h) || self.note_is_private(h))
+    }
+
+    /// Redact `book` in place; returns how many highlights were redacted
+    pub fn apply(&self, book: &mut Book) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+        let before = book.highlights.len();
+        let mut masked = 0;
+        book.highlights.retain_mut(|highlight| {
+            if self.highlight_is_private(highlight) {
+                return false;
+            }
+            if !self.note_is_private(highlight) {
+                return true;
+            }
+            match self.policy {
+                RedactionPolicy::ExcludeHighlight => return false,
+                RedactionPolicy::Exclude => highlight.annotation = None,
+                RedactionPolicy::Mask => highlight.annotation = Some(MASKED_NOTE.to_string()),
+            }
+            masked += 1;
+            true
+        });
+        before - book.highlights.len() + masked
+    }
+}
+
+/// The regex of a `/…/` marker, `None` for plain markers
+fn marker_regex(marker: &str) -> Option<Result<Regex, regex::Error>> {
+    let pattern = marker
+        .trim()
+        .strip_prefix('/')?
+        .strip_suffix('/')
+        .filter(|pattern| !pattern.is_empty())?;
+    Some(RegexBuilder::new(pattern).case_insensitive(true).build())
+}
+
+/// Check that every `/…/` marker is a valid regex
+pub fn validate_redaction_markers(markers: &[String]) -> Result<(), String> {
+    for marker in markers {
+        if let Some(Err(e)) = marker_regex(marker) {
+            return Err(format!("Invalid redaction marker {}: {}", marker, e));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{MarkdownExporter, NoopSink};
+    use crate::models::{ExportFormat, HighlightStyle};
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn book() -> Book {
+        let mut book = Book::new(
+            "vol1".to_string(),
+            "Diário".to_string(),
+            "Autora".to_string(),
+        );
+        for (id, text, note) in [
+            ("hl1", "Public quote", Some("Shared thought")),
+            ("hl2", "Kept quote", Some("Mine only, “#Private” for now")),
+            ("hl3", "Secret quote #private", None),
+            ("hl4", "Plain quote", None),
+        ] {
+            let mut highlight =
+                Highlight::new(id.to_string(), text.to_string(), "2025-01-24".to_string());
+            highlight.annotation = note.map(str::to_string);
+            book.highlights.push(highlight);
+        }
+        book
+    }
+
+    fn config(policy: RedactionPolicy) -> ExportConfig {
+        let mut config = crate::settings::AppSettings::default().export_config;
+        config.redaction = policy;
+        config
+    }
+
+    fn redacted(config: &ExportConfig) -> (Vec<(String, Option<String>)>, usize) {
+        let mut book = book();
+        let count = RedactionRules::new(config).apply(&mut book);
+        let highlights = book
+            .highlights
+            .into_iter()
+            .map(|h| (h.id, h.annotation))
+            .collect();
+        (highlights, count)
+    }
+
+    fn entry(id: &str, note: Option<&str>) -> (String, Option<String>) {
+        (id.to_string(), note.map(str::to_string))
+    }
+
+    #[test]
+    fn test_policies() {
+        assert_eq!(
+            redacted(&config(RedactionPolicy::Exclude)),
+            (
+                vec![
+                    entry("hl1", Some("Shared thought")),
+                    entry("hl2", None),
+                    entry("hl4", None)
+                ],
+                2
+            )
+        );
+        assert_eq!(
+            redacted(&config(RedactionPolicy::ExcludeHighlight)),
+            (
+                vec![entry("hl1", Some("Shared thought")), entry("hl4", None)],
+                2
+            )
+        );
+        assert_eq!(
+            redacted(&config(RedactionPolicy::Mask)),
+            (
+                vec![
+                    entry("hl1", Some("Shared thought")),
+                    entry("hl2", Some(MASKED_NOTE)),
+                    entry("hl4", None)
+                ],
+                2
+            )
+        );
+
+        let mut none = config(RedactionPolicy::Mask);
+        none.redaction_markers.clear();
+        assert_eq!(redacted(&none).1, 0);
+    }
+
+    #[test]
+    fn test_markers_match_normalized_text() {
+        let mut config = config(RedactionPolicy::Exclude);
+        config.redaction_markers =
+            vec!["don't share".to_string(), "/\\bdiary:\\s*\\d+/".to_string()];
+        let rules = RedactionRules::new(&config);
+        // Curly apostrophe, no-break space and case all differ from the marker
+        assert!(rules.is_private("Please DON’T\u{a0}share this"));
+        assert!(rules.is_private("see Diary: 12 for the rest"));
+        assert!(!rules.is_private("my diary is public"));
+        assert!(!rules.is_private("#private"));
+
+        assert!(validate_redaction_markers(&config.redaction_markers).is_ok());
+        assert!(validate_redaction_markers(&["/(unclosed/".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_exports_and_previews_share_the_policy() {
+        let temp = TempDir::new().unwrap();
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let mut config = config(RedactionPolicy::Mask);
+        config.highlight_style = HighlightStyle::Callout;
+
+        let preview = exporter.render_book(&book(), &config);
+        assert!(preview.contains(MASKED_NOTE));
+        assert!(preview.contains("Shared thought"));
+        assert!(!preview.contains("Mine only"));
+        assert!(!preview.contains("Secret quote"));
+
+        for format in [ExportFormat::Ndjson, ExportFormat::Csv] {
+            config.format = format;
+            let report = exporter.export_books_with_events(&[book()], &config, &NoopSink);
+            assert!(report.failures.is_empty(), "{:?}", report.failures);
+            assert_eq!(report.redacted, 2);
+            let written = fs::read_to_string(&report.exported_files[0]).unwrap();
+            assert!(written.contains(MASKED_NOTE), "{}", written);
+            assert!(written.contains("Shared thought"));
+            assert!(!written.contains("Mine only"));
+            assert!(!written.contains("Secret quote"));
+        }
+
+        // Only exports redact: the library still finds private content
+        let mut library = crate::library::LibraryStore::open_in_memory().unwrap();
+        library.merge_books(&[book()]).unwrap();
+        assert_eq!(library.search("Secret", 10).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,110 @@
+//! Machine-readable sidecars of exported markdown
+//!
+//! With `write_sidecars` enabled, every markdown file a full render writes
+//! (`Title - Author.md`, and each part of a split book) gets a
+//! `Title - Author.khi.json` next to it, mapping each highlight to the byte
+//! range of its block in the file. Tools built on the export can then find a
+//! highlight's markdown without parsing anchors out of the text.
+//!
+//! Sidecars are rewritten right after their markdown file and removed with
+//! it. Append mode never re-renders the whole file, so it keeps none; nor do
+//! files rendered from a custom template, whose blocks aren't tracked.
+
+use super::preview::hash_json;
+use super::sink::BlockSpan;
+use crate::models::{Book, ExportConfig, ExportFormat, ExportWriteMode, Highlight};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Extension replacing `.md` in sidecar file names
+pub const SIDECAR_EXTENSION: &str = "khi.json";
+
+/// Contents of a `.khi.json` sidecar
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Sidecar {
+    pub content_id: String,
+    pub slug: String,
+    /// RFC 3339 time of the export
+    pub exported_at: String,
+    /// SHA-256 of the export config the file was rendered with
+    pub config_hash: String,
+    /// Highlights in the order they appear in the file
+    pub highlights: Vec<SidecarEntry>,
+}
+
+/// Where one highlight was rendered
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SidecarEntry {
+    pub highlight_id: String,
+    pub stable_id: String,
+    /// Anchor identifying the highlight across exports (`<!-- khi:… -->`)
+    pub anchor: String,
+    pub byte_range: ByteRange,
+}
+
+/// Half-open byte range `[start, end)` in the markdown file
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Whether exports with `config` write sidecars
+pub fn is_enabled(config: &ExportConfig) -> bool {
+    config.write_sidecars
+        && config.format == ExportFormat::Markdown
+        && config.write_mode == ExportWriteMode::Overwrite
+        && !super::template::is_custom(config)
+}
+
+/// Sidecar path of the markdown file at `markdown`
+pub fn sidecar_path(markdown: &Path) -> PathBuf {
+    markdown.with_extension(SIDECAR_EXTENSION)
+}
+
+/// The sidecar of a file `book` was rendered into, from its recorded blocks
+pub fn build_sidecar(
+    book: &Book,
+    config: &ExportConfig,
+    blocks: &[BlockSpan],
+    exported_at: &str,
+) -> Sidecar {
+    let by_id: HashMap<&str, &Highlight> =
+        book.highlights.iter().map(|h| (h.id.as_str(), h)).collect();
+    let highlights = blocks
+        .iter()
+        .filter_map(|block| {
+            let highlight = by_id.get(block.highlight_id.as_str())?;
+            Some(SidecarEntry {
+                highlight_id: highlight.id.clone(),
+                stable_id: highlight.stable_id.clone(),
+                anchor: super::append::highlight_anchor(highlight),
+                byte_range: ByteRange {
+                    start: block.range.start,
+                    end: block.range.end,
+                },
+            })
+        })
+        .collect();
+    Sidecar {
+        content_id: book.content_id.clone(),
+        slug: book.slug.clone(),
+        exported_at: exported_at.to_string(),
+        config_hash: hash_json(config),
+        highlights,
+    }
+}
+
+/// Remove the sidecar of `markdown`, if there is one
+pub fn remove_sidecar(markdown: &Path) {
+    let path = sidecar_path(markdown);
+    match fs::remove_file(&path) {
+        Ok(()) => log::info!("[EXPORTER] Sidecar removido: {:?}", path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => log::warn!("[EXPORTER] Falha ao remover {:?}: {}", path, e),
+    }
+}
@@ -0,0 +1,182 @@
+//! Streaming markdown output
+//!
+//! Markdown is generated line by line into a `MarkdownSink` instead of being
+//! collected in a `Vec<String>` and joined, so writing a book with thousands
+//! of highlights never holds more than one highlight's text in memory on top
+//! of the output itself (nothing at all when streaming to a file). The sink
+//! can also record the byte span of each highlight's block, for sidecars.
+
+use std::fmt;
+use std::io;
+use std::ops::Range;
+
+/// Byte span of one highlight's block in the output
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockSpan {
+    pub highlight_id: String,
+    pub range: Range<u64>,
+}
+
+/// Lines written to `W`, separated by `\n` (like `Vec<String>::join("\n")`)
+pub struct MarkdownSink<W: fmt::Write> {
+    out: W,
+    started: bool,
+    last_empty: bool,
+    last_ends_with_newline: bool,
+    /// Bytes written so far
+    offset: u64,
+    /// Recorded highlight blocks, when tracking
+    blocks: Option<Vec<BlockSpan>>,
+}
+
+impl<W: fmt::Write> MarkdownSink<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            started: false,
+            last_empty: false,
+            last_ends_with_newline: false,
+            offset: 0,
+            blocks: None,
+        }
+    }
+
+    /// Record where each highlight block lands (see `highlight_line`)
+    pub fn tracking_blocks(mut self) -> Self {
+        self.blocks = Some(Vec::new());
+        self
+    }
+
+    /// Write one line (which may itself hold line breaks)
+    pub fn line(&mut self, line: &str) -> fmt::Result {
+        if self.started {
+            self.out.write_char('\n')?;
+            self.offset += 1;
+        }
+        self.out.write_str(line)?;
+        self.offset += line.len() as u64;
+        self.started = true;
+        self.last_empty = line.is_empty();
+        self.last_ends_with_newline = line.ends_with('\n');
+        Ok(())
+    }
+
+    pub fn blank(&mut self) -> fmt::Result {
+        self.line("")
+    }
+
+    /// Write the block of highlight `highlight_id` as one line, recording
+    /// its span when tracking
+    pub fn highlight_line(&mut self, highlight_id: &str, block: &str) -> fmt::Result {
+        self.line(block)?;
+        if let Some(blocks) = &mut self.blocks {
+            blocks.push(BlockSpan {
+                highlight_id: highlight_id.to_string(),
+                range: self.offset - block.len() as u64..self.offset,
+            });
+        }
+        Ok(())
+    }
+
+    /// Highlight blocks recorded so far, in output order
+    pub fn take_blocks(&mut self) -> Vec<BlockSpan> {
+        self.blocks.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Whether the last line written has text
+    pub fn after_text(&self) -> bool {
+        self.started && !self.last_empty
+    }
+
+    /// Whether the last line written has text and no trailing line break
+    pub fn after_open_text(&self) -> bool {
+        self.after_text() && !self.last_ends_with_newline
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// `fmt::Write` adapter over an `io::Write`, counting the bytes written
+///
+/// `fmt::Error` carries no detail, so the IO error is kept for `take_error`.
+pub struct IoSink<W: io::Write> {
+    inner: W,
+    bytes: u64,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> IoSink<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            bytes: 0,
+            error: None,
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// The IO error behind a `fmt::Error`
+    pub fn take_error(&mut self) -> io::Error {
+        self.error
+            .take()
+            .unwrap_or_else(|| io::Error::other("markdown formatting failed"))
+    }
+}
+
+impl<W: io::Write> fmt::Write for IoSink<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.inner.write_all(s.as_bytes()) {
+            Ok(()) => {
+                self.bytes += s.len() as u64;
+                Ok(())
+            }
+            Err(e) => {
+                self.error = Some(e);
+                Err(fmt::Error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines_join_like_a_vec() {
+        let lines = ["# Título", "", "> texto\n", "fim"];
+        let mut sink = MarkdownSink::new(String::new());
+        for line in lines {
+            sink.line(line).unwrap();
+        }
+        assert!(sink.after_text() && sink.after_open_text());
+        assert_eq!(sink.into_inner(), lines.join("\n"));
+
+        let mut bytes = Vec::new();
+        let mut sink = MarkdownSink::new(IoSink::new(&mut bytes));
+        sink.line("olá").unwrap();
+        sink.blank().unwrap();
+        assert!(!sink.after_text());
+        assert_eq!(sink.into_inner().bytes(), 5);
+        assert_eq!(bytes, "olá\n".as_bytes());
+
+        let mut sink = MarkdownSink::new(String::new()).tracking_blocks();
+        sink.line("# Título").unwrap();
+        sink.blank().unwrap();
+        sink.highlight_line("bm1", "> um\n\np. 1").unwrap();
+        sink.highlight_line("bm2", "> dois").unwrap();
+        let blocks = sink.take_blocks();
+        let text = sink.into_inner();
+        let spans: Vec<&str> = blocks
+            .iter()
+            .map(|b| &text[b.range.start as usize..b.range.end as usize])
+            .collect();
+        assert_eq!(spans, vec!["> um\n\np. 1", "> dois"]);
+        assert_eq!(blocks[1].highlight_id, "bm2");
+    }
+}
@@ -0,0 +1,363 @@
+//! Per-run export snapshots
+//!
+//! With `snapshot_exports` enabled, every export run also archives the files
+//! it produced into `<export_path>/.khi-snapshots/<id>.zip` and lists the
+//! archive in `.khi-snapshots/manifest.json`, so earlier exports can be
+//! restored without version control.
+
+use crate::utils::fs::{atomic_write_with, FileOps};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Snapshot folder inside the export root
+pub const SNAPSHOT_DIR: &str = ".khi-snapshots";
+
+/// Snapshot list inside `SNAPSHOT_DIR`
+pub const MANIFEST_FILENAME: &str = "manifest.json";
+
+/// One archived export run
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotInfo {
+    /// Archive name without `.zip` (UTC timestamp of the run)
+    pub id: String,
+    /// RFC 3339 time of the run
+    pub created_at: String,
+    /// Archived files, relative to the export root
+    pub files: Vec<String>,
+    pub size_bytes: u64,
+}
+
+/// Contents of `manifest.json`, oldest snapshot first
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotManifest {
+    pub snapshots: Vec<SnapshotInfo>,
+}
+
+fn snapshot_dir(export_dir: &Path) -> PathBuf {
+    export_dir.join(SNAPSHOT_DIR)
+}
+
+fn load_manifest(export_dir: &Path) -> Result<SnapshotManifest, SnapshotError> {
+    match fs::read_to_string(snapshot_dir(export_dir).join(MANIFEST_FILENAME)) {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(SnapshotManifest::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save_manifest(
+    ops: &dyn FileOps,
+    export_dir: &Path,
+    manifest: &SnapshotManifest,
+) -> Result<(), SnapshotError> {
+    let path = snapshot_dir(export_dir).join(MANIFEST_FILENAME);
+    let json = serde_json::to_string_pretty(manifest)?;
+    atomic_write_with(ops, &path, |writer| writer.write_all(json.as_bytes()))?;
+    Ok(())
+}
+
+/// Snapshots recorded for an export root, oldest first
+pub fn list_snapshots(export_dir: &Path) -> Result<Vec<SnapshotInfo>, SnapshotError> {
+    Ok(load_manifest(export_dir)?.snapshots)
+}
+
+/// Archive `files` (paths inside `export_dir`) as a new snapshot
+///
+/// Files outside the export root are skipped.
+pub fn write_snapshot(
+    ops: &dyn FileOps,
+    export_dir: &Path,
+    files: &[PathBuf],
+    now: DateTime<Utc>,
+) -> Result<SnapshotInfo, SnapshotError> {
+    let dir = snapshot_dir(export_dir);
+    fs::create_dir_all(&dir)?;
+    let mut manifest = load_manifest(export_dir)?;
+
+    // Two runs within the same millisecond get a numeric suffix
+    let base = now.format("%Y%m%dT%H%M%S%.3fZ").to_string();
+    let mut id = base.clone();
+    let mut suffix = 2;
+    while dir.join(format!("{}.zip", id)).exists() || manifest.snapshots.iter().any(|s| s.id == id)
+    {
+        id = format!("{}-{}", base, suffix);
+        suffix += 1;
+    }
+
+    let archive_path = dir.join(format!("{}.zip", id));
+    let mut zip = ZipWriter::new(fs::File::create(&archive_path)?);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut archived = Vec::new();
+
+    for file in files {
+        let Ok(relative) = file.strip_prefix(export_dir) else {
+            log::warn!(
+                "[SNAPSHOT] Ignoring file outside the export root: {:?}",
+                file
+            );
+            continue;
+        };
+        let name = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if archived.contains(&name) {
+            continue;
+        }
+
+        zip.start_file(name.as_str(), options)?;
+        zip.write_all(&fs::read(file)?)?;
+        archived.push(name);
+    }
+    zip.finish()?;
+
+    let info = SnapshotInfo {
+        id,
+        created_at: now.to_rfc3339(),
+        files: archived,
+        size_bytes: fs::metadata(&archive_path)?.len(),
+    };
+    manifest.snapshots.push(info.clone());
+    save_manifest(ops, export_dir, &manifest)?;
+    Ok(info)
+}
+
+/// Delete the oldest snapshots until at most `keep` remain and their total
+/// size fits `max_bytes` (0 disables either limit); the newest is always kept
+///
+/// Returns the IDs of the removed snapshots.
+pub fn prune_snapshots(
+    ops: &dyn FileOps,
+    export_dir: &Path,
+    keep: usize,
+    max_bytes: u64,
+) -> Result<Vec<String>, SnapshotError> {
+    let mut manifest = load_manifest(export_dir)?;
+    let mut removed = Vec::new();
+
+    loop {
+        let count = manifest.snapshots.len();
+        let total: u64 = manifest.snapshots.iter().map(|s| s.size_bytes).sum();
+        let over_count = keep > 0 && count > keep;
+        let over_size = max_bytes > 0 && total > max_bytes;
+        if count <= 1 || !(over_count || over_size) {
+            break;
+        }
+
+        let oldest = manifest.snapshots.remove(0);
+        match fs::remove_file(snapshot_dir(export_dir).join(format!("{}.zip", oldest.id))) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        removed.push(oldest.id);
+    }
+
+    if !removed.is_empty() {
+        save_manifest(ops, export_dir, &manifest)?;
+    }
+    Ok(removed)
+}
+
+/// Extract a snapshot into `target_dir`
+///
+/// The target must not be inside the export root and must be empty (or not
+/// exist yet), so a restore never overwrites live or existing files.
+pub fn restore_snapshot(
+    export_dir: &Path,
+    id: &str,
+    target_dir: &Path,
+) -> Result<Vec<PathBuf>, SnapshotError> {
+    let manifest = load_manifest(export_dir)?;
+    if !manifest.snapshots.iter().any(|s| s.id == id) {
+        return Err(SnapshotError::NotFound(id.to_string()));
+    }
+
+    if let Ok(root) = export_dir.canonicalize() {
+        if resolve_path(target_dir)?.starts_with(&root) {
+            return Err(SnapshotError::InvalidTarget(format!(
+                "{} is inside the export folder",
+                target_dir.display()
+            )));
+        }
+    }
+    fs::create_dir_all(target_dir)?;
+    let target = target_dir.canonicalize()?;
+    if fs::read_dir(&target)?.next().is_some() {
+        return Err(SnapshotError::InvalidTarget(format!(
+            "{} is not empty",
+            target_dir.display()
+        )));
+    }
+
+    let archive_path = snapshot_dir(export_dir).join(format!("{}.zip", id));
+    let mut archive = ZipArchive::new(fs::File::open(archive_path)?)?;
+    let mut restored = Vec::new();
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        // enclosed_name() rejects absolute paths and `..` components
+        let Some(relative) = entry.enclosed_name().map(Path::to_path_buf) else {
+            continue;
+        };
+        let path = target.join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        fs::write(&path, content)?;
+        restored.push(path);
+    }
+
+    Ok(restored)
+}
+
+/// Canonical form of a path that may not exist yet: its nearest existing
+/// ancestor is canonicalized and the missing components appended
+fn resolve_path(path: &Path) -> Result<PathBuf, SnapshotError> {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    let mut existing = absolute.as_path();
+    let mut missing = Vec::new();
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => break,
+        }
+    }
+
+    let mut resolved = existing.canonicalize()?;
+    resolved.extend(missing.iter().rev());
+    Ok(resolved)
+}
+
+/// Snapshot-related errors
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// IO error
+    Io(std::io::Error),
+    /// Zip archive error
+    Zip(zip::result::ZipError),
+    /// Manifest could not be read or written
+    Manifest(serde_json::Error),
+    /// No snapshot with this ID
+    NotFound(String),
+    /// Restore target is not allowed
+    InvalidTarget(String),
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "IO error: {}", e),
+            SnapshotError::Zip(e) => write!(f, "Zip error: {}", e),
+            SnapshotError::Manifest(e) => write!(f, "Manifest error: {}", e),
+            SnapshotError::NotFound(id) => write!(f, "Snapshot not found: {}", id),
+            SnapshotError::InvalidTarget(msg) => write!(f, "Invalid restore folder: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(err: std::io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+impl From<zip::result::ZipError> for SnapshotError {
+    fn from(err: zip::result::ZipError) -> Self {
+        SnapshotError::Zip(err)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(err: serde_json::Error) -> Self {
+        SnapshotError::Manifest(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fs::SystemFileOps;
+    use tempfile::TempDir;
+
+    fn write_files(root: &Path, files: &[(&str, &str)]) -> Vec<PathBuf> {
+        files
+            .iter()
+            .map(|(name, content)| {
+                let path = root.join(name);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, content).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_prune_by_count_and_size() {
+        let temp = TempDir::new().unwrap();
+        let files = write_files(temp.path(), &[("a.md", "conteúdo")]);
+        let now = Utc::now();
+        for minutes in 0..4 {
+            write_snapshot(
+                &SystemFileOps,
+                temp.path(),
+                &files,
+                now + chrono::Duration::minutes(minutes),
+            )
+            .unwrap();
+        }
+
+        let removed = prune_snapshots(&SystemFileOps, temp.path(), 2, 0).unwrap();
+        assert_eq!(removed.len(), 2);
+        let remaining = list_snapshots(temp.path()).unwrap();
+        assert_eq!(remaining.len(), 2);
+        assert!(!snapshot_dir(temp.path())
+            .join(format!("{}.zip", removed[0]))
+            .exists());
+
+        // A 1-byte budget still keeps the newest snapshot
+        prune_snapshots(&SystemFileOps, temp.path(), 0, 1).unwrap();
+        let remaining_after = list_snapshots(temp.path()).unwrap();
+        assert_eq!(remaining_after, vec![remaining[1].clone()]);
+    }
+
+    #[test]
+    fn test_restore_refuses_export_tree_and_non_empty_target() {
+        let temp = TempDir::new().unwrap();
+        let export = temp.path().join("export");
+        let files = write_files(&export, &[("a.md", "x")]);
+        let info = write_snapshot(&SystemFileOps, &export, &files, Utc::now()).unwrap();
+
+        let inside = restore_snapshot(&export, &info.id, &export.join("restore/new"));
+        assert!(matches!(inside, Err(SnapshotError::InvalidTarget(_))));
+        assert!(!export.join("restore").exists());
+
+        let busy = temp.path().join("busy");
+        write_files(&busy, &[("keep.md", "mine")]);
+        let non_empty = restore_snapshot(&export, &info.id, &busy);
+        assert!(matches!(non_empty, Err(SnapshotError::InvalidTarget(_))));
+        assert_eq!(fs::read_to_string(busy.join("keep.md")).unwrap(), "mine");
+
+        let missing = restore_snapshot(&export, "nope", &temp.path().join("out"));
+        assert!(matches!(missing, Err(SnapshotError::NotFound(_))));
+    }
+}
@@ -0,0 +1,393 @@
+//! Staging folder of transactional exports
+//!
+//! With `ExportConfig::transactional`, books are written into
+//! `<export root>/.khi-staging-<id>/`, each under the path it will have in the
+//! export root. The folder is on the same volume, so moving the files into
+//! place is a rename. Files are moved only once every book has been written.
+//! Otherwise the staging folder is removed and the live tree stays as it
+//! was. Staging folders left behind by a crash are removed at startup
+//! (`remove_orphaned_staging`).
+
+use crate::utils::fs::FileOps;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Name prefix of staging folders in the export root
+pub const STAGING_PREFIX: &str = ".khi-staging-";
+
+/// Live files replaced by a commit, kept until it succeeds
+const PREVIOUS_DIR: &str = ".previous";
+
+/// What's left to do for a staged book once its files are in place
+#[derive(Debug, Default)]
+pub struct BookFinish {
+    /// Part files written when the book was rendered whole; older, higher
+    /// numbered parts are removed
+    pub rendered_parts: Option<usize>,
+    /// Highlight keys recorded in the append-mode manifest, when it tracks
+    /// the book
+    pub manifest: Option<HashSet<String>>,
+    /// New highlights were appended, so the file's sidecar no longer holds
+    pub appended: bool,
+}
+
+/// A transactional export's staging folder, removed when dropped
+#[derive(Debug)]
+pub struct Staging {
+    root: PathBuf,
+    dir: PathBuf,
+    /// Live path and pending work of each book written so far
+    staged: Mutex<Vec<(PathBuf, BookFinish)>>,
+}
+
+impl Staging {
+    /// Create a new staging folder in `root`
+    pub fn create(root: &Path) -> io::Result<Self> {
+        let id = format!(
+            "{}-{}",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%3f"),
+            std::process::id()
+        );
+        let dir = root.join(format!("{}{}", STAGING_PREFIX, id));
+        fs::create_dir_all(&dir)?;
+        log::info!("[EXPORTER] Exportação transacional em {:?}", dir);
+        Ok(Self {
+            root: root.to_path_buf(),
+            dir,
+            staged: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where to write the file that will end up at `live`, creating its
+    /// folder
+    pub fn stage(&self, live: &Path) -> io::Result<PathBuf> {
+        let relative = live.strip_prefix(&self.root).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is outside the export folder", live.display()),
+            )
+        })?;
+        let staged = self.dir.join(relative);
+        if let Some(parent) = staged.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(staged)
+    }
+
+    /// Remember the work left for the book staged for `live`
+    pub fn defer(&self, live: PathBuf, finish: BookFinish) {
+        self.staged
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((live, finish));
+    }
+
+    /// The work deferred for each staged book, by live path
+    pub fn take_deferred(&self) -> Vec<(PathBuf, BookFinish)> {
+        std::mem::take(&mut *self.staged.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Move every staged file into place
+    ///
+    /// Replaced live files are set aside first. If any move fails, the moves
+    /// already made are undone and the live tree is left as it was.
+    pub fn commit(&self, ops: &dyn FileOps) -> io::Result<Vec<PathBuf>> {
+        let mut staged = Vec::new();
+        collect_files(&self.dir, &mut staged)?;
+        staged.retain(|path| !path.starts_with(self.dir.join(PREVIOUS_DIR)));
+        staged.sort();
+
+        let mut moved: Vec<(PathBuf, PathBuf, Option<PathBuf>)> = Vec::new();
+        for file in staged {
+            let relative = file
+                .strip_prefix(&self.dir)
+                .map_err(io::Error::other)?
+                .to_path_buf();
+            let live = self.root.join(&relative);
+            match self.move_into_place(ops, &file, &live, &relative) {
+                Ok(previous) => moved.push((file, live, previous)),
+                Err(e) => {
+                    log::error!("[EXPORTER] ❌ Falha ao mover {:?}, a desfazer: {}", live, e);
+                    for (file, live, previous) in moved.into_iter().rev() {
+                        undo_move(ops, &file, &live, previous.as_deref());
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        log::info!(
+            "[EXPORTER] ✅ {} ficheiro(s) movido(s) para a exportação",
+            moved.len()
+        );
+        Ok(moved.into_iter().map(|(_, live, _)| live).collect())
+    }
+
+    /// Rename `file` to `live`, setting the file it replaces aside; returns
+    /// where that file went
+    fn move_into_place(
+        &self,
+        ops: &dyn FileOps,
+        file: &Path,
+        live: &Path,
+        relative: &Path,
+    ) -> io::Result<Option<PathBuf>> {
+        if let Some(parent) = live.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let previous = if live.is_file() {
+            let previous = self.dir.join(PREVIOUS_DIR).join(relative);
+            if let Some(parent) = previous.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            ops.rename(live, &previous)?;
+            Some(previous)
+        } else {
+            None
+        };
+        if let Err(e) = ops.rename(file, live) {
+            if let Some(previous) = &previous {
+                let _ = ops.rename(previous, live);
+            }
+            return Err(e);
+        }
+        Ok(previous)
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            if e.kind() != io::ErrorKind::NotFound {
+                log::warn!("[EXPORTER] Falha ao remover {:?}: {}", self.dir, e);
+            }
+        }
+    }
+}
+
+/// Put `live` back to how it was before `file` was moved there
+fn undo_move(ops: &dyn FileOps, file: &Path, live: &Path, previous: Option<&Path>) {
+    if let Err(e) = ops.rename(live, file) {
+        log::error!("[EXPORTER] ❌ Falha ao desfazer {:?}: {}", live, e);
+        return;
+    }
+    if let Some(previous) = previous {
+        if let Err(e) = ops.rename(previous, live) {
+            log::error!("[EXPORTER] ❌ Falha ao repor {:?}: {}", live, e);
+        }
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Remove staging folders a crashed export left in `root`; returns how many
+///
+/// Live files a commit had set aside (`.previous`) are first put back where
+/// they were missing. Folders of exports still running in another process
+/// (a headless run) are left alone.
+pub fn remove_orphaned_staging(root: &Path) -> usize {
+    let Ok(entries) = fs::read_dir(root) else {
+        return 0;
+    };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(id) = name.strip_prefix(STAGING_PREFIX) else {
+            continue;
+        };
+        if !path.is_dir() {
+            continue;
+        }
+        if staging_pid(id).is_some_and(is_process_alive) {
+            log::info!("[EXPORTER] Exportação em curso noutro processo: {:?}", path);
+            continue;
+        }
+        if let Err(e) = restore_previous(&path.join(PREVIOUS_DIR), root) {
+            // Keep the folder: it may hold the only copy of a live file
+            log::error!(
+                "[EXPORTER] ❌ Falha ao repor ficheiros de {:?}: {}",
+                path,
+                e
+            );
+            continue;
+        }
+        match fs::remove_dir_all(&path) {
+            Ok(()) => {
+                log::info!("[EXPORTER] Pasta temporária órfã removida: {:?}", path);
+                removed += 1;
+            }
+            Err(e) => log::warn!("[EXPORTER] Falha ao remover {:?}: {}", path, e),
+        }
+    }
+    removed
+}
+
+/// Move the live files set aside in `previous` back under `root`, where
+/// their live path is missing
+fn restore_previous(previous: &Path, root: &Path) -> io::Result<()> {
+    if !previous.is_dir() {
+        return Ok(());
+    }
+    let mut files = Vec::new();
+    collect_files(previous, &mut files)?;
+    for file in files {
+        let relative = file.strip_prefix(previous).map_err(io::Error::other)?;
+        let live = root.join(relative);
+        if live.exists() {
+            continue;
+        }
+        if let Some(parent) = live.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&file, &live)?;
+        log::warn!(
+            "[EXPORTER] Ficheiro reposto após exportação interrompida: {:?}",
+            live
+        );
+    }
+    Ok(())
+}
+
+/// Process ID in a staging folder's `<time>-<pid>` suffix
+fn staging_pid(id: &str) -> Option<u32> {
+    id.rsplit_once('-')?.1.parse().ok()
+}
+
+/// Whether a process with `pid` is running
+fn is_process_alive(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+    #[cfg(unix)]
+    let alive = std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    #[cfg(windows)]
+    let alive = std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()));
+    #[cfg(not(any(unix, windows)))]
+    let alive = false;
+    alive
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fs::{MockFileOps, SystemFileOps};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_failed_commit_restores_live_files() {
+        let temp = TempDir::new().unwrap();
+        fs::write(temp.path().join("a.md"), "old a").unwrap();
+        let staging = Staging::create(temp.path()).unwrap();
+        for (name, content) in [("a.md", "new a"), ("b/c.md", "new c"), ("d.md", "new d")] {
+            let staged = staging.stage(&temp.path().join(name)).unwrap();
+            fs::write(staged, content).unwrap();
+        }
+
+        // The third move fails: the first two are undone
+        let mut ops = MockFileOps::new();
+        ops.expect_rename().returning(|from, to| {
+            if to.ends_with("d.md") {
+                Err(io::Error::other("locked"))
+            } else {
+                fs::rename(from, to)
+            }
+        });
+        assert!(staging.commit(&ops).is_err());
+        assert_eq!(
+            fs::read_to_string(temp.path().join("a.md")).unwrap(),
+            "old a"
+        );
+        assert!(!temp.path().join("b/c.md").exists());
+        assert!(!temp.path().join("d.md").exists());
+
+        let live = staging.commit(&SystemFileOps).unwrap();
+        assert_eq!(live.len(), 3);
+        assert_eq!(
+            fs::read_to_string(temp.path().join("a.md")).unwrap(),
+            "new a"
+        );
+        assert_eq!(
+            fs::read_to_string(temp.path().join("b/c.md")).unwrap(),
+            "new c"
+        );
+
+        let dir = staging.dir().to_path_buf();
+        drop(staging);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_orphaned_staging_is_removed() {
+        let temp = TempDir::new().unwrap();
+        let orphan = temp.path().join(format!("{}crashed", STAGING_PREFIX));
+        fs::create_dir_all(orphan.join("Autor")).unwrap();
+        fs::write(orphan.join("Autor/Livro.md"), "half").unwrap();
+        fs::write(temp.path().join("Livro.md"), "live").unwrap();
+
+        assert_eq!(remove_orphaned_staging(temp.path()), 1);
+        assert!(!orphan.exists());
+        assert!(temp.path().join("Livro.md").exists());
+        assert_eq!(remove_orphaned_staging(&temp.path().join("missing")), 0);
+    }
+
+    #[test]
+    fn test_crash_mid_commit_restores_set_aside_files() {
+        let temp = TempDir::new().unwrap();
+        // A commit set "Autor/Livro.md" aside, then crashed before moving
+        // the new file in; "Outro.md" was already replaced
+        let orphan = temp
+            .path()
+            .join(format!("{}20250101T000000000", STAGING_PREFIX));
+        let previous = orphan.join(PREVIOUS_DIR);
+        fs::create_dir_all(previous.join("Autor")).unwrap();
+        fs::write(previous.join("Autor/Livro.md"), "original").unwrap();
+        fs::write(previous.join("Outro.md"), "old other").unwrap();
+        fs::write(temp.path().join("Outro.md"), "new other").unwrap();
+
+        assert_eq!(remove_orphaned_staging(temp.path()), 1);
+        assert!(!orphan.exists());
+        assert_eq!(
+            fs::read_to_string(temp.path().join("Autor/Livro.md")).unwrap(),
+            "original"
+        );
+        assert_eq!(
+            fs::read_to_string(temp.path().join("Outro.md")).unwrap(),
+            "new other"
+        );
+    }
+
+    #[test]
+    fn test_staging_of_a_running_export_is_kept() {
+        let temp = TempDir::new().unwrap();
+        let running = Staging::create(temp.path()).unwrap();
+        assert_eq!(remove_orphaned_staging(temp.path()), 0);
+        assert!(running.dir().exists());
+        assert_eq!(staging_pid("20250101T000000000-4242"), Some(4242));
+        assert_eq!(staging_pid("crashed"), None);
+    }
+}
@@ -0,0 +1,135 @@
+//! Per-highlight markdown, one renderer per `HighlightStyle`
+//!
+//! The exporter gathers the parts of a highlight once (text, note, location
+//! and the append-mode anchor) and hands them to the renderer picked by
+//! `renderer`, so the styles only differ in layout. Quoting of multi-line
+//! text and notes goes through `quote_lines` for every style that quotes.
+
+use crate::models::{ExportConfig, HighlightStyle};
+
+/// Everything a renderer needs to know about one highlight
+pub struct HighlightParts<'a> {
+    pub text: &'a str,
+    /// The reader's note, if it has any text
+    pub annotation: Option<&'a str>,
+    /// Location parts (chapter, page, …); empty when hidden or unknown
+    pub location: Vec<String>,
+    /// Whether locations are shown at all
+    pub show_location: bool,
+    /// Append-mode anchor with its leading space, or empty
+    pub anchor: String,
+}
+
+impl HighlightParts<'_> {
+    fn location_line(&self) -> Option<String> {
+        (!self.location.is_empty()).then(|| self.location.join(" · "))
+    }
+}
+
+/// Layout of a single highlight's markdown block
+pub trait HighlightRenderer {
+    fn render(&self, parts: &HighlightParts) -> String;
+}
+
+/// Renderer for `config`: `compact` wins over the highlight style
+pub fn renderer(config: &ExportConfig) -> &'static dyn HighlightRenderer {
+    if config.compact {
+        return &CompactItem;
+    }
+    match config.highlight_style {
+        HighlightStyle::Blockquote => &Blockquote,
+        HighlightStyle::Callout => &Callout,
+        HighlightStyle::Plain => &Plain,
+    }
+}
+
+/// `text` quoted `depth` levels deep, line by line (blank lines keep the
+/// quote open)
+pub fn quote_lines(text: &str, depth: usize) -> Vec<String> {
+    let prefix = "> ".repeat(depth);
+    text.trim_end()
+        .lines()
+        .map(|line| {
+            if line.trim().is_empty() {
+                prefix.trim_end().to_string()
+            } else {
+                format!("{}{}", prefix, line)
+            }
+        })
+        .collect()
+}
+
+/// Quote followed by the location line (notes aren't rendered)
+struct Blockquote;
+
+impl HighlightRenderer for Blockquote {
+    fn render(&self, parts: &HighlightParts) -> String {
+        let mut lines = vec![format!("> {}{}", parts.text, parts.anchor)];
+
+        // Location info (no label, just the value)
+        if let Some(location) = parts.location_line() {
+            lines.push(String::new());
+            lines.push(location);
+            lines.push(String::new());
+        } else if !parts.show_location {
+            // Keep consecutive quotes from merging into one
+            lines.push(String::new());
+        }
+
+        lines.join("\n")
+    }
+}
+
+/// Obsidian `[!quote]` callout titled with the location, the note nested
+/// as a `[!note]` callout
+struct Callout;
+
+impl HighlightRenderer for Callout {
+    fn render(&self, parts: &HighlightParts) -> String {
+        let title = parts
+            .location_line()
+            .map(|location| format!(" {}", location))
+            .unwrap_or_default();
+        let mut lines = vec![format!("> [!quote]{}{}", title, parts.anchor)];
+        lines.extend(quote_lines(parts.text, 1));
+        if let Some(note) = parts.annotation {
+            lines.push(">".to_string());
+            lines.push("> > [!note]".to_string());
+            lines.extend(quote_lines(note, 2));
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+}
+
+/// Bare paragraphs: the text, its location and the note
+struct Plain;
+
+impl HighlightRenderer for Plain {
+    fn render(&self, parts: &HighlightParts) -> String {
+        let mut lines = vec![format!("{}{}", parts.text.trim_end(), parts.anchor)];
+        if let Some(location) = parts.location_line() {
+            lines.push(String::new());
+            lines.push(location);
+        }
+        if let Some(note) = parts.annotation {
+            lines.push(String::new());
+            lines.push(format!("Nota: {}", note.trim_end()));
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+}
+
+/// A single list item, whitespace collapsed
+struct CompactItem;
+
+impl HighlightRenderer for CompactItem {
+    fn render(&self, parts: &HighlightParts) -> String {
+        let text = parts.text.split_whitespace().collect::<Vec<_>>().join(" ");
+        match parts.location_line() {
+            Some(location) => format!("- \"{}\" — {}{}", text, location, parts.anchor),
+            None => format!("- \"{}\"{}", text, parts.anchor),
+        }
+    }
+}
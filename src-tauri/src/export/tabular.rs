@@ -0,0 +1,496 @@
+//! CSV and TSV exports
+//!
+//! One row per highlight, with the columns chosen in `TabularOptions`: each
+//! column maps to a function extracting its value from the book and the
+//! highlight. Readwise's CSV import format is just a fixed column preset with
+//! its own header labels.
+
+use super::plain_text;
+use crate::models::{
+    color_label, Book, ExportConfig, ExportFormat, Highlight, TabularColumn, TabularOptions,
+    TitleForm,
+};
+use crate::utils::date::utc_offset;
+use chrono::FixedOffset;
+use std::collections::BTreeMap;
+
+/// Name of the CSV file written to the export root
+pub const CSV_FILENAME: &str = "highlights.csv";
+
+/// Name of the TSV file written to the export root
+pub const TSV_FILENAME: &str = "highlights.tsv";
+
+/// Name of the Readwise CSV written to the export root
+pub const READWISE_FILENAME: &str = "readwise.csv";
+
+type Extract = fn(&Book, &Highlight) -> String;
+
+/// Every column with its default header label and its value
+const COLUMNS: &[(TabularColumn, &str, Extract)] = &[
+    (TabularColumn::Title, "Title", |book, _| {
+        book.title_in(TitleForm::default())
+    }),
+    (TabularColumn::Author, "Author", |book, _| {
+        book.display_author()
+    }),
+    (TabularColumn::Isbn, "ISBN", |book, _| {
+        book.isbn.clone().unwrap_or_default()
+    }),
+    (TabularColumn::Publisher, "Publisher", |book, _| {
+        book.publisher.clone().unwrap_or_default()
+    }),
+    (TabularColumn::Series, "Series", |book, _| {
+        book.series.clone().unwrap_or_default()
+    }),
+    (TabularColumn::Chapter, "Chapter", |_, highlight| {
+        highlight.chapter_title.clone().unwrap_or_default()
+    }),
+    (TabularColumn::Text, "Text", |book, highlight| {
+        plain_text(book, highlight).into_owned()
+    }),
+    (TabularColumn::Annotation, "Annotation", |_, highlight| {
+        highlight.annotation.clone().unwrap_or_default()
+    }),
+    (TabularColumn::DateCreated, "Date", |_, highlight| {
+        format_created(highlight, utc_offset())
+    }),
+    (TabularColumn::Progress, "Progress %", |_, highlight| {
+        highlight
+            .chapter_progress
+            .map(|progress| format!("{:.0}", (progress * 100.0).clamp(0.0, 100.0)))
+            .unwrap_or_default()
+    }),
+    (TabularColumn::Page, "Page", |_, highlight| {
+        highlight
+            .page
+            .map(|page| page.to_string())
+            .unwrap_or_default()
+    }),
+    (TabularColumn::Color, "Color", |_, highlight| {
+        highlight.color.clone().unwrap_or_default()
+    }),
+    // Labelled by the layout, from the configured `color_labels`
+    (TabularColumn::ColorLabel, "Color Label", |_, _| {
+        String::new()
+    }),
+    (TabularColumn::Note, "Note", |book, _| {
+        book.notes.clone().unwrap_or_default()
+    }),
+    (TabularColumn::Tags, "Tags", |_, highlight| {
+        highlight.tags.join(", ")
+    }),
+];
+
+/// Readwise's CSV columns and the headers it expects
+pub const READWISE_PRESET: &[(TabularColumn, &str)] = &[
+    (TabularColumn::Text, "Highlight"),
+    (TabularColumn::Title, "Title"),
+    (TabularColumn::Author, "Author"),
+    (TabularColumn::Annotation, "Note"),
+    (TabularColumn::Page, "Location"),
+    (TabularColumn::DateCreated, "Date"),
+];
+
+fn column(column: TabularColumn) -> &'static (TabularColumn, &'static str, Extract) {
+    COLUMNS
+        .iter()
+        .find(|(c, _, _)| *c == column)
+        .expect("every column is registered")
+}
+
+/// Default header label of `column`
+pub fn default_label(tabular: TabularColumn) -> &'static str {
+    column(tabular).1
+}
+
+/// Value of `column` for one highlight
+pub fn column_value(tabular: TabularColumn, book: &Book, highlight: &Highlight) -> String {
+    (column(tabular).2)(book, highlight)
+}
+
+/// Creation date as wall-clock time at `offset`, or as the device wrote it
+fn format_created(highlight: &Highlight, offset: FixedOffset) -> String {
+    highlight
+        .created_local(offset)
+        .map(|date| date.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| highlight.date_created.clone())
+}
+
+/// Field separator of a tabular file
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Delimiter {
+    Comma,
+    Tab,
+}
+
+/// Columns (with their header labels) and separator of a tabular file
+#[derive(Debug, Clone, PartialEq)]
+pub struct TabularLayout {
+    delimiter: Delimiter,
+    columns: Vec<(TabularColumn, String)>,
+    /// Labels of the `ColorLabel` column, by color name
+    color_labels: BTreeMap<String, String>,
+    /// Offset the `DateCreated` column is shown at
+    offset: FixedOffset,
+    /// Form of the `Title` column
+    title: TitleForm,
+}
+
+impl TabularLayout {
+    /// Layout of a tabular `format` (`None` for the other formats)
+    pub fn for_format(format: ExportFormat, options: &TabularOptions) -> Option<Self> {
+        let configured = || {
+            options
+                .columns
+                .iter()
+                .map(|&c| {
+                    let label = options
+                        .labels
+                        .get(&c)
+                        .filter(|label| !label.trim().is_empty())
+                        .cloned()
+                        .unwrap_or_else(|| default_label(c).to_string());
+                    (c, label)
+                })
+                .collect()
+        };
+        let (delimiter, columns) = match format {
+            ExportFormat::Csv => (Delimiter::Comma, configured()),
+            ExportFormat::Tsv => (Delimiter::Tab, configured()),
+            ExportFormat::Readwise => (
+                Delimiter::Comma,
+                READWISE_PRESET
+                    .iter()
+                    .map(|(c, label)| (*c, label.to_string()))
+                    .collect(),
+            ),
+            _ => return None,
+        };
+        Some(Self {
+            delimiter,
+            columns,
+            color_labels: BTreeMap::new(),
+            offset: utc_offset(),
+            title: TitleForm::default(),
+        })
+    }
+
+    /// Show titles in `form`
+    pub fn with_title_form(mut self, form: TitleForm) -> Self {
+        self.title = form;
+        self
+    }
+
+    /// Show creation dates as wall-clock time at `offset`
+    pub fn with_assumed_offset(mut self, offset: FixedOffset) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Label colors of the `ColorLabel` column with `labels`
+    pub fn with_color_labels(mut self, labels: &BTreeMap<String, String>) -> Self {
+        self.color_labels = labels.clone();
+        self
+    }
+
+    /// Add a `ColorLabel` column when there is none
+    pub fn with_color_label_column(mut self) -> Self {
+        if !self
+            .columns
+            .iter()
+            .any(|(c, _)| *c == TabularColumn::ColorLabel)
+        {
+            self.columns.push((
+                TabularColumn::ColorLabel,
+                default_label(TabularColumn::ColorLabel).to_string(),
+            ));
+        }
+        self
+    }
+
+    /// Header row and one row per highlight, books in order and highlights
+    /// in reading order
+    pub fn render(&self, books: &[&Book]) -> String {
+        let mut out = String::new();
+        self.push_row(
+            &mut out,
+            self.columns.iter().map(|(_, label)| label.clone()),
+        );
+        for book in books {
+            for highlight in book.highlights_by_position() {
+                self.push_row(
+                    &mut out,
+                    self.columns
+                        .iter()
+                        .map(|(c, _)| match (c, &highlight.color) {
+                            (TabularColumn::ColorLabel, Some(color)) => {
+                                color_label(&self.color_labels, color)
+                            }
+                            (TabularColumn::ColorLabel, None) => String::new(),
+                            (TabularColumn::DateCreated, _) => {
+                                format_created(highlight, self.offset)
+                            }
+                            (TabularColumn::Title, _) => book.title_in(self.title),
+                            _ => column_value(*c, book, highlight),
+                        }),
+                );
+            }
+        }
+        out
+    }
+
+    fn push_row(&self, out: &mut String, fields: impl Iterator<Item = String>) {
+        for (index, field) in fields.enumerate() {
+            if index > 0 {
+                out.push(match self.delimiter {
+                    Delimiter::Comma => ',',
+                    Delimiter::Tab => '\t',
+                });
+            }
+            match self.delimiter {
+                Delimiter::Comma => out.push_str(&escape_csv(&field)),
+                Delimiter::Tab => out.push_str(&escape_tsv(&field)),
+            }
+        }
+        out.push('\n');
+    }
+}
+
+/// Quote a CSV field when it holds a comma, quote or line break (RFC 4180)
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// TSV has no quoting: tabs and line breaks become spaces
+fn escape_tsv(field: &str) -> String {
+    field.replace("\r\n", " ").replace(['\t', '\n', '\r'], " ")
+}
+
+/// Filename of a tabular format (`None` for the other formats)
+pub fn tabular_filename(format: ExportFormat) -> Option<&'static str> {
+    match format {
+        ExportFormat::Csv => Some(CSV_FILENAME),
+        ExportFormat::Tsv => Some(TSV_FILENAME),
+        ExportFormat::Readwise => Some(READWISE_FILENAME),
+        _ => None,
+    }
+}
+
+/// Filename and content of a tabular format (`None` for the other formats),
+/// dates shown at `offset` and titles in `title`
+pub fn render_tabular(
+    books: &[&Book],
+    config: &ExportConfig,
+    offset: FixedOffset,
+    title: TitleForm,
+) -> Option<(&'static str, String)> {
+    let mut layout = TabularLayout::for_format(config.format, &config.tabular)?
+        .with_assumed_offset(offset)
+        .with_title_form(title)
+        .with_color_labels(&config.color_labels);
+    // Readwise's preset stays as its importer expects it
+    if config.group_by_color && config.format != ExportFormat::Readwise {
+        layout = layout.with_color_label_column();
+    }
+    Some((tabular_filename(config.format)?, layout.render(books)))
+}
+
+/// Check the configured columns
+pub fn validate_tabular_options(options: &TabularOptions) -> Result<(), String> {
+    if options.columns.is_empty() {
+        return Err("Choose at least one column for CSV/TSV exports".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tabular_books() -> Vec<Book> {
+        let mut book = Book::new(
+            "vol1".to_string(),
+            "O Ano da Morte de Ricardo Reis".to_string(),
+            "José Saramago".to_string(),
+        );
+        book.isbn = Some("9789722105203".to_string());
+        let mut first = Highlight::new(
+            "bm1".to_string(),
+            "Aqui o mar acaba e a terra principia.".to_string(),
+            "2024-03-01T21:15:00.000".to_string(),
+        );
+        first.chapter_title = Some("Capítulo 1".to_string());
+        first.chapter_progress = Some(0.05);
+        first.page = Some(3);
+        let mut second = Highlight::new(
+            "bm2".to_string(),
+            "Sábio é o que se contenta com o \"espetáculo\" do mundo,\nsem mais.".to_string(),
+            "2024-03-02T08:00:00.000".to_string(),
+        );
+        second.chapter_title = Some("Capítulo 2".to_string());
+        second.chapter_progress = Some(0.5);
+        second.annotation = Some("Reis, sempre\tReis".to_string());
+        second.color = Some("yellow".to_string());
+        book.highlights = vec![first, second];
+
+        let mut other = Book::new(
+            "vol2".to_string(),
+            "Walden".to_string(),
+            "Henry David Thoreau".to_string(),
+        );
+        other.highlights.push(Highlight::new(
+            "bm3".to_string(),
+            "Simplify, simplify.".to_string(),
+            "2024-04-10T12:00:00.000".to_string(),
+        ));
+        vec![book, other]
+    }
+
+    fn render(format: ExportFormat, options: &TabularOptions) -> String {
+        let books = tabular_books();
+        let refs: Vec<&Book> = books.iter().collect();
+        TabularLayout::for_format(format, options)
+            .unwrap()
+            .render(&refs)
+    }
+
+    #[test]
+    fn test_csv_golden() {
+        assert_eq!(
+            render(ExportFormat::Csv, &TabularOptions::default()),
+            include_str!("testdata/highlights.csv")
+        );
+    }
+
+    #[test]
+    fn test_tsv_golden() {
+        assert_eq!(
+            render(ExportFormat::Tsv, &TabularOptions::default()),
+            include_str!("testdata/highlights.tsv")
+        );
+    }
+
+    #[test]
+    fn test_readwise_is_a_fixed_preset() {
+        // Configured columns don't apply to the preset
+        let options = TabularOptions {
+            columns: vec![TabularColumn::Color],
+            ..TabularOptions::default()
+        };
+        assert_eq!(
+            render(ExportFormat::Readwise, &options),
+            include_str!("testdata/readwise.csv")
+        );
+    }
+
+    #[test]
+    fn test_custom_column_order() {
+        let options = TabularOptions {
+            columns: vec![
+                TabularColumn::Isbn,
+                TabularColumn::Progress,
+                TabularColumn::Title,
+            ],
+            ..TabularOptions::default()
+        };
+        let csv = render(ExportFormat::Csv, &options);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "ISBN,Progress %,Title");
+        assert_eq!(lines[1], "9789722105203,5,O Ano da Morte de Ricardo Reis");
+        assert_eq!(lines[3], ",,Walden");
+    }
+
+    #[test]
+    fn test_single_column_and_label_overrides() {
+        let options = TabularOptions {
+            columns: vec![TabularColumn::Text],
+            labels: [(TabularColumn::Text, "Destaque".to_string())].into(),
+        };
+        assert_eq!(
+            render(ExportFormat::Tsv, &options),
+            "Destaque\n\
+             Aqui o mar acaba e a terra principia.\n\
+             Sábio é o que se contenta com o \"espetáculo\" do mundo, sem mais.\n\
+             Simplify, simplify.\n"
+        );
+
+        // Labels of unused columns and blank labels are ignored
+        let options = TabularOptions {
+            columns: vec![TabularColumn::Title, TabularColumn::Author],
+            labels: [
+                (TabularColumn::Title, " ".to_string()),
+                (TabularColumn::Color, "Cor".to_string()),
+            ]
+            .into(),
+        };
+        assert!(render(ExportFormat::Csv, &options).starts_with("Title,Author\n"));
+    }
+
+    #[test]
+    fn test_note_and_tags_columns() {
+        let mut books = tabular_books();
+        books[0].notes = Some("Releitura em 2024".to_string());
+        books[0].highlights[1].tags = vec!["lisboa".to_string(), "poesia".to_string()];
+        let refs: Vec<&Book> = books.iter().collect();
+        let options = TabularOptions {
+            columns: vec![
+                TabularColumn::Title,
+                TabularColumn::Note,
+                TabularColumn::Tags,
+            ],
+            ..TabularOptions::default()
+        };
+        let csv = TabularLayout::for_format(ExportFormat::Csv, &options)
+            .unwrap()
+            .render(&refs);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "Title,Note,Tags");
+        assert_eq!(
+            lines[1],
+            "O Ano da Morte de Ricardo Reis,Releitura em 2024,"
+        );
+        assert_eq!(
+            lines[2],
+            "O Ano da Morte de Ricardo Reis,Releitura em 2024,\"lisboa, poesia\""
+        );
+        assert_eq!(lines[3], "Walden,,");
+    }
+
+    #[test]
+    fn test_color_label_column_uses_configured_labels() {
+        let books = tabular_books();
+        let refs: Vec<&Book> = books.iter().collect();
+        let mut config = crate::settings::AppSettings::default().export_config;
+        config.format = ExportFormat::Csv;
+        config.tabular.columns = vec![TabularColumn::Text, TabularColumn::ColorLabel];
+        config.color_labels = [("yellow".to_string(), "Citações".to_string())].into();
+
+        // Chosen as a column, without grouping by color
+        let (_, csv) = render_tabular(&refs, &config, utc_offset(), TitleForm::default()).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "Text,Color Label");
+        assert_eq!(lines[1], "Aqui o mar acaba e a terra principia.,");
+        assert!(csv.contains("sem mais.\",Citações\n"));
+    }
+
+    #[test]
+    fn test_empty_column_list_is_rejected() {
+        let mut options = TabularOptions::default();
+        assert!(validate_tabular_options(&options).is_ok());
+        options.columns.clear();
+        assert!(validate_tabular_options(&options).is_err());
+
+        let mut config = crate::settings::AppSettings::default().export_config;
+        config.tabular = options;
+        assert!(super::super::validate_export_config(&config).is_ok());
+        config.format = ExportFormat::Tsv;
+        assert!(super::super::validate_export_config(&config).is_err());
+        // The Readwise preset never uses the configured columns
+        config.format = ExportFormat::Readwise;
+        assert!(super::super::validate_export_config(&config).is_ok());
+    }
+}
@@ -0,0 +1,568 @@
+//! Custom markdown templates
+//!
+//! With `markdown_template` set, per-book markdown files are rendered from
+//! the template instead of the built-in layout. The syntax is a small subset
+//! of Mustache:
+//!
+//! - `{{title}}` inserts a variable (see `BOOK_VARIABLES` and
+//!   `HIGHLIGHT_VARIABLES`)
+//! - `{{#highlights}}…{{/highlights}}` repeats its body for every highlight,
+//!   in reading order; highlight variables are only available inside it
+//! - `{{#note}}…{{/note}}` (any other variable) renders its body only when the
+//!   variable is not empty
+//!
+//! Parsing never fails: `validate_template` reports unknown placeholders and
+//! unbalanced sections with their byte offsets, and rendering a broken
+//! template does its best with what it could parse.
+
+use super::{format_date, location_parts, MarkdownExporter};
+use crate::db::kobo::parse_kobo_datetime;
+use crate::models::{Book, ExportConfig, ExportFormat, Highlight, TitleForm};
+use crate::sample::sample_books;
+use crate::utils::date::utc_offset;
+use chrono::{FixedOffset, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// Variables describing the book, available everywhere
+pub const BOOK_VARIABLES: &[&str] = &[
+    "title",
+    "author",
+    "isbn",
+    "publisher",
+    "language",
+    "series",
+    "series_index",
+    "description",
+    "read_date",
+    "highlight_count",
+];
+
+/// Variables of one highlight, available inside `{{#highlights}}`
+pub const HIGHLIGHT_VARIABLES: &[&str] = &[
+    "text", "note", "chapter", "location", "date", "color", "page",
+];
+
+/// Section repeated for every highlight
+pub const HIGHLIGHTS_SECTION: &str = "highlights";
+
+/// Whether markdown exports with `config` use the template (blank templates
+/// use the built-in layout)
+pub fn is_custom(config: &ExportConfig) -> bool {
+    config.format == ExportFormat::Markdown && !config.markdown_template.trim().is_empty()
+}
+
+/// Problems found in a template
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateReport {
+    pub valid: bool,
+    /// Problems that make the output differ from what the template says
+    pub errors: Vec<TemplateIssue>,
+    /// Things that are probably mistakes (e.g. empty output)
+    pub warnings: Vec<TemplateIssue>,
+}
+
+/// One problem, located by byte offsets into the template
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateIssue {
+    pub kind: TemplateIssueKind,
+    pub message: String,
+    /// Byte range of the offending tag (`start == end` for the whole output)
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum TemplateIssueKind {
+    UnknownPlaceholder,
+    /// `{{` without a matching `}}`
+    UnterminatedTag,
+    /// `{{#name}}` never closed
+    UnclosedSection,
+    /// `{{/name}}` without an open section
+    UnexpectedClose,
+    /// `{{/name}}` closing a section of another name
+    MismatchedClose,
+    EmptyOutput,
+}
+
+#[derive(Debug, PartialEq)]
+enum Node {
+    Text(Range<usize>),
+    Variable(String),
+    Section { name: String, body: Vec<Node> },
+}
+
+/// Open section while parsing: its name, tag span and the nodes before it
+struct Frame {
+    name: String,
+    tag: Range<usize>,
+    parent: Vec<Node>,
+}
+
+/// Parse `template` into nodes, collecting every error
+fn parse(template: &str) -> (Vec<Node>, Vec<TemplateIssue>) {
+    let mut issues = Vec::new();
+    let mut nodes = Vec::new();
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut position = 0;
+
+    while let Some(found) = template[position..].find("{{") {
+        let open = position + found;
+        if open > position {
+            nodes.push(Node::Text(position..open));
+        }
+        let Some(length) = template[open + 2..].find("}}") else {
+            issues.push(issue(
+                TemplateIssueKind::UnterminatedTag,
+                "Tag is never closed with }}".to_string(),
+                open..template.len(),
+            ));
+            position = template.len();
+            break;
+        };
+        let span = open..open + 2 + length + 2;
+        let tag = template[open + 2..open + 2 + length].trim();
+        position = span.end;
+
+        if let Some(name) = tag.strip_prefix('#') {
+            let name = name.trim().to_string();
+            check_name(&name, &stack, span.clone(), true, &mut issues);
+            stack.push(Frame {
+                name,
+                tag: span,
+                parent: std::mem::take(&mut nodes),
+            });
+        } else if let Some(name) = tag.strip_prefix('/') {
+            let name = name.trim();
+            match stack.pop() {
+                None => issues.push(issue(
+                    TemplateIssueKind::UnexpectedClose,
+                    format!("{{{{/{}}}}} closes no open section", name),
+                    span,
+                )),
+                Some(frame) => {
+                    if frame.name != name {
+                        issues.push(issue(
+                            TemplateIssueKind::MismatchedClose,
+                            format!(
+                                "{{{{/{}}}}} closes the {{{{#{}}}}} section",
+                                name, frame.name
+                            ),
+                            span,
+                        ));
+                    }
+                    nodes = close(frame, nodes);
+                }
+            }
+        } else {
+            check_name(tag, &stack, span, false, &mut issues);
+            nodes.push(Node::Variable(tag.to_string()));
+        }
+    }
+    if position < template.len() {
+        nodes.push(Node::Text(position..template.len()));
+    }
+
+    // Sections left open end with the template
+    while let Some(frame) = stack.pop() {
+        issues.push(issue(
+            TemplateIssueKind::UnclosedSection,
+            format!("{{{{#{}}}}} is never closed", frame.name),
+            frame.tag.clone(),
+        ));
+        nodes = close(frame, nodes);
+    }
+    (nodes, issues)
+}
+
+/// The parent's nodes with the section `frame` holding `body` appended
+fn close(frame: Frame, body: Vec<Node>) -> Vec<Node> {
+    let mut nodes = frame.parent;
+    nodes.push(Node::Section {
+        name: frame.name,
+        body,
+    });
+    nodes
+}
+
+/// Report `name` if it means nothing where it is used
+fn check_name(
+    name: &str,
+    stack: &[Frame],
+    span: Range<usize>,
+    section: bool,
+    issues: &mut Vec<TemplateIssue>,
+) {
+    let in_highlights = stack.iter().any(|f| f.name == HIGHLIGHTS_SECTION);
+    let known = BOOK_VARIABLES.contains(&name)
+        || (in_highlights && HIGHLIGHT_VARIABLES.contains(&name))
+        || (section && name == HIGHLIGHTS_SECTION && !in_highlights);
+    if known {
+        return;
+    }
+    let message = if HIGHLIGHT_VARIABLES.contains(&name) {
+        format!(
+            "{{{{{}}}}} is only available inside {{{{#highlights}}}}",
+            name
+        )
+    } else if name == HIGHLIGHTS_SECTION && section {
+        "{{#highlights}} sections can't be nested".to_string()
+    } else if name == HIGHLIGHTS_SECTION {
+        "{{highlights}} is a section: use {{#highlights}}…{{/highlights}}".to_string()
+    } else {
+        format!("Unknown placeholder {{{{{}}}}}", name)
+    };
+    issues.push(issue(TemplateIssueKind::UnknownPlaceholder, message, span));
+}
+
+fn issue(kind: TemplateIssueKind, message: String, span: Range<usize>) -> TemplateIssue {
+    TemplateIssue {
+        kind,
+        message,
+        start: span.start,
+        end: span.end,
+    }
+}
+
+/// The first error of `template`, for rejecting it before an export
+pub fn check_template(template: &str) -> Result<(), String> {
+    match parse(template).1.into_iter().next() {
+        Some(error) => Err(format!("Invalid markdown template: {}", error.message)),
+        None => Ok(()),
+    }
+}
+
+/// Check `template` fully; `sample` is rendered to catch empty output
+pub fn validate_template(template: &str, sample: &Book, config: &ExportConfig) -> TemplateReport {
+    let (nodes, errors) = parse(template);
+    let mut warnings = Vec::new();
+    let output =
+        Renderer::new(template, sample, config, utc_offset(), TitleForm::default()).render(&nodes);
+    if output.trim().is_empty() {
+        warnings.push(issue(
+            TemplateIssueKind::EmptyOutput,
+            "The template renders nothing for the sample book".to_string(),
+            0..0,
+        ));
+    }
+    TemplateReport {
+        valid: errors.is_empty(),
+        errors,
+        warnings,
+    }
+}
+
+/// `template` rendered for the first sample book with `config`, in memory
+///
+/// A blank template previews the built-in layout.
+pub fn preview_template(template: &str, config: &ExportConfig) -> String {
+    let mut config = config.clone();
+    config.format = ExportFormat::Markdown;
+    config.markdown_template = template.to_string();
+    MarkdownExporter::new(PathBuf::new()).generate_markdown(&sample_books()[0], &config)
+}
+
+/// Render `book` with `config.markdown_template`, with highlight dates on
+/// the calendar at `offset` and the title in `title`
+pub fn render_template(
+    book: &Book,
+    config: &ExportConfig,
+    offset: FixedOffset,
+    title: TitleForm,
+) -> String {
+    let template = config.markdown_template.as_str();
+    let (nodes, _) = parse(template);
+    Renderer::new(template, book, config, offset, title).render(&nodes)
+}
+
+struct Renderer<'a> {
+    template: &'a str,
+    book: &'a Book,
+    config: &'a ExportConfig,
+    offset: FixedOffset,
+    title: TitleForm,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(
+        template: &'a str,
+        book: &'a Book,
+        config: &'a ExportConfig,
+        offset: FixedOffset,
+        title: TitleForm,
+    ) -> Self {
+        Self {
+            template,
+            book,
+            config,
+            offset,
+            title,
+        }
+    }
+
+    fn render(&self, nodes: &[Node]) -> String {
+        let mut out = String::new();
+        self.render_into(&mut out, nodes, None);
+        out
+    }
+
+    fn render_into(&self, out: &mut String, nodes: &[Node], highlight: Option<&Highlight>) {
+        for node in nodes {
+            match node {
+                Node::Text(range) => out.push_str(&self.template[range.clone()]),
+                Node::Variable(name) => out.push_str(&self.value(name, highlight)),
+                Node::Section { name, body } if name == HIGHLIGHTS_SECTION => {
+                    if highlight.is_some() {
+                        continue;
+                    }
+                    for highlight in self.book.highlights_by_position() {
+                        self.render_into(out, body, Some(highlight));
+                    }
+                }
+                Node::Section { name, body } => {
+                    if !self.value(name, highlight).is_empty() {
+                        self.render_into(out, body, highlight);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Value of a variable (empty when unknown or unset)
+    fn value(&self, name: &str, highlight: Option<&Highlight>) -> String {
+        let book = self.book;
+        if let Some(highlight) = highlight {
+            let value = match name {
+                "text" => Some(highlight.text.clone()),
+                "note" => Some(highlight.annotation.clone().unwrap_or_default()),
+                "chapter" => Some(highlight.chapter_title.clone().unwrap_or_default()),
+                "location" => Some(location_parts(book, highlight).join(" · ")),
+                "date" => Some(match highlight.created_local(self.offset) {
+                    Some(created) => self.format(created.date()),
+                    None => highlight.date_created.clone(),
+                }),
+                "color" => Some(highlight.color.clone().unwrap_or_default()),
+                "page" => Some(highlight.page.map(|p| p.to_string()).unwrap_or_default()),
+                _ => None,
+            };
+            if let Some(value) = value {
+                return value;
+            }
+        }
+        match name {
+            "title" => book.title_in(self.title),
+            "author" => book.display_author(),
+            "isbn" => book.isbn.clone().unwrap_or_default(),
+            "publisher" => book.publisher.clone().unwrap_or_default(),
+            "language" => book.language.clone().unwrap_or_default(),
+            "series" => book.series.clone().unwrap_or_default(),
+            "series_index" => book
+                .series_index
+                .map(|index| index.to_string())
+                .unwrap_or_default(),
+            "description" => book.description.clone().unwrap_or_default(),
+            "read_date" => book
+                .date_last_read
+                .as_deref()
+                .map(|date| self.date(date))
+                .unwrap_or_default(),
+            "highlight_count" => book.highlights.len().to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// A Kobo timestamp in the configured date format
+    fn date(&self, raw: &str) -> String {
+        parse_kobo_datetime(raw)
+            .map(|date| self.format(date.date()))
+            .unwrap_or_else(|| raw.to_string())
+    }
+
+    fn format(&self, date: NaiveDate) -> String {
+        format_date(
+            &date.format("%Y-%m-%d").to_string(),
+            &self.config.date_format,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(template: &str) -> ExportConfig {
+        let mut config = crate::settings::AppSettings::default().export_config;
+        config.markdown_template = template.to_string();
+        config
+    }
+
+    fn validate(template: &str) -> TemplateReport {
+        validate_template(template, &sample_books()[0], &config(template))
+    }
+
+    #[test]
+    fn test_renders_book_and_highlight_variables() {
+        let mut book = Book::new(
+            "vol1".to_string(),
+            "Memorial do Convento".to_string(),
+            "José Saramago".to_string(),
+        );
+        let mut first = Highlight::new(
+            "bm1".to_string(),
+            "Era uma vez".to_string(),
+            "2024-03-01T21:15:00.000".to_string(),
+        );
+        first.annotation = Some("início".to_string());
+        first.chapter_progress = Some(0.1);
+        let second = Highlight::new(
+            "bm2".to_string(),
+            "Blimunda".to_string(),
+            "2024-03-02T08:00:00.000".to_string(),
+        );
+        book.highlights = vec![first, second];
+
+        let template = "# {{title}} ({{highlight_count}})\n\
+                        {{#highlights}}> {{text}}{{#note}} — {{note}}{{/note}}\n{{/highlights}}";
+        assert_eq!(
+            render_template(&book, &config(template), utc_offset(), TitleForm::Processed),
+            "# Memorial do Convento (2)\n> Era uma vez — início\n> Blimunda\n"
+        );
+        assert!(validate(template).valid);
+    }
+
+    #[test]
+    fn test_blank_template_previews_the_built_in_layout() {
+        let config = config("");
+        let preview = preview_template(" \n", &config);
+        assert_eq!(preview, include_str!("testdata/template_default.md"));
+
+        // Same as the file a real export of the book writes
+        let temp = tempfile::TempDir::new().unwrap();
+        let exported = MarkdownExporter::new(temp.path().to_path_buf())
+            .export_book(&sample_books()[0], &config)
+            .unwrap();
+        assert_eq!(preview, std::fs::read_to_string(exported).unwrap());
+    }
+
+    #[test]
+    fn test_preview_renders_the_sample_book() {
+        let preview = preview_template(
+            "{{title}} by {{author}}\n{{#highlights}}{{#note}}- {{note}} ({{date}})\n{{/note}}{{/highlights}}",
+            &config(""),
+        );
+        assert!(preview.starts_with("Pride and Prejudice by Jane Austen\n- "));
+    }
+
+    #[test]
+    fn test_issue_offsets_are_bytes_in_multibyte_templates() {
+        let template = "Título: {{título}}\n{{#highlights}}«{{text}}» {{autor}}";
+        let report = validate(template);
+        assert!(!report.valid);
+        assert_eq!(report.errors.len(), 3);
+
+        let unknown = &report.errors[0];
+        assert_eq!(unknown.kind, TemplateIssueKind::UnknownPlaceholder);
+        assert_eq!(&template[unknown.start..unknown.end], "{{título}}");
+        assert_eq!(unknown.start, "Título: ".len());
+        let autor = &report.errors[1];
+        assert_eq!(&template[autor.start..autor.end], "{{autor}}");
+        let unclosed = &report.errors[2];
+        assert_eq!(unclosed.kind, TemplateIssueKind::UnclosedSection);
+        assert_eq!(&template[unclosed.start..unclosed.end], "{{#highlights}}");
+    }
+
+    #[test]
+    fn test_unbalanced_sections_and_scopes() {
+        let kinds = |template: &str| -> Vec<TemplateIssueKind> {
+            validate(template).errors.iter().map(|e| e.kind).collect()
+        };
+        assert_eq!(
+            kinds("{{/highlights}}"),
+            vec![TemplateIssueKind::UnexpectedClose]
+        );
+        assert_eq!(
+            kinds("{{#highlights}}{{#note}}{{/highlights}}{{/note}}"),
+            vec![
+                TemplateIssueKind::MismatchedClose,
+                TemplateIssueKind::MismatchedClose
+            ]
+        );
+        assert_eq!(
+            kinds("{{text}} {{#highlights}}{{#highlights}}{{/highlights}}{{/highlights}}"),
+            vec![
+                TemplateIssueKind::UnknownPlaceholder,
+                TemplateIssueKind::UnknownPlaceholder
+            ]
+        );
+        let unterminated = validate("# {{title");
+        assert_eq!(
+            unterminated.errors[0].kind,
+            TemplateIssueKind::UnterminatedTag
+        );
+        assert_eq!(unterminated.errors[0].start, 2);
+        assert_eq!(unterminated.errors[0].end, 9);
+    }
+
+    #[test]
+    fn test_empty_output_is_a_warning() {
+        let report = validate("{{#isbn}}{{isbn}}{{/isbn}}\n");
+        assert!(report.valid);
+        assert_eq!(report.warnings[0].kind, TemplateIssueKind::EmptyOutput);
+        assert!(validate("{{title}}").warnings.is_empty());
+    }
+
+    #[test]
+    fn test_arbitrary_input_never_panics() {
+        // Deterministic pseudo-random templates, biased towards tag syntax
+        let pieces = [
+            "{{",
+            "}}",
+            "{",
+            "}",
+            "#",
+            "/",
+            "highlights",
+            "text",
+            "note",
+            "title",
+            "é",
+            "🦉",
+            "\u{0}",
+            " ",
+            "\n",
+        ];
+        let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+        let sample = &sample_books()[0];
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for round in 0..2000 {
+            let length = next() % 24;
+            let template = if round % 2 == 0 {
+                // Raw bytes, as lossy UTF-8
+                let bytes: Vec<u8> = (0..length * 2).map(|_| next() as u8).collect();
+                String::from_utf8_lossy(&bytes).into_owned()
+            } else {
+                (0..length)
+                    .map(|_| pieces[(next() >> 16) as usize % pieces.len()])
+                    .collect()
+            };
+            let config = config(&template);
+            let report = validate_template(&template, sample, &config);
+            for issue in report.errors.iter().chain(&report.warnings) {
+                assert!(template.is_char_boundary(issue.start));
+                assert!(template.is_char_boundary(issue.end));
+                assert!(issue.start <= issue.end && issue.end <= template.len());
+            }
+            render_template(sample, &config, utc_offset(), TitleForm::Processed);
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! Rate limiting of progress events
+//!
+//! Fast imports and exports (small libraries, warm caches) send a progress
+//! event per book, hundreds within a few hundred milliseconds: the IPC
+//! channel falls behind and the progress bar can still be moving after the
+//! operation finished. `ThrottledSink` sends at most `max_per_second` of the
+//! events it throttles. The first goes out at once; later ones within the
+//! interval replace each other, and the latest is flushed before any other
+//! event (such as "export-finished") and on `close`. With a trailing flush
+//! it also goes out once the interval has passed, so a slow step after a
+//! burst doesn't leave the progress bar behind. Once closed, throttled
+//! events are dropped, so none arrives after its operation's end.
+
+use super::EventSink;
+use crate::scheduler::{Clock, SystemClock};
+use chrono::{DateTime, Utc};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+/// Progress events sent per second by default
+pub const DEFAULT_EVENTS_PER_SECOND: u32 = 10;
+
+#[derive(Debug, Default)]
+struct ThrottleState {
+    last_sent: Option<DateTime<Utc>>,
+    /// Latest unsent event of each throttled kind
+    pending: Vec<(String, serde_json::Value)>,
+    closed: bool,
+}
+
+impl ThrottleState {
+    /// Send the pending events to `inner`
+    fn send_pending<S: EventSink + ?Sized>(&mut self, inner: &S, now: DateTime<Utc>) {
+        if self.pending.is_empty() {
+            return;
+        }
+        for (event, payload) in std::mem::take(&mut self.pending) {
+            inner.send(&event, payload);
+        }
+        self.last_sent = Some(now);
+    }
+}
+
+/// State shared with the trailing flush thread, which waits on `changed`
+#[derive(Default)]
+struct Shared {
+    state: Mutex<ThrottleState>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, ThrottleState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Forwards events to `inner`, coalescing the `throttled` ones
+pub struct ThrottledSink<'a, S: EventSink + ?Sized, C: Clock = SystemClock> {
+    inner: &'a S,
+    throttled: &'a [&'a str],
+    interval: chrono::Duration,
+    clock: C,
+    shared: Arc<Shared>,
+    trailing: Option<JoinHandle<()>>,
+}
+
+impl<'a, S: EventSink + ?Sized> ThrottledSink<'a, S> {
+    pub fn new(inner: &'a S, throttled: &'a [&'a str]) -> Self {
+        Self::with_clock(inner, throttled, SystemClock)
+    }
+}
+
+impl<'a, S: EventSink + ?Sized, C: Clock> ThrottledSink<'a, S, C> {
+    pub fn with_clock(inner: &'a S, throttled: &'a [&'a str], clock: C) -> Self {
+        Self {
+            inner,
+            throttled,
+            interval: interval(DEFAULT_EVENTS_PER_SECOND),
+            clock,
+            shared: Arc::default(),
+            trailing: None,
+        }
+    }
+
+    pub fn with_max_per_second(mut self, max_per_second: u32) -> Self {
+        self.interval = interval(max_per_second);
+        self
+    }
+
+    /// Send the pending throttled event, if any
+    pub fn flush(&self) {
+        let mut state = self.shared.lock();
+        self.flush_locked(&mut state);
+    }
+
+    /// Flush, then drop every throttled event sent from now on
+    pub fn close(&self) {
+        let mut state = self.shared.lock();
+        self.flush_locked(&mut state);
+        state.closed = true;
+        self.shared.changed.notify_all();
+    }
+
+    fn flush_locked(&self, state: &mut ThrottleState) {
+        state.send_pending(self.inner, self.clock.now());
+    }
+}
+
+impl<'a, S, C> ThrottledSink<'a, S, C>
+where
+    S: EventSink + Clone + Send + 'static,
+    C: Clock + Clone + 'static,
+{
+    /// Also send a pending event once the interval has passed, from a
+    /// thread of its own, when no later event comes to carry it
+    pub fn with_trailing_flush(mut self) -> Self {
+        let (inner, clock) = (self.inner.clone(), self.clock.clone());
+        let (shared, interval) = (Arc::clone(&self.shared), self.interval);
+        self.trailing = Some(std::thread::spawn(move || {
+            let mut state = shared.lock();
+            while !state.closed {
+                let due = state.last_sent.map(|last| last + interval);
+                let wait = due.and_then(|due| (due - clock.now()).to_std().ok());
+                state = match wait {
+                    _ if state.pending.is_empty() => shared
+                        .changed
+                        .wait(state)
+                        .unwrap_or_else(|e| e.into_inner()),
+                    Some(wait) if !wait.is_zero() => {
+                        shared
+                            .changed
+                            .wait_timeout(state, wait)
+                            .unwrap_or_else(|e| e.into_inner())
+                            .0
+                    }
+                    _ => {
+                        state.send_pending(&inner, clock.now());
+                        state
+                    }
+                };
+            }
+        }));
+        self
+    }
+}
+
+impl<S: EventSink + ?Sized, C: Clock> EventSink for ThrottledSink<'_, S, C> {
+    fn send(&self, event: &str, payload: serde_json::Value) {
+        // The lock is held while sending, so events keep their order
+        let mut state = self.shared.lock();
+        if !self.throttled.contains(&event) {
+            self.flush_locked(&mut state);
+            self.inner.send(event, payload);
+            return;
+        }
+        if state.closed {
+            log::debug!("[Events] Dropped {} sent after its operation ended", event);
+            return;
+        }
+        let now = self.clock.now();
+        if state
+            .last_sent
+            .is_some_and(|last| now - last < self.interval)
+        {
+            match state
+                .pending
+                .iter_mut()
+                .find(|(pending, _)| pending == event)
+            {
+                Some(pending) => pending.1 = payload,
+                None => state.pending.push((event.to_string(), payload)),
+            }
+            self.shared.changed.notify_all();
+            return;
+        }
+        // This event supersedes a pending one of its kind; others go first
+        state.pending.retain(|(pending, _)| pending != event);
+        self.flush_locked(&mut state);
+        self.inner.send(event, payload);
+        state.last_sent = Some(now);
+    }
+}
+
+impl<S: EventSink + ?Sized, C: Clock> Drop for ThrottledSink<'_, S, C> {
+    fn drop(&mut self) {
+        self.close();
+        if let Some(trailing) = self.trailing.take() {
+            let _ = trailing.join();
+        }
+    }
+}
+
+fn interval(max_per_second: u32) -> chrono::Duration {
+    chrono::Duration::milliseconds(1000 / i64::from(max_per_second.max(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::time::{Duration, Instant};
+
+    #[derive(Default)]
+    struct Recorder {
+        events: RefCell<Vec<(String, serde_json::Value)>>,
+    }
+
+    impl EventSink for Recorder {
+        fn send(&self, event: &str, payload: serde_json::Value) {
+            self.events.borrow_mut().push((event.to_string(), payload));
+        }
+    }
+
+    /// `Recorder` the trailing flush thread can hold
+    #[derive(Clone, Default)]
+    struct SharedRecorder(Arc<Mutex<Vec<serde_json::Value>>>);
+
+    impl EventSink for SharedRecorder {
+        fn send(&self, _event: &str, payload: serde_json::Value) {
+            self.0.lock().unwrap().push(payload);
+        }
+    }
+
+    #[derive(Clone)]
+    struct FakeClock(Arc<Mutex<DateTime<Utc>>>);
+
+    impl FakeClock {
+        fn new() -> Self {
+            Self(Arc::new(Mutex::new(
+                DateTime::parse_from_rfc3339("2025-01-24T10:00:00Z")
+                    .unwrap()
+                    .with_timezone(&Utc),
+            )))
+        }
+
+        fn advance_ms(&self, ms: i64) {
+            *self.0.lock().unwrap() += chrono::Duration::milliseconds(ms);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_rapid_progress_is_coalesced() {
+        let recorder = Recorder::default();
+        let clock = FakeClock::new();
+        let sink = ThrottledSink::with_clock(&recorder, &["progress"], clock.clone());
+
+        sink.send("started", serde_json::json!({ "total": 500 }));
+        // 500 events over one second
+        for index in 0..500 {
+            let percentage = (index + 1) as f64 / 500.0 * 100.0;
+            sink.send(
+                "progress",
+                serde_json::json!({ "index": index, "percentage": percentage }),
+            );
+            clock.advance_ms(2);
+        }
+        sink.close();
+        sink.send("finished", serde_json::json!({}));
+        sink.send("progress", serde_json::json!({ "index": 500 }));
+        drop(sink);
+
+        let events = recorder.events.borrow();
+        let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names.first(), Some(&"started"));
+        assert_eq!(names.last(), Some(&"finished"));
+        let progress: Vec<&serde_json::Value> = events
+            .iter()
+            .filter(|(name, _)| name == "progress")
+            .map(|(_, payload)| payload)
+            .collect();
+        // One every 100 ms, plus the last one flushed by `close`
+        assert_eq!(progress.len(), 11);
+        assert_eq!(progress[0]["index"], 0);
+        assert_eq!(progress[10]["index"], 499);
+        assert_eq!(progress[10]["percentage"], 100.0);
+        assert!(progress
+            .windows(2)
+            .all(|w| w[0]["percentage"].as_f64() < w[1]["percentage"].as_f64()));
+    }
+
+    #[test]
+    fn test_other_events_flush_pending_progress_first() {
+        let recorder = Recorder::default();
+        let clock = FakeClock::new();
+        let sink = ThrottledSink::with_clock(&recorder, &["progress"], clock.clone())
+            .with_max_per_second(1);
+
+        sink.send("progress", serde_json::json!(1));
+        clock.advance_ms(10);
+        sink.send("progress", serde_json::json!(2));
+        sink.send("progress", serde_json::json!(3));
+        sink.send("finished", serde_json::json!("done"));
+        clock.advance_ms(2000);
+        sink.flush();
+        drop(sink);
+
+        let events = recorder.events.borrow();
+        assert_eq!(
+            *events,
+            vec![
+                ("progress".to_string(), serde_json::json!(1)),
+                ("progress".to_string(), serde_json::json!(3)),
+                ("finished".to_string(), serde_json::json!("done")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pending_progress_goes_out_once_the_interval_passed() {
+        let recorder = SharedRecorder::default();
+        let clock = FakeClock::new();
+        let sink = ThrottledSink::with_clock(&recorder, &["progress"], clock.clone())
+            .with_trailing_flush();
+
+        sink.send("progress", serde_json::json!(1));
+        clock.advance_ms(10);
+        sink.send("progress", serde_json::json!(2));
+        // A slow step: nothing else is sent for a while
+        clock.advance_ms(200);
+        let started = Instant::now();
+        while recorder.0.lock().unwrap().len() < 2 && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![serde_json::json!(1), serde_json::json!(2)]
+        );
+
+        drop(sink);
+        assert_eq!(recorder.0.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,423 @@
+//! Checking `.khi-manifest.json` against the export folder
+//!
+//! The manifest decides what append-mode re-exports leave alone, so it goes
+//! wrong quietly when files are deleted or edited by hand, or an old backup
+//! of the folder is restored. `verify_manifest` compares every entry with
+//! the file on disk (hashed in chunks, whatever its size) and lists markdown
+//! files with khi anchors that the manifest doesn't know; `repair_manifest`
+//! brings the two back in line.
+
+use super::append::{self, anchors_in, ExportManifest};
+use crate::utils::cancel::CancellationToken;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Hashing reads files this many bytes at a time
+const HASH_CHUNK_BYTES: usize = 64 * 1024;
+
+/// State of one exported file
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryStatus {
+    /// On disk as khi last wrote it
+    Ok,
+    /// In the manifest, gone from disk
+    Missing,
+    /// Changed since khi last wrote it
+    Modified,
+    /// Has khi anchors but isn't in the manifest
+    Untracked,
+}
+
+/// One file of the report, by path relative to the export folder
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntryReport {
+    pub path: String,
+    pub status: EntryStatus,
+    /// The next export rewrites the file in full
+    pub reexport: bool,
+}
+
+/// Every manifest entry and untracked file, sorted by path
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestReport {
+    pub entries: Vec<ManifestEntryReport>,
+}
+
+impl ManifestReport {
+    /// Whether manifest and disk agree
+    pub fn is_clean(&self) -> bool {
+        self.entries.iter().all(|e| e.status == EntryStatus::Ok)
+    }
+
+    fn paths_with(&self, status: EntryStatus) -> impl Iterator<Item = &str> {
+        self.entries
+            .iter()
+            .filter(move |e| e.status == status)
+            .map(|e| e.path.as_str())
+    }
+}
+
+/// How `repair_manifest` settles disagreements
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RepairStrategy {
+    /// Adopt the files as they are: re-hash modified and untracked files,
+    /// drop entries of missing ones
+    TrustDisk,
+    /// Keep the manifest and rewrite modified files on the next export
+    TrustManifest,
+    /// Only drop entries of missing files
+    Prune,
+}
+
+/// SHA-256 of a file's contents, read in chunks
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; HASH_CHUNK_BYTES];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Compare the manifest of `export_dir` with the files on disk
+///
+/// Entries written before hashes were recorded count as `Ok` while their
+/// file exists. `cancel` is checked before each file; a stopped check fails
+/// with an `Interrupted` error wrapping `Cancelled`.
+pub fn verify_manifest(
+    export_dir: &Path,
+    cancel: &CancellationToken,
+) -> io::Result<ManifestReport> {
+    let manifest = append::load_manifest(export_dir);
+    verify_against(export_dir, &manifest, cancel)
+}
+
+fn verify_against(
+    export_dir: &Path,
+    manifest: &ExportManifest,
+    cancel: &CancellationToken,
+) -> io::Result<ManifestReport> {
+    let mut entries = Vec::new();
+    let total = manifest.files.len();
+    for (index, key) in manifest.files.keys().enumerate() {
+        cancel.check().map_err(|c| c.with_progress(index, total))?;
+        let path = export_dir.join(key);
+        let status = if !path.is_file() {
+            EntryStatus::Missing
+        } else {
+            match manifest.hashes.get(key) {
+                Some(hash) if *hash != hash_file(&path)? => EntryStatus::Modified,
+                _ => EntryStatus::Ok,
+            }
+        };
+        entries.push(ManifestEntryReport {
+            path: key.clone(),
+            status,
+            reexport: manifest.stale.contains(key),
+        });
+    }
+
+    for path in markdown_files(export_dir)? {
+        cancel.check()?;
+        let key = append::manifest_key(export_dir, &path);
+        if !manifest.files.contains_key(&key) && !file_anchors(&path)?.is_empty() {
+            entries.push(ManifestEntryReport {
+                path: key,
+                status: EntryStatus::Untracked,
+                reexport: false,
+            });
+        }
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(ManifestReport { entries })
+}
+
+/// Settle the disagreements `verify_manifest` finds with `strategy`, save
+/// the manifest and return the report of the result
+///
+/// A repair stopped by `cancel` leaves the manifest untouched.
+pub fn repair_manifest(
+    export_dir: &Path,
+    strategy: RepairStrategy,
+    cancel: &CancellationToken,
+) -> io::Result<ManifestReport> {
+    let mut manifest = append::load_manifest(export_dir);
+    let report = verify_against(export_dir, &manifest, cancel)?;
+
+    let missing: Vec<String> = report
+        .paths_with(EntryStatus::Missing)
+        .map(str::to_string)
+        .collect();
+    if strategy != RepairStrategy::TrustManifest {
+        for key in &missing {
+            manifest.files.remove(key);
+            manifest.hashes.remove(key);
+            manifest.stale.remove(key);
+            manifest.books.remove(key);
+            manifest.adopted.retain(|_, adopted| adopted != key);
+        }
+    }
+
+    match strategy {
+        RepairStrategy::TrustDisk => {
+            for entry in &report.entries {
+                let path = export_dir.join(&entry.path);
+                match entry.status {
+                    EntryStatus::Missing => continue,
+                    EntryStatus::Untracked => {
+                        let mut keys: Vec<String> = file_anchors(&path)?.into_iter().collect();
+                        keys.sort();
+                        manifest.files.insert(entry.path.clone(), keys);
+                    }
+                    EntryStatus::Ok | EntryStatus::Modified => {}
+                }
+                manifest
+                    .hashes
+                    .insert(entry.path.clone(), hash_file(&path)?);
+                manifest.stale.remove(&entry.path);
+            }
+        }
+        RepairStrategy::TrustManifest => {
+            manifest
+                .stale
+                .extend(report.paths_with(EntryStatus::Modified).map(str::to_string));
+        }
+        RepairStrategy::Prune => {}
+    }
+
+    cancel.check()?;
+    append::save_manifest(export_dir, &manifest)?;
+    log::info!(
+        "[EXPORTER] Manifesto reparado ({:?}): {} entrada(s)",
+        strategy,
+        manifest.files.len()
+    );
+    verify_against(export_dir, &manifest, &CancellationToken::new())
+}
+
+/// Markdown files under `export_dir`, skipping hidden folders (snapshots,
+/// git)
+fn markdown_files(export_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    files_with_extensions(export_dir, &["md"])
+}
+
+/// Files under `export_dir` with one of `extensions`, skipping hidden files
+/// and folders
+pub(super) fn files_with_extensions(
+    export_dir: &Path,
+    extensions: &[&str],
+) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![export_dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            let name = entry.file_name();
+            if name.to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file()
+                && path
+                    .extension()
+                    .is_some_and(|ext| extensions.iter().any(|e| ext == *e))
+            {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Keys of the khi anchors in `path`, read a line at a time
+fn file_anchors(path: &Path) -> io::Result<HashSet<String>> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let mut anchors = HashSet::new();
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        anchors.extend(anchors_in(&String::from_utf8_lossy(&line)));
+        line.clear();
+    }
+    Ok(anchors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::MarkdownExporter;
+    use crate::models::{Book, ExportWriteMode, Highlight};
+    use tempfile::TempDir;
+
+    fn book(content_id: &str, title: &str) -> Book {
+        let mut book = Book::new(
+            content_id.to_string(),
+            title.to_string(),
+            "Autora".to_string(),
+        );
+        book.highlights.push(Highlight::new(
+            format!("{}-hl", content_id),
+            format!("Destaque de {}", title),
+            "2025-01-24".to_string(),
+        ));
+        book
+    }
+
+    /// Export three books in append mode, edit the first, delete the second
+    /// and drop an untracked anchored file next to them
+    fn drifted_export() -> (TempDir, Vec<String>) {
+        let temp = TempDir::new().unwrap();
+        let mut config = crate::settings::AppSettings::default().export_config;
+        config.export_path = temp.path().to_string_lossy().to_string();
+        config.write_mode = ExportWriteMode::Append;
+        let exporter = MarkdownExporter::new(temp.path().to_path_buf());
+        let books = vec![book("a", "Alfa"), book("b", "Beta"), book("c", "Gama")];
+        let keys: Vec<String> = exporter
+            .export_books(&books, &config)
+            .into_iter()
+            .map(|path| append::manifest_key(temp.path(), &path.unwrap()))
+            .collect();
+        assert!(verify_manifest(temp.path(), &CancellationToken::new())
+            .unwrap()
+            .is_clean());
+
+        let edited = temp.path().join(&keys[0]);
+        let text = fs::read_to_string(&edited).unwrap();
+        fs::write(&edited, format!("{}\nMinha nota\n", text)).unwrap();
+        fs::remove_file(temp.path().join(&keys[1])).unwrap();
+        fs::create_dir(temp.path().join("Antigos")).unwrap();
+        fs::write(
+            temp.path().join("Antigos/Restaurado.md"),
+            "> Velho <!-- khi:old1 -->\n",
+        )
+        .unwrap();
+        fs::write(temp.path().join("Notas.md"), "no anchors here\n").unwrap();
+        (temp, keys)
+    }
+
+    fn statuses(report: &ManifestReport) -> Vec<(&str, EntryStatus, bool)> {
+        report
+            .entries
+            .iter()
+            .map(|e| (e.path.as_str(), e.status, e.reexport))
+            .collect()
+    }
+
+    #[test]
+    fn test_verify_reports_drift() {
+        let (temp, keys) = drifted_export();
+        let report = verify_manifest(temp.path(), &CancellationToken::new()).unwrap();
+        assert!(!report.is_clean());
+        let mut expected = vec![
+            (keys[0].as_str(), EntryStatus::Modified, false),
+            (keys[1].as_str(), EntryStatus::Missing, false),
+            (keys[2].as_str(), EntryStatus::Ok, false),
+            ("Antigos/Restaurado.md", EntryStatus::Untracked, false),
+        ];
+        expected.sort_by(|a, b| a.0.cmp(b.0));
+        assert_eq!(statuses(&report), expected);
+    }
+
+    #[test]
+    fn test_repair_trust_disk_adopts_files() {
+        let (temp, keys) = drifted_export();
+        let report = repair_manifest(
+            temp.path(),
+            RepairStrategy::TrustDisk,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        assert!(report.is_clean());
+        assert!(!report.entries.iter().any(|e| e.path == keys[1]));
+
+        let manifest = append::load_manifest(temp.path());
+        assert_eq!(manifest.files["Antigos/Restaurado.md"], vec!["old1"]);
+        assert_eq!(
+            manifest.hashes[&keys[0]],
+            hash_file(&temp.path().join(&keys[0])).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_repair_trust_manifest_marks_for_reexport() {
+        let (temp, keys) = drifted_export();
+        let report = repair_manifest(
+            temp.path(),
+            RepairStrategy::TrustManifest,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        let modified = report.entries.iter().find(|e| e.path == keys[0]).unwrap();
+        assert_eq!(modified.status, EntryStatus::Modified);
+        assert!(modified.reexport);
+        assert!(append::load_manifest(temp.path())
+            .files
+            .contains_key(&keys[1]));
+
+        // The next export rewrites the edited file instead of appending
+        let mut config = crate::settings::AppSettings::default().export_config;
+        config.export_path = temp.path().to_string_lossy().to_string();
+        config.write_mode = ExportWriteMode::Append;
+        let exported = MarkdownExporter::new(temp.path().to_path_buf())
+            .export_books(&[book("a", "Alfa")], &config);
+        assert!(exported[0].is_ok());
+        assert!(!fs::read_to_string(temp.path().join(&keys[0]))
+            .unwrap()
+            .contains("Minha nota"));
+        let report = verify_manifest(temp.path(), &CancellationToken::new()).unwrap();
+        let rewritten = report.entries.iter().find(|e| e.path == keys[0]).unwrap();
+        assert_eq!(rewritten.status, EntryStatus::Ok);
+        assert!(!rewritten.reexport);
+    }
+
+    #[test]
+    fn test_repair_prune_drops_missing_only() {
+        let (temp, keys) = drifted_export();
+        let report = repair_manifest(
+            temp.path(),
+            RepairStrategy::Prune,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        let mut expected = vec![
+            (keys[0].as_str(), EntryStatus::Modified, false),
+            (keys[2].as_str(), EntryStatus::Ok, false),
+            ("Antigos/Restaurado.md", EntryStatus::Untracked, false),
+        ];
+        expected.sort_by(|a, b| a.0.cmp(b.0));
+        assert_eq!(statuses(&report), expected);
+    }
+
+    #[test]
+    fn test_hash_file_streams_large_files() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("big.md");
+        let bytes = vec![b'x'; HASH_CHUNK_BYTES * 3 + 17];
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(
+            hash_file(&path).unwrap(),
+            format!("{:x}", Sha256::digest(&bytes))
+        );
+    }
+}
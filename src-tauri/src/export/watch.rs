@@ -0,0 +1,417 @@
+//! Watching the export folder for edits made outside khi
+//!
+//! With `AppSettings::watch_export_dir` on, changes to exported files (say,
+//! a note edited in Obsidian) emit "export-files-changed" with the slugs of
+//! the affected books, so the frontend can refresh diffs and previews. Files
+//! map back to books through the export manifest, which every write mode
+//! records; other changes are ignored.
+//!
+//! Bursts of filesystem events are debounced by `ChangeBatcher`. Files khi
+//! wrote itself are ignored for `SELF_WRITE_GRACE` (see `RecentWrites`), and
+//! events arriving while an export holds the `OperationLock` are dropped.
+//! Exports of other processes (headless syncs) are recognized by the hashes
+//! the manifest keeps of each file.
+
+use super::append::{self, manifest_key, ExportManifest};
+use super::verify::hash_file;
+use crate::scheduler::OperationLock;
+use crate::settings::AppSettings;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+
+/// Event emitted when exported files change outside khi
+pub const EXPORT_FILES_CHANGED: &str = "export-files-changed";
+
+/// Quiet time after the last filesystem event before changes are reported
+pub const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// How long changes to a file khi wrote are taken as its own
+pub const SELF_WRITE_GRACE: Duration = Duration::from_secs(5);
+
+/// Payload of "export-files-changed"
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportFilesChangedEvent {
+    /// Slugs of the books whose files changed, sorted
+    pub slugs: Vec<String>,
+}
+
+/// Files khi wrote lately, shared by exports and the watcher
+#[derive(Debug, Clone, Default)]
+pub struct RecentWrites {
+    written: Arc<Mutex<HashMap<PathBuf, Instant>>>,
+}
+
+impl RecentWrites {
+    /// Remember `paths` as written at `at`
+    pub fn record<I: IntoIterator<Item = PathBuf>>(&self, paths: I, at: Instant) {
+        let mut written = self.written.lock().unwrap_or_else(|e| e.into_inner());
+        written.retain(|_, &mut when| !expired(when, at));
+        for path in paths {
+            written.insert(canonical(&path), at);
+        }
+    }
+
+    /// Whether khi wrote `path` less than `SELF_WRITE_GRACE` before `now`
+    pub fn contains(&self, path: &Path, now: Instant) -> bool {
+        let written = self.written.lock().unwrap_or_else(|e| e.into_inner());
+        written
+            .get(&canonical(path))
+            .is_some_and(|&when| !expired(when, now))
+    }
+}
+
+fn expired(written_at: Instant, now: Instant) -> bool {
+    now.saturating_duration_since(written_at) >= SELF_WRITE_GRACE
+}
+
+/// `path` with symlinks resolved when it still exists, so paths built from
+/// the configured folder match the ones the OS reports
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Changed paths collected until events stop for `debounce`
+#[derive(Debug, Clone)]
+pub struct ChangeBatcher {
+    debounce: Duration,
+    pending: BTreeSet<PathBuf>,
+    last_event: Option<Instant>,
+}
+
+impl ChangeBatcher {
+    pub fn new(debounce: Duration) -> Self {
+        Self {
+            debounce,
+            pending: BTreeSet::new(),
+            last_event: None,
+        }
+    }
+
+    pub fn push(&mut self, path: PathBuf, at: Instant) {
+        self.pending.insert(path);
+        self.last_event = Some(at);
+    }
+
+    /// Drop everything pending (while an export runs)
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.last_event = None;
+    }
+
+    /// When the pending changes are due, if there are any
+    pub fn deadline(&self) -> Option<Instant> {
+        self.last_event.map(|at| at + self.debounce)
+    }
+
+    /// The pending paths khi didn't write itself, once no event came for
+    /// `debounce`; empty before that
+    pub fn take_ready(&mut self, now: Instant, recent: &RecentWrites) -> Vec<PathBuf> {
+        if self.deadline().is_none_or(|deadline| now < deadline) {
+            return Vec::new();
+        }
+        self.last_event = None;
+        std::mem::take(&mut self.pending)
+            .into_iter()
+            .filter(|path| !recent.contains(path, now))
+            .collect()
+    }
+}
+
+/// Slugs of the books written to `paths`, by the manifest of `export_dir`
+///
+/// Files whose contents still hash to what the manifest recorded are khi's
+/// own writes (say, by a headless sync in another process) and are skipped.
+pub fn affected_books(
+    export_dir: &Path,
+    manifest: &ExportManifest,
+    paths: &[PathBuf],
+) -> Vec<String> {
+    paths
+        .iter()
+        .filter_map(|path| {
+            let key = manifest_key(export_dir, path);
+            let written = manifest
+                .hashes
+                .get(&key)
+                .is_some_and(|hash| hash_file(path).is_ok_and(|actual| &actual == hash));
+            (!written).then(|| manifest.books.get(&key)).flatten()
+        })
+        .cloned()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Folder to watch for `settings`: the export path, when watching is on
+pub fn watch_target(settings: &AppSettings) -> Option<PathBuf> {
+    let path = settings.export_config.export_path.trim();
+    (settings.watch_export_dir && !path.is_empty()).then(|| PathBuf::from(path))
+}
+
+/// The running watcher, managed by the app
+#[derive(Clone, Default)]
+pub struct ExportWatchState {
+    active: Arc<Mutex<Option<ActiveWatch>>>,
+    recent: RecentWrites,
+}
+
+struct ActiveWatch {
+    dir: PathBuf,
+    // Dropping the watcher ends its thread
+    _watcher: RecommendedWatcher,
+}
+
+impl ExportWatchState {
+    pub fn recent_writes(&self) -> &RecentWrites {
+        &self.recent
+    }
+
+    /// Start, restart or stop watching to match `settings`
+    pub fn sync(&self, app: &AppHandle, settings: &AppSettings) {
+        let target = watch_target(settings);
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        if active.as_ref().map(|a| &a.dir) == target.as_ref() {
+            return;
+        }
+        if let Some(previous) = active.take() {
+            log::info!("[WATCHER] A parar de observar {:?}", previous.dir);
+        }
+        let Some(dir) = target else {
+            return;
+        };
+        match start(app, &dir, self.recent.clone()) {
+            Ok(watcher) => {
+                log::info!("[WATCHER] A observar {:?}", dir);
+                *active = Some(ActiveWatch {
+                    dir,
+                    _watcher: watcher,
+                });
+            }
+            Err(e) => log::warn!("[WATCHER] Não foi possível observar {:?}: {}", dir, e),
+        }
+    }
+}
+
+fn start(app: &AppHandle, dir: &Path, recent: RecentWrites) -> notify::Result<RecommendedWatcher> {
+    let root = canonical(dir);
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(&root, RecursiveMode::Recursive)?;
+
+    let operations = app
+        .try_state::<OperationLock>()
+        .map(|state| state.inner().clone())
+        .unwrap_or_default();
+    let app = app.clone();
+    thread::spawn(move || watch_loop(&app, &root, events, &recent, &operations));
+    Ok(watcher)
+}
+
+/// Idle wait between checks when nothing is pending
+const IDLE_WAIT: Duration = Duration::from_secs(60);
+
+fn watch_loop(
+    app: &AppHandle,
+    root: &Path,
+    events: Receiver<notify::Result<Event>>,
+    recent: &RecentWrites,
+    operations: &OperationLock,
+) {
+    let mut batcher = ChangeBatcher::new(DEBOUNCE);
+    loop {
+        let wait = batcher.deadline().map_or(IDLE_WAIT, |deadline| {
+            deadline.saturating_duration_since(Instant::now())
+        });
+        match events.recv_timeout(wait) {
+            Ok(Ok(event)) if is_change(&event.kind) => {
+                let now = Instant::now();
+                for path in event.paths {
+                    batcher.push(path, now);
+                }
+            }
+            Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+            Ok(Err(e)) => log::warn!("[WATCHER] Erro ao observar {:?}: {}", root, e),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if operations.is_busy() {
+            batcher.clear();
+            continue;
+        }
+        let changed = batcher.take_ready(Instant::now(), recent);
+        if changed.is_empty() {
+            continue;
+        }
+        let slugs = affected_books(root, &append::load_manifest(root), &changed);
+        if slugs.is_empty() {
+            continue;
+        }
+        log::info!("[WATCHER] {} livro(s) alterado(s) fora do khi", slugs.len());
+        if let Err(e) = app.emit(EXPORT_FILES_CHANGED, ExportFilesChangedEvent { slugs }) {
+            log::error!(
+                "[WATCHER] Failed to emit {} event: {}",
+                EXPORT_FILES_CHANGED,
+                e
+            );
+        }
+    }
+}
+
+/// Whether the event may have changed a file's contents (reads don't)
+fn is_change(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) | EventKind::Any
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn manifest(root: &Path, files: &[(&str, &str)]) -> ExportManifest {
+        let mut manifest = ExportManifest::default();
+        for (file, slug) in files {
+            let key = manifest_key(root, &root.join(file));
+            manifest.books.insert(key, slug.to_string());
+        }
+        manifest
+    }
+
+    #[test]
+    fn test_bursts_are_debounced_and_mapped_to_books() {
+        let root = Path::new("/notes");
+        let manifest = manifest(
+            root,
+            &[
+                ("Walden.md", "walden-3f2a9c"),
+                ("EN/Dune.md", "dune-7b1e04"),
+            ],
+        );
+        let recent = RecentWrites::default();
+        let mut batcher = ChangeBatcher::new(DEBOUNCE);
+        let t0 = Instant::now();
+        let ms = Duration::from_millis;
+
+        // An editor saving twice, then touching a file khi doesn't know
+        batcher.push(root.join("Walden.md"), t0);
+        batcher.push(root.join("Walden.md"), t0 + ms(100));
+        assert!(batcher.take_ready(t0 + ms(400), &recent).is_empty());
+        batcher.push(root.join("EN/Dune.md"), t0 + ms(450));
+        batcher.push(root.join(".obsidian/workspace.json"), t0 + ms(460));
+        assert!(batcher.take_ready(t0 + ms(900), &recent).is_empty());
+
+        let changed = batcher.take_ready(t0 + ms(960), &recent);
+        assert_eq!(changed.len(), 3);
+        assert_eq!(
+            affected_books(root, &manifest, &changed),
+            vec!["dune-7b1e04", "walden-3f2a9c"]
+        );
+        // Reported once
+        assert!(batcher.take_ready(t0 + ms(2000), &recent).is_empty());
+        assert_eq!(batcher.deadline(), None);
+    }
+
+    #[test]
+    fn test_own_writes_are_ignored_within_grace_period() {
+        let temp = TempDir::new().unwrap();
+        let file = temp.path().join("Walden.md");
+        fs::write(&file, "# Walden\n").unwrap();
+        let recent = RecentWrites::default();
+        let mut batcher = ChangeBatcher::new(DEBOUNCE);
+        let t0 = Instant::now();
+
+        // The export recorded the file; the event of its write follows
+        recent.record([file.clone()], t0);
+        batcher.push(file.clone(), t0 + Duration::from_millis(50));
+        assert!(batcher
+            .take_ready(t0 + Duration::from_secs(1), &recent)
+            .is_empty());
+
+        // Recorded after the event arrived: still khi's own write
+        batcher.push(file.clone(), t0 + Duration::from_secs(2));
+        recent.record([file.clone()], t0 + Duration::from_secs(2));
+        assert!(batcher
+            .take_ready(t0 + Duration::from_secs(3), &recent)
+            .is_empty());
+
+        // Past the grace period an edit is the user's
+        let later = t0 + Duration::from_secs(2) + SELF_WRITE_GRACE;
+        batcher.push(file.clone(), later);
+        assert_eq!(batcher.take_ready(later + DEBOUNCE, &recent), vec![file]);
+    }
+
+    #[test]
+    fn test_pausing_drops_pending_changes() {
+        let mut batcher = ChangeBatcher::new(DEBOUNCE);
+        let t0 = Instant::now();
+        batcher.push(PathBuf::from("/notes/Walden.md"), t0);
+        batcher.clear();
+        assert!(batcher
+            .take_ready(t0 + Duration::from_secs(1), &RecentWrites::default())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_watch_target_follows_settings() {
+        let mut settings = AppSettings::default();
+        settings.export_config.export_path = "/Users/reader/Notes".to_string();
+        assert_eq!(watch_target(&settings), None);
+        settings.watch_export_dir = true;
+        assert_eq!(
+            watch_target(&settings),
+            Some(PathBuf::from("/Users/reader/Notes"))
+        );
+        settings.export_config.export_path = "  ".to_string();
+        assert_eq!(watch_target(&settings), None);
+    }
+
+    #[test]
+    fn test_exports_record_book_slugs_in_every_write_mode() {
+        for write_mode in [
+            crate::models::ExportWriteMode::Overwrite,
+            crate::models::ExportWriteMode::Append,
+        ] {
+            let temp = TempDir::new().unwrap();
+            let exporter = crate::export::MarkdownExporter::new(temp.path().to_path_buf());
+            let mut config = AppSettings::default().export_config;
+            config.write_mode = write_mode;
+            let mut book = crate::models::Book::new(
+                "vol1".to_string(),
+                "Walden".to_string(),
+                "Henry David Thoreau".to_string(),
+            );
+            book.slug = "walden-3f2a9c".to_string();
+            book.highlights.push(crate::models::Highlight::new(
+                "hl1".to_string(),
+                "Simplify, simplify.".to_string(),
+                "2025-01-24".to_string(),
+            ));
+            let path = exporter.export_book(&book, &config).unwrap();
+
+            // As khi wrote it (from this process or another): not an edit
+            let manifest = append::load_manifest(temp.path());
+            assert!(affected_books(temp.path(), &manifest, std::slice::from_ref(&path)).is_empty());
+
+            let mut text = fs::read_to_string(&path).unwrap();
+            text.push_str("\nMy own note.\n");
+            fs::write(&path, text).unwrap();
+            assert_eq!(
+                affected_books(temp.path(), &manifest, &[path]),
+                vec!["walden-3f2a9c"],
+                "{:?}",
+                write_mode
+            );
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Synthetic Kobo devices and EPUBs
+//!
+//! Compiled into the app for the pipeline self-test and shared with the unit
+//! tests, so both exercise the same minimal Kobo schema.
+
+use rusqlite::Connection;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Minimal Kobo schema accepted by `check_kobo_schema`
+pub const KOBO_SCHEMA: &str = "CREATE TABLE Bookmark (
+        BookmarkID TEXT PRIMARY KEY, ContentID TEXT, VolumeID TEXT, Text TEXT,
+        Annotation TEXT, StartContainerPath TEXT, ChapterProgress REAL,
+        DateCreated TEXT, Color TEXT
+    );
+    CREATE TABLE content (
+        ContentID TEXT PRIMARY KEY, BookTitle TEXT, Title TEXT, Attribution TEXT,
+        ISBN TEXT, Publisher TEXT, Language TEXT, DateLastRead TEXT,
+        ContentType INTEGER
+    );";
+
+/// Two books with two highlights each, one of them annotated
+pub const TWO_BOOKS_DATA: &str = "
+    INSERT INTO content VALUES ('vol1', NULL, 'Memorial do Convento',
+        'José Saramago', NULL, NULL, 'pt', '2025-01-24', 6);
+    INSERT INTO content VALUES ('vol2', NULL, 'Walden', 'Henry David Thoreau',
+        NULL, NULL, 'en', '2025-02-02', 6);
+    INSERT INTO Bookmark VALUES ('hl1', 'vol1', 'vol1', 'Primeiro', NULL, NULL,
+        0.1, '2025-01-24', NULL);
+    INSERT INTO Bookmark VALUES ('hl2', 'vol1', 'vol1', 'Segundo', 'Nota', NULL,
+        0.2, '2025-01-25', NULL);
+    INSERT INTO Bookmark VALUES ('hl3', 'vol2', 'vol2', 'Simplify, simplify.', NULL,
+        NULL, 0.4, '2025-02-01', NULL);
+    INSERT INTO Bookmark VALUES ('hl4', 'vol2', 'vol2', 'Rather than love, than money.',
+        NULL, NULL, 0.9, '2025-02-02', NULL);";
+
+/// `books` generated books with `highlights_per_book` highlights each, for
+/// databases larger than the hand-written ones
+pub fn generated_books_data(books: usize, highlights_per_book: usize) -> String {
+    let mut data = String::new();
+    for book in 1..=books {
+        data.push_str(&format!(
+            "INSERT INTO content VALUES ('gen{book}', NULL, 'Generated Book {book}',
+                'Author {author}', NULL, NULL, 'en', '2025-01-24', 6);",
+            author = book % 25 + 1
+        ));
+        for highlight in 1..=highlights_per_book {
+            data.push_str(&format!(
+                "INSERT INTO Bookmark VALUES ('gen{book}-{highlight}', 'gen{book}', 'gen{book}',
+                    'Highlight {highlight} of book {book}', NULL, NULL, {progress}, '2025-01-24', NULL);",
+                progress = highlight as f64 / (highlights_per_book + 1) as f64
+            ));
+        }
+    }
+    data
+}
+
+/// Cover image stored in `write_epub_with_cover` EPUBs (a JPEG header)
+pub const COVER_JPEG: &[u8] = &[0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, 0x4A, 0x46, 0x49, 0x46];
+
+/// Write a Kobo volume at `volume`: `.kobo/KoboReader.sqlite` with the Kobo
+/// schema plus the `data` statements, and a `version` file holding `serial`
+///
+/// Returns the database path.
+pub fn create_kobo_volume(volume: &Path, serial: &str, data: &str) -> io::Result<PathBuf> {
+    let kobo_dir = volume.join(".kobo");
+    fs::create_dir_all(&kobo_dir)?;
+    fs::write(kobo_dir.join("version"), serial)?;
+
+    let sqlite_path = kobo_dir.join("KoboReader.sqlite");
+    let conn = Connection::open(&sqlite_path).map_err(io::Error::other)?;
+    conn.execute_batch(KOBO_SCHEMA)
+        .and_then(|_| conn.execute_batch(data))
+        .map_err(io::Error::other)?;
+    Ok(sqlite_path)
+}
+
+/// Write an EPUB whose OPF manifest points at a `COVER_JPEG` cover
+pub fn write_epub_with_cover(path: &Path) -> io::Result<()> {
+    let mut zip = zip::ZipWriter::new(fs::File::create(path)?);
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+    zip.start_file("mimetype", options)?;
+    zip.write_all(b"application/epub+zip")?;
+    zip.start_file("META-INF/container.xml", options)?;
+    zip.write_all(
+        br#"<container><rootfiles><rootfile full-path="OEBPS/content.opf"/></rootfiles></container>"#,
+    )?;
+    zip.start_file("OEBPS/content.opf", options)?;
+    zip.write_all(
+        br#"<package><metadata><meta name="cover" content="front"/></metadata>
+        <manifest><item id="front" href="images/front.jpg" media-type="image/jpeg"/></manifest>
+        </package>"#,
+    )?;
+    zip.start_file("OEBPS/images/front.jpg", options)?;
+    zip.write_all(COVER_JPEG)?;
+    zip.finish()?;
+    Ok(())
+}
@@ -0,0 +1,584 @@
+//! Headless sync: device scan → import → export without a window
+//!
+//! `khi --headless-sync [--export-path DIR] [--device-path DIR] [--dry-run]
+//! [--library-json]` runs the app's import and export code against the saved
+//! settings and library, prints a JSON summary to stdout and exits non-zero
+//! on failure. With `--library-json`, stdout gets the imported books as
+//! versioned library JSON (see `library_json`) and the summary goes to
+//! stderr.
+
+use crate::commands::{
+    attach_book_notes, attach_highlight_tags, attach_slugs, extract_device_books, import_device,
+    library_revision, merge_into_library, record_full_export, saved_assumed_offset,
+    saved_chapter_exclusions, saved_deadline, saved_disambiguators, saved_import_filters,
+    saved_import_hidden, saved_text_normalization, saved_title_options, take_hidden_highlights,
+};
+use crate::covers::CoverExtractor;
+use crate::db::filters::apply_import_filters;
+use crate::device::registry::join_registry;
+use crate::device::DeviceDetector;
+use crate::export::{validate_export_config, ExportFailure, MarkdownExporter, NoopSink};
+use crate::library::{LibraryState, LibraryStore};
+use crate::library_json::LibraryExportV1;
+use crate::models::{expiring_loans_first, Book, KoboDevice, LoanState};
+use crate::platform;
+use crate::profiles::{open_profile, record_import, ProfileError, ProfileManager, ProfileState};
+use crate::settings::{SettingsManager, SettingsState};
+use crate::usage::{self, UsageEvent, UsageKind};
+use crate::utils::cancel::{CancellationToken, OperationKind};
+use crate::utils::date::normalize_highlight_dates;
+use crate::utils::text::NormalizationStage;
+use crate::utils::titles::process_titles;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Flag that switches `run()` to headless mode
+pub const HEADLESS_FLAG: &str = "--headless-sync";
+
+/// Bundle identifier, used for the cache and data folders the app would get
+/// from Tauri
+const APP_IDENTIFIER: &str = "com.bruno.khi";
+
+/// Where mounted volumes are scanned for a Kobo
+const VOLUMES_PATH: &str = "/Volumes";
+
+/// Options of a headless sync run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeadlessOptions {
+    /// Export here instead of the saved export path
+    pub export_path: Option<PathBuf>,
+    /// Use this device folder instead of scanning
+    pub device_path: Option<PathBuf>,
+    /// Read the device and plan the export without writing anything
+    pub dry_run: bool,
+    /// Print the imported books as library JSON instead of the summary
+    pub library_json: bool,
+}
+
+impl HeadlessOptions {
+    /// Parse command-line arguments (without the program name)
+    ///
+    /// Returns `None` when `--headless-sync` is absent, so the window starts.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Self>, String> {
+        let mut headless = false;
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                HEADLESS_FLAG => headless = true,
+                "--dry-run" => options.dry_run = true,
+                "--library-json" => options.library_json = true,
+                "--export-path" | "--device-path" => {
+                    let value = args
+                        .next()
+                        .filter(|v| !v.starts_with("--"))
+                        .ok_or_else(|| format!("Missing value for {}", arg))?;
+                    if arg == "--export-path" {
+                        options.export_path = Some(PathBuf::from(value));
+                    } else {
+                        options.device_path = Some(PathBuf::from(value));
+                    }
+                }
+                // Other arguments belong to the platform (e.g. macOS -psn_*)
+                _ => {}
+            }
+        }
+
+        Ok(headless.then_some(options))
+    }
+}
+
+/// JSON summary printed by a headless sync
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncSummary {
+    pub success: bool,
+    pub dry_run: bool,
+    pub device: Option<KoboDevice>,
+    pub books: usize,
+    pub highlights: usize,
+    /// Highlights new to the library
+    pub new_highlights: usize,
+    /// Books of the library that reached 100% read
+    pub books_finished: usize,
+    pub export_path: Option<String>,
+    /// Written files, or the files that would be written on a dry run
+    pub files: Vec<String>,
+    pub failures: Vec<ExportFailure>,
+    /// The export was skipped: the last full export covers the library
+    pub up_to_date: bool,
+    /// Titles of library loans that expired or are about to, soonest first
+    pub expiring_loans: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// Run a headless sync and print its summary; returns the process exit code
+pub fn run(options: HeadlessOptions) -> i32 {
+    if let Err(e) = crate::utils::logger::init_file_only() {
+        eprintln!("Failed to initialize logger: {}", e);
+    }
+
+    let settings = SettingsState::default();
+    let library = LibraryState::default();
+    let profiles = ProfileState::default();
+    let cache_dir = dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(APP_IDENTIFIER);
+    let mut data_dir = dirs::data_dir().map(|dir| dir.join(APP_IDENTIFIER));
+    if let Some(dir) = data_dir.clone() {
+        // Sync into the profile the app last had active
+        let opened = SettingsManager::default_config_path()
+            .map_err(ProfileError::from)
+            .and_then(|path| {
+                let manager = ProfileManager::load(path.parent().unwrap_or(Path::new("")), &dir)?;
+                let profile = manager.active()?.clone();
+                let (settings_manager, store) = open_profile(&profile)?;
+                profiles.install(manager);
+                Ok((profile, settings_manager, store))
+            });
+        match opened {
+            Ok((profile, settings_manager, store)) => {
+                log::info!("[HEADLESS] Profile: {}", profile.name);
+                settings.replace(settings_manager);
+                library.install(store);
+                data_dir = Some(profile.tracking_dir);
+            }
+            Err(e) => {
+                log::warn!("[HEADLESS] Profiles unavailable: {}", e);
+                match LibraryStore::open(&dir.join("library.sqlite")) {
+                    Ok(mut store) => {
+                        store.set_text_normalization(
+                            saved_text_normalization(&settings).unwrap_or_default(),
+                        );
+                        library.install(store);
+                    }
+                    Err(e) => log::warn!("[HEADLESS] Library unavailable: {}", e),
+                }
+            }
+        }
+    }
+
+    let (summary, books) = sync_with_books(&options, &settings, &library, &cache_dir);
+    if let Some(device) = summary
+        .device
+        .as_ref()
+        .filter(|_| summary.success && !options.dry_run)
+    {
+        record_import(&profiles, device);
+    }
+    if let Some(data_dir) = data_dir.filter(|_| summary.success && !options.dry_run) {
+        usage::record(
+            &data_dir,
+            UsageEvent {
+                new_highlights: summary.new_highlights,
+                books_finished: summary.books_finished,
+                files: summary.files.len(),
+                ..UsageEvent::now(UsageKind::Sync, summary.books, summary.highlights)
+            },
+        );
+    }
+    let summary_json = serde_json::to_string_pretty(&summary);
+    if options.library_json {
+        match &summary_json {
+            Ok(json) => eprintln!("{}", json),
+            Err(e) => eprintln!("Failed to serialize summary: {}", e),
+        }
+        if summary.success {
+            let library = LibraryExportV1::now(&books, summary.device.as_ref());
+            match serde_json::to_string_pretty(&library) {
+                Ok(json) => println!("{}", json),
+                Err(e) => eprintln!("Failed to serialize library: {}", e),
+            }
+        }
+    } else {
+        match summary_json {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("Failed to serialize summary: {}", e),
+        }
+    }
+
+    if summary.success {
+        0
+    } else {
+        1
+    }
+}
+
+/// Scan, import and export with the saved settings
+///
+/// Failures end up in the summary (`success: false`) rather than an error,
+/// so a summary is always printed.
+pub fn sync(
+    options: &HeadlessOptions,
+    settings: &SettingsState,
+    library: &LibraryState,
+    cache_dir: &Path,
+) -> SyncSummary {
+    sync_with_books(options, settings, library, cache_dir).0
+}
+
+/// `sync`, also returning the imported books (empty when the import failed)
+pub fn sync_with_books(
+    options: &HeadlessOptions,
+    settings: &SettingsState,
+    library: &LibraryState,
+    cache_dir: &Path,
+) -> (SyncSummary, Vec<Book>) {
+    let mut summary = SyncSummary {
+        dry_run: options.dry_run,
+        ..Default::default()
+    };
+    let mut imported = Vec::new();
+    if let Err(e) = sync_into(
+        options,
+        settings,
+        library,
+        cache_dir,
+        &mut summary,
+        &mut imported,
+    ) {
+        log::error!("[HEADLESS] ❌ Sync failed: {}", e);
+        summary.error = Some(e);
+    }
+    summary.success = summary.error.is_none() && summary.failures.is_empty();
+    (summary, imported)
+}
+
+fn sync_into(
+    options: &HeadlessOptions,
+    settings: &SettingsState,
+    library: &LibraryState,
+    cache_dir: &Path,
+    summary: &mut SyncSummary,
+    imported: &mut Vec<Book>,
+) -> Result<(), String> {
+    let mut device = find_device(options.device_path.as_deref())?;
+    join_registry(settings, &mut device);
+    log::info!("[HEADLESS] Device: {:?}", device);
+    summary.device = Some(device.clone());
+
+    let normalization = saved_text_normalization(settings)?;
+    let mut books = if options.dry_run {
+        // Same extraction and filters as an import, without recording it
+        let filters = saved_import_filters(settings)?;
+        let mut books = extract_device_books(&device, false, true)?;
+        normalize_highlight_dates(&mut books, saved_assumed_offset(settings));
+        normalization.apply_at(NormalizationStage::Import, &mut books);
+        process_titles(&mut books, &saved_title_options(settings)?);
+        if !saved_import_hidden(settings)? {
+            take_hidden_highlights(&mut books);
+        }
+        apply_import_filters(&mut books, &filters);
+        books
+    } else {
+        let extractor = CoverExtractor::new(cache_dir.to_path_buf());
+        // Nothing can cancel a headless run, but the configured deadlines hold
+        let cancel =
+            CancellationToken::with_timeout(saved_deadline(settings, OperationKind::Import));
+        let (books, hidden) = import_device(settings, &device, false, &extractor, &cancel)?;
+        if let Some(merged) = merge_into_library(library, &books, &hidden) {
+            summary.new_highlights = merged.highlights_added;
+            summary.books_finished = merged.books_finished;
+        }
+        books
+    };
+    // Loans leaving the device soon are exported first
+    let now = chrono::Utc::now().naive_utc();
+    expiring_loans_first(&mut books, now);
+    summary.expiring_loans = books
+        .iter()
+        .filter(|b| {
+            matches!(
+                b.loan_state(now),
+                Some(LoanState::Expired | LoanState::Expiring)
+            )
+        })
+        .map(|b| b.title.clone())
+        .collect();
+    summary.books = books.len();
+    summary.highlights = books.iter().map(|b| b.highlights.len()).sum();
+    imported.clone_from(&books);
+
+    if !options.dry_run && library_is_exported(library) {
+        log::info!("[HEADLESS] Biblioteca sem alterações desde a última exportação");
+        summary.up_to_date = true;
+        return Ok(());
+    }
+
+    normalization.apply_at(NormalizationStage::Export, &mut books);
+    attach_book_notes(library, &mut books);
+    attach_highlight_tags(library, &mut books);
+    attach_slugs(library, &mut books);
+    export(options, settings, library, &books, summary)
+}
+
+/// Whether the library is open and unchanged since its last full export
+fn library_is_exported(library: &LibraryState) -> bool {
+    library
+        .with_store(|store| store.sync_status())
+        .is_ok_and(|status| !status.dirty)
+}
+
+/// The device at `device_path`, or the first Kobo among mounted volumes
+fn find_device(device_path: Option<&Path>) -> Result<KoboDevice, String> {
+    let detector = DeviceDetector::new(PathBuf::from(VOLUMES_PATH));
+    let device = match device_path {
+        Some(path) => detector
+            .check_kobo_device(path)
+            .map_err(|e| format!("Failed to read device: {}", e))?
+            .ok_or_else(|| format!("Not a Kobo device: {}", path.display()))?,
+        None => detector
+            .scan_for_kobo()
+            .map_err(|e| format!("Failed to scan for devices: {}", e))?
+            .ok_or_else(|| "No Kobo device found".to_string())?,
+    };
+
+    if device.is_valid {
+        Ok(device)
+    } else {
+        Err(format!("Kobo database not readable at {}", device.path))
+    }
+}
+
+fn export(
+    options: &HeadlessOptions,
+    settings: &SettingsState,
+    library: &LibraryState,
+    books: &[Book],
+    summary: &mut SyncSummary,
+) -> Result<(), String> {
+    let (mut config, bookmark, library_sort) = settings
+        .with_manager(|manager| {
+            let settings = manager.get();
+            Ok((
+                settings.export_config.clone(),
+                settings.export_path_bookmark.clone(),
+                settings.ui_preferences.library_sort.clone(),
+            ))
+        })
+        .map_err(|e| format!("Failed to load settings: {}", e))?;
+    validate_export_config(&config)?;
+    // The saved bookmark only grants access to the saved folder
+    let bookmark = match &options.export_path {
+        Some(path) => {
+            config.export_path = path.to_string_lossy().to_string();
+            None
+        }
+        None => bookmark,
+    };
+    let export_path = PathBuf::from(&config.export_path);
+    summary.export_path = Some(config.export_path.clone());
+
+    // A dry run must not create the export folder
+    let exporter = if options.dry_run {
+        MarkdownExporter::detached(export_path.clone())
+    } else {
+        MarkdownExporter::try_new(export_path.clone())
+            .map_err(|e| format!("Failed to create export folder: {}", e))?
+    };
+    let exporter = exporter
+        .with_index_sort(library_sort)
+        .with_chapter_exclusions(saved_chapter_exclusions(library))
+        .with_disambiguators(saved_disambiguators(library))
+        .with_cloud_settle(Duration::from_millis(config.cloud_settle_ms))
+        .with_title_options(saved_title_options(settings)?)
+        .with_assumed_offset(saved_assumed_offset(settings))
+        .with_cancellation(CancellationToken::with_timeout(saved_deadline(
+            settings,
+            OperationKind::Export,
+        )));
+    if options.dry_run {
+        summary.files = exporter
+            .planned_files(books, &config)
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+        return Ok(());
+    }
+
+    let _access = platform::security_scope()
+        .access(&export_path, bookmark)
+        .map_err(|e| format!("Failed to access export folder: {}", e))?;
+    let revision = library_revision(library);
+    let report = exporter.export_books_with_events(books, &config, &NoopSink);
+    log::info!(
+        "[HEADLESS] Export: {} ficheiro(s), {} erro(s)",
+        report.exported_files.len(),
+        report.failures.len()
+    );
+    if report.aborted.is_none() && report.cancelled.is_none() && report.failures.is_empty() {
+        record_full_export(library, books, revision);
+    }
+    summary.files = report.exported_files;
+    summary.failures = report.failures;
+    match (report.aborted, report.cancelled) {
+        (Some(reason), _) => Err(format!("Export aborted: {}", reason)),
+        (None, Some(cancelled)) => Err(format!("Export stopped: {}", cancelled)),
+        (None, None) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::SettingsManager;
+    use tempfile::TempDir;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    /// Device folder with a two-highlight Kobo database
+    fn create_mock_device(root: &Path) {
+        crate::fixtures::create_kobo_volume(
+            root,
+            "N418",
+            "INSERT INTO content VALUES ('vol1', NULL, 'Memorial do Convento',
+                'José Saramago', NULL, NULL, 'pt', '2025-01-24', 6);
+            INSERT INTO Bookmark VALUES ('hl1', 'vol1', 'vol1', 'Primeiro', NULL, NULL,
+                0.1, '2025-01-24', NULL);
+            INSERT INTO Bookmark VALUES ('hl2', 'vol1', 'vol1', 'Segundo', NULL, NULL,
+                0.2, '2025-01-25', NULL);",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(HeadlessOptions::from_args(args(&["-psn_0_1"])), Ok(None));
+        assert_eq!(
+            HeadlessOptions::from_args(args(&[
+                "--headless-sync",
+                "--dry-run",
+                "--export-path",
+                "/tmp/notes",
+                "--device-path",
+                "/Volumes/KOBOeReader",
+            ])),
+            Ok(Some(HeadlessOptions {
+                export_path: Some(PathBuf::from("/tmp/notes")),
+                device_path: Some(PathBuf::from("/Volumes/KOBOeReader")),
+                dry_run: true,
+                library_json: false,
+            }))
+        );
+        assert!(HeadlessOptions::from_args(args(&["--headless-sync", "--export-path"])).is_err());
+        assert!(
+            HeadlessOptions::from_args(args(&["--headless-sync", "--library-json"]))
+                .unwrap()
+                .unwrap()
+                .library_json
+        );
+    }
+
+    #[test]
+    fn test_headless_sync_against_mock_device() {
+        let temp = TempDir::new().unwrap();
+        let device_dir = temp.path().join("KOBOeReader");
+        create_mock_device(&device_dir);
+        let settings = SettingsState::from_manager(
+            SettingsManager::with_path(temp.path().join("settings.json")).unwrap(),
+        );
+        let library = LibraryState::default();
+        library.install(LibraryStore::open_in_memory().unwrap());
+        let mut options = HeadlessOptions {
+            export_path: Some(temp.path().join("notes")),
+            device_path: Some(device_dir),
+            dry_run: true,
+            library_json: false,
+        };
+
+        // A dry run plans the file without writing or recording anything
+        let planned = sync(&options, &settings, &library, &temp.path().join("cache"));
+        assert!(planned.success, "{:?}", planned.error);
+        assert_eq!(planned.files.len(), 1);
+        assert!(!Path::new(&planned.files[0]).exists());
+        assert!(!temp.path().join("notes").exists());
+        assert!(settings
+            .with_manager(|m| Ok(m.get().last_import.clone()))
+            .unwrap()
+            .is_none());
+
+        options.dry_run = false;
+        let summary = sync(&options, &settings, &library, &temp.path().join("cache"));
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["success"], true);
+        assert_eq!(json["dryRun"], false);
+        assert_eq!(json["device"]["serialNumber"], "N418");
+        assert_eq!(json["books"], 1);
+        assert_eq!(json["highlights"], 2);
+        assert_eq!(json["files"], serde_json::json!(planned.files));
+        assert!(Path::new(&summary.files[0]).exists());
+        assert_eq!(
+            library
+                .with_store(|store| store.stats())
+                .unwrap()
+                .highlights,
+            2
+        );
+        assert_eq!(json["upToDate"], false);
+
+        // Nothing changed on the device: the next sync doesn't export again
+        std::fs::remove_file(&summary.files[0]).unwrap();
+        let again = sync(&options, &settings, &library, &temp.path().join("cache"));
+        assert!(again.success, "{:?}", again.error);
+        assert!(again.up_to_date);
+        assert!(again.files.is_empty());
+        assert!(!Path::new(&summary.files[0]).exists());
+
+        // Library JSON of the same sync
+        let (summary, books) =
+            sync_with_books(&options, &settings, &library, &temp.path().join("cache"));
+        let json =
+            serde_json::to_value(LibraryExportV1::now(&books, summary.device.as_ref())).unwrap();
+        assert_eq!(json["schemaVersion"], 1);
+        assert_eq!(json["device"]["serialNumber"], "N418");
+        assert_eq!(json["books"][0]["title"], "Memorial do Convento");
+        assert_eq!(json["books"][0]["highlights"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_headless_sync_reports_unwritable_export_folder() {
+        let temp = TempDir::new().unwrap();
+        let device_dir = temp.path().join("KOBOeReader");
+        create_mock_device(&device_dir);
+        let settings = SettingsState::from_manager(
+            SettingsManager::with_path(temp.path().join("settings.json")).unwrap(),
+        );
+        let library = LibraryState::default();
+        library.install(LibraryStore::open_in_memory().unwrap());
+        // A folder can't be created inside a file
+        let blocker = temp.path().join("blocker");
+        std::fs::write(&blocker, "").unwrap();
+        let options = HeadlessOptions {
+            export_path: Some(blocker.join("notes")),
+            device_path: Some(device_dir),
+            ..Default::default()
+        };
+
+        let summary = sync(&options, &settings, &library, &temp.path().join("cache"));
+        assert!(!summary.success);
+        assert!(summary
+            .error
+            .unwrap()
+            .contains("Failed to create export folder"));
+    }
+
+    #[test]
+    fn test_headless_sync_reports_missing_device() {
+        let temp = TempDir::new().unwrap();
+        let settings = SettingsState::from_manager(
+            SettingsManager::with_path(temp.path().join("settings.json")).unwrap(),
+        );
+        let options = HeadlessOptions {
+            device_path: Some(temp.path().join("nothing")),
+            ..Default::default()
+        };
+
+        let summary = sync(&options, &settings, &LibraryState::default(), temp.path());
+        assert!(!summary.success);
+        assert!(summary.error.unwrap().contains("Not a Kobo device"));
+    }
+}
@@ -0,0 +1,775 @@
+//! Kindle "My Clippings.txt" as a secondary source of highlights
+//!
+//! Kindles append every highlight, note and bookmark to `My Clippings.txt`,
+//! entries separated by `==========`:
+//!
+//! ```text
+//! The Title (Last, First)
+//! - Your Highlight on page 12 | Location 180-182 | Added on Monday, March 4, 2019 8:15:32 PM
+//!
+//! The highlighted text
+//! ==========
+//! ```
+//!
+//! The metadata line is localized. Support matrix (`LANGUAGES`):
+//!
+//! | Language   | Highlight      | Note  | Bookmark    | Page   | Location       |
+//! |------------|----------------|-------|-------------|--------|----------------|
+//! | English    | Highlight      | Note  | Bookmark    | page   | Location, Loc. |
+//! | German     | Markierung     | Notiz | Lesezeichen | Seite  | Position       |
+//! | French     | surlignement   | note  | signet      | page   | emplacement    |
+//! | Spanish    | subrayado      | nota  | marcador    | página | posición       |
+//! | Italian    | evidenziazione | nota  | segnalibro  | pagina | posizione      |
+//! | Portuguese | destaque       | nota  | marcador    | página | posição        |
+//!
+//! Dates are read in all of them. Entries in other languages are skipped.
+//! Bookmarks carry no text and are dropped; a note is attached to the
+//! highlight whose location range holds it. Kindle never stores overlapping
+//! highlights, so when a highlight is edited the file gets a second entry
+//! overlapping the first: only the latest is kept. Books get `BookKind::KindleImport` and a content ID
+//! derived from title and author, so re-importing the file merges into the
+//! same library books.
+
+use crate::db::kobo::assign_stable_ids;
+use crate::models::{Book, BookKind, Highlight};
+use crate::utils::slug::{assign_slugs, content_hash};
+use chrono::{NaiveDate, NaiveDateTime};
+use std::fs;
+use std::path::Path;
+
+/// Content ID prefix of every book imported from Kindle clippings
+pub const KINDLE_CONTENT_PREFIX: &str = "kindle:";
+
+const ENTRY_SEPARATOR: &str = "==========";
+
+/// Words Kindle uses in one language's metadata lines, lowercase
+struct ClippingLanguage {
+    highlight: &'static [&'static str],
+    note: &'static [&'static str],
+    bookmark: &'static [&'static str],
+    page: &'static [&'static str],
+    location: &'static [&'static str],
+    months: [&'static str; 12],
+}
+
+const LANGUAGES: &[ClippingLanguage] = &[
+    // English
+    ClippingLanguage {
+        highlight: &["highlight"],
+        note: &["note"],
+        bookmark: &["bookmark"],
+        page: &["page"],
+        location: &["location", "loc."],
+        months: [
+            "january",
+            "february",
+            "march",
+            "april",
+            "may",
+            "june",
+            "july",
+            "august",
+            "september",
+            "october",
+            "november",
+            "december",
+        ],
+    },
+    // German
+    ClippingLanguage {
+        highlight: &["markierung"],
+        note: &["notiz"],
+        bookmark: &["lesezeichen"],
+        page: &["seite"],
+        location: &["position"],
+        months: [
+            "januar",
+            "februar",
+            "märz",
+            "april",
+            "mai",
+            "juni",
+            "juli",
+            "august",
+            "september",
+            "oktober",
+            "november",
+            "dezember",
+        ],
+    },
+    // French
+    ClippingLanguage {
+        highlight: &["surlignement"],
+        note: &["note"],
+        bookmark: &["signet"],
+        page: &["page"],
+        location: &["emplacement"],
+        months: [
+            "janvier",
+            "février",
+            "mars",
+            "avril",
+            "mai",
+            "juin",
+            "juillet",
+            "août",
+            "septembre",
+            "octobre",
+            "novembre",
+            "décembre",
+        ],
+    },
+    // Spanish
+    ClippingLanguage {
+        highlight: &["subrayado"],
+        note: &["nota"],
+        bookmark: &["marcador"],
+        page: &["página"],
+        location: &["posición"],
+        months: [
+            "enero",
+            "febrero",
+            "marzo",
+            "abril",
+            "mayo",
+            "junio",
+            "julio",
+            "agosto",
+            "septiembre",
+            "octubre",
+            "noviembre",
+            "diciembre",
+        ],
+    },
+    // Italian
+    ClippingLanguage {
+        highlight: &["evidenziazione"],
+        note: &["nota"],
+        bookmark: &["segnalibro"],
+        page: &["pagina"],
+        location: &["posizione"],
+        months: [
+            "gennaio",
+            "febbraio",
+            "marzo",
+            "aprile",
+            "maggio",
+            "giugno",
+            "luglio",
+            "agosto",
+            "settembre",
+            "ottobre",
+            "novembre",
+            "dicembre",
+        ],
+    },
+    // Portuguese
+    ClippingLanguage {
+        highlight: &["destaque"],
+        note: &["nota"],
+        bookmark: &["marcador"],
+        page: &["página"],
+        location: &["posição"],
+        months: [
+            "janeiro",
+            "fevereiro",
+            "março",
+            "abril",
+            "maio",
+            "junho",
+            "julho",
+            "agosto",
+            "setembro",
+            "outubro",
+            "novembro",
+            "dezembro",
+        ],
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClippingKind {
+    Highlight,
+    Note,
+    Bookmark,
+}
+
+/// One entry of a clippings file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clipping {
+    pub title: String,
+    /// Attribution in the title line's last parentheses; empty without one
+    pub author: String,
+    pub kind: ClippingKind,
+    pub page: Option<u32>,
+    /// First and last location (equal for notes and bookmarks)
+    pub location: Option<(u32, u32)>,
+    /// Device local time
+    pub added: Option<NaiveDateTime>,
+    pub text: String,
+}
+
+impl Clipping {
+    /// Whether `other` is an edit of `self`: their ranges overlap and one
+    /// text holds the other (two passages of one location are both kept)
+    fn overlaps(&self, other: &Clipping) -> bool {
+        match (self.location, other.location) {
+            (Some((start, end)), Some((other_start, other_end))) => {
+                start <= other_end && other_start <= end && self.same_passage(other)
+            }
+            _ => self.page.is_some() && self.page == other.page && self.text == other.text,
+        }
+    }
+
+    fn same_passage(&self, other: &Clipping) -> bool {
+        let (text, other_text) = (self.text.trim(), other.text.trim());
+        text.contains(other_text) || other_text.contains(text)
+    }
+
+    /// Whether `self` was made no earlier than `other`; clippings without a
+    /// date count as later, as the file is in creation order
+    fn is_newer_than(&self, other: &Clipping) -> bool {
+        match (self.added, other.added) {
+            (Some(added), Some(other_added)) => added >= other_added,
+            _ => true,
+        }
+    }
+}
+
+/// What reading a clippings file kept and dropped
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ClippingStats {
+    pub highlights: usize,
+    /// Notes attached to a highlight
+    pub notes: usize,
+    pub bookmarks: usize,
+    /// Older entries of edited highlights and notes
+    pub duplicates: usize,
+    /// Entries in an unknown language or format
+    pub skipped: usize,
+}
+
+#[derive(Debug)]
+pub enum ClippingsError {
+    Io(std::io::Error),
+    /// Nothing in the file looks like a Kindle clipping
+    NoClippings,
+}
+
+impl std::fmt::Display for ClippingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClippingsError::Io(e) => write!(f, "IO error: {}", e),
+            ClippingsError::NoClippings => write!(f, "No Kindle clippings found in the file"),
+        }
+    }
+}
+
+impl std::error::Error for ClippingsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClippingsError::Io(e) => Some(e),
+            ClippingsError::NoClippings => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ClippingsError {
+    fn from(err: std::io::Error) -> Self {
+        ClippingsError::Io(err)
+    }
+}
+
+/// Books with the highlights of the clippings file at `path`
+pub fn read_clippings(path: &Path) -> Result<Vec<Book>, ClippingsError> {
+    let bytes = fs::read(path)?;
+    let (clippings, skipped) = parse_clippings(&String::from_utf8_lossy(&bytes));
+    if clippings.is_empty() {
+        return Err(ClippingsError::NoClippings);
+    }
+    let (books, mut stats) = clippings_to_books(clippings);
+    stats.skipped = skipped;
+    log::info!(
+        "[Kindle] {} book(s), {} highlight(s), {} note(s) from {:?}; dropped {} bookmark(s), {} edited duplicate(s), {} unreadable entr(ies)",
+        books.len(),
+        stats.highlights,
+        stats.notes,
+        path,
+        stats.bookmarks,
+        stats.duplicates,
+        stats.skipped
+    );
+    Ok(books)
+}
+
+/// The entries of a clippings file, and how many couldn't be read
+pub fn parse_clippings(content: &str) -> (Vec<Clipping>, usize) {
+    let mut clippings = Vec::new();
+    let mut skipped = 0;
+    let mut entry: Vec<&str> = Vec::new();
+    for line in content.lines() {
+        if line.trim() == ENTRY_SEPARATOR {
+            if entry.iter().any(|l| !clean(l).is_empty()) {
+                match parse_entry(&entry) {
+                    Some(clipping) => clippings.push(clipping),
+                    None => skipped += 1,
+                }
+            }
+            entry.clear();
+        } else {
+            entry.push(line);
+        }
+    }
+    // A file cut short after the last separator
+    if entry.iter().any(|l| !clean(l).is_empty()) {
+        match parse_entry(&entry) {
+            Some(clipping) => clippings.push(clipping),
+            None => skipped += 1,
+        }
+    }
+    (clippings, skipped)
+}
+
+/// A line without the byte order marks Kindle scatters through the file
+fn clean(line: &str) -> &str {
+    line.trim_matches(|c: char| c == '\u{feff}' || c.is_whitespace())
+}
+
+fn parse_entry(lines: &[&str]) -> Option<Clipping> {
+    let mut lines = lines.iter().map(|l| clean(l)).skip_while(|l| l.is_empty());
+    let (title, author) = split_title_line(lines.next()?);
+    let metadata = lines.next()?.strip_prefix('-')?.trim().to_lowercase();
+    let text = lines.collect::<Vec<_>>().join("\n").trim().to_string();
+
+    let segments: Vec<&str> = metadata.split('|').map(str::trim).collect();
+    let kind = clipping_kind(segments[0])?;
+    let mut page = None;
+    let mut location = None;
+    for segment in &segments[..segments.len() - 1] {
+        if contains_any(segment, |language| language.page) {
+            page = first_number(segment);
+        } else if contains_any(segment, |language| language.location) {
+            location = location_range(segment);
+        }
+    }
+    // Without "|", the only segment holds everything
+    let last = segments[segments.len() - 1];
+    if segments.len() == 1 {
+        location = location_range(last);
+    }
+    Some(Clipping {
+        title,
+        author,
+        kind,
+        page,
+        location,
+        added: parse_added(last),
+        text,
+    })
+}
+
+/// "Title (Author)": the author is in the last parentheses, which may
+/// contain parentheses of their own
+fn split_title_line(line: &str) -> (String, String) {
+    if let Some(inner) = line.strip_suffix(')') {
+        let mut depth = 1;
+        for (index, c) in inner.char_indices().rev() {
+            match c {
+                ')' => depth += 1,
+                '(' => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                let title = inner[..index].trim();
+                if !title.is_empty() {
+                    return (title.to_string(), inner[index + 1..].trim().to_string());
+                }
+                break;
+            }
+        }
+    }
+    (line.to_string(), String::new())
+}
+
+fn contains_any(segment: &str, words: impl Fn(&ClippingLanguage) -> &[&str]) -> bool {
+    LANGUAGES
+        .iter()
+        .any(|language| words(language).iter().any(|w| segment.contains(w)))
+}
+
+fn clipping_kind(segment: &str) -> Option<ClippingKind> {
+    if contains_any(segment, |language| language.bookmark) {
+        Some(ClippingKind::Bookmark)
+    } else if contains_any(segment, |language| language.highlight) {
+        Some(ClippingKind::Highlight)
+    } else if contains_any(segment, |language| language.note) {
+        Some(ClippingKind::Note)
+    } else {
+        None
+    }
+}
+
+fn numbers(segment: &str) -> Vec<&str> {
+    segment
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|n| !n.is_empty())
+        .collect()
+}
+
+fn first_number(segment: &str) -> Option<u32> {
+    numbers(segment).first()?.parse().ok()
+}
+
+/// "180-182", or "1234-36" as older Kindles abbreviate the end
+fn location_range(segment: &str) -> Option<(u32, u32)> {
+    let (range, _) = segment.split_once(" added").unwrap_or((segment, ""));
+    let numbers = numbers(range);
+    let start: u32 = numbers.first()?.parse().ok()?;
+    let Some(end) = numbers.get(1).filter(|_| range.contains('-')) else {
+        return Some((start, start));
+    };
+    let mut end_value: u32 = end.parse().ok()?;
+    if end_value < start {
+        let scale = 10u32.checked_pow(end.len() as u32)?;
+        end_value += start - start % scale;
+        if end_value < start {
+            end_value += scale;
+        }
+    }
+    Some((start, end_value))
+}
+
+/// The date of "Added on Monday, March 4, 2019 8:15:32 PM" or its
+/// translations; weekday and filler words are ignored
+fn parse_added(segment: &str) -> Option<NaiveDateTime> {
+    let (mut year, mut month, mut day) = (None, None, None);
+    let (mut hour, mut minute, mut second) = (None, 0, 0);
+    let mut meridian = None;
+    for token in segment.split(|c: char| c.is_whitespace() || c == ',') {
+        let token = token.trim_matches('.');
+        if token.is_empty() {
+            continue;
+        }
+        if token.contains(':') {
+            let mut parts = token.split(':').map(|p| p.parse::<u32>().ok());
+            hour = parts.next().flatten();
+            minute = parts.next().flatten().unwrap_or(0);
+            second = parts.next().flatten().unwrap_or(0);
+        } else if token.bytes().all(|b| b.is_ascii_digit()) {
+            let value = token.parse().ok()?;
+            if token.len() == 4 {
+                year = Some(value as i32);
+            } else if day.is_none() {
+                day = Some(value);
+            }
+        } else if token == "am" || token == "a.m" {
+            meridian = Some(false);
+        } else if token == "pm" || token == "p.m" {
+            meridian = Some(true);
+        } else if let Some(number) = LANGUAGES.iter().find_map(|language| {
+            language
+                .months
+                .iter()
+                .position(|m| *m == token)
+                .map(|i| i as u32 + 1)
+        }) {
+            month = Some(number);
+        }
+    }
+    let mut hour = hour.unwrap_or(0);
+    match meridian {
+        Some(false) if hour == 12 => hour = 0,
+        Some(true) if hour < 12 => hour += 12,
+        _ => {}
+    }
+    NaiveDate::from_ymd_opt(year?, month?, day?)?.and_hms_opt(hour, minute, second)
+}
+
+/// Group clippings into books: bookmarks dropped, edited duplicates
+/// collapsed, notes attached to their highlights
+pub fn clippings_to_books(clippings: Vec<Clipping>) -> (Vec<Book>, ClippingStats) {
+    let mut stats = ClippingStats::default();
+    let mut groups: Vec<(String, String, Vec<Clipping>, Vec<Clipping>)> = Vec::new();
+    for clipping in clippings {
+        if clipping.kind == ClippingKind::Bookmark {
+            stats.bookmarks += 1;
+            continue;
+        }
+        let index = match groups.iter().position(|(title, author, _, _)| {
+            *title == clipping.title && *author == clipping.author
+        }) {
+            Some(index) => index,
+            None => {
+                groups.push((
+                    clipping.title.clone(),
+                    clipping.author.clone(),
+                    Vec::new(),
+                    Vec::new(),
+                ));
+                groups.len() - 1
+            }
+        };
+        let (_, _, highlights, notes) = &mut groups[index];
+        let kept = if clipping.kind == ClippingKind::Highlight {
+            highlights
+        } else {
+            notes
+        };
+        match kept.iter().position(|k| k.overlaps(&clipping)) {
+            Some(old) => {
+                stats.duplicates += 1;
+                if clipping.is_newer_than(&kept[old]) {
+                    kept[old] = clipping;
+                }
+            }
+            None => kept.push(clipping),
+        }
+    }
+
+    let mut books: Vec<Book> = groups
+        .into_iter()
+        .map(|(title, author, mut highlights, notes)| {
+            let mut annotations: Vec<Option<String>> = vec![None; highlights.len()];
+            for note in notes {
+                match note_target(&highlights, &note) {
+                    Some(index) => {
+                        stats.notes += 1;
+                        annotations[index] = Some(note.text);
+                    }
+                    // A note on no highlight is kept as a highlight of its own
+                    None => {
+                        highlights.push(note);
+                        annotations.push(None);
+                    }
+                }
+            }
+            stats.highlights += highlights.len();
+            build_book(title, author, highlights, annotations)
+        })
+        .collect();
+    assign_slugs(&mut books);
+    (books, stats)
+}
+
+/// Index of the highlight a note belongs to: the latest whose location
+/// range holds the note's location
+fn note_target(highlights: &[Clipping], note: &Clipping) -> Option<usize> {
+    let (location, _) = note.location?;
+    highlights
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| {
+            h.location
+                .is_some_and(|(start, end)| start <= location && location <= end)
+        })
+        .max_by_key(|(_, h)| (h.location.map(|(_, end)| end == location), h.added))
+        .map(|(index, _)| index)
+}
+
+fn build_book(
+    title: String,
+    author: String,
+    clippings: Vec<Clipping>,
+    annotations: Vec<Option<String>>,
+) -> Book {
+    let content_id = format!(
+        "{}{}",
+        KINDLE_CONTENT_PREFIX,
+        content_hash(&format!("{}\u{0}{}", title, author))
+    );
+    let mut book = Book::new(content_id, title, author);
+    book.kind = BookKind::KindleImport;
+
+    let mut entries: Vec<(Clipping, Option<String>)> =
+        clippings.into_iter().zip(annotations).collect();
+    // Reading order; clippings without a location go last
+    entries.sort_by_key(|(clipping, _)| {
+        (
+            clipping.location.is_none(),
+            clipping.location,
+            clipping.added,
+        )
+    });
+    for (clipping, annotation) in entries {
+        let date_created = clipping
+            .added
+            .map(|added| added.format("%Y-%m-%dT%H:%M:%S%.3f").to_string())
+            .unwrap_or_default();
+        let mut highlight = Highlight::new(String::new(), clipping.text, date_created);
+        highlight.annotation = annotation;
+        highlight.page = clipping.page;
+        book.add_highlight(highlight);
+    }
+
+    assign_stable_ids(&mut book);
+    for highlight in &mut book.highlights {
+        highlight.id = highlight.stable_id.clone();
+    }
+    book.date_last_read = book
+        .highlights
+        .iter()
+        .map(|h| h.date_created.clone())
+        .max()
+        .filter(|date| !date.is_empty());
+    book
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn books_from(content: &str) -> (Vec<Book>, ClippingStats) {
+        let (clippings, skipped) = parse_clippings(content);
+        let (books, mut stats) = clippings_to_books(clippings);
+        stats.skipped = skipped;
+        (books, stats)
+    }
+
+    #[test]
+    fn test_english_clippings() {
+        let content = include_str!("testdata/clippings_en.txt");
+        assert!(content.contains("\r\n"));
+        let (books, stats) = books_from(content);
+
+        assert_eq!(
+            stats,
+            ClippingStats {
+                highlights: 4,
+                notes: 1,
+                bookmarks: 1,
+                duplicates: 1,
+                skipped: 1,
+            }
+        );
+        assert_eq!(books.len(), 2);
+
+        let nineteen = &books[0];
+        assert_eq!(
+            nineteen.title,
+            "Nineteen Eighty-Four (Penguin Modern Classics)"
+        );
+        assert_eq!(nineteen.author, "Orwell, George");
+        assert_eq!(nineteen.authors, vec!["George Orwell"]);
+        assert_eq!(nineteen.kind, BookKind::KindleImport);
+        assert!(nineteen.content_id.starts_with(KINDLE_CONTENT_PREFIX));
+        assert_eq!(nineteen.highlights.len(), 3);
+
+        // The edited highlight keeps its latest, longer text
+        let first = &nineteen.highlights[0];
+        assert_eq!(
+            first.text,
+            "It was a bright cold day in April, and the clocks were striking thirteen."
+        );
+        assert_eq!(first.page, Some(3));
+        assert_eq!(first.date_created, "2019-03-04T20:17:02.000");
+        assert_eq!(
+            first.annotation.as_deref(),
+            Some("The opening line everyone quotes")
+        );
+        assert_eq!(first.id, first.stable_id);
+
+        // "Loc. 1234-36" is 1234-1236
+        assert_eq!(nineteen.highlights[1].text, "War is peace.");
+        assert_eq!(
+            nineteen.highlights[1].date_created,
+            "2019-03-05T00:05:00.000"
+        );
+        assert_eq!(
+            nineteen.highlights[2].text,
+            "Freedom is the freedom to say that two plus two make four."
+        );
+        assert_eq!(
+            nineteen.date_last_read.as_deref(),
+            Some("2019-03-06T09:30:00.000")
+        );
+
+        let walden = &books[1];
+        assert_eq!(walden.title, "Walden");
+        assert_eq!(walden.author, "");
+        assert_eq!(walden.highlights.len(), 1);
+
+        // Ids only depend on the content
+        let (again, _) = books_from(&content.replace("\r\n", "\n"));
+        assert_eq!(again[0].content_id, nineteen.content_id);
+        assert_eq!(again[0].slug, nineteen.slug);
+        let ids = |book: &Book| -> Vec<String> {
+            book.highlights
+                .iter()
+                .map(|h| h.stable_id.clone())
+                .collect()
+        };
+        assert_eq!(ids(&again[0]), ids(nineteen));
+    }
+
+    #[test]
+    fn test_german_clippings() {
+        let (books, stats) = books_from(include_str!("testdata/clippings_de.txt"));
+
+        assert_eq!(stats.highlights, 2);
+        assert_eq!(stats.notes, 1);
+        assert_eq!(stats.bookmarks, 1);
+        assert_eq!(stats.duplicates, 1);
+        assert_eq!(books.len(), 1);
+
+        let book = &books[0];
+        assert_eq!(book.title, "Der Process");
+        assert_eq!(book.author, "Kafka, Franz");
+        let arrest = &book.highlights[0];
+        assert_eq!(
+            arrest.text,
+            "Jemand mußte Josef K. verleumdet haben, denn ohne daß er etwas Böses getan hätte, wurde er eines Morgens verhaftet."
+        );
+        assert_eq!(arrest.date_created, "2020-03-12T21:04:51.000");
+        assert_eq!(
+            arrest.annotation.as_deref(),
+            Some("Der berühmte erste Satz")
+        );
+        assert_eq!(book.highlights[1].page, Some(214));
+        assert_eq!(book.highlights[1].annotation, None);
+    }
+
+    #[test]
+    fn test_distinct_highlights_in_one_location() {
+        let (books, stats) = books_from(include_str!("testdata/clippings_same_location.txt"));
+
+        assert_eq!(stats.highlights, 2);
+        assert_eq!(stats.duplicates, 1);
+        let texts: Vec<&str> = books[0]
+            .highlights
+            .iter()
+            .map(|h| h.text.as_str())
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                "Happy families are all alike;",
+                "every unhappy family is unhappy in its own way.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_title_line_and_metadata() {
+        assert_eq!(
+            split_title_line("Gödel, Escher, Bach (20th Anniversary) (Hofstadter, Douglas R.)"),
+            (
+                "Gödel, Escher, Bach (20th Anniversary)".to_string(),
+                "Hofstadter, Douglas R.".to_string()
+            )
+        );
+        assert_eq!(
+            split_title_line("(Untitled)"),
+            ("(Untitled)".to_string(), String::new())
+        );
+        assert_eq!(location_range("location 998-1002"), Some((998, 1002)));
+        assert_eq!(location_range("loc. 1298-302"), Some((1298, 1302)));
+        assert_eq!(
+            parse_added("añadido el lunes, 4 de marzo de 2019 8:15:32"),
+            NaiveDate::from_ymd_opt(2019, 3, 4).and_then(|d| d.and_hms_opt(8, 15, 32))
+        );
+        assert_eq!(
+            parse_added("added on monday, march 4, 2019 12:01:00 am"),
+            NaiveDate::from_ymd_opt(2019, 3, 4).and_then(|d| d.and_hms_opt(0, 1, 0))
+        );
+    }
+}
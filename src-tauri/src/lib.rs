@@ -1,45 +1,182 @@
 pub mod commands;
+#[cfg(test)]
+mod contract;
 pub mod covers;
 pub mod db;
 pub mod device;
 pub mod export;
+pub mod fixtures;
+pub mod headless;
+pub mod kindle;
+pub mod library;
+pub mod library_json;
 pub mod models;
+pub mod platform;
+pub mod profiles;
+pub mod sample;
+pub mod scheduler;
+pub mod selftest;
 pub mod settings;
+pub mod simulate;
+pub mod startup;
+pub mod updates;
+pub mod usage;
 pub mod utils;
 pub mod window;
 
 use commands::{
-    clear_cover_cache, export_books, get_default_export_path, get_default_settings,
-    get_export_preview, import_highlights, load_settings, pick_export_folder, reset_settings,
-    save_settings, scan_for_device, update_last_import, validate_export_path,
+    adopt_existing_exports, cancel_operation, check_export_path, check_for_updates,
+    clear_cover_cache, create_profile, delete_export_profile, delete_profile, export_books,
+    export_library_json, forget_device, frontend_ready, get_app_info, get_book_chapter_map,
+    get_book_highlights, get_book_notes, get_book_stats, get_default_export_path,
+    get_default_settings, get_excluded_chapters, get_export_diff, get_export_preview,
+    get_full_cover, get_known_devices, get_language_breakdown, get_library_db_stats,
+    get_maintenance_status, get_review_highlights, get_session_metrics, get_settings_backup_info,
+    get_settings_health, get_startup_report, get_sync_status, get_usage_history, import_highlights,
+    import_kindle_clippings, list_export_profiles, list_export_snapshots, list_profiles,
+    load_sample_library, load_settings, mark_reviewed, pick_export_folder, preview_import_filters,
+    preview_template, prewarm_previews, recheck_device_freshness, refresh_book_cover,
+    remove_books_from_library, rename_device, render_highlights_for_clipboard,
+    repair_export_manifest, reset_settings, restore_export_snapshot, restore_settings_section,
+    run_maintenance_task, run_readonly_query, run_self_test, save_export_profile, save_settings,
+    scan_for_device, scan_for_devices, search_highlights, set_book_notes, set_excluded_chapters,
+    simulate_device_event, switch_profile, update_last_import, vacuum_library,
+    validate_export_config, validate_export_path, validate_export_template, verify_cover_paths,
+    verify_export_manifest,
 };
 
 use device::monitor::DeviceMonitor;
+use export::preview::PreviewCache;
+use export::watch::ExportWatchState;
+use library::{LibraryState, LibraryStore};
+use profiles::{ProfileManager, ProfileState};
+use scheduler::{OperationLock, Scheduler, SchedulerState};
+use settings::{SettingsManager, SettingsState};
+use simulate::SimulatorState;
+use startup::{StartupReport, StartupState};
+use tauri::Manager;
+use utils::cancel::OperationRegistry;
+use utils::metrics::SessionMetrics;
+use window::WindowShowState;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    // Cron-friendly sync without a window: `khi --headless-sync`
+    match headless::HeadlessOptions::from_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => std::process::exit(headless::run(options)),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+
+    let mut report = StartupReport::default();
+
     // Initialize logging
     if let Err(e) = utils::logger::init() {
         eprintln!("Failed to initialize logger: {}", e);
+        report.record("logger", e, None);
+    }
+
+    // Move a corrupted settings file aside before it is loaded
+    if let Ok(path) = SettingsManager::default_config_path() {
+        startup::check_json_store("settings", &path, &mut report);
     }
+    let settings_state = SettingsState::default();
+    startup::check_settings(&settings_state, &mut report);
+    startup::clean_export_staging(&settings_state);
 
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
+        .manage(settings_state)
+        .manage(StartupState::new(report))
+        .manage(SchedulerState::default())
+        .manage(OperationLock::default())
+        .manage(LibraryState::default())
+        .manage(ProfileState::default())
+        .manage(SessionMetrics::default())
+        .manage(PreviewCache::default())
+        .manage(ExportWatchState::default())
+        .manage(WindowShowState::default())
+        .manage(SimulatorState::default())
+        .manage(OperationRegistry::default())
         .invoke_handler(tauri::generate_handler![
             scan_for_device,
+            scan_for_devices,
+            get_known_devices,
+            rename_device,
+            forget_device,
             import_highlights,
+            import_kindle_clippings,
             export_books,
+            cancel_operation,
             get_export_preview,
+            prewarm_previews,
             get_default_export_path,
             get_default_settings,
+            validate_export_config,
             validate_export_path,
+            check_export_path,
+            validate_export_template,
+            preview_template,
             load_settings,
             save_settings,
             update_last_import,
             reset_settings,
             pick_export_folder,
-            clear_cover_cache
+            clear_cover_cache,
+            get_settings_backup_info,
+            get_settings_health,
+            get_language_breakdown,
+            list_export_profiles,
+            save_export_profile,
+            delete_export_profile,
+            get_startup_report,
+            get_maintenance_status,
+            run_maintenance_task,
+            get_export_diff,
+            search_highlights,
+            vacuum_library,
+            get_library_db_stats,
+            remove_books_from_library,
+            preview_import_filters,
+            get_review_highlights,
+            render_highlights_for_clipboard,
+            mark_reviewed,
+            get_app_info,
+            get_book_highlights,
+            get_book_chapter_map,
+            get_book_stats,
+            get_book_notes,
+            check_for_updates,
+            list_export_snapshots,
+            restore_export_snapshot,
+            restore_settings_section,
+            verify_export_manifest,
+            repair_export_manifest,
+            adopt_existing_exports,
+            set_book_notes,
+            set_excluded_chapters,
+            get_excluded_chapters,
+            get_sync_status,
+            get_usage_history,
+            list_profiles,
+            create_profile,
+            delete_profile,
+            switch_profile,
+            run_readonly_query,
+            load_sample_library,
+            verify_cover_paths,
+            refresh_book_cover,
+            recheck_device_freshness,
+            get_full_cover,
+            run_self_test,
+            simulate_device_event,
+            export_library_json,
+            get_session_metrics,
+            frontend_ready
         ])
         .setup(|app| {
             // Show window only after frontend signals ready (prevents white flash)
@@ -48,11 +185,158 @@ pub fn run() {
             // Start device monitoring
             let app_handle = app.handle().clone();
             let monitor = DeviceMonitor::new(app_handle);
-            monitor.start_monitoring();
-            
+            if std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                monitor.start_monitoring();
+            }))
+            .is_err()
+            {
+                app.state::<StartupState>()
+                    .record("monitor", "device monitoring could not be started");
+            }
+
+            // Periodic maintenance (skipped while imports/exports run)
+            match (app.path().app_cache_dir(), app.path().app_data_dir()) {
+                (Ok(cache_dir), Ok(data_dir)) => {
+                    let _ = std::fs::create_dir_all(&data_dir);
+                    let mut scheduler = Scheduler::new(
+                        scheduler::AppContext {
+                            cache_dir,
+                            data_dir: data_dir.clone(),
+                        },
+                        data_dir.join("maintenance.json"),
+                        Box::new(scheduler::SystemClock),
+                    );
+                    scheduler.register(Box::new(scheduler::tasks::CoverCacheEvictionTask));
+                    let (dir_handle, handle) = (app.handle().clone(), app.handle().clone());
+                    scheduler.register(Box::new(usage::UsageRetentionTask::new(
+                        move || dir_handle.state::<ProfileState>().tracking_dir(),
+                        move || {
+                            handle
+                                .state::<SettingsState>()
+                                .with_manager(|manager| Ok(manager.get().usage_retention_months))
+                                .unwrap_or(usage::DEFAULT_USAGE_RETENTION_MONTHS)
+                        },
+                    )));
+                    let handle = app.handle().clone();
+                    scheduler.register(Box::new(scheduler::tasks::ParkedRowsPruneTask::new(
+                        move |before| {
+                            handle
+                                .state::<LibraryState>()
+                                .with_store(|store| store.prune_parked(before))
+                                .map_err(|e| e.to_string())
+                        },
+                    )));
+
+                    let state = app.state::<SchedulerState>().inner().clone();
+                    state.install(scheduler);
+                    let operations = app.state::<OperationLock>().inner().clone();
+                    scheduler::start(state, operations, scheduler::TICK_INTERVAL);
+
+                    let library_path = load_profiles(app, &data_dir)
+                        .unwrap_or_else(|| data_dir.join("library.sqlite"));
+                    open_library(app, &library_path);
+                }
+                _ => app
+                    .state::<StartupState>()
+                    .record("scheduler", "app directories unavailable"),
+            }
+
+            // Report edits to exported files, if enabled
+            if let Ok(settings) = app
+                .state::<SettingsState>()
+                .with_manager(|manager| Ok(manager.get().clone()))
+            {
+                app.state::<ExportWatchState>()
+                    .sync(app.handle(), &settings);
+            }
+
+            let report = app.state::<StartupState>().snapshot();
+            if report.safe_mode {
+                log::warn!(
+                    "Application started in safe mode ({} issue(s))",
+                    report.issues.len()
+                );
+            }
+
             log::info!("Application started with device monitoring enabled");
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                // Scratch folders of a simulated device outlive the window
+                app.state::<SimulatorState>().clear();
+            }
+        });
+}
+
+/// Load the profiles and switch the settings to the active profile's;
+/// returns the path of its library
+fn load_profiles(app: &tauri::App, data_dir: &std::path::Path) -> Option<std::path::PathBuf> {
+    let config_dir = SettingsManager::default_config_path()
+        .ok()?
+        .parent()?
+        .to_path_buf();
+    let manager = match ProfileManager::load(&config_dir, data_dir) {
+        Ok(manager) => manager,
+        Err(e) => {
+            log::error!("Profiles unavailable: {}", e);
+            app.state::<StartupState>()
+                .record("profiles", e.to_string());
+            return None;
+        }
+    };
+    let profile = manager.active().ok()?.clone();
+    let settings = app.state::<SettingsState>();
+    let loaded = settings
+        .with_manager(|manager| Ok(manager.config_path().to_path_buf()))
+        .ok();
+    if loaded.as_ref() != Some(&profile.settings_path) {
+        match SettingsManager::with_path(profile.settings_path.clone()) {
+            Ok(manager) => settings.replace(manager),
+            Err(e) => log::error!("Settings of profile {} unavailable: {}", profile.name, e),
+        }
+    }
+    app.state::<ProfileState>().install(manager);
+    log::info!("Active profile: {}", profile.name);
+    Some(profile.library_path)
+}
+
+/// Open the local library, starting fresh if the existing file is corrupt
+///
+/// Other failures (a locked file, a newer schema) leave the file alone and
+/// the library disabled.
+fn open_library(app: &tauri::App, path: &std::path::Path) {
+    let store = match LibraryStore::open(path) {
+        Ok(store) => Some(store),
+        Err(e) if !e.is_corruption() => {
+            log::error!("Library disabled, failed to open {:?}: {}", path, e);
+            app.state::<StartupState>().record("library", e.to_string());
+            None
+        }
+        Err(e) => {
+            log::error!("Failed to open library at {:?}: {}", path, e);
+            let backup = startup::quarantine_database(path).ok();
+            app.state::<StartupState>().record(
+                "library",
+                match &backup {
+                    Some(b) => format!("{} (moved to {})", e, b.display()),
+                    None => e.to_string(),
+                },
+            );
+            LibraryStore::open(path)
+                .map_err(|e| log::error!("Library disabled: {}", e))
+                .ok()
+        }
+    };
+
+    if let Some(mut store) = store {
+        let normalization = app
+            .state::<SettingsState>()
+            .with_manager(|manager| Ok(manager.get().text_normalization.clone()))
+            .unwrap_or_default();
+        store.set_text_normalization(normalization);
+        app.state::<LibraryState>().install(store);
+    }
 }
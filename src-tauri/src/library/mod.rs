@@ -0,0 +1,2372 @@
+//! Local highlight library backed by SQLite
+//!
+//! Books and highlights from every import are merged here, keyed by
+//! `content_id` and the highlight's stable ID, so favorites, tags and export
+//! tracking survive re-imports. Highlight text and notes are indexed with FTS5
+//! for `search_highlights`.
+//!
+//! Writes go through the single `LibraryStore` behind `LibraryState`, one
+//! caller at a time; reads can use `LibraryState::with_reader`, which serves
+//! them from read-only connections in WAL mode, so a long merge doesn't hold
+//! up the UI. Merges commit in bounded chunks (`MERGE_CHUNK_HIGHLIGHTS`) so
+//! readers see an import progress rather than wait for its end.
+
+pub mod removal;
+pub mod review;
+
+use crate::kindle::KINDLE_CONTENT_PREFIX;
+use crate::models::{Book, BookKind, Highlight, HighlightKind, ProgressScope};
+use crate::utils::author::parse_authors;
+use crate::utils::date::{parse_timestamp, to_sortable, utc_offset};
+use crate::utils::disambiguation::{assign_disambiguators, disambiguators};
+use crate::utils::format::{reading_percent, reading_secs};
+use crate::utils::slug::book_slug;
+use crate::utils::text::{ellipsize, snippet_around, Snippet, TextNormalization};
+use crate::utils::titles::{process_titles, TitleOptions};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How long a statement waits for a lock held by another connection
+/// before failing with `SQLITE_BUSY`
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Highlights merged per transaction; a book is never split
+pub const MERGE_CHUNK_HIGHLIGHTS: usize = 500;
+
+/// Pauses before retrying a merge chunk that found the database busy
+/// (e.g. written by a headless run) past `BUSY_TIMEOUT`
+const MERGE_RETRY_DELAYS: &[Duration] = &[
+    Duration::from_millis(250),
+    Duration::from_secs(1),
+    Duration::from_secs(4),
+];
+
+/// Idle read-only connections kept for reuse
+const MAX_IDLE_READERS: usize = 4;
+
+/// Schema version that added `highlights.date_created_utc`
+const CREATED_UTC_VERSION: usize = 8;
+
+/// Schema migrations, applied in order; `user_version` records how many ran
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE books (
+        content_id TEXT PRIMARY KEY,
+        title TEXT NOT NULL,
+        author TEXT NOT NULL,
+        isbn TEXT,
+        publisher TEXT,
+        language TEXT,
+        date_last_read TEXT,
+        description TEXT,
+        cover_path TEXT
+    );
+    CREATE TABLE highlights (
+        stable_id TEXT PRIMARY KEY,
+        device_id TEXT NOT NULL,
+        content_id TEXT NOT NULL REFERENCES books(content_id) ON DELETE CASCADE,
+        text TEXT NOT NULL,
+        annotation TEXT,
+        chapter_title TEXT,
+        chapter_progress REAL,
+        container_path TEXT,
+        date_created TEXT NOT NULL,
+        color TEXT
+    );
+    CREATE INDEX idx_highlights_content ON highlights(content_id);
+    CREATE INDEX idx_highlights_device ON highlights(device_id);
+    CREATE TABLE tags (
+        stable_id TEXT NOT NULL REFERENCES highlights(stable_id) ON DELETE CASCADE,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (stable_id, tag)
+    );
+    CREATE TABLE favorites (
+        stable_id TEXT PRIMARY KEY REFERENCES highlights(stable_id) ON DELETE CASCADE,
+        created_at TEXT NOT NULL
+    );
+    CREATE TABLE export_tracking (
+        stable_id TEXT PRIMARY KEY REFERENCES highlights(stable_id) ON DELETE CASCADE,
+        exported_at TEXT NOT NULL,
+        export_path TEXT NOT NULL
+    );
+    CREATE VIRTUAL TABLE highlights_fts USING fts5(
+        text, annotation, content='highlights', content_rowid='rowid'
+    );
+    CREATE TRIGGER highlights_ai AFTER INSERT ON highlights BEGIN
+        INSERT INTO highlights_fts(rowid, text, annotation)
+        VALUES (new.rowid, new.text, new.annotation);
+    END;
+    CREATE TRIGGER highlights_ad AFTER DELETE ON highlights BEGIN
+        INSERT INTO highlights_fts(highlights_fts, rowid, text, annotation)
+        VALUES ('delete', old.rowid, old.text, old.annotation);
+    END;
+    CREATE TRIGGER highlights_au AFTER UPDATE ON highlights BEGIN
+        INSERT INTO highlights_fts(highlights_fts, rowid, text, annotation)
+        VALUES ('delete', old.rowid, old.text, old.annotation);
+        INSERT INTO highlights_fts(rowid, text, annotation)
+        VALUES (new.rowid, new.text, new.annotation);
+    END;",
+    "CREATE TABLE review_tracking (
+        stable_id TEXT PRIMARY KEY REFERENCES highlights(stable_id) ON DELETE CASCADE,
+        last_reviewed TEXT NOT NULL,
+        review_count INTEGER NOT NULL DEFAULT 0
+    );",
+    "ALTER TABLE highlights ADD COLUMN is_excluded INTEGER NOT NULL DEFAULT 0;",
+    "CREATE TABLE chapter_exclusions (
+        content_id TEXT NOT NULL,
+        chapter TEXT NOT NULL,
+        PRIMARY KEY (content_id, chapter)
+    );",
+    "ALTER TABLE books ADD COLUMN slug TEXT;
+    CREATE INDEX idx_books_slug ON books(slug);",
+    "CREATE TABLE sync_state (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        revision INTEGER NOT NULL DEFAULT 0,
+        last_exported_revision INTEGER NOT NULL DEFAULT 0
+    );
+    INSERT INTO sync_state (id) VALUES (1);
+    ALTER TABLE highlights ADD COLUMN revision INTEGER NOT NULL DEFAULT 0;",
+    "CREATE TABLE parked_tags (
+        stable_id TEXT NOT NULL,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (stable_id, tag)
+    );
+    CREATE TABLE parked_favorites (
+        stable_id TEXT PRIMARY KEY,
+        created_at TEXT NOT NULL
+    );
+    CREATE TABLE parked_export_tracking (
+        stable_id TEXT PRIMARY KEY,
+        exported_at TEXT NOT NULL,
+        export_path TEXT NOT NULL
+    );",
+    "ALTER TABLE highlights ADD COLUMN date_created_utc TEXT;",
+    "CREATE TABLE book_notes (
+        content_id TEXT PRIMARY KEY,
+        notes TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );",
+    "ALTER TABLE books ADD COLUMN thumbnail_path TEXT;",
+    "ALTER TABLE books ADD COLUMN subtitle TEXT;
+    ALTER TABLE books ADD COLUMN raw_title TEXT;",
+    "ALTER TABLE books ADD COLUMN percent_read REAL;
+    ALTER TABLE books ADD COLUMN time_spent_reading_secs INTEGER;",
+    "ALTER TABLE highlights ADD COLUMN figure_path TEXT;",
+    "ALTER TABLE parked_tags ADD COLUMN parked_at TEXT;
+    ALTER TABLE parked_favorites ADD COLUMN parked_at TEXT;
+    ALTER TABLE parked_export_tracking ADD COLUMN parked_at TEXT;
+    UPDATE parked_tags SET parked_at = strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now');
+    UPDATE parked_favorites SET parked_at = strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now');
+    UPDATE parked_export_tracking SET parked_at = strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now');",
+    "ALTER TABLE books ADD COLUMN is_orphaned INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE books ADD COLUMN publication_year INTEGER;",
+    "ALTER TABLE books ADD COLUMN progress_scope TEXT;",
+    "ALTER TABLE books ADD COLUMN kind TEXT;
+    ALTER TABLE books ADD COLUMN source_url TEXT;",
+];
+
+/// Counts from merging an import into the library
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeStats {
+    pub books_added: usize,
+    pub highlights_added: usize,
+    pub highlights_updated: usize,
+    /// Books already in the library that reached 100% read
+    #[serde(default)]
+    pub books_finished: usize,
+}
+
+/// A full-text search result
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchHit {
+    pub stable_id: String,
+    pub content_id: String,
+    pub book_title: String,
+    pub text: String,
+    /// Excerpt around the first match (from the note if only the note matched)
+    pub snippet: String,
+    /// Match position within `snippet`, in UTF-16 code units for JS slicing
+    pub match_start: Option<usize>,
+    pub match_end: Option<usize>,
+    /// BM25 rank (lower is better)
+    pub rank: f64,
+}
+
+/// One page of a book's highlights, for books too large to send at once
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct HighlightPage {
+    pub content_id: String,
+    /// Highlights of the book in total, across every page
+    pub total: usize,
+    pub offset: usize,
+    pub highlights: Vec<Highlight>,
+}
+
+/// A highlight picked in the UI, by book and highlight ID
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct HighlightRef {
+    #[serde(alias = "content_id")]
+    pub content_id: String,
+    /// Stable ID, or the device's bookmark ID
+    #[serde(alias = "highlight_id")]
+    pub highlight_id: String,
+}
+
+/// Why a `HighlightRef` couldn't be resolved
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingReason {
+    /// The book is no longer in the library
+    Book,
+    /// The book is, but not the highlight (or it was deleted on the device)
+    Highlight,
+}
+
+/// A `HighlightRef` with its book (without highlights) and highlight
+pub type ResolvedHighlight = Result<(Book, Highlight), MissingReason>;
+
+/// Whether the library has changed since its last full export
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncStatus {
+    /// Bumped by every change to the library's highlights or their settings
+    pub library_revision: u64,
+    /// Revision the last successful full export covered
+    pub last_exported_revision: u64,
+    pub dirty: bool,
+    /// Highlights added to the library since that export
+    pub new_highlights_since_export: usize,
+}
+
+/// Size and row counts of the library database
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryDbStats {
+    pub schema_version: u32,
+    pub books: usize,
+    pub highlights: usize,
+    pub favorites: usize,
+    pub tags: usize,
+    pub exported: usize,
+    pub size_bytes: u64,
+}
+
+/// Columns read by `highlight_from_row`
+const HIGHLIGHT_COLUMNS: &str = "stable_id, device_id, text, annotation, chapter_title, \
+    chapter_progress, container_path, date_created, color, date_created_utc, figure_path";
+
+fn highlight_from_row(row: &Row) -> rusqlite::Result<Highlight> {
+    let mut highlight = Highlight::new(row.get(1)?, row.get(2)?, row.get(7)?);
+    highlight.stable_id = row.get(0)?;
+    highlight.annotation = row.get(3)?;
+    highlight.chapter_title = row.get(4)?;
+    highlight.chapter_progress = row.get(5)?;
+    highlight.container_path = row.get(6)?;
+    highlight.color = row.get(8)?;
+    highlight.kind = HighlightKind::detect(&highlight.text, highlight.container_path.as_deref());
+    highlight.figure_path = row.get(10)?;
+    if let Some(created) = row.get::<_, Option<String>>(9)? {
+        highlight.date_created_utc = DateTime::parse_from_rfc3339(&created)
+            .ok()
+            .map(|dt| dt.with_timezone(&Utc));
+    }
+    Ok(highlight)
+}
+
+/// Columns read by `book_from_row`
+const BOOK_COLUMNS: &str = "content_id, title, author, isbn, publisher, language, \
+    date_last_read, description, cover_path, slug, \
+    (SELECT notes FROM book_notes n WHERE n.content_id = books.content_id), thumbnail_path, \
+    subtitle, raw_title, percent_read, time_spent_reading_secs, is_orphaned, publication_year, \
+    progress_scope, kind, source_url";
+
+/// Highlights by position in the book
+const POSITION_ORDER: &str = "chapter_progress IS NULL, chapter_progress, \
+    date_created_utc IS NULL, date_created_utc, date_created, stable_id";
+
+/// Highlights by creation (positions only order highlights within a chapter)
+const CREATION_ORDER: &str = "date_created_utc IS NULL, date_created_utc, date_created, stable_id";
+
+fn scope_to_sql(scope: ProgressScope) -> &'static str {
+    match scope {
+        ProgressScope::Chapter => "chapter",
+        ProgressScope::Book => "book",
+    }
+}
+
+fn scope_from_sql(value: &str) -> Option<ProgressScope> {
+    match value {
+        "chapter" => Some(ProgressScope::Chapter),
+        "book" => Some(ProgressScope::Book),
+        _ => None,
+    }
+}
+
+fn kind_to_sql(kind: BookKind) -> &'static str {
+    match kind {
+        BookKind::Book => "book",
+        BookKind::PocketArticle => "pocket_article",
+        BookKind::KindleImport => "kindle_import",
+    }
+}
+
+fn kind_from_sql(value: &str) -> Option<BookKind> {
+    match value {
+        "book" => Some(BookKind::Book),
+        "pocket_article" => Some(BookKind::PocketArticle),
+        "kindle_import" => Some(BookKind::KindleImport),
+        _ => None,
+    }
+}
+
+/// A book without its highlights
+fn book_from_row(row: &Row) -> rusqlite::Result<Book> {
+    let mut book = Book::new(row.get(0)?, row.get(1)?, row.get(2)?);
+    book.authors = parse_authors(&book.author);
+    book.isbn = row.get(3)?;
+    book.publisher = row.get(4)?;
+    book.language = row.get(5)?;
+    book.date_last_read = row.get(6)?;
+    book.description = row.get(7)?;
+    book.cover_path = row.get(8)?;
+    book.slug = row.get::<_, Option<String>>(9)?.unwrap_or_default();
+    book.notes = row.get(10)?;
+    book.thumbnail_path = row.get(11)?;
+    book.subtitle = row.get(12)?;
+    book.raw_title = row.get(13)?;
+    book.percent_read = reading_percent(row.get(14)?);
+    book.time_spent_reading_secs = reading_secs(row.get(15)?);
+    book.is_orphaned = row.get(16)?;
+    book.publication_year = row.get(17)?;
+    book.progress_scope = row
+        .get::<_, Option<String>>(18)?
+        .as_deref()
+        .and_then(scope_from_sql);
+    book.kind = row
+        .get::<_, Option<String>>(19)?
+        .as_deref()
+        .and_then(kind_from_sql)
+        .unwrap_or_default();
+    book.source_url = row.get(20)?;
+    // Rows from before the kind column still carry the Kindle prefix
+    if book.content_id.starts_with(KINDLE_CONTENT_PREFIX) {
+        book.kind = BookKind::KindleImport;
+    }
+    Ok(book)
+}
+
+pub struct LibraryStore {
+    conn: Connection,
+    path: Option<PathBuf>,
+    /// Applied to indexed text and to search queries alike
+    normalization: TextNormalization,
+}
+
+impl LibraryStore {
+    /// Open (or create) the library at `path` and run pending migrations
+    pub fn open(path: &Path) -> Result<Self, LibraryError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        Self::init(conn, Some(path.to_path_buf()))
+    }
+
+    /// Read-only connection to the (already migrated) library at `path`
+    ///
+    /// Statements that write fail, so a reader can't break the writer's
+    /// transaction discipline.
+    fn open_reader(path: &Path, normalization: TextNormalization) -> Result<Self, LibraryError> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(Self {
+            conn,
+            path: Some(path.to_path_buf()),
+            normalization,
+        })
+    }
+
+    /// In-memory library (tests)
+    pub fn open_in_memory() -> Result<Self, LibraryError> {
+        Self::init(Connection::open_in_memory()?, None)
+    }
+
+    fn init(conn: Connection, path: Option<PathBuf>) -> Result<Self, LibraryError> {
+        conn.execute_batch("PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL;")?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        let mut store = Self {
+            conn,
+            path,
+            normalization: TextNormalization::default(),
+        };
+        store.migrate()?;
+        Ok(store)
+    }
+
+    fn schema_version(&self) -> Result<u32, LibraryError> {
+        Ok(self
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?)
+    }
+
+    fn migrate(&mut self) -> Result<(), LibraryError> {
+        let current = self.schema_version()? as usize;
+        if current > MIGRATIONS.len() {
+            return Err(LibraryError::UnsupportedVersion(current as u32));
+        }
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+            let tx = self.conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", (index + 1) as u32)?;
+            tx.commit()?;
+            log::info!("[Library] Applied migration {}", index + 1);
+        }
+        self.backfill_slugs()?;
+        // Merges fill the column since; rows left NULL then can't be parsed
+        if current < CREATED_UTC_VERSION {
+            self.backfill_created_utc()?;
+        }
+        Ok(())
+    }
+
+    /// Give books stored before slugs existed the slug of their content ID
+    fn backfill_slugs(&mut self) -> Result<(), LibraryError> {
+        let tx = self.conn.transaction()?;
+        let missing: Vec<(String, String)> = {
+            let mut stmt = tx.prepare("SELECT content_id, title FROM books WHERE slug IS NULL")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<Result<_, _>>()?
+        };
+        for (content_id, title) in &missing {
+            tx.execute(
+                "UPDATE books SET slug = ?1 WHERE content_id = ?2",
+                params![book_slug(content_id, title), content_id],
+            )?;
+        }
+        tx.commit()?;
+        if !missing.is_empty() {
+            log::info!("[Library] Backfilled {} book slugs", missing.len());
+        }
+        Ok(())
+    }
+
+    /// Normalize the creation dates of highlights stored before they were
+    /// (zone-less values are taken as UTC, as they were then)
+    fn backfill_created_utc(&mut self) -> Result<(), LibraryError> {
+        let tx = self.conn.transaction()?;
+        let missing: Vec<(String, String)> = {
+            let mut stmt = tx.prepare(
+                "SELECT stable_id, date_created FROM highlights WHERE date_created_utc IS NULL",
+            )?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<Result<_, _>>()?
+        };
+        let mut filled = 0;
+        for (stable_id, date_created) in &missing {
+            if let Some(created) = parse_timestamp(date_created, utc_offset()) {
+                filled += tx.execute(
+                    "UPDATE highlights SET date_created_utc = ?1 WHERE stable_id = ?2",
+                    params![to_sortable(&created), stable_id],
+                )?;
+            }
+        }
+        tx.commit()?;
+        if filled > 0 {
+            log::info!("[Library] Backfilled {} highlight dates", filled);
+        }
+        Ok(())
+    }
+
+    /// Use `normalization` for highlights merged and queries searched from now on
+    pub fn set_text_normalization(&mut self, normalization: TextNormalization) {
+        self.normalization = normalization;
+    }
+
+    /// Merge imported books into the library
+    ///
+    /// Books upsert by `content_id`, highlights by stable ID (falling back to
+    /// the device ID for legacy data). Unchanged rows are left alone, so
+    /// merging the same import twice is a no-op. `is_excluded` follows the
+    /// device, so a highlight deleted there is excluded here, never removed.
+    /// Highlights of a removed book get back what its removal parked.
+    ///
+    /// Books are committed in chunks of about `MERGE_CHUNK_HIGHLIGHTS`
+    /// highlights, all under one revision. A failure keeps the chunks
+    /// already committed; merging the import again completes it.
+    pub fn merge_books(&mut self, books: &[Book]) -> Result<MergeStats, LibraryError> {
+        let mut stats = MergeStats::default();
+        let revision = current_revision(&self.conn)? + 1;
+        let mut chunks = 0;
+        for chunk in merge_chunks(books) {
+            self.merge_chunk(chunk, revision, &mut stats)?;
+            chunks += 1;
+        }
+        if chunks > 1 {
+            log::debug!(
+                "[Library] Merged {} books in {} transactions",
+                books.len(),
+                chunks
+            );
+        }
+        Ok(stats)
+    }
+
+    /// Merge `books` in one transaction, adding to `stats`
+    fn merge_chunk(
+        &mut self,
+        books: &[Book],
+        revision: u64,
+        stats: &mut MergeStats,
+    ) -> Result<(), LibraryError> {
+        let tx = self.conn.transaction()?;
+        let mut changed = false;
+
+        for book in books {
+            let stored: Option<Option<f64>> = tx
+                .query_row(
+                    "SELECT percent_read FROM books WHERE content_id = ?1",
+                    [&book.content_id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            match stored {
+                None => stats.books_added += 1,
+                Some(before) => {
+                    let finished = |percent: Option<f64>| percent.is_some_and(|p| p >= 100.0);
+                    if finished(book.percent_read) && !finished(before) {
+                        stats.books_finished += 1;
+                    }
+                }
+            }
+            // A stored slug is kept, so routes and filenames survive retitling
+            let slug = if book.slug.is_empty() {
+                book_slug(&book.content_id, &book.title)
+            } else {
+                book.slug.clone()
+            };
+
+            let book_changed = tx.execute(
+                "INSERT INTO books (content_id, title, author, isbn, publisher, language,
+                                    date_last_read, description, cover_path, slug,
+                                    thumbnail_path, subtitle, raw_title, percent_read,
+                                    time_spent_reading_secs, is_orphaned, publication_year,
+                                    progress_scope, kind, source_url)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16,
+                         ?17, ?18, ?19, ?20)
+                 ON CONFLICT(content_id) DO UPDATE SET
+                    title = excluded.title,
+                    subtitle = excluded.subtitle,
+                    raw_title = excluded.raw_title,
+                    author = excluded.author,
+                    isbn = COALESCE(excluded.isbn, isbn),
+                    publisher = COALESCE(excluded.publisher, publisher),
+                    language = COALESCE(excluded.language, language),
+                    date_last_read = COALESCE(excluded.date_last_read, date_last_read),
+                    description = COALESCE(excluded.description, description),
+                    cover_path = COALESCE(excluded.cover_path, cover_path),
+                    slug = COALESCE(slug, excluded.slug),
+                    thumbnail_path = COALESCE(excluded.thumbnail_path, thumbnail_path),
+                    percent_read = COALESCE(excluded.percent_read, percent_read),
+                    time_spent_reading_secs =
+                        COALESCE(excluded.time_spent_reading_secs, time_spent_reading_secs),
+                    is_orphaned = excluded.is_orphaned,
+                    publication_year = COALESCE(excluded.publication_year, publication_year),
+                    progress_scope = COALESCE(excluded.progress_scope, progress_scope),
+                    kind = excluded.kind,
+                    source_url = COALESCE(excluded.source_url, source_url)
+                 WHERE title IS NOT excluded.title
+                    OR subtitle IS NOT excluded.subtitle
+                    OR raw_title IS NOT excluded.raw_title
+                    OR author IS NOT excluded.author
+                    OR is_orphaned IS NOT excluded.is_orphaned
+                    OR kind IS NOT excluded.kind
+                    OR slug IS NULL
+                    OR (excluded.isbn IS NOT NULL AND isbn IS NOT excluded.isbn)
+                    OR (excluded.publisher IS NOT NULL AND publisher IS NOT excluded.publisher)
+                    OR (excluded.language IS NOT NULL AND language IS NOT excluded.language)
+                    OR (excluded.date_last_read IS NOT NULL
+                        AND date_last_read IS NOT excluded.date_last_read)
+                    OR (excluded.description IS NOT NULL
+                        AND description IS NOT excluded.description)
+                    OR (excluded.cover_path IS NOT NULL AND cover_path IS NOT excluded.cover_path)
+                    OR (excluded.thumbnail_path IS NOT NULL
+                        AND thumbnail_path IS NOT excluded.thumbnail_path)
+                    OR (excluded.percent_read IS NOT NULL
+                        AND percent_read IS NOT excluded.percent_read)
+                    OR (excluded.time_spent_reading_secs IS NOT NULL
+                        AND time_spent_reading_secs IS NOT excluded.time_spent_reading_secs)
+                    OR (excluded.publication_year IS NOT NULL
+                        AND publication_year IS NOT excluded.publication_year)
+                    OR (excluded.progress_scope IS NOT NULL
+                        AND progress_scope IS NOT excluded.progress_scope)
+                    OR (excluded.source_url IS NOT NULL
+                        AND source_url IS NOT excluded.source_url)",
+                params![
+                    book.content_id,
+                    book.title,
+                    book.author,
+                    book.isbn,
+                    book.publisher,
+                    book.language,
+                    book.date_last_read,
+                    book.description,
+                    book.cover_path,
+                    slug,
+                    book.thumbnail_path,
+                    book.subtitle,
+                    book.raw_title,
+                    book.percent_read,
+                    book.time_spent_reading_secs.map(|secs| secs as i64),
+                    book.is_orphaned,
+                    book.publication_year,
+                    book.progress_scope.map(scope_to_sql),
+                    kind_to_sql(book.kind),
+                    book.source_url,
+                ],
+            )?;
+            changed |= book_changed > 0;
+
+            for highlight in &book.highlights {
+                let key = library_key(highlight);
+                let text = self.normalization.normalize(&highlight.text);
+                let annotation = highlight
+                    .annotation
+                    .as_deref()
+                    .map(|note| self.normalization.normalize(note));
+                let exists: bool = tx
+                    .query_row(
+                        "SELECT 1 FROM highlights WHERE stable_id = ?1",
+                        [&key],
+                        |_| Ok(true),
+                    )
+                    .optional()?
+                    .unwrap_or(false);
+
+                let highlight_changed = tx.execute(
+                    "INSERT INTO highlights (stable_id, device_id, content_id, text, annotation,
+                                             chapter_title, chapter_progress, container_path,
+                                             date_created, color, is_excluded, revision,
+                                             date_created_utc, figure_path)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+                     ON CONFLICT(stable_id) DO UPDATE SET
+                        device_id = excluded.device_id,
+                        text = excluded.text,
+                        annotation = excluded.annotation,
+                        chapter_title = excluded.chapter_title,
+                        chapter_progress = excluded.chapter_progress,
+                        container_path = excluded.container_path,
+                        date_created = excluded.date_created,
+                        color = excluded.color,
+                        is_excluded = excluded.is_excluded,
+                        date_created_utc = excluded.date_created_utc,
+                        figure_path = COALESCE(excluded.figure_path, figure_path),
+                        revision = excluded.revision
+                     WHERE device_id IS NOT excluded.device_id
+                        OR text IS NOT excluded.text
+                        OR annotation IS NOT excluded.annotation
+                        OR chapter_title IS NOT excluded.chapter_title
+                        OR chapter_progress IS NOT excluded.chapter_progress
+                        OR container_path IS NOT excluded.container_path
+                        OR date_created IS NOT excluded.date_created
+                        OR color IS NOT excluded.color
+                        OR is_excluded IS NOT excluded.is_excluded
+                        OR date_created_utc IS NOT excluded.date_created_utc
+                        OR (excluded.figure_path IS NOT NULL
+                            AND figure_path IS NOT excluded.figure_path)",
+                    params![
+                        key,
+                        highlight.id,
+                        book.content_id,
+                        text,
+                        annotation,
+                        highlight.chapter_title,
+                        highlight.chapter_progress,
+                        highlight.container_path,
+                        highlight.date_created,
+                        highlight.color,
+                        highlight.is_excluded,
+                        revision,
+                        highlight.created_at().as_ref().map(to_sortable),
+                        highlight.figure_path,
+                    ],
+                )?;
+
+                changed |= highlight_changed > 0;
+                match (exists, highlight_changed > 0) {
+                    (false, _) => {
+                        removal::restore_parked(&tx, key)?;
+                        stats.highlights_added += 1;
+                    }
+                    (true, true) => stats.highlights_updated += 1,
+                    (true, false) => {}
+                }
+            }
+        }
+
+        if changed {
+            set_revision(&tx, revision)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Mark library highlights as excluded, e.g. ones deleted on the device
+    /// whose hidden rows were not imported
+    ///
+    /// Unknown highlights are ignored; returns how many became excluded.
+    pub fn exclude_highlights(&mut self, highlights: &[Highlight]) -> Result<usize, LibraryError> {
+        let tx = self.conn.transaction()?;
+        let mut excluded = 0;
+        for highlight in highlights {
+            excluded += tx.execute(
+                "UPDATE highlights SET is_excluded = 1 WHERE stable_id = ?1 AND is_excluded = 0",
+                [library_key(highlight)],
+            )?;
+        }
+        if excluded > 0 {
+            bump_revision(&tx)?;
+        }
+        tx.commit()?;
+        Ok(excluded)
+    }
+
+    /// Replace the chapters (titles or spine files) excluded from a book's
+    /// exports; an empty list clears them
+    ///
+    /// The book doesn't need to be in the library yet.
+    pub fn set_excluded_chapters(
+        &mut self,
+        content_id: &str,
+        chapters: &[String],
+    ) -> Result<(), LibraryError> {
+        let chapters: BTreeSet<&str> = chapters
+            .iter()
+            .map(|c| c.trim())
+            .filter(|c| !c.is_empty())
+            .collect();
+        let tx = self.conn.transaction()?;
+        let stored: BTreeSet<String> = {
+            let mut stmt =
+                tx.prepare("SELECT chapter FROM chapter_exclusions WHERE content_id = ?1")?;
+            let rows = stmt.query_map([content_id], |row| row.get(0))?;
+            rows.collect::<Result<_, _>>()?
+        };
+        if stored
+            .iter()
+            .map(String::as_str)
+            .eq(chapters.iter().copied())
+        {
+            return Ok(());
+        }
+
+        tx.execute(
+            "DELETE FROM chapter_exclusions WHERE content_id = ?1",
+            [content_id],
+        )?;
+        for chapter in chapters {
+            tx.execute(
+                "INSERT INTO chapter_exclusions (content_id, chapter) VALUES (?1, ?2)",
+                params![content_id, chapter],
+            )?;
+        }
+        bump_revision(&tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Save a book's reading notes (markdown); blank notes delete them
+    ///
+    /// Notes are keyed by content ID and live only in the library: imports
+    /// never touch them and removing the book keeps them unless asked not to.
+    pub fn set_book_notes(&mut self, content_id: &str, notes: &str) -> Result<(), LibraryError> {
+        let tx = self.conn.transaction()?;
+        let changed = if notes.trim().is_empty() {
+            tx.execute("DELETE FROM book_notes WHERE content_id = ?1", [content_id])?
+        } else {
+            tx.execute(
+                "INSERT INTO book_notes (content_id, notes, updated_at) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(content_id) DO UPDATE SET
+                    notes = excluded.notes,
+                    updated_at = excluded.updated_at
+                 WHERE notes IS NOT excluded.notes",
+                params![content_id, notes, chrono::Utc::now().to_rfc3339()],
+            )?
+        };
+        if changed > 0 {
+            bump_revision(&tx)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Normalize the stored highlight texts and notes again with the current
+    /// normalization; returns how many highlights changed
+    ///
+    /// Stored texts are already normalized, so only newly enabled steps take
+    /// effect. Changed highlights get a new revision and count as unsynced.
+    pub fn renormalize_highlights(&mut self) -> Result<usize, LibraryError> {
+        let tx = self.conn.transaction()?;
+        let stored: Vec<(i64, String, Option<String>)> = {
+            let mut stmt = tx.prepare("SELECT rowid, text, annotation FROM highlights")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+            rows.collect::<Result<_, _>>()?
+        };
+        let revision = current_revision(&tx)? + 1;
+
+        let mut changed = 0;
+        for (rowid, text, annotation) in &stored {
+            let normalized = self.normalization.normalize(text);
+            let normalized_annotation = annotation
+                .as_deref()
+                .map(|note| self.normalization.normalize(note));
+            if (&normalized, &normalized_annotation) != (text, annotation) {
+                changed += tx.execute(
+                    "UPDATE highlights SET text = ?1, annotation = ?2, revision = ?3
+                     WHERE rowid = ?4",
+                    params![normalized, normalized_annotation, revision as i64, rowid],
+                )?;
+            }
+        }
+        if changed > 0 {
+            set_revision(&tx, revision)?;
+        }
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    /// Process the stored titles again with `options`, from their raw
+    /// titles; returns how many books changed
+    pub fn reprocess_titles(&mut self, options: &TitleOptions) -> Result<usize, LibraryError> {
+        let tx = self.conn.transaction()?;
+        let stored: Vec<Book> = {
+            let mut stmt =
+                tx.prepare("SELECT content_id, title, subtitle, raw_title, language FROM books")?;
+            let rows = stmt.query_map([], |row| {
+                let mut book = Book::new(row.get(0)?, row.get(1)?, String::new());
+                book.subtitle = row.get(2)?;
+                book.raw_title = row.get(3)?;
+                book.language = row.get(4)?;
+                Ok(book)
+            })?;
+            rows.collect::<Result<_, _>>()?
+        };
+        let mut processed = stored.clone();
+        process_titles(&mut processed, options);
+
+        let mut changed = 0;
+        for (before, after) in stored.iter().zip(&processed) {
+            if (&before.title, &before.subtitle, &before.raw_title)
+                != (&after.title, &after.subtitle, &after.raw_title)
+            {
+                changed += tx.execute(
+                    "UPDATE books SET title = ?1, subtitle = ?2, raw_title = ?3
+                     WHERE content_id = ?4",
+                    params![
+                        after.title,
+                        after.subtitle,
+                        after.raw_title,
+                        after.content_id
+                    ],
+                )?;
+            }
+        }
+        if changed > 0 {
+            bump_revision(&tx)?;
+        }
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    /// A book's reading notes, if it has any
+    pub fn book_notes(&self, content_id: &str) -> Result<Option<String>, LibraryError> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT notes FROM book_notes WHERE content_id = ?1",
+                [content_id],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Tags of every tagged highlight, alphabetized, by stable ID
+    pub fn highlight_tags(&self) -> Result<HashMap<String, Vec<String>>, LibraryError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT stable_id, tag FROM tags ORDER BY stable_id, tag")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+        for row in rows {
+            let (stable_id, tag) = row?;
+            tags.entry(stable_id).or_default().push(tag);
+        }
+        Ok(tags)
+    }
+
+    /// Excluded chapters of every book, by content ID
+    pub fn chapter_exclusions(&self) -> Result<HashMap<String, Vec<String>>, LibraryError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT content_id, chapter FROM chapter_exclusions ORDER BY content_id, rowid",
+        )?;
+        let mut exclusions: HashMap<String, Vec<String>> = HashMap::new();
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (content_id, chapter) = row?;
+            exclusions.entry(content_id).or_default().push(chapter);
+        }
+        Ok(exclusions)
+    }
+
+    /// Cached cover path of every book that has one, by content ID
+    pub fn cover_paths(&self) -> Result<Vec<(String, String)>, LibraryError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT content_id, cover_path FROM books WHERE cover_path IS NOT NULL",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Point a book at a new cover file
+    pub fn set_cover_path(&self, content_id: &str, cover_path: &str) -> Result<(), LibraryError> {
+        self.conn.execute(
+            "UPDATE books SET cover_path = ?2 WHERE content_id = ?1",
+            params![content_id, cover_path],
+        )?;
+        Ok(())
+    }
+
+    /// Point a book at a new cover thumbnail
+    pub fn set_thumbnail_path(
+        &self,
+        content_id: &str,
+        thumbnail_path: &str,
+    ) -> Result<(), LibraryError> {
+        self.conn.execute(
+            "UPDATE books SET thumbnail_path = ?2 WHERE content_id = ?1",
+            params![content_id, thumbnail_path],
+        )?;
+        Ok(())
+    }
+
+    /// Content ID of the book with `slug`
+    pub fn content_id_for_slug(&self, slug: &str) -> Result<Option<String>, LibraryError> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT content_id FROM books WHERE slug = ?1",
+                [slug],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Stored slug of every book, by content ID
+    ///
+    /// A book keeps the slug it was first merged with, whatever its title
+    /// becomes.
+    pub fn slugs(&self) -> Result<HashMap<String, String>, LibraryError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT content_id, slug FROM books WHERE slug IS NOT NULL")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Every book with its highlights, by title
+    ///
+    /// Highlights excluded on the device are left out.
+    pub fn books(&self) -> Result<Vec<Book>, LibraryError> {
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT {} FROM books ORDER BY title, content_id",
+            BOOK_COLUMNS
+        ))?;
+        let mut books = stmt
+            .query_map([], book_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        for book in &mut books {
+            book.highlights = self.highlights_of(&book.content_id)?;
+        }
+        assign_disambiguators(&mut books);
+        Ok(books)
+    }
+
+    /// The book with `content_id` and its highlights, as `books` has it
+    pub fn book(&self, content_id: &str) -> Result<Option<Book>, LibraryError> {
+        let mut book = self
+            .conn
+            .query_row(
+                &format!("SELECT {} FROM books WHERE content_id = ?1", BOOK_COLUMNS),
+                [content_id],
+                book_from_row,
+            )
+            .optional()?;
+        if let Some(book) = &mut book {
+            book.highlights = self.highlights_of(content_id)?;
+            book.disambiguator = self.disambiguators()?.remove(content_id).flatten();
+        }
+        Ok(book)
+    }
+
+    /// Disambiguator of every book, told apart from the same-title-author
+    /// books of the whole library rather than of one import or export
+    pub fn disambiguators(&self) -> Result<HashMap<String, Option<String>>, LibraryError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT content_id, title, author, slug, date_last_read, publication_year FROM books",
+        )?;
+        let books = stmt
+            .query_map([], |row| {
+                let mut book = Book::new(row.get(0)?, row.get(1)?, row.get(2)?);
+                book.slug = row.get::<_, Option<String>>(3)?.unwrap_or_default();
+                book.date_last_read = row.get(4)?;
+                book.publication_year = row.get(5)?;
+                Ok(book)
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(books
+            .iter()
+            .map(|book| book.content_id.clone())
+            .zip(disambiguators(&books))
+            .collect())
+    }
+
+    fn highlights_of(&self, content_id: &str) -> Result<Vec<Highlight>, LibraryError> {
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT {} FROM highlights
+             WHERE content_id = ?1 AND is_excluded = 0
+             ORDER BY {}",
+            HIGHLIGHT_COLUMNS,
+            self.highlight_order(content_id)?
+        ))?;
+        let highlights = stmt
+            .query_map([content_id], highlight_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(highlights)
+    }
+
+    /// `ORDER BY` of a book's highlights: by position, unless positions
+    /// are chapter-relative (kepubs), where chapters would interleave, so by
+    /// creation. Either way total (ties broken by stable ID).
+    fn highlight_order(&self, content_id: &str) -> Result<&'static str, LibraryError> {
+        let scope: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT progress_scope FROM books WHERE content_id = ?1",
+                [content_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        Ok(match scope.as_deref().and_then(scope_from_sql) {
+            Some(ProgressScope::Chapter) => CREATION_ORDER,
+            _ => POSITION_ORDER,
+        })
+    }
+
+    /// `limit` highlights of a book from `offset`, in reading order
+    ///
+    /// Highlights excluded on the device are left out. The order is total
+    /// (ties broken by stable ID), so consecutive pages never overlap.
+    pub fn book_highlights(
+        &self,
+        content_id: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<HighlightPage, LibraryError> {
+        let total: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM highlights WHERE content_id = ?1 AND is_excluded = 0",
+            [content_id],
+            |row| row.get(0),
+        )?;
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT {}
+             FROM highlights
+             WHERE content_id = ?1 AND is_excluded = 0
+             ORDER BY {}
+             LIMIT ?2 OFFSET ?3",
+            HIGHLIGHT_COLUMNS,
+            self.highlight_order(content_id)?
+        ))?;
+        let highlights = stmt
+            .query_map(
+                params![content_id, limit as i64, offset as i64],
+                highlight_from_row,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(HighlightPage {
+            content_id: content_id.to_string(),
+            total: total as usize,
+            offset,
+            highlights,
+        })
+    }
+
+    /// The book (without its highlights) and highlight of every ref, in
+    /// order; refs that no longer resolve get the reason instead
+    pub fn resolve_highlights(
+        &self,
+        refs: &[HighlightRef],
+    ) -> Result<Vec<ResolvedHighlight>, LibraryError> {
+        let mut books: HashMap<String, Option<Book>> = HashMap::new();
+        let mut book_stmt = self.conn.prepare_cached(&format!(
+            "SELECT {} FROM books WHERE content_id = ?1",
+            BOOK_COLUMNS
+        ))?;
+        let mut highlight_stmt = self.conn.prepare_cached(&format!(
+            "SELECT {} FROM highlights
+             WHERE content_id = ?1 AND (stable_id = ?2 OR device_id = ?2) AND is_excluded = 0
+             ORDER BY stable_id = ?2 DESC
+             LIMIT 1",
+            HIGHLIGHT_COLUMNS
+        ))?;
+
+        let mut resolved = Vec::with_capacity(refs.len());
+        for selected in refs {
+            if !books.contains_key(&selected.content_id) {
+                let book = book_stmt
+                    .query_row([&selected.content_id], book_from_row)
+                    .optional()?;
+                books.insert(selected.content_id.clone(), book);
+            }
+            let Some(book) = &books[&selected.content_id] else {
+                resolved.push(Err(MissingReason::Book));
+                continue;
+            };
+            let highlight = highlight_stmt
+                .query_row(
+                    params![selected.content_id, selected.highlight_id],
+                    highlight_from_row,
+                )
+                .optional()?;
+            resolved.push(match highlight {
+                Some(highlight) => Ok((book.clone(), highlight)),
+                None => Err(MissingReason::Highlight),
+            });
+        }
+        Ok(resolved)
+    }
+
+    /// Full-text search over highlight text and notes, best matches first
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, LibraryError> {
+        let match_expr = fts_query(&self.normalization.normalize(query));
+        if match_expr.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT h.stable_id, h.content_id, b.title, h.text,
+                    highlight(highlights_fts, 0, char(1), char(2)),
+                    highlight(highlights_fts, 1, char(1), char(2)),
+                    bm25(highlights_fts)
+             FROM highlights_fts
+             JOIN highlights h ON h.rowid = highlights_fts.rowid
+             JOIN books b ON b.content_id = h.content_id
+             WHERE highlights_fts MATCH ?1
+             ORDER BY bm25(highlights_fts), h.stable_id
+             LIMIT ?2",
+        )?;
+        let hits = stmt
+            .query_map(params![match_expr, limit as i64], |row| {
+                let text: String = row.get(3)?;
+                let marked_text: Option<String> = row.get(4)?;
+                let marked_note: Option<String> = row.get(5)?;
+                let (snippet, range) = [marked_text, marked_note]
+                    .into_iter()
+                    .flatten()
+                    .find_map(|marked| match_snippet(&marked))
+                    .map(|s| (s.text, Some(s.highlight)))
+                    .unwrap_or_else(|| (ellipsize(&text, SNIPPET_CONTEXT * 2), None));
+
+                Ok(SearchHit {
+                    stable_id: row.get(0)?,
+                    content_id: row.get(1)?,
+                    book_title: row.get(2)?,
+                    match_start: range.as_ref().map(|r| utf16_len(&snippet[..r.start])),
+                    match_end: range.as_ref().map(|r| utf16_len(&snippet[..r.end])),
+                    text,
+                    snippet,
+                    rank: row.get(6)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(hits)
+    }
+
+    /// Mark or unmark a highlight as favorite
+    pub fn set_favorite(&mut self, stable_id: &str, favorite: bool) -> Result<(), LibraryError> {
+        let tx = self.conn.transaction()?;
+        let changed = if favorite {
+            tx.execute(
+                "INSERT OR IGNORE INTO favorites (stable_id, created_at) VALUES (?1, ?2)",
+                params![stable_id, chrono::Utc::now().to_rfc3339()],
+            )?
+        } else {
+            tx.execute("DELETE FROM favorites WHERE stable_id = ?1", [stable_id])?
+        };
+        if changed > 0 {
+            bump_revision(&tx)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Record that a highlight was written to an export file
+    pub fn mark_exported(&self, stable_id: &str, export_path: &str) -> Result<(), LibraryError> {
+        self.conn.execute(
+            "INSERT INTO export_tracking (stable_id, exported_at, export_path)
+             VALUES (?1, ?2, ?3)
+             ON CONFLICT(stable_id) DO UPDATE SET
+                exported_at = excluded.exported_at,
+                export_path = excluded.export_path",
+            params![stable_id, chrono::Utc::now().to_rfc3339(), export_path],
+        )?;
+        Ok(())
+    }
+
+    /// `mark_exported` for each (stable ID, export path), in one transaction
+    pub fn mark_exported_many(&mut self, marks: &[(String, String)]) -> Result<(), LibraryError> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO export_tracking (stable_id, exported_at, export_path)
+                 VALUES (?1, ?2, ?3)
+                 ON CONFLICT(stable_id) DO UPDATE SET
+                    exported_at = excluded.exported_at,
+                    export_path = excluded.export_path",
+            )?;
+            let now = chrono::Utc::now().to_rfc3339();
+            for (stable_id, export_path) in marks {
+                stmt.execute(params![stable_id, now, export_path])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Current revision of the library, to pass to `record_full_export` once
+    /// an export started now succeeds
+    pub fn revision(&self) -> Result<u64, LibraryError> {
+        Ok(current_revision(&self.conn)?)
+    }
+
+    /// Record that a full export covered the library as of `revision`
+    ///
+    /// Never moves backwards, so a slow export finishing after a newer one
+    /// can't mark the library dirty again.
+    pub fn record_full_export(&self, revision: u64) -> Result<(), LibraryError> {
+        self.conn.execute(
+            "UPDATE sync_state SET last_exported_revision = MAX(last_exported_revision, ?1)",
+            [revision as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Whether every library book is among `content_ids`, i.e. exporting
+    /// them exports the whole library
+    pub fn covers_library(&self, content_ids: &[&str]) -> Result<bool, LibraryError> {
+        let mut stmt = self.conn.prepare_cached("SELECT content_id FROM books")?;
+        let stored = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        for content_id in stored {
+            if !content_ids.contains(&content_id?.as_str()) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn sync_status(&self) -> Result<SyncStatus, LibraryError> {
+        let (library_revision, last_exported_revision): (i64, i64) = self.conn.query_row(
+            "SELECT revision, last_exported_revision FROM sync_state",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let new_highlights: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM highlights WHERE revision > ?1",
+            [last_exported_revision],
+            |row| row.get(0),
+        )?;
+        Ok(SyncStatus {
+            library_revision: library_revision as u64,
+            last_exported_revision: last_exported_revision as u64,
+            dirty: library_revision > last_exported_revision,
+            new_highlights_since_export: new_highlights as usize,
+        })
+    }
+
+    /// Rebuild the database file to reclaim space
+    pub fn vacuum(&self) -> Result<(), LibraryError> {
+        self.conn.execute_batch(
+            "INSERT INTO highlights_fts(highlights_fts) VALUES ('optimize'); VACUUM;",
+        )?;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Result<LibraryDbStats, LibraryError> {
+        let count = |table: &str| -> Result<usize, LibraryError> {
+            let n: i64 =
+                self.conn
+                    .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                        row.get(0)
+                    })?;
+            Ok(n as usize)
+        };
+
+        Ok(LibraryDbStats {
+            schema_version: self.schema_version()?,
+            books: count("books")?,
+            highlights: count("highlights")?,
+            favorites: count("favorites")?,
+            tags: count("tags")?,
+            exported: count("export_tracking")?,
+            size_bytes: self
+                .path
+                .as_ref()
+                .and_then(|p| std::fs::metadata(p).ok())
+                .map(|m| m.len())
+                .unwrap_or(0),
+        })
+    }
+}
+
+/// Library key of a highlight: stable ID, or device ID for legacy data
+pub(crate) fn library_key(highlight: &Highlight) -> &str {
+    if highlight.stable_id.is_empty() {
+        &highlight.id
+    } else {
+        &highlight.stable_id
+    }
+}
+
+fn current_revision(conn: &Connection) -> rusqlite::Result<u64> {
+    let revision: i64 = conn.query_row("SELECT revision FROM sync_state", [], |row| row.get(0))?;
+    Ok(revision as u64)
+}
+
+/// Raise the library revision to `revision` (a write committed meanwhile
+/// may already have gone past it)
+fn set_revision(conn: &Connection, revision: u64) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE sync_state SET revision = MAX(revision, ?1)",
+        [revision as i64],
+    )?;
+    Ok(())
+}
+
+/// Bump the library revision; call inside the transaction of the change, so
+/// the change and its revision are committed together or not at all
+fn bump_revision(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute("UPDATE sync_state SET revision = revision + 1", [])?;
+    Ok(())
+}
+
+/// Graphemes of context kept on each side of a search match
+const SNIPPET_CONTEXT: usize = 40;
+
+/// Snippet around the first match in FTS5 `highlight()` output (marked with
+/// U+0001/U+0002), or `None` if the column has no match
+fn match_snippet(marked: &str) -> Option<Snippet> {
+    let start = marked.find('\u{1}')?;
+    let end = marked[start..].find('\u{2}')? + start;
+    let plain: String = marked
+        .chars()
+        .filter(|c| !matches!(c, '\u{1}' | '\u{2}'))
+        .collect();
+
+    // Markers are one byte each; only the opening one precedes `end`
+    let marks_before = marked[..start].matches(['\u{1}', '\u{2}']).count();
+    let range = start - marks_before..end - marks_before - 1;
+    Some(snippet_around(&plain, range, SNIPPET_CONTEXT))
+}
+
+fn utf16_len(s: &str) -> usize {
+    s.encode_utf16().count()
+}
+
+/// Turn free text into an FTS5 query: every word quoted, all required
+fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Split `books` into runs of about `MERGE_CHUNK_HIGHLIGHTS` highlights
+fn merge_chunks(books: &[Book]) -> Vec<&[Book]> {
+    let mut chunks = Vec::new();
+    let (mut start, mut highlights) = (0, 0);
+    for (index, book) in books.iter().enumerate() {
+        if index > start && highlights + book.highlights.len() > MERGE_CHUNK_HIGHLIGHTS {
+            chunks.push(&books[start..index]);
+            (start, highlights) = (index, 0);
+        }
+        highlights += book.highlights.len();
+    }
+    if start < books.len() {
+        chunks.push(&books[start..]);
+    }
+    chunks
+}
+
+/// Read-only connections of the installed library
+#[derive(Default)]
+struct ReaderPool {
+    /// `None` while no file-backed library is installed
+    path: Option<PathBuf>,
+    /// The writer's, so searches match what was indexed
+    normalization: TextNormalization,
+    idle: Vec<LibraryStore>,
+}
+
+/// Managed handle to the library (empty until opened in `setup()`)
+#[derive(Default)]
+pub struct LibraryState {
+    store: Mutex<Option<LibraryStore>>,
+    readers: Mutex<ReaderPool>,
+}
+
+impl LibraryState {
+    pub fn install(&self, store: LibraryStore) {
+        let mut guard = self.store.lock().unwrap_or_else(|e| e.into_inner());
+        *self.readers.lock().unwrap_or_else(|e| e.into_inner()) = ReaderPool {
+            path: store.path.clone(),
+            normalization: store.normalization.clone(),
+            idle: Vec::new(),
+        };
+        *guard = Some(store);
+    }
+
+    /// Run a closure against the library, if it is open
+    ///
+    /// Callers are served one at a time; reads that don't need to see their
+    /// own writes should use `with_reader`.
+    pub fn with_store<T>(
+        &self,
+        f: impl FnOnce(&mut LibraryStore) -> Result<T, LibraryError>,
+    ) -> Result<T, LibraryError> {
+        let mut guard = self.store.lock().unwrap_or_else(|e| e.into_inner());
+        let store = guard.as_mut().ok_or(LibraryError::Unavailable)?;
+        let result = f(store);
+        let mut readers = self.readers.lock().unwrap_or_else(|e| e.into_inner());
+        if readers.normalization != store.normalization {
+            readers.normalization = store.normalization.clone();
+        }
+        result
+    }
+
+    /// Merge an import like `LibraryStore::merge_books`, holding the writer
+    /// for one chunk at a time, so other writes (tags, favorites, notes)
+    /// aren't held up until a large import ends
+    ///
+    /// A chunk that finds the database busy is retried after each of
+    /// `MERGE_RETRY_DELAYS`, without holding the writer while it waits.
+    pub fn merge_books(&self, books: &[Book]) -> Result<MergeStats, LibraryError> {
+        let revision = self.with_store(|store| Ok(current_revision(&store.conn)? + 1))?;
+        let mut stats = MergeStats::default();
+        for chunk in merge_chunks(books) {
+            let mut delays = MERGE_RETRY_DELAYS.iter();
+            loop {
+                let mut attempt = stats.clone();
+                match self.with_store(|store| store.merge_chunk(chunk, revision, &mut attempt)) {
+                    Ok(()) => {
+                        stats = attempt;
+                        break;
+                    }
+                    Err(e) if e.is_busy() => match delays.next() {
+                        Some(delay) => {
+                            log::warn!("[Library] Database busy, merge retried in {:?}", delay);
+                            std::thread::sleep(*delay);
+                        }
+                        None => return Err(e),
+                    },
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Run a read-only closure on a connection of its own, without waiting
+    /// for `with_store` callers such as a running merge
+    ///
+    /// It sees the last committed state. An in-memory library has no other
+    /// connection, so there this is `with_store`.
+    pub fn with_reader<T>(
+        &self,
+        f: impl FnOnce(&LibraryStore) -> Result<T, LibraryError>,
+    ) -> Result<T, LibraryError> {
+        let checkout = {
+            let mut readers = self.readers.lock().unwrap_or_else(|e| e.into_inner());
+            let normalization = readers.normalization.clone();
+            readers.path.clone().map(|path| match readers.idle.pop() {
+                Some(mut reader) => {
+                    reader.normalization = normalization;
+                    Ok(reader)
+                }
+                None => LibraryStore::open_reader(&path, normalization),
+            })
+        };
+        let reader = match checkout {
+            Some(reader) => reader?,
+            None => return self.with_store(|store| f(store)),
+        };
+
+        let result = f(&reader);
+        let mut readers = self.readers.lock().unwrap_or_else(|e| e.into_inner());
+        // Connections of a library replaced in the meantime are dropped
+        if result.is_ok() && readers.path == reader.path && readers.idle.len() < MAX_IDLE_READERS {
+            readers.idle.push(reader);
+        }
+        result
+    }
+}
+
+/// Library-related errors
+#[derive(Debug)]
+pub enum LibraryError {
+    /// SQLite error
+    Sqlite(rusqlite::Error),
+    /// IO error
+    Io(std::io::Error),
+    /// The database was written by a newer version of the app
+    UnsupportedVersion(u32),
+    /// The library could not be opened at startup
+    Unavailable,
+}
+
+impl std::fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LibraryError::Sqlite(e) => write!(f, "Database error: {}", e),
+            LibraryError::Io(e) => write!(f, "IO error: {}", e),
+            LibraryError::UnsupportedVersion(v) => {
+                write!(f, "Library schema version {} is newer than supported", v)
+            }
+            LibraryError::Unavailable => write!(f, "Library is not available"),
+        }
+    }
+}
+
+impl LibraryError {
+    /// Whether the database file itself is damaged (not merely locked or
+    /// from a newer version), so it may be moved aside
+    pub fn is_corruption(&self) -> bool {
+        match self {
+            LibraryError::Sqlite(rusqlite::Error::SqliteFailure(e, _)) => matches!(
+                e.code,
+                rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase
+            ),
+            _ => false,
+        }
+    }
+
+    /// Whether another connection held the database, so trying again later
+    /// may succeed
+    pub fn is_busy(&self) -> bool {
+        match self {
+            LibraryError::Sqlite(rusqlite::Error::SqliteFailure(e, _)) => matches!(
+                e.code,
+                rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl std::error::Error for LibraryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LibraryError::Sqlite(e) => Some(e),
+            LibraryError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for LibraryError {
+    fn from(err: rusqlite::Error) -> Self {
+        LibraryError::Sqlite(err)
+    }
+}
+
+impl From<std::io::Error> for LibraryError {
+    fn from(err: std::io::Error) -> Self {
+        LibraryError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
+
+    const WORDS: &[&str] = &[
+        "river", "memory", "garden", "silence", "window", "harbor", "lantern", "meadow",
+    ];
+
+    /// 100 books × 100 highlights of synthetic text
+    fn synthetic_books() -> Vec<Book> {
+        (0..100)
+            .map(|b| {
+                let mut book = Book::new(
+                    format!("vol{}", b),
+                    format!("Book {}", b),
+                    format!("Author {}", b % 7),
+                );
+                for h in 0..100 {
+                    let n = b * 100 + h;
+                    let mut highlight = Highlight::new(
+                        format!("bm{}", n),
+                        format!(
+                            "Passage {} about the {} and the {}",
+                            n,
+                            WORDS[n % WORDS.len()],
+                            WORDS[(n / 3) % WORDS.len()]
+                        ),
+                        "2025-01-24T10:00:00.000".to_string(),
+                    );
+                    highlight.stable_id = format!("s{}", n);
+                    if n % 10 == 0 {
+                        highlight.annotation = Some("Remember the lighthouse".to_string());
+                    }
+                    book.highlights.push(highlight);
+                }
+                book
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_migrations_set_user_version() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("library.sqlite");
+        LibraryStore::open(&path).unwrap();
+
+        // Reopening runs no migration twice
+        let store = LibraryStore::open(&path).unwrap();
+        assert_eq!(
+            store.stats().unwrap().schema_version,
+            MIGRATIONS.len() as u32
+        );
+    }
+
+    #[test]
+    fn test_slugs_backfilled_and_kept_on_retitle() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("library.sqlite");
+        let mut store = LibraryStore::open(&path).unwrap();
+        let mut book = Book::new(
+            "file:///mnt/onboard/Walden (1854).epub".to_string(),
+            "Walden".to_string(),
+            "Thoreau".to_string(),
+        );
+        store.merge_books(std::slice::from_ref(&book)).unwrap();
+        // Simulate a library written before slugs existed
+        store
+            .conn
+            .execute("UPDATE books SET slug = NULL", [])
+            .unwrap();
+        drop(store);
+
+        let mut store = LibraryStore::open(&path).unwrap();
+        let slug = book_slug(&book.content_id, "Walden");
+        assert_eq!(
+            store.content_id_for_slug(&slug).unwrap().as_deref(),
+            Some(book.content_id.as_str())
+        );
+
+        book.title = "Walden; or, Life in the Woods".to_string();
+        book.slug = book_slug(&book.content_id, &book.title);
+        store.merge_books(std::slice::from_ref(&book)).unwrap();
+        assert!(store.content_id_for_slug(&slug).unwrap().is_some());
+        assert_eq!(store.content_id_for_slug("walden").unwrap(), None);
+        assert_eq!(store.slugs().unwrap().get(&book.content_id), Some(&slug));
+    }
+
+    #[test]
+    fn test_created_utc_backfilled_once() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("library.sqlite");
+        let mut store = LibraryStore::open(&path).unwrap();
+        let mut book = Book::new("b".to_string(), "Book".to_string(), "Author".to_string());
+        for (id, date) in [("h1", "2025-03-01T10:00:00"), ("h2", "Unknown")] {
+            let mut highlight = Highlight::new(id.to_string(), id.to_string(), date.to_string());
+            highlight.stable_id = id.to_string();
+            book.highlights.push(highlight);
+        }
+        store.merge_books(&[book]).unwrap();
+        let null_rows = |store: &LibraryStore| -> i64 {
+            store
+                .conn
+                .query_row(
+                    "SELECT COUNT(*) FROM highlights WHERE date_created_utc IS NULL",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert_eq!(null_rows(&store), 1);
+
+        // Rows of a library written before the column existed are filled
+        // when its migration runs
+        store
+            .conn
+            .execute("UPDATE highlights SET date_created_utc = NULL", [])
+            .unwrap();
+        store.backfill_created_utc().unwrap();
+        assert_eq!(null_rows(&store), 1);
+
+        // Later opens don't scan the library again
+        store
+            .conn
+            .execute("UPDATE highlights SET date_created_utc = NULL", [])
+            .unwrap();
+        drop(store);
+        assert_eq!(null_rows(&LibraryStore::open(&path).unwrap()), 2);
+    }
+
+    #[test]
+    fn test_titles_reprocessed_from_raw_titles() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let mut books = vec![Book::new(
+            "vol-pragmatic".to_string(),
+            "THE PRAGMATIC PROGRAMMER: 20TH ANNIVERSARY EDITION".to_string(),
+            "Hunt".to_string(),
+        )];
+        let options = TitleOptions {
+            smart_case: true,
+            split_subtitle: true,
+            ..TitleOptions::default()
+        };
+        process_titles(&mut books, &options);
+        store.merge_books(&books).unwrap();
+        assert_eq!(store.reprocess_titles(&options).unwrap(), 0);
+        let revision = store.revision().unwrap();
+
+        // Turning the steps off restores the device's title
+        assert_eq!(store.reprocess_titles(&TitleOptions::default()).unwrap(), 1);
+        let stored = store.book("vol-pragmatic").unwrap().unwrap();
+        assert_eq!(
+            stored.title,
+            "THE PRAGMATIC PROGRAMMER: 20TH ANNIVERSARY EDITION"
+        );
+        assert_eq!((stored.subtitle, stored.raw_title), (None, None));
+        assert!(store.revision().unwrap() > revision);
+
+        store.reprocess_titles(&options).unwrap();
+        let stored = store.book("vol-pragmatic").unwrap().unwrap();
+        assert_eq!(stored.full_title(), books[0].full_title());
+    }
+
+    #[test]
+    fn test_book_notes_survive_reimport() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let mut book = Book::new(
+            "vol-walden".to_string(),
+            "Walden".to_string(),
+            "Thoreau".to_string(),
+        );
+        store.merge_books(std::slice::from_ref(&book)).unwrap();
+        let revision = store.revision().unwrap();
+        let notes = "Slow start.\n\n---\n\nThen *wonderful*.";
+        store.set_book_notes("vol-walden", notes).unwrap();
+        assert!(store.revision().unwrap() > revision);
+
+        // The device knows nothing of notes: a re-import keeps them
+        book.title = "Walden; or, Life in the Woods".to_string();
+        store.merge_books(&[book]).unwrap();
+        let stored = store.book("vol-walden").unwrap().unwrap();
+        assert_eq!(stored.title, "Walden; or, Life in the Woods");
+        assert_eq!(stored.notes.as_deref(), Some(notes));
+
+        // Saving the same notes changes nothing; blank notes delete them
+        let revision = store.revision().unwrap();
+        store.set_book_notes("vol-walden", notes).unwrap();
+        assert_eq!(store.revision().unwrap(), revision);
+        store.set_book_notes("vol-walden", "  \n").unwrap();
+        assert_eq!(store.book_notes("vol-walden").unwrap(), None);
+        assert_eq!(store.book("vol-walden").unwrap().unwrap().notes, None);
+    }
+
+    #[test]
+    fn test_readers_run_during_a_chunked_merge() {
+        let temp = TempDir::new().unwrap();
+        let state = std::sync::Arc::new(LibraryState::default());
+        state.install(LibraryStore::open(&temp.path().join("library.sqlite")).unwrap());
+        let books: Vec<Book> = synthetic_books().into_iter().take(50).collect();
+        let merging = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (state, merging) = (state.clone(), merging.clone());
+                std::thread::spawn(move || {
+                    let (mut counts, mut slowest) = (Vec::new(), Duration::ZERO);
+                    while merging.load(std::sync::atomic::Ordering::SeqCst) {
+                        let started = Instant::now();
+                        let stats = state.with_reader(|store| store.stats()).unwrap();
+                        state
+                            .with_reader(|store| store.search("lighthouse", 20))
+                            .unwrap();
+                        slowest = slowest.max(started.elapsed());
+                        counts.push(stats.highlights);
+                        std::thread::sleep(Duration::from_millis(5));
+                    }
+                    (counts, slowest)
+                })
+            })
+            .collect();
+
+        // Writes get in between the chunks
+        let writer = {
+            let (state, merging) = (state.clone(), merging.clone());
+            std::thread::spawn(move || {
+                let mut during_merge = 0;
+                while merging.load(std::sync::atomic::Ordering::SeqCst) {
+                    let _ = state.with_store(|store| store.set_cover_path("vol0", "/covers/a.jpg"));
+                    if merging.load(std::sync::atomic::Ordering::SeqCst) {
+                        during_merge += 1;
+                    }
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+                during_merge
+            })
+        };
+
+        let stats = state.merge_books(&books).unwrap();
+        merging.store(false, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(stats.highlights_added, 5_000);
+        assert!(writer.join().unwrap() > 0);
+
+        for reader in readers {
+            let (counts, slowest) = reader.join().unwrap();
+            // Only whole chunks are ever visible, in order
+            assert!(counts.iter().all(|n| n % MERGE_CHUNK_HIGHLIGHTS == 0));
+            assert!(counts.windows(2).all(|w| w[0] <= w[1]));
+            assert!(slowest < Duration::from_secs(1), "read took {:?}", slowest);
+        }
+        let stats = state.with_reader(|store| store.stats()).unwrap();
+        assert_eq!((stats.books, stats.highlights), (50, 5_000));
+        assert_eq!(state.with_reader(|store| store.revision()).unwrap(), 1);
+        // Readers can't write
+        assert!(state
+            .with_reader(|store| store.set_cover_path("vol0", "/covers/vol0.jpg"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_search_10k_highlights_is_fast() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let stats = store.merge_books(&synthetic_books()).unwrap();
+        assert_eq!(stats.highlights_added, 10_000);
+
+        let started = Instant::now();
+        let hits = store.search("lighthouse", 20).unwrap();
+        let elapsed = started.elapsed();
+
+        assert_eq!(hits.len(), 20);
+        let (start, end) = (hits[0].match_start.unwrap(), hits[0].match_end.unwrap());
+        assert_eq!(&hits[0].snippet[start..end], "lighthouse");
+        assert!(
+            elapsed < Duration::from_millis(100),
+            "search took {:?}",
+            elapsed
+        );
+
+        let hits = store.search("garden river", 5000).unwrap();
+        assert!(hits
+            .iter()
+            .all(|h| h.text.contains("garden") && h.text.contains("river")));
+    }
+
+    #[test]
+    fn test_disambiguators_come_from_the_whole_library() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let essays = |content_id: &str, year: i32| {
+            let mut book = Book::new(content_id.to_string(), "Essays".into(), "Montaigne".into());
+            book.publication_year = Some(year);
+            book
+        };
+        store.merge_books(&[essays("vol-2019", 2019)]).unwrap();
+        // Imported on its own later
+        store.merge_books(&[essays("vol-2021", 2021)]).unwrap();
+
+        let labels: Vec<Option<String>> = store
+            .books()
+            .unwrap()
+            .into_iter()
+            .map(|b| b.disambiguator)
+            .collect();
+        assert_eq!(labels, vec![Some("2019".into()), Some("2021".into())]);
+        assert_eq!(
+            store
+                .book("vol-2021")
+                .unwrap()
+                .unwrap()
+                .disambiguator
+                .as_deref(),
+            Some("2021")
+        );
+        assert_eq!(
+            store.disambiguators().unwrap()["vol-2019"].as_deref(),
+            Some("2019")
+        );
+    }
+
+    #[test]
+    fn test_orphaned_flag_follows_the_device() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let mut books: Vec<Book> = synthetic_books().into_iter().take(2).collect();
+        books[1].is_orphaned = true;
+        store.merge_books(&books).unwrap();
+        let orphaned = |store: &LibraryStore| -> Vec<bool> {
+            store
+                .books()
+                .unwrap()
+                .iter()
+                .map(|b| b.is_orphaned)
+                .collect()
+        };
+        assert_eq!(orphaned(&store), vec![false, true]);
+
+        // The book got its content row back
+        books[1].is_orphaned = false;
+        store.merge_books(&books).unwrap();
+        assert_eq!(orphaned(&store), vec![false, false]);
+    }
+
+    #[test]
+    fn test_merge_reimport_is_idempotent() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let books = synthetic_books();
+        store.merge_books(&books).unwrap();
+        store.set_favorite("s42", true).unwrap();
+        store.mark_exported("s42", "Book 0 - Author 0.md").unwrap();
+        let before = store.stats().unwrap();
+
+        let stats = store.merge_books(&books).unwrap();
+        assert_eq!(stats, MergeStats::default());
+        assert_eq!(store.stats().unwrap(), before);
+        assert_eq!(before.favorites, 1);
+        assert_eq!(before.exported, 1);
+    }
+
+    #[test]
+    fn test_sync_status_follows_imports_exports_and_edits() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let mut books = synthetic_books();
+        books.truncate(2);
+        let clean = store.sync_status().unwrap();
+        assert!(!clean.dirty);
+
+        // Import → dirty
+        store.merge_books(&books).unwrap();
+        let status = store.sync_status().unwrap();
+        assert!(status.dirty);
+        assert_eq!(status.new_highlights_since_export, 200);
+
+        // Full export → clean; re-importing the same highlights changes nothing
+        let ids: Vec<&str> = books.iter().map(|b| b.content_id.as_str()).collect();
+        assert!(store.covers_library(&ids).unwrap());
+        assert!(!store.covers_library(&ids[..1]).unwrap());
+        store.record_full_export(store.revision().unwrap()).unwrap();
+        store.merge_books(&books).unwrap();
+        let status = store.sync_status().unwrap();
+        assert!(!status.dirty);
+        assert_eq!(status.new_highlights_since_export, 0);
+
+        // Favorite → dirty again, without new highlights
+        store.set_favorite("s7", true).unwrap();
+        let status = store.sync_status().unwrap();
+        assert!(status.dirty);
+        assert_eq!(status.library_revision, status.last_exported_revision + 1);
+        assert_eq!(status.new_highlights_since_export, 0);
+
+        // An older export finishing late doesn't move the status backwards
+        let revision = store.revision().unwrap();
+        store.record_full_export(revision).unwrap();
+        store.record_full_export(revision - 1).unwrap();
+        assert!(!store.sync_status().unwrap().dirty);
+
+        // Only the rows an import changes are new since the export
+        books[0].highlights[0].annotation = Some("Edited on the device".to_string());
+        books[1].percent_read = Some(80.0);
+        store.merge_books(&books).unwrap();
+        let status = store.sync_status().unwrap();
+        assert!(status.dirty);
+        assert_eq!(status.new_highlights_since_export, 1);
+
+        // Book details changing alone still need an export
+        store.record_full_export(store.revision().unwrap()).unwrap();
+        books[1].percent_read = Some(90.0);
+        store.merge_books(&books).unwrap();
+        assert!(store.sync_status().unwrap().dirty);
+
+        // Finishing a book is counted once
+        books[1].percent_read = Some(100.0);
+        assert_eq!(store.merge_books(&books).unwrap().books_finished, 1);
+        assert_eq!(store.merge_books(&books).unwrap().books_finished, 0);
+    }
+
+    #[test]
+    fn test_revision_is_committed_with_its_change() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let mut books = synthetic_books();
+        books.truncate(1);
+        store.merge_books(&books).unwrap();
+        store.record_full_export(store.revision().unwrap()).unwrap();
+
+        // Simulate a crash between the change and the revision write
+        store
+            .conn
+            .execute_batch(
+                "CREATE TRIGGER crash BEFORE UPDATE OF revision ON sync_state
+                 BEGIN SELECT RAISE(ABORT, 'crash'); END;",
+            )
+            .unwrap();
+        assert!(store.set_favorite("s1", true).is_err());
+        books[0].highlights[0].text = "Changed on the device".to_string();
+        assert!(store.merge_books(&books).is_err());
+
+        // Neither change landed without its revision
+        let stats = store.stats().unwrap();
+        assert_eq!(stats.favorites, 0);
+        let text: String = store
+            .conn
+            .query_row(
+                "SELECT text FROM highlights WHERE stable_id = 's0'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_ne!(text, "Changed on the device");
+        assert!(!store.sync_status().unwrap().dirty);
+    }
+
+    #[test]
+    fn test_merge_updates_changed_highlight() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let mut books = synthetic_books();
+        books.truncate(1);
+        store.merge_books(&books).unwrap();
+
+        // Same stable ID, new device ID and note (e.g. after a factory reset)
+        books[0].highlights[0].id = "reset-bm0".to_string();
+        books[0].highlights[0].annotation = Some("A fresh thought".to_string());
+        let stats = store.merge_books(&books).unwrap();
+
+        assert_eq!(stats.highlights_added, 0);
+        assert_eq!(stats.highlights_updated, 1);
+        let hits = store.search("fresh thought", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].stable_id, "s0");
+    }
+
+    #[test]
+    fn test_highlight_hidden_on_device_is_excluded_not_deleted() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let mut books = synthetic_books();
+        books.truncate(1);
+        store.merge_books(&books).unwrap();
+        let before = store.stats().unwrap();
+        let excluded_count = |store: &LibraryStore| -> i64 {
+            store
+                .conn
+                .query_row(
+                    "SELECT COUNT(*) FROM highlights WHERE is_excluded = 1",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+
+        // Hidden rows imported with `import_hidden_highlights`
+        books[0].highlights[0].is_excluded = true;
+        let stats = store.merge_books(&books).unwrap();
+        assert_eq!(stats.highlights_updated, 1);
+        assert_eq!(excluded_count(&store), 1);
+
+        // Hidden rows skipped on import: excluded through their stable ID
+        let skipped = vec![books[0].highlights[1].clone()];
+        assert_eq!(store.exclude_highlights(&skipped).unwrap(), 1);
+        assert_eq!(store.exclude_highlights(&skipped).unwrap(), 0);
+        assert_eq!(excluded_count(&store), 2);
+        assert_eq!(store.stats().unwrap().highlights, before.highlights);
+    }
+
+    #[test]
+    fn test_chapter_exclusions_are_replaced_per_book() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let chapters = vec![
+            "Copyright".to_string(),
+            " ".to_string(),
+            "Text/dedication.xhtml".to_string(),
+        ];
+        store.set_excluded_chapters("book1", &chapters).unwrap();
+        store
+            .set_excluded_chapters("book2", &["Notes".to_string()])
+            .unwrap();
+
+        let exclusions = store.chapter_exclusions().unwrap();
+        assert_eq!(
+            exclusions["book1"],
+            vec!["Copyright".to_string(), "Text/dedication.xhtml".to_string()]
+        );
+
+        // Saving the same chapters again is not an edit
+        let revision = store.revision().unwrap();
+        store
+            .set_excluded_chapters(
+                "book1",
+                &["Text/dedication.xhtml".to_string(), "Copyright".to_string()],
+            )
+            .unwrap();
+        assert_eq!(store.revision().unwrap(), revision);
+
+        store.set_excluded_chapters("book1", &[]).unwrap();
+        assert!(store.revision().unwrap() > revision);
+        let exclusions = store.chapter_exclusions().unwrap();
+        assert!(!exclusions.contains_key("book1"));
+        assert_eq!(exclusions["book2"], vec!["Notes".to_string()]);
+    }
+
+    #[test]
+    fn test_search_snippet_offsets_are_utf16() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let mut book = Book::new("vol".to_string(), "Livro".to_string(), "Autor".to_string());
+        let mut highlight = Highlight::new(
+            "bm1".to_string(),
+            format!(
+                "{} 👩\u{200d}👧 ação {}",
+                "palavra ".repeat(60),
+                "fim ".repeat(60)
+            ),
+            "2025-01-24T10:00:00.000".to_string(),
+        );
+        highlight.stable_id = "s1".to_string();
+        book.highlights.push(highlight);
+        store.merge_books(&[book]).unwrap();
+
+        let hits = store.search("ação", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        let hit = &hits[0];
+        assert!(hit.snippet.starts_with('…') && hit.snippet.ends_with('…'));
+
+        let units: Vec<u16> = hit.snippet.encode_utf16().collect();
+        let matched =
+            String::from_utf16(&units[hit.match_start.unwrap()..hit.match_end.unwrap()]).unwrap();
+        assert_eq!(matched, "ação");
+    }
+
+    #[test]
+    fn test_indexed_text_and_queries_are_normalized() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let mut book = Book::new("vol".to_string(), "Livro".to_string(), "Autor".to_string());
+        let mut highlight = Highlight::new(
+            "bm1".to_string(),
+            "Um feito extra\u{ad}ordinário".to_string(),
+            "2025-01-24T10:00:00.000".to_string(),
+        );
+        highlight.stable_id = "s1".to_string();
+        book.highlights.push(highlight);
+        store.merge_books(&[book]).unwrap();
+
+        let hits = store.search("extraordinário", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].text, "Um feito extraordinário");
+        assert_eq!(store.search("extra\u{ad}ordinário", 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_highlight_tags_by_stable_id() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        store.merge_books(&synthetic_books()[..1]).unwrap();
+        let stable_id: String = store
+            .conn
+            .query_row("SELECT stable_id FROM highlights LIMIT 1", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        for tag in ["zen", "art"] {
+            store
+                .conn
+                .execute(
+                    "INSERT INTO tags (stable_id, tag) VALUES (?1, ?2)",
+                    params![stable_id, tag],
+                )
+                .unwrap();
+        }
+
+        let tags = store.highlight_tags().unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[&stable_id], vec!["art", "zen"]);
+    }
+
+    #[test]
+    fn test_renormalize_updates_stored_text_and_index() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let mut book = Book::new("vol".to_string(), "Livro".to_string(), "Autor".to_string());
+        let mut highlight = Highlight::new(
+            "bm1".to_string(),
+            "Ele disse “olá”".to_string(),
+            "2025-01-24T10:00:00.000".to_string(),
+        );
+        highlight.stable_id = "s1".to_string();
+        book.highlights.push(highlight);
+        store.merge_books(&[book]).unwrap();
+        store.record_full_export(store.revision().unwrap()).unwrap();
+        assert_eq!(store.renormalize_highlights().unwrap(), 0);
+
+        store.set_text_normalization(TextNormalization {
+            normalize_punctuation: true,
+            ..TextNormalization::default()
+        });
+        assert_eq!(store.renormalize_highlights().unwrap(), 1);
+        assert_eq!(store.renormalize_highlights().unwrap(), 0);
+
+        let hits = store.search("\"olá\"", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].text, "Ele disse \"olá\"");
+        assert_eq!(store.sync_status().unwrap().new_highlights_since_export, 1);
+    }
+
+    #[test]
+    fn test_search_tolerates_fts_syntax() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        store.merge_books(&synthetic_books()[..1]).unwrap();
+
+        assert!(store.search("\"unbalanced AND (", 10).unwrap().is_empty());
+        assert!(store.search("   ", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_book_highlights_pages_are_consistent() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let mut book = Book::new(
+            "ref".to_string(),
+            "Reference".to_string(),
+            "Eds".to_string(),
+        );
+        book.highlights = (0..12_000)
+            .map(|n| {
+                let mut highlight = Highlight::new(
+                    format!("bm{}", n),
+                    format!("Entry {}", n),
+                    "2025-01-24T10:00:00.000".to_string(),
+                );
+                highlight.stable_id = format!("s{}", n);
+                // Ties on position fall back to the stable ID
+                highlight.chapter_progress = Some((n / 3) as f64 / 4000.0);
+                highlight.is_excluded = n == 42;
+                highlight
+            })
+            .collect();
+        store.merge_books(&[book]).unwrap();
+
+        let all = store.book_highlights("ref", 0, 20_000).unwrap();
+        assert_eq!(all.total, 11_999);
+        assert_eq!(all.highlights.len(), 11_999);
+        assert!(all.highlights.iter().all(|h| h.id != "bm42"));
+
+        let mut paged = Vec::new();
+        let mut offset = 0;
+        loop {
+            let page = store.book_highlights("ref", offset, 500).unwrap();
+            assert_eq!((page.total, page.offset), (11_999, offset));
+            if page.highlights.is_empty() {
+                break;
+            }
+            offset += page.highlights.len();
+            paged.extend(page.highlights);
+        }
+        assert_eq!(paged, all.highlights);
+        assert_eq!(paged[0].text, "Entry 0");
+        assert_eq!(paged[11_998].text, "Entry 11999");
+        assert!(store
+            .book_highlights("missing", 0, 10)
+            .unwrap()
+            .highlights
+            .is_empty());
+    }
+
+    #[test]
+    fn test_kepub_highlights_keep_creation_order() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let mut book = Book::new("kepub".to_string(), "Kepub".to_string(), "A".to_string());
+        book.progress_scope = Some(ProgressScope::Chapter);
+        // Chapter-relative positions: chapter 2's start sorts before
+        // chapter 1's end
+        book.highlights = [("ch1-end", 0.9, "10:00"), ("ch2-start", 0.1, "11:00")]
+            .iter()
+            .map(|(id, progress, time)| {
+                let mut highlight = Highlight::new(
+                    id.to_string(),
+                    id.to_string(),
+                    format!("2025-01-24T{}:00.000", time),
+                );
+                highlight.chapter_progress = Some(*progress);
+                highlight
+            })
+            .collect();
+        store.merge_books(&[book.clone()]).unwrap();
+
+        let ids = |store: &LibraryStore| -> Vec<String> {
+            let page = store.book_highlights("kepub", 0, 10).unwrap();
+            page.highlights.into_iter().map(|h| h.id).collect()
+        };
+        assert_eq!(ids(&store), vec!["ch1-end", "ch2-start"]);
+        let stored = store.book("kepub").unwrap().unwrap();
+        assert_eq!(stored.progress_scope, Some(ProgressScope::Chapter));
+        assert_eq!(stored.highlights[0].id, "ch1-end");
+
+        // Book-relative positions order by position
+        book.content_id = "epub".to_string();
+        book.progress_scope = Some(ProgressScope::Book);
+        for highlight in &mut book.highlights {
+            highlight.id = format!("epub-{}", highlight.id);
+        }
+        store.merge_books(&[book]).unwrap();
+        let page = store.book_highlights("epub", 0, 10).unwrap();
+        assert_eq!(page.highlights[0].id, "epub-ch2-start");
+    }
+
+    #[test]
+    fn test_pocket_article_keeps_kind_and_source_url() {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let mut article = Book::new(
+            "https://example.com/post".to_string(),
+            "Post".to_string(),
+            "Writer".to_string(),
+        );
+        article.kind = BookKind::PocketArticle;
+        article.source_url = Some("https://example.com/post".to_string());
+        store.merge_books(std::slice::from_ref(&article)).unwrap();
+
+        let stored = store.book("https://example.com/post").unwrap().unwrap();
+        assert_eq!(stored.kind, BookKind::PocketArticle);
+        assert_eq!(
+            stored.source_url.as_deref(),
+            Some("https://example.com/post")
+        );
+
+        // A re-import without the URL keeps the stored one
+        article.source_url = None;
+        store.merge_books(&[article]).unwrap();
+        let stored = store.book("https://example.com/post").unwrap().unwrap();
+        assert_eq!(
+            stored.source_url.as_deref(),
+            Some("https://example.com/post")
+        );
+    }
+}
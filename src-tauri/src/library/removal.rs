@@ -0,0 +1,702 @@
+//! Removing books from the library, with what was derived from them
+//!
+//! `remove_books` returns a `RemovalPlan` of everything that would go and,
+//! only with `RemovalOptions::confirm`, carries it out. Library rows are
+//! deleted in one transaction before any file is touched.
+//!
+//! Exported files go only when the export manifest lists them for the book
+//! (split parts included) and they still hash to what khi wrote, taking
+//! their sidecars along; others are reported in `kept_files` and left
+//! alone. Covers go only from the cover cache.
+//!
+//! Tags, favorites and export tracking cascade with the highlights. Unless
+//! the options delete them they are parked by stable ID, and `merge_books`
+//! restores them when the book is imported again; `prune_parked` drops those
+//! still waiting after `PARKED_RETENTION_DAYS`. Reading notes are keyed by
+//! content ID, so kept ones simply wait for the book to come back.
+
+use super::{current_revision, set_revision, LibraryError, LibraryStore};
+use crate::export::append::{self, ExportManifest};
+use crate::export::sidecar;
+use crate::export::verify::hash_file;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Component, Path};
+
+/// How long parked rows wait for their book to be imported again
+pub const PARKED_RETENTION_DAYS: i64 = 365;
+
+/// Tables whose rows are parked while their book is out of the library:
+/// (table, parking table, columns both share)
+const PARKED_TABLES: [(&str, &str, &str); 3] = [
+    ("tags", "parked_tags", "stable_id, tag"),
+    ("favorites", "parked_favorites", "stable_id, created_at"),
+    (
+        "export_tracking",
+        "parked_export_tracking",
+        "stable_id, exported_at, export_path",
+    ),
+];
+
+/// What removing books takes with it
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RemovalOptions {
+    /// Delete exported files khi can prove it wrote
+    #[serde(default, alias = "delete_exported_files")]
+    pub delete_exported_files: bool,
+    /// Delete covers from the cover cache
+    #[serde(default, alias = "delete_covers")]
+    pub delete_covers: bool,
+    #[serde(default, alias = "delete_export_tracking")]
+    pub delete_export_tracking: bool,
+    #[serde(default, alias = "delete_tags_and_favorites")]
+    pub delete_tags_and_favorites: bool,
+    /// Delete the books' reading notes
+    #[serde(default, alias = "delete_book_notes")]
+    pub delete_book_notes: bool,
+    /// Carry out the removal; without it only the plan is returned
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+/// A book of a removal plan
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RemovedBook {
+    pub content_id: String,
+    pub title: String,
+    pub slug: Option<String>,
+    pub highlights: usize,
+}
+
+/// Everything a removal deletes (or, in a dry run, would delete)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RemovalPlan {
+    /// Whether the removal was carried out
+    pub confirmed: bool,
+    pub books: Vec<RemovedBook>,
+    /// Requested content IDs that aren't in the library
+    pub unknown: Vec<String>,
+    pub highlights: usize,
+    pub tags: usize,
+    pub favorites: usize,
+    pub export_tracking: usize,
+    pub review_tracking: usize,
+    pub chapter_exclusions: usize,
+    pub book_notes: usize,
+    /// Tag, favorite, export tracking and reading note rows kept for a
+    /// re-import
+    pub parked: usize,
+    pub covers: Vec<String>,
+    /// Exported files to delete, relative to the export folder
+    pub exported_files: Vec<String>,
+    /// Files of the books khi leaves alone: changed since it wrote them
+    pub kept_files: Vec<String>,
+    /// Files that couldn't be deleted
+    pub errors: Vec<String>,
+}
+
+/// Plan the removal of `content_ids` and, with `options.confirm`, carry it
+/// out
+///
+/// `export_dir` is the folder whose manifest lists exported files;
+/// `cover_dir` the cover cache.
+pub fn remove_books(
+    store: &mut LibraryStore,
+    content_ids: &[String],
+    options: &RemovalOptions,
+    export_dir: Option<&Path>,
+    cover_dir: &Path,
+) -> Result<RemovalPlan, LibraryError> {
+    let mut plan = store.removal_plan(content_ids, options)?;
+    if options.delete_covers {
+        plan.covers = removable_covers(store, &plan, cover_dir)?;
+    }
+    let manifest = match export_dir {
+        Some(dir) if options.delete_exported_files => {
+            let manifest = append::load_manifest(dir);
+            let (proven, kept) = exported_files(dir, &manifest, &plan);
+            plan.exported_files = proven;
+            plan.kept_files = kept;
+            Some((dir, manifest))
+        }
+        _ => None,
+    };
+    if !options.confirm {
+        return Ok(plan);
+    }
+
+    store.delete_books(&plan, options)?;
+    plan.confirmed = true;
+    log::info!("[Library] {} livro(s) removido(s)", plan.books.len());
+
+    for cover in &plan.covers {
+        if let Err(e) = fs::remove_file(cover) {
+            plan.errors.push(format!("{}: {}", cover, e));
+        }
+    }
+    if let Some((dir, mut manifest)) = manifest {
+        for key in &plan.exported_files {
+            match fs::remove_file(dir.join(key)) {
+                Ok(()) => forget_file(&mut manifest, key),
+                Err(e) => plan.errors.push(format!("{}: {}", key, e)),
+            }
+        }
+        if !plan.exported_files.is_empty() {
+            if let Err(e) = append::save_manifest(dir, &manifest) {
+                plan.errors
+                    .push(format!("{}: {}", append::MANIFEST_FILENAME, e));
+            }
+        }
+    }
+    Ok(plan)
+}
+
+impl LibraryStore {
+    /// Library rows removing `content_ids` deletes, without touching files
+    pub fn removal_plan(
+        &self,
+        content_ids: &[String],
+        options: &RemovalOptions,
+    ) -> Result<RemovalPlan, LibraryError> {
+        let mut plan = RemovalPlan::default();
+        let requested: BTreeSet<&String> = content_ids.iter().collect();
+        for content_id in requested {
+            let book = self
+                .conn
+                .query_row(
+                    "SELECT title, slug FROM books WHERE content_id = ?1",
+                    [content_id],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+            let Some((title, slug)) = book else {
+                plan.unknown.push(content_id.clone());
+                continue;
+            };
+            let count = |table: &str| -> Result<usize, LibraryError> {
+                let n: i64 = self.conn.query_row(
+                    &format!(
+                        "SELECT COUNT(*) FROM {} WHERE stable_id IN
+                            (SELECT stable_id FROM highlights WHERE content_id = ?1)",
+                        table
+                    ),
+                    [content_id],
+                    |row| row.get(0),
+                )?;
+                Ok(n as usize)
+            };
+            let highlights: i64 = self.conn.query_row(
+                "SELECT COUNT(*) FROM highlights WHERE content_id = ?1",
+                [content_id],
+                |row| row.get(0),
+            )?;
+            let exclusions: i64 = self.conn.query_row(
+                "SELECT COUNT(*) FROM chapter_exclusions WHERE content_id = ?1",
+                [content_id],
+                |row| row.get(0),
+            )?;
+            let notes: i64 = self.conn.query_row(
+                "SELECT COUNT(*) FROM book_notes WHERE content_id = ?1",
+                [content_id],
+                |row| row.get(0),
+            )?;
+
+            let (tags, favorites) = (count("tags")?, count("favorites")?);
+            if options.delete_tags_and_favorites {
+                plan.tags += tags;
+                plan.favorites += favorites;
+            } else {
+                plan.parked += tags + favorites;
+            }
+            let tracking = count("export_tracking")?;
+            if options.delete_export_tracking {
+                plan.export_tracking += tracking;
+            } else {
+                plan.parked += tracking;
+            }
+            if options.delete_book_notes {
+                plan.book_notes += notes as usize;
+            } else {
+                plan.parked += notes as usize;
+            }
+            plan.review_tracking += count("review_tracking")?;
+            plan.chapter_exclusions += exclusions as usize;
+            plan.highlights += highlights as usize;
+            plan.books.push(RemovedBook {
+                content_id: content_id.clone(),
+                title,
+                slug,
+                highlights: highlights as usize,
+            });
+        }
+        Ok(plan)
+    }
+
+    /// Delete the books of `plan` in one transaction, parking what
+    /// `options` keeps
+    fn delete_books(
+        &mut self,
+        plan: &RemovalPlan,
+        options: &RemovalOptions,
+    ) -> Result<(), LibraryError> {
+        let tx = self.conn.transaction()?;
+        let parked_at = Utc::now().to_rfc3339();
+        for book in &plan.books {
+            let of_book = "stable_id IN (SELECT stable_id FROM highlights WHERE content_id = ?1)";
+            for (table, parked, columns) in PARKED_TABLES {
+                let delete = match table {
+                    "export_tracking" => options.delete_export_tracking,
+                    _ => options.delete_tags_and_favorites,
+                };
+                if !delete {
+                    tx.execute(
+                        &format!(
+                            "INSERT OR REPLACE INTO {} ({}, parked_at)
+                                SELECT {}, ?2 FROM {} WHERE {}",
+                            parked, columns, columns, table, of_book
+                        ),
+                        params![book.content_id, parked_at],
+                    )?;
+                }
+            }
+            tx.execute(
+                "DELETE FROM chapter_exclusions WHERE content_id = ?1",
+                [&book.content_id],
+            )?;
+            if options.delete_book_notes {
+                tx.execute(
+                    "DELETE FROM book_notes WHERE content_id = ?1",
+                    [&book.content_id],
+                )?;
+            }
+            // Highlights, and their tags, favorites and tracking, cascade
+            tx.execute(
+                "DELETE FROM books WHERE content_id = ?1",
+                [&book.content_id],
+            )?;
+        }
+        if !plan.books.is_empty() {
+            let revision = current_revision(&tx)? + 1;
+            set_revision(&tx, revision)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Drop rows parked before `before`, whose books never came back
+    pub fn prune_parked(&mut self, before: DateTime<Utc>) -> Result<usize, LibraryError> {
+        let tx = self.conn.transaction()?;
+        let mut removed = 0;
+        for (_, parked, _) in PARKED_TABLES {
+            removed += tx.execute(
+                &format!("DELETE FROM {} WHERE parked_at < ?1", parked),
+                [before.to_rfc3339()],
+            )?;
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+}
+
+/// Give a highlight imported again what was parked when its book was
+/// removed; call right after inserting it
+pub(super) fn restore_parked(conn: &Connection, stable_id: &str) -> rusqlite::Result<()> {
+    for (table, parked, columns) in PARKED_TABLES {
+        let restored = conn.execute(
+            &format!(
+                "INSERT OR IGNORE INTO {} ({}) SELECT {} FROM {} WHERE stable_id = ?1",
+                table, columns, columns, parked
+            ),
+            [stable_id],
+        )?;
+        if restored > 0 {
+            conn.execute(
+                &format!("DELETE FROM {} WHERE stable_id = ?1", parked),
+                [stable_id],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Cover files of the plan's books that are in `cover_dir` and used by no
+/// other book
+fn removable_covers(
+    store: &LibraryStore,
+    plan: &RemovalPlan,
+    cover_dir: &Path,
+) -> Result<Vec<String>, LibraryError> {
+    let removed: BTreeSet<&str> = plan.books.iter().map(|b| b.content_id.as_str()).collect();
+    let (theirs, others): (Vec<_>, Vec<_>) = store
+        .cover_paths()?
+        .into_iter()
+        .partition(|(content_id, _)| removed.contains(content_id.as_str()));
+    let shared: BTreeSet<String> = others.into_iter().map(|(_, path)| path).collect();
+    Ok(theirs
+        .into_iter()
+        .map(|(_, path)| path)
+        .filter(|path| {
+            let file = Path::new(path);
+            file.starts_with(cover_dir) && file.is_file() && !shared.contains(path)
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect())
+}
+
+/// Files the manifest lists for the plan's books (split parts included),
+/// split into those still as khi wrote them and those changed since
+///
+/// A file's sidecar goes with it when it names one of the plan's books.
+fn exported_files(
+    export_dir: &Path,
+    manifest: &ExportManifest,
+    plan: &RemovalPlan,
+) -> (Vec<String>, Vec<String>) {
+    let slugs: BTreeSet<&str> = plan
+        .books
+        .iter()
+        .filter_map(|b| b.slug.as_deref())
+        .collect();
+    let content_ids: BTreeSet<&str> = plan.books.iter().map(|b| b.content_id.as_str()).collect();
+    let mut proven = Vec::new();
+    let mut kept = Vec::new();
+    for (key, slug) in &manifest.books {
+        if !slugs.contains(slug.as_str()) {
+            continue;
+        }
+        let path = export_dir.join(key);
+        if !path.is_file() {
+            continue;
+        }
+        let inside = Path::new(key)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+        let unchanged = manifest
+            .hashes
+            .get(key)
+            .is_some_and(|hash| hash_file(&path).is_ok_and(|actual| &actual == hash));
+        let sidecar = sidecar::sidecar_path(&path);
+        let sidecar_key = sidecar
+            .is_file()
+            .then(|| append::manifest_key(export_dir, &sidecar));
+        if inside && unchanged {
+            proven.push(key.clone());
+            let theirs = fs::read_to_string(&sidecar)
+                .ok()
+                .and_then(|json| serde_json::from_str::<sidecar::Sidecar>(&json).ok())
+                .is_some_and(|found| content_ids.contains(found.content_id.as_str()));
+            match sidecar_key {
+                Some(sidecar_key) if theirs => proven.push(sidecar_key),
+                Some(sidecar_key) => kept.push(sidecar_key),
+                None => {}
+            }
+        } else {
+            kept.push(key.clone());
+            kept.extend(sidecar_key);
+        }
+    }
+    (proven, kept)
+}
+
+fn forget_file(manifest: &mut ExportManifest, key: &str) {
+    manifest.files.remove(key);
+    manifest.hashes.remove(key);
+    manifest.stale.remove(key);
+    manifest.books.remove(key);
+    manifest.adopted.retain(|_, adopted| adopted != key);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::MarkdownExporter;
+    use crate::models::{Book, ExportWriteMode, Highlight};
+    use crate::settings::AppSettings;
+    use tempfile::TempDir;
+
+    fn book(content_id: &str, title: &str, highlights: usize) -> Book {
+        let mut book = Book::new(
+            content_id.to_string(),
+            title.to_string(),
+            "Henry David Thoreau".to_string(),
+        );
+        book.slug = format!("{}-slug", content_id);
+        for n in 0..highlights {
+            let mut highlight = Highlight::new(
+                format!("{}-bm{}", content_id, n),
+                format!("{} passage {}", title, n),
+                "2025-01-24T10:00:00.000".to_string(),
+            );
+            highlight.stable_id = format!("{}-s{}", content_id, n);
+            book.highlights.push(highlight);
+        }
+        book
+    }
+
+    /// Library with two books, the first with a tag, favorite, tracking row,
+    /// review, excluded chapter and reading notes
+    fn store() -> LibraryStore {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        store
+            .merge_books(&[book("vol1", "Walden", 3), book("vol2", "Dune", 2)])
+            .unwrap();
+        store
+            .conn
+            .execute(
+                "INSERT INTO tags (stable_id, tag) VALUES ('vol1-s0', 'nature')",
+                [],
+            )
+            .unwrap();
+        store.set_favorite("vol1-s1", true).unwrap();
+        store.set_favorite("vol2-s0", true).unwrap();
+        store.mark_exported("vol1-s0", "Walden.md").unwrap();
+        store.mark_reviewed(&["vol1-s2".to_string()]).unwrap();
+        store
+            .set_excluded_chapters("vol1", &["Economy".to_string()])
+            .unwrap();
+        store
+            .set_book_notes("vol1", "Slow, then wonderful.")
+            .unwrap();
+        store
+    }
+
+    fn ids() -> Vec<String> {
+        vec!["vol1".to_string(), "missing".to_string()]
+    }
+
+    #[test]
+    fn test_dry_run_lists_without_removing() {
+        let mut store = store();
+        let temp = TempDir::new().unwrap();
+        let options = RemovalOptions {
+            delete_tags_and_favorites: true,
+            ..Default::default()
+        };
+        let before = store.stats().unwrap();
+        let revision = store.revision().unwrap();
+
+        let plan = remove_books(&mut store, &ids(), &options, None, temp.path()).unwrap();
+        assert!(!plan.confirmed);
+        assert_eq!(plan.books.len(), 1);
+        assert_eq!(plan.books[0].slug.as_deref(), Some("vol1-slug"));
+        assert_eq!(plan.unknown, vec!["missing"]);
+        assert_eq!((plan.highlights, plan.tags, plan.favorites), (3, 1, 1));
+        // Export tracking and notes kept for a re-import
+        assert_eq!(
+            (plan.export_tracking, plan.book_notes, plan.parked),
+            (0, 0, 2)
+        );
+        assert_eq!((plan.review_tracking, plan.chapter_exclusions), (1, 1));
+        assert_eq!(store.stats().unwrap(), before);
+        assert_eq!(store.revision().unwrap(), revision);
+
+        let confirmed = RemovalOptions {
+            confirm: true,
+            ..options
+        };
+        let report = remove_books(&mut store, &ids(), &confirmed, None, temp.path()).unwrap();
+        assert!(report.confirmed);
+        assert_eq!(
+            RemovalPlan {
+                confirmed: false,
+                ..report
+            },
+            plan
+        );
+        let after = store.stats().unwrap();
+        assert_eq!((after.books, after.highlights), (1, 2));
+        assert_eq!((after.tags, after.favorites, after.exported), (0, 1, 0));
+        assert!(store.chapter_exclusions().unwrap().is_empty());
+        assert!(store.revision().unwrap() > revision);
+        assert!(store.search("Walden", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_only_files_khi_wrote_are_deleted() {
+        let temp = TempDir::new().unwrap();
+        let export_dir = temp.path().join("notes");
+        let cover_dir = temp.path().join("covers");
+        fs::create_dir_all(&cover_dir).unwrap();
+        let mut store = store();
+
+        // Covers: one in the cache, one picked by the user elsewhere
+        let cached = cover_dir.join("walden.jpg");
+        let elsewhere = temp.path().join("dune.jpg");
+        fs::write(&cached, b"jpg").unwrap();
+        fs::write(&elsewhere, b"jpg").unwrap();
+        store
+            .set_cover_path("vol1", &cached.to_string_lossy())
+            .unwrap();
+        store
+            .set_cover_path("vol2", &elsewhere.to_string_lossy())
+            .unwrap();
+
+        // Exports: Walden untouched, Dune edited by hand, a note khi never
+        // wrote
+        let exporter = MarkdownExporter::new(export_dir.clone());
+        let mut config = AppSettings::default().export_config;
+        config.write_mode = ExportWriteMode::Append;
+        let walden = exporter
+            .export_book(&book("vol1", "Walden", 3), &config)
+            .unwrap();
+        let dune = exporter
+            .export_book(&book("vol2", "Dune", 2), &config)
+            .unwrap();
+        fs::write(&dune, "# Dune\n\nMy own notes\n").unwrap();
+        let own = export_dir.join("Walden notes.md");
+        fs::write(&own, "# Mine\n").unwrap();
+
+        let options = RemovalOptions {
+            delete_exported_files: true,
+            delete_covers: true,
+            delete_export_tracking: true,
+            delete_tags_and_favorites: true,
+            delete_book_notes: true,
+            confirm: true,
+        };
+        let all = vec!["vol1".to_string(), "vol2".to_string()];
+        let report =
+            remove_books(&mut store, &all, &options, Some(&export_dir), &cover_dir).unwrap();
+
+        assert_eq!(report.covers, vec![cached.to_string_lossy().to_string()]);
+        assert_eq!(report.exported_files.len(), 1);
+        assert_eq!(report.kept_files.len(), 1);
+        assert!(report.errors.is_empty());
+        assert!(!cached.exists() && elsewhere.exists());
+        assert!(!walden.exists());
+        assert!(dune.exists() && own.exists());
+
+        let manifest = append::load_manifest(&export_dir);
+        assert!(!manifest.books.contains_key(&report.exported_files[0]));
+        assert!(manifest.books.contains_key(&report.kept_files[0]));
+        assert_eq!(store.stats().unwrap().books, 0);
+    }
+
+    #[test]
+    fn test_overwrite_exports_go_with_their_parts_and_sidecars() {
+        let temp = TempDir::new().unwrap();
+        let export_dir = temp.path().join("notes");
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        let big = book(
+            "vol3",
+            "Walden",
+            crate::export::parts::LARGE_BOOK_HIGHLIGHTS + 1,
+        );
+        store.merge_books(std::slice::from_ref(&big)).unwrap();
+
+        let mut config = AppSettings::default().export_config;
+        config.write_sidecars = true;
+        MarkdownExporter::new(export_dir.clone())
+            .export_book(&big, &config)
+            .unwrap();
+
+        let options = RemovalOptions {
+            delete_exported_files: true,
+            confirm: true,
+            ..Default::default()
+        };
+        let ids = vec!["vol3".to_string()];
+        let report =
+            remove_books(&mut store, &ids, &options, Some(&export_dir), temp.path()).unwrap();
+        // Overview and two parts, each with its sidecar
+        assert_eq!(
+            report.exported_files.len(),
+            6,
+            "{:?}",
+            report.exported_files
+        );
+        assert!(report.kept_files.is_empty() && report.errors.is_empty());
+        let left: Vec<_> = fs::read_dir(&export_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name != append::MANIFEST_FILENAME)
+            .collect();
+        assert!(left.is_empty(), "{:?}", left);
+    }
+
+    #[test]
+    fn test_parked_rows_are_pruned_after_retention() {
+        let mut store = store();
+        let temp = TempDir::new().unwrap();
+        let keep = RemovalOptions {
+            confirm: true,
+            ..Default::default()
+        };
+        remove_books(&mut store, &ids(), &keep, None, temp.path()).unwrap();
+        let parked = |store: &LibraryStore| -> i64 {
+            store
+                .conn
+                .query_row(
+                    "SELECT (SELECT COUNT(*) FROM parked_tags)
+                        + (SELECT COUNT(*) FROM parked_favorites)
+                        + (SELECT COUNT(*) FROM parked_export_tracking)",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert_eq!(parked(&store), 3);
+
+        let day = chrono::Duration::days(1);
+        assert_eq!(store.prune_parked(Utc::now() - day).unwrap(), 0);
+        assert_eq!(parked(&store), 3);
+        assert_eq!(store.prune_parked(Utc::now() + day).unwrap(), 3);
+        assert_eq!(parked(&store), 0);
+
+        // Nothing left to restore
+        store.merge_books(&[book("vol1", "Walden", 3)]).unwrap();
+        let stats = store.stats().unwrap();
+        assert_eq!((stats.tags, stats.favorites, stats.exported), (0, 1, 0));
+    }
+
+    #[test]
+    fn test_reimport_restores_only_what_was_kept() {
+        let temp = TempDir::new().unwrap();
+        let walden = || vec![book("vol1", "Walden", 3)];
+
+        // Kept: parked, then back on re-import
+        let mut store = store();
+        let keep = RemovalOptions {
+            confirm: true,
+            ..Default::default()
+        };
+        remove_books(&mut store, &ids(), &keep, None, temp.path()).unwrap();
+        let stats = store.merge_books(&walden()).unwrap();
+        assert_eq!((stats.books_added, stats.highlights_added), (1, 3));
+        let restored = store.stats().unwrap();
+        assert_eq!(
+            (restored.tags, restored.favorites, restored.exported),
+            (1, 2, 1)
+        );
+        assert_eq!(
+            store.book("vol1").unwrap().unwrap().notes.as_deref(),
+            Some("Slow, then wonderful.")
+        );
+
+        // Deleted: gone for good
+        let delete = RemovalOptions {
+            delete_export_tracking: true,
+            delete_tags_and_favorites: true,
+            delete_book_notes: true,
+            confirm: true,
+            ..Default::default()
+        };
+        let report = remove_books(&mut store, &ids(), &delete, None, temp.path()).unwrap();
+        assert_eq!((report.book_notes, report.parked), (1, 0));
+        store.merge_books(&walden()).unwrap();
+        store.merge_books(&walden()).unwrap();
+        let clean = store.stats().unwrap();
+        assert_eq!((clean.books, clean.highlights), (2, 5));
+        assert_eq!((clean.tags, clean.favorites, clean.exported), (0, 1, 0));
+        assert!(store.chapter_exclusions().unwrap().is_empty());
+        assert_eq!(store.book_notes("vol1").unwrap(), None);
+        assert_eq!(store.search("Walden", 10).unwrap().len(), 3);
+    }
+}
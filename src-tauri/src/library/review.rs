@@ -0,0 +1,357 @@
+//! Daily review: random past highlights, weighted toward stale ones
+//!
+//! Review state lives in `review_tracking`, keyed by stable ID like the rest
+//! of the library, so it survives re-imports.
+
+use super::{LibraryError, LibraryStore};
+use crate::utils::date::{parse_timestamp, utc_offset};
+use chrono::{DateTime, Utc};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Weight of a never-reviewed highlight, in days since last review
+///
+/// Reviews older than this are capped to the same weight.
+const NEVER_REVIEWED_DAYS: f64 = 365.0;
+
+/// Weight floor so just-reviewed highlights can still be drawn
+const MIN_WEIGHT_DAYS: f64 = 0.01;
+
+/// How review highlights are picked
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewMode {
+    /// Every candidate equally likely
+    Uniform,
+    /// Weighted by days since last review; never-reviewed ones first
+    #[default]
+    LeastRecent,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewOptions {
+    #[serde(default)]
+    pub mode: ReviewMode,
+    #[serde(default, alias = "favorites_only")]
+    pub favorites_only: bool,
+    /// Only highlights with this tag
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// Fixed seed for reproducible draws (tests)
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// A highlight picked for review
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewHighlight {
+    pub stable_id: String,
+    pub content_id: String,
+    pub book_title: String,
+    pub author: String,
+    pub text: String,
+    pub annotation: Option<String>,
+    pub chapter_title: Option<String>,
+    /// When the highlight was made, in UTC
+    pub created_at: Option<DateTime<Utc>>,
+    pub favorite: bool,
+    /// RFC 3339 timestamp, `None` if never reviewed
+    pub last_reviewed: Option<String>,
+    pub review_count: u32,
+}
+
+impl LibraryStore {
+    /// Draw up to `count` distinct highlights for review
+    pub fn review_highlights(
+        &self,
+        count: usize,
+        options: &ReviewOptions,
+    ) -> Result<Vec<ReviewHighlight>, LibraryError> {
+        self.review_highlights_at(count, options, Utc::now())
+    }
+
+    fn review_highlights_at(
+        &self,
+        count: usize,
+        options: &ReviewOptions,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<ReviewHighlight>, LibraryError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT h.stable_id, h.content_id, b.title, b.author, h.text, h.annotation,
+                    h.chapter_title, f.stable_id IS NOT NULL, r.last_reviewed,
+                    COALESCE(r.review_count, 0), h.date_created_utc, h.date_created
+             FROM highlights h
+             JOIN books b ON b.content_id = h.content_id
+             LEFT JOIN favorites f ON f.stable_id = h.stable_id
+             LEFT JOIN review_tracking r ON r.stable_id = h.stable_id
+             WHERE h.is_excluded = 0
+               AND (?1 = 0 OR f.stable_id IS NOT NULL)
+               AND (?2 IS NULL OR EXISTS (
+                    SELECT 1 FROM tags t WHERE t.stable_id = h.stable_id AND t.tag = ?2))
+             ORDER BY h.stable_id",
+        )?;
+        let candidates = stmt
+            .query_map(params![options.favorites_only, options.tag], |row| {
+                Ok(ReviewHighlight {
+                    stable_id: row.get(0)?,
+                    content_id: row.get(1)?,
+                    book_title: row.get(2)?,
+                    author: row.get(3)?,
+                    text: row.get(4)?,
+                    annotation: row.get(5)?,
+                    chapter_title: row.get(6)?,
+                    favorite: row.get(7)?,
+                    last_reviewed: row.get(8)?,
+                    review_count: row.get(9)?,
+                    created_at: created_at(row.get(10)?, &row.get::<_, String>(11)?),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut rng = SplitMix64::new(options.seed.unwrap_or_else(time_seed));
+
+        // Weighted sampling without replacement (Efraimidis–Spirakis): each
+        // candidate gets key ln(u) / weight and the largest keys win
+        let mut keyed: Vec<(f64, ReviewHighlight)> = candidates
+            .into_iter()
+            .map(|highlight| {
+                let weight = match options.mode {
+                    ReviewMode::Uniform => 1.0,
+                    ReviewMode::LeastRecent => {
+                        review_weight(highlight.last_reviewed.as_deref(), now)
+                    }
+                };
+                (rng.next_f64().ln() / weight, highlight)
+            })
+            .collect();
+        keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        Ok(keyed
+            .into_iter()
+            .take(count)
+            .map(|(_, highlight)| highlight)
+            .collect())
+    }
+
+    /// Record a review of each highlight; returns how many were known
+    pub fn mark_reviewed(&mut self, stable_ids: &[String]) -> Result<usize, LibraryError> {
+        self.mark_reviewed_at(stable_ids, Utc::now())
+    }
+
+    fn mark_reviewed_at(
+        &mut self,
+        stable_ids: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<usize, LibraryError> {
+        let tx = self.conn.transaction()?;
+        let mut updated = 0;
+        let reviewed_at = now.to_rfc3339();
+
+        for stable_id in stable_ids.iter().collect::<BTreeSet<_>>() {
+            updated += tx.execute(
+                "INSERT INTO review_tracking (stable_id, last_reviewed, review_count)
+                 SELECT stable_id, ?2, 1 FROM highlights WHERE stable_id = ?1
+                 ON CONFLICT(stable_id) DO UPDATE SET
+                    last_reviewed = excluded.last_reviewed,
+                    review_count = review_count + 1",
+                params![stable_id, reviewed_at],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(updated)
+    }
+}
+
+/// Stored UTC creation time, else the raw date parsed as UTC
+fn created_at(normalized: Option<String>, raw: &str) -> Option<DateTime<Utc>> {
+    normalized
+        .and_then(|value| DateTime::parse_from_rfc3339(&value).ok())
+        .map(|dt| dt.with_timezone(&Utc))
+        .or_else(|| parse_timestamp(raw, utc_offset()))
+}
+
+/// Days since the last review, capped; unknown or unparsable dates count as
+/// never reviewed
+fn review_weight(last_reviewed: Option<&str>, now: DateTime<Utc>) -> f64 {
+    match last_reviewed.and_then(|date| DateTime::parse_from_rfc3339(date).ok()) {
+        Some(reviewed) => {
+            let days = (now - reviewed.with_timezone(&Utc)).num_seconds() as f64 / 86_400.0;
+            days.clamp(MIN_WEIGHT_DAYS, NEVER_REVIEWED_DAYS)
+        }
+        None => NEVER_REVIEWED_DAYS,
+    }
+}
+
+fn time_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Small seedable PRNG; sampling needs no cryptographic quality
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in (0, 1], so `ln` never sees zero
+    fn next_f64(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Book, Highlight};
+    use chrono::Duration;
+
+    fn review_store(highlights: usize) -> LibraryStore {
+        let mut store = LibraryStore::open_in_memory().unwrap();
+        store.merge_books(&[review_book(highlights, "bm")]).unwrap();
+        store
+    }
+
+    fn review_book(highlights: usize, device_prefix: &str) -> Book {
+        let mut book = Book::new("vol1".to_string(), "Livro".to_string(), "Autor".to_string());
+        for n in 0..highlights {
+            let mut highlight = Highlight::new(
+                format!("{}{}", device_prefix, n),
+                format!("Passage {}", n),
+                "2025-01-24T10:00:00.000".to_string(),
+            );
+            highlight.stable_id = format!("s{:02}", n);
+            book.highlights.push(highlight);
+        }
+        book
+    }
+
+    fn ids(highlights: &[ReviewHighlight]) -> Vec<&str> {
+        highlights.iter().map(|h| h.stable_id.as_str()).collect()
+    }
+
+    fn seeded(seed: u64, mode: ReviewMode) -> ReviewOptions {
+        ReviewOptions {
+            mode,
+            seed: Some(seed),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_never_reviewed_dominate_early_draws() {
+        let mut store = review_store(20);
+        let now = Utc::now();
+        let reviewed: Vec<String> = (5..20).map(|n| format!("s{:02}", n)).collect();
+        store
+            .mark_reviewed_at(&reviewed, now - Duration::days(1))
+            .unwrap();
+
+        let first_draws = |mode| {
+            (0..200)
+                .filter(|&seed| {
+                    let picked = store
+                        .review_highlights_at(1, &seeded(seed, mode), now)
+                        .unwrap();
+                    !reviewed.contains(&picked[0].stable_id)
+                })
+                .count()
+        };
+
+        // 5 × 365 vs 15 × 1 in weight, versus 5 in 20 uniformly
+        assert!(first_draws(ReviewMode::LeastRecent) >= 190);
+        let uniform = first_draws(ReviewMode::Uniform);
+        assert!((20..=80).contains(&uniform), "uniform: {}", uniform);
+    }
+
+    #[test]
+    fn test_seed_is_deterministic_without_repeats() {
+        let store = review_store(30);
+        let options = seeded(42, ReviewMode::LeastRecent);
+
+        let first = store.review_highlights(10, &options).unwrap();
+        let second = store.review_highlights(10, &options).unwrap();
+        assert_eq!(ids(&first), ids(&second));
+        assert_ne!(
+            ids(&first),
+            ids(&store
+                .review_highlights(10, &seeded(43, ReviewMode::LeastRecent))
+                .unwrap())
+        );
+
+        // Asking for more than exist returns each highlight once
+        let all = store.review_highlights(100, &options).unwrap();
+        let unique: BTreeSet<&str> = ids(&all).into_iter().collect();
+        assert_eq!(all.len(), 30);
+        assert_eq!(unique.len(), 30);
+    }
+
+    #[test]
+    fn test_review_state_survives_reimport() {
+        let mut store = review_store(3);
+        let marked = store
+            .mark_reviewed(&["s01".to_string(), "s01".to_string(), "missing".to_string()])
+            .unwrap();
+        assert_eq!(marked, 1);
+
+        // New device IDs, same stable IDs
+        store.merge_books(&[review_book(3, "moved")]).unwrap();
+        store.mark_reviewed(&["s01".to_string()]).unwrap();
+
+        let all = store
+            .review_highlights(3, &seeded(1, ReviewMode::Uniform))
+            .unwrap();
+        let s01 = all.iter().find(|h| h.stable_id == "s01").unwrap();
+        assert_eq!(s01.review_count, 2);
+        assert!(s01.last_reviewed.is_some());
+        assert!(all
+            .iter()
+            .filter(|h| h.stable_id != "s01")
+            .all(|h| h.last_reviewed.is_none()));
+    }
+
+    #[test]
+    fn test_favorites_and_tag_restrictions() {
+        let mut store = review_store(4);
+        store.set_favorite("s00", true).unwrap();
+        store
+            .conn
+            .execute(
+                "INSERT INTO tags (stable_id, tag) VALUES ('s02', 'stoicism')",
+                [],
+            )
+            .unwrap();
+
+        let favorites = ReviewOptions {
+            favorites_only: true,
+            ..seeded(7, ReviewMode::LeastRecent)
+        };
+        let picked = store.review_highlights(5, &favorites).unwrap();
+        assert_eq!(ids(&picked), vec!["s00"]);
+        assert!(picked[0].favorite);
+
+        let tagged = ReviewOptions {
+            tag: Some("stoicism".to_string()),
+            ..seeded(7, ReviewMode::LeastRecent)
+        };
+        assert_eq!(
+            ids(&store.review_highlights(5, &tagged).unwrap()),
+            vec!["s02"]
+        );
+    }
+}
@@ -0,0 +1,30 @@
+//! Versioned JSON of imported books, for third-party tools
+//!
+//! `Book` and `Highlight` are internal and change between releases. Scripts
+//! consuming khi's output get `LibraryExportV1` instead: a frozen shape,
+//! converted from the models in `v1` and described by a JSON Schema
+//! (`SCHEMA_V1`) to validate against. A breaking change adds a `v2` module
+//! next to it and leaves V1 as it is.
+//!
+//! Written by the `export_library_json` command and printed by
+//! `khi --headless-sync --library-json`.
+
+pub mod v1;
+
+pub use v1::{LibraryExportV1, SCHEMA_V1};
+
+use crate::models::{Book, KoboDevice};
+use crate::utils::fs::atomic_write;
+use std::io;
+use std::path::Path;
+
+/// Write the V1 JSON of `books` (imported from `device`) to `path`
+pub fn write_library_json(
+    path: &Path,
+    books: &[Book],
+    device: Option<&KoboDevice>,
+) -> io::Result<()> {
+    let library = LibraryExportV1::now(books, device);
+    let json = serde_json::to_string_pretty(&library).map_err(io::Error::other)?;
+    atomic_write(path, json.as_bytes())
+}
@@ -0,0 +1,426 @@
+//! Version 1 of the library JSON
+//!
+//! Field set frozen: the converters below build every struct with a full
+//! literal, so a field added here without a source fails to compile, and a
+//! new internal `BookKind` has to be mapped explicitly.
+
+use crate::models::{Book, BookKind, Highlight, KoboDevice};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+/// JSON Schema of `LibraryExportV1`
+pub const SCHEMA_V1: &str = include_str!("library-export-v1.schema.json");
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryExportV1 {
+    /// Always `SCHEMA_VERSION`
+    pub schema_version: u32,
+    /// RFC 3339, UTC
+    pub generated_at: String,
+    pub device: Option<DeviceV1>,
+    pub books: Vec<BookV1>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceV1 {
+    pub name: String,
+    pub serial_number: Option<String>,
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BookV1 {
+    /// Kobo content ID
+    pub id: String,
+    pub slug: String,
+    pub kind: BookKindV1,
+    pub title: String,
+    pub author: String,
+    pub authors: Vec<String>,
+    pub isbn: Option<String>,
+    pub publisher: Option<String>,
+    pub language: Option<String>,
+    pub series: Option<String>,
+    pub series_index: Option<f32>,
+    pub publication_year: Option<i32>,
+    pub description: Option<String>,
+    pub source_url: Option<String>,
+    pub date_last_read: Option<String>,
+    pub highlights: Vec<HighlightV1>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BookKindV1 {
+    Book,
+    PocketArticle,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct HighlightV1 {
+    /// The stable ID, or the device's when the highlight has none
+    pub id: String,
+    pub text: String,
+    pub note: Option<String>,
+    pub chapter: Option<String>,
+    /// Fraction of the chapter or the book
+    pub progress: Option<f64>,
+    pub page: Option<u32>,
+    pub color: Option<String>,
+    pub created_at: String,
+    pub hidden_on_device: bool,
+}
+
+impl LibraryExportV1 {
+    pub fn new(books: &[Book], device: Option<&KoboDevice>, generated_at: DateTime<Utc>) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            generated_at: generated_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            device: device.map(DeviceV1::from),
+            books: books.iter().map(BookV1::from).collect(),
+        }
+    }
+
+    /// Library JSON generated now
+    pub fn now(books: &[Book], device: Option<&KoboDevice>) -> Self {
+        Self::new(books, device, Utc::now())
+    }
+}
+
+impl From<&KoboDevice> for DeviceV1 {
+    fn from(device: &KoboDevice) -> Self {
+        Self {
+            name: device.name.clone(),
+            serial_number: device.serial_number.clone(),
+            model: device.model.clone(),
+        }
+    }
+}
+
+impl From<BookKind> for BookKindV1 {
+    fn from(kind: BookKind) -> Self {
+        match kind {
+            BookKind::Book | BookKind::KindleImport => Self::Book,
+            BookKind::PocketArticle => Self::PocketArticle,
+        }
+    }
+}
+
+impl From<&Book> for BookV1 {
+    fn from(book: &Book) -> Self {
+        Self {
+            id: book.content_id.clone(),
+            slug: book.slug.clone(),
+            kind: book.kind.into(),
+            title: book.title.clone(),
+            author: book.author.clone(),
+            authors: book.authors.clone(),
+            isbn: book.isbn.clone(),
+            publisher: book.publisher.clone(),
+            language: book.language.clone(),
+            series: book.series.clone(),
+            series_index: book.series_index,
+            publication_year: book.publication_year,
+            description: book.description.clone(),
+            source_url: book.source_url.clone(),
+            date_last_read: book.date_last_read.clone(),
+            highlights: book.highlights.iter().map(HighlightV1::from).collect(),
+        }
+    }
+}
+
+impl From<&Highlight> for HighlightV1 {
+    fn from(highlight: &Highlight) -> Self {
+        let id = if highlight.stable_id.is_empty() {
+            &highlight.id
+        } else {
+            &highlight.stable_id
+        };
+        Self {
+            id: id.clone(),
+            text: highlight.text.clone(),
+            note: highlight.annotation.clone(),
+            chapter: highlight.chapter_title.clone(),
+            progress: highlight.chapter_progress,
+            page: highlight.page,
+            color: highlight.color.clone(),
+            created_at: highlight.date_created.clone(),
+            hidden_on_device: highlight.is_excluded,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    /// Check `value` against the JSON Schema keywords `SCHEMA_V1` uses
+    fn validate(root: &Value, schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/definitions/");
+            return validate(root, &root["definitions"][name], value, path, errors);
+        }
+        if let Some(options) = schema["oneOf"].as_array() {
+            let matching = options
+                .iter()
+                .filter(|option| {
+                    let mut option_errors = Vec::new();
+                    validate(root, option, value, path, &mut option_errors);
+                    option_errors.is_empty()
+                })
+                .count();
+            if matching != 1 {
+                errors.push(format!("{}: matches {} oneOf options", path, matching));
+            }
+        }
+        if let Some(types) = schema.get("type") {
+            let types: Vec<&str> = match types {
+                Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+                other => other.as_str().into_iter().collect(),
+            };
+            let matches = |name: &str| match name {
+                "null" => value.is_null(),
+                "boolean" => value.is_boolean(),
+                "string" => value.is_string(),
+                "number" => value.is_number(),
+                "integer" => value.is_i64() || value.is_u64(),
+                "array" => value.is_array(),
+                "object" => value.is_object(),
+                _ => false,
+            };
+            if !types.iter().any(|name| matches(name)) {
+                errors.push(format!("{}: {} is not {:?}", path, value, types));
+                return;
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if value != expected {
+                errors.push(format!("{}: {} is not {}", path, value, expected));
+            }
+        }
+        if let Some(allowed) = schema["enum"].as_array() {
+            if !allowed.contains(value) {
+                errors.push(format!("{}: {} not in {:?}", path, value, allowed));
+            }
+        }
+        if let Some(number) = value.as_f64() {
+            if schema["minimum"].as_f64().is_some_and(|min| number < min)
+                || schema["maximum"].as_f64().is_some_and(|max| number > max)
+            {
+                errors.push(format!("{}: {} out of range", path, number));
+            }
+        }
+        if let (Some(object), Some(properties)) =
+            (value.as_object(), schema["properties"].as_object())
+        {
+            for required in schema["required"].as_array().into_iter().flatten() {
+                let required = required.as_str().unwrap();
+                if !object.contains_key(required) {
+                    errors.push(format!("{}: missing {}", path, required));
+                }
+            }
+            for (key, field) in object {
+                match properties.get(key) {
+                    Some(property) => {
+                        validate(root, property, field, &format!("{}.{}", path, key), errors)
+                    }
+                    None if schema["additionalProperties"] == Value::Bool(false) => {
+                        errors.push(format!("{}: unexpected {}", path, key))
+                    }
+                    None => {}
+                }
+            }
+        }
+        if let (Some(items), Some(item_schema)) = (value.as_array(), schema.get("items")) {
+            for (index, item) in items.iter().enumerate() {
+                validate(
+                    root,
+                    item_schema,
+                    item,
+                    &format!("{}[{}]", path, index),
+                    errors,
+                );
+            }
+        }
+    }
+
+    fn schema_errors(value: &Value) -> Vec<String> {
+        let schema: Value = serde_json::from_str(SCHEMA_V1).unwrap();
+        let mut errors = Vec::new();
+        validate(&schema, &schema, value, "$", &mut errors);
+        errors
+    }
+
+    fn device() -> KoboDevice {
+        KoboDevice {
+            name: "KOBOeReader".to_string(),
+            path: "/Volumes/KOBOeReader".to_string(),
+            is_valid: true,
+            serial_number: Some("N418123456789".to_string()),
+            invalid_reason: None,
+            is_stale: false,
+            model: Some("Kobo Clara 2E".to_string()),
+            friendly_name: None,
+            suggested_profile: None,
+        }
+    }
+
+    /// A book with every field `BookV1` reads set
+    fn full_book() -> Book {
+        let mut book = Book::new(
+            "3456789012".to_string(),
+            "The Slow Web".to_string(),
+            "Jane Doe".to_string(),
+        );
+        book.slug = "the-slow-web-1a2b3c".to_string();
+        book.kind = BookKind::PocketArticle;
+        book.authors = vec!["Jane Doe".to_string()];
+        book.isbn = Some("9780000000000".to_string());
+        book.publisher = Some("Example Press".to_string());
+        book.language = Some("en".to_string());
+        book.series = Some("Essays".to_string());
+        book.series_index = Some(2.0);
+        book.publication_year = Some(2019);
+        book.description = Some("On reading slowly.".to_string());
+        book.source_url = Some("https://example.com/slow-web".to_string());
+        book.date_last_read = Some("2025-03-01T10:00:00".to_string());
+        let mut highlight = Highlight::new(
+            "bm1".to_string(),
+            "Slow down.".to_string(),
+            "2025-03-01T10:00:00".to_string(),
+        );
+        highlight.stable_id = "8d1f0c2b7a6e5d4c".to_string();
+        highlight.annotation = Some("Agreed".to_string());
+        highlight.chapter_title = Some("Intro".to_string());
+        highlight.chapter_progress = Some(0.25);
+        highlight.page = Some(3);
+        highlight.color = Some("yellow".to_string());
+        highlight.is_excluded = true;
+        book.highlights.push(highlight);
+        book
+    }
+
+    #[test]
+    fn test_every_v1_field_is_populated() {
+        let library = LibraryExportV1::new(&[full_book()], Some(&device()), Utc::now());
+
+        // No `..`: a field added to V1 fails to compile until it's checked
+        let LibraryExportV1 {
+            schema_version,
+            generated_at,
+            device,
+            books,
+        } = library;
+        assert_eq!(schema_version, SCHEMA_VERSION);
+        assert!(DateTime::parse_from_rfc3339(&generated_at).is_ok());
+        let DeviceV1 {
+            name,
+            serial_number,
+            model,
+        } = device.unwrap();
+        assert!(!name.is_empty() && serial_number.is_some() && model.is_some());
+
+        let BookV1 {
+            id,
+            slug,
+            kind,
+            title,
+            author,
+            authors,
+            isbn,
+            publisher,
+            language,
+            series,
+            series_index,
+            publication_year,
+            description,
+            source_url,
+            date_last_read,
+            highlights,
+        } = books.into_iter().next().unwrap();
+        assert_eq!(id, "3456789012");
+        assert_eq!(kind, BookKindV1::PocketArticle);
+        assert!(![&slug, &title, &author].iter().any(|s| s.is_empty()));
+        assert!(!authors.is_empty());
+        assert!([
+            isbn,
+            publisher,
+            language,
+            series,
+            description,
+            source_url,
+            date_last_read
+        ]
+        .iter()
+        .all(Option::is_some));
+        assert!(series_index.is_some() && publication_year.is_some());
+
+        let HighlightV1 {
+            id,
+            text,
+            note,
+            chapter,
+            progress,
+            page,
+            color,
+            created_at,
+            hidden_on_device,
+        } = highlights.into_iter().next().unwrap();
+        assert_eq!(id, "8d1f0c2b7a6e5d4c");
+        assert!(!text.is_empty() && !created_at.is_empty());
+        assert!([note, chapter, color].iter().all(Option::is_some));
+        assert!(progress.is_some() && page.is_some() && hidden_on_device);
+    }
+
+    #[test]
+    fn test_fixture_library_round_trips_and_matches_schema() {
+        let mut books = crate::sample::sample_books();
+        books.push(full_book());
+        let library = LibraryExportV1::new(&books, Some(&device()), Utc::now());
+
+        let json = serde_json::to_string_pretty(&library).unwrap();
+        let parsed: LibraryExportV1 = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, library);
+
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(schema_errors(&value), Vec::<String>::new());
+        let without_device = serde_json::to_value(LibraryExportV1::now(&books, None)).unwrap();
+        assert_eq!(schema_errors(&without_device), Vec::<String>::new());
+
+        // The schema is strict enough to catch drift
+        let mut drifted = value.clone();
+        drifted["books"][0]["contentId"] = Value::from("vol1");
+        drifted["books"][0]["highlights"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("text");
+        drifted["schemaVersion"] = Value::from(2);
+        assert_eq!(
+            schema_errors(&drifted).len(),
+            3,
+            "{:?}",
+            schema_errors(&drifted)
+        );
+    }
+
+    #[test]
+    fn test_write_library_json() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("library.json");
+        super::super::write_library_json(&path, &[full_book()], None).unwrap();
+        let value: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(value["schemaVersion"], 1);
+        assert_eq!(
+            value["books"][0]["sourceUrl"],
+            "https://example.com/slow-web"
+        );
+        assert!(schema_errors(&value).is_empty());
+    }
+}
@@ -1,11 +1,32 @@
+use crate::db::kobo::parse_kobo_datetime;
+use crate::db::schema::SchemaCompatibility;
+use crate::utils::author::parse_authors;
+use crate::utils::date::{parse_timestamp, utc_offset};
+use crate::utils::format::{format_duration_hm, format_percent};
+use crate::utils::fs::LineEndings;
+use crate::utils::metrics::MetricsSummary;
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 
+/// A book with its highlights, as imported and as sent back for export
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Book {
     pub content_id: String,
+    /// Title as shown; without the subtitle once titles are processed
     pub title: String,
+    /// Part after the title's first `:`, split off by `utils::titles`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
+    /// Title as stored by Kobo, when title processing changed it
+    #[serde(default, skip_serializing_if = "Option::is_none", alias = "raw_title")]
+    pub raw_title: Option<String>,
+    /// Raw attribution as stored by Kobo (kept for compatibility)
     pub author: String,
+    /// Normalized, de-duplicated author names parsed from `author`
+    #[serde(default)]
+    pub authors: Vec<String>,
     pub isbn: Option<String>,
     pub publisher: Option<String>,
     pub language: Option<String>,
@@ -14,7 +35,134 @@ pub struct Book {
     #[serde(skip)]
     pub file_path: Option<String>,
     pub cover_path: Option<String>,
+    /// Small cover for the library grid; `cover_path` may still be unset
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "thumbnail_path"
+    )]
+    pub thumbnail_path: Option<String>,
+    /// Series name from Calibre metadata (`calibre:series`)
+    #[serde(default)]
+    pub series: Option<String>,
+    /// Position in the series (`calibre:series_index`, may be fractional)
+    #[serde(default)]
+    pub series_index: Option<f32>,
+    /// Table of contents from the Kobo TOC rows (ContentType 899)
+    #[serde(default)]
+    pub toc: Vec<TocEntry>,
     pub highlights: Vec<Highlight>,
+    /// All highlights of the book when `highlights` holds only the first
+    /// page (results sent to the frontend; see `get_book_highlights`)
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "highlight_total"
+    )]
+    pub highlight_total: Option<usize>,
+    /// Words looked up in the dictionary while reading this book
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vocabulary: Vec<VocabEntry>,
+    /// URL- and filesystem-safe ID, unique within an import (see
+    /// `utils::slug`)
+    #[serde(default)]
+    pub slug: String,
+    /// What `chapter_progress` is relative to; `None` when the format is
+    /// unknown (books imported before it was detected)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress_scope: Option<ProgressScope>,
+    /// Year from the EPUB's `<dc:date>`, when known
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "publication_year"
+    )]
+    pub publication_year: Option<i32>,
+    /// Tells this book apart from others of the batch with the same title and
+    /// author ("2019", or the slug); see `utils::disambiguation`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disambiguator: Option<String>,
+    #[serde(default)]
+    pub kind: BookKind,
+    /// Web address of a Pocket article
+    #[serde(default, skip_serializing_if = "Option::is_none", alias = "source_url")]
+    pub source_url: Option<String>,
+    /// Borrowed from a library (OverDrive or an Adobe DRM loan)
+    #[serde(default, alias = "is_loan")]
+    pub is_loan: bool,
+    /// Highlights whose book has no content row left on the device (deleted
+    /// book, bookmarks kept); the title is derived from the file name
+    #[serde(default, alias = "is_orphaned")]
+    pub is_orphaned: bool,
+    /// When the loan ends, as recorded by the device; `None` if unknown
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "loan_expiry"
+    )]
+    pub loan_expiry: Option<String>,
+    /// How far the book was read (0–100), as recorded by the device
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "percent_read"
+    )]
+    pub percent_read: Option<f64>,
+    /// Total time spent reading the book, as recorded by the device
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "time_spent_reading_secs"
+    )]
+    pub time_spent_reading_secs: Option<u64>,
+    /// Reading notes (markdown) kept in the library, never on the device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+/// What kind of content a `Book` holds
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BookKind {
+    #[default]
+    Book,
+    /// A web article saved with Kobo's Pocket integration
+    PocketArticle,
+    /// A book from a Kindle "My Clippings.txt" (see `kindle`)
+    KindleImport,
+}
+
+/// Which form of a book's title a setting uses
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TitleForm {
+    /// As stored by Kobo
+    Raw,
+    /// Title-cased and split by `utils::titles`
+    #[default]
+    Processed,
+}
+
+/// What a Kobo `ChapterProgress` value is a fraction of
+///
+/// Kepubs (store books and Calibre kepub output) report progress within the
+/// current chapter; sideloaded epubs report it within the whole book.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProgressScope {
+    Chapter,
+    Book,
+}
+
+/// One entry of a book's table of contents
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TocEntry {
+    pub title: String,
+    /// Nesting level, 1 for top-level chapters
+    pub depth: u32,
+    /// Position in reading order, from 0
+    pub order: u32,
 }
 
 impl Book {
@@ -22,6 +170,9 @@ impl Book {
         Self {
             content_id,
             title,
+            subtitle: None,
+            raw_title: None,
+            authors: parse_authors(&author),
             author,
             isbn: None,
             publisher: None,
@@ -30,7 +181,25 @@ impl Book {
             description: None,
             file_path: None,
             cover_path: None,
+            thumbnail_path: None,
+            series: None,
+            series_index: None,
+            toc: Vec::new(),
             highlights: Vec::new(),
+            highlight_total: None,
+            vocabulary: Vec::new(),
+            slug: String::new(),
+            progress_scope: None,
+            publication_year: None,
+            disambiguator: None,
+            kind: BookKind::Book,
+            source_url: None,
+            is_loan: false,
+            is_orphaned: false,
+            loan_expiry: None,
+            percent_read: None,
+            time_spent_reading_secs: None,
+            notes: None,
         }
     }
 
@@ -41,8 +210,116 @@ impl Book {
     pub fn add_highlight(&mut self, highlight: Highlight) {
         self.highlights.push(highlight);
     }
+
+    /// Title with its subtitle ("Title: Subtitle")
+    pub fn full_title(&self) -> String {
+        match &self.subtitle {
+            Some(subtitle) => format!("{}: {}", self.title, subtitle),
+            None => self.title.clone(),
+        }
+    }
+
+    /// The whole title in `form`
+    pub fn title_in(&self, form: TitleForm) -> String {
+        match (form, &self.raw_title) {
+            (TitleForm::Raw, Some(raw)) => raw.clone(),
+            _ => self.full_title(),
+        }
+    }
+
+    /// Authors joined for display, falling back to the raw attribution
+    pub fn display_author(&self) -> String {
+        if self.authors.is_empty() {
+            self.author.clone()
+        } else {
+            self.authors.join(", ")
+        }
+    }
+
+    /// Where a library loan stands at `now`; `None` for books that aren't
+    /// loans
+    ///
+    /// Loans without a readable due date are `Active`.
+    pub fn loan_state(&self, now: NaiveDateTime) -> Option<LoanState> {
+        if !self.is_loan {
+            return None;
+        }
+        let Some(expiry) = self.loan_expiry.as_deref().and_then(parse_kobo_datetime) else {
+            return Some(LoanState::Active);
+        };
+        Some(if expiry <= now {
+            LoanState::Expired
+        } else if expiry - now <= Duration::days(LOAN_EXPIRING_DAYS) {
+            LoanState::Expiring
+        } else {
+            LoanState::Active
+        })
+    }
+
+    /// 1-based number of a highlight's chapter in the table of contents
+    pub fn chapter_number(&self, highlight: &Highlight) -> Option<u32> {
+        let title = highlight.chapter_title.as_deref()?;
+        self.toc
+            .iter()
+            .find(|entry| entry.title == title)
+            .map(|entry| entry.order + 1)
+    }
+
+    /// Highlights in reading order
+    ///
+    /// Chapter-relative progress only orders highlights within a chapter, so
+    /// those books sort by TOC position first. Books of unknown format keep
+    /// their stored (creation) order, and highlights without a position go
+    /// last.
+    pub fn highlights_by_position(&self) -> Vec<&Highlight> {
+        let mut highlights: Vec<&Highlight> = self.highlights.iter().collect();
+        match self.progress_scope {
+            None => {}
+            Some(ProgressScope::Book) => highlights.sort_by(|a, b| {
+                position_key(a.chapter_progress).total_cmp(&position_key(b.chapter_progress))
+            }),
+            Some(ProgressScope::Chapter) => highlights.sort_by(|a, b| {
+                let chapter = |h: &Highlight| self.chapter_number(h).unwrap_or(u32::MAX);
+                chapter(a).cmp(&chapter(b)).then(
+                    position_key(a.chapter_progress).total_cmp(&position_key(b.chapter_progress)),
+                )
+            }),
+        }
+        highlights
+    }
+}
+
+/// Loans due within this many days are `LoanState::Expiring`
+pub const LOAN_EXPIRING_DAYS: i64 = 7;
+
+/// Where a library loan stands
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum LoanState {
+    Expired,
+    Expiring,
+    Active,
+}
+
+/// Move expired and expiring loans to the front of `books`, soonest due
+/// first, so their highlights are saved before the book leaves the device
+///
+/// The sort is stable: other books keep their order.
+pub fn expiring_loans_first(books: &mut [Book], now: NaiveDateTime) {
+    books.sort_by_cached_key(|book| match book.loan_state(now) {
+        Some(LoanState::Expired | LoanState::Expiring) => {
+            (0, book.loan_expiry.as_deref().and_then(parse_kobo_datetime))
+        }
+        _ => (1, None),
+    });
 }
 
+/// Sort key of a progress value; missing progress sorts after any position
+fn position_key(progress: Option<f64>) -> f64 {
+    progress.unwrap_or(f64::INFINITY)
+}
+
+/// One highlight (a Kobo bookmark with text)
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Highlight {
@@ -53,7 +330,62 @@ pub struct Highlight {
     pub chapter_progress: Option<f64>,
     pub container_path: Option<String>,
     pub date_created: String,
+    /// `date_created` normalized to UTC; `None` when it can't be parsed
+    #[serde(default, alias = "date_created_utc")]
+    pub date_created_utc: Option<DateTime<Utc>>,
     pub color: Option<String>,
+    /// IDs of the Kobo bookmarks merged into this highlight (empty if not merged)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged_from: Vec<String>,
+    /// Content-derived ID that survives BookmarkID changes (empty for legacy data)
+    #[serde(default)]
+    pub stable_id: String,
+    /// Deleted on the device (a hidden Bookmark row), imported on request
+    #[serde(default, alias = "is_excluded")]
+    pub is_excluded: bool,
+    /// Estimated page from progress and the book's page count, when known
+    #[serde(default)]
+    pub page: Option<u32>,
+    /// Selected text, or an illustration (no text)
+    #[serde(default, skip_serializing_if = "HighlightKind::is_text")]
+    pub kind: HighlightKind,
+    /// Cached image of a figure highlight, extracted from the EPUB
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "figure_path"
+    )]
+    pub figure_path: Option<String>,
+    /// Tags kept in the library, attached for exports
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+/// What a highlight selects
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HighlightKind {
+    #[default]
+    Text,
+    /// A region around a figure: the Bookmark row has no text, only the
+    /// position (`container_path`) of the page holding the image
+    Figure,
+}
+
+impl HighlightKind {
+    /// Kind of a highlight with `text` at `container_path`: blank text
+    /// with a position is a figure
+    pub fn detect(text: &str, container_path: Option<&str>) -> Self {
+        if text.trim().is_empty() && container_path.is_some_and(|path| !path.trim().is_empty()) {
+            HighlightKind::Figure
+        } else {
+            HighlightKind::Text
+        }
+    }
+
+    fn is_text(&self) -> bool {
+        *self == HighlightKind::Text
+    }
 }
 
 impl Highlight {
@@ -61,23 +393,286 @@ impl Highlight {
         Self {
             id,
             text,
+            date_created_utc: parse_timestamp(&date_created, utc_offset()),
             date_created,
             annotation: None,
             chapter_title: None,
             chapter_progress: None,
             container_path: None,
             color: None,
+            merged_from: Vec::new(),
+            stable_id: String::new(),
+            is_excluded: false,
+            page: None,
+            kind: HighlightKind::Text,
+            figure_path: None,
+            tags: Vec::new(),
         }
     }
+
+    /// When the highlight was made, in UTC
+    ///
+    /// Falls back to parsing `date_created` as UTC when it wasn't normalized.
+    pub fn created_at(&self) -> Option<DateTime<Utc>> {
+        self.date_created_utc
+            .or_else(|| parse_timestamp(&self.date_created, utc_offset()))
+    }
+
+    /// `created_at` as wall-clock time at `offset`, the assumed offset of
+    /// the reader, so dates fall on the reader's calendar day
+    pub fn created_local(&self, offset: FixedOffset) -> Option<NaiveDateTime> {
+        self.created_at()
+            .map(|created| created.with_timezone(&offset).naive_local())
+    }
+
+    /// Whether both refer to the same quote: `stable_id` first, device ID second
+    pub fn same_highlight(&self, other: &Highlight) -> bool {
+        (!self.stable_id.is_empty() && self.stable_id == other.stable_id) || self.id == other.id
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Map previous highlight IDs to the IDs of the matching re-imported highlights
+///
+/// State keyed by highlight ID (favorites, export tracking) is carried over
+/// through this map when the device reassigns BookmarkIDs, e.g. after a
+/// factory reset. Stable-ID matches win over device-ID matches.
+pub fn remap_highlight_ids(
+    previous: &[Highlight],
+    current: &[Highlight],
+) -> HashMap<String, String> {
+    let mut remapped = HashMap::new();
+    for old in previous {
+        let matched = current
+            .iter()
+            .find(|new| !old.stable_id.is_empty() && new.stable_id == old.stable_id)
+            .or_else(|| current.iter().find(|new| new.id == old.id));
+        if let Some(new) = matched {
+            remapped.insert(old.id.clone(), new.id.clone());
+        }
+    }
+    remapped
+}
+
+/// Summary statistics over a book's highlights
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BookStats {
+    pub highlights_count: usize,
+    pub notes_count: usize,
+    /// Earliest parseable highlight date (YYYY-MM-DD)
+    pub first_highlight_date: Option<String>,
+    /// Latest parseable highlight date (YYYY-MM-DD)
+    pub last_highlight_date: Option<String>,
+    /// Chapter with most highlights; ties go to the chapter read first
+    pub top_chapter: Option<String>,
+    /// How far the book was read, e.g. "86%"
+    #[serde(default)]
+    pub percent_read: Option<String>,
+    /// Time spent reading, e.g. "4 h 32 m"
+    #[serde(default)]
+    pub time_spent_reading: Option<String>,
+}
+
+impl From<&Book> for BookStats {
+    fn from(book: &Book) -> Self {
+        Self::at_offset(book, utc_offset())
+    }
+}
+
+impl BookStats {
+    /// Statistics of `book` with dates on the calendar at `offset`
+    pub fn at_offset(book: &Book, offset: FixedOffset) -> Self {
+        let highlights: Vec<&Highlight> = book.highlights.iter().collect();
+        let (first_highlight_date, last_highlight_date) = date_range(&highlights, offset);
+        Self {
+            highlights_count: book.highlights.len(),
+            notes_count: book
+                .highlights
+                .iter()
+                .filter(|h| {
+                    h.annotation
+                        .as_deref()
+                        .is_some_and(|a| !a.trim().is_empty())
+                })
+                .count(),
+            first_highlight_date,
+            last_highlight_date,
+            top_chapter: ChapterMap::at_offset(book, offset)
+                .most_highlighted()
+                .map(|chapter| chapter.title.clone()),
+            percent_read: book.percent_read.map(format_percent),
+            time_spent_reading: book.time_spent_reading_secs.map(format_duration_hm),
+        }
+    }
+}
+
+/// Earliest and latest parseable dates (YYYY-MM-DD) of `highlights`, on the
+/// calendar at `offset`
+fn date_range(highlights: &[&Highlight], offset: FixedOffset) -> (Option<String>, Option<String>) {
+    let dates: Vec<NaiveDate> = highlights
+        .iter()
+        .filter_map(|h| h.created_local(offset).map(|dt| dt.date()))
+        .collect();
+    (
+        dates.iter().min().map(|d| d.format("%Y-%m-%d").to_string()),
+        dates.iter().max().map(|d| d.format("%Y-%m-%d").to_string()),
+    )
+}
+
+/// Highlights of one chapter of a `ChapterMap`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChapterSummary {
+    pub title: String,
+    /// Nesting level, for chapters from the table of contents
+    pub depth: Option<u32>,
+    pub highlights_count: usize,
+    /// Earliest parseable highlight date (YYYY-MM-DD)
+    pub first_highlight_date: Option<String>,
+    /// Latest parseable highlight date (YYYY-MM-DD)
+    pub last_highlight_date: Option<String>,
+    /// Mean position of the highlights, in the book's progress scope
+    pub average_progress: Option<f64>,
+}
+
+/// A book's chapters in reading order with how much was highlighted in each
+///
+/// With a table of contents every entry is listed, unhighlighted ones with a
+/// count of 0, followed by chapters it doesn't know. Without one, chapters
+/// are the highlights' chapter titles, ordered by their first position.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChapterMap {
+    pub content_id: String,
+    /// Whether the chapters come from the table of contents
+    pub from_toc: bool,
+    pub chapters: Vec<ChapterSummary>,
+    /// Highlights without a chapter title
+    pub unassigned: usize,
+}
+
+impl ChapterMap {
+    /// Chapter with most highlights; ties go to the chapter read first
+    pub fn most_highlighted(&self) -> Option<&ChapterSummary> {
+        self.chapters
+            .iter()
+            .filter(|chapter| chapter.highlights_count > 0)
+            .fold(None, |best: Option<&ChapterSummary>, chapter| match best {
+                Some(best) if best.highlights_count >= chapter.highlights_count => Some(best),
+                _ => Some(chapter),
+            })
+    }
+}
+
+impl From<&Book> for ChapterMap {
+    fn from(book: &Book) -> Self {
+        Self::at_offset(book, utc_offset())
+    }
+}
+
+impl ChapterMap {
+    /// Chapter map of `book` with dates on the calendar at `offset`
+    pub fn at_offset(book: &Book, offset: FixedOffset) -> Self {
+        // Chapters in order of first appearance, with their highlights
+        let mut grouped: Vec<(&str, Vec<&Highlight>)> = Vec::new();
+        let mut unassigned = 0;
+        for highlight in &book.highlights {
+            let Some(chapter) = highlight.chapter_title.as_deref() else {
+                unassigned += 1;
+                continue;
+            };
+            match grouped.iter_mut().find(|(title, _)| *title == chapter) {
+                Some((_, highlights)) => highlights.push(highlight),
+                None => grouped.push((chapter, vec![highlight])),
+            }
+        }
+        // Stable, so chapters without positions keep their first appearance.
+        // Chapter-relative positions can't order chapters: those keep theirs.
+        if book.progress_scope != Some(ProgressScope::Chapter) {
+            grouped.sort_by(|(_, a), (_, b)| first_position(a).total_cmp(&first_position(b)));
+        }
+
+        let summary = |title: &str, depth: Option<u32>, highlights: &[&Highlight]| {
+            let (first_highlight_date, last_highlight_date) = date_range(highlights, offset);
+            let positions: Vec<f64> = highlights
+                .iter()
+                .filter_map(|h| h.chapter_progress)
+                .collect();
+            ChapterSummary {
+                title: title.to_string(),
+                depth,
+                highlights_count: highlights.len(),
+                first_highlight_date,
+                last_highlight_date,
+                average_progress: (!positions.is_empty())
+                    .then(|| positions.iter().sum::<f64>() / positions.len() as f64),
+            }
+        };
+
+        let mut chapters = Vec::new();
+        for entry in &book.toc {
+            let position = grouped.iter().position(|(title, _)| *title == entry.title);
+            let highlights = position.map(|i| grouped.remove(i).1).unwrap_or_default();
+            chapters.push(summary(&entry.title, Some(entry.depth), &highlights));
+        }
+        for (title, highlights) in grouped {
+            chapters.push(summary(title, None, &highlights));
+        }
+
+        Self {
+            content_id: book.content_id.clone(),
+            from_toc: !book.toc.is_empty(),
+            chapters,
+            unassigned,
+        }
+    }
+}
+
+/// Earliest position of `highlights`; missing progress sorts last
+fn first_position(highlights: &[&Highlight]) -> f64 {
+    highlights
+        .iter()
+        .map(|h| position_key(h.chapter_progress))
+        .fold(f64::INFINITY, f64::min)
+}
+
+/// Parse a highlight timestamp (Kobo datetime, epoch or plain date) as a UTC date
+pub fn parse_highlight_date(value: &str) -> Option<NaiveDate> {
+    parse_kobo_datetime(value).map(|dt| dt.date())
+}
+
+/// A detected Kobo volume, sent back by the frontend to import from it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct KoboDevice {
     pub name: String,
     pub path: String,
     pub is_valid: bool,
     pub serial_number: Option<String>,
+    /// Why the device's database can't be imported, when it isn't valid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invalid_reason: Option<String>,
+    /// Another mount of the same device (same serial) that is outdated, like
+    /// a ghost mount left by an unclean eject
+    #[serde(default, alias = "is_stale")]
+    pub is_stale: bool,
+    /// Model read from `.kobo/version`, when recognized
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Name of the device in the registry of known devices
+    #[serde(
+        default,
+        alias = "friendly_name",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub friendly_name: Option<String>,
+    /// Profile the device was imported into, when it isn't the active one
+    #[serde(
+        default,
+        alias = "suggested_profile",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub suggested_profile: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -88,8 +683,40 @@ pub struct ImportProgress {
     pub total_books: usize,
     pub highlights_found: usize,
     pub percentage: f64,
+    /// Book whose cover thumbnail was just generated
+    #[serde(default, skip_serializing_if = "Option::is_none", alias = "content_id")]
+    pub content_id: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "thumbnail_path"
+    )]
+    pub thumbnail_path: Option<String>,
+}
+
+/// Books of a device import, with how its database schema compares with the
+/// schemas khi was tested with
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportResult {
+    pub books: Vec<Book>,
+    /// `None` when the import record couldn't be read back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<SchemaCompatibility>,
+    /// What the UI shows for an unfamiliar schema (see
+    /// `SchemaCompatibility::message`)
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "schema_warning"
+    )]
+    pub schema_warning: Option<String>,
+    /// Timings of the import's steps
+    #[serde(default)]
+    pub metrics: MetricsSummary,
 }
 
+/// Export options, sent with every export and stored in the settings
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct ExportConfig {
@@ -98,8 +725,170 @@ pub struct ExportConfig {
     pub metadata: MetadataConfig,
     #[serde(alias = "date_format")]
     pub date_format: DateFormat,
+    /// Subfolder pattern relative to the export path (supports `{language}`)
+    #[serde(default, alias = "folder_pattern")]
+    pub folder_pattern: String,
+    /// Filename pattern without extension (empty = `{title} - {author}`)
+    #[serde(default, alias = "filename_pattern")]
+    pub filename_pattern: String,
+    /// Transliterate file and folder names to ASCII (for filesystems and
+    /// tools that choke on accents or other scripts)
+    #[serde(default, alias = "ascii_filenames")]
+    pub ascii_filenames: bool,
+    /// Output format (one markdown file per book, or a single references file)
+    #[serde(default)]
+    pub format: ExportFormat,
+    /// Attach highlights as the `note` of citation records
+    #[serde(default, alias = "citation_notes")]
+    pub citation_notes: bool,
+    /// Render the book's table of contents, linking to chapter headings
+    #[serde(default, alias = "include_toc")]
+    pub include_toc: bool,
+    /// Archive every export run into `.khi-snapshots/<timestamp>.zip`
+    #[serde(default, alias = "snapshot_exports")]
+    pub snapshot_exports: bool,
+    /// Newest snapshots kept (0 keeps all)
+    #[serde(default = "default_snapshot_keep", alias = "snapshot_keep")]
+    pub snapshot_keep: usize,
+    /// Total snapshot size kept, in MB (0 means no limit)
+    #[serde(default = "default_snapshot_max_mb", alias = "snapshot_max_mb")]
+    pub snapshot_max_mb: u64,
+    /// Write a `_Bookshelf.md` index of the exported books to the export root
+    #[serde(default, alias = "write_index")]
+    pub write_index: bool,
+    /// Write a `highlights.atom` feed of the exported highlights
+    #[serde(default, alias = "atom_feed")]
+    pub atom_feed: bool,
+    /// Keep only the N most recent feed entries (0 keeps all)
+    #[serde(default, alias = "feed_limit")]
+    pub feed_limit: usize,
+    /// What goes between consecutive highlights
+    #[serde(default, alias = "highlight_separator")]
+    pub highlight_separator: HighlightSeparator,
+    /// Layout of each highlight (ignored with `compact`)
+    #[serde(default, alias = "highlight_style")]
+    pub highlight_style: HighlightStyle,
+    /// Render highlights as single-line list items instead of blockquotes
+    #[serde(default)]
+    pub compact: bool,
+    /// Render the chapter and page/percentage of each highlight
+    #[serde(default = "default_show_location", alias = "show_location")]
+    pub show_location: bool,
+    /// Chapter titles left out of every book: regexes, or globs prefixed
+    /// with `glob:`
+    #[serde(default, alias = "excluded_chapter_patterns")]
+    pub excluded_chapter_patterns: Vec<String>,
+    /// What happens to highlights and notes carrying a redaction marker
+    #[serde(default)]
+    pub redaction: RedactionPolicy,
+    /// Markers of private notes, matched ignoring case on normalized text;
+    /// `/…/` is a regex
+    #[serde(default = "default_redaction_markers", alias = "redaction_markers")]
+    pub redaction_markers: Vec<String>,
+    /// Export every Pocket article into the `Pocket Articles` folder instead
+    /// of the folder pattern's
+    #[serde(default, alias = "group_pocket_articles")]
+    pub group_pocket_articles: bool,
+    /// Books rendered and written at the same time (1 writes in order)
+    #[serde(
+        default = "default_max_concurrent_writes",
+        alias = "max_concurrent_writes"
+    )]
+    pub max_concurrent_writes: usize,
+    /// Indentation of nested bullets in the Logseq format
+    #[serde(default, alias = "bullet_indentation")]
+    pub bullet_indentation: BulletIndentation,
+    /// Whether re-exports rewrite markdown files or append new highlights
+    #[serde(default, alias = "write_mode")]
+    pub write_mode: ExportWriteMode,
+    /// One journal file per month, or a single combined file
+    #[serde(default, alias = "journal_layout")]
+    pub journal_layout: JournalLayout,
+    /// Commit the exported files when the export path is in a git work tree
+    #[serde(default, alias = "git_commit_after_export")]
+    pub git_commit_after_export: bool,
+    /// Message of those commits (`{date}`, `{book_count}`,
+    /// `{highlight_count}`; empty uses the default)
+    #[serde(default, alias = "commit_message_template")]
+    pub commit_message_template: String,
+    /// Write a `.khi.json` sidecar mapping highlights to byte ranges next to
+    /// each markdown file
+    #[serde(default, alias = "write_sidecars")]
+    pub write_sidecars: bool,
+    /// Columns of the CSV and TSV formats
+    #[serde(default)]
+    pub tabular: TabularOptions,
+    /// Template of per-book markdown files (empty uses the built-in layout)
+    #[serde(default, alias = "markdown_template")]
+    pub markdown_template: String,
+    /// Start text exports with a UTF-8 byte order mark (Excel on Windows
+    /// needs one to read CSV as UTF-8)
+    #[serde(default)]
+    pub bom: bool,
+    /// Line endings of text exports
+    #[serde(default, alias = "line_endings")]
+    pub line_endings: LineEndings,
+    /// Group each book's highlights into one section per color (not
+    /// together with the chapter headings of `include_toc`)
+    #[serde(default, alias = "group_by_color")]
+    pub group_by_color: bool,
+    /// Labels of highlight colors, by color name (e.g. blue → Vocabulary)
+    #[serde(default, alias = "color_labels")]
+    pub color_labels: BTreeMap<String, String>,
+    /// Pause after each file written to a cloud-synced folder, in
+    /// milliseconds, so the sync daemon picks it up before the next one
+    #[serde(default, alias = "cloud_settle_ms")]
+    pub cloud_settle_ms: u64,
+    /// Write every book to a staging folder first and move the files into
+    /// place only when all of them succeeded (per-book formats)
+    #[serde(default)]
+    pub transactional: bool,
+}
+
+/// Label of a highlight color: the one in `labels` (matched ignoring case),
+/// else the color name capitalized
+pub fn color_label(labels: &BTreeMap<String, String>, color: &str) -> String {
+    let color = color.trim();
+    labels
+        .iter()
+        .find(|(name, label)| name.trim().eq_ignore_ascii_case(color) && !label.trim().is_empty())
+        .map(|(_, label)| label.trim().to_string())
+        .unwrap_or_else(|| {
+            let mut chars = color.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
+                .unwrap_or_default()
+        })
+}
+
+pub const DEFAULT_SNAPSHOT_KEEP: usize = 20;
+pub const DEFAULT_SNAPSHOT_MAX_MB: u64 = 500;
+
+fn default_snapshot_keep() -> usize {
+    DEFAULT_SNAPSHOT_KEEP
+}
+
+fn default_snapshot_max_mb() -> u64 {
+    DEFAULT_SNAPSHOT_MAX_MB
+}
+
+/// Redaction marker of new settings
+pub const DEFAULT_REDACTION_MARKER: &str = "#private";
+
+fn default_redaction_markers() -> Vec<String> {
+    vec![DEFAULT_REDACTION_MARKER.to_string()]
 }
 
+fn default_show_location() -> bool {
+    true
+}
+
+fn default_max_concurrent_writes() -> usize {
+    1
+}
+
+/// Header fields of exported markdown
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct MetadataConfig {
@@ -110,6 +899,287 @@ pub struct MetadataConfig {
     pub date_last_read: bool,
     pub language: bool,
     pub description: bool,
+    /// Render the highlight statistics block after the metadata
+    #[serde(default)]
+    pub stats: bool,
+    #[serde(default)]
+    pub series: bool,
+    /// Render the book's dictionary lookups after the highlights
+    #[serde(default)]
+    pub vocabulary: bool,
+    /// Note library loans and their due date
+    #[serde(default)]
+    pub loan: bool,
+    /// Render the book's reading notes under "## Notes" after the metadata
+    #[serde(default, alias = "book_notes")]
+    pub book_notes: bool,
+    /// Write the notes as authored instead of escaping lines that would
+    /// become headings or rules
+    #[serde(default, alias = "book_notes_raw")]
+    pub book_notes_raw: bool,
+    /// Order of the header fields; fields left out follow in the default
+    /// order, so an empty list (older settings) keeps the original layout
+    #[serde(default)]
+    pub order: Vec<MetadataField>,
+}
+
+impl MetadataConfig {
+    /// Whether `field` is switched on
+    pub fn is_enabled(&self, field: MetadataField) -> bool {
+        match field {
+            MetadataField::Author => self.author,
+            MetadataField::Series => self.series,
+            MetadataField::Isbn => self.isbn,
+            MetadataField::Publisher => self.publisher,
+            MetadataField::DateLastRead => self.date_last_read,
+            MetadataField::Language => self.language,
+            MetadataField::Description => self.description,
+            MetadataField::Loan => self.loan,
+        }
+    }
+
+    /// Every field in render order: `order` (duplicates dropped, first
+    /// position wins), then the missing ones in the default order
+    pub fn field_order(&self) -> Vec<MetadataField> {
+        let mut fields: Vec<MetadataField> = Vec::new();
+        for field in self.order.iter().chain(MetadataField::DEFAULT_ORDER) {
+            if !fields.contains(field) {
+                fields.push(*field);
+            }
+        }
+        fields
+    }
+}
+
+/// A field of the exported metadata header
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum MetadataField {
+    Author,
+    Series,
+    Isbn,
+    Publisher,
+    #[serde(alias = "date_last_read")]
+    DateLastRead,
+    Language,
+    Description,
+    Loan,
+}
+
+impl MetadataField {
+    /// The header layout from before the order was configurable
+    pub const DEFAULT_ORDER: &'static [MetadataField] = &[
+        MetadataField::Author,
+        MetadataField::Series,
+        MetadataField::Isbn,
+        MetadataField::Publisher,
+        MetadataField::DateLastRead,
+        MetadataField::Language,
+        MetadataField::Description,
+        MetadataField::Loan,
+    ];
+}
+
+/// Rules for dropping junk highlights at import time
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportFilters {
+    /// Drop highlights shorter than this many characters (0 = off)
+    #[serde(default, alias = "min_highlight_length")]
+    pub min_highlight_length: usize,
+    /// Skip books whose title or content ID contains one of these
+    /// (case-insensitive)
+    #[serde(default, alias = "excluded_books")]
+    pub excluded_books: Vec<String>,
+    /// Drop highlights made only of whitespace and punctuation
+    #[serde(default, alias = "drop_punctuation_only")]
+    pub drop_punctuation_only: bool,
+}
+
+/// Per-language book and highlight counts for the library filter chips
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LanguageStats {
+    pub language: String,
+    pub books_count: usize,
+    pub highlights_count: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Markdown,
+    /// CSL-JSON for Zotero and other citation managers
+    CslJson,
+    Bibtex,
+    /// One JSON object per highlight and line, streamed for large libraries
+    Ndjson,
+    /// Logseq outliner pages, one per book
+    Logseq,
+    /// Every highlight by calendar month, across books
+    Journal,
+    /// One comma-separated row per highlight, with configurable columns
+    Csv,
+    /// One tab-separated row per highlight, with configurable columns
+    Tsv,
+    /// Readwise's CSV import format (a fixed column preset)
+    Readwise,
+}
+
+/// A column of the CSV and TSV exports
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum TabularColumn {
+    Title,
+    Author,
+    Isbn,
+    Publisher,
+    Series,
+    Chapter,
+    Text,
+    Annotation,
+    DateCreated,
+    /// Position as a percentage (of the chapter for kepubs)
+    Progress,
+    Page,
+    Color,
+    /// The color's label (see `ExportConfig::color_labels`)
+    ColorLabel,
+    /// The book's reading notes
+    Note,
+    Tags,
+}
+
+/// Columns of the CSV and TSV exports
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TabularOptions {
+    /// Columns in order (at least one)
+    #[serde(default = "default_tabular_columns")]
+    pub columns: Vec<TabularColumn>,
+    /// Header labels replacing the default column names
+    #[serde(default)]
+    pub labels: BTreeMap<TabularColumn, String>,
+}
+
+pub const DEFAULT_TABULAR_COLUMNS: &[TabularColumn] = &[
+    TabularColumn::Title,
+    TabularColumn::Author,
+    TabularColumn::Chapter,
+    TabularColumn::Text,
+    TabularColumn::Annotation,
+    TabularColumn::DateCreated,
+    TabularColumn::Page,
+];
+
+fn default_tabular_columns() -> Vec<TabularColumn> {
+    DEFAULT_TABULAR_COLUMNS.to_vec()
+}
+
+impl Default for TabularOptions {
+    fn default() -> Self {
+        Self {
+            columns: default_tabular_columns(),
+            labels: BTreeMap::new(),
+        }
+    }
+}
+
+/// Files the journal format writes
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalLayout {
+    /// `Journal/YYYY-MM.md`, one file per month
+    #[default]
+    Monthly,
+    /// A single `Reading Journal.md`
+    Combined,
+}
+
+/// Separator inserted between highlights in markdown output
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HighlightSeparator {
+    /// Highlights follow each other directly
+    #[default]
+    None,
+    /// A horizontal rule (`---`)
+    Rule,
+    /// This many extra blank lines
+    BlankLines(usize),
+}
+
+/// Export handling of private content (a redaction marker in the note or
+/// the highlight itself; a marked highlight is always left out)
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactionPolicy {
+    /// Drop the note, keep the quote
+    #[default]
+    Exclude,
+    /// Drop the whole highlight
+    ExcludeHighlight,
+    /// Replace the note with "[private note]"
+    Mask,
+}
+
+/// How each highlight is laid out in markdown output
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HighlightStyle {
+    /// `> text` followed by the location line
+    #[default]
+    Blockquote,
+    /// Obsidian `> [!quote]` callout titled with the location, the note in
+    /// a nested `> [!note]` callout
+    Callout,
+    /// Bare paragraphs with the note after the location
+    Plain,
+}
+
+/// How markdown files that already exist are updated
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportWriteMode {
+    /// Rewrite the whole file
+    #[default]
+    Overwrite,
+    /// Append only highlights not in the file yet, under a dated heading
+    Append,
+}
+
+/// Indentation of nested bullets in Logseq pages
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BulletIndentation {
+    #[default]
+    Tab,
+    TwoSpaces,
+    FourSpaces,
+}
+
+impl BulletIndentation {
+    /// One level of indentation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BulletIndentation::Tab => "\t",
+            BulletIndentation::TwoSpaces => "  ",
+            BulletIndentation::FourSpaces => "    ",
+        }
+    }
+}
+
+/// A word looked up in the dictionary (Kobo `WordList` row)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct VocabEntry {
+    pub word: String,
+    /// Volume (book content ID) the lookup was made in
+    #[serde(alias = "volume_id")]
+    pub volume_id: String,
+    #[serde(default, alias = "date_created")]
+    pub date_created: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -134,10 +1204,25 @@ mod tests {
         assert_eq!(book.content_id, "content123");
         assert_eq!(book.title, "Test Book");
         assert_eq!(book.author, "Test Author");
+        assert_eq!(book.authors, vec!["Test Author".to_string()]);
         assert!(book.highlights.is_empty());
         assert_eq!(book.highlight_count(), 0);
     }
 
+    #[test]
+    fn test_book_display_author() {
+        let book = Book::new(
+            "id1".to_string(),
+            "Title".to_string(),
+            "Doe, John; Smith, Jane".to_string(),
+        );
+        assert_eq!(book.display_author(), "John Doe, Jane Smith");
+
+        let mut legacy = book.clone();
+        legacy.authors.clear();
+        assert_eq!(legacy.display_author(), "Doe, John; Smith, Jane");
+    }
+
     #[test]
     fn test_book_add_highlight() {
         let mut book = Book::new("id1".to_string(), "Title".to_string(), "Author".to_string());
@@ -177,7 +1262,15 @@ mod tests {
             chapter_progress: Some(0.25),
             container_path: Some("OEBPS/ch01.xhtml".to_string()),
             date_created: "2025-01-24".to_string(),
+            date_created_utc: None,
             color: Some("yellow".to_string()),
+            merged_from: Vec::new(),
+            stable_id: String::new(),
+            is_excluded: false,
+            kind: HighlightKind::Text,
+            figure_path: None,
+            tags: Vec::new(),
+            page: None,
         };
 
         let json = serde_json::to_string(&highlight).unwrap();
@@ -210,6 +1303,11 @@ mod tests {
             path: "/Volumes/KOBOeReader".to_string(),
             is_valid: true,
             serial_number: Some("SN12345".to_string()),
+            invalid_reason: None,
+            is_stale: false,
+            model: None,
+            friendly_name: None,
+            suggested_profile: None,
         };
 
         assert_eq!(device.name, "KOBOeReader");
@@ -231,11 +1329,235 @@ mod tests {
                 date_last_read: false,
                 language: false,
                 description: false,
+                stats: false,
+                series: false,
+                vocabulary: false,
+                loan: false,
+                book_notes: false,
+                book_notes_raw: false,
+                order: Vec::new(),
             },
             date_format: DateFormat::DdMonthYyyy,
+            folder_pattern: String::new(),
+            write_index: false,
+            atom_feed: false,
+            feed_limit: 0,
+            highlight_separator: HighlightSeparator::None,
+            compact: false,
+            show_location: true,
+            excluded_chapter_patterns: Vec::new(),
+            max_concurrent_writes: 1,
+            bullet_indentation: BulletIndentation::Tab,
+            write_mode: ExportWriteMode::Overwrite,
+            journal_layout: JournalLayout::Monthly,
+            git_commit_after_export: false,
+            commit_message_template: String::new(),
+            write_sidecars: false,
+            tabular: TabularOptions::default(),
+            markdown_template: String::new(),
+            ascii_filenames: false,
+            highlight_style: HighlightStyle::Blockquote,
+            redaction: RedactionPolicy::Exclude,
+            redaction_markers: vec![DEFAULT_REDACTION_MARKER.to_string()],
+            group_pocket_articles: false,
+            bom: false,
+            line_endings: LineEndings::Lf,
+            group_by_color: false,
+            color_labels: BTreeMap::new(),
+            cloud_settle_ms: 0,
+            transactional: false,
+            filename_pattern: String::new(),
+            format: ExportFormat::Markdown,
+            citation_notes: false,
+            include_toc: false,
+            snapshot_exports: false,
+            snapshot_keep: DEFAULT_SNAPSHOT_KEEP,
+            snapshot_max_mb: DEFAULT_SNAPSHOT_MAX_MB,
         };
 
         assert!(config.metadata.author);
         assert!(!config.metadata.description);
     }
+
+    fn highlight_in(id: &str, chapter: Option<&str>, date: &str) -> Highlight {
+        let mut highlight = Highlight::new(id.to_string(), "Text".to_string(), date.to_string());
+        highlight.chapter_title = chapter.map(str::to_string);
+        highlight
+    }
+
+    #[test]
+    fn test_expiring_loans_sort_first() {
+        let now =
+            NaiveDateTime::parse_from_str("2025-03-01T12:00:00", "%Y-%m-%dT%H:%M:%S").unwrap();
+        let book = |id: &str, loan: bool, expiry: Option<&str>| {
+            let mut book = Book::new(id.to_string(), id.to_string(), "Author".to_string());
+            book.is_loan = loan;
+            book.loan_expiry = expiry.map(str::to_string);
+            book
+        };
+        let mut books = vec![
+            book("purchase", false, None),
+            book("later", true, Some("2025-06-01T00:00:00.000")),
+            book("soon", true, Some("2025-03-05T00:00:00.000")),
+            book("undated", true, None),
+            book("expired", true, Some("2025-02-01T00:00:00.000")),
+        ];
+        let states: Vec<Option<LoanState>> = books.iter().map(|b| b.loan_state(now)).collect();
+        assert_eq!(
+            states,
+            vec![
+                None,
+                Some(LoanState::Active),
+                Some(LoanState::Expiring),
+                Some(LoanState::Active),
+                Some(LoanState::Expired),
+            ]
+        );
+
+        expiring_loans_first(&mut books, now);
+        let ids: Vec<&str> = books.iter().map(|b| b.content_id.as_str()).collect();
+        assert_eq!(ids, vec!["expired", "soon", "purchase", "later", "undated"]);
+    }
+
+    #[test]
+    fn test_book_stats_single_highlight() {
+        let mut book = Book::new("id1".to_string(), "Title".to_string(), "Author".to_string());
+        let mut highlight = highlight_in("hl1", Some("Chapter 1"), "2025-01-24T10:00:00.000");
+        highlight.annotation = Some("A note".to_string());
+        book.add_highlight(highlight);
+
+        let stats = BookStats::from(&book);
+        assert_eq!(stats.highlights_count, 1);
+        assert_eq!(stats.notes_count, 1);
+        assert_eq!(stats.first_highlight_date.as_deref(), Some("2025-01-24"));
+        assert_eq!(stats.last_highlight_date.as_deref(), Some("2025-01-24"));
+        assert_eq!(stats.top_chapter.as_deref(), Some("Chapter 1"));
+    }
+
+    #[test]
+    fn test_book_stats_skips_unparseable_dates() {
+        let mut book = Book::new("id1".to_string(), "Title".to_string(), "Author".to_string());
+        book.add_highlight(highlight_in("hl1", None, "yesterday"));
+        book.add_highlight(highlight_in("hl2", None, ""));
+
+        let stats = BookStats::from(&book);
+        assert_eq!(stats.highlights_count, 2);
+        assert_eq!(stats.notes_count, 0);
+        assert!(stats.first_highlight_date.is_none());
+        assert!(stats.last_highlight_date.is_none());
+        assert!(stats.top_chapter.is_none());
+    }
+
+    #[test]
+    fn test_book_stats_date_range_and_chapter_tie() {
+        let mut book = Book::new("id1".to_string(), "Title".to_string(), "Author".to_string());
+        book.add_highlight(highlight_in("hl1", Some("Later"), "2025-03-02"));
+        book.add_highlight(highlight_in("hl2", Some("Earlier"), "2025-01-05 08:00:00"));
+        book.add_highlight(highlight_in("hl3", Some("Earlier"), "not a date"));
+        book.add_highlight(highlight_in("hl4", Some("Later"), "2025-02-10"));
+
+        let stats = BookStats::from(&book);
+        assert_eq!(stats.first_highlight_date.as_deref(), Some("2025-01-05"));
+        assert_eq!(stats.last_highlight_date.as_deref(), Some("2025-03-02"));
+        // Two highlights each: the chapter highlighted first wins
+        assert_eq!(stats.top_chapter.as_deref(), Some("Later"));
+    }
+
+    fn chapter_counts(map: &ChapterMap) -> Vec<(&str, usize)> {
+        map.chapters
+            .iter()
+            .map(|c| (c.title.as_str(), c.highlights_count))
+            .collect()
+    }
+
+    #[test]
+    fn test_chapter_map_follows_toc() {
+        let mut book = Book::new("id1".to_string(), "Title".to_string(), "Author".to_string());
+        book.progress_scope = Some(ProgressScope::Chapter);
+        for (order, title) in ["Prologue", "One", "Two", "Three"].iter().enumerate() {
+            book.toc.push(TocEntry {
+                title: title.to_string(),
+                depth: 1,
+                order: order as u32,
+            });
+        }
+        let mut add = |id: &str, chapter: Option<&str>, date: &str, progress: f64| {
+            let mut highlight = highlight_in(id, chapter, date);
+            highlight.chapter_progress = Some(progress);
+            book.add_highlight(highlight);
+        };
+        add("hl1", Some("Three"), "2025-03-01", 0.5);
+        add("hl2", Some("One"), "2025-01-10", 0.2);
+        add("hl3", Some("Three"), "2025-03-04", 0.9);
+        add("hl4", Some("One"), "2025-01-12", 0.4);
+        add("hl5", Some("Epilogue"), "2025-04-01", 0.1);
+        add("hl6", None, "2025-04-02", 0.3);
+
+        let map = ChapterMap::from(&book);
+        assert!(map.from_toc);
+        assert_eq!(
+            chapter_counts(&map),
+            vec![
+                ("Prologue", 0),
+                ("One", 2),
+                ("Two", 0),
+                ("Three", 2),
+                ("Epilogue", 1)
+            ]
+        );
+        assert_eq!(map.unassigned, 1);
+        let three = &map.chapters[3];
+        assert_eq!(three.depth, Some(1));
+        assert_eq!(three.first_highlight_date.as_deref(), Some("2025-03-01"));
+        assert_eq!(three.last_highlight_date.as_deref(), Some("2025-03-04"));
+        assert!((three.average_progress.unwrap() - 0.7).abs() < 1e-9);
+        assert_eq!(map.chapters[0].average_progress, None);
+        assert_eq!(map.chapters[4].depth, None);
+
+        // Tied at two: "One" comes first in the table of contents
+        assert_eq!(map.most_highlighted().unwrap().title, "One");
+        assert_eq!(BookStats::from(&book).top_chapter.as_deref(), Some("One"));
+    }
+
+    #[test]
+    fn test_chapter_map_without_toc_groups_by_title() {
+        let mut book = Book::new("id1".to_string(), "Title".to_string(), "Author".to_string());
+        book.progress_scope = Some(ProgressScope::Book);
+        let mut add = |id: &str, chapter: &str, progress: Option<f64>| {
+            let mut highlight = highlight_in(id, Some(chapter), "2025-01-24");
+            highlight.chapter_progress = progress;
+            book.add_highlight(highlight);
+        };
+        add("hl1", "Late", Some(0.8));
+        add("hl2", "Early", Some(0.1));
+        add("hl3", "Late", Some(0.9));
+        add("hl4", "Unplaced", None);
+        add("hl5", "Early", Some(0.15));
+        add("hl6", "Late", Some(0.85));
+
+        let map = ChapterMap::from(&book);
+        assert!(!map.from_toc);
+        assert_eq!(
+            chapter_counts(&map),
+            vec![("Early", 2), ("Late", 3), ("Unplaced", 1)]
+        );
+        assert!(map.chapters.iter().all(|c| c.depth.is_none()));
+        assert_eq!(map.most_highlighted().unwrap().title, "Late");
+        assert_eq!(map.unassigned, 0);
+    }
+
+    #[test]
+    fn test_chapter_map_of_kepub_keeps_appearance_order() {
+        let mut book = Book::new("id1".to_string(), "Title".to_string(), "Author".to_string());
+        book.progress_scope = Some(ProgressScope::Chapter);
+        // Positions within each chapter: "Two" starts nearer its beginning
+        for (id, chapter, progress) in [("hl1", "One", 0.7), ("hl2", "Two", 0.1)] {
+            let mut highlight = highlight_in(id, Some(chapter), "2025-01-24");
+            highlight.chapter_progress = Some(progress);
+            book.add_highlight(highlight);
+        }
+
+        let map = ChapterMap::from(&book);
+        assert_eq!(chapter_counts(&map), vec![("One", 1), ("Two", 1)]);
+    }
 }
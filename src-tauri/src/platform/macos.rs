@@ -0,0 +1,172 @@
+//! Security-scoped bookmarks through CoreFoundation
+
+use super::{PlatformError, ScopedAccess, SecurityScope};
+use std::ffi::c_void;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+type CFTypeRef = *const c_void;
+type CFIndex = isize;
+type CFOptionFlags = usize;
+type Boolean = u8;
+
+const BOOKMARK_CREATION_WITH_SECURITY_SCOPE: CFOptionFlags = 1 << 11;
+const BOOKMARK_RESOLUTION_WITH_SECURITY_SCOPE: CFOptionFlags = 1 << 10;
+const MAX_PATH_LEN: usize = 4096;
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    fn CFRelease(cf: CFTypeRef);
+    fn CFDataCreate(allocator: CFTypeRef, bytes: *const u8, length: CFIndex) -> CFTypeRef;
+    fn CFDataGetLength(data: CFTypeRef) -> CFIndex;
+    fn CFDataGetBytePtr(data: CFTypeRef) -> *const u8;
+    fn CFURLCreateFromFileSystemRepresentation(
+        allocator: CFTypeRef,
+        buffer: *const u8,
+        buf_len: CFIndex,
+        is_directory: Boolean,
+    ) -> CFTypeRef;
+    fn CFURLGetFileSystemRepresentation(
+        url: CFTypeRef,
+        resolve_against_base: Boolean,
+        buffer: *mut u8,
+        max_buf_len: CFIndex,
+    ) -> Boolean;
+    fn CFURLCreateBookmarkData(
+        allocator: CFTypeRef,
+        url: CFTypeRef,
+        options: CFOptionFlags,
+        resource_properties: CFTypeRef,
+        relative_to: CFTypeRef,
+        error: *mut CFTypeRef,
+    ) -> CFTypeRef;
+    fn CFURLCreateByResolvingBookmarkData(
+        allocator: CFTypeRef,
+        bookmark: CFTypeRef,
+        options: CFOptionFlags,
+        relative_to: CFTypeRef,
+        resource_properties: CFTypeRef,
+        is_stale: *mut Boolean,
+        error: *mut CFTypeRef,
+    ) -> CFTypeRef;
+    fn CFURLStartAccessingSecurityScopedResource(url: CFTypeRef) -> Boolean;
+    fn CFURLStopAccessingSecurityScopedResource(url: CFTypeRef);
+}
+
+/// Security scope backed by CoreFoundation bookmarks (sandboxed builds only)
+pub struct MacSecurityScope;
+
+impl SecurityScope for MacSecurityScope {
+    fn create_bookmark(&self, path: &Path) -> Result<Option<Vec<u8>>, PlatformError> {
+        let bytes = path.as_os_str().as_bytes();
+        // SAFETY: every CF object created here is released before returning
+        unsafe {
+            let url = CFURLCreateFromFileSystemRepresentation(
+                ptr::null(),
+                bytes.as_ptr(),
+                bytes.len() as CFIndex,
+                1,
+            );
+            if url.is_null() {
+                return Err(PlatformError::Bookmark(format!(
+                    "invalid path {}",
+                    path.display()
+                )));
+            }
+
+            let mut error: CFTypeRef = ptr::null();
+            let data = CFURLCreateBookmarkData(
+                ptr::null(),
+                url,
+                BOOKMARK_CREATION_WITH_SECURITY_SCOPE,
+                ptr::null(),
+                ptr::null(),
+                &mut error,
+            );
+            CFRelease(url);
+            if data.is_null() {
+                if !error.is_null() {
+                    CFRelease(error);
+                }
+                return Err(PlatformError::Bookmark(format!(
+                    "could not bookmark {}",
+                    path.display()
+                )));
+            }
+
+            let blob =
+                std::slice::from_raw_parts(CFDataGetBytePtr(data), CFDataGetLength(data) as usize)
+                    .to_vec();
+            CFRelease(data);
+            Ok(Some(blob))
+        }
+    }
+
+    fn access(
+        &self,
+        path: &Path,
+        bookmark: Option<Vec<u8>>,
+    ) -> Result<ScopedAccess, PlatformError> {
+        let Some(bookmark) = bookmark else {
+            return Ok(ScopedAccess::unscoped(path.to_path_buf()));
+        };
+
+        // SAFETY: the resolved URL is released either here on failure or by
+        // the guard's stop closure once access ends
+        unsafe {
+            let data = CFDataCreate(ptr::null(), bookmark.as_ptr(), bookmark.len() as CFIndex);
+            if data.is_null() {
+                return Err(PlatformError::Bookmark(
+                    "could not read bookmark".to_string(),
+                ));
+            }
+
+            let mut is_stale: Boolean = 0;
+            let mut error: CFTypeRef = ptr::null();
+            let url = CFURLCreateByResolvingBookmarkData(
+                ptr::null(),
+                data,
+                BOOKMARK_RESOLUTION_WITH_SECURITY_SCOPE,
+                ptr::null(),
+                ptr::null(),
+                &mut is_stale,
+                &mut error,
+            );
+            CFRelease(data);
+            if !error.is_null() {
+                CFRelease(error);
+            }
+            if url.is_null() || is_stale != 0 {
+                if !url.is_null() {
+                    CFRelease(url);
+                }
+                return Err(PlatformError::StaleBookmark(path.display().to_string()));
+            }
+
+            let mut buffer = vec![0u8; MAX_PATH_LEN];
+            let resolved = if CFURLGetFileSystemRepresentation(
+                url,
+                1,
+                buffer.as_mut_ptr(),
+                MAX_PATH_LEN as CFIndex,
+            ) != 0
+            {
+                let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+                PathBuf::from(std::ffi::OsStr::from_bytes(&buffer[..len]))
+            } else {
+                path.to_path_buf()
+            };
+
+            if CFURLStartAccessingSecurityScopedResource(url) == 0 {
+                CFRelease(url);
+                return Err(PlatformError::AccessDenied(resolved.display().to_string()));
+            }
+
+            Ok(ScopedAccess::scoped(resolved, move || {
+                CFURLStopAccessingSecurityScopedResource(url);
+                CFRelease(url);
+            }))
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Platform-specific file access
+//!
+//! A sandboxed macOS build may only write to user-selected folders through
+//! security-scoped bookmarks. `SecurityScope` hides those calls so the export
+//! path works the same everywhere; outside the sandbox every call is a no-op.
+
+#[cfg(target_os = "macos")]
+mod macos;
+
+use std::path::{Path, PathBuf};
+
+/// Access to a folder, kept open until the guard is dropped
+pub struct ScopedAccess {
+    path: PathBuf,
+    stop: Option<Box<dyn FnOnce()>>,
+}
+
+impl ScopedAccess {
+    /// Access that needs no security scope (unsandboxed or non-macOS)
+    pub fn unscoped(path: PathBuf) -> Self {
+        Self { path, stop: None }
+    }
+
+    /// Access that runs `stop` when released
+    pub fn scoped(path: PathBuf, stop: impl FnOnce() + 'static) -> Self {
+        Self {
+            path,
+            stop: Some(Box::new(stop)),
+        }
+    }
+
+    /// Folder path resolved from the bookmark (or the plain path)
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_scoped(&self) -> bool {
+        self.stop.is_some()
+    }
+}
+
+impl Drop for ScopedAccess {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            stop();
+        }
+    }
+}
+
+/// Creates and resolves security-scoped bookmarks for user-selected folders
+#[cfg_attr(test, mockall::automock)]
+pub trait SecurityScope {
+    /// Bookmark for a folder the user just picked (`None` when not needed)
+    fn create_bookmark(&self, path: &Path) -> Result<Option<Vec<u8>>, PlatformError>;
+
+    /// Start accessing a folder, resolving its bookmark if there is one
+    fn access(&self, path: &Path, bookmark: Option<Vec<u8>>)
+        -> Result<ScopedAccess, PlatformError>;
+}
+
+/// Scope used outside the macOS sandbox: plain paths, no bookmarks
+pub struct NoopSecurityScope;
+
+impl SecurityScope for NoopSecurityScope {
+    fn create_bookmark(&self, _path: &Path) -> Result<Option<Vec<u8>>, PlatformError> {
+        Ok(None)
+    }
+
+    fn access(
+        &self,
+        path: &Path,
+        _bookmark: Option<Vec<u8>>,
+    ) -> Result<ScopedAccess, PlatformError> {
+        Ok(ScopedAccess::unscoped(path.to_path_buf()))
+    }
+}
+
+/// Whether the app runs inside the macOS App Sandbox
+pub fn is_sandboxed() -> bool {
+    cfg!(target_os = "macos") && std::env::var_os("APP_SANDBOX_CONTAINER_ID").is_some()
+}
+
+/// Security scope for the current platform
+pub fn security_scope() -> Box<dyn SecurityScope> {
+    #[cfg(target_os = "macos")]
+    if is_sandboxed() {
+        return Box::new(macos::MacSecurityScope);
+    }
+
+    Box::new(NoopSecurityScope)
+}
+
+/// Platform access errors
+#[derive(Debug)]
+pub enum PlatformError {
+    /// The saved bookmark no longer resolves; the folder must be picked again
+    StaleBookmark(String),
+    /// The system refused to create or resolve a bookmark
+    Bookmark(String),
+    /// The system refused access to the folder
+    AccessDenied(String),
+}
+
+impl std::fmt::Display for PlatformError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlatformError::StaleBookmark(path) => write!(
+                f,
+                "Access to {} has expired, please select the export folder again",
+                path
+            ),
+            PlatformError::Bookmark(msg) => write!(f, "Bookmark error: {}", msg),
+            PlatformError::AccessDenied(path) => write!(f, "Access denied to {}", path),
+        }
+    }
+}
+
+impl std::error::Error for PlatformError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_noop_scope_passes_path_through() {
+        let scope = NoopSecurityScope;
+        let path = Path::new("/tmp/export");
+
+        assert!(scope.create_bookmark(path).unwrap().is_none());
+        let access = scope.access(path, Some(vec![1, 2, 3])).unwrap();
+        assert_eq!(access.path(), path);
+        assert!(!access.is_scoped());
+    }
+
+    #[test]
+    fn test_scoped_access_stops_on_drop() {
+        let stopped = Rc::new(Cell::new(false));
+        let flag = stopped.clone();
+        let access = ScopedAccess::scoped(PathBuf::from("/tmp/export"), move || flag.set(true));
+
+        assert!(access.is_scoped());
+        assert!(!stopped.get());
+        drop(access);
+        assert!(stopped.get());
+    }
+
+    #[test]
+    fn test_stale_bookmark_is_reported() {
+        let mut scope = MockSecurityScope::new();
+        scope
+            .expect_access()
+            .returning(|path, _| Err(PlatformError::StaleBookmark(path.display().to_string())));
+
+        let result = scope.access(Path::new("/tmp/export"), Some(vec![0]));
+        match result {
+            Err(PlatformError::StaleBookmark(path)) => assert_eq!(path, "/tmp/export"),
+            _ => panic!("expected a stale bookmark error"),
+        }
+    }
+}
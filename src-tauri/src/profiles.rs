@@ -0,0 +1,595 @@
+//! Separate libraries for the people sharing one computer account
+//!
+//! `profiles.json`, next to the settings file, lists the profiles and the
+//! files each one owns: its settings, its library and the folder of its
+//! usage history (which tracks imports and exports). The first run of a
+//! version with profiles adopts the existing files as the "Default"
+//! profile, so nothing moves. Switching opens the other profile's settings
+//! and library first and only then swaps both managed handles, so a failed
+//! switch leaves the current profile in place.
+
+use crate::library::{LibraryError, LibraryState, LibraryStore};
+use crate::models::KoboDevice;
+use crate::scheduler::OperationLock;
+use crate::settings::{SettingsError, SettingsManager, SettingsState};
+use crate::utils::fs::atomic_write;
+use crate::utils::slug::{content_hash, slugify};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Profile list, in the settings folder
+pub const PROFILES_FILE: &str = "profiles.json";
+
+/// Profile holding the data of versions without profiles
+pub const DEFAULT_PROFILE: &str = "Default";
+
+/// Folder (under the settings and data folders) of each new profile's files
+const PROFILES_DIR: &str = "profiles";
+
+/// The files of one profile
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub name: String,
+    #[serde(alias = "settings_path")]
+    pub settings_path: PathBuf,
+    #[serde(alias = "library_path")]
+    pub library_path: PathBuf,
+    /// Folder of the usage history (imports and exports)
+    #[serde(alias = "tracking_dir")]
+    pub tracking_dir: PathBuf,
+}
+
+/// Contents of `profiles.json`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfilesConfig {
+    pub active: String,
+    #[serde(default)]
+    pub profiles: Vec<Profile>,
+    /// Profile each device serial was last imported into
+    #[serde(default, alias = "device_profiles")]
+    pub device_profiles: BTreeMap<String, String>,
+}
+
+/// Loads, edits and saves `profiles.json`
+pub struct ProfileManager {
+    config: ProfilesConfig,
+    path: PathBuf,
+    config_dir: PathBuf,
+    data_dir: PathBuf,
+}
+
+impl ProfileManager {
+    /// Profiles of the settings folder `config_dir`, with new profiles'
+    /// libraries under `data_dir`; a first run adopts the existing files as
+    /// the "Default" profile
+    pub fn load(config_dir: &Path, data_dir: &Path) -> Result<Self, ProfileError> {
+        let path = config_dir.join(PROFILES_FILE);
+        let config = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                log::info!("[PROFILES] Dados existentes migrados para o perfil Default");
+                ProfilesConfig {
+                    active: DEFAULT_PROFILE.to_string(),
+                    profiles: vec![Profile {
+                        name: DEFAULT_PROFILE.to_string(),
+                        settings_path: config_dir.join("settings.json"),
+                        library_path: data_dir.join("library.sqlite"),
+                        tracking_dir: data_dir.to_path_buf(),
+                    }],
+                    device_profiles: BTreeMap::new(),
+                }
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let manager = Self {
+            config,
+            path,
+            config_dir: config_dir.to_path_buf(),
+            data_dir: data_dir.to_path_buf(),
+        };
+        manager.active()?;
+        if !manager.path.exists() {
+            fs::create_dir_all(config_dir)?;
+            manager.save()?;
+        }
+        Ok(manager)
+    }
+
+    pub fn config(&self) -> &ProfilesConfig {
+        &self.config
+    }
+
+    pub fn active(&self) -> Result<&Profile, ProfileError> {
+        self.find(&self.config.active)
+    }
+
+    pub fn find(&self, name: &str) -> Result<&Profile, ProfileError> {
+        self.config
+            .profiles
+            .iter()
+            .find(|p| p.name == name.trim())
+            .ok_or_else(|| ProfileError::UnknownProfile(name.to_string()))
+    }
+
+    /// Add an empty profile with its own folders
+    pub fn create(&mut self, name: &str) -> Result<Profile, ProfileError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(ProfileError::InvalidName("name is empty".to_string()));
+        }
+        if self
+            .config
+            .profiles
+            .iter()
+            .any(|p| p.name.eq_ignore_ascii_case(name))
+        {
+            return Err(ProfileError::InvalidName(format!(
+                "\"{}\" already exists",
+                name
+            )));
+        }
+
+        let base = Some(slugify(name))
+            .filter(|slug| !slug.is_empty())
+            .unwrap_or_else(|| content_hash(name));
+        let mut folder = base.clone();
+        let mut suffix = 2;
+        while self.config_dir.join(PROFILES_DIR).join(&folder).exists()
+            || self.data_dir.join(PROFILES_DIR).join(&folder).exists()
+        {
+            folder = format!("{}-{}", base, suffix);
+            suffix += 1;
+        }
+        let data = self.data_dir.join(PROFILES_DIR).join(&folder);
+        let profile = Profile {
+            name: name.to_string(),
+            settings_path: self
+                .config_dir
+                .join(PROFILES_DIR)
+                .join(&folder)
+                .join("settings.json"),
+            library_path: data.join("library.sqlite"),
+            tracking_dir: data,
+        };
+        fs::create_dir_all(&profile.tracking_dir)?;
+        if let Some(parent) = profile.settings_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        self.config.profiles.push(profile.clone());
+        self.save()?;
+        log::info!("[PROFILES] Perfil criado: {}", name);
+        Ok(profile)
+    }
+
+    /// Delete a profile and its files; `confirmation` must repeat its name
+    ///
+    /// The active profile can't be deleted.
+    pub fn delete(&mut self, name: &str, confirmation: &str) -> Result<(), ProfileError> {
+        let profile = self.find(name)?.clone();
+        if confirmation.trim() != profile.name {
+            return Err(ProfileError::NotConfirmed(profile.name));
+        }
+        if profile.name == self.config.active {
+            return Err(ProfileError::Active(profile.name));
+        }
+
+        self.config.profiles.retain(|p| p.name != profile.name);
+        self.config
+            .device_profiles
+            .retain(|_, owner| *owner != profile.name);
+        self.save()?;
+
+        let library = profile.library_path.to_string_lossy().to_string();
+        for file in [
+            profile.settings_path.clone(),
+            profile.library_path.clone(),
+            PathBuf::from(format!("{}-wal", library)),
+            PathBuf::from(format!("{}-shm", library)),
+            profile.tracking_dir.join(crate::usage::USAGE_FILE),
+        ] {
+            let _ = fs::remove_file(file);
+        }
+        // Folders of profiles created here; the Default files sit in the app folders
+        for dir in [profile.settings_path.parent(), Some(&profile.tracking_dir)]
+            .into_iter()
+            .flatten()
+        {
+            if dir.parent().and_then(Path::file_name) == Some(PROFILES_DIR.as_ref()) {
+                let _ = fs::remove_dir_all(dir);
+            }
+        }
+        log::info!("[PROFILES] Perfil apagado: {}", profile.name);
+        Ok(())
+    }
+
+    fn set_active(&mut self, name: &str) -> Result<(), ProfileError> {
+        self.config.active = self.find(name)?.name.clone();
+        self.save()
+    }
+
+    /// Remember that `serial` was imported into the active profile
+    pub fn associate_device(&mut self, serial: &str) -> Result<(), ProfileError> {
+        if self.config.device_profiles.get(serial) == Some(&self.config.active) {
+            return Ok(());
+        }
+        self.config
+            .device_profiles
+            .insert(serial.to_string(), self.config.active.clone());
+        self.save()
+    }
+
+    /// The profile `serial` was imported into, when it isn't the active one
+    pub fn suggestion_for(&self, serial: &str) -> Option<&str> {
+        self.config
+            .device_profiles
+            .get(serial)
+            .filter(|owner| **owner != self.config.active && self.find(owner).is_ok())
+            .map(String::as_str)
+    }
+
+    fn save(&self) -> Result<(), ProfileError> {
+        let json = serde_json::to_string_pretty(&self.config)?;
+        atomic_write(&self.path, json.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Open the settings and library of `profile`
+pub fn open_profile(profile: &Profile) -> Result<(SettingsManager, LibraryStore), ProfileError> {
+    let settings = SettingsManager::with_path(profile.settings_path.clone())?;
+    let mut library = LibraryStore::open(&profile.library_path)?;
+    library.set_text_normalization(settings.get().text_normalization.clone());
+    Ok((settings, library))
+}
+
+/// Profile manager shared across commands (empty until loaded in `setup()`)
+#[derive(Default)]
+pub struct ProfileState {
+    manager: Mutex<Option<ProfileManager>>,
+}
+
+impl ProfileState {
+    pub fn install(&self, manager: ProfileManager) {
+        *self.manager.lock().unwrap_or_else(|e| e.into_inner()) = Some(manager);
+    }
+
+    /// Run a closure against the profile manager, if it is loaded
+    pub fn with_manager<T>(
+        &self,
+        f: impl FnOnce(&mut ProfileManager) -> Result<T, ProfileError>,
+    ) -> Result<T, ProfileError> {
+        let mut guard = self.manager.lock().unwrap_or_else(|e| e.into_inner());
+        match guard.as_mut() {
+            Some(manager) => f(manager),
+            None => Err(ProfileError::Unavailable),
+        }
+    }
+
+    /// Usage history folder of the active profile
+    pub fn tracking_dir(&self) -> Option<PathBuf> {
+        self.with_manager(|m| Ok(m.active()?.tracking_dir.clone()))
+            .ok()
+    }
+}
+
+/// Make `name` the active profile, swapping the managed settings and
+/// library; refused while an import or export runs
+pub fn switch_profile(
+    profiles: &ProfileState,
+    settings: &SettingsState,
+    library: &LibraryState,
+    operations: &OperationLock,
+    name: &str,
+) -> Result<Profile, ProfileError> {
+    profiles.with_manager(|manager| {
+        // Held until the swap is done: keeps imports, exports and
+        // maintenance off the library meanwhile
+        let Some(_operation) = operations.try_begin() else {
+            return Err(ProfileError::Busy);
+        };
+        let profile = manager.find(name)?.clone();
+        let (new_settings, new_library) = open_profile(&profile)?;
+        manager.set_active(&profile.name)?;
+        settings.replace(new_settings);
+        library.install(new_library);
+        log::info!("[PROFILES] Perfil ativo: {}", profile.name);
+        Ok(profile)
+    })
+}
+
+/// Suggest the profile `device` was imported into, when it isn't the active one
+pub fn suggest_profile(profiles: &ProfileState, device: &mut KoboDevice) {
+    let Some(serial) = device.serial_number.as_deref() else {
+        return;
+    };
+    device.suggested_profile = profiles
+        .with_manager(|m| Ok(m.suggestion_for(serial).map(str::to_string)))
+        .unwrap_or_default();
+}
+
+/// Associate an imported device with the active profile
+pub fn record_import(profiles: &ProfileState, device: &KoboDevice) {
+    if let Some(serial) = &device.serial_number {
+        if let Err(e) = profiles.with_manager(|m| m.associate_device(serial)) {
+            log::warn!("[PROFILES] Dispositivo não associado ao perfil: {}", e);
+        }
+    }
+}
+
+/// Profile-related errors
+#[derive(Debug)]
+pub enum ProfileError {
+    /// IO error
+    Io(std::io::Error),
+    /// `profiles.json` is invalid
+    Parse(serde_json::Error),
+    /// Empty or duplicate profile name
+    InvalidName(String),
+    /// No profile has this name
+    UnknownProfile(String),
+    /// The confirmation didn't repeat the profile name
+    NotConfirmed(String),
+    /// The active profile can't be deleted
+    Active(String),
+    /// An import or export is running
+    Busy,
+    /// Profiles were not loaded at startup
+    Unavailable,
+    Settings(SettingsError),
+    Library(LibraryError),
+}
+
+impl std::fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileError::Io(e) => write!(f, "IO error: {}", e),
+            ProfileError::Parse(e) => write!(f, "Invalid profiles file: {}", e),
+            ProfileError::InvalidName(msg) => write!(f, "Invalid profile name: {}", msg),
+            ProfileError::UnknownProfile(name) => write!(f, "Unknown profile: {}", name),
+            ProfileError::NotConfirmed(name) => {
+                write!(f, "Type \"{}\" to confirm deleting the profile", name)
+            }
+            ProfileError::Active(name) => {
+                write!(f, "Profile {} is active and can't be deleted", name)
+            }
+            ProfileError::Busy => write!(f, "An import or export is running"),
+            ProfileError::Unavailable => write!(f, "Profiles are not available"),
+            ProfileError::Settings(e) => write!(f, "Settings error: {}", e),
+            ProfileError::Library(e) => write!(f, "Library error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+impl From<std::io::Error> for ProfileError {
+    fn from(e: std::io::Error) -> Self {
+        ProfileError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ProfileError {
+    fn from(e: serde_json::Error) -> Self {
+        ProfileError::Parse(e)
+    }
+}
+
+impl From<SettingsError> for ProfileError {
+    fn from(e: SettingsError) -> Self {
+        ProfileError::Settings(e)
+    }
+}
+
+impl From<LibraryError> for ProfileError {
+    fn from(e: LibraryError) -> Self {
+        ProfileError::Library(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{import_device, merge_into_library};
+    use crate::covers::CoverExtractor;
+    use crate::device::DeviceDetector;
+    use crate::utils::cancel::CancellationToken;
+    use tempfile::TempDir;
+
+    const ANA_DATA: &str = "
+        INSERT INTO content VALUES ('vol1', NULL, 'Memorial do Convento',
+            'José Saramago', NULL, NULL, 'pt', '2025-01-24', 6);
+        INSERT INTO Bookmark VALUES ('hl1', 'vol1', 'vol1', 'Primeiro', NULL, NULL,
+            0.1, '2025-01-24', NULL);";
+
+    struct App {
+        temp: TempDir,
+        profiles: ProfileState,
+        settings: SettingsState,
+        library: LibraryState,
+        operations: OperationLock,
+    }
+
+    impl App {
+        /// Start like `setup()`: load the profiles and open the active one
+        fn start(temp: TempDir) -> Self {
+            let manager =
+                ProfileManager::load(&temp.path().join("config"), &temp.path().join("data"))
+                    .unwrap();
+            let (settings, library) = open_profile(manager.active().unwrap()).unwrap();
+            let app = Self {
+                temp,
+                profiles: ProfileState::default(),
+                settings: SettingsState::from_manager(settings),
+                library: LibraryState::default(),
+                operations: OperationLock::default(),
+            };
+            app.library.install(library);
+            app.profiles.install(manager);
+            app
+        }
+
+        fn switch(&self, name: &str) -> Result<Profile, ProfileError> {
+            switch_profile(
+                &self.profiles,
+                &self.settings,
+                &self.library,
+                &self.operations,
+                name,
+            )
+        }
+
+        fn plug(&self, volume: &str, serial: &str, data: &str) -> KoboDevice {
+            let volumes = self.temp.path().join(volume);
+            if !volumes.exists() {
+                crate::fixtures::create_kobo_volume(&volumes.join("KOBOeReader"), serial, data)
+                    .unwrap();
+            }
+            let mut device = DeviceDetector::new(volumes)
+                .scan_for_kobo()
+                .unwrap()
+                .unwrap();
+            suggest_profile(&self.profiles, &mut device);
+            device
+        }
+
+        fn import(&self, device: &KoboDevice) {
+            let extractor = CoverExtractor::new(self.temp.path().join("cache"));
+            let (books, hidden) = import_device(
+                &self.settings,
+                device,
+                false,
+                &extractor,
+                &CancellationToken::new(),
+            )
+            .unwrap();
+            merge_into_library(&self.library, &books, &hidden);
+            record_import(&self.profiles, device);
+        }
+
+        /// Books and highlights in the library
+        fn counts(&self) -> (usize, usize) {
+            let stats = self.library.with_store(|store| store.stats()).unwrap();
+            (stats.books, stats.highlights)
+        }
+
+        fn last_import_device(&self) -> Option<String> {
+            self.settings
+                .with_manager(|m| Ok(m.get().last_import.clone()))
+                .unwrap()
+                .and_then(|record| record.device_id)
+        }
+    }
+
+    #[test]
+    fn test_profiles_keep_libraries_apart() {
+        let app = App::start(TempDir::new().unwrap());
+        assert_eq!(
+            app.profiles
+                .with_manager(|m| Ok(m.config().clone()))
+                .unwrap()
+                .active,
+            DEFAULT_PROFILE
+        );
+        app.profiles.with_manager(|m| m.create("Ana")).unwrap();
+
+        let mine = app.plug("mine", "N111", crate::fixtures::TWO_BOOKS_DATA);
+        assert_eq!(mine.suggested_profile, None);
+        app.import(&mine);
+        app.settings
+            .with_manager(|m| {
+                m.get_mut().export_config.export_path = "/notas/default".to_string();
+                m.save()
+            })
+            .unwrap();
+
+        app.switch("Ana").unwrap();
+        assert_eq!(app.counts(), (0, 0));
+        assert_eq!(app.last_import_device(), None);
+        // Her profile suggests going back when my device shows up
+        assert_eq!(
+            app.plug("mine", "N111", crate::fixtures::TWO_BOOKS_DATA)
+                .suggested_profile
+                .as_deref(),
+            Some(DEFAULT_PROFILE)
+        );
+        let hers = app.plug("hers", "N222", ANA_DATA);
+        app.import(&hers);
+        assert_eq!(app.counts(), (1, 1));
+        assert_eq!(app.last_import_device().as_deref(), Some("N222"));
+
+        app.switch(DEFAULT_PROFILE).unwrap();
+        assert_eq!(app.counts(), (2, 4));
+        assert_eq!(app.last_import_device().as_deref(), Some("N111"));
+        assert_eq!(
+            app.plug("hers", "N222", ANA_DATA)
+                .suggested_profile
+                .as_deref(),
+            Some("Ana")
+        );
+        let export_path = |app: &App| {
+            app.settings
+                .with_manager(|m| Ok(m.get().export_config.export_path.clone()))
+                .unwrap()
+        };
+        assert_eq!(export_path(&app), "/notas/default");
+
+        // Across a restart, in the profile that was active
+        app.switch("Ana").unwrap();
+        let App { temp, .. } = app;
+        let app = App::start(temp);
+        assert_eq!(app.counts(), (1, 1));
+        assert_ne!(export_path(&app), "/notas/default");
+    }
+
+    #[test]
+    fn test_switch_refused_while_busy() {
+        let app = App::start(TempDir::new().unwrap());
+        app.profiles.with_manager(|m| m.create("Ana")).unwrap();
+        let running = app.operations.begin();
+        assert!(matches!(app.switch("Ana"), Err(ProfileError::Busy)));
+        drop(running);
+        assert!(matches!(
+            app.switch("Nobody"),
+            Err(ProfileError::UnknownProfile(_))
+        ));
+        assert_eq!(app.switch("Ana").unwrap().name, "Ana");
+    }
+
+    #[test]
+    fn test_create_and_delete_with_confirmation() {
+        let app = App::start(TempDir::new().unwrap());
+        let ana = app.profiles.with_manager(|m| m.create(" Ana ")).unwrap();
+        assert_eq!(ana.name, "Ana");
+        assert!(app.profiles.with_manager(|m| m.create("ana")).is_err());
+        assert!(app.profiles.with_manager(|m| m.create("  ")).is_err());
+        assert!(matches!(
+            app.profiles
+                .with_manager(|m| m.delete(DEFAULT_PROFILE, DEFAULT_PROFILE)),
+            Err(ProfileError::Active(_))
+        ));
+
+        app.switch("Ana").unwrap();
+        app.switch(DEFAULT_PROFILE).unwrap();
+        assert!(ana.library_path.exists());
+        assert!(matches!(
+            app.profiles.with_manager(|m| m.delete("Ana", "yes")),
+            Err(ProfileError::NotConfirmed(_))
+        ));
+        app.profiles
+            .with_manager(|m| m.delete("Ana", "Ana"))
+            .unwrap();
+        assert!(!ana.tracking_dir.exists());
+        assert!(!ana.settings_path.exists());
+        let names: Vec<String> = app
+            .profiles
+            .with_manager(|m| Ok(m.config().profiles.iter().map(|p| p.name.clone()).collect()))
+            .unwrap();
+        assert_eq!(names, vec![DEFAULT_PROFILE]);
+    }
+}
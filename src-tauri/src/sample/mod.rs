@@ -0,0 +1,172 @@
+//! Built-in sample library
+//!
+//! A handful of public-domain books with highlights, annotations and covers,
+//! compiled into the binary so the app can be tried (and the export pipeline
+//! tested) without a Kobo. Sample books are never merged into the library and
+//! export into their own subfolder.
+
+use crate::db::kobo::assign_stable_ids;
+use crate::models::Book;
+use crate::utils::author::parse_authors;
+use crate::utils::slug::assign_slugs;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Content ID prefix of every sample book
+pub const SAMPLE_CONTENT_PREFIX: &str = "khi-sample:";
+
+/// Subfolder of the export path that sample exports go to
+pub const SAMPLE_EXPORT_FOLDER: &str = "Khi Sample Export";
+
+/// Cache subfolder the bundled covers are written to
+const SAMPLE_COVER_DIR: &str = "sample-covers";
+
+const SAMPLE_BOOKS_JSON: &str = include_str!("books.json");
+
+/// Bundled covers: content ID, file name, image bytes
+const SAMPLE_COVERS: &[(&str, &str, &[u8])] = &[
+    (
+        "khi-sample:pride-and-prejudice",
+        "pride-and-prejudice.svg",
+        include_bytes!("covers/pride-and-prejudice.svg"),
+    ),
+    (
+        "khi-sample:meditations",
+        "meditations.svg",
+        include_bytes!("covers/meditations.svg"),
+    ),
+];
+
+/// Sample books ready for display, flagged so the UI can label them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SampleLibrary {
+    pub books: Vec<Book>,
+    pub is_sample: bool,
+    /// Subfolder of the export path that exports of these books go to
+    pub export_folder: String,
+}
+
+/// The sample books, without covers
+pub fn sample_books() -> Vec<Book> {
+    let mut books: Vec<Book> =
+        serde_json::from_str(SAMPLE_BOOKS_JSON).expect("bundled sample library is valid JSON");
+    for book in &mut books {
+        if book.authors.is_empty() {
+            book.authors = parse_authors(&book.author);
+        }
+        assign_stable_ids(book);
+    }
+    assign_slugs(&mut books);
+    books
+}
+
+/// The sample books with their covers written to `cache_dir`
+///
+/// Covers already in the cache are reused.
+pub fn load_sample_library(cache_dir: &Path) -> std::io::Result<SampleLibrary> {
+    let cover_dir = cache_dir.join(SAMPLE_COVER_DIR);
+    fs::create_dir_all(&cover_dir)?;
+
+    let mut books = sample_books();
+    for (content_id, file_name, bytes) in SAMPLE_COVERS {
+        let path = cover_dir.join(file_name);
+        if !path.exists() {
+            fs::write(&path, bytes)?;
+        }
+        if let Some(book) = books.iter_mut().find(|b| b.content_id == *content_id) {
+            book.cover_path = Some(path.to_string_lossy().into_owned());
+            book.thumbnail_path = book.cover_path.clone();
+        }
+    }
+
+    Ok(SampleLibrary {
+        books,
+        is_sample: true,
+        export_folder: SAMPLE_EXPORT_FOLDER.to_string(),
+    })
+}
+
+/// Whether a book comes from the sample library
+pub fn is_sample_book(book: &Book) -> bool {
+    book.content_id.starts_with(SAMPLE_CONTENT_PREFIX)
+}
+
+/// Whether exporting `books` is a sample export: every book is a sample
+pub fn is_sample_export(books: &[Book]) -> bool {
+    !books.is_empty() && books.iter().all(is_sample_book)
+}
+
+/// Where an export of `books` goes: the sample subfolder when every book is a
+/// sample, `export_path` otherwise
+pub fn sample_export_path(books: &[Book], export_path: &Path) -> PathBuf {
+    if is_sample_export(books) {
+        export_path.join(SAMPLE_EXPORT_FOLDER)
+    } else {
+        export_path.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_sample_books_shape() {
+        let books = sample_books();
+        assert_eq!(books.len(), 4);
+        assert!(books.iter().all(is_sample_book));
+
+        let highlights: Vec<_> = books.iter().flat_map(|b| &b.highlights).collect();
+        assert_eq!(highlights.len(), 30);
+        assert!(highlights.iter().any(|h| h.annotation.is_some()));
+        assert!(highlights.iter().any(|h| h.chapter_title.is_none()));
+
+        let ids: HashSet<_> = highlights.iter().map(|h| h.stable_id.as_str()).collect();
+        assert_eq!(ids.len(), highlights.len());
+        assert!(!ids.contains(""));
+        assert!(books.iter().all(|b| !b.authors.is_empty()));
+    }
+
+    #[test]
+    fn test_load_writes_covers_to_cache() {
+        let temp = TempDir::new().unwrap();
+        let library = load_sample_library(temp.path()).unwrap();
+        assert!(library.is_sample);
+        assert_eq!(library.export_folder, SAMPLE_EXPORT_FOLDER);
+
+        let covers: Vec<_> = library
+            .books
+            .iter()
+            .filter_map(|b| b.cover_path.as_deref())
+            .collect();
+        assert_eq!(covers.len(), SAMPLE_COVERS.len());
+        for cover in covers {
+            assert!(Path::new(cover).starts_with(temp.path()));
+            assert!(fs::read_to_string(cover).unwrap().starts_with("<svg"));
+        }
+
+        // A second load reuses the cached covers
+        assert!(load_sample_library(temp.path()).is_ok());
+    }
+
+    #[test]
+    fn test_sample_export_path() {
+        let root = Path::new("/notes");
+        let mut books = sample_books();
+        assert!(is_sample_export(&books));
+        assert_eq!(
+            sample_export_path(&books, root),
+            root.join(SAMPLE_EXPORT_FOLDER)
+        );
+
+        books[0].content_id = "file:///mnt/onboard/real.epub".to_string();
+        assert!(!is_sample_export(&books));
+        assert_eq!(sample_export_path(&books, root), root);
+        assert!(!is_sample_export(&[]));
+        assert_eq!(sample_export_path(&[], root), root);
+    }
+}
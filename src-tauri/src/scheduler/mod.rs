@@ -0,0 +1,461 @@
+//! Background maintenance scheduler
+//!
+//! One thread owns every periodic maintenance task and runs the due ones in
+//! sequence. Last-run times are persisted so intervals survive restarts, and
+//! nothing runs while an import or export holds the `OperationLock`.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub mod tasks;
+
+/// How often the background thread checks for due tasks
+pub const TICK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Paths available to maintenance tasks
+#[derive(Debug, Clone)]
+pub struct AppContext {
+    pub cache_dir: PathBuf,
+    pub data_dir: PathBuf,
+}
+
+/// Outcome of a successful task run
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskReport {
+    pub summary: String,
+}
+
+/// A periodic maintenance job
+pub trait MaintenanceTask: Send {
+    fn name(&self) -> &str;
+    fn interval(&self) -> Duration;
+    fn run(&self, ctx: &AppContext) -> Result<TaskReport, String>;
+}
+
+/// Source of the current time (replaced by a fake clock in tests)
+pub trait Clock: Send {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Status of a registered task, for the debug panel
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskStatus {
+    pub name: String,
+    pub interval_secs: u64,
+    pub last_run: Option<String>,
+    pub last_error: Option<String>,
+}
+
+/// Held while an import or export runs; maintenance waits until released
+#[derive(Debug, Clone, Default)]
+pub struct OperationLock {
+    active: Arc<AtomicUsize>,
+}
+
+impl OperationLock {
+    /// Mark an operation as running until the guard is dropped
+    pub fn begin(&self) -> OperationGuard {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        OperationGuard {
+            active: self.active.clone(),
+        }
+    }
+
+    /// Mark an operation as running, unless one already is
+    pub fn try_begin(&self) -> Option<OperationGuard> {
+        self.active
+            .compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst)
+            .ok()
+            .map(|_| OperationGuard {
+                active: self.active.clone(),
+            })
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.active.load(Ordering::SeqCst) > 0
+    }
+}
+
+pub struct OperationGuard {
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub struct Scheduler {
+    context: AppContext,
+    state_path: PathBuf,
+    clock: Box<dyn Clock>,
+    tasks: Vec<Box<dyn MaintenanceTask>>,
+    last_runs: HashMap<String, DateTime<Utc>>,
+    last_errors: HashMap<String, String>,
+}
+
+impl Scheduler {
+    /// Create a scheduler, restoring last-run times from `state_path`
+    pub fn new(context: AppContext, state_path: PathBuf, clock: Box<dyn Clock>) -> Self {
+        // Stored as task name -> RFC 3339 timestamp
+        let stored: HashMap<String, String> = fs::read_to_string(&state_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        let last_runs = stored
+            .into_iter()
+            .filter_map(|(name, time)| {
+                DateTime::parse_from_rfc3339(&time)
+                    .ok()
+                    .map(|t| (name, t.with_timezone(&Utc)))
+            })
+            .collect();
+
+        Self {
+            context,
+            state_path,
+            clock,
+            tasks: Vec::new(),
+            last_runs,
+            last_errors: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, task: Box<dyn MaintenanceTask>) {
+        self.tasks.push(task);
+    }
+
+    fn is_due(&self, task: &dyn MaintenanceTask, now: DateTime<Utc>) -> bool {
+        match self.last_runs.get(task.name()) {
+            Some(last) => match chrono::Duration::from_std(task.interval()) {
+                Ok(interval) => now - *last >= interval,
+                Err(_) => false,
+            },
+            None => true,
+        }
+    }
+
+    /// Run every due task in order; a failing task does not stop the others
+    ///
+    /// Returns the names of the tasks that ran. Nothing runs while `operations`
+    /// is busy.
+    pub fn run_due(&mut self, operations: &OperationLock) -> Vec<String> {
+        if operations.is_busy() {
+            log::info!("[Scheduler] Import/export in progress, skipping maintenance");
+            return Vec::new();
+        }
+
+        let now = self.clock.now();
+        let due: Vec<usize> = (0..self.tasks.len())
+            .filter(|&i| self.is_due(self.tasks[i].as_ref(), now))
+            .collect();
+
+        let mut ran = Vec::new();
+        for index in due {
+            ran.push(self.tasks[index].name().to_string());
+            // Errors are recorded per task and never stop the remaining ones
+            let _ = self.run_at(index);
+        }
+        if !ran.is_empty() {
+            self.save_state();
+        }
+        ran
+    }
+
+    /// Run a task by name regardless of its interval
+    pub fn run_task(&mut self, name: &str) -> Result<TaskReport, String> {
+        let index = self
+            .tasks
+            .iter()
+            .position(|t| t.name() == name)
+            .ok_or_else(|| format!("Unknown maintenance task: {}", name))?;
+
+        let result = self.run_at(index);
+        self.save_state();
+        result
+    }
+
+    /// Run one task, recording its run time and any error
+    fn run_at(&mut self, index: usize) -> Result<TaskReport, String> {
+        let task = &self.tasks[index];
+        let name = task.name().to_string();
+        log::info!("[Scheduler] Running maintenance task '{}'", name);
+
+        let result = task.run(&self.context);
+        match &result {
+            Ok(report) => {
+                log::info!("[Scheduler] '{}' finished: {}", name, report.summary);
+                self.last_errors.remove(&name);
+            }
+            Err(e) => {
+                log::error!("[Scheduler] '{}' failed: {}", name, e);
+                self.last_errors.insert(name.clone(), e.clone());
+            }
+        }
+        self.last_runs.insert(name, self.clock.now());
+        result
+    }
+
+    /// Status of every registered task
+    pub fn status(&self) -> Vec<TaskStatus> {
+        self.tasks
+            .iter()
+            .map(|task| TaskStatus {
+                name: task.name().to_string(),
+                interval_secs: task.interval().as_secs(),
+                last_run: self.last_runs.get(task.name()).map(|t| t.to_rfc3339()),
+                last_error: self.last_errors.get(task.name()).cloned(),
+            })
+            .collect()
+    }
+
+    fn save_state(&self) {
+        let stored: HashMap<&String, String> = self
+            .last_runs
+            .iter()
+            .map(|(name, time)| (name, time.to_rfc3339()))
+            .collect();
+        let result = serde_json::to_string_pretty(&stored)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(&self.state_path, json).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            log::warn!("[Scheduler] Failed to save maintenance state: {}", e);
+        }
+    }
+}
+
+/// Managed handle to the scheduler, shared with its background thread
+///
+/// Empty until `setup()` installs the scheduler.
+#[derive(Clone, Default)]
+pub struct SchedulerState {
+    scheduler: Arc<Mutex<Option<Scheduler>>>,
+}
+
+impl SchedulerState {
+    pub fn install(&self, scheduler: Scheduler) {
+        *self.scheduler.lock().unwrap_or_else(|e| e.into_inner()) = Some(scheduler);
+    }
+
+    /// Run a closure against the scheduler, if it is installed
+    pub fn with_scheduler<T>(&self, f: impl FnOnce(&mut Scheduler) -> T) -> Result<T, String> {
+        let mut guard = self.scheduler.lock().unwrap_or_else(|e| e.into_inner());
+        match guard.as_mut() {
+            Some(scheduler) => Ok(f(scheduler)),
+            None => Err("Maintenance scheduler is not running".to_string()),
+        }
+    }
+}
+
+/// Start the background thread that runs due tasks every `tick`
+pub fn start(state: SchedulerState, operations: OperationLock, tick: Duration) {
+    std::thread::spawn(move || {
+        log::info!(
+            "[Scheduler] Starting maintenance thread ({:?} interval)",
+            tick
+        );
+        loop {
+            std::thread::sleep(tick);
+            if let Err(e) = state.with_scheduler(|scheduler| scheduler.run_due(&operations)) {
+                log::warn!("[Scheduler] {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[derive(Clone)]
+    struct FakeClock(Arc<Mutex<DateTime<Utc>>>);
+
+    impl FakeClock {
+        fn new() -> Self {
+            Self(Arc::new(Mutex::new(
+                DateTime::parse_from_rfc3339("2025-01-24T10:00:00Z")
+                    .unwrap()
+                    .with_timezone(&Utc),
+            )))
+        }
+
+        fn advance(&self, by: Duration) {
+            let mut now = self.0.lock().unwrap();
+            *now += chrono::Duration::from_std(by).unwrap();
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    struct MockTask {
+        name: &'static str,
+        interval: Duration,
+        fail: bool,
+        runs: Arc<AtomicUsize>,
+    }
+
+    impl MaintenanceTask for MockTask {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn interval(&self) -> Duration {
+            self.interval
+        }
+
+        fn run(&self, _ctx: &AppContext) -> Result<TaskReport, String> {
+            self.runs.fetch_add(1, Ordering::SeqCst);
+            if self.fail {
+                Err("disk full".to_string())
+            } else {
+                Ok(TaskReport {
+                    summary: "done".to_string(),
+                })
+            }
+        }
+    }
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn create_scheduler(
+        temp: &TempDir,
+        clock: &FakeClock,
+    ) -> (Scheduler, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+        let context = AppContext {
+            cache_dir: temp.path().join("cache"),
+            data_dir: temp.path().to_path_buf(),
+        };
+        let mut scheduler = Scheduler::new(
+            context,
+            temp.path().join("maintenance.json"),
+            Box::new(clock.clone()),
+        );
+        let hourly = Arc::new(AtomicUsize::new(0));
+        let failing = Arc::new(AtomicUsize::new(0));
+        scheduler.register(Box::new(MockTask {
+            name: "hourly",
+            interval: HOUR,
+            fail: false,
+            runs: hourly.clone(),
+        }));
+        scheduler.register(Box::new(MockTask {
+            name: "daily-failing",
+            interval: HOUR * 24,
+            fail: true,
+            runs: failing.clone(),
+        }));
+        (scheduler, hourly, failing)
+    }
+
+    #[test]
+    fn test_runs_due_tasks_on_schedule() {
+        let temp = TempDir::new().unwrap();
+        let clock = FakeClock::new();
+        let (mut scheduler, hourly, failing) = create_scheduler(&temp, &clock);
+        let operations = OperationLock::default();
+
+        // Never run before: both are due, and the failure doesn't stop the other
+        assert_eq!(
+            scheduler.run_due(&operations),
+            vec!["hourly", "daily-failing"]
+        );
+        assert!(scheduler.run_due(&operations).is_empty());
+
+        clock.advance(HOUR);
+        assert_eq!(scheduler.run_due(&operations), vec!["hourly"]);
+        assert_eq!(hourly.load(Ordering::SeqCst), 2);
+        assert_eq!(failing.load(Ordering::SeqCst), 1);
+
+        let status = scheduler.status();
+        assert_eq!(status[0].last_error, None);
+        assert_eq!(status[1].last_error.as_deref(), Some("disk full"));
+        assert_eq!(
+            status[1].last_run.as_deref(),
+            Some("2025-01-24T10:00:00+00:00")
+        );
+    }
+
+    #[test]
+    fn test_last_runs_survive_restart() {
+        let temp = TempDir::new().unwrap();
+        let clock = FakeClock::new();
+        let operations = OperationLock::default();
+        {
+            let (mut scheduler, _, _) = create_scheduler(&temp, &clock);
+            scheduler.run_due(&operations);
+        }
+
+        clock.advance(HOUR * 2);
+        let (mut restarted, hourly, failing) = create_scheduler(&temp, &clock);
+        assert_eq!(restarted.run_due(&operations), vec!["hourly"]);
+        assert_eq!(hourly.load(Ordering::SeqCst), 1);
+        assert_eq!(failing.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_skips_while_operation_running() {
+        let temp = TempDir::new().unwrap();
+        let clock = FakeClock::new();
+        let (mut scheduler, hourly, _) = create_scheduler(&temp, &clock);
+        let operations = OperationLock::default();
+
+        let guard = operations.begin();
+        assert!(scheduler.run_due(&operations).is_empty());
+        assert_eq!(hourly.load(Ordering::SeqCst), 0);
+
+        drop(guard);
+        assert_eq!(scheduler.run_due(&operations).len(), 2);
+    }
+
+    #[test]
+    fn test_try_begin_only_when_idle() {
+        let operations = OperationLock::default();
+        let guard = operations.begin();
+        assert!(operations.try_begin().is_none());
+        drop(guard);
+
+        let taken = operations.try_begin().unwrap();
+        assert!(operations.is_busy());
+        assert!(operations.try_begin().is_none());
+        drop(taken);
+        assert!(!operations.is_busy());
+    }
+
+    #[test]
+    fn test_run_task_manually() {
+        let temp = TempDir::new().unwrap();
+        let clock = FakeClock::new();
+        let (mut scheduler, hourly, _) = create_scheduler(&temp, &clock);
+
+        assert_eq!(scheduler.run_task("hourly").unwrap().summary, "done");
+        assert_eq!(
+            scheduler.run_task("daily-failing").unwrap_err(),
+            "disk full"
+        );
+        assert!(scheduler.run_task("missing").is_err());
+        assert_eq!(hourly.load(Ordering::SeqCst), 1);
+    }
+}
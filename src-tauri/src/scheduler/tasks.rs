@@ -0,0 +1,70 @@
+//! Built-in maintenance tasks
+
+use super::{AppContext, MaintenanceTask, TaskReport};
+use crate::covers::CoverExtractor;
+use crate::library::removal::PARKED_RETENTION_DAYS;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+/// Covers not rewritten for this long are dropped from the cache
+const COVER_MAX_AGE: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+/// Removes stale entries from the cover cache once a day
+pub struct CoverCacheEvictionTask;
+
+impl MaintenanceTask for CoverCacheEvictionTask {
+    fn name(&self) -> &str {
+        "cover-cache-eviction"
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_secs(24 * 60 * 60)
+    }
+
+    fn run(&self, ctx: &AppContext) -> Result<TaskReport, String> {
+        if !ctx.cache_dir.exists() {
+            return Ok(TaskReport {
+                summary: "no cover cache".to_string(),
+            });
+        }
+
+        let removed = CoverExtractor::new(ctx.cache_dir.clone())
+            .evict_older_than(COVER_MAX_AGE)
+            .map_err(|e| e.to_string())?;
+        Ok(TaskReport {
+            summary: format!("removed {} stale cover(s)", removed),
+        })
+    }
+}
+
+/// Drops tags, favorites and export tracking parked for removed books that
+/// weren't imported again within `PARKED_RETENTION_DAYS`, once a day
+pub struct ParkedRowsPruneTask {
+    /// Prunes the open library of rows parked before the given time
+    prune: Box<dyn Fn(DateTime<Utc>) -> Result<usize, String> + Send>,
+}
+
+impl ParkedRowsPruneTask {
+    pub fn new(prune: impl Fn(DateTime<Utc>) -> Result<usize, String> + Send + 'static) -> Self {
+        Self {
+            prune: Box::new(prune),
+        }
+    }
+}
+
+impl MaintenanceTask for ParkedRowsPruneTask {
+    fn name(&self) -> &str {
+        "parked-rows-prune"
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_secs(24 * 60 * 60)
+    }
+
+    fn run(&self, _ctx: &AppContext) -> Result<TaskReport, String> {
+        let removed = (self.prune)(Utc::now() - chrono::Duration::days(PARKED_RETENTION_DAYS))?;
+        Ok(TaskReport {
+            summary: format!("removed {} parked row(s)", removed),
+        })
+    }
+}
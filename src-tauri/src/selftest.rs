@@ -0,0 +1,277 @@
+//! End-to-end pipeline self-test
+//!
+//! Support questions usually come down to "which step is broken?". The
+//! self-test runs detection, database extraction, cover extraction and export
+//! against a synthetic device in a scratch directory, then checks the real
+//! environment: settings folder, export path and cover cache.
+
+use crate::covers::CoverExtractor;
+use crate::db::kobo::KoboDatabase;
+use crate::device::DeviceDetector;
+use crate::export::MarkdownExporter;
+use crate::fixtures;
+use crate::models::{Book, KoboDevice};
+use crate::settings::AppSettings;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Outcome of one stage or environment check
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SelfTestCheck {
+    /// "detection", "database", "covers", "export", "settings", "exportPath"
+    /// or "cacheDir"
+    pub name: String,
+    pub passed: bool,
+    pub duration_ms: u64,
+    /// Error (or reason for skipping) when the check failed
+    pub detail: Option<String>,
+}
+
+/// Result of `run_self_test`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SelfTestReport {
+    pub passed: bool,
+    /// Pipeline stages on the synthetic device, in order
+    pub stages: Vec<SelfTestCheck>,
+    /// Checks of the real settings, export and cache locations
+    pub environment: Vec<SelfTestCheck>,
+}
+
+/// Real locations the environment checks look at
+#[derive(Debug, Clone)]
+pub struct SelfTestEnvironment {
+    pub settings_path: PathBuf,
+    pub export_path: PathBuf,
+    pub cache_dir: PathBuf,
+}
+
+/// Run the synthetic pipeline and the environment checks
+pub fn run_self_test(environment: &SelfTestEnvironment) -> SelfTestReport {
+    let scratch = scratch_dir();
+    let stages = run_pipeline(&scratch);
+    if let Err(e) = fs::remove_dir_all(&scratch) {
+        log::warn!("[SelfTest] Failed to remove {:?}: {}", scratch, e);
+    }
+
+    let environment = vec![
+        run_check("settings", || {
+            let folder = environment
+                .settings_path
+                .parent()
+                .ok_or("Settings path has no folder")?;
+            probe_writable(folder)
+        }),
+        run_check("exportPath", || check_export_path(&environment.export_path)),
+        run_check("cacheDir", || probe_writable(&environment.cache_dir)),
+    ];
+
+    let passed = stages.iter().chain(&environment).all(|check| check.passed);
+    log::info!("[SelfTest] Finished (passed: {})", passed);
+    SelfTestReport {
+        passed,
+        stages,
+        environment,
+    }
+}
+
+fn run_pipeline(scratch: &Path) -> Vec<SelfTestCheck> {
+    let volumes = scratch.join("Volumes");
+    let mut device: Option<KoboDevice> = None;
+    let mut books: Option<Vec<Book>> = None;
+
+    let mut stages = vec![run_check("detection", || {
+        fixtures::create_kobo_volume(
+            &volumes.join("KOBOeReader"),
+            "SELFTEST",
+            fixtures::TWO_BOOKS_DATA,
+        )
+        .map_err(|e| format!("Failed to create the synthetic device: {}", e))?;
+        let found = DeviceDetector::new(volumes.clone())
+            .scan_for_kobo()
+            .map_err(|e| e.to_string())?
+            .ok_or("No device detected")?;
+        if !found.is_valid {
+            return Err(found
+                .invalid_reason
+                .unwrap_or_else(|| "Device is not valid".to_string()));
+        }
+        device = Some(found);
+        Ok(())
+    })];
+
+    stages.push(match &device {
+        Some(device) => run_check("database", || {
+            let path = DeviceDetector::new(volumes.clone())
+                .get_database_path(device)
+                .ok_or("KoboReader.sqlite not found")?;
+            let extracted = KoboDatabase::new(&path)
+                .map_err(|e| e.to_string())?
+                .extract_books_with_highlights()
+                .map_err(|e| e.to_string())?;
+            if extracted.len() != 2 {
+                return Err(format!("Expected 2 books, found {}", extracted.len()));
+            }
+            books = Some(extracted);
+            Ok(())
+        }),
+        None => skipped("database", "detection"),
+    });
+
+    stages.push(run_check("covers", || {
+        let epub = scratch.join("book.epub");
+        fixtures::write_epub_with_cover(&epub).map_err(|e| e.to_string())?;
+        let cache = scratch.join("covers");
+        fs::create_dir_all(&cache).map_err(|e| e.to_string())?;
+        let cover = CoverExtractor::new(cache)
+            .extract_cover(&epub)
+            .map_err(|e| e.to_string())?
+            .ok_or("No cover extracted")?;
+        if fs::read(&cover).map_err(|e| e.to_string())? != fixtures::COVER_JPEG {
+            return Err(format!("Unexpected cover contents in {:?}", cover));
+        }
+        Ok(())
+    }));
+
+    stages.push(match &books {
+        Some(books) => run_check("export", || {
+            let export_dir = scratch.join("export");
+            let mut config = AppSettings::default().export_config;
+            config.export_path = export_dir.to_string_lossy().to_string();
+            let results = MarkdownExporter::new(export_dir).export_books(books, &config);
+            for result in &results {
+                let path = result.as_ref().map_err(|e| e.to_string())?;
+                if !path.is_file() {
+                    return Err(format!("Export reported {:?} but it is missing", path));
+                }
+            }
+            if results.len() != books.len() {
+                return Err(format!(
+                    "Exported {} of {} books",
+                    results.len(),
+                    books.len()
+                ));
+            }
+            Ok(())
+        }),
+        None => skipped("export", "database"),
+    });
+
+    stages
+}
+
+/// Time `check` and record its outcome
+fn run_check<F>(name: &str, check: F) -> SelfTestCheck
+where
+    F: FnOnce() -> Result<(), String>,
+{
+    let started = Instant::now();
+    let result = check();
+    let duration_ms = started.elapsed().as_millis() as u64;
+    if let Err(e) = &result {
+        log::warn!("[SelfTest] {} failed: {}", name, e);
+    }
+    SelfTestCheck {
+        name: name.to_string(),
+        passed: result.is_ok(),
+        duration_ms,
+        detail: result.err(),
+    }
+}
+
+fn skipped(name: &str, failed: &str) -> SelfTestCheck {
+    SelfTestCheck {
+        name: name.to_string(),
+        passed: false,
+        duration_ms: 0,
+        detail: Some(format!("Skipped: {} failed", failed)),
+    }
+}
+
+/// Fresh scratch folder under the system temp directory
+fn scratch_dir() -> PathBuf {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!(
+        "khi-self-test-{}-{}",
+        std::process::id(),
+        RUNS.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Create `folder` if needed and write (then remove) a probe file in it
+fn probe_writable(folder: &Path) -> Result<(), String> {
+    fs::create_dir_all(folder).map_err(|e| format!("Cannot create {:?}: {}", folder, e))?;
+    let probe = folder.join(".khi-self-test");
+    fs::write(&probe, b"ok").map_err(|e| format!("Cannot write to {:?}: {}", folder, e))?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+/// An existing writable folder, or one that can be created in an existing
+/// parent (exports create it on demand)
+fn check_export_path(path: &Path) -> Result<(), String> {
+    if path.as_os_str().is_empty() {
+        return Err("No export path configured".to_string());
+    }
+    if path.exists() {
+        if !path.is_dir() {
+            return Err(format!("{:?} is not a folder", path));
+        }
+        return probe_writable(path);
+    }
+    match path.parent() {
+        Some(parent) if parent.is_dir() => Ok(()),
+        _ => Err(format!("Parent folder of {:?} does not exist", path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn environment(root: &Path) -> SelfTestEnvironment {
+        SelfTestEnvironment {
+            settings_path: root.join("config").join("settings.json"),
+            export_path: root.join("notes"),
+            cache_dir: root.join("cache"),
+        }
+    }
+
+    #[test]
+    fn test_self_test_passes_every_stage() {
+        let temp = TempDir::new().unwrap();
+        let report = run_self_test(&environment(temp.path()));
+
+        let names: Vec<&str> = report.stages.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["detection", "database", "covers", "export"]);
+        for check in report.stages.iter().chain(&report.environment) {
+            assert!(check.passed, "{}: {:?}", check.name, check.detail);
+        }
+        assert!(report.passed);
+    }
+
+    #[test]
+    fn test_broken_export_path_fails_only_its_check() {
+        let temp = TempDir::new().unwrap();
+        let mut environment = environment(temp.path());
+        // A file where the export folder should be
+        fs::write(temp.path().join("notes.md"), "").unwrap();
+        environment.export_path = temp.path().join("notes.md");
+
+        let report = run_self_test(&environment);
+        assert!(!report.passed);
+        let failed: Vec<&str> = report
+            .stages
+            .iter()
+            .chain(&report.environment)
+            .filter(|check| !check.passed)
+            .map(|check| check.name.as_str())
+            .collect();
+        assert_eq!(failed, vec!["exportPath"]);
+    }
+}
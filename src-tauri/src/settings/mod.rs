@@ -5,10 +5,31 @@
 //! - UI preferences (theme, window size/position)
 //! - Last import/export records
 
-use crate::models::{DateFormat, ExportConfig, MetadataConfig};
+use crate::db::schema::SchemaCompatibility;
+use crate::device::registry::{default_device_name, KnownDevice};
+use crate::export::git::DEFAULT_COMMIT_MESSAGE;
+use crate::models::{
+    Book, BulletIndentation, DateFormat, ExportConfig, ExportFormat, ExportWriteMode,
+    HighlightSeparator, HighlightStyle, ImportFilters, JournalLayout, KoboDevice, MetadataConfig,
+    RedactionPolicy, TabularOptions, DEFAULT_REDACTION_MARKER, DEFAULT_SNAPSHOT_KEEP,
+    DEFAULT_SNAPSHOT_MAX_MB,
+};
+use crate::updates::{UpdateCheckCache, DEFAULT_CHECK_INTERVAL_HOURS};
+use crate::usage::DEFAULT_USAGE_RETENTION_MONTHS;
+use crate::utils::cancel::OperationKind;
+use crate::utils::fs::LineEndings;
+use crate::utils::metrics::MetricsSummary;
+use crate::utils::path::default_export_dir;
+use crate::utils::text::TextNormalization;
+use crate::utils::titles::TitleOptions;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
 
 /// Application settings structure
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -23,10 +44,148 @@ pub struct AppSettings {
     /// Last import record
     #[serde(default, alias = "last_import")]
     pub last_import: Option<LastImportRecord>,
+    /// Most recent import per device serial number
+    #[serde(default, alias = "device_imports")]
+    pub device_imports: BTreeMap<String, LastImportRecord>,
+    /// Devices seen before, by serial number
+    #[serde(default, alias = "known_devices")]
+    pub known_devices: BTreeMap<String, KnownDevice>,
+    /// Rules applied to highlights right after extraction
+    #[serde(default, alias = "import_filters")]
+    pub import_filters: ImportFilters,
+    /// Import highlights deleted on the device as excluded instead of skipping them
+    #[serde(default, alias = "import_hidden_highlights")]
+    pub import_hidden_highlights: bool,
+    /// Import the dictionary lookups (`WordList`) with each book
+    #[serde(default, alias = "import_vocabulary")]
+    pub import_vocabulary: bool,
+    /// Import highlights made on figures and pull their images from the
+    /// EPUB, which must be on the device during the import
+    #[serde(default, alias = "import_figures")]
+    pub import_figures: bool,
+    /// Extra folder or file names skipped when looking for books on the
+    /// device, besides hidden files and OS junk
+    #[serde(default, alias = "device_ignore")]
+    pub device_ignore: Vec<String>,
+    /// Cleanup of EPUB artifacts (soft hyphens, odd spaces) in highlight text
+    #[serde(default, alias = "text_normalization")]
+    pub text_normalization: TextNormalization,
+    /// Title-casing and subtitle splitting at import, and which title form
+    /// exports use
+    #[serde(default, alias = "title_options")]
+    pub title_options: TitleOptions,
+    /// Offset assumed for device timestamps without a zone, in minutes east
+    /// of UTC (older firmware writes local time)
+    #[serde(default, alias = "assumed_utc_offset_minutes")]
+    pub assumed_utc_offset_minutes: i32,
+    /// Report edits made outside khi to files in the export folder
+    #[serde(default, alias = "watch_export_dir")]
+    pub watch_export_dir: bool,
+    /// Security-scoped bookmark for the picked export folder (sandboxed macOS only)
+    #[serde(
+        default,
+        alias = "export_path_bookmark",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub export_path_bookmark: Option<Vec<u8>>,
+    /// Named export profiles (the active one mirrors `export_config`)
+    #[serde(default, alias = "export_profiles")]
+    pub export_profiles: Vec<NamedExportProfile>,
+    /// Name of the active export profile
+    #[serde(default = "default_profile_name", alias = "active_profile")]
+    pub active_profile: String,
+    /// Allow features that contact the internet (update check)
+    #[serde(default, alias = "allow_network")]
+    pub allow_network: bool,
+    /// Allow read-only SQL queries against the device database
+    #[serde(default, alias = "enable_advanced_queries")]
+    pub enable_advanced_queries: bool,
+    /// Minimum hours between two update checks
+    #[serde(
+        default = "default_update_check_interval",
+        alias = "update_check_interval_hours"
+    )]
+    pub update_check_interval_hours: u32,
+    /// Months of local usage history kept (0 keeps everything)
+    #[serde(
+        default = "default_usage_retention_months",
+        alias = "usage_retention_months"
+    )]
+    pub usage_retention_months: u32,
+    /// Result of the last update check
+    #[serde(default, alias = "update_check")]
+    pub update_check: Option<UpdateCheckCache>,
+    /// How long imports, exports, manifest checks and network requests may
+    /// run before they are cancelled
+    #[serde(default, alias = "operation_timeouts")]
+    pub operation_timeouts: OperationTimeouts,
     /// Version for migration support
     pub version: String,
 }
 
+/// Name of the profile created from a pre-profiles export config
+pub const DEFAULT_PROFILE_NAME: &str = "Default";
+
+fn default_profile_name() -> String {
+    DEFAULT_PROFILE_NAME.to_string()
+}
+
+fn default_update_check_interval() -> u32 {
+    DEFAULT_CHECK_INTERVAL_HOURS
+}
+
+fn default_usage_retention_months() -> u32 {
+    DEFAULT_USAGE_RETENTION_MONTHS
+}
+
+/// A named export configuration for a specific destination
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NamedExportProfile {
+    pub name: String,
+    pub config: ExportConfig,
+}
+
+/// Seconds each kind of long-running operation may take before it is
+/// cancelled; 0 means no deadline
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OperationTimeouts {
+    #[serde(alias = "import_secs")]
+    pub import_secs: u64,
+    #[serde(alias = "export_secs")]
+    pub export_secs: u64,
+    #[serde(alias = "verify_secs")]
+    pub verify_secs: u64,
+    /// Per network request
+    #[serde(alias = "network_secs")]
+    pub network_secs: u64,
+}
+
+impl Default for OperationTimeouts {
+    fn default() -> Self {
+        Self {
+            import_secs: 600,
+            export_secs: 1800,
+            verify_secs: 600,
+            network_secs: 30,
+        }
+    }
+}
+
+impl OperationTimeouts {
+    /// Deadline of an operation of `kind`, counted from its start
+    pub fn deadline(&self, kind: OperationKind) -> Option<Duration> {
+        let secs = match kind {
+            OperationKind::Import => self.import_secs,
+            OperationKind::Export => self.export_secs,
+            OperationKind::Verify => self.verify_secs,
+            OperationKind::Network => self.network_secs,
+        };
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+}
+
 /// UI preferences
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
@@ -51,6 +210,27 @@ pub struct UiPreferences {
     /// Sort preference for library
     #[serde(alias = "library_sort")]
     pub library_sort: SortPreference,
+    /// Report volumes with a `.kobo` folder that fail database validation
+    #[serde(default, alias = "show_invalid_devices")]
+    pub show_invalid_devices: bool,
+    /// Consecutive scans a newly mounted device must stay present before
+    /// it is announced
+    #[serde(default = "default_device_settle_scans", alias = "device_settle_scans")]
+    pub device_settle_scans: u32,
+    /// Import as soon as a device is connected (read by the frontend)
+    #[serde(default = "default_true", alias = "auto_import_on_connect")]
+    pub auto_import_on_connect: bool,
+}
+
+/// Default of `UiPreferences::device_settle_scans`
+pub const DEFAULT_DEVICE_SETTLE_SCANS: u32 = 2;
+
+fn default_device_settle_scans() -> u32 {
+    DEFAULT_DEVICE_SETTLE_SCANS
+}
+
+fn default_true() -> bool {
+    true
 }
 
 /// Theme preference
@@ -89,6 +269,67 @@ pub enum SortPreference {
     DateLastRead,
     #[serde(alias = "highlight_count")]
     HighlightCount,
+    /// Most time spent reading first; books without it last
+    #[serde(alias = "time_spent_reading")]
+    TimeSpentReading,
+}
+
+/// What went wrong with a book during an import
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportWarningKind {
+    /// The book's content row is gone (deleted on the device while its
+    /// bookmarks remain), so only the file name is known
+    OrphanedBook,
+    /// The newest highlight is later than the device's last sleep: the
+    /// book was still open, and newer highlights may be unsaved
+    PossiblyStaleData,
+}
+
+/// A problem with one imported book, shown to the user after the import
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportWarning {
+    pub kind: ImportWarningKind,
+    #[serde(alias = "content_id")]
+    pub content_id: String,
+    pub title: String,
+    pub message: String,
+}
+
+impl ImportWarning {
+    /// Warning for a book without a content row
+    pub fn orphaned_book(book: &Book) -> Self {
+        Self {
+            kind: ImportWarningKind::OrphanedBook,
+            content_id: book.content_id.clone(),
+            title: book.title.clone(),
+            message: format!(
+                "'{}' is no longer on the device but {} highlight(s) of it remain; \
+                 its title comes from the file name and author, ISBN and other \
+                 metadata are unavailable",
+                book.title,
+                book.highlights.len()
+            ),
+        }
+    }
+
+    /// Warning for highlights of `book` made after the device's last sleep
+    /// (`last_suspend`); see `device::freshness`
+    pub fn possibly_stale_data(book: &Book, last_suspend: DateTime<Utc>) -> Self {
+        Self {
+            kind: ImportWarningKind::PossiblyStaleData,
+            content_id: book.content_id.clone(),
+            title: book.title.clone(),
+            message: format!(
+                "'{}' was still open when the Kobo last went to sleep ({}), so its \
+                 latest highlights may not be saved yet. Wake the Kobo, go back to \
+                 the home screen, put it to sleep, then connect it and import again",
+                book.title,
+                last_suspend.format("%Y-%m-%d %H:%M UTC")
+            ),
+        }
+    }
 }
 
 /// Last import record
@@ -106,19 +347,134 @@ pub struct LastImportRecord {
     /// Number of highlights imported
     #[serde(alias = "highlights_count")]
     pub highlights_count: usize,
+    /// How long the import took, in milliseconds
+    #[serde(default, alias = "duration_ms")]
+    pub duration_ms: u64,
+    /// Non-fatal problems hit during the import (e.g. unreadable covers)
+    #[serde(default, alias = "warnings_count")]
+    pub warnings_count: usize,
+    /// Problems with individual books, one entry each (also counted in
+    /// `warnings_count`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ImportWarning>,
+    /// Highlights dropped by the import filters
+    #[serde(default, alias = "filtered_count")]
+    pub filtered_count: usize,
+    /// Where the import's time went
+    #[serde(default, skip_serializing_if = "MetricsSummary::is_empty")]
+    pub metrics: MetricsSummary,
+    /// How the device database compared with the schemas khi was tested with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<SchemaCompatibility>,
 }
 
 impl Default for AppSettings {
     fn default() -> Self {
+        let export_config = ExportConfig::default();
         Self {
-            export_config: ExportConfig::default(),
+            export_profiles: vec![NamedExportProfile {
+                name: default_profile_name(),
+                config: export_config.clone(),
+            }],
+            export_config,
             ui_preferences: UiPreferences::default(),
             last_import: None,
+            device_imports: BTreeMap::new(),
+            known_devices: BTreeMap::new(),
+            import_filters: ImportFilters::default(),
+            import_hidden_highlights: false,
+            import_vocabulary: false,
+            import_figures: false,
+            device_ignore: Vec::new(),
+            text_normalization: TextNormalization::default(),
+            title_options: TitleOptions::default(),
+            assumed_utc_offset_minutes: 0,
+            watch_export_dir: false,
+            export_path_bookmark: None,
+            active_profile: default_profile_name(),
+            allow_network: false,
+            enable_advanced_queries: false,
+            update_check_interval_hours: default_update_check_interval(),
+            usage_retention_months: default_usage_retention_months(),
+            update_check: None,
+            operation_timeouts: OperationTimeouts::default(),
             version: env!("CARGO_PKG_VERSION").to_string(),
         }
     }
 }
 
+impl AppSettings {
+    /// Find an export profile by name (trimmed, case-insensitive)
+    pub fn find_export_profile(&self, name: &str) -> Option<&NamedExportProfile> {
+        let name = name.trim();
+        self.export_profiles
+            .iter()
+            .find(|p| p.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Bring the export profiles in line with `export_config`
+    ///
+    /// Migrates settings from before profiles existed by wrapping the single
+    /// export config as the "Default" profile, repairs a dangling active
+    /// profile name, and copies `export_config` into the active profile.
+    pub fn normalize_export_profiles(&mut self) {
+        if self.export_profiles.is_empty() {
+            self.export_profiles.push(NamedExportProfile {
+                name: default_profile_name(),
+                config: self.export_config.clone(),
+            });
+            self.active_profile = default_profile_name();
+        }
+
+        let active = match self.find_export_profile(&self.active_profile) {
+            Some(profile) => profile.name.clone(),
+            None => {
+                let first = &self.export_profiles[0];
+                self.export_config = first.config.clone();
+                first.name.clone()
+            }
+        };
+        self.active_profile = active;
+
+        let export_config = self.export_config.clone();
+        if let Some(profile) = self
+            .export_profiles
+            .iter_mut()
+            .find(|p| p.name == self.active_profile)
+        {
+            profile.config = export_config;
+        }
+    }
+
+    /// Re-key the per-device import history by serial number
+    ///
+    /// Device IDs used to be the whole `.kobo/version` line; they are now its
+    /// first field. On a clash the entry already under the serial is newer
+    /// and wins.
+    pub fn migrate_device_serials(&mut self) {
+        let serial = |id: &str| id.split(',').next().unwrap_or(id).trim().to_string();
+        let legacy: Vec<String> = self
+            .device_imports
+            .keys()
+            .filter(|id| id.contains(','))
+            .cloned()
+            .collect();
+        for id in legacy {
+            let Some(mut record) = self.device_imports.remove(&id) else {
+                continue;
+            };
+            let serial = serial(&id);
+            record.device_id = Some(serial.clone());
+            self.device_imports.entry(serial).or_insert(record);
+        }
+        if let Some(record) = &mut self.last_import {
+            if let Some(id) = record.device_id.as_mut().filter(|id| id.contains(',')) {
+                *id = serial(id);
+            }
+        }
+    }
+}
+
 impl Default for UiPreferences {
     fn default() -> Self {
         Self {
@@ -129,17 +485,54 @@ impl Default for UiPreferences {
             show_onboarding: true,
             library_view_mode: ViewMode::Grid,
             library_sort: SortPreference::DateLastRead,
+            show_invalid_devices: false,
+            device_settle_scans: DEFAULT_DEVICE_SETTLE_SCANS,
+            auto_import_on_connect: true,
         }
     }
 }
 
 impl Default for ExportConfig {
     fn default() -> Self {
-        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
         Self {
-            export_path: format!("{}/Documents/Kobo Highlights", home),
+            export_path: default_export_dir().to_string_lossy().to_string(),
             metadata: MetadataConfig::default(),
             date_format: DateFormat::DdMonthYyyy,
+            folder_pattern: String::new(),
+            write_index: false,
+            atom_feed: false,
+            feed_limit: 0,
+            highlight_separator: HighlightSeparator::None,
+            compact: false,
+            show_location: true,
+            excluded_chapter_patterns: Vec::new(),
+            max_concurrent_writes: 1,
+            bullet_indentation: BulletIndentation::Tab,
+            write_mode: ExportWriteMode::Overwrite,
+            journal_layout: JournalLayout::Monthly,
+            git_commit_after_export: false,
+            commit_message_template: DEFAULT_COMMIT_MESSAGE.to_string(),
+            write_sidecars: false,
+            tabular: TabularOptions::default(),
+            markdown_template: String::new(),
+            ascii_filenames: false,
+            highlight_style: HighlightStyle::Blockquote,
+            redaction: RedactionPolicy::Exclude,
+            redaction_markers: vec![DEFAULT_REDACTION_MARKER.to_string()],
+            group_pocket_articles: false,
+            bom: false,
+            line_endings: LineEndings::Lf,
+            group_by_color: false,
+            color_labels: BTreeMap::new(),
+            cloud_settle_ms: 0,
+            transactional: false,
+            filename_pattern: String::new(),
+            format: ExportFormat::Markdown,
+            citation_notes: false,
+            include_toc: false,
+            snapshot_exports: false,
+            snapshot_keep: DEFAULT_SNAPSHOT_KEEP,
+            snapshot_max_mb: DEFAULT_SNAPSHOT_MAX_MB,
         }
     }
 }
@@ -153,48 +546,198 @@ impl Default for MetadataConfig {
             date_last_read: true,
             language: true,
             description: false,
+            stats: false,
+            series: false,
+            vocabulary: false,
+            loan: false,
+            book_notes: false,
+            book_notes_raw: false,
+            order: Vec::new(),
+        }
+    }
+}
+
+/// Health of the settings storage, reported to the frontend
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsHealth {
+    /// Whether changes are written to disk (false = in-memory only)
+    pub persistence_available: bool,
+    /// Path of the settings file
+    pub config_path: String,
+}
+
+/// Part of the settings that can be restored from the backup on its own
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SettingsSection {
+    ExportConfig,
+    UiPreferences,
+    /// The named export profiles and which one is active; the export config
+    /// follows the active profile
+    ExportProfiles,
+    All,
+}
+
+impl SettingsSection {
+    /// Top-level settings keys (as serialized) the section covers
+    fn keys(self) -> &'static [&'static str] {
+        match self {
+            SettingsSection::ExportConfig => &["exportConfig"],
+            SettingsSection::UiPreferences => &["uiPreferences"],
+            SettingsSection::ExportProfiles => &["exportProfiles", "activeProfile"],
+            SettingsSection::All => &[],
         }
     }
 }
 
+/// A top-level settings key whose value differs between two versions
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsChange {
+    pub key: String,
+    /// Values changed, added or removed below the key (1 for a plain value)
+    pub changed_values: usize,
+}
+
+/// The automatic settings backup and how it differs from the current settings
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsBackupInfo {
+    pub exists: bool,
+    /// When the backup was written
+    pub modified_at: Option<DateTime<Utc>>,
+    pub changes: Vec<SettingsChange>,
+    /// Sections restoring would change (`All` only if anything does)
+    pub changed_sections: Vec<SettingsSection>,
+}
+
+/// Top-level keys of two serialized settings whose values differ
+fn settings_changes(backup: &Value, current: &Value) -> Vec<SettingsChange> {
+    let empty = Map::new();
+    let backup = backup.as_object().unwrap_or(&empty);
+    let current = current.as_object().unwrap_or(&empty);
+    union_keys(backup, current)
+        .into_iter()
+        .filter_map(|key| {
+            let changed_values = count_changes(
+                backup.get(key).unwrap_or(&Value::Null),
+                current.get(key).unwrap_or(&Value::Null),
+            );
+            (changed_values > 0).then(|| SettingsChange {
+                key: key.clone(),
+                changed_values,
+            })
+        })
+        .collect()
+}
+
+/// Leaf values that differ between `a` and `b`, matching object keys and
+/// array positions; a missing value counts as null
+fn count_changes(a: &Value, b: &Value) -> usize {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => union_keys(a, b)
+            .into_iter()
+            .map(|key| {
+                count_changes(
+                    a.get(key).unwrap_or(&Value::Null),
+                    b.get(key).unwrap_or(&Value::Null),
+                )
+            })
+            .sum(),
+        (Value::Array(a), Value::Array(b)) => (0..a.len().max(b.len()))
+            .map(|i| {
+                count_changes(
+                    a.get(i).unwrap_or(&Value::Null),
+                    b.get(i).unwrap_or(&Value::Null),
+                )
+            })
+            .sum(),
+        (a, b) => usize::from(a != b),
+    }
+}
+
+/// Keys of either object, sorted
+fn union_keys<'a>(a: &'a Map<String, Value>, b: &'a Map<String, Value>) -> Vec<&'a String> {
+    let mut keys: Vec<&String> = a.keys().chain(b.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
 /// Settings manager for loading, saving, and accessing settings
 pub struct SettingsManager {
     pub settings: AppSettings,
     config_path: PathBuf,
+    persistence_available: bool,
 }
 
 impl SettingsManager {
     /// Create a new SettingsManager with the default config path
     pub fn new() -> Result<Self, SettingsError> {
-        let config_dir = Self::get_config_dir()?;
-        let config_path = config_dir.join("settings.json");
-        Self::with_path(config_path)
+        Self::with_path(Self::default_config_path()?)
+    }
+
+    /// Path of the settings file used by `new()`
+    pub fn default_config_path() -> Result<PathBuf, SettingsError> {
+        Ok(Self::get_config_dir()?.join("settings.json"))
     }
 
     /// Create a SettingsManager with a custom config path (useful for testing)
     pub fn with_path(config_path: PathBuf) -> Result<Self, SettingsError> {
-        let settings = if config_path.exists() {
+        let mut settings = if config_path.exists() {
             // Use fallback to handle corrupted settings gracefully
             Self::load_with_fallback(&config_path)?
         } else {
             AppSettings::default()
         };
+        settings.normalize_export_profiles();
+        settings.migrate_device_serials();
+
+        let persistence_available = Self::probe_writable(&config_path);
+        if !persistence_available {
+            log::warn!(
+                "Settings directory for {} is not writable. Running in degraded in-memory mode.",
+                config_path.display()
+            );
+        }
 
         Ok(Self {
             settings,
             config_path,
+            persistence_available,
         })
     }
 
+    /// Check whether the directory holding the settings file accepts writes
+    fn probe_writable(config_path: &Path) -> bool {
+        let dir = config_path.parent().unwrap_or_else(|| Path::new(""));
+        if !dir.as_os_str().is_empty() && !dir.exists() && fs::create_dir_all(dir).is_err() {
+            return false;
+        }
+
+        let probe_path = dir.join(".write_probe");
+        match fs::write(&probe_path, b"") {
+            Ok(()) => {
+                let _ = fs::remove_file(&probe_path);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
     /// Get the configuration directory path
     fn get_config_dir() -> Result<PathBuf, SettingsError> {
         let home = std::env::var("HOME").map_err(|_| SettingsError::HomeNotFound)?;
         let config_dir =
             PathBuf::from(home).join("Library/Application Support/KoboHighlightsExporter");
 
-        // Create directory if it doesn't exist
+        // Create directory if it doesn't exist. A failure here is not fatal:
+        // the writability probe will put the manager in degraded mode instead.
         if !config_dir.exists() {
-            fs::create_dir_all(&config_dir).map_err(SettingsError::IoError)?;
+            if let Err(e) = fs::create_dir_all(&config_dir) {
+                log::warn!("Failed to create settings directory: {}", e);
+            }
         }
 
         Ok(config_dir)
@@ -240,7 +783,20 @@ impl SettingsManager {
     /// 3. Atomic write: Write to temp file, then rename (prevents partial writes)
     /// 4. Post-validation: Read back and verify integrity
     /// 5. Retry with backoff: If write fails, retry up to 3 times
+    ///
+    /// In degraded mode (config directory not writable) this is a no-op so
+    /// that in-memory changes keep working without the retry delays.
     pub fn save(&self) -> Result<(), SettingsError> {
+        self.write(true)
+    }
+
+    /// Write the settings, backing up the file they replace when `backup`
+    fn write(&self, backup: bool) -> Result<(), SettingsError> {
+        if !self.persistence_available {
+            log::warn!("Settings persistence unavailable, keeping changes in memory only");
+            return Ok(());
+        }
+
         let content =
             serde_json::to_string_pretty(&self.settings).map_err(SettingsError::SerializeError)?;
 
@@ -251,7 +807,7 @@ impl SettingsManager {
         }
 
         // Layer 2: Backup previous version (if exists and is valid)
-        if self.config_path.exists() {
+        if backup && self.config_path.exists() {
             let backup_path = self.config_path.with_extension("json.backup");
             if let Err(e) = fs::copy(&self.config_path, &backup_path) {
                 log::warn!("Failed to create settings backup: {}", e);
@@ -357,12 +913,242 @@ impl SettingsManager {
         self.save()
     }
 
-    /// Update the last import record
+    /// Update the last import record (and the per-device entry, if known)
     pub fn set_last_import(&mut self, record: LastImportRecord) -> Result<(), SettingsError> {
+        if let Some(device_id) = &record.device_id {
+            self.settings
+                .device_imports
+                .insert(device_id.clone(), record.clone());
+        }
         self.settings.last_import = Some(record);
         self.save()
     }
 
+    /// Replace the `PossiblyStaleData` warning of `device_id`'s last import
+    /// with `warning` (none: the data is fresh now)
+    pub fn replace_stale_data_warning(
+        &mut self,
+        device_id: &str,
+        warning: Option<ImportWarning>,
+    ) -> Result<(), SettingsError> {
+        let replace = |record: &mut LastImportRecord| {
+            let before = record.warnings.len();
+            record
+                .warnings
+                .retain(|w| w.kind != ImportWarningKind::PossiblyStaleData);
+            // The frontend may have reset the count already
+            record.warnings_count = record
+                .warnings_count
+                .saturating_sub(before - record.warnings.len());
+            if let Some(warning) = &warning {
+                record.warnings.push(warning.clone());
+                record.warnings_count += 1;
+            }
+        };
+        if let Some(record) = self.settings.device_imports.get_mut(device_id) {
+            replace(record);
+        }
+        if let Some(record) = &mut self.settings.last_import {
+            if record.device_id.as_deref() == Some(device_id) {
+                replace(record);
+            }
+        }
+        self.save()
+    }
+
+    /// Registry entry of `device`, recording it on its first scan (`None`
+    /// for devices without a serial number)
+    pub fn register_device(
+        &mut self,
+        device: &KoboDevice,
+    ) -> Result<Option<KnownDevice>, SettingsError> {
+        let Some(serial) = &device.serial_number else {
+            return Ok(None);
+        };
+        if let Some(known) = self.settings.known_devices.get(serial) {
+            return Ok(Some(known.clone()));
+        }
+        let known = KnownDevice::first_seen(serial, device.model.clone());
+        log::info!("Registered device {} as '{}'", serial, known.name);
+        self.settings
+            .known_devices
+            .insert(serial.clone(), known.clone());
+        self.save()?;
+        Ok(Some(known))
+    }
+
+    /// Rename a known device; a blank name restores the default one
+    pub fn rename_device(&mut self, serial: &str, name: &str) -> Result<(), SettingsError> {
+        let known = self
+            .settings
+            .known_devices
+            .get_mut(serial)
+            .ok_or_else(|| SettingsError::UnknownDevice(serial.to_string()))?;
+        known.name = match name.trim() {
+            "" => default_device_name(known.model.as_deref(), serial),
+            name => name.to_string(),
+        };
+        self.save()
+    }
+
+    /// Remove a known device along with its import history
+    pub fn forget_device(&mut self, serial: &str) -> Result<(), SettingsError> {
+        let settings = &mut self.settings;
+        if settings.known_devices.remove(serial).is_none() {
+            return Err(SettingsError::UnknownDevice(serial.to_string()));
+        }
+        settings.device_imports.remove(serial);
+        if settings
+            .last_import
+            .as_ref()
+            .is_some_and(|record| record.device_id.as_deref() == Some(serial))
+        {
+            settings.last_import = None;
+        }
+        self.save()
+    }
+
+    /// Create or overwrite a named export profile
+    ///
+    /// Names are trimmed and matched case-insensitively: saving under an
+    /// existing name overwrites that profile (keeping its original name).
+    /// Saving the active profile also updates `export_config`.
+    pub fn save_export_profile(
+        &mut self,
+        name: &str,
+        config: ExportConfig,
+    ) -> Result<(), SettingsError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(SettingsError::InvalidProfile(
+                "Profile name cannot be empty".to_string(),
+            ));
+        }
+
+        let settings = &mut self.settings;
+        match settings
+            .export_profiles
+            .iter_mut()
+            .find(|p| p.name.eq_ignore_ascii_case(name))
+        {
+            Some(profile) => {
+                profile.config = config.clone();
+                if profile.name == settings.active_profile {
+                    settings.export_config = config;
+                }
+            }
+            None => settings.export_profiles.push(NamedExportProfile {
+                name: name.to_string(),
+                config,
+            }),
+        }
+
+        self.save()
+    }
+
+    /// Delete a named export profile
+    ///
+    /// The last remaining profile cannot be deleted. Deleting the active
+    /// profile makes the first remaining profile active.
+    pub fn delete_export_profile(&mut self, name: &str) -> Result<(), SettingsError> {
+        let settings = &mut self.settings;
+        let index = settings
+            .export_profiles
+            .iter()
+            .position(|p| p.name.eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| SettingsError::InvalidProfile(format!("Profile not found: {}", name)))?;
+
+        if settings.export_profiles.len() == 1 {
+            return Err(SettingsError::InvalidProfile(
+                "Cannot delete the last export profile".to_string(),
+            ));
+        }
+
+        let removed = settings.export_profiles.remove(index);
+        if removed.name == settings.active_profile {
+            let next = &settings.export_profiles[0];
+            settings.active_profile = next.name.clone();
+            settings.export_config = next.config.clone();
+        }
+
+        self.save()
+    }
+
+    /// Path of the backup `save` keeps of the previous settings file
+    fn backup_path(&self) -> PathBuf {
+        self.config_path.with_extension("json.backup")
+    }
+
+    /// Whether a backup exists, when it was written and what differs
+    pub fn backup_info(&self) -> Result<SettingsBackupInfo, SettingsError> {
+        let backup_path = self.backup_path();
+        if !backup_path.exists() {
+            return Ok(SettingsBackupInfo {
+                exists: false,
+                modified_at: None,
+                changes: Vec::new(),
+                changed_sections: Vec::new(),
+            });
+        }
+        let backup = Self::load_from_file(&backup_path)?;
+        let modified_at = fs::metadata(&backup_path)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .map(DateTime::<Utc>::from);
+
+        let to_value = |settings: &AppSettings| {
+            serde_json::to_value(settings).map_err(SettingsError::SerializeError)
+        };
+        let changes = settings_changes(&to_value(&backup)?, &to_value(&self.settings)?);
+        let changed_sections = [
+            SettingsSection::ExportConfig,
+            SettingsSection::UiPreferences,
+            SettingsSection::ExportProfiles,
+        ]
+        .into_iter()
+        .filter(|section| {
+            changes
+                .iter()
+                .any(|c| section.keys().contains(&c.key.as_str()))
+        })
+        .chain((!changes.is_empty()).then_some(SettingsSection::All))
+        .collect();
+        Ok(SettingsBackupInfo {
+            exists: true,
+            modified_at,
+            changes,
+            changed_sections,
+        })
+    }
+
+    /// Take `section` from the backup into the current settings and save
+    ///
+    /// The backup is left as it was, so other sections can still be
+    /// restored from it afterwards.
+    pub fn restore_section(&mut self, section: SettingsSection) -> Result<(), SettingsError> {
+        let backup_path = self.backup_path();
+        if !backup_path.exists() {
+            return Err(SettingsError::NoBackup);
+        }
+        let backup = Self::load_from_file(&backup_path)?;
+        let settings = &mut self.settings;
+        match section {
+            SettingsSection::ExportConfig => settings.export_config = backup.export_config,
+            SettingsSection::UiPreferences => settings.ui_preferences = backup.ui_preferences,
+            SettingsSection::ExportProfiles => {
+                if let Some(active) = backup.find_export_profile(&backup.active_profile) {
+                    settings.export_config = active.config.clone();
+                }
+                settings.export_profiles = backup.export_profiles;
+                settings.active_profile = backup.active_profile;
+            }
+            SettingsSection::All => *settings = backup,
+        }
+        settings.normalize_export_profiles();
+        log::info!("[Settings] Restored {:?} from the backup", section);
+        self.write(false)
+    }
+
     /// Reset settings to defaults
     pub fn reset_to_defaults(&mut self) -> Result<(), SettingsError> {
         self.settings = AppSettings::default();
@@ -373,6 +1159,57 @@ impl SettingsManager {
     pub fn config_path(&self) -> &Path {
         &self.config_path
     }
+
+    /// Whether settings changes are persisted to disk
+    pub fn persistence_available(&self) -> bool {
+        self.persistence_available
+    }
+
+    /// Get the current health of the settings storage
+    pub fn health(&self) -> SettingsHealth {
+        SettingsHealth {
+            persistence_available: self.persistence_available,
+            config_path: self.config_path.to_string_lossy().to_string(),
+        }
+    }
+}
+
+/// Settings manager shared across commands through Tauri managed state
+///
+/// The manager is created lazily on first use so that a missing home
+/// directory surfaces as a command error instead of a startup failure.
+#[derive(Default)]
+pub struct SettingsState {
+    manager: Mutex<Option<SettingsManager>>,
+}
+
+impl SettingsState {
+    /// Create a state wrapping an existing manager (useful for testing)
+    pub fn from_manager(manager: SettingsManager) -> Self {
+        Self {
+            manager: Mutex::new(Some(manager)),
+        }
+    }
+
+    /// Swap in another manager (switching profiles)
+    pub fn replace(&self, manager: SettingsManager) {
+        *self.manager.lock().unwrap_or_else(|e| e.into_inner()) = Some(manager);
+    }
+
+    /// Run a closure against the shared manager, creating it if needed
+    pub fn with_manager<T>(
+        &self,
+        f: impl FnOnce(&mut SettingsManager) -> Result<T, SettingsError>,
+    ) -> Result<T, SettingsError> {
+        let mut guard = self.manager.lock().unwrap_or_else(|e| e.into_inner());
+        if guard.is_none() {
+            *guard = Some(SettingsManager::new()?);
+        }
+        match guard.as_mut() {
+            Some(manager) => f(manager),
+            None => Err(SettingsError::HomeNotFound),
+        }
+    }
 }
 
 /// Settings-related errors
@@ -386,6 +1223,12 @@ pub enum SettingsError {
     ParseError(serde_json::Error),
     /// Serialize error
     SerializeError(serde_json::Error),
+    /// Invalid export profile operation
+    InvalidProfile(String),
+    /// No known device has this serial number
+    UnknownDevice(String),
+    /// There is no settings backup to restore from
+    NoBackup,
 }
 
 impl std::fmt::Display for SettingsError {
@@ -395,6 +1238,9 @@ impl std::fmt::Display for SettingsError {
             SettingsError::IoError(e) => write!(f, "IO error: {}", e),
             SettingsError::ParseError(e) => write!(f, "Parse error: {}", e),
             SettingsError::SerializeError(e) => write!(f, "Serialize error: {}", e),
+            SettingsError::InvalidProfile(msg) => write!(f, "Invalid profile: {}", msg),
+            SettingsError::UnknownDevice(serial) => write!(f, "Unknown device: {}", serial),
+            SettingsError::NoBackup => write!(f, "No settings backup to restore from"),
         }
     }
 }
@@ -466,8 +1312,50 @@ mod tests {
                 date_last_read: false,
                 language: false,
                 description: true,
+                stats: false,
+                series: false,
+                vocabulary: false,
+                loan: false,
+                book_notes: false,
+                book_notes_raw: false,
+                order: Vec::new(),
             },
             date_format: DateFormat::Iso8601,
+            folder_pattern: String::new(),
+            write_index: false,
+            atom_feed: false,
+            feed_limit: 0,
+            highlight_separator: HighlightSeparator::None,
+            compact: false,
+            show_location: true,
+            excluded_chapter_patterns: Vec::new(),
+            max_concurrent_writes: 1,
+            bullet_indentation: BulletIndentation::Tab,
+            write_mode: ExportWriteMode::Overwrite,
+            journal_layout: JournalLayout::Monthly,
+            git_commit_after_export: false,
+            commit_message_template: String::new(),
+            write_sidecars: false,
+            tabular: TabularOptions::default(),
+            markdown_template: String::new(),
+            ascii_filenames: false,
+            highlight_style: HighlightStyle::Blockquote,
+            redaction: RedactionPolicy::Exclude,
+            redaction_markers: vec![DEFAULT_REDACTION_MARKER.to_string()],
+            group_pocket_articles: false,
+            bom: false,
+            line_endings: LineEndings::Lf,
+            group_by_color: false,
+            color_labels: BTreeMap::new(),
+            cloud_settle_ms: 0,
+            transactional: false,
+            filename_pattern: String::new(),
+            format: ExportFormat::Markdown,
+            citation_notes: false,
+            include_toc: false,
+            snapshot_exports: false,
+            snapshot_keep: DEFAULT_SNAPSHOT_KEEP,
+            snapshot_max_mb: DEFAULT_SNAPSHOT_MAX_MB,
         };
 
         manager.set_export_config(new_config.clone()).unwrap();
@@ -492,6 +1380,9 @@ mod tests {
             show_onboarding: false,
             library_view_mode: ViewMode::List,
             library_sort: SortPreference::Author,
+            show_invalid_devices: false,
+            device_settle_scans: DEFAULT_DEVICE_SETTLE_SCANS,
+            auto_import_on_connect: false,
         };
 
         manager.set_ui_preferences(new_prefs).unwrap();
@@ -504,6 +1395,7 @@ mod tests {
             manager.get().ui_preferences.library_view_mode,
             ViewMode::List
         );
+        assert!(!manager.get().ui_preferences.auto_import_on_connect);
     }
 
     #[test]
@@ -518,6 +1410,12 @@ mod tests {
             device_id: Some("Kobo123".to_string()),
             books_count: 5,
             highlights_count: 42,
+            duration_ms: 0,
+            warnings_count: 0,
+            warnings: Vec::new(),
+            filtered_count: 0,
+            metrics: MetricsSummary::default(),
+            schema: None,
         };
 
         manager.set_last_import(record.clone()).unwrap();
@@ -529,6 +1427,34 @@ mod tests {
         assert_eq!(saved_record.highlights_count, 42);
     }
 
+    #[test]
+    fn test_replace_stale_data_warning_with_reset_count() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager =
+            SettingsManager::with_path(temp_dir.path().join("settings.json")).unwrap();
+        let book = Book::new("book1".to_string(), "Book".to_string(), "A".to_string());
+        manager
+            .set_last_import(LastImportRecord {
+                timestamp: "2025-01-29T14:00:00Z".to_string(),
+                device_id: Some("Kobo123".to_string()),
+                books_count: 1,
+                highlights_count: 1,
+                duration_ms: 0,
+                // Reset after the warning was shown
+                warnings_count: 0,
+                warnings: vec![ImportWarning::possibly_stale_data(&book, Utc::now())],
+                filtered_count: 0,
+                metrics: MetricsSummary::default(),
+                schema: None,
+            })
+            .unwrap();
+
+        manager.replace_stale_data_warning("Kobo123", None).unwrap();
+        let record = manager.get().last_import.as_ref().unwrap();
+        assert!(record.warnings.is_empty());
+        assert_eq!(record.warnings_count, 0);
+    }
+
     #[test]
     fn test_reset_to_defaults() {
         let temp_dir = TempDir::new().unwrap();
@@ -544,6 +1470,12 @@ mod tests {
             device_id: None,
             books_count: 1,
             highlights_count: 1,
+            duration_ms: 0,
+            warnings_count: 0,
+            warnings: Vec::new(),
+            filtered_count: 0,
+            metrics: MetricsSummary::default(),
+            schema: None,
         });
 
         // Reset
@@ -633,12 +1565,324 @@ mod tests {
     fn test_export_config_default() {
         let config = ExportConfig::default();
 
-        assert!(config.export_path.contains("Documents"));
         assert!(config.export_path.contains("Kobo Highlights"));
+        assert_eq!(PathBuf::from(&config.export_path), default_export_dir());
         assert!(config.metadata.author);
         assert_eq!(config.date_format, DateFormat::DdMonthYyyy);
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_read_only_config_dir_degraded_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join("config");
+        fs::create_dir_all(&config_dir).unwrap();
+        let config_path = config_dir.join("settings.json");
+
+        fs::set_permissions(&config_dir, fs::Permissions::from_mode(0o555)).unwrap();
+        if fs::write(config_dir.join("probe"), b"").is_ok() {
+            // Running with privileges that ignore permissions (e.g. root)
+            fs::set_permissions(&config_dir, fs::Permissions::from_mode(0o755)).unwrap();
+            return;
+        }
+
+        let mut manager = SettingsManager::with_path(config_path.clone()).unwrap();
+        assert!(!manager.persistence_available());
+        assert!(!manager.health().persistence_available);
+
+        let start = std::time::Instant::now();
+        manager
+            .set_last_import(LastImportRecord {
+                timestamp: "2025-01-29T14:00:00Z".to_string(),
+                device_id: None,
+                books_count: 3,
+                highlights_count: 7,
+                duration_ms: 0,
+                warnings_count: 0,
+                warnings: Vec::new(),
+                filtered_count: 0,
+                metrics: MetricsSummary::default(),
+                schema: None,
+            })
+            .unwrap();
+        manager.get_mut().ui_preferences.theme = ThemePreference::Dark;
+        manager.save().unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_millis(100));
+
+        // Changes live in memory only
+        assert_eq!(manager.get().last_import.as_ref().unwrap().books_count, 3);
+        assert_eq!(manager.get().ui_preferences.theme, ThemePreference::Dark);
+        assert!(!config_path.exists());
+
+        fs::set_permissions(&config_dir, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_writable_config_dir_reports_persistence() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("nested").join("settings.json");
+
+        let manager = SettingsManager::with_path(config_path.clone()).unwrap();
+        assert!(manager.persistence_available());
+        assert_eq!(
+            manager.health().config_path,
+            config_path.to_string_lossy().to_string()
+        );
+        // Probe file must not be left behind
+        assert!(!temp_dir.path().join("nested").join(".write_probe").exists());
+    }
+
+    #[test]
+    fn test_settings_state_shares_manager() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("settings.json");
+        let state = SettingsState::from_manager(SettingsManager::with_path(config_path).unwrap());
+
+        state
+            .with_manager(|m| {
+                m.get_mut().ui_preferences.theme = ThemePreference::Light;
+                Ok(())
+            })
+            .unwrap();
+
+        let theme = state
+            .with_manager(|m| Ok(m.get().ui_preferences.theme.clone()))
+            .unwrap();
+        assert_eq!(theme, ThemePreference::Light);
+    }
+
+    fn create_profile_config(path: &str) -> ExportConfig {
+        ExportConfig {
+            export_path: path.to_string(),
+            ..ExportConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_legacy_settings_migrated_to_default_profile() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("settings.json");
+
+        // Settings written before profiles existed
+        let mut legacy = serde_json::to_value(AppSettings::default()).unwrap();
+        legacy["exportConfig"]["exportPath"] = serde_json::json!("/legacy/path");
+        legacy.as_object_mut().unwrap().remove("exportProfiles");
+        legacy.as_object_mut().unwrap().remove("activeProfile");
+        fs::write(&config_path, legacy.to_string()).unwrap();
+
+        let manager = SettingsManager::with_path(config_path).unwrap();
+        let settings = manager.get();
+
+        assert_eq!(settings.export_profiles.len(), 1);
+        assert_eq!(settings.export_profiles[0].name, DEFAULT_PROFILE_NAME);
+        assert_eq!(
+            settings.export_profiles[0].config.export_path,
+            "/legacy/path"
+        );
+        assert_eq!(settings.active_profile, DEFAULT_PROFILE_NAME);
+    }
+
+    #[test]
+    fn test_device_imports_rekeyed_by_serial() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("settings.json");
+
+        let record = |device_id: &str, timestamp: &str| {
+            serde_json::json!({
+                "timestamp": timestamp,
+                "deviceId": device_id,
+                "booksCount": 1,
+                "highlightsCount": 2
+            })
+        };
+        let legacy_id =
+            "N418000000001,4.1.15,4.38.21908,4.1.15,4.1.15,00000000-0000-0000-0000-000000000386";
+        let mut settings = serde_json::to_value(AppSettings::default()).unwrap();
+        settings["deviceImports"] = serde_json::json!({
+            legacy_id: record(legacy_id, "2024-01-01T00:00:00Z"),
+            "N418000000002,4.1.15": record("N418000000002,4.1.15", "2024-01-01T00:00:00Z"),
+            "N418000000002": record("N418000000002", "2025-01-01T00:00:00Z"),
+        });
+        settings["lastImport"] = record(legacy_id, "2024-01-01T00:00:00Z");
+        fs::write(&config_path, settings.to_string()).unwrap();
+
+        let manager = SettingsManager::with_path(config_path).unwrap();
+        let settings = manager.get();
+        let keys: Vec<&str> = settings.device_imports.keys().map(String::as_str).collect();
+        assert_eq!(keys, vec!["N418000000001", "N418000000002"]);
+        let first = &settings.device_imports["N418000000001"];
+        assert_eq!(first.device_id.as_deref(), Some("N418000000001"));
+        assert_eq!(
+            settings.device_imports["N418000000002"].timestamp,
+            "2025-01-01T00:00:00Z"
+        );
+        assert_eq!(
+            settings.last_import.as_ref().unwrap().device_id.as_deref(),
+            Some("N418000000001")
+        );
+    }
+
+    #[test]
+    fn test_restore_export_config_section_keeps_newer_ui_preferences() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("settings.json");
+        let mut manager = SettingsManager::with_path(config_path.clone()).unwrap();
+        assert!(!manager.backup_info().unwrap().exists);
+        assert!(matches!(
+            manager.restore_section(SettingsSection::All),
+            Err(SettingsError::NoBackup)
+        ));
+
+        manager
+            .set_export_config(create_profile_config("/exports/before"))
+            .unwrap();
+        // Saving backs up the settings above
+        manager
+            .set_export_config(create_profile_config("/exports/after"))
+            .unwrap();
+        manager.get_mut().ui_preferences.theme = ThemePreference::Dark;
+
+        let info = manager.backup_info().unwrap();
+        assert!(info.exists && info.modified_at.is_some());
+        let change = |key: &str| info.changes.iter().find(|c| c.key == key).cloned();
+        assert_eq!(change("exportConfig").unwrap().changed_values, 1);
+        assert_eq!(change("uiPreferences").unwrap().changed_values, 1);
+        assert_eq!(change("lastImport"), None);
+        assert!(info
+            .changed_sections
+            .contains(&SettingsSection::ExportConfig));
+        assert!(info
+            .changed_sections
+            .contains(&SettingsSection::UiPreferences));
+
+        manager
+            .restore_section(SettingsSection::ExportConfig)
+            .unwrap();
+        for settings in [
+            manager.get().clone(),
+            SettingsManager::load_from_file(&config_path).unwrap(),
+        ] {
+            assert_eq!(settings.export_config.export_path, "/exports/before");
+            assert_eq!(settings.ui_preferences.theme, ThemePreference::Dark);
+        }
+
+        // The backup still holds what the other sections looked like
+        manager
+            .restore_section(SettingsSection::UiPreferences)
+            .unwrap();
+        assert_ne!(manager.get().ui_preferences.theme, ThemePreference::Dark);
+        assert_eq!(manager.get().export_config.export_path, "/exports/before");
+    }
+
+    #[test]
+    fn test_count_changes_matches_keys_and_positions() {
+        let backup = serde_json::json!({ "a": { "b": 1, "c": [1, 2, 3] }, "d": "x" });
+        let current = serde_json::json!({ "a": { "b": 2, "c": [1, 2] }, "e": true });
+        assert_eq!(
+            settings_changes(&backup, &current),
+            vec![
+                SettingsChange {
+                    key: "a".into(),
+                    changed_values: 2
+                },
+                SettingsChange {
+                    key: "d".into(),
+                    changed_values: 1
+                },
+                SettingsChange {
+                    key: "e".into(),
+                    changed_values: 1
+                },
+            ]
+        );
+        assert_eq!(count_changes(&backup, &backup), 0);
+    }
+
+    #[test]
+    fn test_save_export_profile_collision_overwrites() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager =
+            SettingsManager::with_path(temp_dir.path().join("settings.json")).unwrap();
+
+        manager
+            .save_export_profile("Obsidian", create_profile_config("/vault"))
+            .unwrap();
+        manager
+            .save_export_profile(" obsidian ", create_profile_config("/vault2"))
+            .unwrap();
+
+        let profiles = &manager.get().export_profiles;
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[1].name, "Obsidian");
+        assert_eq!(profiles[1].config.export_path, "/vault2");
+
+        assert!(manager
+            .save_export_profile("  ", create_profile_config("/x"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_save_active_profile_updates_export_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager =
+            SettingsManager::with_path(temp_dir.path().join("settings.json")).unwrap();
+
+        manager
+            .save_export_profile("default", create_profile_config("/archive"))
+            .unwrap();
+
+        assert_eq!(manager.get().export_config.export_path, "/archive");
+    }
+
+    #[test]
+    fn test_delete_export_profile() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager =
+            SettingsManager::with_path(temp_dir.path().join("settings.json")).unwrap();
+        manager
+            .save_export_profile("Archive", create_profile_config("/archive"))
+            .unwrap();
+
+        // Deleting the active profile activates the first remaining one
+        manager.delete_export_profile(DEFAULT_PROFILE_NAME).unwrap();
+        assert_eq!(manager.get().active_profile, "Archive");
+        assert_eq!(manager.get().export_config.export_path, "/archive");
+
+        // The last profile can't be deleted, unknown names are rejected
+        assert!(manager.delete_export_profile("Archive").is_err());
+        assert!(manager.delete_export_profile("Missing").is_err());
+    }
+
+    #[test]
+    fn test_export_profiles_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("settings.json");
+        {
+            let mut manager = SettingsManager::with_path(config_path.clone()).unwrap();
+            manager
+                .save_export_profile("Vault", create_profile_config("/vault"))
+                .unwrap();
+        }
+
+        let manager = SettingsManager::with_path(config_path.clone()).unwrap();
+        assert_eq!(manager.get().export_profiles.len(), 2);
+        assert_eq!(
+            manager
+                .get()
+                .find_export_profile("vault")
+                .unwrap()
+                .config
+                .export_path,
+            "/vault"
+        );
+
+        let json = fs::read_to_string(config_path).unwrap();
+        assert!(json.contains("exportProfiles"));
+        assert!(json.contains("activeProfile"));
+    }
+
     #[test]
     fn test_frontend_payload_deserialization() {
         // This JSON represents exactly what the Frontend sends (based on our analysis)
@@ -675,4 +1919,21 @@ mod tests {
             Err(e) => panic!("Deserialization failed: {}", e),
         }
     }
+
+    #[test]
+    fn test_export_path_bookmark_serialization() {
+        let mut settings = AppSettings::default();
+        let json = serde_json::to_string(&settings).unwrap();
+        assert!(!json.contains("exportPathBookmark"));
+
+        settings.export_path_bookmark = Some(vec![0, 98, 111, 111, 107, 255]);
+        let json = serde_json::to_string(&settings).unwrap();
+        assert!(json.contains("\"exportPathBookmark\":[0,98,111,111,107,255]"));
+
+        let deserialized: AppSettings = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            deserialized.export_path_bookmark,
+            settings.export_path_bookmark
+        );
+    }
 }
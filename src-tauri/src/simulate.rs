@@ -0,0 +1,499 @@
+//! Simulated device events for frontend work without a Kobo
+//!
+//! `simulate_device_event` (debug builds only) runs the real detection,
+//! import and export code against devices built with `crate::fixtures`, so
+//! the device, progress and error flows of the UI can be exercised anywhere.
+//! Each scenario works in its own scratch folder: the device `connect`
+//! creates lives until `disconnect` (or the app exits, see
+//! `SimulatorState::clear`), every other folder is removed before the
+//! scenario returns.
+
+use crate::commands::import_device_with_events;
+use crate::covers::CoverExtractor;
+use crate::db::kobo::KoboDatabase;
+use crate::device::monitor::{DeviceDetectedEvent, DeviceDisconnectedEvent};
+use crate::device::DeviceDetector;
+use crate::export::{EventSink, MarkdownExporter};
+use crate::fixtures;
+use crate::models::KoboDevice;
+use crate::settings::{AppSettings, SettingsManager, SettingsState};
+use crate::utils::cancel::CancellationToken;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Serial number of simulated devices
+pub const SIMULATED_SERIAL: &str = "SIMULATED";
+
+/// Books in the `import_slow` database
+pub const SLOW_IMPORT_BOOKS: usize = 300;
+
+/// Highlights per book in the `import_slow` database
+pub const SLOW_IMPORT_HIGHLIGHTS: usize = 5;
+
+/// Pause after each "import-progress" of `import_slow`, so the events
+/// stream instead of arriving all at once
+pub const SLOW_IMPORT_DELAY: Duration = Duration::from_millis(10);
+
+/// What `simulate_device_event` plays
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SimulationScenario {
+    /// A mock device appears ("device-detected")
+    Connect,
+    /// The connected mock device goes away ("device-disconnected")
+    Disconnect,
+    /// Import of a large generated database ("import-progress" per book)
+    ImportSlow,
+    /// Import of a corrupted database (fails like the real import)
+    ImportError,
+    /// Export into a read-only folder (every book fails)
+    ExportError,
+}
+
+impl SimulationScenario {
+    fn name(self) -> &'static str {
+        match self {
+            SimulationScenario::Connect => "connect",
+            SimulationScenario::Disconnect => "disconnect",
+            SimulationScenario::ImportSlow => "import_slow",
+            SimulationScenario::ImportError => "import_error",
+            SimulationScenario::ExportError => "export_error",
+        }
+    }
+}
+
+/// Result of a scenario
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationReport {
+    pub scenario: SimulationScenario,
+    /// Names of the events sent, in order
+    pub events: Vec<String>,
+}
+
+/// Scratch folder removed when dropped
+#[derive(Debug)]
+struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    fn create(root: &Path, scenario: SimulationScenario) -> Result<Self, String> {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        let path = root.join(format!(
+            "khi-simulate-{}-{}-{}",
+            scenario.name(),
+            std::process::id(),
+            RUNS.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path).map_err(|e| format!("Cannot create {:?}: {}", path, e))?;
+        Ok(ScratchDir(path))
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.0) {
+            log::warn!("[Simulate] Failed to remove {:?}: {}", self.0, e);
+        }
+    }
+}
+
+/// Simulator state kept between commands: the connected mock device
+#[derive(Debug)]
+pub struct SimulatorState {
+    root: PathBuf,
+    progress_delay: Duration,
+    connected: Mutex<Option<ScratchDir>>,
+}
+
+impl Default for SimulatorState {
+    fn default() -> Self {
+        Self::new(std::env::temp_dir(), SLOW_IMPORT_DELAY)
+    }
+}
+
+impl SimulatorState {
+    /// Simulator creating its scratch folders under `root`
+    pub fn new(root: PathBuf, progress_delay: Duration) -> Self {
+        Self {
+            root,
+            progress_delay,
+            connected: Mutex::new(None),
+        }
+    }
+
+    /// Remove the connected mock device, if any; managed state isn't
+    /// dropped when the app exits, so call this on exit
+    pub fn clear(&self) {
+        self.connected
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+    }
+}
+
+/// Forwards events, recording their names and pacing import progress
+struct RecordingSink<'a> {
+    inner: &'a dyn EventSink,
+    progress_delay: Duration,
+    events: RefCell<Vec<String>>,
+}
+
+impl EventSink for RecordingSink<'_> {
+    fn send(&self, event: &str, payload: serde_json::Value) {
+        self.inner.send(event, payload);
+        self.events.borrow_mut().push(event.to_string());
+        if event == crate::commands::IMPORT_PROGRESS && !self.progress_delay.is_zero() {
+            std::thread::sleep(self.progress_delay);
+        }
+    }
+}
+
+/// Reject simulations outside development builds
+pub fn require_dev_build(debug_build: bool) -> Result<(), String> {
+    if debug_build {
+        Ok(())
+    } else {
+        Err("Device simulation is only available in development builds".to_string())
+    }
+}
+
+/// Play `scenario`, sending its events to `sink`
+///
+/// Fails the way the real command would (`import_error`), or when the
+/// fixtures can't be written.
+pub fn run_scenario(
+    scenario: SimulationScenario,
+    state: &SimulatorState,
+    sink: &dyn EventSink,
+) -> Result<SimulationReport, String> {
+    log::info!("[Simulate] Running {}", scenario.name());
+    let recorder = RecordingSink {
+        inner: sink,
+        progress_delay: state.progress_delay,
+        events: RefCell::new(Vec::new()),
+    };
+    match scenario {
+        SimulationScenario::Connect => connect(state, &recorder)?,
+        SimulationScenario::Disconnect => disconnect(state, &recorder),
+        SimulationScenario::ImportSlow => import(state, scenario, &recorder)?,
+        SimulationScenario::ImportError => import(state, scenario, &recorder)?,
+        SimulationScenario::ExportError => export_error(state, &recorder)?,
+    }
+    Ok(SimulationReport {
+        scenario,
+        events: recorder.events.into_inner(),
+    })
+}
+
+fn connect(state: &SimulatorState, sink: &dyn EventSink) -> Result<(), String> {
+    let scratch = ScratchDir::create(&state.root, SimulationScenario::Connect)?;
+    let device = mock_device(&scratch.0, fixtures::TWO_BOOKS_DATA)?;
+    // A second connect replaces (and removes) the earlier device
+    *state.connected.lock().unwrap_or_else(|e| e.into_inner()) = Some(scratch);
+    send(sink, "device-detected", &DeviceDetectedEvent { device });
+    Ok(())
+}
+
+fn disconnect(state: &SimulatorState, sink: &dyn EventSink) {
+    let connected = state
+        .connected
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+    if connected.is_none() {
+        log::info!("[Simulate] No simulated device connected");
+    }
+    drop(connected);
+    send(sink, "device-disconnected", &DeviceDisconnectedEvent);
+}
+
+/// `import_slow` and `import_error`, through the real import with throwaway
+/// settings and cover cache
+fn import(
+    state: &SimulatorState,
+    scenario: SimulationScenario,
+    sink: &dyn EventSink,
+) -> Result<(), String> {
+    let scratch = ScratchDir::create(&state.root, scenario)?;
+    let device = if scenario == SimulationScenario::ImportError {
+        corrupted_device(&scratch.0)?
+    } else {
+        let data = fixtures::generated_books_data(SLOW_IMPORT_BOOKS, SLOW_IMPORT_HIGHLIGHTS);
+        mock_device(&scratch.0, &data)?
+    };
+    let settings = SettingsManager::with_path(scratch.0.join("settings.json"))
+        .map_err(|e| format!("Failed to create settings: {}", e))?;
+    let extractor = CoverExtractor::new(scratch.0.join("covers"));
+    let (books, _) = import_device_with_events(
+        &SettingsState::from_manager(settings),
+        &device,
+        false,
+        &extractor,
+        sink,
+        &CancellationToken::new(),
+    )?;
+    log::info!("[Simulate] Imported {} books", books.len());
+    Ok(())
+}
+
+fn export_error(state: &SimulatorState, sink: &dyn EventSink) -> Result<(), String> {
+    let scratch = ScratchDir::create(&state.root, SimulationScenario::ExportError)?;
+    let database = fixtures::create_kobo_volume(
+        &scratch.0.join("KOBOeReader"),
+        SIMULATED_SERIAL,
+        fixtures::TWO_BOOKS_DATA,
+    )
+    .map_err(|e| format!("Failed to create the mock device: {}", e))?;
+    let books = KoboDatabase::new(&database)
+        .map_err(|e| e.to_string())?
+        .extract_books_with_highlights()
+        .map_err(|e| e.to_string())?;
+
+    let export_dir = scratch.0.join("export");
+    block_export_dir(&export_dir)?;
+    let mut config = AppSettings::default().export_config;
+    config.export_path = export_dir.to_string_lossy().to_string();
+    let report =
+        MarkdownExporter::new(export_dir.clone()).export_books_with_events(&books, &config, sink);
+    log::info!(
+        "[Simulate] Export failed for {} of {} books",
+        report.failures.len(),
+        report.total_books
+    );
+    set_readonly(&export_dir, false);
+    Ok(())
+}
+
+/// Write a Kobo volume holding `data` under `scratch` and detect it
+fn mock_device(scratch: &Path, data: &str) -> Result<KoboDevice, String> {
+    let volume = scratch.join("KOBOeReader");
+    fixtures::create_kobo_volume(&volume, SIMULATED_SERIAL, data)
+        .map_err(|e| format!("Failed to create the mock device: {}", e))?;
+    detect(&volume)
+}
+
+/// A mock device whose database is garbage
+fn corrupted_device(scratch: &Path) -> Result<KoboDevice, String> {
+    let volume = scratch.join("KOBOeReader");
+    let database = fixtures::create_kobo_volume(&volume, SIMULATED_SERIAL, "")
+        .map_err(|e| format!("Failed to create the mock device: {}", e))?;
+    fs::write(&database, b"not a sqlite database, just garbage bytes")
+        .map_err(|e| format!("Failed to corrupt {:?}: {}", database, e))?;
+    detect(&volume)
+}
+
+fn detect(volume: &Path) -> Result<KoboDevice, String> {
+    DeviceDetector::new(volume.parent().unwrap_or(volume).to_path_buf())
+        .check_kobo_device(volume)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("No device detected at {:?}", volume))
+}
+
+/// Make `dir` a folder nothing can be written to
+///
+/// Permissions don't stop privileged accounts, so when a probe write still
+/// succeeds the folder is replaced by a regular file of the same name.
+fn block_export_dir(dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Cannot create {:?}: {}", dir, e))?;
+    set_readonly(dir, true);
+    let probe = dir.join(".khi-simulate");
+    if fs::write(&probe, b"ok").is_err() {
+        return Ok(());
+    }
+    log::info!(
+        "[Simulate] {:?} is still writable, blocking it with a file",
+        dir
+    );
+    set_readonly(dir, false);
+    fs::remove_dir_all(dir)
+        .and_then(|_| fs::write(dir, b"not a folder"))
+        .map_err(|e| format!("Cannot block {:?}: {}", dir, e))
+}
+
+fn set_readonly(path: &Path, readonly: bool) {
+    let Ok(metadata) = fs::metadata(path) else {
+        return;
+    };
+    let mut permissions = metadata.permissions();
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(readonly);
+    if let Err(e) = fs::set_permissions(path, permissions) {
+        log::warn!(
+            "[Simulate] Failed to change permissions of {:?}: {}",
+            path,
+            e
+        );
+    }
+}
+
+fn send<T: Serialize>(sink: &dyn EventSink, event: &str, payload: &T) {
+    match serde_json::to_value(payload) {
+        Ok(value) => sink.send(event, value),
+        Err(e) => log::error!("[Simulate] Failed to serialize {} event: {}", event, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::IMPORT_PROGRESS;
+    use crate::export::{ExportBookStatus, ExportProgressEvent, ExportReport};
+    use crate::models::ImportProgress;
+    use tempfile::TempDir;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: RefCell<Vec<(String, serde_json::Value)>>,
+    }
+
+    impl EventSink for Recorder {
+        fn send(&self, event: &str, payload: serde_json::Value) {
+            self.events.borrow_mut().push((event.to_string(), payload));
+        }
+    }
+
+    impl Recorder {
+        fn names(&self) -> Vec<String> {
+            self.events
+                .borrow()
+                .iter()
+                .map(|(n, _)| n.clone())
+                .collect()
+        }
+
+        fn payloads<T: serde::de::DeserializeOwned>(&self, event: &str) -> Vec<T> {
+            self.events
+                .borrow()
+                .iter()
+                .filter(|(n, _)| n == event)
+                .map(|(_, p)| serde_json::from_value(p.clone()).unwrap())
+                .collect()
+        }
+    }
+
+    fn simulator() -> (TempDir, SimulatorState) {
+        let temp = TempDir::new().unwrap();
+        let state = SimulatorState::new(temp.path().to_path_buf(), Duration::ZERO);
+        (temp, state)
+    }
+
+    fn is_empty(dir: &Path) -> bool {
+        fs::read_dir(dir).unwrap().next().is_none()
+    }
+
+    #[test]
+    fn test_connect_and_disconnect() {
+        let (temp, state) = simulator();
+        let sink = Recorder::default();
+
+        let report = run_scenario(SimulationScenario::Connect, &state, &sink).unwrap();
+        assert_eq!(report.events, vec!["device-detected"]);
+        let detected: Vec<DeviceDetectedEvent> = sink.payloads("device-detected");
+        let device = &detected[0].device;
+        assert!(device.is_valid);
+        assert_eq!(device.serial_number.as_deref(), Some(SIMULATED_SERIAL));
+        assert!(Path::new(&device.path).join(".kobo").is_dir());
+        // The device can be imported like a real one
+        let books = crate::commands::extract_device_books(device, false, false).unwrap();
+        assert_eq!(books.len(), 2);
+
+        let report = run_scenario(SimulationScenario::Disconnect, &state, &sink).unwrap();
+        assert_eq!(report.events, vec!["device-disconnected"]);
+        assert_eq!(sink.names(), vec!["device-detected", "device-disconnected"]);
+        assert!(!Path::new(&device.path).exists());
+        assert!(is_empty(temp.path()));
+
+        // Disconnecting again still sends the event
+        let report = run_scenario(SimulationScenario::Disconnect, &state, &sink).unwrap();
+        assert_eq!(report.events, vec!["device-disconnected"]);
+    }
+
+    #[test]
+    fn test_reconnect_replaces_device() {
+        let (temp, state) = simulator();
+        let sink = Recorder::default();
+        run_scenario(SimulationScenario::Connect, &state, &sink).unwrap();
+        run_scenario(SimulationScenario::Connect, &state, &sink).unwrap();
+
+        let detected: Vec<DeviceDetectedEvent> = sink.payloads("device-detected");
+        assert!(!Path::new(&detected[0].device.path).exists());
+        assert!(Path::new(&detected[1].device.path).exists());
+        assert_eq!(fs::read_dir(temp.path()).unwrap().count(), 1);
+
+        state.clear();
+        assert!(is_empty(temp.path()));
+    }
+
+    #[test]
+    fn test_import_slow_streams_progress() {
+        let (temp, state) = simulator();
+        let sink = Recorder::default();
+
+        let report = run_scenario(SimulationScenario::ImportSlow, &state, &sink).unwrap();
+        assert_eq!(report.events.len(), SLOW_IMPORT_BOOKS);
+        assert!(report.events.iter().all(|e| e == IMPORT_PROGRESS));
+
+        let progress: Vec<ImportProgress> = sink.payloads(IMPORT_PROGRESS);
+        assert_eq!(progress[0].books_processed, 1);
+        let last = progress.last().unwrap();
+        assert_eq!(last.books_processed, SLOW_IMPORT_BOOKS);
+        assert_eq!(
+            last.highlights_found,
+            SLOW_IMPORT_BOOKS * SLOW_IMPORT_HIGHLIGHTS
+        );
+        assert_eq!(last.percentage, 100.0);
+        assert!(is_empty(temp.path()));
+    }
+
+    #[test]
+    fn test_import_error_fails_like_a_real_import() {
+        let (temp, state) = simulator();
+        let sink = Recorder::default();
+
+        let error = run_scenario(SimulationScenario::ImportError, &state, &sink).unwrap_err();
+        assert!(error.starts_with("Failed to open database"), "{}", error);
+        assert!(sink.names().is_empty());
+        assert!(is_empty(temp.path()));
+    }
+
+    #[test]
+    fn test_export_error_fails_every_book() {
+        let (temp, state) = simulator();
+        let sink = Recorder::default();
+
+        let report = run_scenario(SimulationScenario::ExportError, &state, &sink).unwrap();
+        assert_eq!(
+            report.events,
+            vec![
+                "export-started",
+                "export-progress",
+                "export-progress",
+                "export-finished"
+            ]
+        );
+        let progress: Vec<ExportProgressEvent> = sink.payloads("export-progress");
+        assert!(progress
+            .iter()
+            .all(|p| p.status == ExportBookStatus::Failed && p.error.is_some()));
+        let finished: Vec<ExportReport> = sink.payloads("export-finished");
+        assert_eq!(finished[0].failures.len(), 2);
+        assert!(finished[0].exported_files.is_empty());
+        assert!(is_empty(temp.path()));
+    }
+
+    #[test]
+    fn test_rejected_in_release_builds() {
+        assert!(require_dev_build(true).is_ok());
+        assert_eq!(
+            require_dev_build(false).unwrap_err(),
+            "Device simulation is only available in development builds"
+        );
+        let scenario: SimulationScenario = serde_json::from_str("\"import_slow\"").unwrap();
+        assert_eq!(scenario, SimulationScenario::ImportSlow);
+    }
+}
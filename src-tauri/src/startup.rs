@@ -0,0 +1,293 @@
+//! Safe-mode startup: capture component failures instead of panicking
+//!
+//! Each startup step records problems into a `StartupReport` kept in managed
+//! state, so the window always comes up and the frontend can explain what
+//! went wrong via `get_startup_report`.
+
+use crate::export::staging::remove_orphaned_staging;
+use crate::settings::SettingsState;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A startup component that failed or had to be recovered
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StartupIssue {
+    /// Component name ("logger", "settings", "library", "monitor")
+    pub component: String,
+    pub message: String,
+    /// Where a corrupted store was moved to, if it was quarantined
+    pub backup_path: Option<String>,
+}
+
+/// Outcome of application startup
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StartupReport {
+    /// True when any component failed and the app runs with reduced features
+    pub safe_mode: bool,
+    pub issues: Vec<StartupIssue>,
+}
+
+impl StartupReport {
+    /// Record a failed component and switch to safe mode
+    pub fn record(
+        &mut self,
+        component: &str,
+        message: impl Into<String>,
+        backup_path: Option<&Path>,
+    ) {
+        let message = message.into();
+        log::warn!("[Startup] {} failed: {}", component, message);
+        self.safe_mode = true;
+        self.issues.push(StartupIssue {
+            component: component.to_string(),
+            message,
+            backup_path: backup_path.map(|p| p.to_string_lossy().to_string()),
+        });
+    }
+}
+
+/// Managed state holding the startup report
+#[derive(Default)]
+pub struct StartupState {
+    report: Mutex<StartupReport>,
+}
+
+impl StartupState {
+    pub fn new(report: StartupReport) -> Self {
+        Self {
+            report: Mutex::new(report),
+        }
+    }
+
+    /// Record a failure found after the state was handed to Tauri
+    pub fn record(&self, component: &str, message: impl Into<String>) {
+        self.report
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .record(component, message, None);
+    }
+
+    /// Current copy of the report
+    pub fn snapshot(&self) -> StartupReport {
+        self.report
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+/// Move a corrupted file aside as `<name>.corrupted-<timestamp>`
+pub fn quarantine_corrupted(path: &Path) -> std::io::Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let backup_path = path.with_file_name(format!("{}.corrupted-{}", file_name, timestamp));
+    fs::rename(path, &backup_path)?;
+    Ok(backup_path)
+}
+
+/// Move a corrupted SQLite database aside like `quarantine_corrupted`,
+/// taking its `-wal` and `-shm` files along so they can't be applied to the
+/// fresh database
+pub fn quarantine_database(path: &Path) -> std::io::Result<PathBuf> {
+    let backup_path = quarantine_corrupted(path)?;
+    for suffix in ["-wal", "-shm"] {
+        let mut companion = path.as_os_str().to_owned();
+        companion.push(suffix);
+        let companion = PathBuf::from(companion);
+        if companion.exists() {
+            let mut moved = backup_path.as_os_str().to_owned();
+            moved.push(suffix);
+            fs::rename(&companion, PathBuf::from(moved))?;
+        }
+    }
+    Ok(backup_path)
+}
+
+/// Check that a JSON store is readable, quarantining it if not
+///
+/// A missing file is fine (it is created on first save). Returns false when
+/// the store had to be moved aside or could not be read.
+pub fn check_json_store(component: &str, path: &Path, report: &mut StartupReport) -> bool {
+    if !path.exists() {
+        return true;
+    }
+
+    let error = match fs::read_to_string(path) {
+        Ok(content) => match serde_json::from_str::<serde_json::Value>(&content) {
+            Ok(_) => return true,
+            Err(e) => e.to_string(),
+        },
+        Err(e) => e.to_string(),
+    };
+
+    match quarantine_corrupted(path) {
+        Ok(backup_path) => report.record(
+            component,
+            format!("{} store unreadable ({})", component, error),
+            Some(&backup_path),
+        ),
+        Err(e) => report.record(
+            component,
+            format!(
+                "{} store unreadable ({}) and could not be moved aside: {}",
+                component, error, e
+            ),
+            None,
+        ),
+    }
+    false
+}
+
+/// Load the settings manager eagerly so failures show up in the report
+pub fn check_settings(state: &SettingsState, report: &mut StartupReport) {
+    match state.with_manager(|manager| Ok(manager.health())) {
+        Ok(health) if !health.persistence_available => report.record(
+            "settings",
+            format!(
+                "settings are read-only ({} is not writable)",
+                health.config_path
+            ),
+            None,
+        ),
+        Ok(_) => {}
+        Err(e) => report.record("settings", e.to_string(), None),
+    }
+}
+
+/// Remove staging folders that interrupted transactional exports left in
+/// the export folder of the settings and of each export profile
+pub fn clean_export_staging(state: &SettingsState) {
+    let Ok(roots) = state.with_manager(|manager| {
+        let settings = manager.get();
+        Ok(std::iter::once(settings.export_config.export_path.clone())
+            .chain(
+                settings
+                    .export_profiles
+                    .iter()
+                    .map(|profile| profile.config.export_path.clone()),
+            )
+            .collect::<BTreeSet<String>>())
+    }) else {
+        return;
+    };
+    let removed: usize = roots
+        .iter()
+        .filter(|root| !root.is_empty())
+        .map(|root| remove_orphaned_staging(Path::new(root)))
+        .sum();
+    if removed > 0 {
+        log::warn!(
+            "[Startup] Removed {} staging folder(s) of interrupted exports",
+            removed
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::SettingsManager;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_corrupted_library_is_quarantined() {
+        let temp = TempDir::new().unwrap();
+        let library_path = temp.path().join("library.json");
+        fs::write(&library_path, "{\"books\": [").unwrap();
+
+        let mut report = StartupReport::default();
+        assert!(!check_json_store("library", &library_path, &mut report));
+
+        assert!(report.safe_mode);
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].component, "library");
+        assert!(!library_path.exists());
+        let backup = PathBuf::from(report.issues[0].backup_path.as_ref().unwrap());
+        assert!(backup.exists());
+        assert!(backup
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("library.json.corrupted-"));
+        assert_eq!(fs::read_to_string(backup).unwrap(), "{\"books\": [");
+    }
+
+    #[test]
+    fn test_corrupted_database_moves_with_its_journal() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("library.sqlite");
+        fs::write(&path, "not a database").unwrap();
+        let error = crate::library::LibraryStore::open(&path).err().unwrap();
+        assert!(error.is_corruption(), "{}", error);
+
+        fs::write(temp.path().join("library.sqlite-wal"), "wal").unwrap();
+        fs::write(temp.path().join("library.sqlite-shm"), "shm").unwrap();
+        let backup = quarantine_database(&path).unwrap();
+        let mut names: Vec<String> = fs::read_dir(temp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        let backup_name = backup.file_name().unwrap().to_string_lossy().to_string();
+        assert_eq!(
+            names,
+            vec![
+                backup_name.clone(),
+                format!("{}-shm", backup_name),
+                format!("{}-wal", backup_name)
+            ]
+        );
+
+        // A newer schema isn't corruption
+        crate::library::LibraryStore::open(&path).unwrap();
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.pragma_update(None, "user_version", 999).unwrap();
+        drop(conn);
+        let error = crate::library::LibraryStore::open(&path).err().unwrap();
+        assert!(!error.is_corruption());
+    }
+
+    #[test]
+    fn test_startup_continues_after_corrupted_settings() {
+        let temp = TempDir::new().unwrap();
+        let settings_path = temp.path().join("settings.json");
+        fs::write(&settings_path, "not json").unwrap();
+
+        let mut report = StartupReport::default();
+        check_json_store("settings", &settings_path, &mut report);
+        let state = SettingsState::from_manager(SettingsManager::with_path(settings_path).unwrap());
+        check_settings(&state, &mut report);
+
+        let state = StartupState::new(report);
+        state.record("monitor", "could not start");
+        let report = state.snapshot();
+        assert!(report.safe_mode);
+        let components: Vec<&str> = report.issues.iter().map(|i| i.component.as_str()).collect();
+        assert_eq!(components, vec!["settings", "monitor"]);
+    }
+
+    #[test]
+    fn test_healthy_startup_is_not_safe_mode() {
+        let temp = TempDir::new().unwrap();
+        let store = temp.path().join("library.json");
+        fs::write(&store, "[]").unwrap();
+
+        let mut report = StartupReport::default();
+        assert!(check_json_store("library", &store, &mut report));
+        assert!(check_json_store(
+            "missing",
+            &temp.path().join("none.json"),
+            &mut report
+        ));
+        assert_eq!(report, StartupReport::default());
+        assert!(store.exists());
+    }
+}
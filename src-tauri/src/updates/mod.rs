@@ -0,0 +1,567 @@
+//! App version/build info and the GitHub release update check
+//!
+//! The git hash and build timestamp are embedded by `build.rs`. The update
+//! check only runs when network access is allowed in settings, and its
+//! result is cached in settings so the releases API is queried at most once
+//! per `update_check_interval_hours`.
+
+use crate::utils::cancel::{CancellationToken, Cancelled};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::path::Path;
+use std::time::Duration;
+
+/// Latest published release of the app
+pub const RELEASES_URL: &str =
+    "https://api.github.com/repos/BrunoMiguelMonteiro/khi/releases/latest";
+
+/// Default minimum time between two release checks
+pub const DEFAULT_CHECK_INTERVAL_HOURS: u32 = 24;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Version and build details for the About panel
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AppInfo {
+    pub version: String,
+    /// Short commit hash, `None` when built outside a git checkout
+    pub git_hash: Option<String>,
+    /// RFC 3339 build timestamp
+    pub build_date: Option<String>,
+    pub platform: String,
+    pub arch: String,
+    pub settings_path: Option<String>,
+    pub data_dir: Option<String>,
+}
+
+/// Build the app info from compile-time values and runtime paths
+pub fn app_info(settings_path: Option<&Path>, data_dir: Option<&Path>) -> AppInfo {
+    AppInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_hash: option_env!("KHI_GIT_HASH")
+            .filter(|hash| !hash.is_empty())
+            .map(str::to_string),
+        build_date: option_env!("KHI_BUILD_TIMESTAMP")
+            .and_then(|ts| ts.parse::<i64>().ok())
+            .and_then(|ts| DateTime::<Utc>::from_timestamp(ts, 0))
+            .map(|dt| dt.to_rfc3339()),
+        platform: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        settings_path: settings_path.map(|p| p.to_string_lossy().to_string()),
+        data_dir: data_dir.map(|p| p.to_string_lossy().to_string()),
+    }
+}
+
+/// A semantic version (`1.4.0`, `v2.0.0-beta.2`); build metadata is ignored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre: Vec<PreRelease>,
+}
+
+/// One dot-separated pre-release identifier
+///
+/// Numeric identifiers sort before alphanumeric ones, as semver requires.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PreRelease {
+    Numeric(u64),
+    Alpha(String),
+}
+
+impl Version {
+    /// Parse a version or release tag, tolerating a leading `v` and a
+    /// missing patch number
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let value = value.strip_prefix(['v', 'V']).unwrap_or(value);
+        let value = value.split('+').next()?;
+        let (core, pre) = match value.split_once('-') {
+            Some((core, pre)) => (core, Some(pre)),
+            None => (value, None),
+        };
+
+        let mut numbers = core.split('.').map(|n| n.parse::<u64>().ok());
+        let major = numbers.next()??;
+        let minor = numbers.next()??;
+        let patch = numbers.next().unwrap_or(Some(0))?;
+        if numbers.next().is_some() {
+            return None;
+        }
+
+        let pre = match pre {
+            Some(pre) => pre
+                .split('.')
+                .map(|id| match id {
+                    "" => None,
+                    id if id.chars().all(|c| c.is_ascii_digit()) => {
+                        id.parse().ok().map(PreRelease::Numeric)
+                    }
+                    id => Some(PreRelease::Alpha(id.to_string())),
+                })
+                .collect::<Option<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+
+        Some(Self {
+            major,
+            minor,
+            patch,
+            pre,
+        })
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+                // A release is newer than any of its pre-releases
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => self.pre.cmp(&other.pre),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A published release
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReleaseInfo {
+    pub tag: String,
+    pub url: String,
+}
+
+/// Where the latest release is looked up
+#[cfg_attr(test, mockall::automock)]
+pub trait ReleaseSource {
+    fn latest_release(&self) -> Result<ReleaseInfo, UpdateError>;
+}
+
+/// GitHub releases API
+pub struct GithubReleases {
+    url: String,
+    cancel: CancellationToken,
+}
+
+impl GithubReleases {
+    pub fn new() -> Self {
+        Self {
+            url: RELEASES_URL.to_string(),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Give up when `token` is cancelled; its deadline caps the request
+    /// timeout
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+}
+
+impl Default for GithubReleases {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Deserialize)]
+struct GithubRelease {
+    tag_name: String,
+    html_url: String,
+}
+
+impl ReleaseSource for GithubReleases {
+    fn latest_release(&self) -> Result<ReleaseInfo, UpdateError> {
+        self.cancel.check()?;
+        let timeout = self
+            .cancel
+            .remaining()
+            .map_or(REQUEST_TIMEOUT, |remaining| remaining.min(REQUEST_TIMEOUT));
+        let body = ureq::get(&self.url)
+            .timeout(timeout)
+            .set("Accept", "application/vnd.github+json")
+            .set("User-Agent", concat!("khi/", env!("CARGO_PKG_VERSION")))
+            .call()
+            .map_err(|e| match self.cancel.check() {
+                // The request timed out because the deadline passed
+                Err(cancelled) => UpdateError::Cancelled(cancelled),
+                Ok(()) => UpdateError::Http(e.to_string()),
+            })?
+            .into_string()?;
+        let release: GithubRelease = serde_json::from_str(&body)?;
+
+        Ok(ReleaseInfo {
+            tag: release.tag_name,
+            url: release.html_url,
+        })
+    }
+}
+
+/// Last release check, persisted in settings
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateCheckCache {
+    /// RFC 3339 time of the check
+    #[serde(alias = "checked_at")]
+    pub checked_at: String,
+    pub latest: String,
+    pub url: String,
+}
+
+/// Result of `check_for_updates`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateStatus {
+    pub current: String,
+    pub latest: String,
+    pub update_available: bool,
+    pub url: String,
+    pub checked_at: String,
+    /// Served from the cache without contacting GitHub
+    pub cached: bool,
+}
+
+impl UpdateStatus {
+    fn from_cache(current: &str, cache: &UpdateCheckCache, cached: bool) -> Self {
+        let update_available = match (Version::parse(&cache.latest), Version::parse(current)) {
+            (Some(latest), Some(current)) => latest > current,
+            _ => false,
+        };
+        Self {
+            current: current.to_string(),
+            latest: cache.latest.clone(),
+            update_available,
+            url: cache.url.clone(),
+            checked_at: cache.checked_at.clone(),
+            cached,
+        }
+    }
+}
+
+/// Whether a cached check is younger than `interval_hours`
+pub fn cache_is_fresh(cache: &UpdateCheckCache, interval_hours: u32, now: DateTime<Utc>) -> bool {
+    match DateTime::parse_from_rfc3339(&cache.checked_at) {
+        Ok(checked_at) => {
+            let age = now - checked_at.with_timezone(&Utc);
+            // A check "from the future" (clock change) is not trusted
+            age >= chrono::Duration::zero()
+                && age < chrono::Duration::hours(i64::from(interval_hours))
+        }
+        Err(_) => false,
+    }
+}
+
+/// Compare `current` with the latest release, reusing a fresh cache unless
+/// `force` is set; `cache` is replaced after a successful lookup
+pub fn check_for_updates(
+    source: &dyn ReleaseSource,
+    current: &str,
+    cache: &mut Option<UpdateCheckCache>,
+    interval_hours: u32,
+    force: bool,
+    now: DateTime<Utc>,
+) -> Result<UpdateStatus, UpdateError> {
+    if let Some(cached) = cache.as_ref() {
+        if !force && cache_is_fresh(cached, interval_hours, now) {
+            return Ok(UpdateStatus::from_cache(current, cached, true));
+        }
+    }
+
+    let release = source.latest_release()?;
+    if Version::parse(&release.tag).is_none() {
+        return Err(UpdateError::InvalidVersion(release.tag));
+    }
+
+    let fresh = UpdateCheckCache {
+        checked_at: now.to_rfc3339(),
+        latest: release.tag.trim_start_matches(['v', 'V']).to_string(),
+        url: release.url,
+    };
+    let status = UpdateStatus::from_cache(current, &fresh, false);
+    *cache = Some(fresh);
+    Ok(status)
+}
+
+/// Update check errors
+#[derive(Debug)]
+pub enum UpdateError {
+    /// Network access is disabled in settings
+    NetworkDisabled,
+    /// Request failed or returned an error status
+    Http(String),
+    /// IO error reading the response
+    Io(std::io::Error),
+    /// Unexpected response body
+    Parse(serde_json::Error),
+    /// Release tag is not a semantic version
+    InvalidVersion(String),
+    /// The check was cancelled or timed out
+    Cancelled(Cancelled),
+}
+
+impl std::fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateError::NetworkDisabled => write!(f, "Network access is disabled in settings"),
+            UpdateError::Http(msg) => write!(f, "HTTP error: {}", msg),
+            UpdateError::Io(e) => write!(f, "IO error: {}", e),
+            UpdateError::Parse(e) => write!(f, "Parse error: {}", e),
+            UpdateError::InvalidVersion(tag) => write!(f, "Invalid release version: {}", tag),
+            UpdateError::Cancelled(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+impl std::error::Error for UpdateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UpdateError::Io(e) => Some(e),
+            UpdateError::Parse(e) => Some(e),
+            UpdateError::Cancelled(c) => Some(c),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for UpdateError {
+    fn from(err: std::io::Error) -> Self {
+        UpdateError::Io(err)
+    }
+}
+
+impl From<Cancelled> for UpdateError {
+    fn from(cancelled: Cancelled) -> Self {
+        UpdateError::Cancelled(cancelled)
+    }
+}
+
+impl From<serde_json::Error> for UpdateError {
+    fn from(err: serde_json::Error) -> Self {
+        UpdateError::Parse(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(value: &str) -> Version {
+        Version::parse(value).unwrap()
+    }
+
+    fn release_source(tag: &str, calls: usize) -> MockReleaseSource {
+        let mut source = MockReleaseSource::new();
+        let tag = tag.to_string();
+        source
+            .expect_latest_release()
+            .times(calls)
+            .returning(move || {
+                Ok(ReleaseInfo {
+                    tag: tag.clone(),
+                    url: "https://github.com/BrunoMiguelMonteiro/khi/releases/tag/v1.4.0"
+                        .to_string(),
+                })
+            });
+        source
+    }
+
+    #[test]
+    fn test_version_parse() {
+        assert_eq!(v("v1.4.2"), v("1.4.2"));
+        assert_eq!(v("1.4"), v("1.4.0"));
+        assert_eq!(v("1.4.0+build.7"), v("1.4.0"));
+        assert_eq!(
+            v("2.0.0-rc.1").pre,
+            vec![PreRelease::Alpha("rc".to_string()), PreRelease::Numeric(1)]
+        );
+        assert!(Version::parse("latest").is_none());
+        assert!(Version::parse("1.2.3.4").is_none());
+        assert!(Version::parse("1.2.3-").is_none());
+    }
+
+    #[test]
+    fn test_version_ordering_with_pre_releases() {
+        // Precedence example from the semver spec, lowest first
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.10.0",
+            "2.0.0-alpha",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_check_reports_newer_release() {
+        let now = Utc::now();
+        let mut cache = None;
+        let status = check_for_updates(
+            &release_source("v1.4.0", 1),
+            "1.3.0",
+            &mut cache,
+            24,
+            false,
+            now,
+        )
+        .unwrap();
+
+        assert!(status.update_available);
+        assert!(!status.cached);
+        assert_eq!(status.latest, "1.4.0");
+        assert_eq!(cache.unwrap().checked_at, now.to_rfc3339());
+
+        let mut cache = None;
+        let status = check_for_updates(
+            &release_source("v1.3.0-beta.1", 1),
+            "1.3.0",
+            &mut cache,
+            24,
+            false,
+            now,
+        )
+        .unwrap();
+        assert!(!status.update_available);
+    }
+
+    #[test]
+    fn test_cache_respects_interval_and_force() {
+        let now = Utc::now();
+        let mut cache = Some(UpdateCheckCache {
+            checked_at: (now - chrono::Duration::hours(3)).to_rfc3339(),
+            latest: "1.4.0".to_string(),
+            url: "https://example.com".to_string(),
+        });
+
+        // Fresh cache: the source must not be called
+        let status = check_for_updates(
+            &release_source("v9.0.0", 0),
+            "1.3.0",
+            &mut cache,
+            24,
+            false,
+            now,
+        )
+        .unwrap();
+        assert!(status.cached);
+        assert_eq!(status.latest, "1.4.0");
+
+        // Forced, or past the interval: the source is queried and the cache replaced
+        check_for_updates(
+            &release_source("v1.5.0", 1),
+            "1.3.0",
+            &mut cache,
+            24,
+            true,
+            now,
+        )
+        .unwrap();
+        assert_eq!(cache.as_ref().unwrap().latest, "1.5.0");
+        let later = now + chrono::Duration::hours(25);
+        let status = check_for_updates(
+            &release_source("v1.6.0", 1),
+            "1.3.0",
+            &mut cache,
+            24,
+            false,
+            later,
+        )
+        .unwrap();
+        assert_eq!(status.latest, "1.6.0");
+
+        // Clock moved backwards: cache is not trusted
+        let stale = cache.clone().unwrap();
+        assert!(!cache_is_fresh(
+            &stale,
+            24,
+            later - chrono::Duration::hours(1)
+        ));
+    }
+
+    #[test]
+    fn test_failed_check_keeps_cache() {
+        let mut source = MockReleaseSource::new();
+        source
+            .expect_latest_release()
+            .returning(|| Err(UpdateError::Http("timed out".to_string())));
+        let cached = UpdateCheckCache {
+            checked_at: "2020-01-01T00:00:00+00:00".to_string(),
+            latest: "1.4.0".to_string(),
+            url: "https://example.com".to_string(),
+        };
+        let mut cache = Some(cached.clone());
+
+        assert!(check_for_updates(&source, "1.3.0", &mut cache, 24, false, Utc::now()).is_err());
+        assert_eq!(cache, Some(cached));
+        assert!(check_for_updates(
+            &release_source("nightly", 1),
+            "1.3.0",
+            &mut cache,
+            24,
+            true,
+            Utc::now()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_deadline_stops_hanging_request() {
+        // Accepts connections and never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let _held: Vec<_> = listener.incoming().collect();
+        });
+        let source = GithubReleases {
+            url: format!("http://{}/releases/latest", address),
+            cancel: CancellationToken::new(),
+        }
+        .with_cancellation(CancellationToken::with_timeout(Some(
+            Duration::from_millis(200),
+        )));
+
+        let started = std::time::Instant::now();
+        let mut cache = None;
+        let error =
+            check_for_updates(&source, "1.3.0", &mut cache, 24, true, Utc::now()).unwrap_err();
+
+        assert!(
+            matches!(
+                &error,
+                UpdateError::Cancelled(Cancelled {
+                    reason: crate::utils::cancel::CancelReason::DeadlineExceeded,
+                    ..
+                })
+            ),
+            "{}",
+            error
+        );
+        assert!(started.elapsed() < REQUEST_TIMEOUT);
+        assert_eq!(cache, None);
+        // An expired token doesn't even connect
+        assert!(matches!(
+            source.latest_release(),
+            Err(UpdateError::Cancelled(_))
+        ));
+    }
+}
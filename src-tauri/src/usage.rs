@@ -0,0 +1,471 @@
+//! Local usage history
+//!
+//! Every successful import, export and headless sync appends one event to
+//! `usage.jsonl` in the app data folder, so the app can show a "year in
+//! review" without any of it leaving the machine. Events hold a timestamp
+//! and counts only: never book titles, highlight text or device paths.
+//! Events older than the retention setting are pruned by scheduled
+//! maintenance.
+
+use crate::scheduler::{AppContext, MaintenanceTask, TaskReport};
+use crate::utils::fs::{atomic_write_with, SystemFileOps};
+use chrono::{DateTime, Datelike, Months, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Name of the history file in the app data folder
+pub const USAGE_FILE: &str = "usage.jsonl";
+
+/// Months of history kept by default
+pub const DEFAULT_USAGE_RETENTION_MONTHS: u32 = 24;
+
+/// Held while the history file is written, so a prune rewriting it can't
+/// drop an event appended meanwhile
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// What an event records
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageKind {
+    Import,
+    Export,
+    /// Headless sync (import and export in one run)
+    Sync,
+}
+
+/// One usage event
+///
+/// Deliberately nothing but counts: adding a field that could identify a
+/// book or quote a highlight would defeat the point of this history.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageEvent {
+    pub kind: UsageKind,
+    /// RFC 3339 timestamp
+    pub at: String,
+    pub books: usize,
+    pub highlights: usize,
+    /// Highlights new to the library (imports and syncs)
+    #[serde(default)]
+    pub new_highlights: usize,
+    /// Books of the library that reached 100% read (imports and syncs)
+    #[serde(default)]
+    pub books_finished: usize,
+    /// Files written (exports and syncs)
+    #[serde(default)]
+    pub files: usize,
+}
+
+impl UsageEvent {
+    pub fn now(kind: UsageKind, books: usize, highlights: usize) -> Self {
+        Self {
+            kind,
+            at: Utc::now().to_rfc3339(),
+            books,
+            highlights,
+            new_highlights: 0,
+            books_finished: 0,
+            files: 0,
+        }
+    }
+
+    /// `YYYY-MM` of the event (UTC); `None` if the timestamp is unreadable
+    pub fn month(&self) -> Option<String> {
+        DateTime::parse_from_rfc3339(&self.at)
+            .ok()
+            .map(|at| at.to_utc().format("%Y-%m").to_string())
+    }
+}
+
+/// Months to aggregate, as `YYYY-MM` (inclusive; open ends are unbounded)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageRange {
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub to: Option<String>,
+}
+
+/// Totals of one calendar month (UTC)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MonthlyUsage {
+    /// `YYYY-MM`
+    pub month: String,
+    pub imports: usize,
+    pub exports: usize,
+    pub syncs: usize,
+    /// Highlights new to the library
+    pub highlights_gained: usize,
+    /// Books that reached 100% read
+    pub books_finished: usize,
+    /// Most books seen by one import or sync
+    pub books: usize,
+    pub files_written: usize,
+}
+
+/// The history in monthly buckets, oldest first (months without events
+/// are left out)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageHistory {
+    pub months: Vec<MonthlyUsage>,
+    pub total_events: usize,
+}
+
+/// Append-only usage history file
+pub struct UsageLog {
+    path: PathBuf,
+}
+
+impl UsageLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// The history in the app data folder `data_dir`
+    pub fn in_dir(data_dir: &Path) -> Self {
+        Self::new(data_dir.join(USAGE_FILE))
+    }
+
+    pub fn append(&self, event: &UsageEvent) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+        let _write = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())
+    }
+
+    /// Every readable event, in the order appended (a missing file is an
+    /// empty history; malformed lines are skipped)
+    pub fn events(&self) -> io::Result<Vec<UsageEvent>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(event) => Some(event),
+                Err(e) => {
+                    log::debug!("Skipping malformed usage event: {}", e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// Events within `range`, in monthly buckets
+    pub fn history(&self, range: &UsageRange) -> io::Result<UsageHistory> {
+        let mut months: BTreeMap<String, MonthlyUsage> = BTreeMap::new();
+        let mut total_events = 0;
+        for event in self.events()? {
+            let Some(month) = event.month() else {
+                continue;
+            };
+            let after_start = range.from.as_ref().is_none_or(|from| month >= *from);
+            let before_end = range.to.as_ref().is_none_or(|to| month <= *to);
+            if !(after_start && before_end) {
+                continue;
+            }
+            total_events += 1;
+            let bucket = months.entry(month.clone()).or_insert_with(|| MonthlyUsage {
+                month,
+                ..Default::default()
+            });
+            match event.kind {
+                UsageKind::Import => bucket.imports += 1,
+                UsageKind::Export => bucket.exports += 1,
+                UsageKind::Sync => bucket.syncs += 1,
+            }
+            bucket.highlights_gained += event.new_highlights;
+            bucket.books_finished += event.books_finished;
+            bucket.files_written += event.files;
+            if event.kind != UsageKind::Export {
+                bucket.books = bucket.books.max(event.books);
+            }
+        }
+        Ok(UsageHistory {
+            months: months.into_values().collect(),
+            total_events,
+        })
+    }
+
+    /// Drop events from before the first day of the month `months` before
+    /// `now`'s; `0` keeps everything. Returns how many were dropped.
+    pub fn prune(&self, months: u32, now: DateTime<Utc>) -> io::Result<usize> {
+        if months == 0 {
+            return Ok(0);
+        }
+        let Some(cutoff) = now
+            .with_day(1)
+            .and_then(|start| start.checked_sub_months(Months::new(months)))
+            .map(|start| start.format("%Y-%m").to_string())
+        else {
+            return Ok(0);
+        };
+        let _write = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let events = self.events()?;
+        let (kept, dropped): (Vec<_>, Vec<_>) = events
+            .into_iter()
+            .partition(|event| event.month().is_none_or(|month| month >= cutoff));
+        if dropped.is_empty() {
+            return Ok(0);
+        }
+        atomic_write_with(&SystemFileOps, &self.path, |writer| {
+            for event in &kept {
+                serde_json::to_writer(&mut *writer, event)?;
+                writer.write_all(b"\n")?;
+            }
+            Ok(())
+        })?;
+        Ok(dropped.len())
+    }
+}
+
+/// Record `event` in the history of `data_dir`; the history is optional, so
+/// a failure is only logged
+pub fn record(data_dir: &Path, event: UsageEvent) {
+    if let Err(e) = UsageLog::in_dir(data_dir).append(&event) {
+        log::warn!("Usage event not recorded: {}", e);
+    }
+}
+
+/// Prunes the usage history to the retention setting once a day
+pub struct UsageRetentionTask {
+    /// Current history folder (the active profile's); `None` falls back to
+    /// the app data folder
+    usage_dir: Box<dyn Fn() -> Option<PathBuf> + Send>,
+    /// Current retention in months (read on every run, so changes apply)
+    retention: Box<dyn Fn() -> u32 + Send>,
+}
+
+impl UsageRetentionTask {
+    pub fn new(
+        usage_dir: impl Fn() -> Option<PathBuf> + Send + 'static,
+        retention: impl Fn() -> u32 + Send + 'static,
+    ) -> Self {
+        Self {
+            usage_dir: Box::new(usage_dir),
+            retention: Box::new(retention),
+        }
+    }
+}
+
+impl MaintenanceTask for UsageRetentionTask {
+    fn name(&self) -> &str {
+        "usage-retention"
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_secs(24 * 60 * 60)
+    }
+
+    fn run(&self, ctx: &AppContext) -> Result<TaskReport, String> {
+        let dir = (self.usage_dir)().unwrap_or_else(|| ctx.data_dir.clone());
+        let removed = UsageLog::in_dir(&dir)
+            .prune((self.retention)(), Utc::now())
+            .map_err(|e| e.to_string())?;
+        Ok(TaskReport {
+            summary: format!("removed {} old usage event(s)", removed),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn at(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date).unwrap().to_utc()
+    }
+
+    fn event(kind: UsageKind, date: &str, new_highlights: usize) -> UsageEvent {
+        UsageEvent {
+            at: date.to_string(),
+            new_highlights,
+            books_finished: usize::from(
+                kind != UsageKind::Export && new_highlights.is_multiple_of(4),
+            ),
+            files: if kind == UsageKind::Import { 0 } else { 3 },
+            ..UsageEvent::now(kind, 4, 40)
+        }
+    }
+
+    /// One import per month of 2024, an export every other month and a
+    /// sync in December
+    fn simulated_year(log: &UsageLog) {
+        for month in 1..=12 {
+            let date = format!("2024-{:02}-15T20:00:00Z", month);
+            log.append(&event(UsageKind::Import, &date, month)).unwrap();
+            if month % 2 == 0 {
+                log.append(&event(UsageKind::Export, &date, 0)).unwrap();
+            }
+        }
+        log.append(&event(UsageKind::Sync, "2024-12-31T23:59:59Z", 5))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_monthly_aggregates() {
+        let temp = TempDir::new().unwrap();
+        let log = UsageLog::in_dir(temp.path());
+        simulated_year(&log);
+
+        let year = log.history(&UsageRange::default()).unwrap();
+        assert_eq!(year.total_events, 19);
+        assert_eq!(year.months.len(), 12);
+        assert_eq!(year.months[0].month, "2024-01");
+        assert_eq!(year.months[0].imports, 1);
+        assert_eq!(year.months[0].exports, 0);
+        let december = &year.months[11];
+        assert_eq!(
+            (december.imports, december.exports, december.syncs),
+            (1, 1, 1)
+        );
+        assert_eq!(december.highlights_gained, 12 + 5);
+        assert_eq!(december.files_written, 6);
+        let gained: usize = year.months.iter().map(|m| m.highlights_gained).sum();
+        assert_eq!(gained, 78 + 5);
+        let finished: usize = year.months.iter().map(|m| m.books_finished).sum();
+        assert_eq!(finished, 3);
+
+        let summer = log
+            .history(&UsageRange {
+                from: Some("2024-06".to_string()),
+                to: Some("2024-08".to_string()),
+            })
+            .unwrap();
+        let months: Vec<&str> = summer.months.iter().map(|m| m.month.as_str()).collect();
+        assert_eq!(months, vec!["2024-06", "2024-07", "2024-08"]);
+    }
+
+    #[test]
+    fn test_pruning_respects_the_retention_boundary() {
+        let temp = TempDir::new().unwrap();
+        let log = UsageLog::in_dir(temp.path());
+        simulated_year(&log);
+
+        // Keeping 6 months on 2025-03-10 keeps September 2024 onwards
+        assert_eq!(log.prune(0, at("2025-03-10T00:00:00Z")).unwrap(), 0);
+        let removed = log.prune(6, at("2025-03-10T00:00:00Z")).unwrap();
+        assert_eq!(removed, 8 + 4);
+        let history = log.history(&UsageRange::default()).unwrap();
+        assert_eq!(history.months[0].month, "2024-09");
+        assert_eq!(history.total_events, 19 - removed);
+
+        // Nothing more to drop, and the file stays appendable
+        assert_eq!(log.prune(6, at("2025-03-31T23:00:00Z")).unwrap(), 0);
+        log.append(&event(UsageKind::Import, "2025-03-31T23:00:00Z", 1))
+            .unwrap();
+        assert_eq!(log.events().unwrap().len(), 8);
+    }
+
+    #[test]
+    fn test_retention_task_prunes_the_current_profile() {
+        let temp = TempDir::new().unwrap();
+        let profile_dir = temp.path().join("profiles").join("ana");
+        let (default_log, profile_log) = (
+            UsageLog::in_dir(temp.path()),
+            UsageLog::in_dir(&profile_dir),
+        );
+        default_log
+            .append(&event(UsageKind::Import, "2020-01-01T00:00:00Z", 1))
+            .unwrap();
+        profile_log
+            .append(&event(UsageKind::Import, "2020-01-01T00:00:00Z", 1))
+            .unwrap();
+
+        let dir = profile_dir.clone();
+        let task = UsageRetentionTask::new(move || Some(dir.clone()), || 12);
+        let ctx = AppContext {
+            cache_dir: temp.path().join("cache"),
+            data_dir: temp.path().to_path_buf(),
+        };
+        task.run(&ctx).unwrap();
+        assert!(profile_log.events().unwrap().is_empty());
+        assert_eq!(default_log.events().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_prune_keeps_events_appended_meanwhile() {
+        let temp = TempDir::new().unwrap();
+        let log = UsageLog::in_dir(temp.path());
+        simulated_year(&log);
+
+        let appender = std::thread::spawn({
+            let log = UsageLog::in_dir(temp.path());
+            move || {
+                for _ in 0..200 {
+                    log.append(&event(UsageKind::Import, "2025-03-01T00:00:00Z", 1))
+                        .unwrap();
+                }
+            }
+        });
+        for _ in 0..20 {
+            log.prune(6, at("2025-03-10T00:00:00Z")).unwrap();
+        }
+        appender.join().unwrap();
+        log.prune(6, at("2025-03-10T00:00:00Z")).unwrap();
+
+        let march = log
+            .history(&UsageRange {
+                from: Some("2025-03".to_string()),
+                to: None,
+            })
+            .unwrap();
+        assert_eq!(march.months[0].imports, 200);
+    }
+
+    #[test]
+    fn test_events_hold_counts_only() {
+        let temp = TempDir::new().unwrap();
+        let log = UsageLog::in_dir(temp.path());
+        assert!(log.events().unwrap().is_empty());
+        log.append(&event(UsageKind::Export, "2024-05-01T08:00:00Z", 0))
+            .unwrap();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(temp.path().join(USAGE_FILE))
+            .unwrap();
+        file.write_all(b"{broken\n").unwrap();
+
+        let line = fs::read_to_string(temp.path().join(USAGE_FILE)).unwrap();
+        let value: serde_json::Value = serde_json::from_str(line.lines().next().unwrap()).unwrap();
+        let mut keys: Vec<&str> = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(|k| k.as_str())
+            .collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![
+                "at",
+                "books",
+                "booksFinished",
+                "files",
+                "highlights",
+                "kind",
+                "newHighlights"
+            ]
+        );
+        assert_eq!(log.events().unwrap().len(), 1);
+    }
+}
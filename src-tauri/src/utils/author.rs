@@ -0,0 +1,139 @@
+//! Author attribution parsing
+//!
+//! Kobo's `Attribution` column is inconsistent for multi-author books
+//! ("Doe, John; Smith, Jane", "John Doe & Jane Smith", "Doe, John"). These
+//! helpers split and normalize it into a list of "First Last" names.
+
+/// Trailing parts that follow a comma without being a first name
+const NAME_SUFFIXES: &[&str] = &[
+    "jr", "jr.", "sr", "sr.", "ii", "iii", "iv", "phd", "ph.d.", "md", "inc", "inc.", "ltd",
+    "ltd.", "llc", "co", "co.", "corp", "corp.",
+];
+
+fn collapse_whitespace(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn is_suffix(part: &str) -> bool {
+    NAME_SUFFIXES.contains(&part.trim().to_lowercase().as_str())
+}
+
+/// Split on " and " (case-insensitive, whole word)
+fn split_on_and(value: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+
+    for word in value.split_whitespace() {
+        if word.eq_ignore_ascii_case("and") && !current.is_empty() {
+            parts.push(current.join(" "));
+            current.clear();
+        } else {
+            current.push(word);
+        }
+    }
+    if !current.is_empty() {
+        parts.push(current.join(" "));
+    }
+
+    parts
+}
+
+/// Normalize one segment, which may hold "Last, First" or a comma list
+fn normalize_segment(segment: &str) -> Vec<String> {
+    let parts: Vec<String> = segment
+        .split(',')
+        .map(collapse_whitespace)
+        .filter(|p| !p.is_empty())
+        .collect();
+
+    match parts.len() {
+        0 => Vec::new(),
+        1 => parts,
+        2 if is_suffix(&parts[1]) => vec![format!("{}, {}", parts[0], parts[1])],
+        2 => vec![format!("{} {}", parts[1], parts[0])],
+        // "John Doe, Jane Smith, Bob Lee": a plain list of full names
+        _ if parts.iter().all(|p| p.contains(' ')) => parts,
+        // "Doe, John, Smith, Jane": Last/First pairs
+        n if n % 2 == 0 => parts
+            .chunks(2)
+            .map(|pair| format!("{} {}", pair[1], pair[0]))
+            .collect(),
+        _ => vec![parts.join(", ")],
+    }
+}
+
+/// Parse a raw attribution into normalized, de-duplicated author names
+///
+/// Splits on `;`, `&` and ` and `, turns "Last, First" into "First Last"
+/// and trims whitespace. Corporate names ("Acme, Inc.") are kept as-is.
+pub fn parse_authors(raw: &str) -> Vec<String> {
+    let mut authors: Vec<String> = Vec::new();
+
+    for segment in raw.split([';', '&']).flat_map(split_on_and) {
+        for name in normalize_segment(&segment) {
+            if !authors.iter().any(|a| a.eq_ignore_ascii_case(&name)) {
+                authors.push(name);
+            }
+        }
+    }
+
+    authors
+}
+
+/// Sort key for an author list: the first author's surname, then the full name
+pub fn author_sort_key(authors: &[String]) -> String {
+    let first = authors.first().map(String::as_str).unwrap_or("");
+    let surname = first
+        .split(", ")
+        .next()
+        .and_then(|name| name.split_whitespace().last())
+        .unwrap_or("");
+    format!("{} {}", surname, first).trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_authors_table() {
+        let cases: &[(&str, &[&str])] = &[
+            ("John Doe", &["John Doe"]),
+            ("Doe, John", &["John Doe"]),
+            ("  Doe ,   John  ", &["John Doe"]),
+            ("Doe, John; Smith, Jane", &["John Doe", "Jane Smith"]),
+            ("John Doe & Jane Smith", &["John Doe", "Jane Smith"]),
+            ("John Doe and Jane Smith", &["John Doe", "Jane Smith"]),
+            ("Doe, John & Smith, Jane", &["John Doe", "Jane Smith"]),
+            (
+                "John Doe, Jane Smith, Bob Lee",
+                &["John Doe", "Jane Smith", "Bob Lee"],
+            ),
+            ("Doe, John, Smith, Jane", &["John Doe", "Jane Smith"]),
+            ("Doe, John; John Doe", &["John Doe"]),
+            ("Le Guin, Ursula K.", &["Ursula K. Le Guin"]),
+            ("Martin Luther King, Jr.", &["Martin Luther King, Jr."]),
+            ("Acme, Inc.", &["Acme, Inc."]),
+            ("World Health Organization", &["World Health Organization"]),
+            ("", &[]),
+        ];
+
+        for (raw, expected) in cases {
+            assert_eq!(parse_authors(raw), *expected, "input: {:?}", raw);
+        }
+    }
+
+    #[test]
+    fn test_author_sort_key_uses_first_surname() {
+        let authors = parse_authors("Smith, Jane; Adams, Zoe");
+        assert_eq!(author_sort_key(&authors), "smith jane smith");
+
+        let mut keys = [
+            author_sort_key(&parse_authors("Zoe Adams")),
+            author_sort_key(&parse_authors("Aaron Zimmer")),
+        ];
+        keys.sort();
+        assert!(keys[0].starts_with("adams"));
+        assert_eq!(author_sort_key(&[]), "");
+    }
+}
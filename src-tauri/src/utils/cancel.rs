@@ -0,0 +1,266 @@
+//! Cancelling long-running operations and giving them deadlines
+//!
+//! Imports, exports, manifest checks and network requests take a
+//! `CancellationToken` and check it where stopping is safe: between books,
+//! between files, before each request. A token stops an operation when
+//! `cancel_operation` cancels it through the `OperationRegistry` or when its
+//! deadline passes, so nothing hangs forever on a dead network mount.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Kinds of long-running operations, each with its own default deadline
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationKind {
+    Import,
+    Export,
+    /// Manifest verification and repair
+    Verify,
+    /// Requests to the internet (update check)
+    Network,
+}
+
+/// Why an operation stopped early
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelReason {
+    /// `cancel_operation` was called
+    Requested,
+    /// The operation's deadline passed
+    DeadlineExceeded,
+}
+
+/// An operation stopped by its token, with how far it got
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Cancelled {
+    pub reason: CancelReason,
+    /// Items (books, files) finished before stopping, when counted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+}
+
+impl Cancelled {
+    /// Record that `completed` of `total` items were done
+    pub fn with_progress(mut self, completed: usize, total: usize) -> Self {
+        self.completed = Some(completed);
+        self.total = Some(total);
+        self
+    }
+}
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.reason {
+            CancelReason::Requested => write!(f, "Cancelled")?,
+            CancelReason::DeadlineExceeded => write!(f, "Timed out")?,
+        }
+        match (self.completed, self.total) {
+            (Some(completed), Some(total)) => write!(f, " ({} of {} done)", completed, total),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl From<Cancelled> for std::io::Error {
+    fn from(cancelled: Cancelled) -> Self {
+        std::io::Error::new(std::io::ErrorKind::Interrupted, cancelled)
+    }
+}
+
+#[derive(Debug)]
+struct TokenState {
+    cancelled: AtomicBool,
+    deadline: Option<Instant>,
+}
+
+/// Shared flag plus optional deadline; clones observe the same state
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    state: Arc<TokenState>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CancellationToken {
+    /// Token that only stops when cancelled
+    pub fn new() -> Self {
+        Self::with_timeout(None)
+    }
+
+    /// Token that also stops once `timeout` has elapsed from now
+    pub fn with_timeout(timeout: Option<Duration>) -> Self {
+        Self {
+            state: Arc::new(TokenState {
+                cancelled: AtomicBool::new(false),
+                deadline: timeout.map(|timeout| Instant::now() + timeout),
+            }),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Why the token stops its operation, if it does
+    pub fn reason(&self) -> Option<CancelReason> {
+        if self.state.cancelled.load(Ordering::SeqCst) {
+            Some(CancelReason::Requested)
+        } else if self.state.deadline.is_some_and(|d| Instant::now() >= d) {
+            Some(CancelReason::DeadlineExceeded)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.reason().is_some()
+    }
+
+    /// `Err` once cancelled or past the deadline
+    pub fn check(&self) -> Result<(), Cancelled> {
+        match self.reason() {
+            Some(reason) => Err(Cancelled {
+                reason,
+                completed: None,
+                total: None,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Time left before the deadline (`None` without one)
+    pub fn remaining(&self) -> Option<Duration> {
+        self.state
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+}
+
+/// Tokens of the running operations, by the ID the frontend gave them
+#[derive(Debug, Default)]
+pub struct OperationRegistry {
+    tokens: Mutex<HashMap<String, CancellationToken>>,
+}
+
+impl OperationRegistry {
+    /// Token for a new operation, registered under `id` (when given) until
+    /// the returned handle drops
+    pub fn begin(&self, id: Option<String>, timeout: Option<Duration>) -> OperationHandle<'_> {
+        let token = CancellationToken::with_timeout(timeout);
+        if let Some(id) = &id {
+            let mut tokens = self.tokens.lock().unwrap_or_else(|e| e.into_inner());
+            if tokens.insert(id.clone(), token.clone()).is_some() {
+                log::warn!(
+                    "[Cancel] Operation ID {} reused, the older one can't be cancelled",
+                    id
+                );
+            }
+        }
+        OperationHandle {
+            registry: self,
+            id,
+            token,
+        }
+    }
+
+    /// Cancel the operation registered as `id`; false when none is running
+    pub fn cancel(&self, id: &str) -> bool {
+        let tokens = self.tokens.lock().unwrap_or_else(|e| e.into_inner());
+        match tokens.get(id) {
+            Some(token) => {
+                log::info!("[Cancel] Cancelling operation {}", id);
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A running operation's token; unregisters the operation when dropped
+#[derive(Debug)]
+pub struct OperationHandle<'a> {
+    registry: &'a OperationRegistry,
+    id: Option<String>,
+    token: CancellationToken,
+}
+
+impl OperationHandle<'_> {
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+impl Drop for OperationHandle<'_> {
+    fn drop(&mut self) {
+        let Some(id) = &self.id else {
+            return;
+        };
+        let mut tokens = self
+            .registry
+            .tokens
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        // A newer operation may have taken the ID over
+        if tokens
+            .get(id)
+            .is_some_and(|token| Arc::ptr_eq(&token.state, &self.token.state))
+        {
+            tokens.remove(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_cancels_by_id() {
+        let registry = OperationRegistry::default();
+        let export = registry.begin(Some("export-1".into()), None);
+        let import = registry.begin(Some("import-1".into()), None);
+        let anonymous = registry.begin(None, None);
+
+        assert!(registry.cancel("export-1"));
+        assert_eq!(export.token().reason(), Some(CancelReason::Requested));
+        assert!(!import.token().is_cancelled());
+        assert!(!anonymous.token().is_cancelled());
+
+        drop(export);
+        assert!(!registry.cancel("export-1"));
+        assert!(registry.cancel("import-1"));
+    }
+
+    #[test]
+    fn test_deadline_and_progress() {
+        let token = CancellationToken::with_timeout(Some(Duration::ZERO));
+        let cancelled = token.check().unwrap_err();
+        assert_eq!(cancelled.reason, CancelReason::DeadlineExceeded);
+        assert_eq!(token.remaining(), Some(Duration::ZERO));
+        assert_eq!(
+            cancelled.with_progress(3, 10).to_string(),
+            "Timed out (3 of 10 done)"
+        );
+
+        let token = CancellationToken::new();
+        assert!(token.check().is_ok());
+        assert_eq!(token.remaining(), None);
+        let clone = token.clone();
+        clone.cancel();
+        assert_eq!(token.check().unwrap_err().to_string(), "Cancelled");
+    }
+}
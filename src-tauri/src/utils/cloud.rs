@@ -0,0 +1,160 @@
+//! Cloud-synced export folders
+//!
+//! Sync daemons (iCloud Drive, Dropbox, OneDrive, Google Drive) pick up files
+//! as soon as they change and hold them open while uploading, so a rename
+//! over a file being uploaded can fail with `EPERM`/`EBUSY`. Detection is
+//! path-based only: the folder names the clients use on macOS and Windows.
+
+use serde::{Deserialize, Serialize};
+
+/// Sync service of an export folder, serialized as its `name`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum CloudProvider {
+    #[serde(rename = "iCloud Drive", alias = "i_cloud_drive")]
+    ICloudDrive,
+    #[serde(rename = "Dropbox", alias = "dropbox")]
+    Dropbox,
+    #[serde(rename = "OneDrive", alias = "one_drive")]
+    OneDrive,
+    #[serde(rename = "Google Drive", alias = "google_drive")]
+    GoogleDrive,
+}
+
+impl CloudProvider {
+    /// Name shown to the user
+    pub fn name(self) -> &'static str {
+        match self {
+            CloudProvider::ICloudDrive => "iCloud Drive",
+            CloudProvider::Dropbox => "Dropbox",
+            CloudProvider::OneDrive => "OneDrive",
+            CloudProvider::GoogleDrive => "Google Drive",
+        }
+    }
+
+    /// Provider of one path component (case-insensitive)
+    fn of_component(component: &str) -> Option<Self> {
+        let component = component.to_lowercase();
+        // ~/Library/Mobile Documents (iCloud's real location), the Finder's
+        // "iCloud Drive" and Windows' iCloudDrive folder
+        if matches!(
+            component.as_str(),
+            "mobile documents" | "icloud drive" | "iclouddrive" | "com~apple~clouddocs"
+        ) {
+            return Some(CloudProvider::ICloudDrive);
+        }
+        // "Dropbox", "Dropbox (Team)" and ~/Library/CloudStorage/Dropbox-Team
+        if component == "dropbox"
+            || component.starts_with("dropbox (")
+            || component.starts_with("dropbox-")
+        {
+            return Some(CloudProvider::Dropbox);
+        }
+        // "OneDrive", "OneDrive - Company" and CloudStorage/OneDrive-Personal
+        if component == "onedrive"
+            || component.starts_with("onedrive - ")
+            || component.starts_with("onedrive-")
+        {
+            return Some(CloudProvider::OneDrive);
+        }
+        // "Google Drive", CloudStorage/GoogleDrive-<account> and the
+        // "My Drive" folder of Drive for desktop's virtual drive
+        if component == "google drive"
+            || component.starts_with("googledrive-")
+            || component == "my drive"
+        {
+            return Some(CloudProvider::GoogleDrive);
+        }
+        None
+    }
+}
+
+/// Sync service `path` is inside of, if any
+///
+/// Accepts `/` and `\` separators, so Windows paths are recognized on every
+/// platform.
+pub fn detect_cloud_provider(path: &str) -> Option<CloudProvider> {
+    path.split(['/', '\\'])
+        .filter(|component| !component.is_empty())
+        .find_map(CloudProvider::of_component)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_serializes_as_its_name() {
+        for provider in [
+            CloudProvider::ICloudDrive,
+            CloudProvider::Dropbox,
+            CloudProvider::OneDrive,
+            CloudProvider::GoogleDrive,
+        ] {
+            assert_eq!(
+                serde_json::to_value(provider).unwrap(),
+                serde_json::json!(provider.name())
+            );
+        }
+        // Reports saved before read back
+        let old: CloudProvider = serde_json::from_str("\"i_cloud_drive\"").unwrap();
+        assert_eq!(old, CloudProvider::ICloudDrive);
+    }
+
+    #[test]
+    fn test_detect_cloud_provider() {
+        let cases: &[(&str, Option<CloudProvider>)] = &[
+            (
+                "/Users/ana/Library/Mobile Documents/com~apple~CloudDocs/Notes",
+                Some(CloudProvider::ICloudDrive),
+            ),
+            (
+                "/Users/ana/Library/Mobile Documents/iCloud~md~obsidian/Documents/Vault",
+                Some(CloudProvider::ICloudDrive),
+            ),
+            (
+                r"C:\Users\ana\iCloudDrive\Notes",
+                Some(CloudProvider::ICloudDrive),
+            ),
+            ("/Users/ana/Dropbox/Notes", Some(CloudProvider::Dropbox)),
+            (
+                "/Users/ana/Dropbox (Acme)/Notes",
+                Some(CloudProvider::Dropbox),
+            ),
+            (
+                "/Users/ana/Library/CloudStorage/Dropbox-Acme/Notes",
+                Some(CloudProvider::Dropbox),
+            ),
+            (
+                r"C:\Users\ana\OneDrive\Notes",
+                Some(CloudProvider::OneDrive),
+            ),
+            (
+                r"C:\Users\ana\OneDrive - Acme Corp\Notes",
+                Some(CloudProvider::OneDrive),
+            ),
+            (
+                "/Users/ana/Library/CloudStorage/OneDrive-Personal/Notes",
+                Some(CloudProvider::OneDrive),
+            ),
+            (
+                "/Users/ana/Library/CloudStorage/GoogleDrive-ana@example.com/My Drive/Notes",
+                Some(CloudProvider::GoogleDrive),
+            ),
+            (r"G:\My Drive\Notes", Some(CloudProvider::GoogleDrive)),
+            (
+                "/Users/ana/Google Drive/Notes",
+                Some(CloudProvider::GoogleDrive),
+            ),
+            // Local folders, including lookalike names
+            ("/Users/ana/Documents/Kobo Highlights", None),
+            ("/Users/ana/Documents/Dropbox notes", None),
+            (r"\\nas\share\notes", None),
+            ("", None),
+        ];
+
+        for (path, expected) in cases {
+            assert_eq!(detect_cloud_provider(path), *expected, "{}", path);
+        }
+        assert_eq!(CloudProvider::ICloudDrive.name(), "iCloud Drive");
+    }
+}
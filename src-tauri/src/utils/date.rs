@@ -0,0 +1,242 @@
+//! Highlight timestamps across firmware versions
+//!
+//! `DateCreated` comes in several shapes: older firmware writes local time
+//! without a zone (`2025-01-24T10:15:30`), newer firmware fractional-second
+//! UTC (`2025-01-24T10:15:30.000Z`), and at least one beta wrote Unix epoch
+//! milliseconds as a string. Everything is normalized to UTC so highlights
+//! from several devices sort and group together.
+
+use crate::models::Book;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+
+/// Numeric values from this magnitude up are epoch milliseconds
+///
+/// 10^11 seconds is the year 5138, 10^11 milliseconds March 1973.
+const EPOCH_MILLIS_FROM: i64 = 100_000_000_000;
+
+/// Fewest digits of an epoch value (10^8 seconds is March 1973); shorter
+/// numbers are more likely years or garbage
+const EPOCH_MIN_DIGITS: usize = 9;
+
+/// Zone-less layouts, tried in order
+const LOCAL_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+];
+
+/// UTC, the offset assumed when none is configured
+pub fn utc_offset() -> FixedOffset {
+    FixedOffset::east_opt(0).expect("zero offset is valid")
+}
+
+/// Offset of `minutes` east of UTC; out-of-range values fall back to UTC
+pub fn assumed_offset(minutes: i32) -> FixedOffset {
+    minutes
+        .checked_mul(60)
+        .and_then(FixedOffset::east_opt)
+        .unwrap_or_else(|| {
+            log::warn!("[Date] Offset inválido ({} min), usando UTC", minutes);
+            utc_offset()
+        })
+}
+
+/// Parse a highlight timestamp into UTC
+///
+/// Accepts ISO 8601 with or without zone and fractional seconds,
+/// `YYYY-MM-DD HH:MM:SS`, bare dates (midnight) and Unix epoch seconds or
+/// milliseconds. Values without a zone are read as local time at
+/// `assumed_offset`.
+pub fn parse_timestamp(value: &str, assumed_offset: FixedOffset) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+
+    if value.len() >= EPOCH_MIN_DIGITS && value.bytes().all(|b| b.is_ascii_digit()) {
+        return parse_epoch(value);
+    }
+
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S%.f%z", "%Y-%m-%d %H:%M:%S%.f%z"] {
+        if let Ok(dt) = DateTime::parse_from_str(value, format) {
+            return Some(dt.with_timezone(&Utc));
+        }
+    }
+
+    LOCAL_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .and_then(|naive| assumed_offset.from_local_datetime(&naive).single())
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+fn parse_epoch(value: &str) -> Option<DateTime<Utc>> {
+    let number: i64 = value.parse().ok()?;
+    if number >= EPOCH_MILLIS_FROM {
+        DateTime::from_timestamp_millis(number)
+    } else {
+        DateTime::from_timestamp(number, 0)
+    }
+}
+
+/// Fixed-width RFC 3339 (`2025-01-24T10:15:30.000Z`), so stored values sort
+/// chronologically as text
+pub fn to_sortable(dt: &DateTime<Utc>) -> String {
+    dt.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Re-normalize every highlight's `date_created` at `assumed_offset`
+pub fn normalize_highlight_dates(books: &mut [Book], assumed_offset: FixedOffset) {
+    let mut unparsed = 0;
+    for highlight in books.iter_mut().flat_map(|book| book.highlights.iter_mut()) {
+        highlight.date_created_utc = parse_timestamp(&highlight.date_created, assumed_offset);
+        if highlight.date_created_utc.is_none() {
+            unparsed += 1;
+        }
+    }
+    if unparsed > 0 {
+        log::warn!("[Date] {} destaques com data não reconhecida", unparsed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Highlight;
+
+    fn utc(value: &str) -> Option<DateTime<Utc>> {
+        Some(
+            DateTime::parse_from_rfc3339(value)
+                .unwrap()
+                .with_timezone(&Utc),
+        )
+    }
+
+    #[test]
+    fn test_parse_timestamp_formats() {
+        let plus_two = assumed_offset(120);
+        let cases: &[(&str, FixedOffset, Option<DateTime<Utc>>)] = &[
+            // Zone-less local time (older firmware)
+            (
+                "2025-01-24T10:15:30",
+                utc_offset(),
+                utc("2025-01-24T10:15:30Z"),
+            ),
+            ("2025-01-24T10:15:30", plus_two, utc("2025-01-24T08:15:30Z")),
+            (
+                "2025-01-24T10:15:30.000",
+                utc_offset(),
+                utc("2025-01-24T10:15:30Z"),
+            ),
+            (
+                "2025-01-24T10:15:30.250",
+                plus_two,
+                utc("2025-01-24T08:15:30.250Z"),
+            ),
+            ("2025-01-24 10:15:30", plus_two, utc("2025-01-24T08:15:30Z")),
+            (
+                "2025-01-24 10:15:30.5",
+                utc_offset(),
+                utc("2025-01-24T10:15:30.500Z"),
+            ),
+            (
+                "2025-01-24T10:15",
+                utc_offset(),
+                utc("2025-01-24T10:15:00Z"),
+            ),
+            // Explicit zones ignore the assumed offset
+            (
+                "2025-01-24T10:15:30Z",
+                plus_two,
+                utc("2025-01-24T10:15:30Z"),
+            ),
+            (
+                "2025-01-24T10:15:30.000Z",
+                plus_two,
+                utc("2025-01-24T10:15:30Z"),
+            ),
+            (
+                "2025-01-24T10:15:30+01:00",
+                plus_two,
+                utc("2025-01-24T09:15:30Z"),
+            ),
+            (
+                "2025-01-24T10:15:30.123-03:00",
+                plus_two,
+                utc("2025-01-24T13:15:30.123Z"),
+            ),
+            (
+                "2025-01-24T10:15:30+0100",
+                plus_two,
+                utc("2025-01-24T09:15:30Z"),
+            ),
+            (
+                "  2025-01-24T10:15:30Z \n",
+                utc_offset(),
+                utc("2025-01-24T10:15:30Z"),
+            ),
+            // Bare dates are local midnight
+            ("2025-01-24", utc_offset(), utc("2025-01-24T00:00:00Z")),
+            ("2025-01-24", plus_two, utc("2025-01-23T22:00:00Z")),
+            // Epoch seconds and milliseconds (beta firmware)
+            ("1737713730", plus_two, utc("2025-01-24T10:15:30Z")),
+            ("1737713730250", plus_two, utc("2025-01-24T10:15:30.250Z")),
+            // Garbage
+            ("", utc_offset(), None),
+            ("Unknown", utc_offset(), None),
+            ("2025", utc_offset(), None),
+            ("2025-13-40T10:15:30", utc_offset(), None),
+            ("24/01/2025", utc_offset(), None),
+            ("-1737713730", utc_offset(), None),
+            ("99999999999999999999999", utc_offset(), None),
+        ];
+
+        for (value, offset, expected) in cases {
+            assert_eq!(
+                parse_timestamp(value, *offset),
+                *expected,
+                "{:?} at {}",
+                value,
+                offset
+            );
+        }
+    }
+
+    #[test]
+    fn test_normalize_keeps_raw_values() {
+        let mut book = Book::new("book".into(), "Book".into(), "Author".into());
+        book.highlights = vec![
+            Highlight::new("a".into(), "A".into(), "2025-01-24T10:15:30".into()),
+            Highlight::new("b".into(), "B".into(), "1737713730000".into()),
+            Highlight::new("c".into(), "C".into(), "garbage".into()),
+        ];
+        let mut books = vec![book];
+
+        normalize_highlight_dates(&mut books, assumed_offset(-300));
+
+        let highlights = &books[0].highlights;
+        assert_eq!(highlights[0].date_created, "2025-01-24T10:15:30");
+        assert_eq!(highlights[0].date_created_utc, utc("2025-01-24T15:15:30Z"));
+        assert_eq!(highlights[1].date_created_utc, utc("2025-01-24T10:15:30Z"));
+        assert_eq!(highlights[2].date_created, "garbage");
+        assert_eq!(highlights[2].date_created_utc, None);
+        assert_eq!(
+            to_sortable(&highlights[0].date_created_utc.unwrap()),
+            "2025-01-24T15:15:30.000Z"
+        );
+
+        assert_eq!(assumed_offset(i32::MAX), utc_offset());
+        assert_eq!(assumed_offset(24 * 60), utc_offset());
+    }
+}
@@ -0,0 +1,130 @@
+//! Books that share a title and author
+//!
+//! Two volumes of collected essays can both be "Essays" by the same author.
+//! They sort in a total order (title, author, last read, content ID), and
+//! each gets a disambiguator — its publication year when that alone tells
+//! it apart, its slug otherwise — that exports append to filenames and the
+//! UI shows next to the title.
+
+use crate::models::Book;
+use crate::utils::slug::book_slug;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Title and author as compared for siblings: trimmed, whitespace collapsed
+/// and lowercased
+pub fn title_author_key(book: &Book) -> (String, String) {
+    let normalize = |value: &str| {
+        value
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+    (normalize(&book.title), normalize(&book.author))
+}
+
+/// Total order of books: title, author, `date_last_read`, then content ID
+pub fn book_order(a: &Book, b: &Book) -> Ordering {
+    a.title
+        .cmp(&b.title)
+        .then_with(|| a.author.cmp(&b.author))
+        .then_with(|| a.date_last_read.cmp(&b.date_last_read))
+        .then_with(|| a.content_id.cmp(&b.content_id))
+}
+
+/// Sort `books` by `book_order`
+pub fn sort_books(books: &mut [Book]) {
+    books.sort_by(book_order);
+}
+
+/// Disambiguator of each book, `None` for books without a same-title-author
+/// sibling in `books`
+pub fn disambiguators(books: &[Book]) -> Vec<Option<String>> {
+    let mut groups: HashMap<(String, String), Vec<usize>> = HashMap::new();
+    for (index, book) in books.iter().enumerate() {
+        groups
+            .entry(title_author_key(book))
+            .or_default()
+            .push(index);
+    }
+
+    let mut result = vec![None; books.len()];
+    for siblings in groups.values().filter(|siblings| siblings.len() > 1) {
+        for &index in siblings {
+            let book = &books[index];
+            let year_is_unique = book.publication_year.is_some_and(|year| {
+                siblings
+                    .iter()
+                    .filter(|&&other| books[other].publication_year == Some(year))
+                    .count()
+                    == 1
+            });
+            result[index] = Some(match book.publication_year {
+                Some(year) if year_is_unique => year.to_string(),
+                _ if book.slug.is_empty() => book_slug(&book.content_id, &book.title),
+                _ => book.slug.clone(),
+            });
+        }
+    }
+    result
+}
+
+/// Set every book's `disambiguator` for the batch `books`
+pub fn assign_disambiguators(books: &mut [Book]) {
+    let disambiguators = disambiguators(books);
+    for (book, disambiguator) in books.iter_mut().zip(disambiguators) {
+        book.disambiguator = disambiguator;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn essays(content_id: &str, year: Option<i32>) -> Book {
+        let mut book = Book::new(
+            content_id.to_string(),
+            "Essays".to_string(),
+            "Michel de Montaigne".to_string(),
+        );
+        book.publication_year = year;
+        book
+    }
+
+    #[test]
+    fn test_same_title_author_books_order_and_disambiguators() {
+        let mut books = vec![
+            essays("vol-b", Some(2021)),
+            Book::new(
+                "vol-c".to_string(),
+                "Dune".to_string(),
+                "Frank Herbert".to_string(),
+            ),
+            essays("vol-a", Some(2019)),
+        ];
+        let mut reversed: Vec<Book> = books.iter().rev().cloned().collect();
+        sort_books(&mut books);
+        sort_books(&mut reversed);
+        let ids = |books: &[Book]| -> Vec<String> {
+            books.iter().map(|b| b.content_id.clone()).collect()
+        };
+        assert_eq!(ids(&books), vec!["vol-c", "vol-a", "vol-b"]);
+        assert_eq!(ids(&reversed), ids(&books));
+
+        assign_disambiguators(&mut books);
+        let labels: Vec<Option<&str>> = books.iter().map(|b| b.disambiguator.as_deref()).collect();
+        assert_eq!(labels, vec![None, Some("2019"), Some("2021")]);
+
+        // Without telling years apart, the slug does
+        books[2].publication_year = Some(2019);
+        books[2].slug = "essays-2".to_string();
+        assign_disambiguators(&mut books);
+        assert_eq!(books[1].disambiguator, Some(book_slug("vol-a", "Essays")));
+        assert_eq!(books[2].disambiguator.as_deref(), Some("essays-2"));
+
+        // A book alone in its batch needs none
+        assign_disambiguators(&mut books[..2]);
+        assert_eq!(books[1].disambiguator, None);
+    }
+}
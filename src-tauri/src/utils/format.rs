@@ -0,0 +1,67 @@
+//! Display forms of reading statistics
+//!
+//! Kobo records how far a book was read (`___PercentRead`, 0–100) and the
+//! seconds spent reading it (`TimeSpentReading`). The raw values are
+//! normalized once, on extraction (`reading_percent`, `reading_secs`), and
+//! rendered with `format_percent` and `format_duration_hm` wherever they are
+//! shown, so the export and the stats command agree. Units are not localized
+//! until exports have a language of their own.
+
+/// Percent read from a raw value: clamped to 0–100, `None` when not finite
+pub fn reading_percent(raw: Option<f64>) -> Option<f64> {
+    raw.filter(|value| value.is_finite())
+        .map(|value| value.clamp(0.0, 100.0))
+}
+
+/// Seconds spent reading from a raw value: `None` unless positive, as the
+/// device stores 0 for books never opened
+pub fn reading_secs(raw: Option<i64>) -> Option<u64> {
+    raw.filter(|secs| *secs > 0).map(|secs| secs as u64)
+}
+
+/// Whole percent, e.g. "86%" (values are clamped to 0–100)
+pub fn format_percent(percent: f64) -> String {
+    let percent = reading_percent(Some(percent)).unwrap_or(0.0);
+    format!("{}%", percent.round() as u8)
+}
+
+/// Hours and minutes, e.g. "4 h 32 m"; hours don't roll over into days,
+/// and durations under a minute are "0 m"
+pub fn format_duration_hm(secs: u64) -> String {
+    let hours = secs / 3600;
+    let minutes = secs % 3600 / 60;
+    match (hours, minutes) {
+        (0, minutes) => format!("{} m", minutes),
+        (hours, 0) => format!("{} h", hours),
+        (hours, minutes) => format!("{} h {} m", hours, minutes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reading_stats_boundaries_and_missing_values() {
+        assert_eq!(format_duration_hm(0), "0 m");
+        assert_eq!(format_duration_hm(59), "0 m");
+        assert_eq!(format_duration_hm(3599), "59 m");
+        assert_eq!(format_duration_hm(3600), "1 h");
+        assert_eq!(format_duration_hm(4 * 3600 + 32 * 60 + 10), "4 h 32 m");
+        assert_eq!(format_duration_hm(27 * 3600 + 5 * 60), "27 h 5 m");
+
+        assert_eq!(format_percent(0.0), "0%");
+        assert_eq!(format_percent(85.6), "86%");
+        assert_eq!(format_percent(100.0), "100%");
+        assert_eq!(format_percent(140.0), "100%");
+        assert_eq!(format_percent(-3.0), "0%");
+
+        assert_eq!(reading_percent(None), None);
+        assert_eq!(reading_percent(Some(f64::NAN)), None);
+        assert_eq!(reading_percent(Some(101.0)), Some(100.0));
+        assert_eq!(reading_secs(None), None);
+        assert_eq!(reading_secs(Some(0)), None);
+        assert_eq!(reading_secs(Some(-20)), None);
+        assert_eq!(reading_secs(Some(3600)), Some(3600));
+    }
+}
@@ -0,0 +1,454 @@
+//! Crash-safe file writes
+//!
+//! Content goes to `<name>.tmp` next to the target, is synced to disk and
+//! then renamed over the target, so a crash or a full disk mid-write leaves
+//! the previous file intact instead of a truncated one. `atomic_write_text`
+//! adds a byte order mark and CRLF line endings on request, for text files
+//! opened in Excel or legacy note tools.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// File operations used by atomic writes, swappable in tests to simulate
+/// failures
+#[cfg_attr(test, mockall::automock)]
+pub trait FileOps: Send + Sync {
+    /// Create (or truncate) `path` for writing
+    fn create(&self, path: &Path) -> io::Result<File>;
+    /// Replace `to` with `from`
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+}
+
+/// The real filesystem
+pub struct SystemFileOps;
+
+impl FileOps for SystemFileOps {
+    fn create(&self, path: &Path) -> io::Result<File> {
+        File::create(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+}
+
+/// Waits before each retry of a rename blocked by a sync daemon
+pub const RENAME_RETRY_DELAYS: &[Duration] = &[
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+];
+
+/// Whether a failed rename is worth retrying: sync daemons holding the
+/// target open cause `EPERM`/`EACCES` or `EBUSY`
+pub fn is_transient_rename_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::PermissionDenied | io::ErrorKind::ResourceBusy
+    )
+}
+
+/// `FileOps` for cloud-synced folders: renames are retried while a sync
+/// daemon blocks them, then given `settle` before the next write
+pub struct CloudSyncOps<'a> {
+    inner: &'a dyn FileOps,
+    retry_delays: &'a [Duration],
+    settle: Duration,
+}
+
+impl<'a> CloudSyncOps<'a> {
+    pub fn new(inner: &'a dyn FileOps, retry_delays: &'a [Duration], settle: Duration) -> Self {
+        Self {
+            inner,
+            retry_delays,
+            settle,
+        }
+    }
+}
+
+impl FileOps for CloudSyncOps<'_> {
+    fn create(&self, path: &Path) -> io::Result<File> {
+        self.inner.create(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut delays = self.retry_delays.iter();
+        loop {
+            match self.inner.rename(from, to) {
+                Ok(()) => break,
+                Err(e) if is_transient_rename_error(&e) => match delays.next() {
+                    Some(delay) => {
+                        log::warn!("[FS] Rename de {:?} bloqueado ({}), a repetir", to, e);
+                        std::thread::sleep(*delay);
+                    }
+                    None => return Err(e),
+                },
+                Err(e) => return Err(e),
+            }
+        }
+        if !self.settle.is_zero() {
+            std::thread::sleep(self.settle);
+        }
+        Ok(())
+    }
+}
+
+/// Temporary sibling of `path` used while writing it (`notes.md.tmp`)
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Whether an error means the disk (or the user's quota) is full
+pub fn is_disk_full(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded
+    )
+}
+
+/// Atomically replace `path` with `bytes`
+pub fn atomic_write(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    atomic_write_with(&SystemFileOps, path, |writer| writer.write_all(bytes))
+}
+
+/// Atomically replace `path` with whatever `write` produces
+///
+/// The writer is buffered; on any error the temporary file is removed and
+/// the previous file left untouched.
+pub fn atomic_write_with<T>(
+    ops: &dyn FileOps,
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<T>,
+) -> io::Result<T> {
+    let temp = temp_path(path);
+    let result = write_and_rename(ops, path, &temp, write);
+    if result.is_err() {
+        if let Err(e) = fs::remove_file(&temp) {
+            if e.kind() != io::ErrorKind::NotFound {
+                log::warn!("Failed to remove temporary file {:?}: {}", temp, e);
+            }
+        }
+    }
+    result
+}
+
+/// UTF-8 byte order mark
+pub const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Line endings of written text files
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEndings {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+/// Byte-level form of a written text file
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TextEncoding {
+    /// Start with a UTF-8 byte order mark
+    #[serde(default)]
+    pub bom: bool,
+    #[serde(default, alias = "line_endings")]
+    pub line_endings: LineEndings,
+}
+
+/// Writer applying a `TextEncoding` to the UTF-8 text written through it
+///
+/// A BOM at the start of the text is dropped (the encoding decides whether
+/// the file gets one), and with `CrLf` every `\n` not already preceded by
+/// `\r` becomes `\r\n`. Text must arrive in whole characters, as
+/// `fmt::Write` passes it.
+pub struct TextEncoder<W: Write> {
+    inner: W,
+    encoding: TextEncoding,
+    /// Bytes of text received, and written out
+    received: u64,
+    written: u64,
+    /// Length of the BOM dropped from the text
+    dropped: u64,
+    last_cr: bool,
+    /// Text offsets of the `\n` given a `\r`
+    expanded: Vec<u64>,
+}
+
+impl<W: Write> TextEncoder<W> {
+    /// Wrap `inner`, writing the BOM right away when the encoding has one
+    pub fn new(mut inner: W, encoding: TextEncoding) -> io::Result<Self> {
+        let mut written = 0;
+        if encoding.bom {
+            inner.write_all(UTF8_BOM)?;
+            written = UTF8_BOM.len() as u64;
+        }
+        Ok(Self {
+            inner,
+            encoding,
+            received: 0,
+            written,
+            dropped: 0,
+            last_cr: false,
+            expanded: Vec::new(),
+        })
+    }
+
+    /// Bytes written to the inner writer, BOM included
+    pub fn bytes(&self) -> u64 {
+        self.written
+    }
+
+    /// Where byte `offset` of the text ended up in the output
+    pub fn encoded_offset(&self, offset: u64) -> u64 {
+        let offset = offset.saturating_sub(self.dropped);
+        let bom = if self.encoding.bom {
+            UTF8_BOM.len() as u64
+        } else {
+            0
+        };
+        offset + bom + self.expanded.partition_point(|&lf| lf < offset) as u64
+    }
+}
+
+impl<W: Write> Write for TextEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut text = buf;
+        if self.received == 0 && text.starts_with(UTF8_BOM) {
+            text = &text[UTF8_BOM.len()..];
+            self.dropped = UTF8_BOM.len() as u64;
+        }
+        if self.encoding.line_endings == LineEndings::CrLf {
+            let mut start = 0;
+            for (index, &byte) in text.iter().enumerate() {
+                let after_cr = if index == 0 {
+                    self.last_cr
+                } else {
+                    text[index - 1] == b'\r'
+                };
+                if byte == b'\n' && !after_cr {
+                    self.inner.write_all(&text[start..index])?;
+                    self.inner.write_all(b"\r")?;
+                    self.expanded.push(self.received + index as u64);
+                    self.written += 1;
+                    start = index;
+                }
+            }
+            self.inner.write_all(&text[start..])?;
+        } else {
+            self.inner.write_all(text)?;
+        }
+        if let Some(&last) = text.last() {
+            self.last_cr = last == b'\r';
+        }
+        self.received += text.len() as u64;
+        self.written += text.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// `atomic_write_with`, passing what `write` produces through a
+/// `TextEncoder`
+pub fn atomic_write_text<T>(
+    ops: &dyn FileOps,
+    path: &Path,
+    encoding: TextEncoding,
+    write: impl FnOnce(&mut TextEncoder<&mut BufWriter<File>>) -> io::Result<T>,
+) -> io::Result<T> {
+    atomic_write_with(ops, path, |writer| {
+        let mut encoder = TextEncoder::new(writer, encoding)?;
+        write(&mut encoder)
+    })
+}
+
+fn write_and_rename<T>(
+    ops: &dyn FileOps,
+    path: &Path,
+    temp: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<T>,
+) -> io::Result<T> {
+    let mut writer = BufWriter::new(ops.create(temp)?);
+    let value = write(&mut writer)?;
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    drop(file);
+    ops.rename(temp, path)?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn failing_rename(error: fn() -> io::Error) -> MockFileOps {
+        let mut ops = MockFileOps::new();
+        ops.expect_create().returning(|path| File::create(path));
+        ops.expect_rename().returning(move |_, _| Err(error()));
+        ops
+    }
+
+    #[test]
+    fn test_cloud_rename_retries_busy_target() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("notes.md");
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        let mut ops = MockFileOps::new();
+        ops.expect_create().returning(|path| File::create(path));
+        let counter = attempts.clone();
+        ops.expect_rename().returning(move |from, to| {
+            if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                Err(io::Error::from(io::ErrorKind::ResourceBusy))
+            } else {
+                fs::rename(from, to)
+            }
+        });
+        let delays = [Duration::ZERO; 3];
+        let cloud = CloudSyncOps::new(&ops, &delays, Duration::ZERO);
+
+        atomic_write_with(&cloud, &path, |writer| writer.write_all(b"synced")).unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "synced");
+
+        // Out of retries: the error surfaces and the temporary file is gone
+        let busy = failing_rename(|| io::Error::from(io::ErrorKind::PermissionDenied));
+        let cloud = CloudSyncOps::new(&busy, &delays[..1], Duration::ZERO);
+        let err = atomic_write_with(&cloud, &path, |writer| writer.write_all(b"lost")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "synced");
+        assert!(!temp_path(&path).exists());
+
+        // Other failures aren't retried
+        assert!(!is_transient_rename_error(&io::Error::from(
+            io::ErrorKind::NotFound
+        )));
+    }
+
+    #[test]
+    fn test_atomic_write_replaces_file() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("notes.md");
+        fs::write(&path, "old").unwrap();
+
+        atomic_write(&path, b"new").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!temp_path(&path).exists());
+        assert_eq!(temp_path(&path), temp.path().join("notes.md.tmp"));
+    }
+
+    fn encode(encoding: TextEncoding, pieces: &[&str]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut encoder = TextEncoder::new(&mut out, encoding).unwrap();
+        for piece in pieces {
+            encoder.write_all(piece.as_bytes()).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn test_text_encoding_round_trips() {
+        let crlf = TextEncoding {
+            bom: false,
+            line_endings: LineEndings::CrLf,
+        };
+        let bom = TextEncoding {
+            bom: true,
+            ..Default::default()
+        };
+
+        // Defaults leave the bytes alone
+        let text = "título;autor\nWalden;Thoreau\n";
+        assert_eq!(encode(TextEncoding::default(), &[text]), text.as_bytes());
+        assert_eq!(encode(bom, &[text]), [UTF8_BOM, text.as_bytes()].concat());
+        // One BOM, whether or not the text brought its own
+        let with_bom = format!("\u{feff}{}", text);
+        assert_eq!(encode(bom, &[&with_bom]), encode(bom, &[text]));
+        assert_eq!(
+            encode(TextEncoding::default(), &[&with_bom]),
+            text.as_bytes()
+        );
+
+        // Existing CRLF kept, also across writes
+        assert_eq!(
+            encode(crlf, &["a\nb\r", "\nc\r\n", "\n"]),
+            b"a\r\nb\r\nc\r\n\r\n"
+        );
+        let both = TextEncoding {
+            bom: true,
+            line_endings: LineEndings::CrLf,
+        };
+        assert_eq!(encode(both, &["x\n"]), b"\xEF\xBB\xBFx\r\n");
+
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("highlights.csv");
+        let bytes = atomic_write_text(&SystemFileOps, &path, both, |w| {
+            w.write_all(b"a\nb\n")?;
+            Ok(w.bytes())
+        })
+        .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"\xEF\xBB\xBFa\r\nb\r\n");
+        assert_eq!(bytes, 9);
+    }
+
+    #[test]
+    fn test_encoded_offsets_follow_the_output() {
+        let encoding = TextEncoding {
+            bom: true,
+            line_endings: LineEndings::CrLf,
+        };
+        let mut out = Vec::new();
+        let mut encoder = TextEncoder::new(&mut out, encoding).unwrap();
+        let text = "# T\n\n> one\n> two\n";
+        encoder.write_all(text.as_bytes()).unwrap();
+        let start = text.find("> two").unwrap() as u64;
+        let (from, to) = (
+            encoder.encoded_offset(start) as usize,
+            encoder.encoded_offset(text.len() as u64) as usize,
+        );
+        assert_eq!(to, out.len());
+        assert_eq!(&out[from..to], b"> two\r\n");
+    }
+
+    #[test]
+    fn test_failure_before_rename_keeps_original() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("notes.md");
+        fs::write(&path, "good export").unwrap();
+
+        let ops = failing_rename(|| io::Error::other("crash before rename"));
+        let result = atomic_write_with(&ops, &path, |w| w.write_all(b"half"));
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "good export");
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn test_failed_write_keeps_original() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("notes.md");
+        fs::write(&path, "good export").unwrap();
+
+        let result = atomic_write_with(&SystemFileOps, &path, |w| {
+            w.write_all(b"partial")?;
+            Err::<(), _>(io::Error::from(io::ErrorKind::StorageFull))
+        });
+
+        assert!(is_disk_full(&result.unwrap_err()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "good export");
+        assert!(!temp_path(&path).exists());
+    }
+}
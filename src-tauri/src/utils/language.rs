@@ -0,0 +1,190 @@
+//! Language code normalization
+//!
+//! The Kobo database stores languages in inconsistent forms (`en-US`, `eng`,
+//! `pt_PT`, `EN`). These helpers reduce them to ISO 639-1 two-letter codes.
+
+use crate::models::{Book, LanguageStats};
+use std::collections::HashMap;
+
+/// Folder/label used for books without a recognizable language
+pub const UNKNOWN_LANGUAGE: &str = "unknown";
+
+/// ISO 639-2 codes (bibliographic and terminology variants) mapped to ISO 639-1
+const THREE_LETTER_CODES: &[(&str, &str)] = &[
+    ("ara", "ar"),
+    ("baq", "eu"),
+    ("cat", "ca"),
+    ("ces", "cs"),
+    ("chi", "zh"),
+    ("cze", "cs"),
+    ("dan", "da"),
+    ("deu", "de"),
+    ("dut", "nl"),
+    ("ell", "el"),
+    ("eng", "en"),
+    ("eus", "eu"),
+    ("fin", "fi"),
+    ("fra", "fr"),
+    ("fre", "fr"),
+    ("ger", "de"),
+    ("glg", "gl"),
+    ("gre", "el"),
+    ("heb", "he"),
+    ("hun", "hu"),
+    ("ita", "it"),
+    ("jpn", "ja"),
+    ("kor", "ko"),
+    ("nld", "nl"),
+    ("nob", "nb"),
+    ("nor", "no"),
+    ("pol", "pl"),
+    ("por", "pt"),
+    ("ron", "ro"),
+    ("rum", "ro"),
+    ("rus", "ru"),
+    ("spa", "es"),
+    ("swe", "sv"),
+    ("tur", "tr"),
+    ("ukr", "uk"),
+    ("zho", "zh"),
+];
+
+/// Normalize a raw language value to a lowercase ISO 639-1 code
+///
+/// Region suffixes are dropped (`en-US`, `pt_PT`) and known three-letter
+/// codes are mapped to their two-letter form. Returns `None` when the value
+/// can't be recognized.
+pub fn normalize_language_code(raw: &str) -> Option<String> {
+    let lower = raw.trim().to_lowercase();
+    let primary = lower.split(['-', '_']).next().unwrap_or("");
+
+    if !primary.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    match primary.len() {
+        2 => Some(primary.to_string()),
+        3 => THREE_LETTER_CODES
+            .iter()
+            .find(|(three, _)| *three == primary)
+            .map(|(_, two)| two.to_string()),
+        _ => None,
+    }
+}
+
+/// Folder name for a book language: uppercase ISO 639-1 code or `unknown`
+pub fn language_folder_name(raw: Option<&str>) -> String {
+    raw.and_then(normalize_language_code)
+        .map(|code| code.to_uppercase())
+        .unwrap_or_else(|| UNKNOWN_LANGUAGE.to_string())
+}
+
+/// Whether `name` is a folder name `language_folder_name` gives
+pub fn is_language_folder_name(name: &str) -> bool {
+    name == UNKNOWN_LANGUAGE
+        || normalize_language_code(name).is_some_and(|code| code.to_uppercase() == name)
+}
+
+/// Count books and highlights per normalized language
+///
+/// Sorted by book count (descending), then by language code.
+pub fn language_breakdown(books: &[Book]) -> Vec<LanguageStats> {
+    let mut counts: HashMap<String, (usize, usize)> = HashMap::new();
+
+    for book in books {
+        let language = language_folder_name(book.language.as_deref());
+        let entry = counts.entry(language).or_insert((0, 0));
+        entry.0 += 1;
+        entry.1 += book.highlights.len();
+    }
+
+    let mut stats: Vec<LanguageStats> = counts
+        .into_iter()
+        .map(
+            |(language, (books_count, highlights_count))| LanguageStats {
+                language,
+                books_count,
+                highlights_count,
+            },
+        )
+        .collect();
+
+    stats.sort_by(|a, b| {
+        b.books_count
+            .cmp(&a.books_count)
+            .then_with(|| a.language.cmp(&b.language))
+    });
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Highlight;
+
+    #[test]
+    fn test_normalize_language_code_table() {
+        let cases = [
+            ("en", Some("en")),
+            ("EN", Some("en")),
+            ("en-US", Some("en")),
+            ("en_GB", Some("en")),
+            ("pt_PT", Some("pt")),
+            ("pt-BR", Some("pt")),
+            ("eng", Some("en")),
+            ("por", Some("pt")),
+            ("ger", Some("de")),
+            ("deu", Some("de")),
+            ("fre", Some("fr")),
+            (" de ", Some("de")),
+            ("", None),
+            ("xyz", None),
+            ("english", None),
+            ("e1", None),
+        ];
+
+        for (raw, expected) in cases {
+            assert_eq!(
+                normalize_language_code(raw).as_deref(),
+                expected,
+                "input: {:?}",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn test_language_folder_name() {
+        assert_eq!(language_folder_name(Some("en-US")), "EN");
+        assert_eq!(language_folder_name(Some("por")), "PT");
+        assert_eq!(language_folder_name(Some("???")), "unknown");
+        assert_eq!(language_folder_name(None), "unknown");
+    }
+
+    #[test]
+    fn test_language_breakdown() {
+        let mut en1 = Book::new("1".to_string(), "A".to_string(), "X".to_string());
+        en1.language = Some("en-US".to_string());
+        en1.add_highlight(Highlight::new("h1".into(), "t".into(), "d".into()));
+        en1.add_highlight(Highlight::new("h2".into(), "t".into(), "d".into()));
+
+        let mut en2 = Book::new("2".to_string(), "B".to_string(), "X".to_string());
+        en2.language = Some("eng".to_string());
+        en2.add_highlight(Highlight::new("h3".into(), "t".into(), "d".into()));
+
+        let mut pt = Book::new("3".to_string(), "C".to_string(), "X".to_string());
+        pt.language = Some("pt_PT".to_string());
+
+        let none = Book::new("4".to_string(), "D".to_string(), "X".to_string());
+
+        let stats = language_breakdown(&[en1, en2, pt, none]);
+
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[0].language, "EN");
+        assert_eq!(stats[0].books_count, 2);
+        assert_eq!(stats[0].highlights_count, 3);
+        assert_eq!(stats[1].language, "PT");
+        assert_eq!(stats[2].language, "unknown");
+    }
+}
@@ -2,14 +2,18 @@ use chrono::Local;
 use log::{Level, Metadata, Record};
 use std::fs::{self, File, OpenOptions};
 use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 
 static LOGGER: SimpleLogger = SimpleLogger {
     file: Mutex::new(None),
+    stdout: AtomicBool::new(true),
 };
 
 struct SimpleLogger {
     file: Mutex<Option<File>>,
+    /// Echo to stdout (off when stdout carries machine-readable output)
+    stdout: AtomicBool,
 }
 
 impl log::Log for SimpleLogger {
@@ -29,7 +33,9 @@ impl log::Log for SimpleLogger {
             );
 
             // Print to stdout anyway (for dev)
-            print!("{}", msg);
+            if self.stdout.load(Ordering::Relaxed) {
+                print!("{}", msg);
+            }
 
             // Write to file if available
             if let Ok(mut lock) = self.file.lock() {
@@ -44,6 +50,16 @@ impl log::Log for SimpleLogger {
 }
 
 pub fn init() -> Result<(), String> {
+    init_with_stdout(true)
+}
+
+/// Log to the file only, keeping stdout free (headless sync)
+pub fn init_file_only() -> Result<(), String> {
+    init_with_stdout(false)
+}
+
+fn init_with_stdout(stdout: bool) -> Result<(), String> {
+    LOGGER.stdout.store(stdout, Ordering::Relaxed);
     let log_dir = dirs::home_dir()
         .ok_or("Could not find home directory")?
         .join("Library/Logs/com.bruno.kobo-highlights-exporter");
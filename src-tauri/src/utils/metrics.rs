@@ -0,0 +1,430 @@
+//! Lightweight timing metrics
+//!
+//! Commands time their steps (`db_extract`, `covers`, `render`, `write`, …)
+//! with `Metrics::span`. An ending span is sent down a channel rather than
+//! taking a lock, so threads rendering in parallel never wait on each other;
+//! the spans are gathered when the summary is taken.
+//! Each run's `MetricsSummary` is attached to its result and logged as one
+//! line; `SessionMetrics` accumulates runs for `get_session_metrics`.
+
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::Instant;
+
+/// Span durations kept per name for session percentiles
+const MAX_SESSION_SAMPLES: usize = 10_000;
+
+thread_local! {
+    /// Names of the spans open on this thread, innermost last
+    static OPEN_SPANS: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Debug, Default)]
+struct SpanRecord {
+    parent: Option<&'static str>,
+    durations_us: Vec<u64>,
+}
+
+/// A span that ended: name, parent and duration in microseconds
+type EndedSpan = (&'static str, Option<&'static str>, u64);
+
+/// Spans and counters of one command run
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    /// Where spans go when they end
+    ended: mpsc::Sender<EndedSpan>,
+    /// Ended spans not yet gathered into `spans`
+    pending: Mutex<mpsc::Receiver<EndedSpan>>,
+    spans: Mutex<BTreeMap<&'static str, SpanRecord>>,
+    counters: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let (ended, pending) = mpsc::channel();
+        Self {
+            started: Instant::now(),
+            ended,
+            pending: Mutex::new(pending),
+            spans: Mutex::new(BTreeMap::new()),
+            counters: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Start timing `name`; the span is recorded when the guard drops
+    ///
+    /// A span opened while another is open on the same thread is recorded
+    /// as its child.
+    pub fn span(&self, name: &'static str) -> Span<'_> {
+        let parent = OPEN_SPANS.with(|open| {
+            let mut open = open.borrow_mut();
+            let parent = open.last().copied();
+            open.push(name);
+            parent
+        });
+        Span {
+            metrics: self,
+            name,
+            parent,
+            started: Instant::now(),
+        }
+    }
+
+    /// Add `amount` to the counter `name` (bytes written, books, …)
+    pub fn add(&self, name: &'static str, amount: u64) {
+        *self
+            .counters
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(name)
+            .or_default() += amount;
+    }
+
+    fn record(&self, name: &'static str, parent: Option<&'static str>, duration_us: u64) {
+        // The receiver lives as long as `self`, so this can't fail
+        let _ = self.ended.send((name, parent, duration_us));
+    }
+
+    /// The recorded spans, with the ones ended since last time gathered in
+    fn gathered_spans(&self) -> std::sync::MutexGuard<'_, BTreeMap<&'static str, SpanRecord>> {
+        let mut spans = self.spans.lock().unwrap_or_else(|e| e.into_inner());
+        let pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        for (name, parent, duration_us) in pending.try_iter() {
+            let record = spans.entry(name).or_default();
+            record.parent = record.parent.or(parent);
+            record.durations_us.push(duration_us);
+        }
+        spans
+    }
+
+    /// Aggregate of everything recorded so far
+    pub fn summary(&self) -> MetricsSummary {
+        let spans = self.gathered_spans();
+        let counters = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        MetricsSummary {
+            total_ms: self.started.elapsed().as_millis() as u64,
+            spans: spans
+                .iter()
+                .map(|(name, record)| SpanSummary {
+                    name: name.to_string(),
+                    parent: record.parent.map(str::to_string),
+                    count: record.durations_us.len() as u64,
+                    total_us: record.durations_us.iter().sum(),
+                    max_us: record.durations_us.iter().copied().max().unwrap_or(0),
+                    durations_us: record.durations_us.clone(),
+                })
+                .collect(),
+            counters: counters
+                .iter()
+                .map(|(name, value)| (name.to_string(), *value))
+                .collect(),
+        }
+    }
+
+    /// The summary, clearing the recorded spans and counters
+    pub fn take(&self) -> MetricsSummary {
+        let summary = self.summary();
+        self.spans.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        self.counters
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+        summary
+    }
+}
+
+/// A running span, recorded on drop
+pub struct Span<'a> {
+    metrics: &'a Metrics,
+    name: &'static str,
+    parent: Option<&'static str>,
+    started: Instant,
+}
+
+impl Drop for Span<'_> {
+    fn drop(&mut self) {
+        let duration_us = self.started.elapsed().as_micros() as u64;
+        OPEN_SPANS.with(|open| {
+            let mut open = open.borrow_mut();
+            if let Some(index) = open.iter().rposition(|name| *name == self.name) {
+                open.remove(index);
+            }
+        });
+        self.metrics.record(self.name, self.parent, duration_us);
+    }
+}
+
+/// One span name of a run
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SpanSummary {
+    pub name: String,
+    /// Span that was open around this one (on the same thread)
+    pub parent: Option<String>,
+    pub count: u64,
+    pub total_us: u64,
+    pub max_us: u64,
+    /// Individual durations, for session percentiles
+    #[serde(skip)]
+    pub durations_us: Vec<u64>,
+}
+
+/// Where the time of one run went
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsSummary {
+    pub total_ms: u64,
+    pub spans: Vec<SpanSummary>,
+    pub counters: BTreeMap<String, u64>,
+}
+
+impl MetricsSummary {
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty() && self.counters.is_empty()
+    }
+
+    pub fn span(&self, name: &str) -> Option<&SpanSummary> {
+        self.spans.iter().find(|span| span.name == name)
+    }
+
+    /// One log line: `import 412ms: db_extract 120.0ms, covers 250.3ms ×35, …`
+    pub fn log_line(&self, label: &str) -> String {
+        let mut parts: Vec<String> = self
+            .spans
+            .iter()
+            .map(|span| {
+                let ms = span.total_us as f64 / 1000.0;
+                if span.count > 1 {
+                    format!("{} {:.1}ms ×{}", span.name, ms, span.count)
+                } else {
+                    format!("{} {:.1}ms", span.name, ms)
+                }
+            })
+            .collect();
+        parts.extend(
+            self.counters
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value)),
+        );
+        format!("{} {}ms: {}", label, self.total_ms, parts.join(", "))
+    }
+}
+
+/// Percentiles of one span name over the session
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SpanPercentiles {
+    pub name: String,
+    pub count: u64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+}
+
+/// Cumulative metrics since the app started, for `get_session_metrics`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionMetricsReport {
+    pub uptime_secs: u64,
+    pub total_imports: u64,
+    pub total_exports: u64,
+    pub total_export_bytes: u64,
+    /// Renders served from the render cache (previews, diffs and exports),
+    /// and rendered afresh
+    #[serde(default)]
+    pub preview_cache_hits: u64,
+    #[serde(default)]
+    pub preview_cache_misses: u64,
+    pub spans: Vec<SpanPercentiles>,
+}
+
+/// Managed state accumulating every run of the session
+pub struct SessionMetrics {
+    started: Instant,
+    imports: AtomicU64,
+    exports: AtomicU64,
+    export_bytes: AtomicU64,
+    preview_hits: AtomicU64,
+    preview_misses: AtomicU64,
+    samples: Mutex<BTreeMap<String, Vec<u64>>>,
+}
+
+impl Default for SessionMetrics {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            imports: AtomicU64::new(0),
+            exports: AtomicU64::new(0),
+            export_bytes: AtomicU64::new(0),
+            preview_hits: AtomicU64::new(0),
+            preview_misses: AtomicU64::new(0),
+            samples: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl SessionMetrics {
+    pub fn record_import(&self, summary: &MetricsSummary) {
+        self.imports.fetch_add(1, Ordering::Relaxed);
+        self.record_spans(summary);
+    }
+
+    pub fn record_export(&self, summary: &MetricsSummary, bytes: u64) {
+        self.exports.fetch_add(1, Ordering::Relaxed);
+        self.export_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.record_render_cache(summary);
+        self.record_spans(summary);
+    }
+
+    /// Add the render cache hits and misses an exporter counted
+    pub fn record_render_cache(&self, summary: &MetricsSummary) {
+        let counter = |name: &str| summary.counters.get(name).copied().unwrap_or(0);
+        self.preview_hits
+            .fetch_add(counter("render_cache_hits"), Ordering::Relaxed);
+        self.preview_misses
+            .fetch_add(counter("render_cache_misses"), Ordering::Relaxed);
+    }
+
+    /// Count a render cache lookup as a hit or miss
+    pub fn record_preview(&self, hit: bool) {
+        let counter = if hit {
+            &self.preview_hits
+        } else {
+            &self.preview_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_spans(&self, summary: &MetricsSummary) {
+        let mut samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        for span in &summary.spans {
+            let durations = samples.entry(span.name.clone()).or_default();
+            durations.extend(&span.durations_us);
+            if durations.len() > MAX_SESSION_SAMPLES {
+                let excess = durations.len() - MAX_SESSION_SAMPLES;
+                durations.drain(..excess);
+            }
+        }
+    }
+
+    pub fn report(&self) -> SessionMetricsReport {
+        let samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        SessionMetricsReport {
+            uptime_secs: self.started.elapsed().as_secs(),
+            total_imports: self.imports.load(Ordering::Relaxed),
+            total_exports: self.exports.load(Ordering::Relaxed),
+            total_export_bytes: self.export_bytes.load(Ordering::Relaxed),
+            preview_cache_hits: self.preview_hits.load(Ordering::Relaxed),
+            preview_cache_misses: self.preview_misses.load(Ordering::Relaxed),
+            spans: samples
+                .iter()
+                .map(|(name, durations)| {
+                    let mut sorted = durations.clone();
+                    sorted.sort_unstable();
+                    SpanPercentiles {
+                        name: name.clone(),
+                        count: sorted.len() as u64,
+                        p50_ms: percentile(&sorted, 50) as f64 / 1000.0,
+                        p95_ms: percentile(&sorted, 95) as f64 / 1000.0,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted samples
+fn percentile(sorted: &[u64], percent: usize) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_spans_nest_and_aggregate() {
+        let metrics = Metrics::new();
+        {
+            let _export = metrics.span("export");
+            for _ in 0..3 {
+                let _render = metrics.span("render");
+                let _write = metrics.span("write");
+            }
+        }
+        metrics.add("bytes_written", 100);
+        metrics.add("bytes_written", 20);
+
+        let summary = metrics.summary();
+        let names: Vec<&str> = summary.spans.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["export", "render", "write"]);
+        assert_eq!(summary.span("export").unwrap().parent, None);
+        assert_eq!(
+            summary.span("render").unwrap().parent.as_deref(),
+            Some("export")
+        );
+        assert_eq!(
+            summary.span("write").unwrap().parent.as_deref(),
+            Some("render")
+        );
+        assert_eq!(summary.span("render").unwrap().count, 3);
+        assert!(
+            summary.span("export").unwrap().total_us >= summary.span("render").unwrap().total_us
+        );
+        assert_eq!(summary.counters["bytes_written"], 120);
+        assert!(summary.log_line("export").contains("render"));
+
+        // Spans on worker threads are roots there and still aggregate
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| drop(metrics.span("write")));
+            }
+        });
+        let summary = metrics.take();
+        assert_eq!(summary.span("write").unwrap().count, 7);
+        assert!(metrics.summary().is_empty());
+    }
+
+    #[test]
+    fn test_session_percentiles() {
+        let session = SessionMetrics::default();
+        let summary = MetricsSummary {
+            spans: vec![SpanSummary {
+                name: "write".to_string(),
+                durations_us: (1..=100).map(|ms| ms * 1000).collect(),
+                ..SpanSummary::default()
+            }],
+            ..MetricsSummary::default()
+        };
+        session.record_export(&summary, 2048);
+        session.record_import(&MetricsSummary::default());
+
+        let report = session.report();
+        assert_eq!(report.total_exports, 1);
+        assert_eq!(report.total_imports, 1);
+        assert_eq!(report.total_export_bytes, 2048);
+        assert_eq!(
+            report.spans,
+            vec![SpanPercentiles {
+                name: "write".to_string(),
+                count: 100,
+                p50_ms: 50.0,
+                p95_ms: 95.0,
+            }]
+        );
+    }
+}
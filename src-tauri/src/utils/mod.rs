@@ -1 +1,14 @@
+pub mod author;
+pub mod cancel;
+pub mod cloud;
+pub mod date;
+pub mod disambiguation;
+pub mod format;
+pub mod fs;
+pub mod language;
 pub mod logger;
+pub mod metrics;
+pub mod path;
+pub mod slug;
+pub mod text;
+pub mod titles;
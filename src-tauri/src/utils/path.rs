@@ -0,0 +1,131 @@
+//! Export path helpers that work with drive-letter (`C:\Notes`) and UNC
+//! (`\\server\share\notes`) paths as well as unix paths
+
+use crate::utils::cloud::CloudProvider;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf, Prefix};
+
+/// Folder created inside the documents folder by default
+pub const DEFAULT_EXPORT_FOLDER: &str = "Kobo Highlights";
+
+/// `<Documents>/Kobo Highlights`, falling back to `<home>/Documents`
+pub fn default_export_dir() -> PathBuf {
+    dirs::document_dir()
+        .or_else(|| dirs::home_dir().map(|home| home.join("Documents")))
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(DEFAULT_EXPORT_FOLDER)
+}
+
+/// An export folder as checked before use (`check_export_path`)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportPathCheck {
+    /// The folder exists or can be created
+    pub valid: bool,
+    /// The folder is synced by a cloud service; exports into it retry
+    /// renames the sync daemon blocks
+    pub cloud_synced: bool,
+    /// That service, by name ("iCloud Drive", "Dropbox", …)
+    pub cloud_provider: Option<CloudProvider>,
+}
+
+/// The `\\server\share` root of a UNC path, if `path` is one
+///
+/// Accepts both separators and the verbatim `\\?\UNC\` form; verbatim disk
+/// and device paths (`\\?\C:\`, `\\.\COM1`) are not shares.
+pub fn unc_share_root(path: &str) -> Option<PathBuf> {
+    let rest = match path.strip_prefix(r"\\?\UNC\") {
+        Some(rest) => rest,
+        None => path.strip_prefix(r"\\")?,
+    };
+    let mut parts = rest.split(['\\', '/']).filter(|part| !part.is_empty());
+    let server = parts.next()?;
+    let share = parts.next()?;
+    if server == "?" || server == "." {
+        return None;
+    }
+
+    // A share root is a prefix, not something `PathBuf::push` can build
+    Some(PathBuf::from(format!(r"\\{}\{}", server, share)))
+}
+
+/// `file://` URL of an absolute path, with `/` separators on every platform
+///
+/// `encode` is applied to each path segment.
+pub fn file_url(path: &Path, encode: impl Fn(&str) -> String) -> String {
+    let mut url = String::from("file://");
+    let mut segments: Vec<String> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => match prefix.kind() {
+                Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) => {
+                    url.push_str(&server.to_string_lossy());
+                    segments.push(encode(&share.to_string_lossy()));
+                }
+                Prefix::Disk(drive) | Prefix::VerbatimDisk(drive) => {
+                    segments.push(format!("{}:", drive as char));
+                }
+                _ => segments.push(encode(&prefix.as_os_str().to_string_lossy())),
+            },
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => segments.push("..".to_string()),
+            Component::Normal(segment) => segments.push(encode(&segment.to_string_lossy())),
+        }
+    }
+    for segment in segments {
+        url.push('/');
+        url.push_str(&segment);
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unc_share_root() {
+        assert_eq!(
+            unc_share_root(r"\\server\share\notes\Kobo"),
+            Some(PathBuf::from(r"\\server\share"))
+        );
+        assert_eq!(
+            unc_share_root(r"\\?\UNC\nas\books"),
+            Some(PathBuf::from(r"\\nas\books"))
+        );
+        assert_eq!(unc_share_root(r"\\server"), None);
+        assert_eq!(unc_share_root(r"\\?\C:\Notes"), None);
+        assert_eq!(unc_share_root(r"C:\Notes"), None);
+        assert_eq!(unc_share_root("/home/user/Notes"), None);
+    }
+
+    #[test]
+    fn test_default_export_dir() {
+        let dir = default_export_dir();
+        assert!(dir.ends_with(DEFAULT_EXPORT_FOLDER));
+        assert!(dir.parent().is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_url_unix() {
+        let url = file_url(Path::new("/home/user/My Notes/a.md"), |s| {
+            s.replace(' ', "%20")
+        });
+        assert_eq!(url, "file:///home/user/My%20Notes/a.md");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_file_url_windows() {
+        let encode = |s: &str| s.replace(' ', "%20");
+        assert_eq!(
+            file_url(Path::new(r"D:\My Notes\a.md"), encode),
+            "file:///D:/My%20Notes/a.md"
+        );
+        assert_eq!(
+            file_url(Path::new(r"\\nas\books\Kobo\a.md"), encode),
+            "file://nas/books/Kobo/a.md"
+        );
+    }
+}
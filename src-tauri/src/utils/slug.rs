@@ -0,0 +1,157 @@
+//! URL- and filesystem-safe book identifiers
+//!
+//! Sideloaded books have `file:///mnt/onboard/...` content IDs full of
+//! spaces, parentheses and unicode, which make poor routes, cache filenames
+//! or anchors. A slug is the slugified title followed by a short hash of the
+//! content ID (`walden-3f2a9c41d0be`), stable across imports.
+
+use crate::models::Book;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Hex characters of the content ID hash in a slug
+pub const SLUG_HASH_LEN: usize = 12;
+
+/// Longest title part of a slug
+const SLUG_TITLE_MAX: usize = 40;
+
+/// Lowercase ASCII words joined by `-`; accents are stripped and other
+/// scripts (CJK, …) dropped, so the result may be empty
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.nfkd().filter(|c| !is_combining_mark(*c)) {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(SLUG_TITLE_MAX);
+    slug.trim_end_matches('-').to_string()
+}
+
+/// First `SLUG_HASH_LEN` hex characters of the content ID's SHA-256
+pub fn content_hash(content_id: &str) -> String {
+    let digest = format!("{:x}", Sha256::digest(content_id.as_bytes()));
+    digest[..SLUG_HASH_LEN].to_string()
+}
+
+/// Slug of a book, without the in-import collision suffix
+pub fn book_slug(content_id: &str, title: &str) -> String {
+    join_slug(&slugify(title), &content_hash(content_id))
+}
+
+fn join_slug(title: &str, hash: &str) -> String {
+    if title.is_empty() {
+        hash.to_string()
+    } else {
+        format!("{}-{}", title, hash)
+    }
+}
+
+/// Give every book of an import a unique slug
+///
+/// Colliding slugs get `-2`, `-3`, … in content ID order, so the result
+/// doesn't depend on the order books were extracted in.
+pub fn assign_slugs(books: &mut [Book]) {
+    assign_slugs_with(books, content_hash)
+}
+
+fn assign_slugs_with(books: &mut [Book], hash: impl Fn(&str) -> String) {
+    let mut order: Vec<usize> = (0..books.len()).collect();
+    order.sort_by(|&a, &b| books[a].content_id.cmp(&books[b].content_id));
+
+    let mut used = HashSet::new();
+    for index in order {
+        let book = &mut books[index];
+        let base = join_slug(&slugify(&book.title), &hash(&book.content_id));
+        let mut slug = base.clone();
+        let mut suffix = 2;
+        while !used.insert(slug.clone()) {
+            slug = format!("{}-{}", base, suffix);
+            suffix += 1;
+        }
+        book.slug = slug;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(content_id: &str, title: &str) -> Book {
+        Book::new(
+            content_id.to_string(),
+            title.to_string(),
+            "Autor".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_slugify_accents_and_cjk() {
+        assert_eq!(
+            slugify("Memórias Póstumas de Brás Cubas"),
+            "memorias-postumas-de-bras-cubas"
+        );
+        assert_eq!(
+            slugify("  Éloge (de l'ombre), vol. 2!  "),
+            "eloge-de-l-ombre-vol-2"
+        );
+        assert_eq!(slugify("ノルウェイの森"), "");
+        assert_eq!(slugify("1Q84 (村上春樹)"), "1q84");
+        assert!(slugify(&"palavra ".repeat(20)).len() <= SLUG_TITLE_MAX);
+        assert!(!slugify(&"palavra ".repeat(20)).ends_with('-'));
+    }
+
+    #[test]
+    fn test_slugs_are_url_and_filesystem_safe() {
+        let mut books = vec![
+            book(
+                "file:///mnt/onboard/Livros/Os Lusíadas (ed. 1572).epub",
+                "Os Lusíadas",
+            ),
+            book(
+                "file:///mnt/onboard/ノルウェイの森.kepub.epub",
+                "ノルウェイの森",
+            ),
+        ];
+        assign_slugs(&mut books);
+
+        for book in &books {
+            assert!(book
+                .slug
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'));
+        }
+        assert!(books[0].slug.starts_with("os-lusiadas-"));
+        assert_eq!(books[1].slug.len(), SLUG_HASH_LEN);
+    }
+
+    #[test]
+    fn test_slugs_stable_across_imports() {
+        let mut first = vec![book("vol1", "Walden"), book("vol2", "Meditations")];
+        let mut second = vec![book("vol2", "Meditations"), book("vol1", "Walden")];
+        assign_slugs(&mut first);
+        assign_slugs(&mut second);
+
+        assert_eq!(first[0].slug, second[1].slug);
+        assert_eq!(first[1].slug, second[0].slug);
+        assert_eq!(first[0].slug, book_slug("vol1", "Walden"));
+    }
+
+    #[test]
+    fn test_forced_hash_collision_gets_counter() {
+        let mut books = vec![
+            book("vol-b", "Same Title"),
+            book("vol-a", "Same Title"),
+            book("vol-c", "Same Title"),
+        ];
+        assign_slugs_with(&mut books, |_| "000000000000".to_string());
+
+        assert_eq!(books[1].slug, "same-title-000000000000");
+        assert_eq!(books[0].slug, "same-title-000000000000-2");
+        assert_eq!(books[2].slug, "same-title-000000000000-3");
+    }
+}
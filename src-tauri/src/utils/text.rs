@@ -0,0 +1,653 @@
+//! UTF-8 safe truncation helpers for highlight previews, highlight text
+//! normalization and export filename sanitizing
+//!
+//! Highlight text is arbitrary user content (accents, CJK, emoji), so byte
+//! slicing like `&text[..120]` can panic or split a character in two. These
+//! helpers only ever cut on character or grapheme boundaries.
+
+use crate::models::Book;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+pub const ELLIPSIS: &str = "…";
+
+/// First `max` characters (code points) of `s`
+pub fn truncate_chars(s: &str, max: usize) -> &str {
+    match s.char_indices().nth(max) {
+        Some((index, _)) => &s[..index],
+        None => s,
+    }
+}
+
+/// First `max` user-perceived characters (grapheme clusters) of `s`
+pub fn truncate_graphemes(s: &str, max: usize) -> &str {
+    match s.grapheme_indices(true).nth(max) {
+        Some((index, _)) => &s[..index],
+        None => s,
+    }
+}
+
+/// Truncate to `max` graphemes, appending "…" only if something was cut
+pub fn ellipsize(s: &str, max: usize) -> String {
+    let truncated = truncate_graphemes(s, max);
+    if truncated.len() == s.len() {
+        s.to_string()
+    } else {
+        format!("{}{}", truncated.trim_end(), ELLIPSIS)
+    }
+}
+
+/// A preview centered on a match
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snippet {
+    pub text: String,
+    /// Byte range of the match within `text`
+    pub highlight: Range<usize>,
+}
+
+/// Cut `s` down to the match at `byte_range` plus `context` graphemes on
+/// each side, marking cut ends with "…"
+///
+/// The range may fall inside a character or grapheme; it is widened to the
+/// enclosing grapheme boundaries and clamped to `s`.
+pub fn snippet_around(s: &str, byte_range: Range<usize>, context: usize) -> Snippet {
+    let bounds: Vec<(usize, usize)> = s
+        .grapheme_indices(true)
+        .map(|(index, g)| (index, index + g.len()))
+        .collect();
+    if bounds.is_empty() {
+        return Snippet {
+            text: String::new(),
+            highlight: 0..0,
+        };
+    }
+
+    let start = byte_range.start.min(s.len());
+    let end = byte_range.end.clamp(start, s.len());
+
+    // Graphemes containing the first and last byte of the match
+    let first = bounds
+        .iter()
+        .position(|&(_, g_end)| g_end > start)
+        .unwrap_or(bounds.len() - 1);
+    let last = if end > start {
+        bounds
+            .iter()
+            .position(|&(_, g_end)| g_end >= end)
+            .unwrap_or(bounds.len() - 1)
+    } else {
+        first
+    };
+
+    let match_start = bounds[first].0;
+    let match_end = if end > start {
+        bounds[last].1
+    } else {
+        match_start
+    };
+
+    let from = first.saturating_sub(context);
+    let to = (last + 1 + context).min(bounds.len());
+    let (mut cut_start, mut cut_end) = (bounds[from].0, bounds[to - 1].1);
+
+    // Don't leave a space between a cut end and its ellipsis
+    if from > 0 {
+        let before = &s[cut_start..match_start];
+        cut_start += before.len() - before.trim_start().len();
+    }
+    if to < bounds.len() {
+        let after = &s[match_end..cut_end];
+        cut_end -= after.len() - after.trim_end().len();
+    }
+
+    let prefix = if from > 0 { ELLIPSIS } else { "" };
+    let suffix = if to < bounds.len() { ELLIPSIS } else { "" };
+    let offset = prefix.len() + match_start - cut_start;
+
+    Snippet {
+        text: format!("{}{}{}", prefix, &s[cut_start..cut_end], suffix),
+        highlight: offset..offset + (match_end - match_start),
+    }
+}
+
+/// When highlight text is normalized
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalizationStage {
+    /// Right after extraction, so the library and exports see clean text
+    #[default]
+    Import,
+    /// Only when exporting; imported books keep the device text
+    Export,
+}
+
+/// Cleanup of EPUB artifacts in highlight text, one toggle per step
+///
+/// Steps run in field order. The library applies the same steps to the text
+/// it indexes and to search queries, so both sides always match.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TextNormalization {
+    #[serde(default)]
+    pub stage: NormalizationStage,
+    /// Unicode NFC (composed accents)
+    #[serde(default = "default_true")]
+    pub nfc: bool,
+    /// Remove soft hyphens (U+00AD)
+    #[serde(default = "default_true", alias = "strip_soft_hyphens")]
+    pub strip_soft_hyphens: bool,
+    /// Expand typographic ligatures such as "ﬁ" to their letters
+    #[serde(default = "default_true", alias = "expand_ligatures")]
+    pub expand_ligatures: bool,
+    /// Turn no-break, narrow and other fixed-width spaces into plain spaces
+    #[serde(default = "default_true", alias = "normalize_spaces")]
+    pub normalize_spaces: bool,
+    /// Turn curly quotes and dashes into ASCII
+    #[serde(default, alias = "normalize_punctuation")]
+    pub normalize_punctuation: bool,
+    /// Collapse whitespace runs (keeping paragraph breaks) and trim the ends
+    #[serde(default = "default_true", alias = "collapse_whitespace")]
+    pub collapse_whitespace: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for TextNormalization {
+    fn default() -> Self {
+        Self {
+            stage: NormalizationStage::default(),
+            nfc: true,
+            strip_soft_hyphens: true,
+            expand_ligatures: true,
+            normalize_spaces: true,
+            normalize_punctuation: false,
+            collapse_whitespace: true,
+        }
+    }
+}
+
+impl TextNormalization {
+    /// Apply the enabled steps to `text`; applying twice changes nothing
+    pub fn normalize(&self, text: &str) -> String {
+        let mut text = if self.nfc {
+            text.nfc().collect()
+        } else {
+            text.to_string()
+        };
+        if self.strip_soft_hyphens {
+            text = text.replace('\u{ad}', "");
+        }
+        if self.expand_ligatures {
+            text = map_chars(&text, ligature);
+        }
+        if self.normalize_spaces {
+            text = map_chars(&text, |c| is_fixed_space(c).then_some(" "));
+        }
+        if self.normalize_punctuation {
+            text = map_chars(&text, ascii_punctuation);
+        }
+        if self.collapse_whitespace {
+            text = collapse_whitespace(&text);
+        }
+        text
+    }
+
+    /// Normalize `books` when `stage` is the configured stage
+    pub fn apply_at(&self, stage: NormalizationStage, books: &mut [Book]) {
+        if self.stage == stage {
+            for book in books {
+                self.normalize_book(book);
+            }
+        }
+    }
+
+    /// Normalize the text, note and chapter of every highlight of `book`
+    pub fn normalize_book(&self, book: &mut Book) {
+        for highlight in &mut book.highlights {
+            highlight.text = self.normalize(&highlight.text);
+            if let Some(annotation) = &highlight.annotation {
+                highlight.annotation = Some(self.normalize(annotation));
+            }
+            if let Some(chapter) = &highlight.chapter_title {
+                highlight.chapter_title = Some(self.normalize(chapter));
+            }
+        }
+    }
+}
+
+fn map_chars(text: &str, replacement: impl Fn(char) -> Option<&'static str>) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match replacement(c) {
+            Some(replaced) => out.push_str(replaced),
+            None => out.push(c),
+        }
+    }
+    out
+}
+
+fn ligature(c: char) -> Option<&'static str> {
+    Some(match c {
+        '\u{fb00}' => "ff",
+        '\u{fb01}' => "fi",
+        '\u{fb02}' => "fl",
+        '\u{fb03}' => "ffi",
+        '\u{fb04}' => "ffl",
+        '\u{fb05}' | '\u{fb06}' => "st",
+        _ => return None,
+    })
+}
+
+/// No-break, narrow no-break and the fixed-width spaces U+2000–U+200A
+fn is_fixed_space(c: char) -> bool {
+    matches!(
+        c,
+        '\u{a0}' | '\u{2000}'..='\u{200a}' | '\u{202f}' | '\u{205f}'
+    )
+}
+
+fn ascii_punctuation(c: char) -> Option<&'static str> {
+    Some(match c {
+        '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}' | '\u{2032}' => "'",
+        '\u{201c}' | '\u{201d}' | '\u{201e}' | '\u{201f}' | '\u{2033}' => "\"",
+        '\u{2010}' | '\u{2011}' | '\u{2012}' | '\u{2013}' => "-",
+        '\u{2014}' | '\u{2015}' => "--",
+        _ => return None,
+    })
+}
+
+/// Runs without a line break become one space, runs with one become "\n"
+/// and longer ones a single blank line
+fn collapse_whitespace(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut newlines = 0;
+    let mut in_run = false;
+
+    for c in text.trim().chars() {
+        if c.is_whitespace() {
+            in_run = true;
+            if c == '\n' {
+                newlines += 1;
+            }
+            continue;
+        }
+        if in_run {
+            out.push_str(match newlines {
+                0 => " ",
+                1 => "\n",
+                _ => "\n\n",
+            });
+            in_run = false;
+            newlines = 0;
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Sanitize a filename by removing invalid characters
+pub fn sanitize_filename(filename: &str) -> String {
+    if filename.trim().is_empty() {
+        return "Untitled".to_string();
+    }
+
+    filename
+        .trim()
+        .replace(':', " -")
+        .replace(['/', '\\', '?', '*', '|', '"', '<', '>'], "-")
+        .replace(|c: char| c.is_ascii_control(), "")
+}
+
+/// `text` in ASCII: accents are stripped, letters like `ß`, `ø` or Cyrillic
+/// are transliterated and each run of anything else (CJK, emoji, …) becomes
+/// one `_`
+pub fn transliterate_ascii(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.nfkd().filter(|c| !is_combining_mark(*c)) {
+        if c.is_ascii() {
+            out.push(c);
+        } else if c.is_whitespace() || is_fixed_space(c) {
+            out.push(' ');
+        } else if let Some(ascii) = ascii_punctuation(c) {
+            out.push_str(ascii);
+        } else if let Some(ascii) = c.to_lowercase().next().and_then(ascii_letter) {
+            if c.is_uppercase() {
+                let mut letters = ascii.chars();
+                out.extend(letters.next().map(|first| first.to_ascii_uppercase()));
+                out.push_str(letters.as_str());
+            } else {
+                out.push_str(ascii);
+            }
+        } else if !out.ends_with('_') {
+            out.push('_');
+        }
+    }
+    out
+}
+
+/// A sanitized ASCII-only version of the filename (or folder name) `name`,
+/// or `None` when no letter or digit survives transliteration
+pub fn ascii_filename(name: &str) -> Option<String> {
+    let ascii = sanitize_filename(&transliterate_ascii(name));
+    let collapsed = ascii
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_matches(|c: char| c == '_' || c == ' ')
+        .to_string();
+    collapsed
+        .chars()
+        .any(|c| c.is_ascii_alphanumeric())
+        .then_some(collapsed)
+}
+
+/// ASCII for lowercase letters that don't decompose into a base letter and
+/// accents
+fn ascii_letter(c: char) -> Option<&'static str> {
+    Some(match c {
+        // Latin
+        'ß' => "ss",
+        'æ' => "ae",
+        'œ' => "oe",
+        'ø' => "o",
+        'đ' | 'ð' => "d",
+        'ł' => "l",
+        'þ' => "th",
+        'ı' => "i",
+        'ħ' => "h",
+        // Cyrillic (й, ё and ї arrive as и, е and і after decomposition)
+        'а' => "a",
+        'б' => "b",
+        'в' => "v",
+        'г' | 'ґ' => "g",
+        'д' => "d",
+        'е' | 'э' => "e",
+        'є' => "ye",
+        'ж' => "zh",
+        'з' => "z",
+        'и' | 'і' => "i",
+        'к' => "k",
+        'л' => "l",
+        'м' => "m",
+        'н' => "n",
+        'о' => "o",
+        'п' => "p",
+        'р' => "r",
+        'с' => "s",
+        'т' => "t",
+        'у' => "u",
+        'ф' => "f",
+        'х' => "kh",
+        'ц' => "ts",
+        'ч' => "ch",
+        'ш' => "sh",
+        'щ' => "shch",
+        'ъ' | 'ь' => "",
+        'ы' => "y",
+        'ю' => "yu",
+        'я' => "ya",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "👩‍👩‍👧" is one grapheme of 5 code points; "é" below is e + U+0301
+    const FAMILY: &str = "👩\u{200d}👩\u{200d}👧";
+    const ACCENT: &str = "cafe\u{301}";
+
+    #[test]
+    fn test_truncate_chars_never_splits_code_points() {
+        let text = "ação 日本語";
+        for max in 0..=text.chars().count() + 1 {
+            let cut = truncate_chars(text, max);
+            assert_eq!(cut.chars().count(), max.min(text.chars().count()));
+            assert!(text.starts_with(cut));
+        }
+        assert_eq!(truncate_chars("日本語", 2), "日本");
+    }
+
+    #[test]
+    fn test_truncate_graphemes_keeps_clusters_whole() {
+        let text = format!("a{}b{}", FAMILY, ACCENT);
+        assert_eq!(truncate_graphemes(&text, 0), "");
+        assert_eq!(truncate_graphemes(&text, 1), "a");
+        assert_eq!(truncate_graphemes(&text, 2), format!("a{}", FAMILY));
+        assert_eq!(
+            truncate_graphemes(&text, 7),
+            format!("a{}b{}", FAMILY, ACCENT)
+        );
+        assert_eq!(truncate_graphemes(&text, 100), text);
+
+        // Char truncation would split the ZWJ sequence and the accent
+        assert_eq!(truncate_chars(FAMILY, 2), "👩\u{200d}");
+        assert_eq!(truncate_graphemes(ACCENT, 4), ACCENT);
+    }
+
+    #[test]
+    fn test_ellipsize_only_when_truncated() {
+        assert_eq!(ellipsize("curto", 5), "curto");
+        assert_eq!(ellipsize("curto", 10), "curto");
+        assert_eq!(ellipsize("", 0), "");
+        assert_eq!(ellipsize("curto", 4), "curt…");
+        assert_eq!(ellipsize("um dois", 3), "um…");
+        assert_eq!(ellipsize("吾輩は猫である", 3), "吾輩は…");
+        assert_eq!(
+            ellipsize(&format!("{}{}", FAMILY, FAMILY), 1),
+            format!("{}…", FAMILY)
+        );
+    }
+
+    #[test]
+    fn test_snippet_around_centers_match() {
+        let text = "one two three four five";
+        let start = text.find("three").unwrap();
+        let snippet = snippet_around(text, start..start + 5, 5);
+
+        assert_eq!(snippet.text, "…two three four…");
+        assert_eq!(&snippet.text[snippet.highlight.clone()], "three");
+    }
+
+    #[test]
+    fn test_snippet_around_without_cuts() {
+        let snippet = snippet_around("whole", 1..3, 10);
+        assert_eq!(snippet.text, "whole");
+        assert_eq!(snippet.highlight, 1..3);
+
+        let empty = snippet_around("", 0..3, 2);
+        assert_eq!(empty.text, "");
+        assert_eq!(empty.highlight, 0..0);
+    }
+
+    #[test]
+    fn test_snippet_around_widens_to_graphemes() {
+        let text = format!("x{}y{}z", FAMILY, ACCENT);
+        let family_start = 1;
+
+        // Every byte range inside the emoji widens to the whole cluster
+        for start in family_start..family_start + FAMILY.len() {
+            for end in start + 1..=family_start + FAMILY.len() {
+                let snippet = snippet_around(&text, start..end, 0);
+                assert_eq!(snippet.text, format!("…{}…", FAMILY));
+                assert_eq!(&snippet.text[snippet.highlight.clone()], FAMILY);
+            }
+        }
+
+        // Matching just the combining mark keeps the base letter
+        let mark = text.find('\u{301}').unwrap();
+        let snippet = snippet_around(&text, mark..mark + 2, 1);
+        assert_eq!(&snippet.text[snippet.highlight.clone()], "e\u{301}");
+        assert_eq!(snippet.text, "…fe\u{301}z");
+    }
+
+    #[test]
+    fn test_snippet_around_every_boundary_in_cjk() {
+        let text = "東京は日本の首都です";
+        for start in 0..=text.len() {
+            for end in start..=text.len() + 2 {
+                for context in 0..3 {
+                    let snippet = snippet_around(text, start..end, context);
+                    let marked = &snippet.text[snippet.highlight.clone()];
+                    let body = snippet.text.trim_matches('…');
+                    assert!(text.contains(body));
+                    assert!(body.contains(marked));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_snippet_around_clamps_out_of_range() {
+        let snippet = snippet_around("abc", 10..20, 1);
+        assert_eq!(snippet.text, "…bc");
+        assert_eq!(&snippet.text[snippet.highlight.clone()], "");
+
+        let snippet = snippet_around("abc", Range { start: 2, end: 1 }, 0);
+        assert_eq!(snippet.text, "…c");
+        assert_eq!(snippet.highlight.len(), 0);
+    }
+
+    fn only(step: impl FnOnce(&mut TextNormalization)) -> TextNormalization {
+        let mut options = TextNormalization {
+            stage: NormalizationStage::Import,
+            nfc: false,
+            strip_soft_hyphens: false,
+            expand_ligatures: false,
+            normalize_spaces: false,
+            normalize_punctuation: false,
+            collapse_whitespace: false,
+        };
+        step(&mut options);
+        options
+    }
+
+    fn assert_idempotent(options: &TextNormalization, text: &str) -> String {
+        let once = options.normalize(text);
+        assert_eq!(options.normalize(&once), once);
+        once
+    }
+
+    #[test]
+    fn test_normalize_nfc() {
+        let options = only(|o| o.nfc = true);
+        assert_eq!(assert_idempotent(&options, ACCENT), "café");
+    }
+
+    #[test]
+    fn test_normalize_soft_hyphens() {
+        let options = only(|o| o.strip_soft_hyphens = true);
+        assert_eq!(
+            assert_idempotent(&options, "extra\u{ad}ordi\u{ad}nário"),
+            "extraordinário"
+        );
+    }
+
+    #[test]
+    fn test_normalize_ligatures() {
+        let options = only(|o| o.expand_ligatures = true);
+        assert_eq!(
+            assert_idempotent(&options, "\u{fb01}m da e\u{fb01}cácia"),
+            "fim da eficácia"
+        );
+    }
+
+    #[test]
+    fn test_normalize_spaces() {
+        let options = only(|o| o.normalize_spaces = true);
+        assert_eq!(
+            assert_idempotent(&options, "«\u{a0}Sim\u{202f}!\u{2009}»"),
+            "« Sim ! »"
+        );
+    }
+
+    #[test]
+    fn test_normalize_punctuation() {
+        let options = only(|o| o.normalize_punctuation = true);
+        assert_eq!(
+            assert_idempotent(
+                &options,
+                "\u{201c}It\u{2019}s 1\u{2013}2\u{201d} \u{2014} he said"
+            ),
+            "\"It's 1-2\" -- he said"
+        );
+        // Off by default: typographic quotes are legitimate text
+        assert!(!TextNormalization::default().normalize_punctuation);
+    }
+
+    #[test]
+    fn test_collapse_whitespace_keeps_paragraphs() {
+        let options = only(|o| o.collapse_whitespace = true);
+        assert_eq!(
+            assert_idempotent(&options, "  um \t dois\n  três\n\n\n\nquatro  "),
+            "um dois\ntrês\n\nquatro"
+        );
+    }
+
+    #[test]
+    fn test_normalize_combined() {
+        let options = TextNormalization {
+            normalize_punctuation: true,
+            ..Default::default()
+        };
+        let raw = " \u{201c}A\u{a0}de\u{301}\u{ad}cada  da e\u{fb01}ca\u{301}cia\u{201d}\u{a0}\u{2014}\n\n\n nota ";
+        assert_eq!(
+            assert_idempotent(&options, raw),
+            "\"A década da eficácia\" --\n\nnota"
+        );
+    }
+
+    #[test]
+    fn test_ascii_filename_accented_latin() {
+        assert_eq!(
+            ascii_filename("Crime e Castigo - Fiódor Dostoiévski").as_deref(),
+            Some("Crime e Castigo - Fiodor Dostoievski")
+        );
+        assert_eq!(
+            ascii_filename("Straße der Ørsted-Æra: Œuvres").as_deref(),
+            Some("Strasse der Orsted-Aera - Oeuvres")
+        );
+        // Curly quotes become straight ones, which filenames can't hold
+        assert_eq!(
+            ascii_filename("\u{201c}Łódź\u{201d} \u{2013} ﬁm").as_deref(),
+            Some("-Lodz- - fim")
+        );
+    }
+
+    #[test]
+    fn test_ascii_filename_cyrillic() {
+        assert_eq!(
+            ascii_filename("Преступление и наказание").as_deref(),
+            Some("Prestuplenie i nakazanie")
+        );
+        assert_eq!(
+            ascii_filename("Фёдор Достоевский").as_deref(),
+            Some("Fedor Dostoevskii")
+        );
+        assert_eq!(
+            ascii_filename("Щедрин, Чехов, Жуков").as_deref(),
+            Some("Shchedrin, Chekhov, Zhukov")
+        );
+    }
+
+    #[test]
+    fn test_ascii_filename_cjk() {
+        assert_eq!(transliterate_ascii("ノルウェイの森"), "_");
+        assert_eq!(ascii_filename("ノルウェイの森 - 村上春樹"), None);
+        assert_eq!(
+            ascii_filename("1Q84 (村上春樹)").as_deref(),
+            Some("1Q84 (_)")
+        );
+        assert_eq!(
+            ascii_filename("森 Norwegian Wood 🌲").as_deref(),
+            Some("Norwegian Wood")
+        );
+        assert_eq!(ascii_filename("").as_deref(), Some("Untitled"));
+    }
+}
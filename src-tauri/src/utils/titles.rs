@@ -0,0 +1,418 @@
+//! Optional clean-up of book titles: smart title case and subtitle splitting
+//!
+//! Kobo metadata often has titles in capitals ("THE PRAGMATIC PROGRAMMER:
+//! 20TH ANNIVERSARY EDITION") or the subtitle run into the title. Both steps
+//! are off by default; processed books keep the title as Kobo stored it in
+//! `raw_title`, and settings pick which form display, filenames and sorting
+//! use.
+
+use crate::models::{Book, TitleForm};
+use crate::utils::language::normalize_language_code;
+use serde::{Deserialize, Serialize};
+
+/// Words following a title's `:` that make it part of the title rather than
+/// a subtitle, when a number or roman numeral comes next
+const SEQUENCE_MARKERS: &[&str] = &[
+    "book",
+    "chapter",
+    "episode",
+    "part",
+    "season",
+    "vol",
+    "vol.",
+    "volume",
+    "capítulo",
+    "episódio",
+    "episodio",
+    "libro",
+    "livro",
+    "parte",
+    "tome",
+    "tomo",
+    "épisode",
+];
+
+/// Words kept in their usual capitals, whatever case the title is in
+const CASE_EXCEPTIONS: &[&str] = &[
+    "AI",
+    "API",
+    "BBC",
+    "CEO",
+    "CIA",
+    "CSS",
+    "DNA",
+    "eBay",
+    "FBI",
+    "GitHub",
+    "HTML",
+    "iOS",
+    "iPad",
+    "iPhone",
+    "JavaScript",
+    "macOS",
+    "MIT",
+    "NASA",
+    "NATO",
+    "NYC",
+    "SQL",
+    "UK",
+    "USA",
+    "WWI",
+    "WWII",
+];
+
+/// Articles, conjunctions and short prepositions, lowercased inside titles
+fn small_words(language: &str) -> &'static [&'static str] {
+    match language {
+        "pt" => &[
+            "a", "à", "ao", "aos", "as", "às", "com", "da", "das", "de", "do", "dos", "e", "em",
+            "na", "nas", "no", "nos", "o", "os", "ou", "para", "por", "sem", "um", "uma", "umas",
+            "uns",
+        ],
+        "es" => &[
+            "a", "al", "con", "de", "del", "e", "el", "en", "la", "las", "los", "o", "para", "por",
+            "sin", "sobre", "u", "un", "una", "unas", "unos", "y",
+        ],
+        "fr" => &[
+            "à", "au", "aux", "avec", "dans", "de", "des", "du", "en", "et", "la", "le", "les",
+            "ou", "par", "pour", "sur", "un", "une",
+        ],
+        "it" => &[
+            "a", "al", "con", "da", "del", "della", "di", "e", "ed", "fra", "gli", "i", "il", "in",
+            "la", "le", "lo", "o", "per", "su", "tra", "un", "una", "uno",
+        ],
+        "de" => &[
+            "an", "auf", "das", "dem", "den", "der", "des", "die", "ein", "eine", "einem", "einen",
+            "einer", "für", "im", "in", "mit", "oder", "und", "von", "vom", "zu", "zum", "zur",
+        ],
+        _ => &[
+            "a", "an", "and", "as", "at", "but", "by", "for", "from", "in", "into", "nor", "of",
+            "on", "onto", "or", "so", "the", "to", "up", "via", "vs", "with", "yet",
+        ],
+    }
+}
+
+/// Which steps run at import and which title form each use takes
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TitleOptions {
+    /// Title-case titles written entirely in capitals (or lowercase)
+    #[serde(default, alias = "smart_case")]
+    pub smart_case: bool,
+    /// Move the part after the first `:` into `subtitle`
+    #[serde(default, alias = "split_subtitle")]
+    pub split_subtitle: bool,
+    /// Title in exported notes
+    #[serde(default)]
+    pub display: TitleForm,
+    #[serde(default)]
+    pub filenames: TitleForm,
+    /// Title the bookshelf index sorts by
+    #[serde(default)]
+    pub sorting: TitleForm,
+    /// Keep the subtitle in processed filenames; off for shorter names
+    #[serde(default = "default_true", alias = "filename_subtitle")]
+    pub filename_subtitle: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for TitleOptions {
+    fn default() -> Self {
+        Self {
+            smart_case: false,
+            split_subtitle: false,
+            display: TitleForm::default(),
+            filenames: TitleForm::default(),
+            sorting: TitleForm::default(),
+            filename_subtitle: true,
+        }
+    }
+}
+
+impl TitleOptions {
+    /// Title for a book's export filename
+    pub fn filename_title(&self, book: &Book) -> String {
+        match self.filenames {
+            TitleForm::Processed if !self.filename_subtitle => book.title.clone(),
+            form => book.title_in(form),
+        }
+    }
+}
+
+fn is_latin(c: char) -> bool {
+    matches!(c, '\u{41}'..='\u{24f}' | '\u{1e00}'..='\u{1eff}')
+}
+
+/// Lowercase, with Turkish and Azerbaijani dotted and dotless i
+fn to_lower(word: &str, turkic: bool) -> String {
+    if !turkic {
+        return word.to_lowercase();
+    }
+    word.chars()
+        .map(|c| match c {
+            'I' => "ı".to_string(),
+            'İ' => "i".to_string(),
+            c => c.to_lowercase().collect(),
+        })
+        .collect()
+}
+
+/// `word` (already lowercase) with its first letter in capitals
+fn capitalize(word: &str, turkic: bool) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some('i') if turkic => format!("İ{}", chars.as_str()),
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Roman numerals up to 39, the ones titles use ("Part IV", "Henry VIII")
+fn is_roman_numeral(word: &str) -> bool {
+    let units = word.trim_start_matches('X');
+    !word.is_empty()
+        && word.len() - units.len() <= 3
+        && matches!(
+            units,
+            "" | "I" | "II" | "III" | "IV" | "V" | "VI" | "VII" | "VIII" | "IX"
+        )
+}
+
+/// Numbers with an ordinal suffix ("20TH", "1ST")
+fn is_ordinal(lower: &str) -> bool {
+    let digits = lower.trim_start_matches(|c: char| c.is_ascii_digit());
+    digits.len() < lower.len() && matches!(digits, "st" | "nd" | "rd" | "th")
+}
+
+/// Case one word (no spaces, hyphens or surrounding punctuation)
+fn case_word(word: &str, small: &[&str], edge: bool, turkic: bool) -> String {
+    if let Some(exception) = CASE_EXCEPTIONS
+        .iter()
+        .find(|exception| exception.eq_ignore_ascii_case(word))
+    {
+        return exception.to_string();
+    }
+    let lower = to_lower(word, turkic);
+    if word.chars().any(|c| c.is_ascii_digit()) {
+        return if is_ordinal(&lower) {
+            lower
+        } else {
+            word.to_string()
+        };
+    }
+    if !edge && small.contains(&lower.as_str()) {
+        return lower;
+    }
+    if is_roman_numeral(&word.to_uppercase()) {
+        return word.to_uppercase();
+    }
+    capitalize(&lower, turkic)
+}
+
+/// Title case for titles written in a single case
+///
+/// Titles with both capitals and lowercase, or with letters outside the Latin
+/// script, come back unchanged. Articles, conjunctions and short prepositions
+/// of the book's language (English when unknown) are lowercased except at
+/// the start, the end and after a `:`.
+pub fn smart_title_case(title: &str, language: Option<&str>) -> String {
+    let has_upper = title.chars().any(char::is_uppercase);
+    let has_lower = title.chars().any(char::is_lowercase);
+    if has_upper == has_lower || title.chars().any(|c| c.is_alphabetic() && !is_latin(c)) {
+        return title.to_string();
+    }
+
+    let language = language.and_then(normalize_language_code);
+    let turkic = matches!(language.as_deref(), Some("tr" | "az"));
+    let small = small_words(language.as_deref().unwrap_or("en"));
+
+    let words: Vec<&str> = title.split(' ').collect();
+    let last = words.iter().rposition(|word| !word.is_empty());
+    let mut after_colon = true;
+    let mut cased = Vec::with_capacity(words.len());
+    for (index, word) in words.iter().enumerate() {
+        let not_alphanumeric = |c: char| !c.is_alphanumeric();
+        let start = word.len() - word.trim_start_matches(not_alphanumeric).len();
+        let end = word.trim_end_matches(not_alphanumeric).len().max(start);
+        let edge = after_colon || Some(index) == last;
+        let core = word[start..end]
+            .split('-')
+            .map(|part| case_word(part, small, edge, turkic))
+            .collect::<Vec<_>>()
+            .join("-");
+        cased.push(format!("{}{}{}", &word[..start], core, &word[end..]));
+        if !word.is_empty() {
+            after_colon = word.ends_with(':');
+        }
+    }
+    cased.join(" ")
+}
+
+/// Title and subtitle of `title`, split at its first `": "`
+///
+/// Numbered parts ("Star Wars: Episode IV", "Dune: Part 2") stay in the
+/// title; `None` when there's nothing to split.
+pub fn split_subtitle(title: &str) -> Option<(&str, &str)> {
+    let (main, subtitle) = title.split_once(": ")?;
+    let (main, subtitle) = (main.trim(), subtitle.trim());
+    if main.is_empty() || subtitle.is_empty() {
+        return None;
+    }
+    let mut words = subtitle.split_whitespace();
+    if let (Some(marker), Some(number)) = (words.next(), words.next()) {
+        let number = number.trim_end_matches(|c: char| !c.is_alphanumeric());
+        let numbered =
+            number.chars().all(|c| c.is_ascii_digit()) || is_roman_numeral(&number.to_uppercase());
+        if numbered && SEQUENCE_MARKERS.contains(&marker.to_lowercase().as_str()) {
+            return None;
+        }
+    }
+    Some((main, subtitle))
+}
+
+/// Apply the enabled title steps to `books`, starting from the raw titles
+///
+/// Running it again (or with other options) re-processes from `raw_title`,
+/// so turning the steps off restores Kobo's titles.
+pub fn process_titles(books: &mut [Book], options: &TitleOptions) {
+    for book in books.iter_mut() {
+        let raw = book
+            .raw_title
+            .take()
+            .unwrap_or_else(|| std::mem::take(&mut book.title));
+        let mut title = if options.smart_case {
+            smart_title_case(&raw, book.language.as_deref())
+        } else {
+            raw.clone()
+        };
+        book.subtitle = None;
+        if options.split_subtitle {
+            if let Some((main, subtitle)) = split_subtitle(&title) {
+                book.subtitle = Some(subtitle.to_string());
+                title = main.to_string();
+            }
+        }
+        if title != raw || book.subtitle.is_some() {
+            book.raw_title = Some(raw);
+        }
+        book.title = title;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_titles() {
+        let options = TitleOptions {
+            smart_case: true,
+            split_subtitle: true,
+            ..TitleOptions::default()
+        };
+        // (raw title, language, title, subtitle)
+        let cases: &[(&str, Option<&str>, &str, Option<&str>)] = &[
+            (
+                "THE PRAGMATIC PROGRAMMER: 20TH ANNIVERSARY EDITION",
+                None,
+                "The Pragmatic Programmer",
+                Some("20th Anniversary Edition"),
+            ),
+            (
+                "THE LORD OF THE RINGS",
+                Some("en-US"),
+                "The Lord of the Rings",
+                None,
+            ),
+            (
+                "a history of the world in 10½ chapters",
+                None,
+                "A History of the World in 10½ Chapters",
+                None,
+            ),
+            (
+                "NASA AND THE SPACE RACE: WHAT IT WAS FOR",
+                None,
+                "NASA and the Space Race",
+                Some("What It Was For"),
+            ),
+            ("HENRY VIII", None, "Henry VIII", None),
+            ("O NOME DA ROSA", Some("por"), "O Nome da Rosa", None),
+            ("İSTANBUL HATIRALAR", Some("tr"), "İstanbul Hatıralar", None),
+            (
+                "SELF-RELIANCE AND OTHER ESSAYS",
+                None,
+                "Self-Reliance and Other Essays",
+                None,
+            ),
+            ("1Q84", None, "1Q84", None),
+            // Mixed case is the author's choice
+            ("eBay for Dummies", None, "eBay for Dummies", None),
+            (
+                "The Pragmatic Programmer",
+                None,
+                "The Pragmatic Programmer",
+                None,
+            ),
+            // Subtitles vs numbered parts
+            ("1984: A Novel", None, "1984", Some("A Novel")),
+            ("Star Wars: Episode IV", None, "Star Wars: Episode IV", None),
+            ("STAR WARS: EPISODE IV", None, "Star Wars: Episode IV", None),
+            ("Dune: Part 2", None, "Dune: Part 2", None),
+            ("Ratio 16:9", None, "Ratio 16:9", None),
+            // Non-Latin scripts pass through
+            ("ВОЙНА И МИР", Some("ru"), "ВОЙНА И МИР", None),
+            ("ノルウェイの森", Some("ja"), "ノルウェイの森", None),
+        ];
+
+        for (raw, language, title, subtitle) in cases {
+            let mut book = Book::new("id".into(), raw.to_string(), "Author".into());
+            book.language = language.map(str::to_string);
+            let mut books = vec![book];
+
+            process_titles(&mut books, &options);
+            let book = &books[0];
+            assert_eq!(book.title, *title, "{}", raw);
+            assert_eq!(book.subtitle.as_deref(), *subtitle, "{}", raw);
+            assert_eq!(book.title_in(TitleForm::Raw), *raw);
+
+            // Turning the steps off restores the raw title
+            process_titles(&mut books, &TitleOptions::default());
+            assert_eq!(books[0].title, *raw);
+            assert_eq!(books[0].raw_title, None);
+        }
+    }
+
+    #[test]
+    fn test_filename_title() {
+        let mut books = vec![Book::new(
+            "id".into(),
+            "THE PRAGMATIC PROGRAMMER: 20TH ANNIVERSARY EDITION".into(),
+            "Author".into(),
+        )];
+        let mut options = TitleOptions {
+            smart_case: true,
+            split_subtitle: true,
+            ..TitleOptions::default()
+        };
+        process_titles(&mut books, &options);
+
+        assert_eq!(
+            options.filename_title(&books[0]),
+            "The Pragmatic Programmer: 20th Anniversary Edition"
+        );
+        options.filename_subtitle = false;
+        assert_eq!(
+            options.filename_title(&books[0]),
+            "The Pragmatic Programmer"
+        );
+        options.filenames = TitleForm::Raw;
+        assert_eq!(
+            options.filename_title(&books[0]),
+            "THE PRAGMATIC PROGRAMMER: 20TH ANNIVERSARY EDITION"
+        );
+    }
+}
@@ -1,39 +1,283 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use tauri::{Listener, Manager};
+//! Showing the main window once the frontend is ready
+//!
+//! The window starts hidden (no white flash) and is shown when the frontend
+//! signals that the theme is applied: by invoking `frontend_ready`, or by the
+//! older "app-ready" event. The event can fire before its listener exists
+//! (fast dev reloads), the command can't, so a signal that arrives before
+//! `setup_window_show` is kept and acted on there. Without any signal a
+//! fallback shows the window after `SHOW_FALLBACK`; its thread exits as soon
+//! as the window is shown. Whatever shows it emits "window-shown".
 
-pub fn setup_window_show(app: &tauri::App) {
-    let window = app
-        .get_webview_window("main")
-        .expect("main window not found");
+use serde::{Deserialize, Serialize};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Listener, Manager};
 
-    let shown = Arc::new(AtomicBool::new(false));
-    let shown_clone = shown.clone();
-    let window_clone = window.clone();
+/// Event announcing the window is visible, so the frontend can start
+/// animations
+pub const WINDOW_SHOWN: &str = "window-shown";
 
-    // Listen for app-ready event from frontend
-    app.listen("app-ready", move |_| {
-        if !shown_clone.swap(true, Ordering::SeqCst) {
-            log::info!("[Window] Showing window (app-ready received)");
-            if let Err(e) = window_clone.show() {
-                log::error!("[Window] Failed to show window: {}", e);
+/// The window is shown after this long without a ready signal
+pub const SHOW_FALLBACK: Duration = Duration::from_secs(3);
+
+/// What got the window shown
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ShowTrigger {
+    /// The `frontend_ready` command
+    FrontendReady,
+    /// The "app-ready" event
+    AppReady,
+    /// Nothing signalled within `SHOW_FALLBACK`
+    Fallback,
+}
+
+/// Payload of "window-shown"
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowShownEvent {
+    pub trigger: ShowTrigger,
+}
+
+/// Input of the show state machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShowEvent {
+    /// `setup_window_show` ran: the window exists and the timer started
+    Armed,
+    /// The frontend signalled it is ready
+    Ready(ShowTrigger),
+    /// `SHOW_FALLBACK` elapsed
+    TimerElapsed,
+}
+
+/// What to do after a `ShowEvent`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShowAction {
+    /// Show the window now
+    Show(ShowTrigger),
+    /// Nothing yet: a ready signal waits for the window to be armed
+    Wait,
+    /// The window is already shown
+    Ignore,
+}
+
+/// Decides which of the racing signals shows the window, exactly once
+#[derive(Debug, Default)]
+pub struct ShowMachine {
+    armed: bool,
+    /// First ready signal, kept until the window is armed
+    ready: Option<ShowTrigger>,
+    shown: Option<ShowTrigger>,
+}
+
+impl ShowMachine {
+    pub fn on(&mut self, event: ShowEvent) -> ShowAction {
+        if self.shown.is_some() {
+            return ShowAction::Ignore;
+        }
+        let trigger = match event {
+            ShowEvent::Armed => {
+                self.armed = true;
+                self.ready
+            }
+            ShowEvent::Ready(trigger) => {
+                self.ready.get_or_insert(trigger);
+                self.armed.then_some(trigger)
+            }
+            ShowEvent::TimerElapsed => self.armed.then_some(ShowTrigger::Fallback),
+        };
+        match trigger {
+            Some(trigger) => {
+                self.shown = Some(trigger);
+                ShowAction::Show(trigger)
             }
-        } else {
-            log::info!("[Window] Window already shown, ignoring duplicate show");
+            None => ShowAction::Wait,
+        }
+    }
+
+    /// What showed the window, once shown
+    pub fn shown(&self) -> Option<ShowTrigger> {
+        self.shown
+    }
+}
+
+/// The show state machine, shared by the listener, the command and the
+/// fallback thread
+#[derive(Debug, Default)]
+pub struct WindowShowState {
+    machine: Mutex<ShowMachine>,
+    changed: Condvar,
+}
+
+impl WindowShowState {
+    pub fn handle(&self, event: ShowEvent) -> ShowAction {
+        let mut machine = self.machine.lock().unwrap_or_else(|e| e.into_inner());
+        let action = machine.on(event);
+        if matches!(action, ShowAction::Show(_)) {
+            self.changed.notify_all();
         }
+        action
+    }
+
+    /// Wait up to `timeout` for the window to be shown; `Show(Fallback)`
+    /// when it wasn't, `Ignore` as soon as it is
+    pub fn wait_for_show(&self, timeout: Duration) -> ShowAction {
+        let machine = self.machine.lock().unwrap_or_else(|e| e.into_inner());
+        let (mut machine, _) = self
+            .changed
+            .wait_timeout_while(machine, timeout, |m| m.shown().is_none())
+            .unwrap_or_else(|e| e.into_inner());
+        machine.on(ShowEvent::TimerElapsed)
+    }
+}
+
+/// Listen for "app-ready", show the window if the frontend was already
+/// ready, and start the fallback timer otherwise
+pub fn setup_window_show(app: &tauri::App) {
+    let handle = app.handle().clone();
+    app.listen("app-ready", move |_| {
+        signal_ready(&handle, ShowTrigger::AppReady);
     });
 
-    // Fallback: show after 3 seconds if frontend doesn't emit event
-    let shown_fallback = shown.clone();
+    let action = app.state::<WindowShowState>().handle(ShowEvent::Armed);
+    if matches!(action, ShowAction::Show(_)) {
+        log::info!("[Window] Frontend was ready before the listener, showing window");
+        perform(app.handle(), action);
+        return;
+    }
+
+    let handle = app.handle().clone();
     std::thread::spawn(move || {
-        std::thread::sleep(std::time::Duration::from_secs(3));
-        if !shown_fallback.swap(true, Ordering::SeqCst) {
-            log::warn!("[Window] Showing window via fallback timer (3s)");
-            if let Err(e) = window.show() {
-                log::error!("[Window] Failed to show window via fallback: {}", e);
-            }
+        let action = handle
+            .state::<WindowShowState>()
+            .wait_for_show(SHOW_FALLBACK);
+        if action == ShowAction::Show(ShowTrigger::Fallback) {
+            log::warn!(
+                "[Window] Showing window via fallback timer ({}s)",
+                SHOW_FALLBACK.as_secs()
+            );
         }
+        perform(&handle, action);
     });
 
-    log::info!("[Window] Window show listener registered, fallback timer started (3s)");
+    log::info!(
+        "[Window] Window show listener registered, fallback timer started ({}s)",
+        SHOW_FALLBACK.as_secs()
+    );
+}
+
+/// The frontend is ready: show the window, or remember it until armed
+pub fn signal_ready(app: &AppHandle, trigger: ShowTrigger) {
+    let action = app
+        .state::<WindowShowState>()
+        .handle(ShowEvent::Ready(trigger));
+    match action {
+        ShowAction::Show(_) => log::info!("[Window] Showing window ({:?} received)", trigger),
+        ShowAction::Wait => log::info!("[Window] {:?} received before setup, waiting", trigger),
+        ShowAction::Ignore => {
+            log::info!("[Window] Window already shown, ignoring duplicate show")
+        }
+    }
+    perform(app, action);
+}
+
+fn perform(app: &AppHandle, action: ShowAction) {
+    let ShowAction::Show(trigger) = action else {
+        return;
+    };
+    let Some(window) = app.get_webview_window("main") else {
+        log::error!("[Window] Main window not found, cannot show it");
+        return;
+    };
+    if let Err(e) = window.show() {
+        log::error!("[Window] Failed to show window: {}", e);
+        return;
+    }
+    if let Err(e) = app.emit(WINDOW_SHOWN, WindowShownEvent { trigger }) {
+        log::error!("[Window] Failed to emit {}: {}", WINDOW_SHOWN, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    #[test]
+    fn test_show_machine_races() {
+        // Ready, then the timer: the timer finds the window shown
+        let mut machine = ShowMachine::default();
+        assert_eq!(machine.on(ShowEvent::Armed), ShowAction::Wait);
+        assert_eq!(
+            machine.on(ShowEvent::Ready(ShowTrigger::AppReady)),
+            ShowAction::Show(ShowTrigger::AppReady)
+        );
+        assert_eq!(machine.on(ShowEvent::TimerElapsed), ShowAction::Ignore);
+
+        // The timer, then a late ready signal
+        let mut machine = ShowMachine::default();
+        machine.on(ShowEvent::Armed);
+        assert_eq!(
+            machine.on(ShowEvent::TimerElapsed),
+            ShowAction::Show(ShowTrigger::Fallback)
+        );
+        assert_eq!(
+            machine.on(ShowEvent::Ready(ShowTrigger::FrontendReady)),
+            ShowAction::Ignore
+        );
+        assert_eq!(machine.shown(), Some(ShowTrigger::Fallback));
+
+        // Both signals: only the first shows
+        let mut machine = ShowMachine::default();
+        machine.on(ShowEvent::Armed);
+        assert_eq!(
+            machine.on(ShowEvent::Ready(ShowTrigger::FrontendReady)),
+            ShowAction::Show(ShowTrigger::FrontendReady)
+        );
+        assert_eq!(
+            machine.on(ShowEvent::Ready(ShowTrigger::AppReady)),
+            ShowAction::Ignore
+        );
+
+        // Ready before the listener: shown when armed, the timer is moot
+        let mut machine = ShowMachine::default();
+        assert_eq!(
+            machine.on(ShowEvent::Ready(ShowTrigger::FrontendReady)),
+            ShowAction::Wait
+        );
+        assert_eq!(
+            machine.on(ShowEvent::Armed),
+            ShowAction::Show(ShowTrigger::FrontendReady)
+        );
+        assert_eq!(machine.on(ShowEvent::TimerElapsed), ShowAction::Ignore);
+    }
+
+    #[test]
+    fn test_fallback_wait_ends_when_shown() {
+        let state = Arc::new(WindowShowState::default());
+        state.handle(ShowEvent::Armed);
+        let waiter = {
+            let state = state.clone();
+            std::thread::spawn(move || {
+                let started = Instant::now();
+                (
+                    state.wait_for_show(Duration::from_secs(30)),
+                    started.elapsed(),
+                )
+            })
+        };
+        std::thread::sleep(Duration::from_millis(20));
+        state.handle(ShowEvent::Ready(ShowTrigger::AppReady));
+        let (action, waited) = waiter.join().unwrap();
+        assert_eq!(action, ShowAction::Ignore);
+        assert!(waited < Duration::from_secs(5));
+
+        let state = WindowShowState::default();
+        state.handle(ShowEvent::Armed);
+        assert_eq!(
+            state.wait_for_show(Duration::from_millis(10)),
+            ShowAction::Show(ShowTrigger::Fallback)
+        );
+    }
 }
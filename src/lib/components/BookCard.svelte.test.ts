@@ -82,6 +82,12 @@ describe('BookCard', () => {
     expect(img).toHaveAttribute('src', '/path/to/cover.jpg');
   });
 
+  it('prefers the thumbnail over the full-size cover', () => {
+    const book = { ...mockBook, thumbnailPath: '/path/to/cover_thumb.jpg' };
+    const { container } = render(BookCard, { props: { book } });
+    expect(container.querySelector('img')).toHaveAttribute('src', '/path/to/cover_thumb.jpg');
+  });
+
   it('renders placeholder when coverPath is missing', () => {
     const { container } = render(BookCard, { props: { book: mockBookNoCover } });
     expect(screen.getByText('BW')).toBeInTheDocument();
@@ -1,6 +1,7 @@
 import '@testing-library/jest-dom';
 import { describe, it, expect, vi } from 'vitest';
-import { render, screen, fireEvent } from '@testing-library/svelte';
+import { render, screen, fireEvent, waitFor } from '@testing-library/svelte';
+import { invoke } from '@tauri-apps/api/core';
 import BookDetailsView from './BookDetailsView.svelte';
 import type { Book, Highlight } from '../types';
 
@@ -116,4 +117,28 @@ describe('BookDetailsView', () => {
     render(BookDetailsView, { props: { book: mockBookNoHighlights } });
     expect(screen.getByText(/no highlights/i)).toBeInTheDocument();
   });
+
+  it('loads the highlights left out of a trimmed book', async () => {
+    vi.mocked(invoke).mockImplementation(async (command, args) => {
+      if (command !== 'get_book_highlights') return undefined;
+      const { offset } = args as { offset: number };
+      return {
+        contentId: 'book-1',
+        total: 3,
+        offset,
+        highlights: mockHighlights.slice(offset, offset + 2),
+      };
+    });
+    const trimmed = { ...mockBook, highlights: [mockHighlights[0]], highlightTotal: 3 };
+    render(BookDetailsView, { props: { book: trimmed } });
+
+    expect(screen.getByText('3 highlights')).toBeInTheDocument();
+    await waitFor(() => expect(screen.getByText('Third highlight')).toBeInTheDocument());
+    expect(invoke).toHaveBeenCalledWith('get_book_highlights', {
+      contentId: 'book-1',
+      offset: 2,
+      limit: 500,
+    });
+    vi.mocked(invoke).mockReset();
+  });
 });
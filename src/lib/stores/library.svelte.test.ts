@@ -144,13 +144,40 @@ describe('Library Store', () => {
 						isSelected: false
 					}
 				];
-				vi.mocked(invoke).mockResolvedValueOnce(importedBooks);
+				vi.mocked(invoke).mockResolvedValueOnce({ books: importedBooks, schema: { status: 'known' } });
 
 				const result = await library.importHighlights();
 
-				expect(invoke).toHaveBeenCalledWith('import_highlights', { device: mockDevice });
+				expect(invoke).toHaveBeenCalledWith('import_highlights', {
+					device: mockDevice,
+					operationId: expect.stringMatching(/^import-/)
+				});
 				expect(result).toEqual(importedBooks);
 				expect(library.books).toEqual(importedBooks);
+				expect(library.schemaWarning).toBeUndefined();
+			});
+
+			it('should keep the warning about an unfamiliar schema', async () => {
+				vi.mocked(invoke).mockResolvedValueOnce({
+					name: 'Kobo Clara',
+					path: '/Volumes/KOBOeReader',
+					isValid: true
+				});
+				await library.scanForDevice();
+
+				const schemaWarning =
+					'Your firmware is newer than khi has been tested with — import proceeded, 2 new column(s) ignored';
+				vi.mocked(invoke).mockResolvedValueOnce({
+					books: [],
+					schema: {
+						status: 'newerUnknown',
+						differences: { dbVersion: 180, knownVersion: 174, newColumns: ['a', 'b'], missingColumns: [] }
+					},
+					schemaWarning
+				});
+
+				await library.importHighlights();
+				expect(library.schemaWarning).toBe(schemaWarning);
 			});
 
 			it('should set importing state during import', async () => {
@@ -163,7 +190,7 @@ describe('Library Store', () => {
 				await library.scanForDevice();
 
 				vi.mocked(invoke).mockImplementationOnce(
-					() => new Promise((resolve) => setTimeout(() => resolve([]), 10))
+					() => new Promise((resolve) => setTimeout(() => resolve({ books: [] }), 10))
 				);
 
 				const importPromise = library.importHighlights();
@@ -1,11 +1,14 @@
 import { invoke } from '@tauri-apps/api/core';
-import type { Book, KoboDevice, ImportProgress, ExportConfig, UiState } from '../types';
+import type { Book, KoboDevice, ImportProgress, ImportResult, ExportConfig, UiState } from '../types';
 
 class LibraryStore {
 	books = $state<Book[]>([]);
 	selectedBookIds = $state<string[]>([]);
 	isImporting = $state(false);
 	importProgress = $state<ImportProgress | undefined>(undefined);
+	importOperationId: string | undefined = undefined;
+	/** Set when the last import read a database schema khi wasn't tested with */
+	schemaWarning = $state<string | undefined>(undefined);
 	connectedDevice = $state<KoboDevice | undefined>(undefined);
 	isScanning = $state(false);
 	uiState = $state<UiState>('no-device');
@@ -99,20 +102,31 @@ class LibraryStore {
 		};
 
 		try {
-			const importedBooks = await invoke<Book[]>('import_highlights', {
-				device: this.connectedDevice
+			// Registered, so the import gets the configured deadline and can be cancelled
+			this.importOperationId = `import-${Date.now()}`;
+			const result = await invoke<ImportResult>('import_highlights', {
+				device: this.connectedDevice,
+				operationId: this.importOperationId
 			});
-			this.addBooks(importedBooks);
-			return importedBooks;
+			this.schemaWarning = result.schemaWarning;
+			this.addBooks(result.books);
+			return result.books;
 		} catch (error) {
 			console.error('Failed to import highlights:', error);
 			throw error;
 		} finally {
 			this.isImporting = false;
 			this.importProgress = undefined;
+			this.importOperationId = undefined;
 		}
 	}
 
+	/** Stop the running import; false when none is running */
+	async cancelImport(): Promise<boolean> {
+		if (!this.importOperationId) return false;
+		return invoke<boolean>('cancel_operation', { operationId: this.importOperationId });
+	}
+
 	async exportBooks(exportPath: string): Promise<string[]> {
 		if (this.selectedBooks.length === 0) throw new Error('No books selected for export');
 
@@ -297,6 +297,7 @@ export function getSortLabel(sort: SortPreference): string {
     author: "Author",
     date_last_read: "Date Read",
     highlight_count: "Highlight Count",
+    time_spent_reading: "Time Spent Reading",
   };
   return labels[sort];
 }
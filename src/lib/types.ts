@@ -5,27 +5,136 @@
 
 export interface Book {
   contentId: string;
+  /** Without the subtitle once titles are processed */
   title: string;
+  /** Part after the title's first ":", when split off */
+  subtitle?: string;
+  /** Title as stored by Kobo, when processing changed it */
+  rawTitle?: string;
   author: string;
   isbn?: string;
   publisher?: string;
   language?: string;
   dateLastRead?: string;
   description?: string;
+  /** Full-size cover; may be missing until `get_full_cover` generates it */
   coverPath?: string;
+  /** Small cover for the library grid */
+  thumbnailPath?: string;
   highlights: Highlight[];
+  /** Highlight count when `highlights` holds only the first page (large books); load the rest with `get_book_highlights` */
+  highlightTotal?: number;
+  /** What chapterProgress is a fraction of (kepubs: the chapter) */
+  progressScope?: 'chapter' | 'book';
+  /** Year from the EPUB's dc:date */
+  publicationYear?: number;
+  /** Set when another book of the batch has the same title and author ("2019", or the slug) */
+  disambiguator?: string;
+  kind?: 'book' | 'pocket_article' | 'kindle_import';
+  /** Article URL of Pocket articles */
+  sourceUrl?: string;
+  /** Borrowed from a library (OverDrive or Adobe DRM) */
+  isLoan?: boolean;
+  /** When the loan ends, as recorded by the device */
+  loanExpiry?: string;
+  /** How far the book was read (0–100), as recorded by the device */
+  percentRead?: number;
+  /** Total time spent reading, in seconds, as recorded by the device */
+  timeSpentReadingSecs?: number;
+  /** The device no longer has the book, only its highlights; title from the file name */
+  isOrphaned?: boolean;
+  /** Reading notes (markdown) kept in the library */
+  notes?: string;
   isSelected: boolean;
 }
 
+/** How a device's database schema compares with the ones khi was tested with */
+export type SchemaCompatibility =
+  | { status: 'known' }
+  | { status: 'newerUnknown' | 'olderUnknown'; differences: SchemaDifferences };
+
+export interface SchemaDifferences {
+  dbVersion: number | null;
+  knownVersion: number;
+  newColumns: string[];
+  missingColumns: string[];
+}
+
+/** Result of `import_highlights` */
+export interface ImportResult {
+  books: Book[];
+  schema?: SchemaCompatibility;
+  /** Shown after importing from unfamiliar firmware */
+  schemaWarning?: string;
+  metrics?: MetricsSummary;
+}
+
+/** Timings of one run: spans in microseconds, named counters */
+export interface MetricsSummary {
+  totalMs: number;
+  spans: { name: string; parent: string | null; count: number; totalUs: number; maxUs: number }[];
+  counters: Record<string, number>;
+}
+
+/** One page of a book's highlights from the library (`get_book_highlights`) */
+export interface HighlightPage {
+  contentId: string;
+  total: number;
+  offset: number;
+  highlights: Highlight[];
+}
+
 export interface Highlight {
   id: string;
   text: string;
   annotation?: string;
   chapterTitle?: string;
   chapterProgress?: number;
+  /** Estimated page, when the device records the book's page count */
+  page?: number;
   containerPath?: string;
   dateCreated: string;
+  /** `dateCreated` normalized to UTC (RFC 3339); null when unparseable */
+  dateCreatedUtc?: string | null;
   color?: string;
+  /** 'figure' for highlights made on an illustration (empty text); omitted for text */
+  kind?: 'text' | 'figure';
+  /** Cached image of a figure highlight */
+  figurePath?: string;
+  /** Tags kept in the library, attached for exports */
+  tags?: string[];
+}
+
+/** One chapter of a book's reading map */
+export interface ChapterSummary {
+  title: string;
+  /** TOC nesting level; null for chapters the TOC doesn't list */
+  depth: number | null;
+  highlightsCount: number;
+  firstHighlightDate: string | null;
+  lastHighlightDate: string | null;
+  averageProgress: number | null;
+}
+
+/** Result of `get_book_chapter_map` */
+export interface ChapterMap {
+  contentId: string;
+  /** Chapters come from the TOC, unhighlighted ones included */
+  fromToc: boolean;
+  chapters: ChapterSummary[];
+  /** Highlights without a chapter */
+  unassigned: number;
+}
+
+/** Result of `get_book_stats`; reading stats are formatted ("86%", "4 h 32 m") */
+export interface BookStats {
+  highlightsCount: number;
+  notesCount: number;
+  firstHighlightDate: string | null;
+  lastHighlightDate: string | null;
+  topChapter: string | null;
+  percentRead: string | null;
+  timeSpentReading: string | null;
 }
 
 export interface KoboDevice {
@@ -33,6 +142,10 @@ export interface KoboDevice {
   path: string;
   isValid: boolean;
   serialNumber?: string;
+  /** Why the database can't be imported, e.g. "Database not readable (encrypted?)" */
+  invalidReason?: string;
+  /** Outdated second mount of the same device (same serial) */
+  isStale?: boolean;
 }
 
 export interface ImportProgress {
@@ -41,6 +154,24 @@ export interface ImportProgress {
   totalBooks: number;
   highlightsFound: number;
   percentage: number;
+  /** Book whose cover thumbnail was just generated */
+  contentId?: string;
+  thumbnailPath?: string;
+}
+
+/** Scenario of the dev-only `simulate_device_event` command */
+export type SimulationScenario =
+  | 'connect'
+  | 'disconnect'
+  | 'import_slow'
+  | 'import_error'
+  | 'export_error';
+
+/** Result of `simulate_device_event` */
+export interface SimulationReport {
+  scenario: SimulationScenario;
+  /** Names of the events sent, in order */
+  events: string[];
 }
 
 export interface ExportConfig {
@@ -52,8 +183,22 @@ export interface ExportConfig {
     dateLastRead: boolean;
     language: boolean;
     description: boolean;
+    /** Render the book's reading notes under "## Notes" */
+    bookNotes?: boolean;
+    /** Write the notes as authored, without escaping headings and rules */
+    bookNotesRaw?: boolean;
   };
   dateFormat: 'dd_mm_yyyy' | 'dd_month_yyyy' | 'iso8601';
+  /** Stage every book and move the files into place only if all succeed */
+  transactional?: boolean;
+}
+
+/** Result of `check_export_path` */
+export interface ExportPathCheck {
+  valid: boolean;
+  /** Synced by iCloud Drive, Dropbox, OneDrive or Google Drive */
+  cloudSynced: boolean;
+  cloudProvider: string | null;
 }
 
 export interface AppState {
@@ -90,6 +235,7 @@ export interface ExportHighlightData {
   chapter: string | null;
   location: string;
   date: string;
+  color?: string | null;
 }
 
 /** Export data for a book with highlights */
@@ -116,10 +262,80 @@ export interface AppSettings {
   uiPreferences: UiPreferences;
   /** Last import record */
   lastImport?: LastImportSettingsRecord;
+  /** Offset assumed for device timestamps without a zone, in minutes east of UTC */
+  assumedUtcOffsetMinutes?: number;
+  /** Report edits made outside khi to files in the export folder */
+  watchExportDir?: boolean;
+  /** Title-casing and subtitle splitting at import, and which title form exports use */
+  titleOptions?: TitleOptions;
+  /** Seconds imports, exports, manifest checks and network requests may run (0: no limit) */
+  operationTimeouts?: OperationTimeouts;
+  /** Import figure highlights and their images (the EPUB must be on the device) */
+  importFigures?: boolean;
   /** Version for migration support */
   version: string;
 }
 
+/** Part of the settings `restore_settings_section` takes from the backup */
+export type SettingsSection = 'export_config' | 'ui_preferences' | 'export_profiles' | 'all';
+
+/** A top-level settings key that differs between the backup and the current settings */
+export interface SettingsChange {
+  key: string;
+  /** Values changed, added or removed below the key */
+  changedValues: number;
+}
+
+/** Result of `get_settings_backup_info` */
+export interface SettingsBackupInfo {
+  exists: boolean;
+  /** When the backup was written */
+  modifiedAt: string | null;
+  changes: SettingsChange[];
+  /** Sections restoring would change */
+  changedSections: SettingsSection[];
+}
+
+/** Which form of a book's title a setting uses */
+export type TitleForm = 'raw' | 'processed';
+
+/** Title processing at import and the title form of each use */
+export interface TitleOptions {
+  /** Title-case titles written entirely in capitals (or lowercase) */
+  smartCase: boolean;
+  /** Move the part after the first ":" into `subtitle` */
+  splitSubtitle: boolean;
+  /** Title in exported notes */
+  display: TitleForm;
+  filenames: TitleForm;
+  /** Title the bookshelf index sorts by */
+  sorting: TitleForm;
+  /** Keep the subtitle in processed filenames */
+  filenameSubtitle: boolean;
+}
+
+/** Deadlines of long-running operations, in seconds */
+export interface OperationTimeouts {
+  importSecs: number;
+  exportSecs: number;
+  verifySecs: number;
+  networkSecs: number;
+}
+
+/** Why an operation started with an `operationId` stopped early */
+export interface Cancelled {
+  reason: 'requested' | 'deadline_exceeded';
+  /** Books or files finished before stopping */
+  completed?: number;
+  total?: number;
+}
+
+/** Payload of the "export-files-changed" event */
+export interface ExportFilesChangedEvent {
+  /** Slugs of the books whose exported files changed outside khi */
+  slugs: string[];
+}
+
 /** UI preferences */
 export interface UiPreferences {
   /** Theme preference */
@@ -147,7 +363,12 @@ export type ThemePreference = 'system' | 'light' | 'dark';
 export type ViewMode = 'grid' | 'list';
 
 /** Sort preference for library */
-export type SortPreference = 'title' | 'author' | 'date_last_read' | 'highlight_count';
+export type SortPreference =
+  | 'title'
+  | 'author'
+  | 'date_last_read'
+  | 'highlight_count'
+  | 'time_spent_reading';
 
 /** Last import record for settings */
 export interface LastImportSettingsRecord {
@@ -159,5 +380,22 @@ export interface LastImportSettingsRecord {
   booksCount: number;
   /** Number of highlights imported */
   highlightsCount: number;
+  /** Problems with individual books */
+  warnings?: ImportWarning[];
+}
+
+/** A problem with one imported book */
+export interface ImportWarning {
+  kind: 'orphaned_book' | 'possibly_stale_data';
+  contentId: string;
+  title: string;
+  message: string;
+}
+
+/** Whether an import may be missing highlights the device hasn't saved */
+export interface DeviceFreshness {
+  newestHighlight?: string;
+  lastSuspend?: string;
+  warning?: ImportWarning;
 }
 export type UiState = 'no-device' | 'scanning' | 'importing' | 'library' | 'book-details';